@@ -41,6 +41,94 @@ pub enum DBError {
     /// Notification not found
     #[error("Notification not found")]
     NotificationNotFound,
+
+    /// Webhook not found
+    #[error("Webhook not found")]
+    WebhookNotFound,
+
+    /// Fraud heuristics aren't enabled on the captcha
+    #[error("Fraud thresholds not found")]
+    FraudThresholdsNotFound,
+
+    /// Two-factor authentication is not set up
+    #[error("Two-factor authentication is not set up")]
+    TotpNotFound,
+
+    /// Email verification token not found
+    #[error("Email verification token not found")]
+    EmailVerificationNotFound,
+
+    /// Pending email change not found
+    #[error("Email change not found")]
+    EmailChangeNotFound,
+
+    /// Session revocation token not found
+    #[error("Session revocation token not found")]
+    SessionRevocationNotFound,
+
+    /// OpenID Connect identity isn't linked to any user
+    #[error("OpenID Connect identity not linked")]
+    OidcIdentityNotFound,
+
+    /// Session not found
+    #[error("Session not found")]
+    SessionNotFound,
+
+    /// Organization not found
+    #[error("Organization not found")]
+    OrgNotFound,
+
+    /// Organization name is taken
+    #[error("Organization name is taken")]
+    OrgNameTaken,
+
+    /// User isn't a member of the organization
+    #[error("Organization member not found")]
+    OrgMemberNotFound,
+
+    /// Traffic burst not found
+    #[error("Burst not found")]
+    BurstNotFound,
+
+    /// Invitation not found, already accepted or expired
+    #[error("Invitation not found")]
+    InviteNotFound,
+
+    /// External ID is used by another captcha of the user
+    #[error("External ID is taken")]
+    ExternalIdTaken,
+
+    /// Email domain isn't banned
+    #[error("Banned email domain not found")]
+    BannedDomainNotFound,
+
+    /// Difficulty decay isn't enabled on the captcha
+    #[error("Difficulty decay not found")]
+    DifficultyDecayNotFound,
+
+    /// API token not found
+    #[error("API token not found")]
+    ApiTokenNotFound,
+
+    /// Automatic notifications aren't enabled on the captcha
+    #[error("Alert thresholds not found")]
+    AlertThresholdsNotFound,
+
+    /// The widget of the captcha uses the default theme
+    #[error("Widget theme not found")]
+    WidgetThemeNotFound,
+
+    /// Email isn't among the emails that couldn't be sent
+    #[error("Dead letter not found")]
+    DeadLetterNotFound,
+
+    /// User has as many captchas as they are allowed to
+    #[error("Sitekey limit reached")]
+    SitekeyLimitReached,
+
+    /// Demo session created as many captchas as it is allowed to
+    #[error("Session sitekey limit reached")]
+    SessionSitekeyLimitReached,
 }
 
 /// Convenience type alias for grouping driver-specific errors
@@ -137,6 +137,40 @@ pub trait MCDatabase: std::marker::Send + std::marker::Sync + CloneSPDatabase {
     /// Get all captchas belonging to user
     async fn get_all_user_captchas(&self, username: &str) -> DBResult<Vec<Captcha>>;
 
+    /// Get up to `limit` captchas belonging to user, skipping the first
+    /// `offset`, in the order that they were created
+    async fn get_user_captchas_page(
+        &self,
+        username: &str,
+        offset: usize,
+        limit: usize,
+    ) -> DBResult<Vec<Captcha>>;
+
+    /// Count captchas belonging to user
+    async fn count_user_captchas(&self, username: &str) -> DBResult<usize>;
+
+    /// Get keys of all captchas, across all users
+    async fn get_all_captcha_keys(&self) -> DBResult<Vec<String>>;
+
+    /// Get keys of up to `limit` captchas, across all users, most recently
+    /// fetched first. Captchas that were never fetched come last
+    async fn get_recently_active_captcha_keys(
+        &self,
+        limit: usize,
+    ) -> DBResult<Vec<String>>;
+
+    /// Check if a user has a captcha with the given description, ignoring the
+    /// captcha `except`, if set
+    async fn captcha_description_exists(
+        &self,
+        username: &str,
+        description: &str,
+        except: Option<&str>,
+    ) -> DBResult<bool>;
+
+    /// Get username of the owner of a captcha
+    async fn get_captcha_owner(&self, captcha_key: &str) -> DBResult<String>;
+
     /// update captcha metadata; doesn't change captcha key
     async fn update_captcha_metadata(
         &self,
@@ -187,6 +221,40 @@ pub trait MCDatabase: std::marker::Send + std::marker::Sync + CloneSPDatabase {
     /// Get captcha's cooldown period
     async fn get_captcha_cooldown(&self, captcha_key: &str) -> DBResult<i32>;
 
+    /// Set strict single-use mode for validation tokens of a captcha
+    async fn update_captcha_strict_tokens(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        strict: bool,
+    ) -> DBResult<()>;
+
+    /// Check if validation tokens of a captcha are strictly single-use
+    async fn captcha_strict_tokens(&self, captcha_key: &str) -> DBResult<bool>;
+
+    /// Set scheduling priority of PoW verifications of a captcha
+    async fn update_captcha_priority(
+        &self,
+        captcha_key: &str,
+        high_priority: bool,
+    ) -> DBResult<()>;
+
+    /// Check if PoW verifications of a captcha are scheduled ahead of others
+    async fn captcha_high_priority(&self, captcha_key: &str) -> DBResult<bool>;
+
+    /// Mark validation token as consumed. Returns false if the token was
+    /// already consumed
+    async fn consume_token(&self, captcha_key: &str, token: &str) -> DBResult<bool>;
+
+    /// Check if validation token was consumed
+    async fn token_is_consumed(&self, captcha_key: &str, token: &str) -> DBResult<bool>;
+
+    /// record validation token replays
+    async fn record_token_replay(&self, captcha_key: &str) -> DBResult<()>;
+
+    /// fetch validation token replays
+    async fn fetch_token_replays(&self, user: &str, key: &str) -> DBResult<Vec<i64>>;
+
     /// Add traffic configuration
     async fn add_traffic_pattern(
         &self,
@@ -231,11 +299,20 @@ pub trait MCDatabase: std::marker::Send + std::marker::Sync + CloneSPDatabase {
     /// record PoWConfig fetches
     async fn record_fetch(&self, key: &str) -> DBResult<()>;
 
-    /// record PoWConfig solves
-    async fn record_solve(&self, key: &str) -> DBResult<()>;
+    /// record PoWConfig solves, along with the country of the solver if known
+    async fn record_solve(&self, key: &str, country: Option<&str>) -> DBResult<()>;
+
+    /// record PoWConfig confirms, along with the country of the solver if known
+    async fn record_confirm(&self, key: &str, country: Option<&str>) -> DBResult<()>;
+
+    /// record a batch of PoWConfig fetches
+    async fn record_fetches(&self, records: &[StatsRecord]) -> DBResult<()>;
 
-    /// record PoWConfig confirms
-    async fn record_confirm(&self, key: &str) -> DBResult<()>;
+    /// record a batch of PoWConfig solves
+    async fn record_solves(&self, records: &[StatsRecord]) -> DBResult<()>;
+
+    /// record a batch of PoWConfig confirms
+    async fn record_confirms(&self, records: &[StatsRecord]) -> DBResult<()>;
 
     /// fetch PoWConfig fetches
     async fn fetch_config_fetched(&self, user: &str, key: &str) -> DBResult<Vec<i64>>;
@@ -261,6 +338,23 @@ pub trait MCDatabase: std::marker::Send + std::marker::Sync + CloneSPDatabase {
         offset: usize,
     ) -> DBResult<Vec<PerformanceAnalytics>>;
 
+    /// fetch up to `limit` PoW analytics logged after the one with ID `after`,
+    /// oldest first
+    async fn analytics_fetch_after(
+        &self,
+        captcha_id: &str,
+        after: usize,
+        limit: usize,
+    ) -> DBResult<Vec<PerformanceAnalytics>>;
+
+    /// fetch 50th, 90th and 99th percentile solve times of a captcha for each
+    /// difficulty factor and worker type, ordered by difficulty factor
+    async fn analytics_fetch_percentiles(
+        &self,
+        user: &str,
+        key: &str,
+    ) -> DBResult<Vec<SolveTimePercentiles>>;
+
     /// Create psuedo ID against campaign ID to publish analytics
     async fn analytics_create_psuedo_id_if_not_exists(
         &self,
@@ -279,6 +373,9 @@ pub trait MCDatabase: std::marker::Send + std::marker::Sync + CloneSPDatabase {
         psuedo_id: &str,
     ) -> DBResult<String>;
 
+    /// Get number of analytics records of a captcha
+    async fn analytics_count(&self, captcha_key: &str) -> DBResult<usize>;
+
     /// Delete all records for campaign
     async fn analytics_delete_all_records_for_campaign(
         &self,
@@ -325,56 +422,1724 @@ pub trait MCDatabase: std::marker::Send + std::marker::Sync + CloneSPDatabase {
         duration: u32,
         location: u32,
     ) -> DBResult<Option<usize>>;
-}
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
-/// Log Proof-of-Work CAPTCHA performance analytics
-pub struct CreatePerformanceAnalytics {
-    /// time taken to generate proof
-    pub time: u32,
-    /// difficulty factor for which the proof was generated
-    pub difficulty_factor: u32,
-    /// worker/client type: wasm, javascript, python, etc.
-    pub worker_type: String,
-}
+    /// Reclaim space and refresh planner statistics after large deletions
+    async fn run_maintenance(&self) -> DBResult<()>;
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
-/// Proof-of-Work CAPTCHA performance analytics
-pub struct PerformanceAnalytics {
-    /// log ID
-    pub id: usize,
-    /// time taken to generate proof
-    pub time: u32,
-    /// difficulty factor for which the proof was generated
-    pub difficulty_factor: u32,
-    /// worker/client type: wasm, javascript, python, etc.
-    pub worker_type: String,
-}
+    /// Set webhook of a captcha; replaces existing webhook, if any
+    async fn set_webhook(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        webhook: &Webhook,
+    ) -> DBResult<()>;
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
-/// Captcha statistics with time recorded in UNIX epoch formats
-pub struct StatsUnixTimestamp {
-    /// times at which the configuration were fetched
-    pub config_fetches: Vec<i64>,
-    /// times at which the PoW was solved
-    pub solves: Vec<i64>,
-    /// times at which the PoW token was verified
-    pub confirms: Vec<i64>,
-}
+    /// Get webhook of a captcha
+    async fn get_webhook(&self, captcha_key: &str) -> DBResult<Webhook>;
 
-#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
-/// Represents notification
-pub struct Notification {
-    /// receiver name  of the notification
-    pub name: Option<String>,
-    /// heading of the notification
-    pub heading: Option<String>,
-    /// message of the notification
-    pub message: Option<String>,
-    /// when notification was received
-    pub received: Option<i64>,
-    /// db assigned ID of the notification
-    pub id: Option<i32>,
+    /// Delete webhook of a captcha
+    async fn delete_webhook(&self, username: &str, captcha_key: &str) -> DBResult<()>;
+
+    /// Log webhook delivery attempt
+    async fn record_webhook_delivery(
+        &self,
+        captcha_key: &str,
+        delivery: &CreateWebhookDelivery,
+    ) -> DBResult<()>;
+
+    /// Fetch webhook delivery logs of a captcha, newest first
+    async fn fetch_webhook_deliveries(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        limit: u32,
+    ) -> DBResult<Vec<WebhookDelivery>>;
+
+    /// Set thresholds of fraud heuristics of a captcha, enabling them; replaces
+    /// existing thresholds, if any
+    async fn set_fraud_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        thresholds: &FraudThresholds,
+    ) -> DBResult<()>;
+
+    /// Get thresholds of fraud heuristics of a captcha
+    async fn get_fraud_thresholds(&self, captcha_key: &str)
+        -> DBResult<FraudThresholds>;
+
+    /// Delete thresholds of fraud heuristics of a captcha, disabling them
+    async fn delete_fraud_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()>;
+
+    /// Record acceptance of a legal document(terms of service, data processing
+    /// agreement, etc.) by a user
+    async fn accept_agreement(
+        &self,
+        username: &str,
+        agreement: &AcceptAgreement,
+    ) -> DBResult<()>;
+
+    /// Fetch all agreements accepted by a user, newest first
+    async fn fetch_agreements(&self, username: &str) -> DBResult<Vec<Agreement>>;
+
+    /// Set TOTP secret of a user. Replaces existing secret, if any, and leaves
+    /// two-factor authentication disabled until [MCDatabase::enable_totp] is called
+    async fn set_totp(&self, username: &str, secret: &str) -> DBResult<()>;
+
+    /// Get TOTP configuration of a user
+    async fn get_totp(&self, username: &str) -> DBResult<Totp>;
+
+    /// Enable two-factor authentication and replace recovery codes of a user
+    async fn enable_totp(
+        &self,
+        username: &str,
+        recovery_codes: &[String],
+    ) -> DBResult<()>;
+
+    /// Delete TOTP secret and recovery codes of a user
+    async fn delete_totp(&self, username: &str) -> DBResult<()>;
+
+    /// Consume recovery code of a user. Returns false when the code doesn't exist
+    async fn use_recovery_code(&self, username: &str, code_hash: &str)
+        -> DBResult<bool>;
+
+    /// Record that a user used a TOTP code of time step `step`. Returns false when
+    /// a code of the same or a later step was already used, so that codes can't
+    /// be used twice. Replacing the secret with [MCDatabase::set_totp] forgets
+    /// used steps
+    async fn use_totp_step(&self, username: &str, step: i64) -> DBResult<bool>;
+
+    /// Store email verification token of a user. Replaces pending token, if any
+    async fn add_email_verification(
+        &self,
+        username: &str,
+        v: &AddEmailVerification,
+    ) -> DBResult<()>;
+
+    /// Delete email verification token and return its details
+    async fn consume_email_verification(
+        &self,
+        token: &str,
+    ) -> DBResult<EmailVerification>;
+
+    /// Store pending email change of a user. Replaces pending change, if any
+    async fn add_email_change(&self, username: &str, c: &AddEmailChange)
+        -> DBResult<()>;
+
+    /// Get pending email change of a user
+    async fn get_email_change(&self, username: &str) -> DBResult<Option<EmailChange>>;
+
+    /// Delete pending email change with confirmation token `token` and return
+    /// its details
+    async fn consume_email_change(&self, token: &str) -> DBResult<EmailChange>;
+
+    /// Delete pending email change with cancellation token `cancel_token` and
+    /// return its details
+    async fn cancel_email_change(&self, cancel_token: &str) -> DBResult<EmailChange>;
+
+    /// Mark email address of a user as verified or unverified
+    async fn set_email_verified(&self, username: &str, verified: bool) -> DBResult<()>;
+
+    /// Check if email address of a user is verified
+    async fn is_email_verified(&self, username: &str) -> DBResult<bool>;
+
+    /// Link OpenID Connect identity to a user
+    async fn link_oidc_identity(
+        &self,
+        username: &str,
+        identity: &OidcIdentity,
+    ) -> DBResult<()>;
+
+    /// Get username of the user to whom an OpenID Connect identity is linked
+    async fn get_oidc_user(&self, identity: &OidcIdentity) -> DBResult<String>;
+
+    /// Create sign-in session of a user
+    async fn create_session(
+        &self,
+        username: &str,
+        session: &CreateSession,
+    ) -> DBResult<()>;
+
+    /// Get session
+    async fn get_session(&self, id: &str) -> DBResult<Session>;
+
+    /// Update last activity time of a session to now
+    async fn touch_session(&self, id: &str) -> DBResult<()>;
+
+    /// Get all sessions of a user, most recently active first
+    async fn get_user_sessions(&self, username: &str) -> DBResult<Vec<Session>>;
+
+    /// Delete session of a user
+    async fn delete_session(&self, username: &str, id: &str) -> DBResult<()>;
+
+    /// Delete all sessions of a user
+    async fn delete_user_sessions(&self, username: &str) -> DBResult<()>;
+
+    /// Record device from which a user signed in. Returns `true` when the
+    /// device wasn't known yet
+    async fn add_known_device(&self, username: &str, device: &str) -> DBResult<bool>;
+
+    /// Count devices from which a user signed in
+    async fn count_known_devices(&self, username: &str) -> DBResult<usize>;
+
+    /// Forget all devices from which a user signed in
+    async fn delete_known_devices(&self, username: &str) -> DBResult<()>;
+
+    /// Store token that revokes all sessions of a user
+    async fn add_session_revocation(&self, username: &str, token: &str) -> DBResult<()>;
+
+    /// Delete session revocation token and return its details
+    async fn consume_session_revocation(
+        &self,
+        token: &str,
+    ) -> DBResult<SessionRevocation>;
+
+    /// Grant or revoke the instance administrator role of a user
+    async fn set_admin(&self, username: &str, is_admin: bool) -> DBResult<()>;
+
+    /// Check if a user is an instance administrator
+    async fn is_admin(&self, username: &str) -> DBResult<bool>;
+
+    /// Get a page of users of the instance, ordered by username
+    async fn get_users(&self, page: usize, limit: usize) -> DBResult<Vec<InstanceUser>>;
+
+    /// Get instance-wide counts of users and captchas
+    async fn get_instance_stats(&self) -> DBResult<InstanceStats>;
+
+    /// Set or clear the public key to which notifications of a user can be
+    /// encrypted
+    async fn set_notification_key(
+        &self,
+        username: &str,
+        key: Option<&str>,
+    ) -> DBResult<()>;
+
+    /// Get the public key to which notifications of a user can be encrypted
+    async fn get_notification_key(&self, username: &str) -> DBResult<Option<String>>;
+
+    /// Create notification whose message is encrypted to the receiver's
+    /// notification key
+    async fn create_encrypted_notification(&self, p: &AddNotification) -> DBResult<()>;
+
+    /// Create organization. Its creator becomes its owner
+    async fn create_org(&self, username: &str, name: &str) -> DBResult<()>;
+
+    /// Check if an organization exists
+    async fn org_exists(&self, name: &str) -> DBResult<bool>;
+
+    /// Delete organization. Its captchas are kept by the members holding them
+    async fn delete_org(&self, name: &str) -> DBResult<()>;
+
+    /// Get all organizations of which a user is a member, ordered by name
+    async fn get_user_orgs(&self, username: &str) -> DBResult<Vec<OrgMembership>>;
+
+    /// Add member to organization, or change the role of a member
+    async fn set_org_member(
+        &self,
+        org: &str,
+        username: &str,
+        role: OrgRole,
+    ) -> DBResult<()>;
+
+    /// Get role of a member of an organization
+    async fn get_org_role(&self, org: &str, username: &str) -> DBResult<OrgRole>;
+
+    /// Get all members of an organization, ordered by username
+    async fn get_org_members(&self, org: &str) -> DBResult<Vec<OrgMember>>;
+
+    /// Remove member from organization
+    async fn remove_org_member(&self, org: &str, username: &str) -> DBResult<()>;
+
+    /// Move captcha into an organization, or out of it when `org` is None.
+    /// `username` becomes the member holding the captcha
+    async fn set_captcha_org(
+        &self,
+        username: &str,
+        key: &str,
+        org: Option<&str>,
+    ) -> DBResult<()>;
+
+    /// Get organization that owns a captcha, if any
+    async fn get_captcha_org(&self, key: &str) -> DBResult<Option<String>>;
+
+    /// Get all captchas owned by an organization
+    async fn get_org_captchas(&self, org: &str) -> DBResult<Vec<Captcha>>;
+
+    /// Hand captchas of an organization that are held by member `from` over to
+    /// member `to`
+    async fn reassign_org_captchas(
+        &self,
+        org: &str,
+        from: &str,
+        to: &str,
+    ) -> DBResult<()>;
+
+    /// Schedule a traffic burst for a captcha
+    async fn add_burst(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        burst: &CreateBurst,
+    ) -> DBResult<()>;
+
+    /// Get traffic bursts of a captcha, ordered by start time
+    async fn get_bursts(&self, captcha_key: &str) -> DBResult<Vec<Burst>>;
+
+    /// Delete a traffic burst of a captcha
+    async fn delete_burst(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        id: i32,
+    ) -> DBResult<()>;
+
+    /// Get traffic bursts of all captchas that overlap the window between
+    /// unix timestamps `from` and `until`
+    async fn get_scheduled_bursts(&self, from: i64, until: i64) -> DBResult<Vec<Burst>>;
+
+    /// Delete traffic bursts that ended before unix timestamp `before`
+    async fn delete_ended_bursts(&self, before: i64) -> DBResult<()>;
+
+    /// Create an invitation issued by a user
+    async fn create_invite(&self, username: &str, invite: &CreateInvite)
+        -> DBResult<()>;
+
+    /// Get an invitation by its token
+    async fn get_invite(&self, token: &str) -> DBResult<Invite>;
+
+    /// Get invitations issued by a user, newest first
+    async fn get_user_invites(&self, username: &str) -> DBResult<Vec<Invite>>;
+
+    /// Delete an invitation issued by a user
+    async fn delete_invite(&self, username: &str, id: i32) -> DBResult<()>;
+
+    /// Mark an invitation accepted at unix timestamp `now`. Fails when the
+    /// invitation was already accepted or has expired
+    async fn accept_invite(&self, token: &str, now: i64) -> DBResult<()>;
+
+    /// Mark an accepted invitation pending again
+    async fn reopen_invite(&self, token: &str) -> DBResult<()>;
+
+    /// Set quota overrides of a user; replaces existing overrides, if any
+    async fn set_user_quota(&self, username: &str, quota: &UserQuota) -> DBResult<()>;
+
+    /// Get quota overrides of a user
+    async fn get_user_quota(&self, username: &str) -> DBResult<UserQuota>;
+
+    /// Set or clear the data residency region of a user
+    async fn set_residency(&self, username: &str, region: Option<&str>) -> DBResult<()>;
+
+    /// Get the data residency region of a user
+    async fn get_residency(&self, username: &str) -> DBResult<Option<String>>;
+
+    /// Mark the registration of a user pending or approved
+    async fn set_pending_approval(&self, username: &str, pending: bool) -> DBResult<()>;
+
+    /// Check if the registration of a user is pending approval
+    async fn is_pending_approval(&self, username: &str) -> DBResult<bool>;
+
+    /// Get registrations that are pending approval, in pages of `limit`
+    async fn get_pending_registrations(
+        &self,
+        page: usize,
+        limit: usize,
+    ) -> DBResult<Vec<PendingRegistration>>;
+
+    /// Set or clear the caller-supplied external ID of a captcha. External IDs
+    /// are unique per user
+    async fn set_captcha_external_id(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        external_id: Option<&str>,
+    ) -> DBResult<()>;
+
+    /// Get captcha of a user by its external ID
+    async fn get_captcha_by_external_id(
+        &self,
+        username: &str,
+        external_id: &str,
+    ) -> DBResult<Captcha>;
+
+    /// Suspend or unsuspend a user
+    async fn set_suspended(&self, username: &str, suspended: bool) -> DBResult<()>;
+
+    /// Check if a user is suspended
+    async fn is_suspended(&self, username: &str) -> DBResult<bool>;
+
+    /// Check if the owner of a captcha is suspended
+    async fn is_captcha_owner_suspended(&self, captcha_key: &str) -> DBResult<bool>;
+
+    /// Ban an email domain from registration. Banning a banned domain is a
+    /// no-op
+    async fn ban_email_domain(&self, domain: &str) -> DBResult<()>;
+
+    /// Lift the ban on an email domain
+    async fn unban_email_domain(&self, domain: &str) -> DBResult<()>;
+
+    /// Check if an email domain is banned
+    async fn is_email_domain_banned(&self, domain: &str) -> DBResult<bool>;
+
+    /// Get banned email domains, sorted by name
+    async fn get_banned_email_domains(&self) -> DBResult<Vec<String>>;
+
+    /// Set difficulty decay of a captcha, enabling it; replaces existing decay,
+    /// if any
+    async fn set_difficulty_decay(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        decay: &DifficultyDecay,
+    ) -> DBResult<()>;
+
+    /// Get difficulty decay of a captcha
+    async fn get_difficulty_decay(&self, captcha_key: &str)
+        -> DBResult<DifficultyDecay>;
+
+    /// Delete difficulty decay of a captcha, disabling it
+    async fn delete_difficulty_decay(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()>;
+
+    /// Record a change of the difficulty of a captcha in its escalation log
+    async fn add_escalation_log_entry(
+        &self,
+        captcha_key: &str,
+        entry: &EscalationLogEntry,
+    ) -> DBResult<()>;
+
+    /// Get the escalation log of a captcha, newest first
+    async fn get_escalation_log(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        limit: u32,
+    ) -> DBResult<Vec<EscalationLogEntry>>;
+
+    /// Replace domains that a captcha can be embedded on
+    async fn set_allowed_domains(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        domains: &[String],
+    ) -> DBResult<()>;
+
+    /// Get domains that a captcha can be embedded on, sorted by name
+    async fn get_allowed_domains(&self, captcha_key: &str) -> DBResult<Vec<String>>;
+
+    /// Set unix timestamp after which a captcha needs allowed domains to serve
+    /// challenges
+    async fn set_allowed_domains_deadline(
+        &self,
+        captcha_key: &str,
+        deadline: i64,
+    ) -> DBResult<()>;
+
+    /// Get unix timestamp after which a captcha needs allowed domains to serve
+    /// challenges, if it was set
+    async fn get_allowed_domains_deadline(
+        &self,
+        captcha_key: &str,
+    ) -> DBResult<Option<i64>>;
+
+    /// Get captchas that have neither allowed domains nor a deadline to set them
+    async fn get_captchas_without_allowed_domains(
+        &self,
+    ) -> DBResult<Vec<UnrestrictedCaptcha>>;
+
+    /// Record that a captcha was created from the demo account at unix
+    /// timestamp `created_at`, in demo session `session`
+    async fn add_demo_sitekey(
+        &self,
+        captcha_key: &str,
+        session: Option<&str>,
+        created_at: i64,
+    ) -> DBResult<()>;
+
+    /// Create captcha of the demo account `username` in demo session `session`,
+    /// unless the account has `limit` captchas or the session created
+    /// `session_limit` of them. A `session_limit` of zero disables the session
+    /// limit. The limits are checked and the captcha is created atomically
+    async fn create_demo_captcha(
+        &self,
+        username: &str,
+        p: &CreateCaptcha,
+        session: Option<&str>,
+        created_at: i64,
+        limit: usize,
+        session_limit: usize,
+    ) -> DBResult<()>;
+
+    /// Count captchas that were created in a demo session
+    async fn count_demo_sitekeys(&self, session: &str) -> DBResult<usize>;
+
+    /// Check if a captcha was created from the demo account
+    async fn is_demo_sitekey(&self, captcha_key: &str) -> DBResult<bool>;
+
+    /// Get keys of captchas that were created from the demo account before unix
+    /// timestamp `created_before`
+    async fn get_expired_demo_sitekeys(
+        &self,
+        created_before: i64,
+    ) -> DBResult<Vec<String>>;
+
+    /// Record that user `username` is a trial account that expires at unix
+    /// timestamp `expires_at`
+    async fn add_trial(&self, username: &str, expires_at: i64) -> DBResult<()>;
+
+    /// Count trial accounts, including expired ones that weren't deleted yet
+    async fn count_trials(&self) -> DBResult<usize>;
+
+    /// Get usernames of trial accounts that expired at or before unix
+    /// timestamp `now`
+    async fn get_expired_trials(&self, now: i64) -> DBResult<Vec<String>>;
+
+    /// Get notifications of a user that match `filter`, newest first, in
+    /// pages of `limit`
+    async fn get_notifications(
+        &self,
+        username: &str,
+        filter: &NotificationFilter,
+        page: usize,
+        limit: usize,
+    ) -> DBResult<Vec<Notification>>;
+
+    /// Add API token of a user
+    async fn add_api_token(&self, p: &AddApiToken) -> DBResult<()>;
+
+    /// Get API tokens of a user, newest first
+    async fn get_api_tokens(&self, username: &str) -> DBResult<Vec<ApiToken>>;
+
+    /// Delete API token of a user
+    async fn delete_api_token(&self, username: &str, id: i32) -> DBResult<()>;
+
+    /// Get the owner and scope of the API token whose hash is `token_hash`
+    async fn get_api_token_owner(&self, token_hash: &str) -> DBResult<ApiTokenOwner>;
+
+    /// Mark all notifications of a user read
+    async fn mark_all_notifications_read(&self, username: &str) -> DBResult<()>;
+
+    /// Delete a notification of a user
+    async fn delete_notification(&self, username: &str, id: i32) -> DBResult<()>;
+
+    /// Set webhook that receives notifications of a user; replaces existing
+    /// webhook, if any
+    async fn set_notification_webhook(
+        &self,
+        username: &str,
+        webhook: &NotificationWebhook,
+    ) -> DBResult<()>;
+
+    /// Get webhook that receives notifications of a user
+    async fn get_notification_webhook(
+        &self,
+        username: &str,
+    ) -> DBResult<NotificationWebhook>;
+
+    /// Delete webhook that receives notifications of a user
+    async fn delete_notification_webhook(&self, username: &str) -> DBResult<()>;
+
+    /// Log notification webhook delivery attempt
+    async fn record_notification_webhook_delivery(
+        &self,
+        username: &str,
+        delivery: &CreateWebhookDelivery,
+    ) -> DBResult<()>;
+
+    /// Fetch notification webhook delivery logs of a user, newest first
+    async fn fetch_notification_webhook_deliveries(
+        &self,
+        username: &str,
+        limit: u32,
+    ) -> DBResult<Vec<WebhookDelivery>>;
+
+    /// Set thresholds of automatic notifications on traffic of a captcha,
+    /// enabling them; replaces existing thresholds, if any
+    async fn set_alert_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        thresholds: &AlertThresholds,
+    ) -> DBResult<()>;
+
+    /// Get thresholds of automatic notifications on traffic of a captcha
+    async fn get_alert_thresholds(&self, captcha_key: &str)
+        -> DBResult<AlertThresholds>;
+
+    /// Delete thresholds of automatic notifications on traffic of a captcha,
+    /// disabling them
+    async fn delete_alert_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()>;
+
+    /// Get all captchas whose alert thresholds enable anomaly detection
+    async fn get_captchas_with_anomaly_alerts(&self) -> DBResult<Vec<OwnedCaptcha>>;
+
+    /// Set the theme of the widget of a captcha; replaces the existing theme,
+    /// if any
+    async fn set_widget_theme(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        theme: &WidgetTheme,
+    ) -> DBResult<()>;
+
+    /// Get the theme of the widget of a captcha
+    async fn get_widget_theme(&self, captcha_key: &str) -> DBResult<WidgetTheme>;
+
+    /// Delete the theme of the widget of a captcha, restoring the default
+    async fn delete_widget_theme(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()>;
+
+    /// Set invisible mode of a captcha
+    async fn update_captcha_invisible_mode(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        mode: &InvisibleMode,
+    ) -> DBResult<()>;
+
+    /// Get invisible mode of a captcha
+    async fn captcha_invisible_mode(&self, captcha_key: &str)
+        -> DBResult<InvisibleMode>;
+
+    /// Set the fallback challenge of a captcha
+    async fn update_captcha_fallback(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        fallback: &Fallback,
+    ) -> DBResult<()>;
+
+    /// Get the fallback challenge of a captcha
+    async fn captcha_fallback(&self, captcha_key: &str) -> DBResult<Fallback>;
+
+    /// Delete up to `limit` read notifications, of all users, that were
+    /// received before `before`. Returns the number of deleted notifications
+    async fn delete_read_notifications(&self, before: i64, limit: u32) -> DBResult<u64>;
+
+    /// fetch up to `limit` PoWConfig fetches within `range`, oldest first,
+    /// skipping the first `offset`
+    async fn fetch_config_fetched_range(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+        offset: usize,
+    ) -> DBResult<Vec<i64>>;
+
+    /// fetch up to `limit` PoWConfig solves within `range`, oldest first,
+    /// skipping the first `offset`
+    async fn fetch_solve_range(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+        offset: usize,
+    ) -> DBResult<Vec<i64>>;
+
+    /// fetch up to `limit` PoWConfig confirms within `range`, oldest first,
+    /// skipping the first `offset`
+    async fn fetch_confirm_range(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+        offset: usize,
+    ) -> DBResult<Vec<i64>>;
+
+    /// fetch the latest `limit` PoWConfig fetches within `range`, newest first
+    async fn fetch_config_fetched_latest(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+    ) -> DBResult<Vec<i64>>;
+
+    /// fetch the latest `limit` PoWConfig solves within `range`, newest first
+    async fn fetch_solve_latest(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+    ) -> DBResult<Vec<i64>>;
+
+    /// fetch the latest `limit` PoWConfig confirms within `range`, newest first
+    async fn fetch_confirm_latest(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+    ) -> DBResult<Vec<i64>>;
+
+    /// count fetches, solves and confirms within `range` in buckets of
+    /// `bucket` seconds, aligned to the unix epoch, oldest first. Empty
+    /// buckets are omitted
+    async fn fetch_stats_series(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        bucket: u32,
+    ) -> DBResult<Vec<StatsBucket>>;
+
+    /// Set whether recommended levels are applied to a captcha automatically
+    async fn update_captcha_auto_recommendation(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        enabled: bool,
+    ) -> DBResult<()>;
+
+    /// Check if recommended levels are applied to a captcha automatically
+    async fn captcha_auto_recommendation(&self, captcha_key: &str) -> DBResult<bool>;
+
+    /// Get all captchas that recommended levels are applied to automatically
+    async fn get_captchas_with_auto_recommendation(&self)
+        -> DBResult<Vec<OwnedCaptcha>>;
+
+    /// count solves and confirms by country of the solver, most solves first.
+    /// Records whose country isn't known are left out
+    async fn fetch_country_stats(
+        &self,
+        user: &str,
+        key: &str,
+    ) -> DBResult<Vec<CountryStats>>;
+
+    /// Record PoW challenge `string` of a captcha, issued by an instance of a
+    /// cluster, that can be verified until unix timestamp `expires_at`
+    async fn add_cluster_challenge(
+        &self,
+        captcha_key: &str,
+        string: &str,
+        difficulty_factor: u32,
+        expires_at: i64,
+    ) -> DBResult<()>;
+
+    /// Remove PoW challenge `string` of a captcha to verify it. Returns its
+    /// difficulty factor, or `None` if it wasn't issued, was verified already
+    /// or expired by unix timestamp `now`
+    async fn take_cluster_challenge(
+        &self,
+        captcha_key: &str,
+        string: &str,
+        now: i64,
+    ) -> DBResult<Option<u32>>;
+
+    /// Record validation token of a captcha, issued by an instance of a
+    /// cluster to the client whose IP address hashes to `ip_hash`, that can be
+    /// validated until unix timestamp `expires_at`
+    async fn add_cluster_token(
+        &self,
+        captcha_key: &str,
+        token: &str,
+        ip_hash: &str,
+        expires_at: i64,
+    ) -> DBResult<()>;
+
+    /// Get hash of the IP address that a cluster's validation token of a
+    /// captcha was issued to, or `None` if it wasn't issued, was validated
+    /// already or expired by unix timestamp `now`
+    async fn get_cluster_token_ip(
+        &self,
+        captcha_key: &str,
+        token: &str,
+        now: i64,
+    ) -> DBResult<Option<String>>;
+
+    /// Remove validation token of a captcha to validate it. Returns false if it
+    /// wasn't issued, was validated already or expired by unix timestamp `now`
+    async fn take_cluster_token(
+        &self,
+        captcha_key: &str,
+        token: &str,
+        now: i64,
+    ) -> DBResult<bool>;
+
+    /// Record visitor counts of captchas, as counted by instance `instance_id`
+    /// of a cluster at unix timestamp `now`
+    async fn report_cluster_visitors(
+        &self,
+        instance_id: &str,
+        visitors: &[VisitorCount],
+        now: i64,
+    ) -> DBResult<()>;
+
+    /// Get visitor counts of captchas, summed over the instances of a cluster
+    /// other than `instance_id` that reported at or after unix timestamp `since`
+    async fn get_cluster_visitors(
+        &self,
+        instance_id: &str,
+        since: i64,
+    ) -> DBResult<Vec<VisitorCount>>;
+
+    /// Record fallback challenge `id` of a captcha, started on an instance of a
+    /// cluster, that can be completed from unix timestamp `ready_at` until
+    /// `expires_at`
+    async fn add_cluster_fallback(
+        &self,
+        captcha_key: &str,
+        id: &str,
+        ready_at: i64,
+        expires_at: i64,
+    ) -> DBResult<()>;
+
+    /// Get unix timestamp from which a cluster's fallback challenge `id` of a
+    /// captcha can be completed, or `None` if it wasn't started, was completed
+    /// already or expired by unix timestamp `now`
+    async fn get_cluster_fallback(
+        &self,
+        captcha_key: &str,
+        id: &str,
+        now: i64,
+    ) -> DBResult<Option<i64>>;
+
+    /// Remove fallback challenge `id` of a captcha to complete it. Returns
+    /// false if it wasn't started, was completed already, isn't ready yet or
+    /// expired by unix timestamp `now`
+    async fn take_cluster_fallback(
+        &self,
+        captcha_key: &str,
+        id: &str,
+        now: i64,
+    ) -> DBResult<bool>;
+
+    /// Count a fallback challenge started at unix timestamp `now`, on any
+    /// instance of a cluster, by the client whose IP address hashes to
+    /// `ip_hash`. When the client's window ended by `now`, a new one that ends
+    /// at `expires_at` is started. Returns the challenges counted in the window
+    async fn count_cluster_fallback(
+        &self,
+        ip_hash: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> DBResult<u32>;
+
+    /// Delete cluster challenges, fallback challenges, fallback windows and
+    /// tokens that expired by unix timestamp `now`, and visitor counts reported
+    /// before `stale_before`
+    async fn purge_cluster_state(&self, now: i64, stale_before: i64) -> DBResult<()>;
+
+    /// Add email to the outbox. Returns its ID
+    async fn add_outbox_email(&self, e: &AddOutboxEmail<'_>) -> DBResult<i32>;
+
+    /// Count emails in the outbox, without those that were given up on
+    async fn count_outbox_emails(&self) -> DBResult<usize>;
+
+    /// Get up to `limit` emails of the outbox that are due to be sent by unix
+    /// timestamp `now`, oldest first
+    async fn get_due_outbox_emails(
+        &self,
+        now: i64,
+        limit: usize,
+    ) -> DBResult<Vec<OutboxEmail>>;
+
+    /// Claim email for an attempt at sending it, if it was attempted `attempts`
+    /// times so far. Other instances don't attempt it until unix timestamp
+    /// `lease_until`. Returns false if it was claimed in the meantime
+    async fn claim_outbox_email(
+        &self,
+        id: i32,
+        attempts: u32,
+        lease_until: i64,
+    ) -> DBResult<bool>;
+
+    /// Delete email that was sent from the outbox
+    async fn delete_outbox_email(&self, id: i32) -> DBResult<()>;
+
+    /// Record failed attempt at sending email. It is attempted again at unix
+    /// timestamp `next_attempt_at`, or given up on and moved to the dead
+    /// letters when `None`
+    async fn fail_outbox_email(
+        &self,
+        id: i32,
+        error: &str,
+        next_attempt_at: Option<i64>,
+    ) -> DBResult<()>;
+
+    /// Get emails that were given up on, newest first
+    async fn get_dead_letters(&self) -> DBResult<Vec<OutboxEmail>>;
+
+    /// Move email from the dead letters back to the outbox, to be sent from
+    /// unix timestamp `now`
+    async fn retry_dead_letter(&self, id: i32, now: i64) -> DBResult<()>;
+
+    /// Count escalations by visitor count in the escalation log of a captcha
+    /// at or after unix timestamp `since`
+    async fn count_escalations(&self, captcha_key: &str, since: i64) -> DBResult<u64>;
+
+    /// Subscribe a user to stats digests, or change the frequency of their
+    /// subscription. New subscriptions are first sent after one period
+    async fn set_digest_frequency(
+        &self,
+        username: &str,
+        frequency: DigestFrequency,
+    ) -> DBResult<()>;
+
+    /// Get the frequency of the stats digests of a user, if subscribed
+    async fn get_digest_frequency(
+        &self,
+        username: &str,
+    ) -> DBResult<Option<DigestFrequency>>;
+
+    /// Unsubscribe a user from stats digests
+    async fn delete_digest_subscription(&self, username: &str) -> DBResult<()>;
+
+    /// Get all stats digest subscriptions
+    async fn get_digest_subscriptions(&self) -> DBResult<Vec<DigestSubscription>>;
+
+    /// Mark the digest of a user as sent at unix timestamp `now`, if it was
+    /// last sent at or before `due_before`. Returns false if it was sent in the
+    /// meantime
+    async fn claim_digest(
+        &self,
+        username: &str,
+        due_before: i64,
+        now: i64,
+    ) -> DBResult<bool>;
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Frequency of stats digests
+pub enum DigestFrequency {
+    Daily,
+    #[default]
+    Weekly,
+    Monthly,
+}
+
+impl DigestFrequency {
+    /// name of the frequency, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+        }
+    }
+
+    /// parse name of a frequency, as stored in the database
+    pub fn parse(frequency: &str) -> Option<Self> {
+        match frequency {
+            "daily" => Some(Self::Daily),
+            "weekly" => Some(Self::Weekly),
+            "monthly" => Some(Self::Monthly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Stats digest subscription of a user
+pub struct DigestSubscription {
+    pub username: String,
+    pub frequency: DigestFrequency,
+    /// unix timestamp at which the last digest was sent, or of the
+    /// subscription when none was sent yet
+    pub last_sent: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+/// Email to add to the outbox
+pub struct AddOutboxEmail<'a> {
+    /// envelope sender
+    pub sender: Option<&'a str>,
+    /// envelope recipients
+    pub recipients: &'a [String],
+    /// formatted message, with headers
+    pub message: &'a str,
+    /// unix timestamp from which the email is due to be sent
+    pub next_attempt_at: i64,
+    /// time of creation
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Email in the outbox
+pub struct OutboxEmail {
+    /// database assigned ID of the email
+    pub id: i32,
+    /// envelope sender
+    pub sender: Option<String>,
+    /// envelope recipients
+    pub recipients: Vec<String>,
+    /// formatted message, with headers
+    pub message: String,
+    /// number of attempts at sending the email
+    pub attempts: u32,
+    /// error of the last failed attempt
+    pub last_error: Option<String>,
+    /// time of creation
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Stats entry that is written in batches
+pub struct StatsRecord {
+    /// captcha key
+    pub key: String,
+    /// unix timestamp of the event
+    pub time: i64,
+    /// ISO 3166-1 alpha-2 code of the country of the solver, if known. Not
+    /// recorded for fetches
+    pub country: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Number of solves and confirms from a country
+pub struct CountryStats {
+    /// ISO 3166-1 alpha-2 country code
+    pub country: String,
+    pub solves: u64,
+    pub confirms: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Number of visitors of a captcha, as counted by instances of a cluster
+pub struct VisitorCount {
+    /// captcha key
+    pub key: String,
+    pub visitors: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Range of stats records, as unix timestamps
+pub struct TimeRange {
+    /// start of the range, inclusive
+    pub from: i64,
+    /// end of the range, exclusive
+    pub to: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Number of stats records within a time bucket
+pub struct StatsBucket {
+    /// start of the bucket, as a unix timestamp
+    pub time: i64,
+    pub config_fetches: u64,
+    pub solves: u64,
+    pub confirms: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Webhook that is notified of verification events on a captcha
+pub struct Webhook {
+    /// URL to which events are POSTed
+    pub url: String,
+    /// secret used to sign event payloads
+    pub secret: String,
+    /// notify on difficulty escalations
+    pub on_escalation: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Webhook that receives the notifications of a user
+pub struct NotificationWebhook {
+    /// URL to which notifications are POSTed
+    pub url: String,
+    /// secret used to sign notification payloads
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Data required to log a webhook delivery attempt
+pub struct CreateWebhookDelivery<'a> {
+    /// event that was delivered
+    pub event: &'a str,
+    /// HTTP status code returned by the receiver; None when the request failed
+    pub status: Option<u16>,
+    /// delivery attempt number, starting from 1
+    pub attempt: u32,
+    /// was the delivery successful
+    pub success: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Webhook delivery log entry
+pub struct WebhookDelivery {
+    /// event that was delivered
+    pub event: String,
+    /// HTTP status code returned by the receiver; None when the request failed
+    pub status: Option<u16>,
+    /// delivery attempt number, starting from 1
+    pub attempt: u32,
+    /// was the delivery successful
+    pub success: bool,
+    /// time of delivery attempt
+    pub time: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Thresholds of fraud heuristics of a captcha. Signals are disabled by setting
+/// their threshold to 0
+pub struct FraudThresholds {
+    /// hashes per second above which a reported solve time is implausible
+    pub max_hash_rate: u32,
+    /// multiple of the difficulty factor above which a nonce is an outlier
+    pub nonce_outlier_factor: u32,
+    /// number of times that an identical result can be submitted before it is
+    /// suspicious
+    pub repeated_result_limit: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Thresholds of automatic notifications on traffic of a captcha
+pub struct AlertThresholds {
+    /// notify when the visitor count crosses the visitor threshold of a level
+    pub levels: bool,
+    /// notify when difficulty escalates to the highest level
+    pub highest_level: bool,
+    /// percentage of failed solves above which to notify; 0 disables it
+    pub failure_rate: u32,
+    /// number of solves that have to be attempted before the failure rate is
+    /// checked
+    pub min_attempts: u32,
+    /// multiple of the usual traffic above which fetch floods and solve spikes
+    /// are reported; 0 disables anomaly detection
+    #[serde(default)]
+    pub anomaly_factor: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Color scheme of the widget of a captcha
+pub enum ThemeMode {
+    Light,
+    Dark,
+    /// follows the color scheme preferred by the visitor
+    #[default]
+    Auto,
+}
+
+impl ThemeMode {
+    /// name of the color scheme, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Light => "light",
+            Self::Dark => "dark",
+            Self::Auto => "auto",
+        }
+    }
+
+    /// parse name of a color scheme, as stored in the database
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "light" => Some(Self::Light),
+            "dark" => Some(Self::Dark),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+/// Appearance of the widget of a captcha
+pub struct WidgetTheme {
+    pub mode: ThemeMode,
+    /// color of the checkbox and progress bar, as `#rrggbb`
+    pub accent_color: String,
+    /// background color, as `#rrggbb`; that of the color scheme when unset
+    pub background_color: Option<String>,
+    /// text color, as `#rrggbb`; that of the color scheme when unset
+    pub text_color: Option<String>,
+    /// radius of the corners of the widget, in pixels
+    pub border_radius: u32,
+    /// show the mCaptcha logo and links
+    pub show_logo: bool,
+}
+
+impl Default for WidgetTheme {
+    fn default() -> Self {
+        Self {
+            mode: ThemeMode::default(),
+            accent_color: "#65a2e0".into(),
+            background_color: None,
+            text_color: None,
+            border_radius: 0,
+            show_logo: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Invisible mode of a captcha: challenges are solved in the background,
+/// without visitor interaction, while their difficulty factor is at most
+/// `threshold`
+pub struct InvisibleMode {
+    pub enabled: bool,
+    /// highest difficulty factor of challenges that are solved in the
+    /// background
+    pub threshold: u32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+/// Fallback challenge of a captcha, for visitors who can't solve PoW: they
+/// wait `wait` seconds instead
+pub struct Fallback {
+    pub enabled: bool,
+    /// seconds that visitors wait before they are issued validation tokens
+    pub wait: u32,
+}
+
+impl Default for Fallback {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            wait: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Captcha and its owner
+pub struct OwnedCaptcha {
+    /// username of the owner of the captcha
+    pub owner: String,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Captcha that can be embedded on any domain
+pub struct UnrestrictedCaptcha {
+    /// username of the owner of the captcha
+    pub owner: String,
+    pub key: String,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Shape of the decline of difficulty once an attack subsides
+pub enum DecayCurve {
+    /// difficulty falls by the same amount each step
+    #[default]
+    Linear,
+    /// difficulty falls by half of what remains each step
+    Exponential,
+}
+
+impl DecayCurve {
+    /// name of the curve, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::Exponential => "exponential",
+        }
+    }
+
+    /// parse name of a curve, as stored in the database
+    pub fn parse(curve: &str) -> Option<Self> {
+        match curve {
+            "linear" => Some(Self::Linear),
+            "exponential" => Some(Self::Exponential),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Gradual decline of the difficulty of a captcha after a sustained attack
+pub struct DifficultyDecay {
+    /// seconds that difficulty has to stay raised before it declines
+    /// gradually. Shorter surges end as soon as visitor counts drop
+    pub sustain: u32,
+    /// seconds between steps down
+    pub step: u32,
+    /// number of steps it takes difficulty to fall to that of the visitor count
+    pub steps: u32,
+    pub curve: DecayCurve,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Change of the difficulty of a captcha
+pub enum EscalationKind {
+    /// visitor counts raised difficulty
+    #[default]
+    Escalation,
+    /// difficulty stepped down after an attack
+    Decay,
+}
+
+impl EscalationKind {
+    /// name of the change, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Escalation => "escalation",
+            Self::Decay => "decay",
+        }
+    }
+
+    /// parse name of a change, as stored in the database
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "escalation" => Some(Self::Escalation),
+            "decay" => Some(Self::Decay),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Entry of the escalation log of a captcha
+pub struct EscalationLogEntry {
+    pub kind: EscalationKind,
+    /// difficulty factor after the change
+    pub difficulty_factor: u32,
+    /// unix timestamp of the change
+    pub time: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Quotas of a user that override instance-wide quotas. Unset quotas follow
+/// the instance; 0 disables a quota
+pub struct UserQuota {
+    /// maximum number of captchas the user can hold
+    pub max_captchas: Option<u32>,
+    /// maximum number of analytics records stored per captcha
+    pub max_analytics_records: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Traffic burst to schedule for a captcha
+pub struct CreateBurst {
+    /// unix timestamp at which the burst starts
+    pub starts: i64,
+    /// unix timestamp at which the burst ends
+    pub ends: i64,
+    /// minimum difficulty factor served during the burst
+    pub difficulty_factor: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Traffic burst scheduled for a captcha
+pub struct Burst {
+    pub id: i32,
+    /// captcha key
+    pub key: String,
+    /// unix timestamp at which the burst starts
+    pub starts: i64,
+    /// unix timestamp at which the burst ends
+    pub ends: i64,
+    /// minimum difficulty factor served during the burst
+    pub difficulty_factor: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// User of the instance, as listed to administrators
+pub struct InstanceUser {
+    pub name: String,
+    pub email: Option<String>,
+    /// is the user an instance administrator
+    pub is_admin: bool,
+    /// number of captchas owned by the user
+    pub captchas: u32,
+    /// data residency region of the user
+    pub residency: Option<String>,
+    /// is the user suspended
+    pub suspended: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Registration that is pending approval
+pub struct PendingRegistration {
+    pub name: String,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Instance-wide counts
+pub struct InstanceStats {
+    /// number of registered users
+    pub users: u32,
+    /// number of captchas
+    pub captchas: u32,
+}
+
+#[derive(
+    Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+/// Role of a member of an organization. Roles are ordered by privilege
+pub enum OrgRole {
+    /// can view the organization's captchas
+    #[default]
+    Viewer,
+    /// can also update the organization's captchas and add captchas to it
+    Editor,
+    /// can also manage members and remove captchas from the organization
+    Owner,
+}
+
+impl OrgRole {
+    /// name of the role, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Owner => "owner",
+        }
+    }
+
+    /// parse name of a role, as stored in the database
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "viewer" => Some(Self::Viewer),
+            "editor" => Some(Self::Editor),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Invitation to issue
+pub struct CreateInvite<'a> {
+    /// invitation token, as sent in the signup link
+    pub token: &'a str,
+    /// email address of the invitee
+    pub email: &'a str,
+    /// organization that the invitee joins on signup
+    pub org: Option<&'a str>,
+    /// role of the invitee in `org`
+    pub role: Option<OrgRole>,
+    /// unix timestamp at which the invitation expires
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Status of an invitation
+pub enum InviteStatus {
+    Pending,
+    Accepted,
+    Expired,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Invitation to sign up
+pub struct Invite {
+    pub id: i32,
+    /// email address of the invitee
+    pub email: String,
+    /// organization that the invitee joins on signup
+    pub org: Option<String>,
+    /// role of the invitee in `org`
+    pub role: Option<OrgRole>,
+    /// user who issued the invitation
+    pub invited_by: String,
+    /// unix timestamp at which the invitation was issued
+    pub created_at: i64,
+    /// unix timestamp at which the invitation expires
+    pub expires_at: i64,
+    /// unix timestamp at which the invitation was accepted
+    pub accepted_at: Option<i64>,
+}
+
+impl Invite {
+    /// status of the invitation at unix timestamp `now`
+    pub fn status(&self, now: i64) -> InviteStatus {
+        if self.accepted_at.is_some() {
+            InviteStatus::Accepted
+        } else if self.expires_at <= now {
+            InviteStatus::Expired
+        } else {
+            InviteStatus::Pending
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Member of an organization
+pub struct OrgMember {
+    pub username: String,
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Organization of which a user is a member
+pub struct OrgMembership {
+    /// name of the organization
+    pub org: String,
+    /// role of the user in the organization
+    pub role: OrgRole,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// TOTP two-factor authentication configuration of a user
+pub struct Totp {
+    /// base32 encoded TOTP secret
+    pub secret: String,
+    /// is two-factor authentication enabled; false while enrollment is pending
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Data required to add an email verification token
+pub struct AddEmailVerification<'a> {
+    /// verification token, as sent in the verification link
+    pub token: &'a str,
+    /// email address that is being verified
+    pub email: &'a str,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Pending email verification
+pub struct EmailVerification {
+    /// user to whom the token was issued
+    pub username: String,
+    /// email address that is being verified
+    pub email: String,
+    /// time at which the token was created
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Data required to add a pending email change
+pub struct AddEmailChange<'a> {
+    /// confirmation token, as sent to the new address
+    pub token: &'a str,
+    /// cancellation token, as sent to the current address
+    pub cancel_token: &'a str,
+    /// new email address
+    pub email: &'a str,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Pending email change
+pub struct EmailChange {
+    /// user that requested the change
+    pub username: String,
+    /// new email address
+    pub email: String,
+    /// time at which the change was requested
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Token that revokes all sessions of a user, as sent in security alerts
+pub struct SessionRevocation {
+    /// user to whom the token was issued
+    pub username: String,
+    /// time at which the token was created
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// OpenID Connect identity
+pub struct OidcIdentity<'a> {
+    /// issuer of the identity
+    pub issuer: &'a str,
+    /// subject identifier, unique within the issuer
+    pub subject: &'a str,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Data required to create a sign-in session
+pub struct CreateSession<'a> {
+    /// session identifier, as stored in the session cookie
+    pub id: &'a str,
+    /// IP address from which the user signed in
+    pub ip: &'a str,
+    /// user agent of the device from which the user signed in
+    pub user_agent: &'a str,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Sign-in session
+pub struct Session {
+    /// session identifier
+    pub id: String,
+    /// user to whom the session belongs
+    pub username: String,
+    /// IP address from which the user signed in
+    pub ip: String,
+    /// user agent of the device from which the user signed in
+    pub user_agent: String,
+    /// time of sign-in
+    pub created_at: i64,
+    /// time of last activity
+    pub last_active: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Data required to add an API token
+pub struct AddApiToken<'a> {
+    /// user to whom the token belongs
+    pub username: &'a str,
+    /// name of the token, chosen by the user
+    pub name: &'a str,
+    /// hash of the token; tokens themselves aren't stored
+    pub token_hash: &'a str,
+    /// what the token can be used for
+    pub scope: &'a str,
+    /// time of creation
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// API token of a user
+pub struct ApiToken {
+    /// database assigned ID of the token
+    pub id: i32,
+    /// name of the token, chosen by the user
+    pub name: String,
+    /// what the token can be used for
+    pub scope: String,
+    /// time of creation
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Owner and scope of an API token
+pub struct ApiTokenOwner {
+    /// user to whom the token belongs
+    pub username: String,
+    /// what the token can be used for
+    pub scope: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Data required to record acceptance of a legal document
+pub struct AcceptAgreement<'a> {
+    /// document that was accepted, e.g. "tos"
+    pub document: &'a str,
+    /// version of the document that was accepted
+    pub version: &'a str,
+    /// hash of the IP address from which the document was accepted
+    pub ip_hash: &'a str,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Legal document acceptance record
+pub struct Agreement {
+    /// document that was accepted
+    pub document: String,
+    /// version of the document that was accepted
+    pub version: String,
+    /// hash of the IP address from which the document was accepted
+    pub ip_hash: String,
+    /// time of acceptance
+    pub accepted_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Log Proof-of-Work CAPTCHA performance analytics
+pub struct CreatePerformanceAnalytics {
+    /// time taken to generate proof
+    pub time: u32,
+    /// difficulty factor for which the proof was generated
+    pub difficulty_factor: u32,
+    /// worker/client type: wasm, javascript, python, etc.
+    pub worker_type: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Proof-of-Work CAPTCHA performance analytics
+pub struct PerformanceAnalytics {
+    /// log ID
+    pub id: usize,
+    /// time taken to generate proof
+    pub time: u32,
+    /// difficulty factor for which the proof was generated
+    pub difficulty_factor: u32,
+    /// worker/client type: wasm, javascript, python, etc.
+    pub worker_type: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Percentiles of the time taken to generate proofs, for a difficulty factor
+/// and worker type
+pub struct SolveTimePercentiles {
+    pub difficulty_factor: u32,
+    /// worker/client type: wasm, javascript, python, etc.
+    pub worker_type: String,
+    /// number of analytics records
+    pub samples: u64,
+    pub p50: u32,
+    pub p90: u32,
+    pub p99: u32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Captcha statistics with time recorded in UNIX epoch formats
+pub struct StatsUnixTimestamp {
+    /// times at which the configuration were fetched
+    pub config_fetches: Vec<i64>,
+    /// times at which the PoW was solved
+    pub solves: Vec<i64>,
+    /// times at which the PoW token was verified
+    pub confirms: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+/// Represents notification
+pub struct Notification {
+    /// receiver name  of the notification
+    pub name: Option<String>,
+    /// heading of the notification
+    pub heading: Option<String>,
+    /// message of the notification
+    pub message: Option<String>,
+    /// when notification was received
+    pub received: Option<i64>,
+    /// db assigned ID of the notification
+    pub id: Option<i32>,
+    /// is the message encrypted to the receiver's notification key
+    pub encrypted: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+/// Read status of notifications to get
+pub enum NotificationStatus {
+    /// read and unread notifications
+    All,
+    /// only notifications that were marked read
+    Read,
+    /// only notifications that weren't marked read
+    #[default]
+    Unread,
+}
+
+impl NotificationStatus {
+    /// read status that notifications must have, if any
+    pub fn read(&self) -> Option<bool> {
+        match self {
+            Self::All => None,
+            Self::Read => Some(true),
+            Self::Unread => Some(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Filter on the notifications of a user
+pub struct NotificationFilter {
+    /// read status of notifications
+    pub status: NotificationStatus,
+    /// only notifications received at or after this unix timestamp
+    pub after: Option<i64>,
+    /// only notifications received before this unix timestamp
+    pub before: Option<i64>,
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
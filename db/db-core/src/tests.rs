@@ -4,6 +4,10 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 //! Test utilities
+use proptest::prelude::*;
+use proptest::strategy::ValueTree;
+use proptest::test_runner::TestRunner;
+
 use crate::errors::*;
 use crate::prelude::*;
 
@@ -192,6 +196,92 @@ pub async fn database_works<'a, T: MCDatabase>(
         .unwrap();
     let new_notifications = db.get_all_unread_notifications(an.to).await.unwrap();
     assert_eq!(new_notifications.len(), 1);
+    assert!(!new_notifications[0].encrypted);
+    // only read notifications received before the cutoff are deleted
+    assert_eq!(db.delete_read_notifications(0, 10).await.unwrap(), 0);
+
+    // filter and paginate notifications
+    let mut filter = NotificationFilter::default();
+    let unread = db.get_notifications(an.to, &filter, 0, 10).await.unwrap();
+    assert_eq!(unread, new_notifications);
+    filter.status = NotificationStatus::Read;
+    let read = db.get_notifications(an.to, &filter, 0, 10).await.unwrap();
+    assert_eq!(read.len(), 1);
+    assert_eq!(read[0].id, notifications[0].id);
+    filter.status = NotificationStatus::All;
+    assert_eq!(
+        db.get_notifications(an.to, &filter, 0, 10)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        db.get_notifications(an.to, &filter, 1, 1)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(db
+        .get_notifications(an.to, &filter, 1, 2)
+        .await
+        .unwrap()
+        .is_empty());
+    let received = read[0].received.unwrap();
+    filter.after = Some(received + 1);
+    assert!(db
+        .get_notifications(an.to, &filter, 0, 10)
+        .await
+        .unwrap()
+        .is_empty());
+    filter.after = None;
+    filter.before = Some(received - 600);
+    assert!(db
+        .get_notifications(an.to, &filter, 0, 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // delete and bulk mark-read notifications
+    db.delete_notification(an.to, notifications[0].id.unwrap())
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.delete_notification(an.to, notifications[0].id.unwrap())
+            .await,
+        Err(DBError::NotificationNotFound)
+    ));
+    db.create_notification(an).await.unwrap();
+    assert_eq!(
+        db.get_all_unread_notifications(an.to).await.unwrap().len(),
+        2
+    );
+    db.mark_all_notifications_read(an.to).await.unwrap();
+    assert!(db
+        .get_all_unread_notifications(an.to)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // 4. encrypted notifications
+    assert_eq!(db.get_notification_key(an.to).await.unwrap(), None);
+    db.set_notification_key(an.to, Some("publickey"))
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_notification_key(an.to).await.unwrap().as_deref(),
+        Some("publickey")
+    );
+    db.create_encrypted_notification(an).await.unwrap();
+    let notifications = db.get_all_unread_notifications(an.to).await.unwrap();
+    assert_eq!(notifications.iter().filter(|n| n.encrypted).count(), 1);
+    db.set_notification_key(an.to, None).await.unwrap();
+    assert_eq!(db.get_notification_key(an.to).await.unwrap(), None);
+    assert!(matches!(
+        db.set_notification_key("nonexistentkeyuser", None).await,
+        Err(DBError::AccountNotFound)
+    ));
 
     // create captcha
     db.create_captcha(p.username, c).await.unwrap();
@@ -212,6 +302,35 @@ pub async fn database_works<'a, T: MCDatabase>(
     let all_user_captchas = db.get_all_user_captchas(p.username).await.unwrap();
     assert_eq!(all_user_captchas.len(), 1);
     assert_eq!(all_user_captchas[0], captcha);
+    assert_eq!(db.count_user_captchas(p.username).await.unwrap(), 1);
+    assert_eq!(
+        db.get_user_captchas_page(p.username, 0, 10).await.unwrap(),
+        vec![captcha.clone()]
+    );
+    assert!(db
+        .get_user_captchas_page(p.username, 1, 10)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(db
+        .get_all_captcha_keys()
+        .await
+        .unwrap()
+        .contains(&c.key.to_string()));
+    assert!(db
+        .get_recently_active_captcha_keys(1000)
+        .await
+        .unwrap()
+        .contains(&c.key.to_string()));
+    assert_eq!(db.get_captcha_owner(c.key).await.unwrap(), p.username);
+    assert!(db
+        .captcha_description_exists(p.username, c.description, None)
+        .await
+        .unwrap());
+    assert!(!db
+        .captcha_description_exists(p.username, c.description, Some(c.key))
+        .await
+        .unwrap());
 
     // get captcha cooldown duration
     assert_eq!(db.get_captcha_cooldown(c.key).await.unwrap(), c.duration);
@@ -274,8 +393,8 @@ pub async fn database_works<'a, T: MCDatabase>(
         .is_empty());
 
     db.record_fetch(c.key).await.unwrap();
-    db.record_solve(c.key).await.unwrap();
-    db.record_confirm(c.key).await.unwrap();
+    db.record_solve(c.key, None).await.unwrap();
+    db.record_confirm(c.key, Some("IN")).await.unwrap();
 
     // analytics start
     db.analytics_create_psuedo_id_if_not_exists(c.key)
@@ -346,6 +465,12 @@ pub async fn database_works<'a, T: MCDatabase>(
         .await
         .unwrap()
         .is_empty());
+    assert_eq!(db.analytics_fetch_after(c.key, 0, limit).await.unwrap(), a);
+    assert!(db
+        .analytics_fetch_after(c.key, a[0].id, limit)
+        .await
+        .unwrap()
+        .is_empty());
 
     db.analytics_delete_all_records_for_campaign(c.key)
         .await
@@ -378,6 +503,10 @@ pub async fn database_works<'a, T: MCDatabase>(
     for a in rest_analytics.iter() {
         db.analysis_save(c.key, &a).await.unwrap();
     }
+    assert_eq!(
+        db.analytics_count(c.key).await.unwrap(),
+        rest_analytics.len()
+    );
     assert!(db
         .stats_get_entry_at_location_for_time_limit_asc(1, 2)
         .await
@@ -396,6 +525,35 @@ pub async fn database_works<'a, T: MCDatabase>(
         Some(3)
     );
 
+    db.analysis_save(
+        c.key,
+        &CreatePerformanceAnalytics {
+            time: 10,
+            difficulty_factor: 2,
+            worker_type: "wasm".into(),
+        },
+    )
+    .await
+    .unwrap();
+    let percentiles = db
+        .analytics_fetch_percentiles(p.username, c.key)
+        .await
+        .unwrap();
+    assert_eq!(percentiles.len(), rest_analytics.len());
+    assert_eq!(
+        percentiles[0],
+        SolveTimePercentiles {
+            difficulty_factor: 2,
+            worker_type: "wasm".into(),
+            samples: 2,
+            p50: 2,
+            p90: 10,
+            p99: 10,
+        }
+    );
+    assert_eq!(percentiles[3].difficulty_factor, 5);
+    assert_eq!(percentiles[3].p99, 5);
+
     db.analytics_delete_all_records_for_campaign(c.key)
         .await
         .unwrap();
@@ -439,28 +597,1464 @@ pub async fn database_works<'a, T: MCDatabase>(
     assert_eq!(db.fetch_solve(p.username, c.key).await.unwrap().len(), 1);
     assert_eq!(db.fetch_confirm(p.username, c.key).await.unwrap().len(), 1);
 
-    // update captcha key; set key = username;
-    db.update_captcha_key(p.username, c.key, p.username)
+    // batched stats
+    let records = vec![
+        StatsRecord {
+            key: c.key.into(),
+            time: 1_700_000_000,
+            country: Some("DE".into()),
+        },
+        StatsRecord {
+            key: c.key.into(),
+            time: 1_700_000_001,
+            country: None,
+        },
+    ];
+    db.record_fetches(&[]).await.unwrap();
+    db.record_fetches(&records).await.unwrap();
+    db.record_solves(&records).await.unwrap();
+    db.record_confirms(&records).await.unwrap();
+    assert_eq!(
+        db.fetch_config_fetched(p.username, c.key)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+    assert_eq!(db.fetch_solve(p.username, c.key).await.unwrap().len(), 3);
+    assert_eq!(db.fetch_confirm(p.username, c.key).await.unwrap().len(), 3);
+
+    // range-filtered stats
+    let range = TimeRange {
+        from: 1_700_000_000,
+        to: 1_700_000_001,
+    };
+    assert_eq!(
+        db.fetch_config_fetched_range(p.username, c.key, &range, 10, 0)
+            .await
+            .unwrap(),
+        vec![1_700_000_000]
+    );
+    let range = TimeRange {
+        from: 1_700_000_000,
+        to: 1_700_000_002,
+    };
+    assert_eq!(
+        db.fetch_solve_range(p.username, c.key, &range, 10, 0)
+            .await
+            .unwrap(),
+        vec![1_700_000_000, 1_700_000_001]
+    );
+    assert_eq!(
+        db.fetch_confirm_range(p.username, c.key, &range, 1, 1)
+            .await
+            .unwrap(),
+        vec![1_700_000_001]
+    );
+    assert_eq!(
+        db.fetch_solve_latest(p.username, c.key, &range, 10)
+            .await
+            .unwrap(),
+        vec![1_700_000_001, 1_700_000_000]
+    );
+    assert_eq!(
+        db.fetch_confirm_latest(p.username, c.key, &range, 1)
+            .await
+            .unwrap(),
+        vec![1_700_000_001]
+    );
+    assert_eq!(
+        db.fetch_config_fetched_latest(p.username, c.key, &range, 10)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+    let series = db
+        .fetch_stats_series(p.username, c.key, &range, 60)
         .await
         .unwrap();
-    // checking for captcha with old key; shouldn't exist
-    assert!(!db.captcha_exists(Some(p.username), c.key).await.unwrap());
-    // checking for captcha with new key; shouldn exist
+    assert_eq!(
+        series,
+        vec![StatsBucket {
+            time: 1_699_999_980,
+            config_fetches: 2,
+            solves: 2,
+            confirms: 2,
+        }]
+    );
+    let series = db
+        .fetch_stats_series(p.username, c.key, &range, 1)
+        .await
+        .unwrap();
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[1].time, 1_700_000_001);
+
+    // country stats
+    assert_eq!(
+        db.fetch_country_stats(p.username, c.key).await.unwrap(),
+        vec![
+            CountryStats {
+                country: "DE".into(),
+                solves: 1,
+                confirms: 1,
+            },
+            CountryStats {
+                country: "IN".into(),
+                solves: 0,
+                confirms: 1,
+            },
+        ]
+    );
+
+    // automatically applied recommendations
+    assert!(!db.captcha_auto_recommendation(c.key).await.unwrap());
+    db.update_captcha_auto_recommendation(p.username, c.key, true)
+        .await
+        .unwrap();
+    assert!(db.captcha_auto_recommendation(c.key).await.unwrap());
     assert!(db
-        .captcha_exists(Some(p.username), p.username)
+        .get_captchas_with_auto_recommendation()
         .await
-        .unwrap());
+        .unwrap()
+        .contains(&OwnedCaptcha {
+            owner: p.username.into(),
+            key: c.key.into(),
+        }));
+    db.update_captcha_auto_recommendation(p.username, c.key, false)
+        .await
+        .unwrap();
+    assert!(!db
+        .get_captchas_with_auto_recommendation()
+        .await
+        .unwrap()
+        .iter()
+        .any(|c2| c2.key == c.key));
 
-    // delete captcha levels
-    db.delete_captcha_levels(p.username, c.key).await.unwrap();
+    // strict single-use validation tokens
+    assert!(!db.captcha_strict_tokens(c.key).await.unwrap());
+    db.update_captcha_strict_tokens(p.username, c.key, true)
+        .await
+        .unwrap();
+    assert!(db.captcha_strict_tokens(c.key).await.unwrap());
+    assert!(!db.token_is_consumed(c.key, p.username).await.unwrap());
+    assert!(db.consume_token(c.key, p.username).await.unwrap());
+    assert!(db.token_is_consumed(c.key, p.username).await.unwrap());
+    assert!(!db.consume_token(c.key, p.username).await.unwrap());
+    assert!(db
+        .fetch_token_replays(p.username, c.key)
+        .await
+        .unwrap()
+        .is_empty());
+    db.record_token_replay(c.key).await.unwrap();
+    assert_eq!(
+        db.fetch_token_replays(p.username, c.key)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    db.update_captcha_strict_tokens(p.username, c.key, false)
+        .await
+        .unwrap();
+    assert!(!db.captcha_strict_tokens(c.key).await.unwrap());
 
-    // update captcha; set description = username and duration *= duration;
-    let mut c2 = c.clone();
-    c2.duration *= c2.duration;
-    c2.description = p.username;
-    db.update_captcha_metadata(p.username, &c2).await.unwrap();
+    // scheduling priority of PoW verifications
+    assert!(!db.captcha_high_priority(c.key).await.unwrap());
+    db.update_captcha_priority(c.key, true).await.unwrap();
+    assert!(db.captcha_high_priority(c.key).await.unwrap());
+    db.update_captcha_priority(c.key, false).await.unwrap();
+    assert!(!db.captcha_high_priority(c.key).await.unwrap());
 
-    // delete captcha; updated key = p.username so invoke delete with it
-    db.delete_captcha(p.username, p.username).await.unwrap();
-    assert!(!db.captcha_exists(Some(p.username), c.key).await.unwrap());
+    // webhooks
+    assert!(matches!(
+        db.get_webhook(c.key).await,
+        Err(DBError::WebhookNotFound)
+    ));
+    let mut webhook = Webhook {
+        url: "https://example.com/webhook".into(),
+        secret: p.username.into(),
+        on_escalation: false,
+    };
+    db.set_webhook(p.username, c.key, &webhook).await.unwrap();
+    assert_eq!(db.get_webhook(c.key).await.unwrap(), webhook);
+    webhook.on_escalation = true;
+    db.set_webhook(p.username, c.key, &webhook).await.unwrap();
+    assert_eq!(db.get_webhook(c.key).await.unwrap(), webhook);
+    let delivery = CreateWebhookDelivery {
+        event: "solve",
+        status: Some(200),
+        attempt: 1,
+        success: true,
+    };
+    db.record_webhook_delivery(c.key, &delivery).await.unwrap();
+    let deliveries = db
+        .fetch_webhook_deliveries(p.username, c.key, 10)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].event, delivery.event);
+    assert_eq!(deliveries[0].status, delivery.status);
+    assert_eq!(deliveries[0].attempt, delivery.attempt);
+    assert!(deliveries[0].success);
+    db.delete_webhook(p.username, c.key).await.unwrap();
+    assert!(matches!(
+        db.get_webhook(c.key).await,
+        Err(DBError::WebhookNotFound)
+    ));
+
+    // notification webhooks
+    assert!(matches!(
+        db.get_notification_webhook(p.username).await,
+        Err(DBError::WebhookNotFound)
+    ));
+    let mut webhook = NotificationWebhook {
+        url: "https://example.com/notifications".into(),
+        secret: p.username.into(),
+    };
+    db.set_notification_webhook(p.username, &webhook)
+        .await
+        .unwrap();
+    webhook.url = "https://example.com/notifications2".into();
+    db.set_notification_webhook(p.username, &webhook)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_notification_webhook(p.username).await.unwrap(),
+        webhook
+    );
+    let delivery = CreateWebhookDelivery {
+        event: "notification",
+        status: None,
+        attempt: 2,
+        success: false,
+    };
+    db.record_notification_webhook_delivery(p.username, &delivery)
+        .await
+        .unwrap();
+    let deliveries = db
+        .fetch_notification_webhook_deliveries(p.username, 10)
+        .await
+        .unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].event, delivery.event);
+    assert_eq!(deliveries[0].status, None);
+    assert_eq!(deliveries[0].attempt, delivery.attempt);
+    assert!(!deliveries[0].success);
+    db.delete_notification_webhook(p.username).await.unwrap();
+    assert!(matches!(
+        db.delete_notification_webhook(p.username).await,
+        Err(DBError::WebhookNotFound)
+    ));
+
+    // fraud heuristics
+    assert!(matches!(
+        db.get_fraud_thresholds(c.key).await,
+        Err(DBError::FraudThresholdsNotFound)
+    ));
+    let mut thresholds = FraudThresholds {
+        max_hash_rate: 3_000_000_000,
+        nonce_outlier_factor: 10,
+        repeated_result_limit: 3,
+    };
+    db.set_fraud_thresholds(p.username, c.key, &thresholds)
+        .await
+        .unwrap();
+    assert_eq!(db.get_fraud_thresholds(c.key).await.unwrap(), thresholds);
+    thresholds.nonce_outlier_factor = 0;
+    db.set_fraud_thresholds(p.username, c.key, &thresholds)
+        .await
+        .unwrap();
+    assert_eq!(db.get_fraud_thresholds(c.key).await.unwrap(), thresholds);
+    db.delete_fraud_thresholds(p.username, c.key).await.unwrap();
+    assert!(matches!(
+        db.delete_fraud_thresholds(p.username, c.key).await,
+        Err(DBError::FraudThresholdsNotFound)
+    ));
+
+    // automatic notifications
+    assert!(matches!(
+        db.get_alert_thresholds(c.key).await,
+        Err(DBError::AlertThresholdsNotFound)
+    ));
+    let mut alerts = AlertThresholds {
+        levels: true,
+        highest_level: false,
+        failure_rate: 50,
+        min_attempts: 20,
+        anomaly_factor: 0,
+    };
+    db.set_alert_thresholds(p.username, c.key, &alerts)
+        .await
+        .unwrap();
+    assert_eq!(db.get_alert_thresholds(c.key).await.unwrap(), alerts);
+    assert!(!db
+        .get_captchas_with_anomaly_alerts()
+        .await
+        .unwrap()
+        .iter()
+        .any(|c2| c2.key == c.key));
+    alerts.highest_level = true;
+    alerts.anomaly_factor = 5;
+    db.set_alert_thresholds(p.username, c.key, &alerts)
+        .await
+        .unwrap();
+    assert_eq!(db.get_alert_thresholds(c.key).await.unwrap(), alerts);
+    assert!(db
+        .get_captchas_with_anomaly_alerts()
+        .await
+        .unwrap()
+        .contains(&OwnedCaptcha {
+            owner: p.username.into(),
+            key: c.key.into(),
+        }));
+    db.delete_alert_thresholds(p.username, c.key).await.unwrap();
+    assert!(matches!(
+        db.delete_alert_thresholds(p.username, c.key).await,
+        Err(DBError::AlertThresholdsNotFound)
+    ));
+
+    // widget theme
+    assert!(matches!(
+        db.get_widget_theme(c.key).await,
+        Err(DBError::WidgetThemeNotFound)
+    ));
+    let mut theme = WidgetTheme {
+        mode: ThemeMode::Dark,
+        accent_color: "#ff8800".into(),
+        background_color: Some("#101010".into()),
+        text_color: None,
+        border_radius: 8,
+        show_logo: false,
+    };
+    db.set_widget_theme(p.username, c.key, &theme)
+        .await
+        .unwrap();
+    assert_eq!(db.get_widget_theme(c.key).await.unwrap(), theme);
+    theme.mode = ThemeMode::Auto;
+    theme.background_color = None;
+    theme.text_color = Some("#eeeeee".into());
+    db.set_widget_theme(p.username, c.key, &theme)
+        .await
+        .unwrap();
+    assert_eq!(db.get_widget_theme(c.key).await.unwrap(), theme);
+    db.delete_widget_theme(p.username, c.key).await.unwrap();
+    assert!(matches!(
+        db.delete_widget_theme(p.username, c.key).await,
+        Err(DBError::WidgetThemeNotFound)
+    ));
+
+    // invisible mode
+    assert_eq!(
+        db.captcha_invisible_mode(c.key).await.unwrap(),
+        InvisibleMode::default()
+    );
+    let invisible = InvisibleMode {
+        enabled: true,
+        threshold: 500,
+    };
+    db.update_captcha_invisible_mode(p.username, c.key, &invisible)
+        .await
+        .unwrap();
+    assert_eq!(db.captcha_invisible_mode(c.key).await.unwrap(), invisible);
+
+    // fallback challenge
+    assert_eq!(
+        db.captcha_fallback(c.key).await.unwrap(),
+        Fallback::default()
+    );
+    let fallback = Fallback {
+        enabled: true,
+        wait: 30,
+    };
+    db.update_captcha_fallback(p.username, c.key, &fallback)
+        .await
+        .unwrap();
+    assert_eq!(db.captcha_fallback(c.key).await.unwrap(), fallback);
+
+    db.run_maintenance().await.unwrap();
+
+    // legal document acceptance
+    assert!(db.fetch_agreements(p.username).await.unwrap().is_empty());
+    let agreement = AcceptAgreement {
+        document: "tos",
+        version: "1",
+        ip_hash: "iphash",
+    };
+    db.accept_agreement(p.username, &agreement).await.unwrap();
+    let agreements = db.fetch_agreements(p.username).await.unwrap();
+    assert_eq!(agreements.len(), 1);
+    assert_eq!(agreements[0].document, agreement.document);
+    assert_eq!(agreements[0].version, agreement.version);
+    assert_eq!(agreements[0].ip_hash, agreement.ip_hash);
+
+    // two-factor authentication
+    assert!(matches!(
+        db.get_totp(p.username).await,
+        Err(DBError::TotpNotFound)
+    ));
+    db.set_totp(p.username, "secret").await.unwrap();
+    let totp = db.get_totp(p.username).await.unwrap();
+    assert_eq!(totp.secret, "secret");
+    assert!(!totp.enabled);
+    let codes = vec!["code1".to_string(), "code2".to_string()];
+    db.enable_totp(p.username, &codes).await.unwrap();
+    assert!(db.get_totp(p.username).await.unwrap().enabled);
+    assert!(db.use_recovery_code(p.username, "code1").await.unwrap());
+    assert!(!db.use_recovery_code(p.username, "code1").await.unwrap());
+    assert!(db.use_totp_step(p.username, 10).await.unwrap());
+    assert!(!db.use_totp_step(p.username, 10).await.unwrap());
+    assert!(!db.use_totp_step(p.username, 9).await.unwrap());
+    assert!(db.use_totp_step(p.username, 11).await.unwrap());
+    // a new secret forgets used steps
+    db.set_totp(p.username, "secret").await.unwrap();
+    assert!(db.use_totp_step(p.username, 10).await.unwrap());
+    db.enable_totp(p.username, &codes).await.unwrap();
+    db.delete_totp(p.username).await.unwrap();
+    assert!(!db.use_recovery_code(p.username, "code2").await.unwrap());
+    assert!(matches!(
+        db.get_totp(p.username).await,
+        Err(DBError::TotpNotFound)
+    ));
+
+    // email verification
+    assert!(!db.is_email_verified(p.username).await.unwrap());
+    let v = AddEmailVerification {
+        token: "verificationtoken",
+        email: p.email.unwrap(),
+    };
+    db.add_email_verification(p.username, &v).await.unwrap();
+    let verification = db.consume_email_verification(v.token).await.unwrap();
+    assert_eq!(verification.username, p.username);
+    assert_eq!(verification.email, v.email);
+    assert!(matches!(
+        db.consume_email_verification(v.token).await,
+        Err(DBError::EmailVerificationNotFound)
+    ));
+    db.set_email_verified(p.username, true).await.unwrap();
+    assert!(db.is_email_verified(p.username).await.unwrap());
+    db.set_email_verified(p.username, false).await.unwrap();
+    assert!(!db.is_email_verified(p.username).await.unwrap());
+
+    // email change
+    assert!(db.get_email_change(p.username).await.unwrap().is_none());
+    let c = AddEmailChange {
+        token: "changetoken",
+        cancel_token: "changecanceltoken",
+        email: "changed@example.com",
+    };
+    db.add_email_change(p.username, &c).await.unwrap();
+    let change = db.get_email_change(p.username).await.unwrap().unwrap();
+    assert_eq!(change.username, p.username);
+    assert_eq!(change.email, c.email);
+    let change = db.consume_email_change(c.token).await.unwrap();
+    assert_eq!(change.email, c.email);
+    assert!(matches!(
+        db.consume_email_change(c.token).await,
+        Err(DBError::EmailChangeNotFound)
+    ));
+    db.add_email_change(p.username, &c).await.unwrap();
+    assert_eq!(
+        db.cancel_email_change(c.cancel_token)
+            .await
+            .unwrap()
+            .username,
+        p.username
+    );
+    assert!(db.get_email_change(p.username).await.unwrap().is_none());
+    assert!(matches!(
+        db.cancel_email_change(c.cancel_token).await,
+        Err(DBError::EmailChangeNotFound)
+    ));
+
+    // OpenID Connect identities
+    let identity = OidcIdentity {
+        issuer: "https://idp.example.org",
+        subject: "oidcsubject",
+    };
+    assert!(matches!(
+        db.get_oidc_user(&identity).await,
+        Err(DBError::OidcIdentityNotFound)
+    ));
+    db.link_oidc_identity(p.username, &identity).await.unwrap();
+    assert_eq!(db.get_oidc_user(&identity).await.unwrap(), p.username);
+    let other_issuer = OidcIdentity {
+        issuer: "https://other.example.org",
+        ..identity.clone()
+    };
+    assert!(matches!(
+        db.get_oidc_user(&other_issuer).await,
+        Err(DBError::OidcIdentityNotFound)
+    ));
+
+    // sessions
+    let session = CreateSession {
+        id: "sessionid",
+        ip: "127.0.0.1",
+        user_agent: "Mozilla/5.0",
+    };
+    db.create_session(p.username, &session).await.unwrap();
+    db.create_session(
+        p.username,
+        &CreateSession {
+            id: "othersessionid",
+            ..session.clone()
+        },
+    )
+    .await
+    .unwrap();
+    let s = db.get_session(session.id).await.unwrap();
+    assert_eq!(s.username, p.username);
+    assert_eq!(s.ip, session.ip);
+    assert_eq!(s.user_agent, session.user_agent);
+    db.touch_session(session.id).await.unwrap();
+    assert!(db.get_session(session.id).await.unwrap().last_active >= s.last_active);
+    assert_eq!(db.get_user_sessions(p.username).await.unwrap().len(), 2);
+    db.delete_session(p.username, session.id).await.unwrap();
+    assert!(matches!(
+        db.get_session(session.id).await,
+        Err(DBError::SessionNotFound)
+    ));
+    assert!(matches!(
+        db.delete_session(p.username, session.id).await,
+        Err(DBError::SessionNotFound)
+    ));
+    db.delete_user_sessions(p.username).await.unwrap();
+    assert!(db.get_user_sessions(p.username).await.unwrap().is_empty());
+
+    // known devices and session revocation
+    assert_eq!(db.count_known_devices(p.username).await.unwrap(), 0);
+    assert!(db
+        .add_known_device(p.username, "knowndevice")
+        .await
+        .unwrap());
+    assert!(!db
+        .add_known_device(p.username, "knowndevice")
+        .await
+        .unwrap());
+    assert!(db
+        .add_known_device(p.username, "knowndevice2")
+        .await
+        .unwrap());
+    assert_eq!(db.count_known_devices(p.username).await.unwrap(), 2);
+    db.delete_known_devices(p.username).await.unwrap();
+    assert_eq!(db.count_known_devices(p.username).await.unwrap(), 0);
+    db.add_session_revocation(p.username, "revocationtoken")
+        .await
+        .unwrap();
+    let r = db
+        .consume_session_revocation("revocationtoken")
+        .await
+        .unwrap();
+    assert_eq!(r.username, p.username);
+    assert!(matches!(
+        db.consume_session_revocation("revocationtoken").await,
+        Err(DBError::SessionRevocationNotFound)
+    ));
+
+    // instance administrators
+    assert!(!db.is_admin(p.username).await.unwrap());
+    db.set_admin(p.username, true).await.unwrap();
+    assert!(db.is_admin(p.username).await.unwrap());
+    db.set_admin(p.username, true).await.unwrap();
+    assert!(matches!(
+        db.set_admin("nonexistentadminuser", true).await,
+        Err(DBError::AccountNotFound)
+    ));
+    let stats = db.get_instance_stats().await.unwrap();
+    assert!(stats.users >= 1);
+    assert!(stats.captchas >= 1);
+    let mut page = 0;
+    let user = loop {
+        let users = db.get_users(page, 50).await.unwrap();
+        assert!(!users.is_empty());
+        if let Some(user) = users.into_iter().find(|u| u.name == p.username) {
+            break user;
+        }
+        page += 1;
+    };
+    assert!(user.is_admin);
+    assert!(!user.suspended);
+    assert_eq!(user.email.as_deref(), p.email);
+    assert_eq!(user.captchas, 1);
+    db.set_admin(p.username, false).await.unwrap();
+    assert!(!db.is_admin(p.username).await.unwrap());
+
+    // organizations
+    let org = format!("{}-org", p.username);
+    let org = org.as_str();
+    if db.org_exists(org).await.unwrap() {
+        db.delete_org(org).await.unwrap();
+    }
+    assert!(matches!(
+        db.get_org_role(org, p.username).await,
+        Err(DBError::OrgMemberNotFound)
+    ));
+    db.create_org(p.username, org).await.unwrap();
+    assert!(db.org_exists(org).await.unwrap());
+    assert!(matches!(
+        db.create_org(p.username, org).await,
+        Err(DBError::OrgNameTaken)
+    ));
+    assert_eq!(
+        db.get_org_role(org, p.username).await.unwrap(),
+        OrgRole::Owner
+    );
+    assert_eq!(
+        db.get_user_orgs(p.username).await.unwrap(),
+        vec![OrgMembership {
+            org: org.into(),
+            role: OrgRole::Owner
+        }]
+    );
+    db.set_org_member(org, p.username, OrgRole::Editor)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_org_members(org).await.unwrap(),
+        vec![OrgMember {
+            username: p.username.into(),
+            role: OrgRole::Editor
+        }]
+    );
+    assert_eq!(db.get_captcha_org(c.key).await.unwrap(), None);
+    db.set_captcha_org(p.username, c.key, Some(org))
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_captcha_org(c.key).await.unwrap().as_deref(),
+        Some(org)
+    );
+    let org_captchas = db.get_org_captchas(org).await.unwrap();
+    assert_eq!(org_captchas.len(), 1);
+    assert_eq!(org_captchas[0].key, c.key);
+    db.reassign_org_captchas(org, p.username, p.username)
+        .await
+        .unwrap();
+    assert_eq!(db.get_captcha_owner(c.key).await.unwrap(), p.username);
+    db.set_captcha_org(p.username, c.key, None).await.unwrap();
+    assert!(db.get_org_captchas(org).await.unwrap().is_empty());
+    assert!(matches!(
+        db.set_captcha_org(p.username, "nonexistentorgcaptcha", None)
+            .await,
+        Err(DBError::CaptchaNotFound)
+    ));
+    db.remove_org_member(org, p.username).await.unwrap();
+    assert!(matches!(
+        db.remove_org_member(org, p.username).await,
+        Err(DBError::OrgMemberNotFound)
+    ));
+    assert!(db.get_user_orgs(p.username).await.unwrap().is_empty());
+    db.delete_org(org).await.unwrap();
+    assert!(matches!(
+        db.delete_org(org).await,
+        Err(DBError::OrgNotFound)
+    ));
+
+    // traffic bursts
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    assert!(db.get_bursts(c.key).await.unwrap().is_empty());
+    let burst = CreateBurst {
+        starts: now + 600,
+        ends: now + 1200,
+        difficulty_factor: 50_000,
+    };
+    db.add_burst(p.username, c.key, &burst).await.unwrap();
+    let ended = CreateBurst {
+        starts: now - 1200,
+        ends: now - 600,
+        difficulty_factor: 50_000,
+    };
+    db.add_burst(p.username, c.key, &ended).await.unwrap();
+    let bursts = db.get_bursts(c.key).await.unwrap();
+    assert_eq!(bursts.len(), 2);
+    assert_eq!(bursts[0].starts, ended.starts);
+    assert_eq!(bursts[1].key, c.key);
+    assert_eq!(bursts[1].ends, burst.ends);
+    assert_eq!(bursts[1].difficulty_factor, burst.difficulty_factor);
+    let scheduled = db.get_scheduled_bursts(now, now + 600).await.unwrap();
+    assert!(scheduled.contains(&bursts[1]));
+    assert!(!scheduled.contains(&bursts[0]));
+    assert!(!db
+        .get_scheduled_bursts(now, now + 60)
+        .await
+        .unwrap()
+        .contains(&bursts[1]));
+    db.delete_ended_bursts(now).await.unwrap();
+    assert_eq!(db.get_bursts(c.key).await.unwrap(), vec![bursts[1].clone()]);
+    db.delete_burst(p.username, c.key, bursts[1].id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.delete_burst(p.username, c.key, bursts[1].id).await,
+        Err(DBError::BurstNotFound)
+    ));
+
+    // invitations
+    let token = format!("{}invite", p.username);
+    let invite = CreateInvite {
+        token: &token,
+        email: "invitee@example.com",
+        org: None,
+        role: None,
+        expires_at: now + 600,
+    };
+    db.create_invite(p.username, &invite).await.unwrap();
+    let stored = db.get_invite(&token).await.unwrap();
+    assert_eq!(stored.email, invite.email);
+    assert_eq!(stored.invited_by, p.username);
+    assert_eq!(stored.expires_at, invite.expires_at);
+    assert_eq!(stored.status(now), InviteStatus::Pending);
+    assert_eq!(stored.status(now + 600), InviteStatus::Expired);
+    assert_eq!(
+        db.get_user_invites(p.username).await.unwrap(),
+        vec![stored.clone()]
+    );
+    db.accept_invite(&token, now).await.unwrap();
+    assert!(matches!(
+        db.accept_invite(&token, now).await,
+        Err(DBError::InviteNotFound)
+    ));
+    let accepted = db.get_invite(&token).await.unwrap();
+    assert_eq!(accepted.status(now), InviteStatus::Accepted);
+    db.reopen_invite(&token).await.unwrap();
+    assert!(matches!(
+        db.accept_invite(&token, now + 600).await,
+        Err(DBError::InviteNotFound)
+    ));
+    db.delete_invite(p.username, stored.id).await.unwrap();
+    assert!(matches!(
+        db.delete_invite(p.username, stored.id).await,
+        Err(DBError::InviteNotFound)
+    ));
+    assert!(matches!(
+        db.get_invite(&token).await,
+        Err(DBError::InviteNotFound)
+    ));
+
+    // user quotas
+    assert_eq!(
+        db.get_user_quota(p.username).await.unwrap(),
+        UserQuota::default()
+    );
+    let mut quota = UserQuota {
+        max_captchas: Some(5),
+        max_analytics_records: None,
+    };
+    db.set_user_quota(p.username, &quota).await.unwrap();
+    assert_eq!(db.get_user_quota(p.username).await.unwrap(), quota);
+    quota.max_analytics_records = Some(0);
+    db.set_user_quota(p.username, &quota).await.unwrap();
+    assert_eq!(db.get_user_quota(p.username).await.unwrap(), quota);
+    assert!(matches!(
+        db.get_user_quota("nonexistentquotauser").await,
+        Err(DBError::AccountNotFound)
+    ));
+
+    // data residency
+    assert_eq!(db.get_residency(p.username).await.unwrap(), None);
+    db.set_residency(p.username, Some("eu")).await.unwrap();
+    db.set_residency(p.username, Some("eu")).await.unwrap();
+    assert_eq!(
+        db.get_residency(p.username).await.unwrap().as_deref(),
+        Some("eu")
+    );
+    db.set_residency(p.username, None).await.unwrap();
+    assert_eq!(db.get_residency(p.username).await.unwrap(), None);
+    assert!(matches!(
+        db.set_residency("nonexistentresidencyuser", Some("eu"))
+            .await,
+        Err(DBError::AccountNotFound)
+    ));
+
+    // registration approval
+    assert!(!db.is_pending_approval(p.username).await.unwrap());
+    db.set_pending_approval(p.username, true).await.unwrap();
+    db.set_pending_approval(p.username, true).await.unwrap();
+    assert!(db.is_pending_approval(p.username).await.unwrap());
+    assert!(db
+        .get_pending_registrations(0, 1000)
+        .await
+        .unwrap()
+        .iter()
+        .any(|r| r.name == p.username));
+    db.set_pending_approval(p.username, false).await.unwrap();
+    assert!(!db.is_pending_approval(p.username).await.unwrap());
+    assert!(!db
+        .get_pending_registrations(0, 1000)
+        .await
+        .unwrap()
+        .iter()
+        .any(|r| r.name == p.username));
+    assert!(matches!(
+        db.set_pending_approval("nonexistentpendinguser", true)
+            .await,
+        Err(DBError::AccountNotFound)
+    ));
+
+    // external IDs
+    assert!(matches!(
+        db.get_captcha_by_external_id(p.username, "external").await,
+        Err(DBError::CaptchaNotFound)
+    ));
+    db.set_captcha_external_id(p.username, c.key, Some("external"))
+        .await
+        .unwrap();
+    db.set_captcha_external_id(p.username, c.key, Some("external"))
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_captcha_by_external_id(p.username, "external")
+            .await
+            .unwrap()
+            .key,
+        c.key
+    );
+    db.set_captcha_external_id(p.username, c.key, None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.get_captcha_by_external_id(p.username, "external").await,
+        Err(DBError::CaptchaNotFound)
+    ));
+    assert!(matches!(
+        db.set_captcha_external_id(p.username, "nonexistentexternal", Some("external"))
+            .await,
+        Err(DBError::CaptchaNotFound)
+    ));
+
+    // suspensions
+    assert!(!db.is_suspended(p.username).await.unwrap());
+    assert!(!db.is_captcha_owner_suspended(c.key).await.unwrap());
+    db.set_suspended(p.username, true).await.unwrap();
+    assert!(db.is_suspended(p.username).await.unwrap());
+    assert!(db.is_captcha_owner_suspended(c.key).await.unwrap());
+    db.set_suspended(p.username, false).await.unwrap();
+    assert!(!db.is_suspended(p.username).await.unwrap());
+    assert!(matches!(
+        db.set_suspended("nonexistentsuspendeduser", true).await,
+        Err(DBError::AccountNotFound)
+    ));
+    assert!(matches!(
+        db.is_captcha_owner_suspended("nonexistentsuspendedcaptcha")
+            .await,
+        Err(DBError::CaptchaNotFound)
+    ));
+
+    // banned email domains
+    let domain = format!("{}.example", p.username);
+    let domain = domain.as_str();
+    assert!(!db.is_email_domain_banned(domain).await.unwrap());
+    db.ban_email_domain(domain).await.unwrap();
+    db.ban_email_domain(domain).await.unwrap();
+    assert!(db.is_email_domain_banned(domain).await.unwrap());
+    assert!(db
+        .get_banned_email_domains()
+        .await
+        .unwrap()
+        .iter()
+        .any(|d| d == domain));
+    db.unban_email_domain(domain).await.unwrap();
+    assert!(!db.is_email_domain_banned(domain).await.unwrap());
+    assert!(matches!(
+        db.unban_email_domain(domain).await,
+        Err(DBError::BannedDomainNotFound)
+    ));
+
+    // difficulty decay
+    assert!(matches!(
+        db.get_difficulty_decay(c.key).await,
+        Err(DBError::DifficultyDecayNotFound)
+    ));
+    let mut decay = DifficultyDecay {
+        sustain: 300,
+        step: 60,
+        steps: 5,
+        curve: DecayCurve::Linear,
+    };
+    db.set_difficulty_decay(p.username, c.key, &decay)
+        .await
+        .unwrap();
+    assert_eq!(db.get_difficulty_decay(c.key).await.unwrap(), decay);
+    decay.curve = DecayCurve::Exponential;
+    db.set_difficulty_decay(p.username, c.key, &decay)
+        .await
+        .unwrap();
+    assert_eq!(db.get_difficulty_decay(c.key).await.unwrap(), decay);
+    db.delete_difficulty_decay(p.username, c.key).await.unwrap();
+    assert!(matches!(
+        db.delete_difficulty_decay(p.username, c.key).await,
+        Err(DBError::DifficultyDecayNotFound)
+    ));
+
+    // escalation log
+    let escalation = EscalationLogEntry {
+        kind: EscalationKind::Escalation,
+        difficulty_factor: 5000,
+        time: 1_700_000_000,
+    };
+    let decayed = EscalationLogEntry {
+        kind: EscalationKind::Decay,
+        difficulty_factor: 2500,
+        time: 1_700_000_060,
+    };
+    db.add_escalation_log_entry(c.key, &escalation)
+        .await
+        .unwrap();
+    db.add_escalation_log_entry(c.key, &decayed).await.unwrap();
+    let log = db.get_escalation_log(p.username, c.key, 10).await.unwrap();
+    assert_eq!(log, vec![decayed.clone(), escalation]);
+    let log = db.get_escalation_log(p.username, c.key, 1).await.unwrap();
+    assert_eq!(log, vec![decayed]);
+    assert_eq!(
+        db.count_escalations(c.key, escalation.time).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        db.count_escalations(c.key, escalation.time + 1)
+            .await
+            .unwrap(),
+        0
+    );
+
+    // allowed domains
+    assert!(db.get_allowed_domains(c.key).await.unwrap().is_empty());
+    assert!(db
+        .get_captchas_without_allowed_domains()
+        .await
+        .unwrap()
+        .iter()
+        .any(|u| u.key == c.key && u.owner == p.username));
+    let domains = vec!["b.example.com".to_string(), "a.example.com".to_string()];
+    db.set_allowed_domains(p.username, c.key, &domains)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_allowed_domains(c.key).await.unwrap(),
+        vec!["a.example.com".to_string(), "b.example.com".to_string()]
+    );
+    assert!(!db
+        .get_captchas_without_allowed_domains()
+        .await
+        .unwrap()
+        .iter()
+        .any(|u| u.key == c.key));
+    db.set_allowed_domains(p.username, c.key, &[])
+        .await
+        .unwrap();
+    assert!(db.get_allowed_domains(c.key).await.unwrap().is_empty());
+
+    assert_eq!(db.get_allowed_domains_deadline(c.key).await.unwrap(), None);
+    db.set_allowed_domains_deadline(c.key, 1_700_000_000)
+        .await
+        .unwrap();
+    db.set_allowed_domains_deadline(c.key, 1_700_000_000)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_allowed_domains_deadline(c.key).await.unwrap(),
+        Some(1_700_000_000)
+    );
+    assert!(!db
+        .get_captchas_without_allowed_domains()
+        .await
+        .unwrap()
+        .iter()
+        .any(|u| u.key == c.key));
+    assert!(matches!(
+        db.set_allowed_domains_deadline("nonexistentdeadline", 1_700_000_000)
+            .await,
+        Err(DBError::CaptchaNotFound)
+    ));
+    assert!(matches!(
+        db.get_allowed_domains_deadline("nonexistentdeadline").await,
+        Err(DBError::CaptchaNotFound)
+    ));
+
+    // demo sitekeys
+    const DEMO_SESSION: &str = "dbcoretestdemosession";
+    assert!(!db.is_demo_sitekey(c.key).await.unwrap());
+    assert_eq!(db.count_demo_sitekeys(DEMO_SESSION).await.unwrap(), 0);
+    db.add_demo_sitekey(c.key, Some(DEMO_SESSION), 1_700_000_000)
+        .await
+        .unwrap();
+    assert!(db.is_demo_sitekey(c.key).await.unwrap());
+    assert_eq!(db.count_demo_sitekeys(DEMO_SESSION).await.unwrap(), 1);
+    assert!(!db
+        .get_expired_demo_sitekeys(1_700_000_000)
+        .await
+        .unwrap()
+        .iter()
+        .any(|k| k == c.key));
+    assert!(db
+        .get_expired_demo_sitekeys(1_700_000_001)
+        .await
+        .unwrap()
+        .iter()
+        .any(|k| k == c.key));
+    let sitekeys = db.get_all_user_captchas(p.username).await.unwrap().len();
+    let demo = CreateCaptcha {
+        key: "dbcoretestdemositekey",
+        ..c.clone()
+    };
+    assert!(matches!(
+        db.create_demo_captcha(p.username, &demo, Some(DEMO_SESSION), 0, sitekeys, 0)
+            .await,
+        Err(DBError::SitekeyLimitReached)
+    ));
+    assert!(matches!(
+        db.create_demo_captcha(p.username, &demo, Some(DEMO_SESSION), 0, 100, 1)
+            .await,
+        Err(DBError::SessionSitekeyLimitReached)
+    ));
+    assert!(!db.captcha_exists(None, demo.key).await.unwrap());
+    db.create_demo_captcha(p.username, &demo, Some(DEMO_SESSION), 0, 100, 2)
+        .await
+        .unwrap();
+    assert!(db.is_demo_sitekey(demo.key).await.unwrap());
+    assert_eq!(db.count_demo_sitekeys(DEMO_SESSION).await.unwrap(), 2);
+    db.delete_captcha(p.username, demo.key).await.unwrap();
+
+    // trial accounts
+    let trials = db.count_trials().await.unwrap();
+    db.add_trial(p.username, 1_700_000_000).await.unwrap();
+    assert_eq!(db.count_trials().await.unwrap(), trials + 1);
+    assert!(!db
+        .get_expired_trials(1_699_999_999)
+        .await
+        .unwrap()
+        .iter()
+        .any(|u| u == p.username));
+    assert!(db
+        .get_expired_trials(1_700_000_000)
+        .await
+        .unwrap()
+        .iter()
+        .any(|u| u == p.username));
+
+    // API tokens
+    const TOKEN_HASH: &str = "dbcoretestapitokenhash";
+    let token = AddApiToken {
+        username: p.username,
+        name: "monitoring",
+        token_hash: TOKEN_HASH,
+        scope: "read:stats",
+        created_at: 1_700_000_000,
+    };
+    db.add_api_token(&token).await.unwrap();
+    let tokens = db.get_api_tokens(p.username).await.unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].name, token.name);
+    assert_eq!(tokens[0].scope, token.scope);
+    assert_eq!(tokens[0].created_at, token.created_at);
+    let owner = db.get_api_token_owner(TOKEN_HASH).await.unwrap();
+    assert_eq!(owner.username, p.username);
+    assert_eq!(owner.scope, token.scope);
+    db.delete_api_token(p.username, tokens[0].id).await.unwrap();
+    assert!(matches!(
+        db.delete_api_token(p.username, tokens[0].id).await,
+        Err(DBError::ApiTokenNotFound)
+    ));
+    assert!(matches!(
+        db.get_api_token_owner(TOKEN_HASH).await,
+        Err(DBError::ApiTokenNotFound)
+    ));
+
+    // cluster challenges and tokens can be taken once, until they expire
+    db.add_cluster_challenge(c.key, "clusterstring", 500, now + 60)
+        .await
+        .unwrap();
+    db.add_cluster_challenge(c.key, "expiredstring", 500, now - 60)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.take_cluster_challenge(c.key, "clusterstring", now)
+            .await
+            .unwrap(),
+        Some(500)
+    );
+    for string in ["clusterstring", "expiredstring"] {
+        assert!(db
+            .take_cluster_challenge(c.key, string, now)
+            .await
+            .unwrap()
+            .is_none());
+    }
+    db.add_cluster_token(c.key, "clustertoken", "iphash", now + 60)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_cluster_token_ip(c.key, "clustertoken", now)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("iphash")
+    );
+    assert!(db
+        .get_cluster_token_ip(c.key, "clustertoken", now + 60)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(db
+        .take_cluster_token(c.key, "clustertoken", now)
+        .await
+        .unwrap());
+    assert!(db
+        .get_cluster_token_ip(c.key, "clustertoken", now)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(!db
+        .take_cluster_token(c.key, "clustertoken", now)
+        .await
+        .unwrap());
+
+    // cluster fallback challenges can be completed once, after they are ready
+    // and until they expire
+    db.add_cluster_fallback(c.key, "clusterfallback", now + 10, now + 60)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_cluster_fallback(c.key, "clusterfallback", now)
+            .await
+            .unwrap(),
+        Some(now + 10)
+    );
+    assert!(!db
+        .take_cluster_fallback(c.key, "clusterfallback", now)
+        .await
+        .unwrap());
+    assert!(db
+        .get_cluster_fallback(c.key, "clusterfallback", now + 60)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(db
+        .take_cluster_fallback(c.key, "clusterfallback", now + 10)
+        .await
+        .unwrap());
+    assert!(!db
+        .take_cluster_fallback(c.key, "clusterfallback", now + 10)
+        .await
+        .unwrap());
+    assert!(db
+        .get_cluster_fallback(c.key, "clusterfallback", now)
+        .await
+        .unwrap()
+        .is_none());
+
+    // fallback challenges are counted per window
+    let ip_hash = format!("fallbackiphash{now}");
+    for started in 1..=2 {
+        assert_eq!(
+            db.count_cluster_fallback(&ip_hash, now, now + 60)
+                .await
+                .unwrap(),
+            started
+        );
+    }
+    assert_eq!(
+        db.count_cluster_fallback(&ip_hash, now + 60, now + 120)
+            .await
+            .unwrap(),
+        1
+    );
+
+    // visitors counted by an instance are only summed for the others
+    let visitors = |visitors| {
+        vec![VisitorCount {
+            key: c.key.into(),
+            visitors,
+        }]
+    };
+    db.report_cluster_visitors("instance-a", &visitors(10), now)
+        .await
+        .unwrap();
+    db.report_cluster_visitors("instance-b", &visitors(5), now)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_cluster_visitors("instance-a", now).await.unwrap(),
+        visitors(5)
+    );
+    assert_eq!(
+        db.get_cluster_visitors("instance-c", now).await.unwrap(),
+        visitors(15)
+    );
+    db.report_cluster_visitors("instance-b", &visitors(7), now + 1)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_cluster_visitors("instance-c", now + 1)
+            .await
+            .unwrap(),
+        visitors(7)
+    );
+    db.purge_cluster_state(now, now + 1).await.unwrap();
+    assert_eq!(
+        db.get_cluster_visitors("instance-c", now).await.unwrap(),
+        visitors(7)
+    );
+
+    // emails are attempted once per claim, until they are given up on
+    let recipients = vec!["outbox@example.org".to_string()];
+    let email = AddOutboxEmail {
+        sender: Some("admin@example.org"),
+        recipients: &recipients,
+        message: "Subject: outbox",
+        next_attempt_at: now + 60,
+        created_at: now,
+    };
+    let id = db.add_outbox_email(&email).await.unwrap();
+    let outbox = |emails: Vec<OutboxEmail>| emails.into_iter().find(|e| e.id == id);
+    assert!(db.count_outbox_emails().await.unwrap() >= 1);
+    assert!(outbox(db.get_due_outbox_emails(now, 100).await.unwrap()).is_none());
+    let due = outbox(db.get_due_outbox_emails(now + 60, 100).await.unwrap()).unwrap();
+    assert_eq!(due.recipients, recipients);
+    assert_eq!(due.sender.as_deref(), email.sender);
+    assert_eq!(due.attempts, 0);
+    assert!(db.claim_outbox_email(id, 0, now + 120).await.unwrap());
+    assert!(!db.claim_outbox_email(id, 0, now + 120).await.unwrap());
+    db.fail_outbox_email(id, "unreachable", Some(now))
+        .await
+        .unwrap();
+    let due = outbox(db.get_due_outbox_emails(now, 100).await.unwrap()).unwrap();
+    assert_eq!(due.attempts, 1);
+    assert_eq!(due.last_error.as_deref(), Some("unreachable"));
+    assert!(db.claim_outbox_email(id, 1, now + 120).await.unwrap());
+    db.fail_outbox_email(id, "rejected", None).await.unwrap();
+    assert!(outbox(db.get_due_outbox_emails(now + 120, 100).await.unwrap()).is_none());
+    let dead = outbox(db.get_dead_letters().await.unwrap()).unwrap();
+    assert_eq!(dead.attempts, 2);
+    assert_eq!(dead.last_error.as_deref(), Some("rejected"));
+    // dead letters are sent again once retried
+    db.retry_dead_letter(id, now).await.unwrap();
+    assert!(matches!(
+        db.retry_dead_letter(id, now).await,
+        Err(DBError::DeadLetterNotFound)
+    ));
+    let due = outbox(db.get_due_outbox_emails(now, 100).await.unwrap()).unwrap();
+    assert_eq!(due.attempts, 0);
+    db.delete_outbox_email(id).await.unwrap();
+    assert!(outbox(db.get_due_outbox_emails(now, 100).await.unwrap()).is_none());
+
+    // stats digests
+    let subscription = |subscriptions: Vec<DigestSubscription>| {
+        subscriptions.into_iter().find(|s| s.username == p.username)
+    };
+    assert_eq!(db.get_digest_frequency(p.username).await.unwrap(), None);
+    db.set_digest_frequency(p.username, DigestFrequency::Daily)
+        .await
+        .unwrap();
+    db.set_digest_frequency(p.username, DigestFrequency::Weekly)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_digest_frequency(p.username).await.unwrap(),
+        Some(DigestFrequency::Weekly)
+    );
+    let s = subscription(db.get_digest_subscriptions().await.unwrap()).unwrap();
+    assert_eq!(s.frequency, DigestFrequency::Weekly);
+    // claimed once per period
+    let sent = s.last_sent;
+    assert!(!db
+        .claim_digest(p.username, sent - 1, sent + 60)
+        .await
+        .unwrap());
+    assert!(db.claim_digest(p.username, sent, sent + 60).await.unwrap());
+    assert!(!db.claim_digest(p.username, sent, sent + 60).await.unwrap());
+    let s = subscription(db.get_digest_subscriptions().await.unwrap()).unwrap();
+    assert_eq!(s.last_sent, sent + 60);
+    db.delete_digest_subscription(p.username).await.unwrap();
+    assert_eq!(db.get_digest_frequency(p.username).await.unwrap(), None);
+    assert!(subscription(db.get_digest_subscriptions().await.unwrap()).is_none());
+
+    // update captcha key; set key = username;
+    db.update_captcha_key(p.username, c.key, p.username)
+        .await
+        .unwrap();
+    // checking for captcha with old key; shouldn't exist
+    assert!(!db.captcha_exists(Some(p.username), c.key).await.unwrap());
+    // checking for captcha with new key; shouldn exist
+    assert!(db
+        .captcha_exists(Some(p.username), p.username)
+        .await
+        .unwrap());
+
+    // delete captcha levels
+    db.delete_captcha_levels(p.username, c.key).await.unwrap();
+
+    // update captcha; set description = username and duration *= duration;
+    let mut c2 = c.clone();
+    c2.duration *= c2.duration;
+    c2.description = p.username;
+    db.update_captcha_metadata(p.username, &c2).await.unwrap();
+
+    // delete captcha; updated key = p.username so invoke delete with it
+    db.delete_captcha(p.username, p.username).await.unwrap();
+    assert!(!db.captcha_exists(Some(p.username), c.key).await.unwrap());
+}
+
+/// inputs made of SQL meta-characters. Backends must treat them as data
+pub const SQL_META_INPUTS: [&str; 8] = [
+    "'",
+    "\"",
+    "' OR '1'='1",
+    "'; DROP TABLE mcaptcha_users; --",
+    "\\' OR 1=1 #",
+    "%",
+    "_",
+    "$1 ? :name",
+];
+
+/// inputs that are hard on encodings and collations. Backends may fail to
+/// store them, depending on their character set
+pub const UNICODE_INPUTS: [&str; 6] = [
+    "ünïcödé",
+    "漢字かな",
+    "🦀🔥",
+    "\u{202e}desrever",
+    "e\u{301}",
+    "\u{feff}",
+];
+
+/// adversarial inputs: SQL meta-characters, unicode and inputs longer than any
+/// column
+pub fn adversarial_inputs() -> Vec<String> {
+    SQL_META_INPUTS
+        .iter()
+        .chain(UNICODE_INPUTS.iter())
+        .map(|s| s.to_string())
+        .chain(["a".repeat(101), "'".repeat(10_000), "🦀".repeat(1_000)])
+        .collect()
+}
+
+/// number of inputs that property tests generate
+pub const GENERATED_INPUTS: usize = 64;
+
+/// generate `count` values of `strategy`. The seed is fixed, so failures
+/// reproduce
+pub fn generate<S: Strategy>(strategy: S, count: usize) -> Vec<S::Value> {
+    let mut runner = TestRunner::deterministic();
+    (0..count)
+        .map(|_| strategy.new_tree(&mut runner).unwrap().current())
+        .collect()
+}
+
+/// short ASCII strings that start with a SQL meta-character and mix more of
+/// them with text. Backends must treat them as data
+pub fn sql_meta_strategy() -> impl Strategy<Value = String> {
+    let meta = prop::sample::select(vec!['\'', '"', '%', '_', ';', '#', '\\', '$', '?']);
+    let rest =
+        proptest::string::string_regex(r#"[a-z0-9 '"%_;#\\$?:=*()-]{0,40}"#).unwrap();
+    (meta, rest).prop_map(|(meta, rest)| format!("{meta}{rest}"))
+}
+
+/// test that database functions treat adversarial strings, and strings
+/// generated by [sql_meta_strategy] and of arbitrary unicode, as data: lookups
+/// don't match anything and writes either round trip or fail, without
+/// panicking
+pub async fn adversarial_inputs_work<'a, T: MCDatabase>(db: &T, p: &Register<'a>) {
+    for (i, input) in adversarial_inputs().iter().enumerate() {
+        let is_sql_meta = SQL_META_INPUTS.contains(&input.as_str());
+        check_adversarial_input(db, p, &i.to_string(), input, is_sql_meta).await;
+    }
+    for (i, input) in generate(sql_meta_strategy(), GENERATED_INPUTS)
+        .iter()
+        .enumerate()
+    {
+        check_adversarial_input(db, p, &format!("meta{i}"), input, true).await;
+    }
+    for (i, input) in generate(any::<String>(), GENERATED_INPUTS)
+        .iter()
+        .enumerate()
+    {
+        check_adversarial_input(db, p, &format!("any{i}"), input, false).await;
+    }
+
+    // nothing was dropped or matched along the way
+    assert!(db.username_exists(p.username).await.unwrap());
+}
+
+/// check database functions with `input`. Inputs that are `storable` must be
+/// stored as is; others may be rejected
+async fn check_adversarial_input<'a, T: MCDatabase>(
+    db: &T,
+    p: &Register<'a>,
+    id: &str,
+    input: &str,
+    storable: bool,
+) {
+    // lookups
+    let exists = [
+        db.username_exists(input).await,
+        db.email_exists(input).await,
+        db.captcha_exists(None, input).await,
+        db.captcha_exists(Some(p.username), input).await,
+        db.org_exists(input).await,
+        db.captcha_description_exists(p.username, input, None).await,
+    ];
+    for res in exists {
+        if storable {
+            assert!(!res.unwrap(), "{input} matched");
+        } else {
+            assert!(!matches!(res, Ok(true)), "{input} matched");
+        }
+    }
+
+    // getters fail, with not found errors when inputs can be stored
+    macro_rules! not_found {
+        ($res:expr, $err:pat) => {
+            let res = $res;
+            assert!(res.is_err(), "{input} matched");
+            if storable {
+                assert!(matches!(res, Err($err)), "{input}: {res:?}");
+            }
+        };
+    }
+    not_found!(db.get_secret(input).await, DBError::AccountNotFound);
+    not_found!(db.get_captcha_owner(input).await, DBError::CaptchaNotFound);
+    not_found!(
+        db.get_captcha_config(p.username, input).await,
+        DBError::CaptchaNotFound
+    );
+    not_found!(db.get_session(input).await, DBError::SessionNotFound);
+    not_found!(db.get_invite(input).await, DBError::InviteNotFound);
+    not_found!(db.get_user_quota(input).await, DBError::AccountNotFound);
+    assert!(db.get_password(&Login::Username(input)).await.is_err());
+    assert!(db.get_password(&Login::Email(input)).await.is_err());
+
+    // writes
+    let key = format!("adversarialkey{id}");
+    let c = CreateCaptcha {
+        duration: 30,
+        description: input,
+        key: &key,
+    };
+    match db.create_captcha(p.username, &c).await {
+        Ok(()) => {
+            let captcha = db.get_captcha_config(p.username, &key).await.unwrap();
+            assert_eq!(captcha.description, input);
+            db.delete_captcha(p.username, &key).await.unwrap();
+        }
+        Err(_) => assert!(!storable, "{input} wasn't stored"),
+    }
+
+    let secret = format!("adversarialsecret{id}");
+    let user = Register {
+        username: input,
+        secret: &secret,
+        hash: p.hash,
+        email: None,
+    };
+    match db.register(&user).await {
+        Ok(()) => {
+            assert!(db.username_exists(input).await.unwrap());
+            db.delete_user(input).await.unwrap();
+        }
+        Err(_) => assert!(!storable, "{input} wasn't stored"),
+    }
 }
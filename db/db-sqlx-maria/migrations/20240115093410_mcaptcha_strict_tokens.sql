@@ -0,0 +1,24 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config
+	ADD COLUMN strict_tokens BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS mcaptcha_consumed_tokens (
+	config_id INTEGER NOT NULL,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	time timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_config_id_consumed_tokens`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_token_replays (
+	config_id INTEGER NOT NULL,
+	time timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_config_id_token_replays`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,26 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_webhooks (
+	config_id INTEGER NOT NULL UNIQUE,
+	url VARCHAR(2048) NOT NULL,
+	secret VARCHAR(100) NOT NULL,
+	on_escalation BOOLEAN NOT NULL DEFAULT false,
+	CONSTRAINT `fk_mcaptcha_config_id_webhooks`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_webhook_deliveries (
+	config_id INTEGER NOT NULL,
+	event VARCHAR(30) NOT NULL,
+	status INTEGER DEFAULT NULL,
+	attempt INTEGER NOT NULL,
+	success BOOLEAN NOT NULL,
+	time timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_config_id_webhook_deliveries`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
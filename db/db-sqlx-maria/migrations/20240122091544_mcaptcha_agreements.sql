@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_agreements (
+	user_id INT NOT NULL,
+	document VARCHAR(30) NOT NULL,
+	version VARCHAR(100) NOT NULL,
+	ip_hash VARCHAR(100) NOT NULL,
+	accepted_at timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_agreements`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
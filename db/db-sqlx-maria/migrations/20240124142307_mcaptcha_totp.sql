@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_totp (
+	user_id INT NOT NULL UNIQUE,
+	secret VARCHAR(100) NOT NULL,
+	enabled BOOLEAN NOT NULL DEFAULT false,
+	CONSTRAINT `fk_mcaptcha_user_totp`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_totp_recovery_codes (
+	user_id INT NOT NULL,
+	code_hash VARCHAR(100) NOT NULL,
+	CONSTRAINT `fk_mcaptcha_user_totp_recovery_codes`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
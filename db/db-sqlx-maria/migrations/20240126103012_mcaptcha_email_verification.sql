@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN verified BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS mcaptcha_email_verifications (
+	user_id INT NOT NULL UNIQUE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	email VARCHAR(100) NOT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_email_verifications`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_oidc_identities (
+	user_id INT NOT NULL,
+	issuer VARCHAR(500) NOT NULL,
+	subject VARCHAR(255) NOT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	UNIQUE(issuer, subject),
+	CONSTRAINT `fk_mcaptcha_user_oidc_identities`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
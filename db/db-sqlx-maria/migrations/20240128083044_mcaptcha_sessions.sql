@@ -0,0 +1,14 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_sessions (
+	session_id VARCHAR(100) PRIMARY KEY NOT NULL,
+	user_id INT NOT NULL,
+	ip VARCHAR(100) NOT NULL,
+	user_agent VARCHAR(500) NOT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	last_active timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_sessions`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_fraud_thresholds (
+	config_id INTEGER NOT NULL UNIQUE,
+	max_hash_rate BIGINT NOT NULL,
+	nonce_outlier_factor INTEGER NOT NULL,
+	repeated_result_limit INTEGER NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_fraud_thresholds`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
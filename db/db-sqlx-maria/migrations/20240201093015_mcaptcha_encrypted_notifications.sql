@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN notification_key TEXT DEFAULT NULL;
+ALTER TABLE mcaptcha_notifications ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE mcaptcha_notifications MODIFY message TEXT NOT NULL;
@@ -0,0 +1,32 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_orgs (
+	ID INT auto_increment,
+	PRIMARY KEY(ID),
+	name VARCHAR(100) NOT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `org_name` UNIQUE (name)
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_org_members (
+	org_id INT NOT NULL,
+	user_id INT NOT NULL,
+	role VARCHAR(10) NOT NULL,
+	UNIQUE(org_id, user_id),
+	CONSTRAINT `fk_mcaptcha_org_members_org_id`
+		FOREIGN KEY (org_id)
+		REFERENCES mcaptcha_orgs (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE,
+	CONSTRAINT `fk_mcaptcha_org_members_user_id`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+ALTER TABLE mcaptcha_config ADD COLUMN org_id INT DEFAULT NULL;
+ALTER TABLE mcaptcha_config ADD CONSTRAINT `fk_mcaptcha_config_org_id`
+	FOREIGN KEY (org_id)
+	REFERENCES mcaptcha_orgs (ID)
+	ON DELETE SET NULL
+	ON UPDATE CASCADE;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_bursts (
+	ID INT auto_increment,
+	PRIMARY KEY(ID),
+	config_id INTEGER NOT NULL,
+	starts_at timestamp NOT NULL,
+	ends_at timestamp NOT NULL,
+	difficulty_factor INTEGER NOT NULL,
+	INDEX `mcaptcha_bursts_ends_at` (ends_at),
+	CONSTRAINT `fk_mcaptcha_config_id_bursts`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_invites (
+	ID INT auto_increment,
+	PRIMARY KEY(ID),
+	token VARCHAR(100) NOT NULL UNIQUE,
+	email VARCHAR(100) NOT NULL,
+	invited_by INT NOT NULL,
+	org_id INT DEFAULT NULL,
+	role VARCHAR(10) DEFAULT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	expires_at timestamp NOT NULL,
+	accepted_at timestamp NULL DEFAULT NULL,
+	CONSTRAINT `fk_mcaptcha_invites_invited_by`
+		FOREIGN KEY (invited_by)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE,
+	CONSTRAINT `fk_mcaptcha_invites_org_id`
+		FOREIGN KEY (org_id)
+		REFERENCES mcaptcha_orgs (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
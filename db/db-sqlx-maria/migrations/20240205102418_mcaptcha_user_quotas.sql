@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_user_quotas (
+	user_id INT NOT NULL UNIQUE,
+	max_captchas INT DEFAULT NULL,
+	max_analytics_records INT DEFAULT NULL,
+	CONSTRAINT `fk_mcaptcha_user_quotas_user_id`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN residency VARCHAR(32) DEFAULT NULL;
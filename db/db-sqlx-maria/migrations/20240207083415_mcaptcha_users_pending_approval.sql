@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN pending_approval BOOLEAN NOT NULL DEFAULT false;
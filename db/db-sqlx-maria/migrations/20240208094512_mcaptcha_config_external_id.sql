@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config ADD COLUMN external_id VARCHAR(100) DEFAULT NULL;
+ALTER TABLE mcaptcha_config ADD UNIQUE KEY external_id (user_id, external_id);
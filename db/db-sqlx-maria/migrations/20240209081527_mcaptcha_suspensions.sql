@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN suspended BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS mcaptcha_banned_email_domains (
+	ID INT auto_increment,
+	PRIMARY KEY(ID),
+	domain VARCHAR(100) NOT NULL UNIQUE,
+	created_at timestamp NOT NULL DEFAULT now()
+);
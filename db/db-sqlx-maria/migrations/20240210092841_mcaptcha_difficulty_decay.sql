@@ -0,0 +1,25 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_difficulty_decay (
+	config_id INTEGER NOT NULL UNIQUE,
+	sustain INTEGER NOT NULL,
+	step INTEGER NOT NULL,
+	steps INTEGER NOT NULL,
+	curve VARCHAR(20) NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_difficulty_decay`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_escalation_log (
+	config_id INTEGER NOT NULL,
+	kind VARCHAR(20) NOT NULL,
+	difficulty_factor INTEGER NOT NULL,
+	time timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_config_id_escalation_log`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
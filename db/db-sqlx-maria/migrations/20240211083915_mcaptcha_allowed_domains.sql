@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_allowed_domains (
+	config_id INTEGER NOT NULL,
+	domain VARCHAR(255) NOT NULL,
+	UNIQUE(config_id, domain),
+	CONSTRAINT `fk_mcaptcha_config_id_allowed_domains`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+ALTER TABLE mcaptcha_config ADD COLUMN domains_deadline timestamp NULL DEFAULT NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_demo_sitekeys (
+	config_id INTEGER NOT NULL UNIQUE,
+	session_id VARCHAR(100) DEFAULT NULL,
+	created_at timestamp NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_demo_sitekeys`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_demo_sitekeys_session_id ON mcaptcha_demo_sitekeys(session_id);
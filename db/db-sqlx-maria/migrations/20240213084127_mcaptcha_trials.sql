@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_trials (
+	user_id INT NOT NULL UNIQUE,
+	expires_at timestamp NOT NULL,
+	CONSTRAINT `fk_mcaptcha_user_trials`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_trials_expires_at ON mcaptcha_trials(expires_at);
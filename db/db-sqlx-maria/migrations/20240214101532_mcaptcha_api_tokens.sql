@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_api_tokens (
+	id INT auto_increment,
+	PRIMARY KEY(id),
+	user_id INT NOT NULL,
+	name VARCHAR(100) NOT NULL,
+	token_hash VARCHAR(64) NOT NULL UNIQUE,
+	scope VARCHAR(50) NOT NULL,
+	created_at timestamp NOT NULL,
+	CONSTRAINT `fk_mcaptcha_user_api_tokens`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_api_tokens_user_id ON mcaptcha_api_tokens(user_id);
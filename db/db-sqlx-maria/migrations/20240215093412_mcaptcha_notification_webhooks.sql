@@ -0,0 +1,28 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_notification_webhooks (
+	user_id INT NOT NULL UNIQUE,
+	url VARCHAR(2048) NOT NULL,
+	secret VARCHAR(100) NOT NULL,
+	CONSTRAINT `fk_mcaptcha_user_notification_webhooks`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_notification_webhook_deliveries (
+	user_id INT NOT NULL,
+	event VARCHAR(30) NOT NULL,
+	status INTEGER DEFAULT NULL,
+	attempt INTEGER NOT NULL,
+	success BOOLEAN NOT NULL,
+	time timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_notification_webhook_deliveries`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_notification_webhook_deliveries_user_id
+	ON mcaptcha_notification_webhook_deliveries(user_id);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_alert_thresholds (
+	config_id INTEGER NOT NULL UNIQUE,
+	levels BOOLEAN NOT NULL,
+	highest_level BOOLEAN NOT NULL,
+	failure_rate INTEGER NOT NULL,
+	min_attempts INTEGER NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_alert_thresholds`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_alert_thresholds ADD COLUMN anomaly_factor INTEGER NOT NULL DEFAULT 0;
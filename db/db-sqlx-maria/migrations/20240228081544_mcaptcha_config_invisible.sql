@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config
+	ADD COLUMN invisible BOOLEAN NOT NULL DEFAULT false,
+	ADD COLUMN invisible_threshold INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,36 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_challenges (
+	config_id INTEGER NOT NULL,
+	string VARCHAR(100) NOT NULL UNIQUE,
+	difficulty_factor INTEGER NOT NULL,
+	expires_at timestamp NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_cluster_challenges`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_tokens (
+	config_id INTEGER NOT NULL,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	expires_at timestamp NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_cluster_tokens`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_visitors (
+	config_id INTEGER NOT NULL,
+	instance_id VARCHAR(100) NOT NULL,
+	visitors INTEGER NOT NULL,
+	updated_at timestamp NOT NULL,
+	UNIQUE(config_id, instance_id),
+	CONSTRAINT `fk_mcaptcha_config_id_cluster_visitors`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_email_outbox (
+	id INT auto_increment,
+	PRIMARY KEY(id),
+	sender VARCHAR(320) DEFAULT NULL,
+	recipients TEXT NOT NULL,
+	message MEDIUMTEXT NOT NULL,
+	attempts INT NOT NULL DEFAULT 0,
+	next_attempt_at timestamp NOT NULL,
+	last_error TEXT DEFAULT NULL,
+	dead BOOLEAN NOT NULL DEFAULT false,
+	created_at timestamp NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_email_outbox_next_attempt_at
+	ON mcaptcha_email_outbox(dead, next_attempt_at);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_email_changes (
+	user_id INT NOT NULL UNIQUE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	cancel_token VARCHAR(100) NOT NULL UNIQUE,
+	email VARCHAR(100) NOT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_email_changes`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_digest_subscriptions (
+	user_id INT NOT NULL UNIQUE,
+	frequency VARCHAR(16) NOT NULL,
+	last_sent timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_digest_subscriptions`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
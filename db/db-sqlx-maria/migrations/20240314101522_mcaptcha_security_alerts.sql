@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_known_devices (
+	user_id INT NOT NULL,
+	device VARCHAR(64) NOT NULL,
+	created_at timestamp NOT NULL DEFAULT now(),
+	UNIQUE(user_id, device),
+	CONSTRAINT `fk_mcaptcha_user_known_devices`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_session_revocations (
+	user_id INT NOT NULL,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	created_at timestamp NOT NULL DEFAULT now(),
+	CONSTRAINT `fk_mcaptcha_user_session_revocations`
+		FOREIGN KEY (user_id)
+		REFERENCES mcaptcha_users (ID)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
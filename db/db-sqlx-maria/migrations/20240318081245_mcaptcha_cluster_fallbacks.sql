@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_fallbacks (
+	config_id INTEGER NOT NULL,
+	challenge_id VARCHAR(100) NOT NULL UNIQUE,
+	ready_at timestamp NOT NULL,
+	expires_at timestamp NOT NULL,
+	CONSTRAINT `fk_mcaptcha_config_id_cluster_fallbacks`
+		FOREIGN KEY (config_id)
+		REFERENCES mcaptcha_config (config_id)
+		ON DELETE CASCADE
+		ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_fallback_windows (
+	ip_hash VARCHAR(64) NOT NULL UNIQUE,
+	started INTEGER NOT NULL,
+	expires_at timestamp NOT NULL
+);
@@ -23,7 +23,11 @@ pub fn map_register_err(e: Error) -> DBError {
     if let Error::Database(err) = e {
         if err.code() == Some(Cow::from("23000")) {
             let msg = err.message();
-            if msg.contains("for key 'name'") {
+            if msg.contains("for key 'org_name'") {
+                DBError::OrgNameTaken
+            } else if msg.contains("for key 'external_id'") {
+                DBError::ExternalIdTaken
+            } else if msg.contains("for key 'name'") {
                 DBError::UsernameTaken
             } else if msg.contains("for key 'email'") {
                 DBError::EmailTaken
@@ -8,6 +8,7 @@ SELECT
     mcaptcha_notifications.heading,
     mcaptcha_notifications.message,
     mcaptcha_notifications.received,
+    mcaptcha_notifications.encrypted as `encrypted: bool`,
     mcaptcha_users.name
 FROM
     mcaptcha_notifications 
@@ -0,0 +1,14 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config
+	ADD COLUMN strict_tokens BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS mcaptcha_consumed_tokens (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	time timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_token_replays (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	time timestamptz NOT NULL DEFAULT now()
+);
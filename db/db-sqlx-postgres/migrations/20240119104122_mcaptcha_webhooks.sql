@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_webhooks (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL UNIQUE,
+	url VARCHAR(2048) NOT NULL,
+	secret VARCHAR(100) NOT NULL,
+	on_escalation BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_webhook_deliveries (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	event VARCHAR(30) NOT NULL,
+	status INTEGER DEFAULT NULL,
+	attempt INTEGER NOT NULL,
+	success BOOLEAN NOT NULL,
+	time timestamptz NOT NULL DEFAULT now()
+);
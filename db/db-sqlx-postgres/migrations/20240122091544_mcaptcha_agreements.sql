@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_agreements (
+	user_id INTEGER NOT NULL references mcaptcha_users(ID) ON DELETE CASCADE,
+	document VARCHAR(30) NOT NULL,
+	version VARCHAR(100) NOT NULL,
+	ip_hash VARCHAR(100) NOT NULL,
+	accepted_at timestamptz NOT NULL DEFAULT now()
+);
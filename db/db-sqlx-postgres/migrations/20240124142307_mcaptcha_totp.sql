@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_totp (
+	user_id INTEGER NOT NULL UNIQUE references mcaptcha_users(ID) ON DELETE CASCADE,
+	secret VARCHAR(100) NOT NULL,
+	enabled BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_totp_recovery_codes (
+	user_id INTEGER NOT NULL references mcaptcha_users(ID) ON DELETE CASCADE,
+	code_hash VARCHAR(100) NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN verified BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS mcaptcha_email_verifications (
+	user_id INTEGER NOT NULL UNIQUE references mcaptcha_users(ID) ON DELETE CASCADE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	email VARCHAR(100) NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_oidc_identities (
+	user_id INTEGER NOT NULL references mcaptcha_users(ID) ON DELETE CASCADE,
+	issuer VARCHAR(500) NOT NULL,
+	subject VARCHAR(255) NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	UNIQUE(issuer, subject)
+);
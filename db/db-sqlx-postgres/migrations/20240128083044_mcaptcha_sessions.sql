@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_sessions (
+	session_id VARCHAR(100) PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL references mcaptcha_users(ID) ON DELETE CASCADE,
+	ip VARCHAR(100) NOT NULL,
+	user_agent VARCHAR(500) NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	last_active timestamptz NOT NULL DEFAULT now()
+);
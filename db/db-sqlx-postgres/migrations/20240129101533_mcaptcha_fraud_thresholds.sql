@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_fraud_thresholds (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL UNIQUE,
+	max_hash_rate BIGINT NOT NULL,
+	nonce_outlier_factor INTEGER NOT NULL,
+	repeated_result_limit INTEGER NOT NULL
+);
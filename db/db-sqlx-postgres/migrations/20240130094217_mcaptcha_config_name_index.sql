@@ -0,0 +1,2 @@
+-- Add migration script here
+CREATE INDEX IF NOT EXISTS mcaptcha_config_user_id_name ON mcaptcha_config(user_id, name);
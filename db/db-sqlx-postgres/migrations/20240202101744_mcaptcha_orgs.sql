@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_orgs (
+	ID SERIAL PRIMARY KEY NOT NULL,
+	name VARCHAR(100) NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	CONSTRAINT mcaptcha_orgs_name_key UNIQUE (name)
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_org_members (
+	org_id INTEGER references mcaptcha_orgs(ID) ON DELETE CASCADE NOT NULL,
+	user_id INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL,
+	role VARCHAR(10) NOT NULL,
+	UNIQUE(org_id, user_id)
+);
+
+ALTER TABLE mcaptcha_config ADD COLUMN org_id INTEGER
+	references mcaptcha_orgs(ID) ON DELETE SET NULL DEFAULT NULL;
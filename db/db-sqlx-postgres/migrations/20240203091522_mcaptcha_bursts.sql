@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_bursts (
+	ID SERIAL PRIMARY KEY NOT NULL,
+	config_id INTEGER references mcaptcha_config(config_id) ON DELETE CASCADE NOT NULL,
+	starts_at timestamptz NOT NULL,
+	ends_at timestamptz NOT NULL,
+	difficulty_factor INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_bursts_ends_at ON mcaptcha_bursts(ends_at);
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_invites (
+	ID SERIAL PRIMARY KEY NOT NULL,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	email VARCHAR(100) NOT NULL,
+	invited_by INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL,
+	org_id INTEGER references mcaptcha_orgs(ID) ON DELETE CASCADE DEFAULT NULL,
+	role VARCHAR(10) DEFAULT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	expires_at timestamptz NOT NULL,
+	accepted_at timestamptz DEFAULT NULL
+);
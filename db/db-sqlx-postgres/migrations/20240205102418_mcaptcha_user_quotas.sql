@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_user_quotas (
+	user_id INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL UNIQUE,
+	max_captchas INTEGER DEFAULT NULL,
+	max_analytics_records INTEGER DEFAULT NULL
+);
+
+-- analytics records are counted per sitekey to enforce quotas
+CREATE INDEX IF NOT EXISTS mcaptcha_pow_analytics_config_id
+	ON mcaptcha_pow_analytics(config_id);
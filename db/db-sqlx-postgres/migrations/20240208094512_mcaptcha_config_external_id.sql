@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config ADD COLUMN external_id VARCHAR(100) DEFAULT NULL;
+ALTER TABLE mcaptcha_config
+	ADD CONSTRAINT mcaptcha_config_external_id_key UNIQUE (user_id, external_id);
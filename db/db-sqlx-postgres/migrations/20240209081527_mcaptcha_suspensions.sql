@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_users ADD COLUMN suspended BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS mcaptcha_banned_email_domains (
+	ID SERIAL PRIMARY KEY NOT NULL,
+	domain VARCHAR(100) NOT NULL UNIQUE,
+	created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_difficulty_decay (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL UNIQUE,
+	sustain INTEGER NOT NULL,
+	step INTEGER NOT NULL,
+	steps INTEGER NOT NULL,
+	curve VARCHAR(20) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_escalation_log (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL,
+	kind VARCHAR(20) NOT NULL,
+	difficulty_factor INTEGER NOT NULL,
+	time timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_allowed_domains (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL,
+	domain VARCHAR(255) NOT NULL,
+	UNIQUE(config_id, domain)
+);
+
+ALTER TABLE mcaptcha_config ADD COLUMN domains_deadline timestamptz DEFAULT NULL;
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_demo_sitekeys (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL UNIQUE,
+	session_id VARCHAR(100) DEFAULT NULL,
+	created_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_demo_sitekeys_session_id ON mcaptcha_demo_sitekeys(session_id);
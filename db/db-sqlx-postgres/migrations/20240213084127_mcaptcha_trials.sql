@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_trials (
+	user_id INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL UNIQUE,
+	expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_trials_expires_at ON mcaptcha_trials(expires_at);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_api_tokens (
+	id SERIAL PRIMARY KEY NOT NULL,
+	user_id INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL,
+	name VARCHAR(100) NOT NULL,
+	token_hash VARCHAR(64) NOT NULL UNIQUE,
+	scope VARCHAR(50) NOT NULL,
+	created_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_api_tokens_user_id ON mcaptcha_api_tokens(user_id);
@@ -0,0 +1,18 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_notification_webhooks (
+	user_id INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL UNIQUE,
+	url VARCHAR(2048) NOT NULL,
+	secret VARCHAR(100) NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_notification_webhook_deliveries (
+	user_id INTEGER references mcaptcha_users(ID) ON DELETE CASCADE NOT NULL,
+	event VARCHAR(30) NOT NULL,
+	status INTEGER DEFAULT NULL,
+	attempt INTEGER NOT NULL,
+	success BOOLEAN NOT NULL,
+	time timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_notification_webhook_deliveries_user_id
+	ON mcaptcha_notification_webhook_deliveries(user_id);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_alert_thresholds (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL UNIQUE,
+	levels BOOLEAN NOT NULL,
+	highest_level BOOLEAN NOT NULL,
+	failure_rate INTEGER NOT NULL,
+	min_attempts INTEGER NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+CREATE INDEX IF NOT EXISTS mcaptcha_notifications_received
+	ON mcaptcha_notifications(received);
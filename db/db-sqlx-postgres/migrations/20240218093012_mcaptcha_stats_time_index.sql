@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE INDEX IF NOT EXISTS mcaptcha_pow_fetched_stats_time
+	ON mcaptcha_pow_fetched_stats(config_id, time);
+CREATE INDEX IF NOT EXISTS mcaptcha_pow_solved_stats_time
+	ON mcaptcha_pow_solved_stats(config_id, time);
+CREATE INDEX IF NOT EXISTS mcaptcha_pow_confirmed_stats_time
+	ON mcaptcha_pow_confirmed_stats(config_id, time);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_pow_solved_stats ADD COLUMN country CHAR(2) DEFAULT NULL;
+ALTER TABLE mcaptcha_pow_confirmed_stats ADD COLUMN country CHAR(2) DEFAULT NULL;
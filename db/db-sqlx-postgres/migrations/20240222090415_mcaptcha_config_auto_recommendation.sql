@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config
+	ADD COLUMN auto_recommendation BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_widget_themes (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE NOT NULL UNIQUE,
+	mode VARCHAR(10) NOT NULL,
+	accent_color VARCHAR(7) NOT NULL,
+	background_color VARCHAR(7),
+	text_color VARCHAR(7),
+	border_radius INTEGER NOT NULL,
+	show_logo BOOLEAN NOT NULL
+);
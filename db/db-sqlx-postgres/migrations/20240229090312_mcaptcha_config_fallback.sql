@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config
+	ADD COLUMN fallback BOOLEAN NOT NULL DEFAULT false,
+	ADD COLUMN fallback_wait INTEGER NOT NULL DEFAULT 10;
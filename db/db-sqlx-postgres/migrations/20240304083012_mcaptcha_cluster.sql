@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_challenges (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	string VARCHAR(100) NOT NULL UNIQUE,
+	difficulty_factor INTEGER NOT NULL,
+	expires_at timestamptz NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_tokens (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	expires_at timestamptz NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_visitors (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	instance_id VARCHAR(100) NOT NULL,
+	visitors INTEGER NOT NULL,
+	updated_at timestamptz NOT NULL,
+	UNIQUE(config_id, instance_id)
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_config
+	ADD COLUMN high_priority BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_email_outbox (
+	id SERIAL PRIMARY KEY NOT NULL,
+	sender VARCHAR(320) DEFAULT NULL,
+	recipients TEXT NOT NULL,
+	message TEXT NOT NULL,
+	attempts INTEGER NOT NULL DEFAULT 0,
+	next_attempt_at timestamptz NOT NULL,
+	last_error TEXT DEFAULT NULL,
+	dead BOOLEAN NOT NULL DEFAULT false,
+	created_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS mcaptcha_email_outbox_next_attempt_at
+	ON mcaptcha_email_outbox(dead, next_attempt_at);
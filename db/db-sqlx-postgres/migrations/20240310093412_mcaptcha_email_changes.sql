@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_email_changes (
+	user_id INTEGER NOT NULL UNIQUE references mcaptcha_users(ID) ON DELETE CASCADE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	cancel_token VARCHAR(100) NOT NULL UNIQUE,
+	email VARCHAR(100) NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_digest_subscriptions (
+	user_id INTEGER NOT NULL UNIQUE references mcaptcha_users(ID) ON DELETE CASCADE,
+	frequency VARCHAR(16) NOT NULL,
+	last_sent timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_known_devices (
+	user_id INTEGER NOT NULL references mcaptcha_users(ID) ON DELETE CASCADE,
+	device VARCHAR(64) NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	UNIQUE(user_id, device)
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_session_revocations (
+	user_id INTEGER NOT NULL references mcaptcha_users(ID) ON DELETE CASCADE,
+	token VARCHAR(100) NOT NULL UNIQUE,
+	created_at timestamptz NOT NULL DEFAULT now()
+);
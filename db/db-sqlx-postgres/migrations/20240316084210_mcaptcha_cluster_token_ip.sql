@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_cluster_tokens ADD COLUMN IF NOT EXISTS ip_hash VARCHAR(64) DEFAULT NULL;
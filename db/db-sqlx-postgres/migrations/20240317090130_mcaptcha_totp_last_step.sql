@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE mcaptcha_totp ADD COLUMN IF NOT EXISTS last_step BIGINT DEFAULT NULL;
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_fallbacks (
+	config_id INTEGER references mcaptcha_config(config_id)  ON DELETE CASCADE,
+	challenge_id VARCHAR(100) NOT NULL UNIQUE,
+	ready_at timestamptz NOT NULL,
+	expires_at timestamptz NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS mcaptcha_cluster_fallback_windows (
+	ip_hash VARCHAR(64) NOT NULL UNIQUE,
+	started INTEGER NOT NULL,
+	expires_at timestamptz NOT NULL
+);
@@ -32,6 +32,10 @@ pub fn map_register_err(e: Error) -> DBError {
                 DBError::SecretTaken
             } else if msg.contains("mcaptcha_config_key_key") {
                 DBError::CaptchaKeyTaken
+            } else if msg.contains("mcaptcha_orgs_name_key") {
+                DBError::OrgNameTaken
+            } else if msg.contains("mcaptcha_config_external_id_key") {
+                DBError::ExternalIdTaken
             } else {
                 DBError::DBError(Box::new(Error::Database(err)))
             }
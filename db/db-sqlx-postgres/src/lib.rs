@@ -90,6 +90,7 @@ impl Migrate for Database {
 #[async_trait]
 impl MCDatabase for Database {
     /// ping DB
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn ping(&self) -> bool {
         use sqlx::Connection;
 
@@ -101,6 +102,7 @@ impl MCDatabase for Database {
     }
 
     /// register a new user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn register(&self, p: &Register) -> DBResult<()> {
         let res = if let Some(email) = &p.email {
             sqlx::query!(
@@ -129,6 +131,7 @@ impl MCDatabase for Database {
     }
 
     /// delete a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn delete_user(&self, username: &str) -> DBResult<()> {
         sqlx::query!("DELETE FROM mcaptcha_users WHERE name = ($1)", username)
             .execute(&self.pool)
@@ -138,6 +141,7 @@ impl MCDatabase for Database {
     }
 
     /// check if username exists
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn username_exists(&self, username: &str) -> DBResult<bool> {
         let res = sqlx::query!(
             "SELECT EXISTS (SELECT 1 from mcaptcha_users WHERE name = $1)",
@@ -156,6 +160,7 @@ impl MCDatabase for Database {
     }
 
     /// get user email
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_email(&self, username: &str) -> DBResult<Option<String>> {
         struct Email {
             email: Option<String>,
@@ -173,6 +178,7 @@ impl MCDatabase for Database {
     }
 
     /// check if email exists
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn email_exists(&self, email: &str) -> DBResult<bool> {
         let res = sqlx::query!(
             "SELECT EXISTS (SELECT 1 from mcaptcha_users WHERE email = $1)",
@@ -191,6 +197,7 @@ impl MCDatabase for Database {
     }
 
     /// update a user's email
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_email(&self, p: &UpdateEmail) -> DBResult<()> {
         sqlx::query!(
             "UPDATE mcaptcha_users set email = $1
@@ -206,6 +213,7 @@ impl MCDatabase for Database {
     }
 
     /// get a user's password
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_password(&self, l: &Login) -> DBResult<NameHash> {
         struct Password {
             name: String,
@@ -241,6 +249,7 @@ impl MCDatabase for Database {
     }
 
     /// update user's password
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_password(&self, p: &NameHash) -> DBResult<()> {
         sqlx::query!(
             "UPDATE mcaptcha_users set password = $1
@@ -256,6 +265,7 @@ impl MCDatabase for Database {
     }
 
     /// update username
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_username(&self, current: &str, new: &str) -> DBResult<()> {
         sqlx::query!(
             "UPDATE mcaptcha_users set name = $1
@@ -271,6 +281,7 @@ impl MCDatabase for Database {
     }
 
     /// get a user's secret
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_secret(&self, username: &str) -> DBResult<Secret> {
         let secret = sqlx::query_as!(
             Secret,
@@ -285,6 +296,7 @@ impl MCDatabase for Database {
     }
 
     /// get a user's secret from a captcha key
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_secret_from_captcha(&self, key: &str) -> DBResult<Secret> {
         let secret = sqlx::query_as!(
             Secret,
@@ -301,6 +313,7 @@ impl MCDatabase for Database {
     }
 
     /// update a user's secret
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_secret(&self, username: &str, secret: &str) -> DBResult<()> {
         sqlx::query!(
             "UPDATE mcaptcha_users set secret = $1
@@ -316,6 +329,7 @@ impl MCDatabase for Database {
     }
 
     /// create new captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn create_captcha(&self, username: &str, p: &CreateCaptcha) -> DBResult<()> {
         sqlx::query!(
             "INSERT INTO mcaptcha_config
@@ -334,6 +348,7 @@ impl MCDatabase for Database {
     }
 
     /// Get captcha config
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_captcha_config(&self, username: &str, key: &str) -> DBResult<Captcha> {
         let captcha = sqlx::query_as!(
             InternaleCaptchaConfig,
@@ -351,6 +366,7 @@ impl MCDatabase for Database {
     }
 
     /// Get all captchas belonging to user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_all_user_captchas(&self, username: &str) -> DBResult<Vec<Captcha>> {
         let mut res = sqlx::query_as!(
             InternaleCaptchaConfig,
@@ -369,7 +385,132 @@ impl MCDatabase for Database {
         Ok(captchas)
     }
 
+    /// Get up to `limit` captchas belonging to user, skipping the first
+    /// `offset`, in the order that they were created
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_user_captchas_page(
+        &self,
+        username: &str,
+        offset: usize,
+        limit: usize,
+    ) -> DBResult<Vec<Captcha>> {
+        let res = sqlx::query_as!(
+            InternaleCaptchaConfig,
+            "SELECT key, name, config_id, duration FROM mcaptcha_config WHERE
+            user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)
+            ORDER BY config_id
+            LIMIT $2 OFFSET $3",
+            &username,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(res.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Count captchas belonging to user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_user_captchas(&self, username: &str) -> DBResult<usize> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) as count FROM mcaptcha_config
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(count.count.unwrap_or_default() as usize)
+    }
+
+    /// Get keys of all captchas, across all users
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_all_captcha_keys(&self) -> DBResult<Vec<String>> {
+        struct Key {
+            key: String,
+        }
+
+        let keys = sqlx::query_as!(Key, "SELECT key FROM mcaptcha_config")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(keys.into_iter().map(|k| k.key).collect())
+    }
+
+    /// Get keys of up to `limit` captchas, across all users, most recently
+    /// fetched first. Captchas that were never fetched come last
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_recently_active_captcha_keys(
+        &self,
+        limit: usize,
+    ) -> DBResult<Vec<String>> {
+        struct Key {
+            key: String,
+        }
+
+        let keys = sqlx::query_as!(
+            Key,
+            "SELECT mcaptcha_config.key FROM mcaptcha_config
+            LEFT JOIN mcaptcha_pow_fetched_stats
+                ON mcaptcha_pow_fetched_stats.config_id = mcaptcha_config.config_id
+            GROUP BY mcaptcha_config.config_id, mcaptcha_config.key
+            ORDER BY MAX(mcaptcha_pow_fetched_stats.time) DESC NULLS LAST
+            LIMIT $1",
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(keys.into_iter().map(|k| k.key).collect())
+    }
+
+    /// Check if a user has a captcha with the given description, ignoring the
+    /// captcha `except`, if set
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn captcha_description_exists(
+        &self,
+        username: &str,
+        description: &str,
+        except: Option<&str>,
+    ) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "SELECT EXISTS (
+                SELECT 1 FROM mcaptcha_config WHERE name = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+                AND key != $3
+            )",
+            description,
+            username,
+            except.unwrap_or_default(),
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(res.exists.unwrap_or(false))
+    }
+
+    /// Get username of the owner of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_captcha_owner(&self, captcha_key: &str) -> DBResult<String> {
+        let owner = sqlx::query!(
+            "SELECT name FROM mcaptcha_users
+            WHERE ID = (SELECT user_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(owner.name)
+    }
+
     /// update captcha metadata; doesn't change captcha key
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_captcha_metadata(
         &self,
         username: &str,
@@ -392,6 +533,7 @@ impl MCDatabase for Database {
     }
 
     /// update captcha key; doesn't change metadata
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_captcha_key(
         &self,
         username: &str,
@@ -413,6 +555,7 @@ impl MCDatabase for Database {
     }
 
     /// Add levels to captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn add_captcha_levels(
         &self,
         username: &str,
@@ -484,6 +627,7 @@ impl MCDatabase for Database {
     }
 
     /// check if captcha exists
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn captcha_exists(
         &self,
         username: Option<&str>,
@@ -527,6 +671,7 @@ impl MCDatabase for Database {
     }
 
     /// Delete all levels of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn delete_captcha_levels(
         &self,
         username: &str,
@@ -551,6 +696,7 @@ impl MCDatabase for Database {
     }
 
     /// Delete captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn delete_captcha(&self, username: &str, captcha_key: &str) -> DBResult<()> {
         sqlx::query!(
             "DELETE FROM mcaptcha_config WHERE key = ($1)
@@ -567,6 +713,7 @@ impl MCDatabase for Database {
     }
 
     /// Get captcha levels
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_captcha_levels(
         &self,
         username: Option<&str>,
@@ -616,6 +763,7 @@ impl MCDatabase for Database {
     }
 
     /// Get captcha's cooldown period
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_captcha_cooldown(&self, captcha_key: &str) -> DBResult<i32> {
         struct DurationResp {
             duration: i32,
@@ -633,7 +781,170 @@ impl MCDatabase for Database {
 
         Ok(resp.duration)
     }
+
+    /// Set strict single-use mode for validation tokens of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn update_captcha_strict_tokens(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        strict: bool,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_config SET strict_tokens = $1
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            AND key = $3",
+            strict,
+            username,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(())
+    }
+
+    /// Check if validation tokens of a captcha are strictly single-use
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn captcha_strict_tokens(&self, captcha_key: &str) -> DBResult<bool> {
+        struct StrictResp {
+            strict_tokens: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            StrictResp,
+            "SELECT strict_tokens FROM mcaptcha_config WHERE key = $1",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(resp.strict_tokens)
+    }
+
+    /// Set scheduling priority of PoW verifications of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn update_captcha_priority(
+        &self,
+        captcha_key: &str,
+        high_priority: bool,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_config SET high_priority = $1 WHERE key = $2",
+            high_priority,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(())
+    }
+
+    /// Check if PoW verifications of a captcha are scheduled ahead of others
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn captcha_high_priority(&self, captcha_key: &str) -> DBResult<bool> {
+        struct PriorityResp {
+            high_priority: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            PriorityResp,
+            "SELECT high_priority FROM mcaptcha_config WHERE key = $1",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(resp.high_priority)
+    }
+
+    /// Mark validation token as consumed. Returns false if the token was
+    /// already consumed
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn consume_token(&self, captcha_key: &str, token: &str) -> DBResult<bool> {
+        let now = now_unix_time_stamp();
+        let res = sqlx::query!(
+            "INSERT INTO mcaptcha_consumed_tokens (config_id, token, time)
+            VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2, $3)
+            ON CONFLICT (token) DO NOTHING",
+            captcha_key,
+            token,
+            &now
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Check if validation token was consumed
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn token_is_consumed(&self, captcha_key: &str, token: &str) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "SELECT EXISTS (
+                SELECT 1 FROM mcaptcha_consumed_tokens
+                WHERE token = $1
+                AND config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $2)
+            )",
+            token,
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(res.exists.unwrap_or(false))
+    }
+
+    /// record validation token replays
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_token_replay(&self, captcha_key: &str) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        let _ = sqlx::query!(
+            "INSERT INTO mcaptcha_token_replays
+            (config_id, time) VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2)",
+            captcha_key,
+            &now
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// fetch validation token replays
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_token_replays(&self, user: &str, key: &str) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_token_replays
+            WHERE
+                config_id = (
+                    SELECT config_id FROM mcaptcha_config
+                WHERE
+                    key = $1
+                AND
+                     user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+                ORDER BY time DESC",
+            &key,
+            &user
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
     /// Add traffic configuration
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn add_traffic_pattern(
         &self,
         username: &str,
@@ -664,6 +975,7 @@ impl MCDatabase for Database {
     }
 
     /// Get traffic configuration
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_traffic_pattern(
         &self,
         username: &str,
@@ -705,6 +1017,7 @@ impl MCDatabase for Database {
     }
 
     /// Get all easy captcha configurations on instance
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_all_easy_captchas(
         &self,
         limit: usize,
@@ -765,6 +1078,7 @@ impl MCDatabase for Database {
     }
 
     /// Delete traffic configuration
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn delete_traffic_pattern(
         &self,
         username: &str,
@@ -791,6 +1105,7 @@ impl MCDatabase for Database {
     }
 
     /// create new notification
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn create_notification(&self, p: &AddNotification) -> DBResult<()> {
         let now = now_unix_time_stamp();
         sqlx::query!(
@@ -816,6 +1131,7 @@ impl MCDatabase for Database {
     }
 
     /// get all unread notifications
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_all_unread_notifications(
         &self,
         username: &str,
@@ -839,6 +1155,7 @@ impl MCDatabase for Database {
     }
 
     /// mark a notification read
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn mark_notification_read(&self, username: &str, id: i32) -> DBResult<()> {
         sqlx::query_file_as!(
             Notification,
@@ -854,6 +1171,7 @@ impl MCDatabase for Database {
     }
 
     /// record PoWConfig fetches
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn record_fetch(&self, key: &str) -> DBResult<()> {
         let now = now_unix_time_stamp();
         let _ = sqlx::query!(
@@ -869,13 +1187,15 @@ impl MCDatabase for Database {
     }
 
     /// record PoWConfig solves
-    async fn record_solve(&self, key: &str) -> DBResult<()> {
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_solve(&self, key: &str, country: Option<&str>) -> DBResult<()> {
         let now = OffsetDateTime::now_utc();
         let _ = sqlx::query!(
         "INSERT INTO mcaptcha_pow_solved_stats 
-        (config_id, time) VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2)",
+        (config_id, time, country) VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2, $3)",
         key,
         &now,
+        country,
     )
     .execute(&self.pool)
     .await
@@ -884,13 +1204,15 @@ impl MCDatabase for Database {
     }
 
     /// record PoWConfig confirms
-    async fn record_confirm(&self, key: &str) -> DBResult<()> {
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_confirm(&self, key: &str, country: Option<&str>) -> DBResult<()> {
         let now = now_unix_time_stamp();
         let _ = sqlx::query!(
         "INSERT INTO mcaptcha_pow_confirmed_stats 
-        (config_id, time) VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2)",
+        (config_id, time, country) VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2, $3)",
         key,
-        &now
+        &now,
+        country,
     )
     .execute(&self.pool)
     .await
@@ -898,7 +1220,29 @@ impl MCDatabase for Database {
         Ok(())
     }
 
+    /// record a batch of PoWConfig fetches
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_fetches(&self, records: &[StatsRecord]) -> DBResult<()> {
+        insert_stats_records(&self.pool, "mcaptcha_pow_fetched_stats", records, false)
+            .await
+    }
+
+    /// record a batch of PoWConfig solves
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_solves(&self, records: &[StatsRecord]) -> DBResult<()> {
+        insert_stats_records(&self.pool, "mcaptcha_pow_solved_stats", records, true)
+            .await
+    }
+
+    /// record a batch of PoWConfig confirms
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_confirms(&self, records: &[StatsRecord]) -> DBResult<()> {
+        insert_stats_records(&self.pool, "mcaptcha_pow_confirmed_stats", records, true)
+            .await
+    }
+
     /// fetch PoWConfig fetches
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn fetch_config_fetched(&self, user: &str, key: &str) -> DBResult<Vec<i64>> {
         let records = sqlx::query_as!(
             Date,
@@ -925,6 +1269,7 @@ impl MCDatabase for Database {
     }
 
     /// fetch PoWConfig solves
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn fetch_solve(&self, user: &str, key: &str) -> DBResult<Vec<i64>> {
         let records = sqlx::query_as!(
             Date,
@@ -949,6 +1294,7 @@ impl MCDatabase for Database {
     }
 
     /// fetch PoWConfig confirms
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn fetch_confirm(&self, user: &str, key: &str) -> DBResult<Vec<i64>> {
         let records = sqlx::query_as!(
             Date,
@@ -974,6 +1320,7 @@ impl MCDatabase for Database {
     }
 
     /// record PoW timing
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analysis_save(
         &self,
         captcha_id: &str,
@@ -995,6 +1342,7 @@ impl MCDatabase for Database {
     }
 
     /// fetch PoW analytics
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analytics_fetch(
         &self,
         captcha_id: &str,
@@ -1047,7 +1395,117 @@ impl MCDatabase for Database {
         Ok(res)
     }
 
+    /// fetch PoW analytics logged after an ID
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn analytics_fetch_after(
+        &self,
+        captcha_id: &str,
+        after: usize,
+        limit: usize,
+    ) -> DBResult<Vec<PerformanceAnalytics>> {
+        struct P {
+            id: i32,
+            time: i32,
+            difficulty_factor: i32,
+            worker_type: String,
+        }
+
+        impl From<P> for PerformanceAnalytics {
+            fn from(v: P) -> Self {
+                Self {
+                    time: v.time as u32,
+                    difficulty_factor: v.difficulty_factor as u32,
+                    worker_type: v.worker_type,
+                    id: v.id as usize,
+                }
+            }
+        }
+
+        let mut c = sqlx::query_as!(
+            P,
+            "SELECT id, time, difficulty_factor, worker_type FROM mcaptcha_pow_analytics
+            WHERE 
+                config_id = (
+                    SELECT 
+                        config_id FROM mcaptcha_config 
+                    WHERE 
+                        key = $1
+                        )
+                AND ID > $2
+                ORDER BY ID
+                LIMIT $3
+                ",
+            &captcha_id,
+            after as i32,
+            limit as i32
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        let mut res = Vec::with_capacity(c.len());
+        for i in c.drain(0..) {
+            res.push(i.into())
+        }
+
+        Ok(res)
+    }
+
+    /// fetch percentile solve times by difficulty factor and worker type
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn analytics_fetch_percentiles(
+        &self,
+        user: &str,
+        key: &str,
+    ) -> DBResult<Vec<SolveTimePercentiles>> {
+        struct InnerPercentiles {
+            difficulty_factor: i32,
+            worker_type: String,
+            samples: i64,
+            p50: i32,
+            p90: i32,
+            p99: i32,
+        }
+
+        let percentiles = sqlx::query_as!(
+            InnerPercentiles,
+            r#"SELECT
+                difficulty_factor,
+                worker_type,
+                COUNT(*) AS "samples!",
+                PERCENTILE_DISC(0.5) WITHIN GROUP (ORDER BY time) AS "p50!",
+                PERCENTILE_DISC(0.9) WITHIN GROUP (ORDER BY time) AS "p90!",
+                PERCENTILE_DISC(0.99) WITHIN GROUP (ORDER BY time) AS "p99!"
+            FROM mcaptcha_pow_analytics
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE
+                    key = $1
+                AND
+                    user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2))
+            GROUP BY difficulty_factor, worker_type
+            ORDER BY difficulty_factor, worker_type"#,
+            &key,
+            &user,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(percentiles
+            .into_iter()
+            .map(|p| SolveTimePercentiles {
+                difficulty_factor: p.difficulty_factor as u32,
+                worker_type: p.worker_type,
+                samples: p.samples as u64,
+                p50: p.p50 as u32,
+                p90: p.p90 as u32,
+                p99: p.p99 as u32,
+            })
+            .collect())
+    }
+
     /// Create psuedo ID against campaign ID to publish analytics
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analytics_create_psuedo_id_if_not_exists(
         &self,
         captcha_id: &str,
@@ -1072,6 +1530,7 @@ impl MCDatabase for Database {
     }
 
     /// Get psuedo ID from campaign ID
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analytics_get_psuedo_id_from_capmaign_id(
         &self,
         captcha_id: &str,
@@ -1093,6 +1552,7 @@ impl MCDatabase for Database {
     }
 
     /// Get campaign ID from psuedo ID
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analytics_get_capmaign_id_from_psuedo_id(
         &self,
         psuedo_id: &str,
@@ -1124,6 +1584,26 @@ impl MCDatabase for Database {
         Ok(res.key)
     }
 
+    /// Get number of analytics records of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn analytics_count(&self, captcha_key: &str) -> DBResult<usize> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) AS count FROM mcaptcha_pow_analytics
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(count.count.unwrap_or_default() as usize)
+    }
+
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analytics_delete_all_records_for_campaign(
         &self,
         campaign_id: &str,
@@ -1158,6 +1638,7 @@ impl MCDatabase for Database {
     }
 
     /// Get all psuedo IDs
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn analytics_get_all_psuedo_ids(&self, page: usize) -> DBResult<Vec<String>> {
         const LIMIT: usize = 50;
         let offset = LIMIT * page;
@@ -1181,6 +1662,7 @@ impl MCDatabase for Database {
     }
 
     /// Track maximum nonce received against captcha levels
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn update_max_nonce_for_level(
         &self,
         captcha_key: &str,
@@ -1211,6 +1693,7 @@ impl MCDatabase for Database {
     }
 
     /// Get maximum nonce tracked so far for captcha levels
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn get_max_nonce_for_level(
         &self,
         captcha_key: &str,
@@ -1279,6 +1762,7 @@ impl MCDatabase for Database {
     }
 
     /// Get number of analytics entries that are under a certain duration
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn stats_get_num_logs_under_time(&self, duration: u32) -> DBResult<usize> {
         struct Count {
             count: Option<i64>,
@@ -1298,6 +1782,7 @@ impl MCDatabase for Database {
 
     /// Get the entry at a location in the list of analytics entires under a certain time limit
     /// and sorted in ascending order
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
     async fn stats_get_entry_at_location_for_time_limit_asc(
         &self,
         duration: u32,
@@ -1327,9 +1812,3949 @@ impl MCDatabase for Database {
             Err(e) => Err(map_row_not_found_err(e, DBError::CaptchaNotFound)),
         }
     }
-}
 
-#[derive(Clone)]
+    /// Reclaim space and refresh planner statistics after large deletions
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn run_maintenance(&self) -> DBResult<()> {
+        sqlx::query!(
+            "VACUUM ANALYZE
+                mcaptcha_users,
+                mcaptcha_config,
+                mcaptcha_levels,
+                mcaptcha_track_nonce,
+                mcaptcha_sitekey_user_provided_avg_traffic,
+                mcaptcha_pow_fetched_stats,
+                mcaptcha_pow_solved_stats,
+                mcaptcha_pow_confirmed_stats,
+                mcaptcha_pow_analytics,
+                mcaptcha_psuedo_campaign_id,
+                mcaptcha_notifications,
+                mcaptcha_consumed_tokens,
+                mcaptcha_token_replays,
+                mcaptcha_webhooks,
+                mcaptcha_webhook_deliveries"
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Set webhook of a captcha; replaces existing webhook, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_webhook(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        webhook: &Webhook,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_webhooks (config_id, url, secret, on_escalation)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = ($1)
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                $3, $4, $5)
+            ON CONFLICT (config_id) DO UPDATE SET
+                url = EXCLUDED.url,
+                secret = EXCLUDED.secret,
+                on_escalation = EXCLUDED.on_escalation",
+            captcha_key,
+            username,
+            &webhook.url,
+            &webhook.secret,
+            webhook.on_escalation,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get webhook of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_webhook(&self, captcha_key: &str) -> DBResult<Webhook> {
+        let webhook = sqlx::query_as!(
+            Webhook,
+            "SELECT url, secret, on_escalation FROM mcaptcha_webhooks
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::WebhookNotFound))?;
+        Ok(webhook)
+    }
+
+    /// Delete webhook of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_webhook(&self, username: &str, captcha_key: &str) -> DBResult<()> {
+        sqlx::query!(
+            "DELETE FROM mcaptcha_webhooks
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = ($1)
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            )",
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::WebhookNotFound))?;
+        Ok(())
+    }
+
+    /// Log webhook delivery attempt
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_webhook_delivery(
+        &self,
+        captcha_key: &str,
+        delivery: &CreateWebhookDelivery,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_webhook_deliveries
+            (config_id, event, status, attempt, success, time)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1),
+                $2, $3, $4, $5, $6)",
+            captcha_key,
+            delivery.event,
+            delivery.status.map(|s| s as i32),
+            delivery.attempt as i32,
+            delivery.success,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Fetch webhook delivery logs of a captcha, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_webhook_deliveries(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        limit: u32,
+    ) -> DBResult<Vec<WebhookDelivery>> {
+        let records = sqlx::query_as!(
+            InnerWebhookDelivery,
+            "SELECT event, status, attempt, success, time FROM mcaptcha_webhook_deliveries
+            WHERE
+                config_id = (
+                    SELECT config_id FROM mcaptcha_config
+                WHERE
+                    key = $1
+                AND
+                     user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+                ORDER BY time DESC
+                LIMIT $3",
+            captcha_key,
+            username,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(records.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Set thresholds of fraud heuristics of a captcha, enabling them; replaces
+    /// existing thresholds, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_fraud_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        thresholds: &FraudThresholds,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_fraud_thresholds
+                (config_id, max_hash_rate, nonce_outlier_factor, repeated_result_limit)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                $3, $4, $5)
+            ON CONFLICT (config_id) DO UPDATE SET
+                max_hash_rate = EXCLUDED.max_hash_rate,
+                nonce_outlier_factor = EXCLUDED.nonce_outlier_factor,
+                repeated_result_limit = EXCLUDED.repeated_result_limit",
+            captcha_key,
+            username,
+            thresholds.max_hash_rate as i64,
+            thresholds.nonce_outlier_factor as i32,
+            thresholds.repeated_result_limit as i32,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get thresholds of fraud heuristics of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_fraud_thresholds(
+        &self,
+        captcha_key: &str,
+    ) -> DBResult<FraudThresholds> {
+        let thresholds = sqlx::query_as!(
+            InnerFraudThresholds,
+            "SELECT max_hash_rate, nonce_outlier_factor, repeated_result_limit
+            FROM mcaptcha_fraud_thresholds
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::FraudThresholdsNotFound))?;
+        Ok(thresholds.into())
+    }
+
+    /// Delete thresholds of fraud heuristics of a captcha, disabling them
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_fraud_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_fraud_thresholds
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            )",
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::FraudThresholdsNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::FraudThresholdsNotFound);
+        }
+        Ok(())
+    }
+
+    /// Record acceptance of a legal document by a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn accept_agreement(
+        &self,
+        username: &str,
+        agreement: &AcceptAgreement,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_agreements
+            (user_id, document, version, ip_hash, accepted_at)
+            VALUES (
+                (SELECT ID FROM mcaptcha_users WHERE name = $1),
+                $2, $3, $4, $5)",
+            username,
+            agreement.document,
+            agreement.version,
+            agreement.ip_hash,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Fetch all agreements accepted by a user, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_agreements(&self, username: &str) -> DBResult<Vec<Agreement>> {
+        let records = sqlx::query_as!(
+            InnerAgreement,
+            "SELECT document, version, ip_hash, accepted_at FROM mcaptcha_agreements
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)
+            ORDER BY accepted_at DESC",
+            username,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+
+        Ok(records.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Set TOTP secret of a user; two-factor authentication stays disabled
+    /// until it is enabled
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_totp(&self, username: &str, secret: &str) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_totp (user_id, secret, enabled)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, false)
+            ON CONFLICT (user_id) DO UPDATE SET
+                secret = EXCLUDED.secret,
+                enabled = false,
+                last_step = NULL",
+            username,
+            secret,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get TOTP configuration of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_totp(&self, username: &str) -> DBResult<Totp> {
+        let totp = sqlx::query_as!(
+            Totp,
+            "SELECT secret, enabled FROM mcaptcha_totp
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::TotpNotFound))?;
+        Ok(totp)
+    }
+
+    /// Enable two-factor authentication and replace recovery codes of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn enable_totp(
+        &self,
+        username: &str,
+        recovery_codes: &[String],
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_totp SET enabled = true
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::TotpNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::TotpNotFound);
+        }
+
+        sqlx::query!(
+            "DELETE FROM mcaptcha_totp_recovery_codes
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+
+        for code in recovery_codes.iter() {
+            sqlx::query!(
+                "INSERT INTO mcaptcha_totp_recovery_codes (user_id, code_hash)
+                VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2)",
+                username,
+                code,
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        }
+        Ok(())
+    }
+
+    /// Delete TOTP secret and recovery codes of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_totp(&self, username: &str) -> DBResult<()> {
+        sqlx::query!(
+            "DELETE FROM mcaptcha_totp_recovery_codes
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        sqlx::query!(
+            "DELETE FROM mcaptcha_totp
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Consume recovery code of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn use_recovery_code(
+        &self,
+        username: &str,
+        code_hash: &str,
+    ) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_totp_recovery_codes
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)
+            AND code_hash = $2",
+            username,
+            code_hash,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Record that a user used a TOTP code of time step `step`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn use_totp_step(&self, username: &str, step: i64) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_totp SET last_step = $2
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)
+            AND (last_step IS NULL OR last_step < $2)",
+            username,
+            step,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::TotpNotFound))?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Store email verification token of a user. Replaces pending token, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_email_verification(
+        &self,
+        username: &str,
+        v: &AddEmailVerification,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_email_verifications (user_id, token, email, created_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3, $4)
+            ON CONFLICT (user_id) DO UPDATE SET
+                token = EXCLUDED.token,
+                email = EXCLUDED.email,
+                created_at = EXCLUDED.created_at",
+            username,
+            v.token,
+            v.email,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Delete email verification token and return its details
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn consume_email_verification(
+        &self,
+        token: &str,
+    ) -> DBResult<EmailVerification> {
+        let v = sqlx::query_as!(
+            InnerEmailVerification,
+            r#"DELETE FROM mcaptcha_email_verifications WHERE token = $1
+            RETURNING
+                (SELECT name FROM mcaptcha_users
+                    WHERE ID = mcaptcha_email_verifications.user_id) as "username!",
+                email,
+                created_at"#,
+            token,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::EmailVerificationNotFound))?;
+        Ok(v.into())
+    }
+
+    /// Store pending email change of a user. Replaces pending change, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_email_change(
+        &self,
+        username: &str,
+        c: &AddEmailChange,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_email_changes (user_id, token, cancel_token, email, created_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3, $4, $5)
+            ON CONFLICT (user_id) DO UPDATE SET
+                token = EXCLUDED.token,
+                cancel_token = EXCLUDED.cancel_token,
+                email = EXCLUDED.email,
+                created_at = EXCLUDED.created_at",
+            username,
+            c.token,
+            c.cancel_token,
+            c.email,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get pending email change of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_email_change(&self, username: &str) -> DBResult<Option<EmailChange>> {
+        let c = sqlx::query_as!(
+            InnerEmailVerification,
+            "SELECT mcaptcha_users.name as username, mcaptcha_email_changes.email, created_at
+            FROM mcaptcha_email_changes
+            INNER JOIN mcaptcha_users
+                ON mcaptcha_users.ID = mcaptcha_email_changes.user_id
+            WHERE mcaptcha_users.name = $1",
+            username,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(c.map(|c| c.into()))
+    }
+
+    /// Delete pending email change with confirmation token `token` and return
+    /// its details
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn consume_email_change(&self, token: &str) -> DBResult<EmailChange> {
+        let c = sqlx::query_as!(
+            InnerEmailVerification,
+            r#"DELETE FROM mcaptcha_email_changes WHERE token = $1
+            RETURNING
+                (SELECT name FROM mcaptcha_users
+                    WHERE ID = mcaptcha_email_changes.user_id) as "username!",
+                email,
+                created_at"#,
+            token,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::EmailChangeNotFound))?;
+        Ok(c.into())
+    }
+
+    /// Delete pending email change with cancellation token `cancel_token` and
+    /// return its details
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn cancel_email_change(&self, cancel_token: &str) -> DBResult<EmailChange> {
+        let c = sqlx::query_as!(
+            InnerEmailVerification,
+            r#"DELETE FROM mcaptcha_email_changes WHERE cancel_token = $1
+            RETURNING
+                (SELECT name FROM mcaptcha_users
+                    WHERE ID = mcaptcha_email_changes.user_id) as "username!",
+                email,
+                created_at"#,
+            cancel_token,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::EmailChangeNotFound))?;
+        Ok(c.into())
+    }
+
+    /// Mark email address of a user as verified or unverified
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_email_verified(&self, username: &str, verified: bool) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_users SET verified = $2 WHERE name = $1",
+            username,
+            verified,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::AccountNotFound);
+        }
+        Ok(())
+    }
+
+    /// Check if email address of a user is verified
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_email_verified(&self, username: &str) -> DBResult<bool> {
+        struct VerifiedResp {
+            verified: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            VerifiedResp,
+            "SELECT verified FROM mcaptcha_users WHERE name = $1",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(resp.verified)
+    }
+
+    /// Link OpenID Connect identity to a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn link_oidc_identity(
+        &self,
+        username: &str,
+        identity: &OidcIdentity,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_oidc_identities (user_id, issuer, subject, created_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3, $4)",
+            username,
+            identity.issuer,
+            identity.subject,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Get username of the user to whom an OpenID Connect identity is linked
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_oidc_user(&self, identity: &OidcIdentity) -> DBResult<String> {
+        struct Username {
+            name: String,
+        }
+
+        let user = sqlx::query_as!(
+            Username,
+            "SELECT mcaptcha_users.name FROM mcaptcha_users
+            INNER JOIN mcaptcha_oidc_identities
+                ON mcaptcha_oidc_identities.user_id = mcaptcha_users.ID
+            WHERE issuer = $1 AND subject = $2",
+            identity.issuer,
+            identity.subject,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::OidcIdentityNotFound))?;
+        Ok(user.name)
+    }
+
+    /// Create sign-in session of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn create_session(
+        &self,
+        username: &str,
+        session: &CreateSession,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_sessions
+                (session_id, user_id, ip, user_agent, created_at, last_active)
+            VALUES ($1, (SELECT ID FROM mcaptcha_users WHERE name = $2), $3, $4, $5, $6)",
+            session.id,
+            username,
+            session.ip,
+            session.user_agent,
+            &now,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get session
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_session(&self, id: &str) -> DBResult<Session> {
+        let session = sqlx::query_as!(
+            InnerSession,
+            "SELECT session_id, mcaptcha_users.name as username, ip, user_agent,
+                created_at, last_active
+            FROM mcaptcha_sessions
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_sessions.user_id
+            WHERE session_id = $1",
+            id,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::SessionNotFound))?;
+        Ok(session.into())
+    }
+
+    /// Update last activity time of a session to now
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn touch_session(&self, id: &str) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "UPDATE mcaptcha_sessions SET last_active = $1 WHERE session_id = $2",
+            &now,
+            id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::SessionNotFound))?;
+        Ok(())
+    }
+
+    /// Get all sessions of a user, most recently active first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_user_sessions(&self, username: &str) -> DBResult<Vec<Session>> {
+        let sessions = sqlx::query_as!(
+            InnerSession,
+            "SELECT session_id, mcaptcha_users.name as username, ip, user_agent,
+                created_at, last_active
+            FROM mcaptcha_sessions
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_sessions.user_id
+            WHERE mcaptcha_users.name = $1
+            ORDER BY last_active DESC",
+            username,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(sessions.into_iter().map(|s| s.into()).collect())
+    }
+
+    /// Delete session of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_session(&self, username: &str, id: &str) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_sessions
+            WHERE session_id = $1
+            AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            id,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::SessionNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::SessionNotFound);
+        }
+        Ok(())
+    }
+
+    /// Delete all sessions of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_user_sessions(&self, username: &str) -> DBResult<()> {
+        sqlx::query!(
+            "DELETE FROM mcaptcha_sessions
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Record device from which a user signed in. Returns `true` when the
+    /// device wasn't known yet
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_known_device(&self, username: &str, device: &str) -> DBResult<bool> {
+        let now = now_unix_time_stamp();
+        let res = sqlx::query!(
+            "INSERT INTO mcaptcha_known_devices (user_id, device, created_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3)
+            ON CONFLICT (user_id, device) DO NOTHING",
+            username,
+            device,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Count devices from which a user signed in
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_known_devices(&self, username: &str) -> DBResult<usize> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) as count FROM mcaptcha_known_devices
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(count.count.unwrap_or_default() as usize)
+    }
+
+    /// Forget all devices from which a user signed in
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_known_devices(&self, username: &str) -> DBResult<()> {
+        sqlx::query!(
+            "DELETE FROM mcaptcha_known_devices
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(())
+    }
+
+    /// Store token that revokes all sessions of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_session_revocation(&self, username: &str, token: &str) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_session_revocations (user_id, token, created_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3)",
+            username,
+            token,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Delete session revocation token and return its details
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn consume_session_revocation(
+        &self,
+        token: &str,
+    ) -> DBResult<SessionRevocation> {
+        let r = sqlx::query_as!(
+            InnerSessionRevocation,
+            r#"DELETE FROM mcaptcha_session_revocations WHERE token = $1
+            RETURNING
+                (SELECT name FROM mcaptcha_users
+                    WHERE ID = mcaptcha_session_revocations.user_id) as "username!",
+                created_at"#,
+            token,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::SessionRevocationNotFound))?;
+        Ok(r.into())
+    }
+
+    /// Grant or revoke the instance administrator role of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_admin(&self, username: &str, is_admin: bool) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_users SET is_admin = $2 WHERE name = $1",
+            username,
+            is_admin,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        if res.rows_affected() == 0 && !self.username_exists(username).await? {
+            return Err(DBError::AccountNotFound);
+        }
+        Ok(())
+    }
+
+    /// Check if a user is an instance administrator
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_admin(&self, username: &str) -> DBResult<bool> {
+        struct AdminResp {
+            is_admin: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            AdminResp,
+            "SELECT is_admin FROM mcaptcha_users WHERE name = $1",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(resp.is_admin)
+    }
+
+    /// Get a page of users of the instance, ordered by username
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_users(&self, page: usize, limit: usize) -> DBResult<Vec<InstanceUser>> {
+        let offset = limit * page;
+        let users = sqlx::query_as!(
+            InnerInstanceUser,
+            "SELECT mcaptcha_users.name, mcaptcha_users.email, mcaptcha_users.is_admin,
+                mcaptcha_users.residency, mcaptcha_users.suspended,
+                COUNT(mcaptcha_config.config_id) AS captchas
+            FROM mcaptcha_users
+            LEFT JOIN mcaptcha_config ON mcaptcha_config.user_id = mcaptcha_users.ID
+            GROUP BY mcaptcha_users.ID, mcaptcha_users.name, mcaptcha_users.email,
+                mcaptcha_users.is_admin, mcaptcha_users.residency,
+                mcaptcha_users.suspended
+            ORDER BY mcaptcha_users.name ASC LIMIT $1 OFFSET $2",
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(users.into_iter().map(|u| u.into()).collect())
+    }
+
+    /// Get instance-wide counts of users and captchas
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_instance_stats(&self) -> DBResult<InstanceStats> {
+        struct Count {
+            users: Option<i64>,
+            captchas: Option<i64>,
+        }
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT
+                (SELECT COUNT(*) FROM mcaptcha_users) AS users,
+                (SELECT COUNT(*) FROM mcaptcha_config) AS captchas",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(InstanceStats {
+            users: count.users.unwrap_or_default() as u32,
+            captchas: count.captchas.unwrap_or_default() as u32,
+        })
+    }
+
+    /// Set or clear the public key to which notifications of a user can be
+    /// encrypted
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_notification_key(
+        &self,
+        username: &str,
+        key: Option<&str>,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_users SET notification_key = $2 WHERE name = $1",
+            username,
+            key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        if res.rows_affected() == 0 && !self.username_exists(username).await? {
+            return Err(DBError::AccountNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get the public key to which notifications of a user can be encrypted
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_notification_key(&self, username: &str) -> DBResult<Option<String>> {
+        struct KeyResp {
+            notification_key: Option<String>,
+        }
+
+        let resp = sqlx::query_as!(
+            KeyResp,
+            "SELECT notification_key FROM mcaptcha_users WHERE name = $1",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(resp.notification_key)
+    }
+
+    /// Create notification whose message is encrypted to the receiver's
+    /// notification key
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn create_encrypted_notification(&self, p: &AddNotification) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_notifications (
+              heading, message, tx, rx, received, encrypted)
+              VALUES  (
+              $1, $2,
+                  (SELECT ID FROM mcaptcha_users WHERE name = $3),
+                  (SELECT ID FROM mcaptcha_users WHERE name = $4),
+                  $5, true
+                      );",
+            p.heading,
+            p.message,
+            p.from,
+            p.to,
+            now
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+
+        Ok(())
+    }
+
+    /// Create organization. Its creator becomes its owner
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn create_org(&self, username: &str, name: &str) -> DBResult<()> {
+        sqlx::query!("INSERT INTO mcaptcha_orgs (name) VALUES ($1)", name)
+            .execute(&self.pool)
+            .await
+            .map_err(map_register_err)?;
+        self.set_org_member(name, username, OrgRole::Owner).await
+    }
+
+    /// Check if an organization exists
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn org_exists(&self, name: &str) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "SELECT EXISTS (SELECT 1 from mcaptcha_orgs WHERE name = $1)",
+            name,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(res.exists.unwrap_or(false))
+    }
+
+    /// Delete organization. Its captchas are kept by the members holding them
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_org(&self, name: &str) -> DBResult<()> {
+        let res = sqlx::query!("DELETE FROM mcaptcha_orgs WHERE name = $1", name)
+            .execute(&self.pool)
+            .await
+            .map_err(map_register_err)?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::OrgNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get all organizations of which a user is a member, ordered by name
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_user_orgs(&self, username: &str) -> DBResult<Vec<OrgMembership>> {
+        let orgs = sqlx::query_as!(
+            InnerOrgMember,
+            "SELECT mcaptcha_orgs.name, mcaptcha_org_members.role
+            FROM mcaptcha_org_members
+            INNER JOIN mcaptcha_orgs ON mcaptcha_orgs.ID = mcaptcha_org_members.org_id
+            WHERE mcaptcha_org_members.user_id = (
+                SELECT ID FROM mcaptcha_users WHERE name = $1)
+            ORDER BY mcaptcha_orgs.name ASC",
+            username,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(orgs.into_iter().map(|o| o.into()).collect())
+    }
+
+    /// Add member to organization, or change the role of a member
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_org_member(
+        &self,
+        org: &str,
+        username: &str,
+        role: OrgRole,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_org_members (org_id, user_id, role)
+            VALUES (
+                (SELECT ID FROM mcaptcha_orgs WHERE name = $1),
+                (SELECT ID FROM mcaptcha_users WHERE name = $2),
+                $3
+            )
+            ON CONFLICT (org_id, user_id) DO UPDATE SET role = EXCLUDED.role",
+            org,
+            username,
+            role.as_str(),
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Get role of a member of an organization
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_org_role(&self, org: &str, username: &str) -> DBResult<OrgRole> {
+        let res = sqlx::query!(
+            "SELECT role FROM mcaptcha_org_members
+            WHERE org_id = (SELECT ID FROM mcaptcha_orgs WHERE name = $1)
+            AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            org,
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::OrgMemberNotFound))?;
+        Ok(OrgRole::parse(&res.role).unwrap_or_default())
+    }
+
+    /// Get all members of an organization, ordered by username
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_org_members(&self, org: &str) -> DBResult<Vec<OrgMember>> {
+        let members = sqlx::query_as!(
+            InnerOrgMember,
+            "SELECT mcaptcha_users.name, mcaptcha_org_members.role
+            FROM mcaptcha_org_members
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_org_members.user_id
+            WHERE mcaptcha_org_members.org_id = (
+                SELECT ID FROM mcaptcha_orgs WHERE name = $1)
+            ORDER BY mcaptcha_users.name ASC",
+            org,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::OrgNotFound))?;
+        Ok(members.into_iter().map(|m| m.into()).collect())
+    }
+
+    /// Remove member from organization
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn remove_org_member(&self, org: &str, username: &str) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_org_members
+            WHERE org_id = (SELECT ID FROM mcaptcha_orgs WHERE name = $1)
+            AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            org,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::OrgMemberNotFound);
+        }
+        Ok(())
+    }
+
+    /// Move captcha into an organization, or out of it when `org` is None.
+    /// `username` becomes the member holding the captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_captcha_org(
+        &self,
+        username: &str,
+        key: &str,
+        org: Option<&str>,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_config SET
+                user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1),
+                org_id = (SELECT ID FROM mcaptcha_orgs WHERE name = $3)
+            WHERE key = $2",
+            username,
+            key,
+            org,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        if res.rows_affected() == 0 && !self.captcha_exists(None, key).await? {
+            return Err(DBError::CaptchaNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get organization that owns a captcha, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_captcha_org(&self, key: &str) -> DBResult<Option<String>> {
+        let res = sqlx::query!(
+            r#"SELECT mcaptcha_orgs.name as "name?" FROM mcaptcha_config
+            LEFT JOIN mcaptcha_orgs ON mcaptcha_orgs.ID = mcaptcha_config.org_id
+            WHERE mcaptcha_config.key = $1"#,
+            key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(res.name)
+    }
+
+    /// Get all captchas owned by an organization
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_org_captchas(&self, org: &str) -> DBResult<Vec<Captcha>> {
+        let captchas = sqlx::query_as!(
+            InternaleCaptchaConfig,
+            "SELECT key, name, config_id, duration FROM mcaptcha_config WHERE
+            org_id = (SELECT ID FROM mcaptcha_orgs WHERE name = $1)
+            ORDER BY name ASC",
+            org,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::OrgNotFound))?;
+        Ok(captchas.into_iter().map(|c| c.into()).collect())
+    }
+
+    /// Hand captchas of an organization that are held by member `from` over to
+    /// member `to`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn reassign_org_captchas(
+        &self,
+        org: &str,
+        from: &str,
+        to: &str,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_config SET
+                user_id = (SELECT ID FROM mcaptcha_users WHERE name = $3)
+            WHERE org_id = (SELECT ID FROM mcaptcha_orgs WHERE name = $1)
+            AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            org,
+            from,
+            to,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Schedule a traffic burst for a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_burst(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        burst: &CreateBurst,
+    ) -> DBResult<()> {
+        let starts = timestamp_to_date_time(burst.starts)?;
+        let ends = timestamp_to_date_time(burst.ends)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_bursts
+                (config_id, starts_at, ends_at, difficulty_factor)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                $3, $4, $5)",
+            captcha_key,
+            username,
+            &starts,
+            &ends,
+            burst.difficulty_factor as i32,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get traffic bursts of a captcha, ordered by start time
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_bursts(&self, captcha_key: &str) -> DBResult<Vec<Burst>> {
+        let bursts = sqlx::query_as!(
+            InnerBurst,
+            "SELECT mcaptcha_bursts.ID, mcaptcha_config.key,
+                mcaptcha_bursts.starts_at, mcaptcha_bursts.ends_at,
+                mcaptcha_bursts.difficulty_factor
+            FROM mcaptcha_bursts
+            INNER JOIN mcaptcha_config
+                ON mcaptcha_config.config_id = mcaptcha_bursts.config_id
+            WHERE mcaptcha_config.key = $1
+            ORDER BY mcaptcha_bursts.starts_at ASC",
+            captcha_key,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(bursts.into_iter().map(|b| b.into()).collect())
+    }
+
+    /// Delete a traffic burst of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_burst(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        id: i32,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_bursts
+            WHERE ID = $1
+            AND config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = $2
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $3)
+            )",
+            id,
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::BurstNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::BurstNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get traffic bursts of all captchas that overlap the window between
+    /// unix timestamps `from` and `until`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_scheduled_bursts(&self, from: i64, until: i64) -> DBResult<Vec<Burst>> {
+        let from = timestamp_to_date_time(from)?;
+        let until = timestamp_to_date_time(until)?;
+        let bursts = sqlx::query_as!(
+            InnerBurst,
+            "SELECT mcaptcha_bursts.ID, mcaptcha_config.key,
+                mcaptcha_bursts.starts_at, mcaptcha_bursts.ends_at,
+                mcaptcha_bursts.difficulty_factor
+            FROM mcaptcha_bursts
+            INNER JOIN mcaptcha_config
+                ON mcaptcha_config.config_id = mcaptcha_bursts.config_id
+            WHERE mcaptcha_bursts.ends_at > $1
+            AND mcaptcha_bursts.starts_at <= $2
+            ORDER BY mcaptcha_bursts.starts_at ASC",
+            &from,
+            &until,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::BurstNotFound))?;
+        Ok(bursts.into_iter().map(|b| b.into()).collect())
+    }
+
+    /// Delete traffic bursts that ended before unix timestamp `before`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_ended_bursts(&self, before: i64) -> DBResult<()> {
+        let before = timestamp_to_date_time(before)?;
+        sqlx::query!("DELETE FROM mcaptcha_bursts WHERE ends_at <= $1", &before)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| map_row_not_found_err(e, DBError::BurstNotFound))?;
+        Ok(())
+    }
+
+    /// Create an invitation issued by a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn create_invite(
+        &self,
+        username: &str,
+        invite: &CreateInvite,
+    ) -> DBResult<()> {
+        let expires_at = timestamp_to_date_time(invite.expires_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_invites
+                (token, email, invited_by, org_id, role, created_at, expires_at)
+            VALUES (
+                $1, $2,
+                (SELECT ID FROM mcaptcha_users WHERE name = $3),
+                (SELECT ID FROM mcaptcha_orgs WHERE name = $4),
+                $5, $6, $7)",
+            invite.token,
+            invite.email,
+            username,
+            invite.org,
+            invite.role.map(|r| r.as_str()),
+            &now_unix_time_stamp(),
+            &expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get an invitation by its token
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_invite(&self, token: &str) -> DBResult<Invite> {
+        let invite = sqlx::query_as!(
+            InnerInvite,
+            r#"SELECT mcaptcha_invites.ID as id, mcaptcha_invites.email,
+                mcaptcha_orgs.name as "org?", mcaptcha_invites.role,
+                mcaptcha_users.name as invited_by, mcaptcha_invites.created_at,
+                mcaptcha_invites.expires_at, mcaptcha_invites.accepted_at
+            FROM mcaptcha_invites
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_invites.invited_by
+            LEFT JOIN mcaptcha_orgs ON mcaptcha_orgs.ID = mcaptcha_invites.org_id
+            WHERE mcaptcha_invites.token = $1"#,
+            token,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::InviteNotFound))?;
+        Ok(invite.into())
+    }
+
+    /// Get invitations issued by a user, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_user_invites(&self, username: &str) -> DBResult<Vec<Invite>> {
+        let invites = sqlx::query_as!(
+            InnerInvite,
+            r#"SELECT mcaptcha_invites.ID as id, mcaptcha_invites.email,
+                mcaptcha_orgs.name as "org?", mcaptcha_invites.role,
+                mcaptcha_users.name as invited_by, mcaptcha_invites.created_at,
+                mcaptcha_invites.expires_at, mcaptcha_invites.accepted_at
+            FROM mcaptcha_invites
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_invites.invited_by
+            LEFT JOIN mcaptcha_orgs ON mcaptcha_orgs.ID = mcaptcha_invites.org_id
+            WHERE mcaptcha_users.name = $1
+            ORDER BY mcaptcha_invites.ID DESC"#,
+            username,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(invites.into_iter().map(|i| i.into()).collect())
+    }
+
+    /// Delete an invitation issued by a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_invite(&self, username: &str, id: i32) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_invites
+            WHERE ID = $1
+            AND invited_by = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            id,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::InviteNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::InviteNotFound);
+        }
+        Ok(())
+    }
+
+    /// Mark an invitation accepted at unix timestamp `now`. Fails when the
+    /// invitation was already accepted or has expired
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn accept_invite(&self, token: &str, now: i64) -> DBResult<()> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_invites SET accepted_at = $1
+            WHERE token = $2 AND accepted_at IS NULL AND expires_at > $1",
+            &now,
+            token,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::InviteNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::InviteNotFound);
+        }
+        Ok(())
+    }
+
+    /// Mark an accepted invitation pending again
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn reopen_invite(&self, token: &str) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_invites SET accepted_at = NULL WHERE token = $1",
+            token,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::InviteNotFound))?;
+        Ok(())
+    }
+
+    /// Set quota overrides of a user; replaces existing overrides, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_user_quota(&self, username: &str, quota: &UserQuota) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_user_quotas (user_id, max_captchas, max_analytics_records)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                max_captchas = EXCLUDED.max_captchas,
+                max_analytics_records = EXCLUDED.max_analytics_records",
+            username,
+            quota.max_captchas.map(|q| q as i32),
+            quota.max_analytics_records.map(|q| q as i32),
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get quota overrides of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_user_quota(&self, username: &str) -> DBResult<UserQuota> {
+        struct InnerUserQuota {
+            max_captchas: Option<i32>,
+            max_analytics_records: Option<i32>,
+        }
+
+        let quota = sqlx::query_as!(
+            InnerUserQuota,
+            r#"SELECT
+                mcaptcha_user_quotas.max_captchas as "max_captchas?",
+                mcaptcha_user_quotas.max_analytics_records as "max_analytics_records?"
+            FROM mcaptcha_users
+            LEFT JOIN mcaptcha_user_quotas
+                ON mcaptcha_user_quotas.user_id = mcaptcha_users.ID
+            WHERE mcaptcha_users.name = $1"#,
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(UserQuota {
+            max_captchas: quota.max_captchas.map(|q| q as u32),
+            max_analytics_records: quota.max_analytics_records.map(|q| q as u32),
+        })
+    }
+
+    /// Set or clear the data residency region of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_residency(&self, username: &str, region: Option<&str>) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_users SET residency = $2 WHERE name = $1",
+            username,
+            region,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        if res.rows_affected() == 0 && !self.username_exists(username).await? {
+            return Err(DBError::AccountNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get the data residency region of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_residency(&self, username: &str) -> DBResult<Option<String>> {
+        struct Residency {
+            residency: Option<String>,
+        }
+
+        let resp = sqlx::query_as!(
+            Residency,
+            "SELECT residency FROM mcaptcha_users WHERE name = $1",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(resp.residency)
+    }
+
+    /// Mark the registration of a user pending or approved
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_pending_approval(&self, username: &str, pending: bool) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_users SET pending_approval = $2 WHERE name = $1",
+            username,
+            pending,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        if res.rows_affected() == 0 && !self.username_exists(username).await? {
+            return Err(DBError::AccountNotFound);
+        }
+        Ok(())
+    }
+
+    /// Check if the registration of a user is pending approval
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_pending_approval(&self, username: &str) -> DBResult<bool> {
+        struct PendingResp {
+            pending_approval: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            PendingResp,
+            "SELECT pending_approval FROM mcaptcha_users WHERE name = $1",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(resp.pending_approval)
+    }
+
+    /// Get registrations that are pending approval, in pages of `limit`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_pending_registrations(
+        &self,
+        page: usize,
+        limit: usize,
+    ) -> DBResult<Vec<PendingRegistration>> {
+        let offset = limit * page;
+        let registrations = sqlx::query_as!(
+            PendingRegistration,
+            "SELECT name, email FROM mcaptcha_users
+            WHERE pending_approval = true
+            ORDER BY name ASC LIMIT $1 OFFSET $2",
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(registrations)
+    }
+
+    /// Set or clear the caller-supplied external ID of a captcha. External IDs
+    /// are unique per user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_captcha_external_id(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        external_id: Option<&str>,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_config SET external_id = $1
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            AND key = $3",
+            external_id,
+            username,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        if res.rows_affected() == 0
+            && !self.captcha_exists(Some(username), captcha_key).await?
+        {
+            return Err(DBError::CaptchaNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get captcha of a user by its external ID
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_captcha_by_external_id(
+        &self,
+        username: &str,
+        external_id: &str,
+    ) -> DBResult<Captcha> {
+        let captcha = sqlx::query_as!(
+            InternaleCaptchaConfig,
+            "SELECT config_id, duration, name, key from mcaptcha_config WHERE
+                        external_id = $1 AND
+                        user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2) ",
+            &external_id,
+            &username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(captcha.into())
+    }
+
+    /// Suspend or unsuspend a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_suspended(&self, username: &str, suspended: bool) -> DBResult<()> {
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_users SET suspended = $2 WHERE name = $1",
+            username,
+            suspended,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        if res.rows_affected() == 0 && !self.username_exists(username).await? {
+            return Err(DBError::AccountNotFound);
+        }
+        Ok(())
+    }
+
+    /// Check if a user is suspended
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_suspended(&self, username: &str) -> DBResult<bool> {
+        struct SuspendedResp {
+            suspended: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            SuspendedResp,
+            "SELECT suspended FROM mcaptcha_users WHERE name = $1",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(resp.suspended)
+    }
+
+    /// Check if the owner of a captcha is suspended
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_captcha_owner_suspended(&self, captcha_key: &str) -> DBResult<bool> {
+        struct SuspendedResp {
+            suspended: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            SuspendedResp,
+            "SELECT suspended FROM mcaptcha_users WHERE ID = (
+                SELECT user_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(resp.suspended)
+    }
+
+    /// Ban an email domain from registration. Banning a banned domain is a
+    /// no-op
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn ban_email_domain(&self, domain: &str) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_banned_email_domains (domain) VALUES ($1)
+            ON CONFLICT (domain) DO NOTHING",
+            domain,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Lift the ban on an email domain
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn unban_email_domain(&self, domain: &str) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_banned_email_domains WHERE domain = $1",
+            domain,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::BannedDomainNotFound);
+        }
+        Ok(())
+    }
+
+    /// Check if an email domain is banned
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_email_domain_banned(&self, domain: &str) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "SELECT EXISTS (SELECT 1 from mcaptcha_banned_email_domains WHERE domain = $1)",
+            domain,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+
+        Ok(res.exists.unwrap_or(false))
+    }
+
+    /// Get banned email domains, sorted by name
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_banned_email_domains(&self) -> DBResult<Vec<String>> {
+        struct DomainResp {
+            domain: String,
+        }
+
+        let domains = sqlx::query_as!(
+            DomainResp,
+            "SELECT domain FROM mcaptcha_banned_email_domains ORDER BY domain ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(domains.into_iter().map(|d| d.domain).collect())
+    }
+
+    /// Set difficulty decay of a captcha, enabling it; replaces existing decay,
+    /// if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_difficulty_decay(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        decay: &DifficultyDecay,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_difficulty_decay
+                (config_id, sustain, step, steps, curve)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                $3, $4, $5, $6)
+            ON CONFLICT (config_id) DO UPDATE SET
+                sustain = EXCLUDED.sustain,
+                step = EXCLUDED.step,
+                steps = EXCLUDED.steps,
+                curve = EXCLUDED.curve",
+            captcha_key,
+            username,
+            decay.sustain as i32,
+            decay.step as i32,
+            decay.steps as i32,
+            decay.curve.as_str(),
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get difficulty decay of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_difficulty_decay(
+        &self,
+        captcha_key: &str,
+    ) -> DBResult<DifficultyDecay> {
+        let decay = sqlx::query_as!(
+            InnerDifficultyDecay,
+            "SELECT sustain, step, steps, curve
+            FROM mcaptcha_difficulty_decay
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::DifficultyDecayNotFound))?;
+        Ok(decay.into())
+    }
+
+    /// Delete difficulty decay of a captcha, disabling it
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_difficulty_decay(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_difficulty_decay
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            )",
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::DifficultyDecayNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::DifficultyDecayNotFound);
+        }
+        Ok(())
+    }
+
+    /// Record a change of the difficulty of a captcha in its escalation log
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_escalation_log_entry(
+        &self,
+        captcha_key: &str,
+        entry: &EscalationLogEntry,
+    ) -> DBResult<()> {
+        let time = timestamp_to_date_time(entry.time)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_escalation_log
+            (config_id, kind, difficulty_factor, time)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1),
+                $2, $3, $4)",
+            captcha_key,
+            entry.kind.as_str(),
+            entry.difficulty_factor as i32,
+            &time,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get the escalation log of a captcha, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_escalation_log(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        limit: u32,
+    ) -> DBResult<Vec<EscalationLogEntry>> {
+        let records = sqlx::query_as!(
+            InnerEscalationLogEntry,
+            "SELECT kind, difficulty_factor, time FROM mcaptcha_escalation_log
+            WHERE
+                config_id = (
+                    SELECT config_id FROM mcaptcha_config
+                WHERE
+                    key = $1
+                AND
+                     user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+                ORDER BY time DESC
+                LIMIT $3",
+            captcha_key,
+            username,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(records.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Replace domains that a captcha can be embedded on
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_allowed_domains(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        domains: &[String],
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "DELETE FROM mcaptcha_allowed_domains
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            )",
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+
+        for domain in domains.iter() {
+            sqlx::query!(
+                "INSERT INTO mcaptcha_allowed_domains (config_id, domain)
+                VALUES (
+                    (SELECT config_id FROM mcaptcha_config WHERE key = $1
+                    AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                    $3)",
+                captcha_key,
+                username,
+                domain,
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        }
+        Ok(())
+    }
+
+    /// Get domains that a captcha can be embedded on, sorted by name
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_allowed_domains(&self, captcha_key: &str) -> DBResult<Vec<String>> {
+        struct DomainResp {
+            domain: String,
+        }
+
+        let domains = sqlx::query_as!(
+            DomainResp,
+            "SELECT domain FROM mcaptcha_allowed_domains
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)
+            ORDER BY domain ASC",
+            captcha_key,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(domains.into_iter().map(|d| d.domain).collect())
+    }
+
+    /// Set unix timestamp after which a captcha needs allowed domains to serve
+    /// challenges
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_allowed_domains_deadline(
+        &self,
+        captcha_key: &str,
+        deadline: i64,
+    ) -> DBResult<()> {
+        let deadline = timestamp_to_date_time(deadline)?;
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_config SET domains_deadline = $1 WHERE key = $2",
+            &deadline,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        if res.rows_affected() == 0 && !self.captcha_exists(None, captcha_key).await? {
+            return Err(DBError::CaptchaNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get unix timestamp after which a captcha needs allowed domains to serve
+    /// challenges, if it was set
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_allowed_domains_deadline(
+        &self,
+        captcha_key: &str,
+    ) -> DBResult<Option<i64>> {
+        let res = sqlx::query!(
+            "SELECT domains_deadline FROM mcaptcha_config WHERE key = $1",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(res.domains_deadline.map(|d| d.unix_timestamp()))
+    }
+
+    /// Get captchas that have neither allowed domains nor a deadline to set them
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_captchas_without_allowed_domains(
+        &self,
+    ) -> DBResult<Vec<UnrestrictedCaptcha>> {
+        let captchas = sqlx::query_as!(
+            UnrestrictedCaptcha,
+            "SELECT
+                mcaptcha_users.name as owner,
+                mcaptcha_config.key as key,
+                mcaptcha_config.name as description
+            FROM mcaptcha_config
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_config.user_id
+            WHERE mcaptcha_config.domains_deadline IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM mcaptcha_allowed_domains
+                WHERE mcaptcha_allowed_domains.config_id = mcaptcha_config.config_id
+            )",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(captchas)
+    }
+
+    /// Record that a captcha was created from the demo account at unix
+    /// timestamp `created_at`, in demo session `session`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_demo_sitekey(
+        &self,
+        captcha_key: &str,
+        session: Option<&str>,
+        created_at: i64,
+    ) -> DBResult<()> {
+        let created_at = timestamp_to_date_time(created_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_demo_sitekeys (config_id, session_id, created_at)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1),
+                $2, $3)",
+            captcha_key,
+            session,
+            &created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Create captcha of the demo account in a demo session, within limits
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn create_demo_captcha(
+        &self,
+        username: &str,
+        p: &CreateCaptcha,
+        session: Option<&str>,
+        created_at: i64,
+        limit: usize,
+        session_limit: usize,
+    ) -> DBResult<()> {
+        struct User {
+            id: i32,
+        }
+
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let created_at = timestamp_to_date_time(created_at)?;
+        let mut tx = self.pool.begin().await.map_err(map_register_err)?;
+
+        // locking the account row serializes concurrent demo visitors, so that
+        // they can't go over the limits together
+        let user = sqlx::query_as!(
+            User,
+            "SELECT ID AS id FROM mcaptcha_users WHERE name = $1 FOR UPDATE",
+            username,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) AS count FROM mcaptcha_config WHERE user_id = $1",
+            user.id,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(map_register_err)?;
+        if count.count.unwrap_or_default() as usize >= limit {
+            return Err(DBError::SitekeyLimitReached);
+        }
+
+        if let (true, Some(session)) = (session_limit > 0, session) {
+            let count = sqlx::query_as!(
+                Count,
+                "SELECT COUNT(*) AS count FROM mcaptcha_demo_sitekeys WHERE session_id = $1",
+                session,
+            )
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(map_register_err)?;
+            if count.count.unwrap_or_default() as usize >= session_limit {
+                return Err(DBError::SessionSitekeyLimitReached);
+            }
+        }
+
+        sqlx::query!(
+            "INSERT INTO mcaptcha_config
+        (key, user_id, duration, name)
+        VALUES ($1, $2, $3, $4)",
+            p.key,
+            user.id,
+            p.duration as i32,
+            p.description,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(map_register_err)?;
+
+        sqlx::query!(
+            "INSERT INTO mcaptcha_demo_sitekeys (config_id, session_id, created_at)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1),
+                $2, $3)",
+            p.key,
+            session,
+            &created_at,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(map_register_err)?;
+
+        tx.commit().await.map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Count captchas that were created in a demo session
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_demo_sitekeys(&self, session: &str) -> DBResult<usize> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) AS count FROM mcaptcha_demo_sitekeys WHERE session_id = $1",
+            session,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(count.count.unwrap_or_default() as usize)
+    }
+
+    /// Check if a captcha was created from the demo account
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn is_demo_sitekey(&self, captcha_key: &str) -> DBResult<bool> {
+        let res = sqlx::query!(
+            "SELECT EXISTS (
+                SELECT 1 FROM mcaptcha_demo_sitekeys
+                WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)
+            )",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(res.exists.unwrap_or_default())
+    }
+
+    /// Get keys of captchas that were created from the demo account before unix
+    /// timestamp `created_before`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_expired_demo_sitekeys(
+        &self,
+        created_before: i64,
+    ) -> DBResult<Vec<String>> {
+        struct Key {
+            key: String,
+        }
+
+        let created_before = timestamp_to_date_time(created_before)?;
+        let keys = sqlx::query_as!(
+            Key,
+            "SELECT mcaptcha_config.key FROM mcaptcha_demo_sitekeys
+            INNER JOIN mcaptcha_config
+                ON mcaptcha_config.config_id = mcaptcha_demo_sitekeys.config_id
+            WHERE mcaptcha_demo_sitekeys.created_at < $1",
+            &created_before,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(keys.into_iter().map(|k| k.key).collect())
+    }
+
+    /// Record that user `username` is a trial account that expires at unix
+    /// timestamp `expires_at`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_trial(&self, username: &str, expires_at: i64) -> DBResult<()> {
+        let expires_at = timestamp_to_date_time(expires_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_trials (user_id, expires_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2)",
+            username,
+            &expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Count trial accounts, including expired ones that weren't deleted yet
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_trials(&self) -> DBResult<usize> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let count =
+            sqlx::query_as!(Count, "SELECT COUNT(*) AS count FROM mcaptcha_trials")
+                .fetch_one(&self.pool)
+                .await
+                .map_err(map_register_err)?;
+        Ok(count.count.unwrap_or_default() as usize)
+    }
+
+    /// Get usernames of trial accounts that expired at or before unix
+    /// timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_expired_trials(&self, now: i64) -> DBResult<Vec<String>> {
+        struct Name {
+            name: String,
+        }
+
+        let now = timestamp_to_date_time(now)?;
+        let names = sqlx::query_as!(
+            Name,
+            "SELECT mcaptcha_users.name FROM mcaptcha_trials
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_trials.user_id
+            WHERE mcaptcha_trials.expires_at <= $1",
+            &now,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(names.into_iter().map(|n| n.name).collect())
+    }
+
+    /// Get notifications of a user that match `filter`, newest first, in
+    /// pages of `limit`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_notifications(
+        &self,
+        username: &str,
+        filter: &NotificationFilter,
+        page: usize,
+        limit: usize,
+    ) -> DBResult<Vec<Notification>> {
+        let offset = limit * page;
+        let read = filter.status.read();
+        let after = filter.after.map(timestamp_to_date_time).transpose()?;
+        let before = filter.before.map(timestamp_to_date_time).transpose()?;
+        let notifications = sqlx::query_as!(
+            InnerNotification,
+            "SELECT mcaptcha_notifications.id, mcaptcha_notifications.heading,
+                mcaptcha_notifications.message, mcaptcha_notifications.received,
+                mcaptcha_notifications.encrypted, mcaptcha_users.name
+            FROM mcaptcha_notifications
+            INNER JOIN mcaptcha_users ON mcaptcha_notifications.tx = mcaptcha_users.id
+            WHERE mcaptcha_notifications.rx = (SELECT id FROM mcaptcha_users WHERE name = $1)
+            AND ($2::BOOLEAN IS NULL OR COALESCE(mcaptcha_notifications.read, false) = $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR mcaptcha_notifications.received >= $3)
+            AND ($4::TIMESTAMPTZ IS NULL OR mcaptcha_notifications.received < $4)
+            ORDER BY mcaptcha_notifications.received DESC, mcaptcha_notifications.id DESC
+            LIMIT $5 OFFSET $6",
+            username,
+            read,
+            after,
+            before,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(notifications.into_iter().map(|n| n.into()).collect())
+    }
+
+    /// Add API token of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_api_token(&self, p: &AddApiToken) -> DBResult<()> {
+        let created_at = timestamp_to_date_time(p.created_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_api_tokens (user_id, name, token_hash, scope, created_at)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3, $4, $5)",
+            p.username,
+            p.name,
+            p.token_hash,
+            p.scope,
+            &created_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(map_register_err)?;
+        Ok(())
+    }
+
+    /// Get API tokens of a user, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_api_tokens(&self, username: &str) -> DBResult<Vec<ApiToken>> {
+        struct InnerApiToken {
+            id: i32,
+            name: String,
+            scope: String,
+            created_at: OffsetDateTime,
+        }
+
+        let tokens = sqlx::query_as!(
+            InnerApiToken,
+            "SELECT mcaptcha_api_tokens.id, mcaptcha_api_tokens.name,
+                mcaptcha_api_tokens.scope, mcaptcha_api_tokens.created_at
+            FROM mcaptcha_api_tokens
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_api_tokens.user_id
+            WHERE mcaptcha_users.name = $1
+            ORDER BY mcaptcha_api_tokens.id DESC",
+            username,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(tokens
+            .into_iter()
+            .map(|t| ApiToken {
+                id: t.id,
+                name: t.name,
+                scope: t.scope,
+                created_at: t.created_at.unix_timestamp(),
+            })
+            .collect())
+    }
+
+    /// Delete API token of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_api_token(&self, username: &str, id: i32) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_api_tokens
+            WHERE id = $1
+            AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            id,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::ApiTokenNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::ApiTokenNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get the owner and scope of the API token whose hash is `token_hash`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_api_token_owner(&self, token_hash: &str) -> DBResult<ApiTokenOwner> {
+        let owner = sqlx::query_as!(
+            ApiTokenOwner,
+            "SELECT mcaptcha_users.name AS username, mcaptcha_api_tokens.scope
+            FROM mcaptcha_api_tokens
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_api_tokens.user_id
+            WHERE mcaptcha_api_tokens.token_hash = $1",
+            token_hash,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::ApiTokenNotFound))?;
+        Ok(owner)
+    }
+
+    /// Mark all notifications of a user read
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn mark_all_notifications_read(&self, username: &str) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_notifications SET read = TRUE
+            WHERE rx = (SELECT ID FROM mcaptcha_users WHERE name = $1)
+            AND read IS NULL",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Delete a notification of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_notification(&self, username: &str, id: i32) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_notifications
+            WHERE id = $1
+            AND rx = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            id,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::NotificationNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::NotificationNotFound);
+        }
+        Ok(())
+    }
+
+    /// Set webhook that receives notifications of a user; replaces existing
+    /// webhook, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_notification_webhook(
+        &self,
+        username: &str,
+        webhook: &NotificationWebhook,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_notification_webhooks (user_id, url, secret)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET
+                url = EXCLUDED.url,
+                secret = EXCLUDED.secret",
+            username,
+            &webhook.url,
+            &webhook.secret,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get webhook that receives notifications of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_notification_webhook(
+        &self,
+        username: &str,
+    ) -> DBResult<NotificationWebhook> {
+        let webhook = sqlx::query_as!(
+            NotificationWebhook,
+            "SELECT url, secret FROM mcaptcha_notification_webhooks
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::WebhookNotFound))?;
+        Ok(webhook)
+    }
+
+    /// Delete webhook that receives notifications of a user
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_notification_webhook(&self, username: &str) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_notification_webhooks
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::WebhookNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::WebhookNotFound);
+        }
+        Ok(())
+    }
+
+    /// Log notification webhook delivery attempt
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn record_notification_webhook_delivery(
+        &self,
+        username: &str,
+        delivery: &CreateWebhookDelivery,
+    ) -> DBResult<()> {
+        let now = now_unix_time_stamp();
+        sqlx::query!(
+            "INSERT INTO mcaptcha_notification_webhook_deliveries
+            (user_id, event, status, attempt, success, time)
+            VALUES (
+                (SELECT ID FROM mcaptcha_users WHERE name = $1),
+                $2, $3, $4, $5, $6)",
+            username,
+            delivery.event,
+            delivery.status.map(|s| s as i32),
+            delivery.attempt as i32,
+            delivery.success,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Fetch notification webhook delivery logs of a user, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_notification_webhook_deliveries(
+        &self,
+        username: &str,
+        limit: u32,
+    ) -> DBResult<Vec<WebhookDelivery>> {
+        let records = sqlx::query_as!(
+            InnerWebhookDelivery,
+            "SELECT event, status, attempt, success, time
+            FROM mcaptcha_notification_webhook_deliveries
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)
+            ORDER BY time DESC
+            LIMIT $2",
+            username,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+
+        Ok(records.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Set thresholds of automatic notifications on traffic of a captcha,
+    /// enabling them; replaces existing thresholds, if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_alert_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        thresholds: &AlertThresholds,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_alert_thresholds
+                (config_id, levels, highest_level, failure_rate, min_attempts, anomaly_factor)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                $3, $4, $5, $6, $7)
+            ON CONFLICT (config_id) DO UPDATE SET
+                levels = EXCLUDED.levels,
+                highest_level = EXCLUDED.highest_level,
+                failure_rate = EXCLUDED.failure_rate,
+                min_attempts = EXCLUDED.min_attempts,
+                anomaly_factor = EXCLUDED.anomaly_factor",
+            captcha_key,
+            username,
+            thresholds.levels,
+            thresholds.highest_level,
+            thresholds.failure_rate as i32,
+            thresholds.min_attempts as i32,
+            thresholds.anomaly_factor as i32,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get thresholds of automatic notifications on traffic of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_alert_thresholds(
+        &self,
+        captcha_key: &str,
+    ) -> DBResult<AlertThresholds> {
+        let thresholds = sqlx::query_as!(
+            InnerAlertThresholds,
+            "SELECT levels, highest_level, failure_rate, min_attempts, anomaly_factor
+            FROM mcaptcha_alert_thresholds
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AlertThresholdsNotFound))?;
+        Ok(thresholds.into())
+    }
+
+    /// Delete thresholds of automatic notifications on traffic of a captcha,
+    /// disabling them
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_alert_thresholds(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_alert_thresholds
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            )",
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AlertThresholdsNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::AlertThresholdsNotFound);
+        }
+        Ok(())
+    }
+
+    /// Get all captchas whose alert thresholds enable anomaly detection
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_captchas_with_anomaly_alerts(&self) -> DBResult<Vec<OwnedCaptcha>> {
+        let captchas = sqlx::query_as!(
+            OwnedCaptcha,
+            "SELECT
+                mcaptcha_users.name as owner,
+                mcaptcha_config.key as key
+            FROM mcaptcha_alert_thresholds
+            INNER JOIN mcaptcha_config
+                ON mcaptcha_config.config_id = mcaptcha_alert_thresholds.config_id
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_config.user_id
+            WHERE mcaptcha_alert_thresholds.anomaly_factor > 0",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(captchas)
+    }
+
+    /// Set the theme of the widget of a captcha; replaces the existing theme,
+    /// if any
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_widget_theme(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        theme: &WidgetTheme,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "INSERT INTO mcaptcha_widget_themes
+                (config_id, mode, accent_color, background_color, text_color, border_radius, show_logo)
+            VALUES (
+                (SELECT config_id FROM mcaptcha_config WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)),
+                $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (config_id) DO UPDATE SET
+                mode = EXCLUDED.mode,
+                accent_color = EXCLUDED.accent_color,
+                background_color = EXCLUDED.background_color,
+                text_color = EXCLUDED.text_color,
+                border_radius = EXCLUDED.border_radius,
+                show_logo = EXCLUDED.show_logo",
+            captcha_key,
+            username,
+            theme.mode.as_str(),
+            &theme.accent_color,
+            theme.background_color.as_deref(),
+            theme.text_color.as_deref(),
+            theme.border_radius as i32,
+            theme.show_logo,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get the theme of the widget of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_widget_theme(&self, captcha_key: &str) -> DBResult<WidgetTheme> {
+        let theme = sqlx::query_as!(
+            InnerWidgetTheme,
+            "SELECT mode, accent_color, background_color, text_color, border_radius, show_logo
+            FROM mcaptcha_widget_themes
+            WHERE config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::WidgetThemeNotFound))?;
+        Ok(theme.into())
+    }
+
+    /// Delete the theme of the widget of a captcha, restoring the default
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_widget_theme(
+        &self,
+        username: &str,
+        captcha_key: &str,
+    ) -> DBResult<()> {
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_widget_themes
+            WHERE config_id = (
+                SELECT config_id FROM mcaptcha_config
+                WHERE key = $1
+                AND user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            )",
+            captcha_key,
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::WidgetThemeNotFound))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::WidgetThemeNotFound);
+        }
+        Ok(())
+    }
+
+    /// Set invisible mode of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn update_captcha_invisible_mode(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        mode: &InvisibleMode,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_config SET invisible = $1, invisible_threshold = $2
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $3)
+            AND key = $4",
+            mode.enabled,
+            mode.threshold as i32,
+            username,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(())
+    }
+
+    /// Get invisible mode of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn captcha_invisible_mode(
+        &self,
+        captcha_key: &str,
+    ) -> DBResult<InvisibleMode> {
+        struct InvisibleResp {
+            invisible: bool,
+            invisible_threshold: i32,
+        }
+
+        let resp = sqlx::query_as!(
+            InvisibleResp,
+            "SELECT invisible, invisible_threshold FROM mcaptcha_config
+            WHERE key = $1",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(InvisibleMode {
+            enabled: resp.invisible,
+            threshold: resp.invisible_threshold as u32,
+        })
+    }
+
+    /// Set the fallback challenge of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn update_captcha_fallback(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        fallback: &Fallback,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_config SET fallback = $1, fallback_wait = $2
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $3)
+            AND key = $4",
+            fallback.enabled,
+            fallback.wait as i32,
+            username,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(())
+    }
+
+    /// Get the fallback challenge of a captcha
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn captcha_fallback(&self, captcha_key: &str) -> DBResult<Fallback> {
+        struct FallbackResp {
+            fallback: bool,
+            fallback_wait: i32,
+        }
+
+        let resp = sqlx::query_as!(
+            FallbackResp,
+            "SELECT fallback, fallback_wait FROM mcaptcha_config
+            WHERE key = $1",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Fallback {
+            enabled: resp.fallback,
+            wait: resp.fallback_wait as u32,
+        })
+    }
+
+    /// Delete up to `limit` read notifications, of all users, that were
+    /// received before `before`. Returns the number of deleted notifications
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_read_notifications(&self, before: i64, limit: u32) -> DBResult<u64> {
+        let before = timestamp_to_date_time(before)?;
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_notifications
+            WHERE id IN (
+                SELECT id FROM mcaptcha_notifications
+                WHERE read = TRUE AND received < $1
+                ORDER BY id
+                LIMIT $2
+            )",
+            before,
+            limit as i64,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.rows_affected())
+    }
+
+    /// fetch PoWConfig fetches within a time range
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_config_fetched_range(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+        offset: usize,
+    ) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_pow_fetched_stats
+            WHERE
+                config_id = (
+                    SELECT
+                        config_id FROM mcaptcha_config
+                    WHERE
+                        key = $1
+                    AND
+                        user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+            AND time >= $3 AND time < $4
+            ORDER BY time ASC
+            LIMIT $5 OFFSET $6",
+            &key,
+            &user,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
+    /// fetch PoWConfig solves within a time range
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_solve_range(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+        offset: usize,
+    ) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_pow_solved_stats
+            WHERE
+                config_id = (
+                    SELECT
+                        config_id FROM mcaptcha_config
+                    WHERE
+                        key = $1
+                    AND
+                        user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+            AND time >= $3 AND time < $4
+            ORDER BY time ASC
+            LIMIT $5 OFFSET $6",
+            &key,
+            &user,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
+    /// fetch PoWConfig confirms within a time range
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_confirm_range(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+        offset: usize,
+    ) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_pow_confirmed_stats
+            WHERE
+                config_id = (
+                    SELECT
+                        config_id FROM mcaptcha_config
+                    WHERE
+                        key = $1
+                    AND
+                        user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+            AND time >= $3 AND time < $4
+            ORDER BY time ASC
+            LIMIT $5 OFFSET $6",
+            &key,
+            &user,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            limit as i64,
+            offset as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
+    /// fetch PoWConfig fetches within a time range, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_config_fetched_latest(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+    ) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_pow_fetched_stats
+            WHERE
+                config_id = (
+                    SELECT
+                        config_id FROM mcaptcha_config
+                    WHERE
+                        key = $1
+                    AND
+                        user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+            AND time >= $3 AND time < $4
+            ORDER BY time DESC
+            LIMIT $5",
+            &key,
+            &user,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
+    /// fetch PoWConfig solves within a time range, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_solve_latest(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+    ) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_pow_solved_stats
+            WHERE
+                config_id = (
+                    SELECT
+                        config_id FROM mcaptcha_config
+                    WHERE
+                        key = $1
+                    AND
+                        user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+            AND time >= $3 AND time < $4
+            ORDER BY time DESC
+            LIMIT $5",
+            &key,
+            &user,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
+    /// fetch PoWConfig confirms within a time range, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_confirm_latest(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        limit: usize,
+    ) -> DBResult<Vec<i64>> {
+        let records = sqlx::query_as!(
+            Date,
+            "SELECT time FROM mcaptcha_pow_confirmed_stats
+            WHERE
+                config_id = (
+                    SELECT
+                        config_id FROM mcaptcha_config
+                    WHERE
+                        key = $1
+                    AND
+                        user_id = (
+                        SELECT
+                            ID FROM mcaptcha_users WHERE name = $2))
+            AND time >= $3 AND time < $4
+            ORDER BY time DESC
+            LIMIT $5",
+            &key,
+            &user,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(Date::dates_to_unix(records))
+    }
+
+    /// count PoWConfig fetches, solves and confirms in time buckets
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_stats_series(
+        &self,
+        user: &str,
+        key: &str,
+        range: &TimeRange,
+        bucket: u32,
+    ) -> DBResult<Vec<StatsBucket>> {
+        struct InnerStatsBucket {
+            bucket: i64,
+            config_fetches: i64,
+            solves: i64,
+            confirms: i64,
+        }
+
+        let config_id = sqlx::query!(
+            "SELECT config_id FROM mcaptcha_config
+            WHERE
+                key = $1
+            AND
+                user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            &key,
+            &user,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?
+        .config_id;
+
+        let buckets = sqlx::query_as!(
+            InnerStatsBucket,
+            r#"SELECT
+                bucket AS "bucket!",
+                SUM(config_fetches)::BIGINT AS "config_fetches!",
+                SUM(solves)::BIGINT AS "solves!",
+                SUM(confirms)::BIGINT AS "confirms!"
+            FROM (
+                SELECT
+                    (FLOOR(EXTRACT(EPOCH FROM time) / $4::BIGINT) * $4::BIGINT)::BIGINT AS bucket,
+                    1 AS config_fetches, 0 AS solves, 0 AS confirms
+                FROM mcaptcha_pow_fetched_stats
+                WHERE config_id = $1 AND time >= $2 AND time < $3
+                UNION ALL
+                SELECT
+                    (FLOOR(EXTRACT(EPOCH FROM time) / $4::BIGINT) * $4::BIGINT)::BIGINT,
+                    0, 1, 0
+                FROM mcaptcha_pow_solved_stats
+                WHERE config_id = $1 AND time >= $2 AND time < $3
+                UNION ALL
+                SELECT
+                    (FLOOR(EXTRACT(EPOCH FROM time) / $4::BIGINT) * $4::BIGINT)::BIGINT,
+                    0, 0, 1
+                FROM mcaptcha_pow_confirmed_stats
+                WHERE config_id = $1 AND time >= $2 AND time < $3
+            ) AS events
+            GROUP BY bucket
+            ORDER BY bucket"#,
+            config_id,
+            timestamp_to_date_time(range.from)?,
+            timestamp_to_date_time(range.to)?,
+            bucket as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(buckets
+            .into_iter()
+            .map(|b| StatsBucket {
+                time: b.bucket,
+                config_fetches: b.config_fetches as u64,
+                solves: b.solves as u64,
+                confirms: b.confirms as u64,
+            })
+            .collect())
+    }
+
+    /// Set whether recommended levels are applied to a captcha automatically
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn update_captcha_auto_recommendation(
+        &self,
+        username: &str,
+        captcha_key: &str,
+        enabled: bool,
+    ) -> DBResult<()> {
+        sqlx::query!(
+            "UPDATE mcaptcha_config SET auto_recommendation = $1
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            AND key = $3",
+            enabled,
+            username,
+            captcha_key,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(())
+    }
+
+    /// Check if recommended levels are applied to a captcha automatically
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn captcha_auto_recommendation(&self, captcha_key: &str) -> DBResult<bool> {
+        struct AutoResp {
+            auto_recommendation: bool,
+        }
+
+        let resp = sqlx::query_as!(
+            AutoResp,
+            "SELECT auto_recommendation FROM mcaptcha_config
+            WHERE key = $1",
+            captcha_key,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+
+        Ok(resp.auto_recommendation)
+    }
+
+    /// Get all captchas that recommended levels are applied to automatically
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_captchas_with_auto_recommendation(
+        &self,
+    ) -> DBResult<Vec<OwnedCaptcha>> {
+        let captchas = sqlx::query_as!(
+            OwnedCaptcha,
+            "SELECT
+                mcaptcha_users.name as owner,
+                mcaptcha_config.key as key
+            FROM mcaptcha_config
+            INNER JOIN mcaptcha_users ON mcaptcha_users.ID = mcaptcha_config.user_id
+            WHERE mcaptcha_config.auto_recommendation = true",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(captchas)
+    }
+
+    /// count PoWConfig solves and confirms by country of the solver
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fetch_country_stats(
+        &self,
+        user: &str,
+        key: &str,
+    ) -> DBResult<Vec<CountryStats>> {
+        struct InnerCountryStats {
+            country: String,
+            solves: i64,
+            confirms: i64,
+        }
+
+        let config_id = sqlx::query!(
+            "SELECT config_id FROM mcaptcha_config
+            WHERE
+                key = $1
+            AND
+                user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)",
+            &key,
+            &user,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?
+        .config_id;
+
+        let countries = sqlx::query_as!(
+            InnerCountryStats,
+            r#"SELECT
+                country AS "country!",
+                SUM(solves)::BIGINT AS "solves!",
+                SUM(confirms)::BIGINT AS "confirms!"
+            FROM (
+                SELECT country, 1 AS solves, 0 AS confirms
+                FROM mcaptcha_pow_solved_stats
+                WHERE config_id = $1 AND country IS NOT NULL
+                UNION ALL
+                SELECT country, 0, 1
+                FROM mcaptcha_pow_confirmed_stats
+                WHERE config_id = $1 AND country IS NOT NULL
+            ) AS events
+            GROUP BY country
+            ORDER BY 2 DESC, 3 DESC, country"#,
+            config_id,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+
+        Ok(countries
+            .into_iter()
+            .map(|c| CountryStats {
+                country: c.country,
+                solves: c.solves as u64,
+                confirms: c.confirms as u64,
+            })
+            .collect())
+    }
+
+    /// Record PoW challenge `string` of a captcha, issued by an instance of a
+    /// cluster, that can be verified until unix timestamp `expires_at`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_cluster_challenge(
+        &self,
+        captcha_key: &str,
+        string: &str,
+        difficulty_factor: u32,
+        expires_at: i64,
+    ) -> DBResult<()> {
+        let expires_at = timestamp_to_date_time(expires_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_cluster_challenges
+                (config_id, string, difficulty_factor, expires_at)
+            VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2, $3, $4)",
+            captcha_key,
+            string,
+            difficulty_factor as i32,
+            &expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Remove PoW challenge `string` of a captcha to verify it. Returns its
+    /// difficulty factor, or `None` if it wasn't issued, was verified already
+    /// or expired by unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn take_cluster_challenge(
+        &self,
+        captcha_key: &str,
+        string: &str,
+        now: i64,
+    ) -> DBResult<Option<u32>> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_challenges
+            WHERE string = $1
+            AND config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $2)
+            AND expires_at > $3
+            RETURNING difficulty_factor",
+            string,
+            captcha_key,
+            &now,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.map(|r| r.difficulty_factor as u32))
+    }
+
+    /// Record validation token of a captcha, issued by an instance of a
+    /// cluster, that can be validated until unix timestamp `expires_at`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_cluster_token(
+        &self,
+        captcha_key: &str,
+        token: &str,
+        ip_hash: &str,
+        expires_at: i64,
+    ) -> DBResult<()> {
+        let expires_at = timestamp_to_date_time(expires_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_cluster_tokens (config_id, token, ip_hash, expires_at)
+            VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2, $3, $4)",
+            captcha_key,
+            token,
+            ip_hash,
+            &expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get hash of the IP address that a cluster's validation token of a
+    /// captcha was issued to, or `None` if it wasn't issued, was validated
+    /// already or expired by unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_cluster_token_ip(
+        &self,
+        captcha_key: &str,
+        token: &str,
+        now: i64,
+    ) -> DBResult<Option<String>> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "SELECT ip_hash FROM mcaptcha_cluster_tokens
+            WHERE token = $1
+            AND config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $2)
+            AND expires_at > $3",
+            token,
+            captcha_key,
+            &now,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.and_then(|r| r.ip_hash))
+    }
+
+    /// Remove validation token of a captcha to validate it. Returns false if it
+    /// wasn't issued, was validated already or expired by unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn take_cluster_token(
+        &self,
+        captcha_key: &str,
+        token: &str,
+        now: i64,
+    ) -> DBResult<bool> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_tokens
+            WHERE token = $1
+            AND config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $2)
+            AND expires_at > $3",
+            token,
+            captcha_key,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Record visitor counts of captchas, as counted by instance `instance_id`
+    /// of a cluster at unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn report_cluster_visitors(
+        &self,
+        instance_id: &str,
+        visitors: &[VisitorCount],
+        now: i64,
+    ) -> DBResult<()> {
+        let now = timestamp_to_date_time(now)?;
+        for v in visitors.iter() {
+            // captchas that were deleted in the meantime are skipped
+            sqlx::query!(
+                "INSERT INTO mcaptcha_cluster_visitors
+                    (config_id, instance_id, visitors, updated_at)
+                SELECT config_id, $2, $3, $4 FROM mcaptcha_config WHERE key = $1
+                ON CONFLICT (config_id, instance_id) DO UPDATE SET
+                    visitors = EXCLUDED.visitors,
+                    updated_at = EXCLUDED.updated_at",
+                &v.key,
+                instance_id,
+                v.visitors as i32,
+                &now,
+            )
+            .execute(&self.pool)
+            .await
+            .map_err(|e| DBError::DBError(Box::new(e)))?;
+        }
+        Ok(())
+    }
+
+    /// Get visitor counts of captchas, summed over the instances of a cluster
+    /// other than `instance_id` that reported at or after unix timestamp `since`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_cluster_visitors(
+        &self,
+        instance_id: &str,
+        since: i64,
+    ) -> DBResult<Vec<VisitorCount>> {
+        struct InnerVisitorCount {
+            key: String,
+            visitors: i64,
+        }
+
+        let since = timestamp_to_date_time(since)?;
+        let counts = sqlx::query_as!(
+            InnerVisitorCount,
+            r#"SELECT
+                mcaptcha_config.key,
+                SUM(mcaptcha_cluster_visitors.visitors)::BIGINT AS "visitors!"
+            FROM mcaptcha_cluster_visitors
+            INNER JOIN mcaptcha_config
+                ON mcaptcha_config.config_id = mcaptcha_cluster_visitors.config_id
+            WHERE mcaptcha_cluster_visitors.instance_id != $1
+            AND mcaptcha_cluster_visitors.updated_at >= $2
+            GROUP BY mcaptcha_config.key"#,
+            instance_id,
+            &since,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+
+        Ok(counts
+            .into_iter()
+            .map(|c| VisitorCount {
+                key: c.key,
+                visitors: c.visitors as u32,
+            })
+            .collect())
+    }
+
+    /// Record fallback challenge `id` of a captcha, started on an instance of a
+    /// cluster, that can be completed from unix timestamp `ready_at` until
+    /// `expires_at`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_cluster_fallback(
+        &self,
+        captcha_key: &str,
+        id: &str,
+        ready_at: i64,
+        expires_at: i64,
+    ) -> DBResult<()> {
+        let ready_at = timestamp_to_date_time(ready_at)?;
+        let expires_at = timestamp_to_date_time(expires_at)?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_cluster_fallbacks
+                (config_id, challenge_id, ready_at, expires_at)
+            VALUES ((SELECT config_id FROM mcaptcha_config WHERE key = $1), $2, $3, $4)",
+            captcha_key,
+            id,
+            &ready_at,
+            &expires_at,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+        Ok(())
+    }
+
+    /// Get unix timestamp from which a cluster's fallback challenge `id` of a
+    /// captcha can be completed, or `None` if it wasn't started, was completed
+    /// already or expired by unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_cluster_fallback(
+        &self,
+        captcha_key: &str,
+        id: &str,
+        now: i64,
+    ) -> DBResult<Option<i64>> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "SELECT ready_at FROM mcaptcha_cluster_fallbacks
+            WHERE challenge_id = $1
+            AND config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $2)
+            AND expires_at > $3",
+            id,
+            captcha_key,
+            &now,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.map(|r| r.ready_at.unix_timestamp()))
+    }
+
+    /// Remove fallback challenge `id` of a captcha to complete it. Returns
+    /// false if it wasn't started, was completed already, isn't ready yet or
+    /// expired by unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn take_cluster_fallback(
+        &self,
+        captcha_key: &str,
+        id: &str,
+        now: i64,
+    ) -> DBResult<bool> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_fallbacks
+            WHERE challenge_id = $1
+            AND config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $2)
+            AND ready_at <= $3
+            AND expires_at > $3",
+            id,
+            captcha_key,
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Count a fallback challenge started at unix timestamp `now`, on any
+    /// instance of a cluster, by the client whose IP address hashes to
+    /// `ip_hash`. When the client's window ended by `now`, a new one that ends
+    /// at `expires_at` is started. Returns the challenges counted in the window
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_cluster_fallback(
+        &self,
+        ip_hash: &str,
+        now: i64,
+        expires_at: i64,
+    ) -> DBResult<u32> {
+        let now = timestamp_to_date_time(now)?;
+        let expires_at = timestamp_to_date_time(expires_at)?;
+        let res = sqlx::query!(
+            "INSERT INTO mcaptcha_cluster_fallback_windows AS w
+                (ip_hash, started, expires_at)
+            VALUES ($1, 1, $3)
+            ON CONFLICT (ip_hash) DO UPDATE SET
+                started = CASE WHEN w.expires_at <= $2 THEN 1 ELSE w.started + 1 END,
+                expires_at = CASE WHEN w.expires_at <= $2
+                    THEN EXCLUDED.expires_at ELSE w.expires_at END
+            RETURNING started",
+            ip_hash,
+            &now,
+            &expires_at,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.started as u32)
+    }
+
+    /// Delete cluster challenges, fallback challenges, fallback windows and
+    /// tokens that expired by unix timestamp `now`, and visitor counts reported
+    /// before `stale_before`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn purge_cluster_state(&self, now: i64, stale_before: i64) -> DBResult<()> {
+        let now = timestamp_to_date_time(now)?;
+        let stale_before = timestamp_to_date_time(stale_before)?;
+        sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_challenges WHERE expires_at <= $1",
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_tokens WHERE expires_at <= $1",
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_fallbacks WHERE expires_at <= $1",
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_fallback_windows WHERE expires_at <= $1",
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        sqlx::query!(
+            "DELETE FROM mcaptcha_cluster_visitors WHERE updated_at < $1",
+            &stale_before,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(())
+    }
+
+    /// Add email to the outbox. Returns its ID
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn add_outbox_email(&self, e: &AddOutboxEmail<'_>) -> DBResult<i32> {
+        let recipients = e.recipients.join("\n");
+        let next_attempt_at = timestamp_to_date_time(e.next_attempt_at)?;
+        let created_at = timestamp_to_date_time(e.created_at)?;
+        let id = sqlx::query!(
+            "INSERT INTO mcaptcha_email_outbox
+                (sender, recipients, message, next_attempt_at, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id",
+            e.sender,
+            &recipients,
+            e.message,
+            &next_attempt_at,
+            &created_at,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?
+        .id;
+        Ok(id)
+    }
+
+    /// Count emails in the outbox, without those that were given up on
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_outbox_emails(&self) -> DBResult<usize> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) AS count FROM mcaptcha_email_outbox WHERE dead = false",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(count.count.unwrap_or_default() as usize)
+    }
+
+    /// Get up to `limit` emails of the outbox that are due to be sent by unix
+    /// timestamp `now`, oldest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_due_outbox_emails(
+        &self,
+        now: i64,
+        limit: usize,
+    ) -> DBResult<Vec<OutboxEmail>> {
+        let now = timestamp_to_date_time(now)?;
+        let emails = sqlx::query_as!(
+            InnerOutboxEmail,
+            "SELECT id, sender, recipients, message, attempts, last_error, created_at
+            FROM mcaptcha_email_outbox
+            WHERE dead = false AND next_attempt_at <= $1
+            ORDER BY next_attempt_at, id
+            LIMIT $2",
+            &now,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(emails.into_iter().map(|e| e.into()).collect())
+    }
+
+    /// Claim email for an attempt at sending it, if it was attempted `attempts`
+    /// times so far. Other instances don't attempt it until unix timestamp
+    /// `lease_until`. Returns false if it was claimed in the meantime
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn claim_outbox_email(
+        &self,
+        id: i32,
+        attempts: u32,
+        lease_until: i64,
+    ) -> DBResult<bool> {
+        let lease_until = timestamp_to_date_time(lease_until)?;
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_email_outbox
+            SET attempts = attempts + 1, next_attempt_at = $1
+            WHERE id = $2 AND attempts = $3 AND dead = false",
+            &lease_until,
+            id,
+            attempts as i32,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    /// Delete email that was sent from the outbox
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_outbox_email(&self, id: i32) -> DBResult<()> {
+        sqlx::query!("DELETE FROM mcaptcha_email_outbox WHERE id = $1", id)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(())
+    }
+
+    /// Record failed attempt at sending email. It is attempted again at unix
+    /// timestamp `next_attempt_at`, or given up on and moved to the dead
+    /// letters when `None`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn fail_outbox_email(
+        &self,
+        id: i32,
+        error: &str,
+        next_attempt_at: Option<i64>,
+    ) -> DBResult<()> {
+        match next_attempt_at {
+            Some(next_attempt_at) => {
+                let next_attempt_at = timestamp_to_date_time(next_attempt_at)?;
+                sqlx::query!(
+                    "UPDATE mcaptcha_email_outbox
+                    SET last_error = $1, next_attempt_at = $2
+                    WHERE id = $3",
+                    error,
+                    &next_attempt_at,
+                    id,
+                )
+                .execute(&self.pool)
+                .await
+            }
+            None => {
+                sqlx::query!(
+                    "UPDATE mcaptcha_email_outbox
+                    SET last_error = $1, dead = true
+                    WHERE id = $2",
+                    error,
+                    id,
+                )
+                .execute(&self.pool)
+                .await
+            }
+        }
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(())
+    }
+
+    /// Get emails that were given up on, newest first
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_dead_letters(&self) -> DBResult<Vec<OutboxEmail>> {
+        let emails = sqlx::query_as!(
+            InnerOutboxEmail,
+            "SELECT id, sender, recipients, message, attempts, last_error, created_at
+            FROM mcaptcha_email_outbox
+            WHERE dead = true
+            ORDER BY id DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(emails.into_iter().map(|e| e.into()).collect())
+    }
+
+    /// Move email from the dead letters back to the outbox, to be sent from
+    /// unix timestamp `now`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn retry_dead_letter(&self, id: i32, now: i64) -> DBResult<()> {
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_email_outbox
+            SET dead = false, attempts = 0, next_attempt_at = $1
+            WHERE id = $2 AND dead = true",
+            &now,
+            id,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        if res.rows_affected() == 0 {
+            return Err(DBError::DeadLetterNotFound);
+        }
+        Ok(())
+    }
+
+    /// Count escalations by visitor count in the escalation log of a captcha
+    /// at or after unix timestamp `since`
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn count_escalations(&self, captcha_key: &str, since: i64) -> DBResult<u64> {
+        struct Count {
+            count: Option<i64>,
+        }
+
+        let since = timestamp_to_date_time(since)?;
+        let count = sqlx::query_as!(
+            Count,
+            "SELECT COUNT(*) AS count FROM mcaptcha_escalation_log
+            WHERE
+                config_id = (SELECT config_id FROM mcaptcha_config WHERE key = $1)
+            AND kind = $2
+            AND time >= $3",
+            captcha_key,
+            EscalationKind::Escalation.as_str(),
+            &since,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(count.count.unwrap_or_default() as u64)
+    }
+
+    /// Subscribe a user to stats digests, or change the frequency of their
+    /// subscription. New subscriptions are first sent after one period
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn set_digest_frequency(
+        &self,
+        username: &str,
+        frequency: DigestFrequency,
+    ) -> DBResult<()> {
+        // whole seconds, as digests are claimed by unix timestamp
+        let now = timestamp_to_date_time(now_unix_time_stamp().unix_timestamp())?;
+        sqlx::query!(
+            "INSERT INTO mcaptcha_digest_subscriptions (user_id, frequency, last_sent)
+            VALUES ((SELECT ID FROM mcaptcha_users WHERE name = $1), $2, $3)
+            ON CONFLICT (user_id) DO UPDATE SET frequency = EXCLUDED.frequency",
+            username,
+            frequency.as_str(),
+            &now,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| map_row_not_found_err(e, DBError::AccountNotFound))?;
+        Ok(())
+    }
+
+    /// Get the frequency of the stats digests of a user, if subscribed
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_digest_frequency(
+        &self,
+        username: &str,
+    ) -> DBResult<Option<DigestFrequency>> {
+        let res = sqlx::query!(
+            "SELECT frequency FROM mcaptcha_digest_subscriptions
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.and_then(|r| DigestFrequency::parse(&r.frequency)))
+    }
+
+    /// Unsubscribe a user from stats digests
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn delete_digest_subscription(&self, username: &str) -> DBResult<()> {
+        sqlx::query!(
+            "DELETE FROM mcaptcha_digest_subscriptions
+            WHERE user_id = (SELECT ID FROM mcaptcha_users WHERE name = $1)",
+            username,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(())
+    }
+
+    /// Get all stats digest subscriptions
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn get_digest_subscriptions(&self) -> DBResult<Vec<DigestSubscription>> {
+        let subscriptions = sqlx::query_as!(
+            InnerDigestSubscription,
+            "SELECT mcaptcha_users.name as username, frequency, last_sent
+            FROM mcaptcha_digest_subscriptions
+            INNER JOIN mcaptcha_users
+                ON mcaptcha_users.ID = mcaptcha_digest_subscriptions.user_id
+            ORDER BY last_sent",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(subscriptions.into_iter().map(|s| s.into()).collect())
+    }
+
+    /// Mark the digest of a user as sent at unix timestamp `now`, if it was
+    /// last sent at or before `due_before`. Returns false if it was sent in the
+    /// meantime
+    #[tracing::instrument(skip_all, fields(db.system = "postgresql"))]
+    async fn claim_digest(
+        &self,
+        username: &str,
+        due_before: i64,
+        now: i64,
+    ) -> DBResult<bool> {
+        let due_before = timestamp_to_date_time(due_before)?;
+        let now = timestamp_to_date_time(now)?;
+        let res = sqlx::query!(
+            "UPDATE mcaptcha_digest_subscriptions SET last_sent = $1
+            WHERE
+                user_id = (SELECT ID FROM mcaptcha_users WHERE name = $2)
+            AND last_sent <= $3",
+            &now,
+            username,
+            &due_before,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| DBError::DBError(Box::new(e)))?;
+        Ok(res.rows_affected() == 1)
+    }
+}
+
+/// maximum number of rows in a single multi-row INSERT
+const STATS_INSERT_CHUNK: usize = 1000;
+
+/// insert stats records into `table` using multi-row INSERTs
+/// insert `records` into `table`. The country of the records is only written
+/// when `country` is set, as fetches don't record it
+async fn insert_stats_records(
+    pool: &PgPool,
+    table: &str,
+    records: &[StatsRecord],
+    country: bool,
+) -> DBResult<()> {
+    for chunk in records.chunks(STATS_INSERT_CHUNK) {
+        let mut rows = Vec::with_capacity(chunk.len());
+        for r in chunk.iter() {
+            let time = OffsetDateTime::from_unix_timestamp(r.time)
+                .map_err(|e| DBError::DBError(Box::new(e)))?;
+            rows.push((r.key.as_str(), time, r.country.as_deref()));
+        }
+
+        let columns = if country {
+            "config_id, time, country"
+        } else {
+            "config_id, time"
+        };
+        let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new(format!(
+            "INSERT INTO {table} ({columns}) "
+        ));
+        query.push_values(rows, |mut b, (key, time, c)| {
+            b.push("(SELECT config_id FROM mcaptcha_config WHERE key = ")
+                .push_bind_unseparated(key)
+                .push_unseparated(")")
+                .push_bind(time);
+            if country {
+                b.push_bind(c);
+            }
+        });
+        query
+            .build()
+            .execute(pool)
+            .await
+            .map_err(|e| map_row_not_found_err(e, DBError::CaptchaNotFound))?;
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
 struct Date {
     time: OffsetDateTime,
 }
@@ -1347,6 +5772,11 @@ fn now_unix_time_stamp() -> OffsetDateTime {
     OffsetDateTime::now_utc()
 }
 
+fn timestamp_to_date_time(timestamp: i64) -> DBResult<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map_err(|e| DBError::DBError(Box::new(e)))
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 /// Represents notification
 pub struct InnerNotification {
@@ -1360,6 +5790,8 @@ pub struct InnerNotification {
     pub received: Option<OffsetDateTime>,
     /// db assigned ID of the notification
     pub id: Option<i32>,
+    /// is the message encrypted to the receiver's notification key
+    pub encrypted: bool,
 }
 
 impl From<InnerNotification> for Notification {
@@ -1370,6 +5802,7 @@ impl From<InnerNotification> for Notification {
             message: n.message,
             received: n.received.map(|t| t.unix_timestamp()),
             id: n.id,
+            encrypted: n.encrypted,
         }
     }
 }
@@ -1412,3 +5845,326 @@ impl From<InnerTraffic> for TrafficPattern {
         }
     }
 }
+
+struct InnerWebhookDelivery {
+    event: String,
+    status: Option<i32>,
+    attempt: i32,
+    success: bool,
+    time: OffsetDateTime,
+}
+
+impl From<InnerWebhookDelivery> for WebhookDelivery {
+    fn from(v: InnerWebhookDelivery) -> Self {
+        WebhookDelivery {
+            event: v.event,
+            status: v.status.map(|s| s as u16),
+            attempt: v.attempt as u32,
+            success: v.success,
+            time: v.time.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerWidgetTheme {
+    mode: String,
+    accent_color: String,
+    background_color: Option<String>,
+    text_color: Option<String>,
+    border_radius: i32,
+    show_logo: bool,
+}
+
+impl From<InnerWidgetTheme> for WidgetTheme {
+    fn from(v: InnerWidgetTheme) -> Self {
+        WidgetTheme {
+            mode: ThemeMode::parse(&v.mode).unwrap_or_default(),
+            accent_color: v.accent_color,
+            background_color: v.background_color,
+            text_color: v.text_color,
+            border_radius: v.border_radius as u32,
+            show_logo: v.show_logo,
+        }
+    }
+}
+
+struct InnerAlertThresholds {
+    levels: bool,
+    highest_level: bool,
+    failure_rate: i32,
+    min_attempts: i32,
+    anomaly_factor: i32,
+}
+
+impl From<InnerAlertThresholds> for AlertThresholds {
+    fn from(v: InnerAlertThresholds) -> Self {
+        AlertThresholds {
+            levels: v.levels,
+            highest_level: v.highest_level,
+            failure_rate: v.failure_rate as u32,
+            min_attempts: v.min_attempts as u32,
+            anomaly_factor: v.anomaly_factor as u32,
+        }
+    }
+}
+
+struct InnerFraudThresholds {
+    max_hash_rate: i64,
+    nonce_outlier_factor: i32,
+    repeated_result_limit: i32,
+}
+
+impl From<InnerFraudThresholds> for FraudThresholds {
+    fn from(v: InnerFraudThresholds) -> Self {
+        FraudThresholds {
+            max_hash_rate: v.max_hash_rate as u32,
+            nonce_outlier_factor: v.nonce_outlier_factor as u32,
+            repeated_result_limit: v.repeated_result_limit as u32,
+        }
+    }
+}
+
+struct InnerDifficultyDecay {
+    sustain: i32,
+    step: i32,
+    steps: i32,
+    curve: String,
+}
+
+impl From<InnerDifficultyDecay> for DifficultyDecay {
+    fn from(v: InnerDifficultyDecay) -> Self {
+        DifficultyDecay {
+            sustain: v.sustain as u32,
+            step: v.step as u32,
+            steps: v.steps as u32,
+            curve: DecayCurve::parse(&v.curve).unwrap_or_default(),
+        }
+    }
+}
+
+struct InnerEscalationLogEntry {
+    kind: String,
+    difficulty_factor: i32,
+    time: OffsetDateTime,
+}
+
+impl From<InnerEscalationLogEntry> for EscalationLogEntry {
+    fn from(v: InnerEscalationLogEntry) -> Self {
+        EscalationLogEntry {
+            kind: EscalationKind::parse(&v.kind).unwrap_or_default(),
+            difficulty_factor: v.difficulty_factor as u32,
+            time: v.time.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerSession {
+    session_id: String,
+    username: String,
+    ip: String,
+    user_agent: String,
+    created_at: OffsetDateTime,
+    last_active: OffsetDateTime,
+}
+
+impl From<InnerSession> for Session {
+    fn from(s: InnerSession) -> Self {
+        Session {
+            id: s.session_id,
+            username: s.username,
+            ip: s.ip,
+            user_agent: s.user_agent,
+            created_at: s.created_at.unix_timestamp(),
+            last_active: s.last_active.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerAgreement {
+    document: String,
+    version: String,
+    ip_hash: String,
+    accepted_at: OffsetDateTime,
+}
+
+impl From<InnerAgreement> for Agreement {
+    fn from(v: InnerAgreement) -> Self {
+        Agreement {
+            document: v.document,
+            version: v.version,
+            ip_hash: v.ip_hash,
+            accepted_at: v.accepted_at.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerEmailVerification {
+    username: String,
+    email: String,
+    created_at: OffsetDateTime,
+}
+
+impl From<InnerEmailVerification> for EmailVerification {
+    fn from(v: InnerEmailVerification) -> Self {
+        EmailVerification {
+            username: v.username,
+            email: v.email,
+            created_at: v.created_at.unix_timestamp(),
+        }
+    }
+}
+
+impl From<InnerEmailVerification> for EmailChange {
+    fn from(c: InnerEmailVerification) -> Self {
+        EmailChange {
+            username: c.username,
+            email: c.email,
+            created_at: c.created_at.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerSessionRevocation {
+    username: String,
+    created_at: OffsetDateTime,
+}
+
+impl From<InnerSessionRevocation> for SessionRevocation {
+    fn from(r: InnerSessionRevocation) -> Self {
+        SessionRevocation {
+            username: r.username,
+            created_at: r.created_at.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerInstanceUser {
+    name: String,
+    email: Option<String>,
+    is_admin: bool,
+    captchas: Option<i64>,
+    residency: Option<String>,
+    suspended: bool,
+}
+
+impl From<InnerInstanceUser> for InstanceUser {
+    fn from(u: InnerInstanceUser) -> Self {
+        InstanceUser {
+            name: u.name,
+            email: u.email,
+            is_admin: u.is_admin,
+            captchas: u.captchas.unwrap_or_default() as u32,
+            residency: u.residency,
+            suspended: u.suspended,
+        }
+    }
+}
+
+struct InnerBurst {
+    id: i32,
+    key: String,
+    starts_at: OffsetDateTime,
+    ends_at: OffsetDateTime,
+    difficulty_factor: i32,
+}
+
+impl From<InnerBurst> for Burst {
+    fn from(b: InnerBurst) -> Self {
+        Burst {
+            id: b.id,
+            key: b.key,
+            starts: b.starts_at.unix_timestamp(),
+            ends: b.ends_at.unix_timestamp(),
+            difficulty_factor: b.difficulty_factor as u32,
+        }
+    }
+}
+
+struct InnerInvite {
+    id: i32,
+    email: String,
+    org: Option<String>,
+    role: Option<String>,
+    invited_by: String,
+    created_at: OffsetDateTime,
+    expires_at: OffsetDateTime,
+    accepted_at: Option<OffsetDateTime>,
+}
+
+impl From<InnerInvite> for Invite {
+    fn from(i: InnerInvite) -> Self {
+        Invite {
+            id: i.id,
+            email: i.email,
+            org: i.org,
+            role: i.role.as_deref().and_then(OrgRole::parse),
+            invited_by: i.invited_by,
+            created_at: i.created_at.unix_timestamp(),
+            expires_at: i.expires_at.unix_timestamp(),
+            accepted_at: i.accepted_at.map(|t| t.unix_timestamp()),
+        }
+    }
+}
+
+struct InnerOrgMember {
+    name: String,
+    role: String,
+}
+
+impl From<InnerOrgMember> for OrgMember {
+    fn from(m: InnerOrgMember) -> Self {
+        OrgMember {
+            username: m.name,
+            role: OrgRole::parse(&m.role).unwrap_or_default(),
+        }
+    }
+}
+
+impl From<InnerOrgMember> for OrgMembership {
+    fn from(m: InnerOrgMember) -> Self {
+        OrgMembership {
+            org: m.name,
+            role: OrgRole::parse(&m.role).unwrap_or_default(),
+        }
+    }
+}
+
+struct InnerOutboxEmail {
+    id: i32,
+    sender: Option<String>,
+    recipients: String,
+    message: String,
+    attempts: i32,
+    last_error: Option<String>,
+    created_at: OffsetDateTime,
+}
+
+impl From<InnerOutboxEmail> for OutboxEmail {
+    fn from(e: InnerOutboxEmail) -> Self {
+        OutboxEmail {
+            id: e.id,
+            sender: e.sender,
+            recipients: e.recipients.lines().map(|r| r.to_string()).collect(),
+            message: e.message,
+            attempts: e.attempts as u32,
+            last_error: e.last_error,
+            created_at: e.created_at.unix_timestamp(),
+        }
+    }
+}
+
+struct InnerDigestSubscription {
+    username: String,
+    frequency: String,
+    last_sent: OffsetDateTime,
+}
+
+impl From<InnerDigestSubscription> for DigestSubscription {
+    fn from(s: InnerDigestSubscription) -> Self {
+        DigestSubscription {
+            username: s.username,
+            frequency: DigestFrequency::parse(&s.frequency).unwrap_or_default(),
+            last_sent: s.last_sent.unix_timestamp(),
+        }
+    }
+}
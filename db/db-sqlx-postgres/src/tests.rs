@@ -69,6 +69,7 @@ async fn everyting_works() {
         description: CAPTCHA_DESCRIPTION,
     };
     database_works(&db, &p, &c, &LEVELS, &TRAFFIC_PATTERN, &ADD_NOTIFICATION).await;
+    adversarial_inputs_work(&db, &p).await;
     drop(db);
     sqlx::Postgres::drop_database(&url).await.unwrap();
 }
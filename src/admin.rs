@@ -0,0 +1,245 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Instance administrators
+//!
+//! Administrators can list users, view instance-wide counts and delete
+//! abusive accounts. Users listed in the `admins` setting are made
+//! administrators on startup, and administrators can be promoted and demoted
+//! from the command line with `mcaptcha admin promote|demote <username>`.
+//! [AdminGate] restricts the admin API and pages to administrators.
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_identity::RequestIdentity;
+use actix_web::body::EitherBody;
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::{http::header, Error, HttpResponse, ResponseError};
+use db_core::errors::DBError;
+use futures::future::LocalBoxFuture;
+
+use crate::errors::*;
+use crate::AppData;
+
+/// usage of the command-line interface
+pub const USAGE: &str =
+    "Usage: mcaptcha [demo | --print-config | check-config [--smtp] | admin promote|demote <username>]";
+
+/// paths that are restricted to administrators. Besides the admin pages and
+/// API, the state of background jobs and internal queues is instance-wide
+const ADMIN_PATHS: [&str; 7] = [
+    "/admin",
+    "/api/v1/admin",
+    "/jobs",
+    "/api/v1/meta/jobs",
+    "/api/v1/meta/load",
+    "/api/v1/meta/mail_queue",
+    "/api/v1/meta/stats_queue",
+];
+
+fn is_admin_path(path: &str) -> bool {
+    ADMIN_PATHS
+        .iter()
+        .any(|p| path == *p || path.starts_with(&format!("{p}/")))
+}
+
+/// make users listed in settings administrators
+pub async fn bootstrap(data: &AppData) {
+    for username in data.settings.admins.iter() {
+        match data.db.set_admin(username, true).await {
+            Ok(()) => log::info!("{username} is an instance administrator"),
+            Err(DBError::AccountNotFound) => {
+                log::warn!(
+                    "Unable to make {username} an administrator: account not found"
+                )
+            }
+            Err(e) => log::error!("Unable to make {username} an administrator: {e}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// administration command, as passed on the command line
+pub enum Command {
+    /// make user an administrator
+    Promote(String),
+    /// revoke administrator role of user
+    Demote(String),
+}
+
+impl Command {
+    /// parse command-line arguments, excluding the program name
+    pub fn parse(args: &[String]) -> Option<Self> {
+        match args {
+            [admin, action, username] if admin == "admin" => match action.as_str() {
+                "promote" => Some(Self::Promote(username.clone())),
+                "demote" => Some(Self::Demote(username.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub async fn run(&self, data: &AppData) -> ServiceResult<()> {
+        match self {
+            Self::Promote(username) => {
+                data.db.set_admin(username, true).await?;
+                println!("{username} is now an instance administrator");
+            }
+            Self::Demote(username) => {
+                data.db.set_admin(username, false).await?;
+                println!("{username} is no longer an instance administrator");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Middleware that restricts the admin API and pages to administrators.
+/// Signed-in users that aren't administrators are redirected to the
+/// dashboard, and their API requests are rejected with
+/// [ServiceError::AdminRequired]. Requests that aren't signed in are left to
+/// the authentication middleware of the routes.
+///
+/// Must be registered before the identity middleware so that it runs after it.
+pub struct AdminGate;
+
+impl<S, B> Transform<S, ServiceRequest> for AdminGate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AdminGateMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AdminGateMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AdminGateMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AdminGateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let data = req.app_data::<AppData>().cloned();
+            let username = req.get_identity();
+            if let (Some(data), Some(username), true) =
+                (data, username, is_admin_path(req.path()))
+            {
+                let is_admin = match data.db.is_admin(&username).await {
+                    Ok(is_admin) => is_admin,
+                    Err(DBError::AccountNotFound) => false,
+                    Err(e) => return Err(ServiceError::from(e).into()),
+                };
+                if !is_admin {
+                    let resp = if req.path().starts_with("/api/") {
+                        ServiceError::AdminRequired.error_response()
+                    } else {
+                        HttpResponse::Found()
+                            .append_header((header::LOCATION, crate::PAGES.panel.home))
+                            .finish()
+                    };
+                    return Ok(req.into_response(resp).map_into_right_body());
+                }
+            }
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn admin_paths_work() {
+        assert!(is_admin_path(PAGES.panel.admin));
+        assert!(is_admin_path(V1_API_ROUTES.admin.users));
+        assert!(is_admin_path(PAGES.panel.jobs));
+        assert!(is_admin_path(V1_API_ROUTES.meta.load));
+        assert!(!is_admin_path(V1_API_ROUTES.meta.health));
+        assert!(!is_admin_path("/administrator"));
+        assert!(!is_admin_path(PAGES.panel.home));
+    }
+
+    #[test]
+    fn command_parse_works() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Command::parse(&args(&["admin", "promote", "alice"])),
+            Some(Command::Promote("alice".into()))
+        );
+        assert_eq!(
+            Command::parse(&args(&["admin", "demote", "alice"])),
+            Some(Command::Demote("alice".into()))
+        );
+        assert_eq!(Command::parse(&args(&["admin", "promote"])), None);
+        assert_eq!(Command::parse(&args(&["admin", "delete", "alice"])), None);
+        assert_eq!(Command::parse(&args(&["serve"])), None);
+    }
+
+    #[actix_rt::test]
+    async fn bootstrap_works_pg() {
+        let data = pg::get_data().await;
+        bootstrap_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn bootstrap_works_maria() {
+        let data = maria::get_data().await;
+        bootstrap_works(data).await;
+    }
+
+    async fn bootstrap_works(data: ArcData) {
+        const NAME: &str = "adminbootstrapuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminbootstrapuser@a.com";
+
+        delete_user(&data, NAME).await;
+        register(&data, NAME, EMAIL, PASSWORD).await;
+
+        let mut settings = data.settings.clone();
+        settings.admins = vec![NAME.into(), "adminbootstrapnonexistent".into()];
+        let data = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let data = actix_web::web::Data::new(data);
+        bootstrap(&data).await;
+        assert!(data.db.is_admin(NAME).await.unwrap());
+
+        Command::Demote(NAME.into()).run(&data).await.unwrap();
+        assert!(!data.db.is_admin(NAME).await.unwrap());
+        Command::Promote(NAME.into()).run(&data).await.unwrap();
+        assert!(data.db.is_admin(NAME).await.unwrap());
+        assert!(Command::Promote("adminbootstrapnonexistent".into())
+            .run(&data)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Acceptance of legal documents on commercial instances
+//!
+//! Operators configure the current version of their terms of service and data
+//! processing agreement. [AgreementGate] stops signed-in users from using the
+//! dashboard until they have accepted the current version of every configured
+//! document, so bumping a version prompts everyone to accept it again.
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_identity::RequestIdentity;
+use actix_web::body::EitherBody;
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::{http::header, Error, HttpResponse, ResponseError};
+use futures::future::LocalBoxFuture;
+
+use crate::client_ip::hash_ip;
+use crate::errors::*;
+use crate::settings::Agreements;
+use crate::AppData;
+
+/// terms of service
+pub const TOS: &str = "tos";
+/// data processing agreement
+pub const DPA: &str = "dpa";
+
+/// paths that require the user to have accepted all agreements
+const GATED_PATHS: [&str; 12] = [
+    "/admin",
+    "/sitekey",
+    "/notifications",
+    "/jobs",
+    "/settings",
+    "/utils",
+    "/api/v1/account",
+    "/api/v1/admin",
+    "/api/v1/mcaptcha",
+    "/api/v1/notifications",
+    "/api/v1/stats",
+    "/api/v2",
+];
+
+/// legal document that a user has to accept
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    /// document identifier, as stored in acceptance records
+    pub name: &'static str,
+    pub title: &'static str,
+    pub version: String,
+    pub url: Option<String>,
+}
+
+impl Agreements {
+    /// documents configured on this instance
+    pub fn documents(&self) -> Vec<Document> {
+        let mut docs = Vec::with_capacity(2);
+        if let Some(version) = &self.tos_version {
+            docs.push(Document {
+                name: TOS,
+                title: "Terms of Service",
+                version: version.clone(),
+                url: self.tos_url.clone(),
+            });
+        }
+        if let Some(version) = &self.dpa_version {
+            docs.push(Document {
+                name: DPA,
+                title: "Data Processing Agreement",
+                version: version.clone(),
+                url: self.dpa_url.clone(),
+            });
+        }
+        docs
+    }
+}
+
+/// documents whose current version the user hasn't accepted yet
+pub async fn pending(data: &AppData, username: &str) -> ServiceResult<Vec<Document>> {
+    if !data.settings.commercial {
+        return Ok(Vec::default());
+    }
+    let docs = data.settings.agreements.documents();
+    if docs.is_empty() {
+        return Ok(docs);
+    }
+
+    let accepted = data.db.fetch_agreements(username).await?;
+    Ok(docs
+        .into_iter()
+        .filter(|d| {
+            !accepted
+                .iter()
+                .any(|a| a.document == d.name && a.version == d.version)
+        })
+        .collect())
+}
+
+/// accept current version of all pending documents
+pub async fn accept(data: &AppData, username: &str, ip: &str) -> ServiceResult<()> {
+    // hashed so that acceptance can be attributed without storing the address
+    let ip_hash = hash_ip(&data.settings.server.cookie_secret, ip);
+    for doc in pending(data, username).await?.iter() {
+        let agreement = db_core::AcceptAgreement {
+            document: doc.name,
+            version: &doc.version,
+            ip_hash: &ip_hash,
+        };
+        data.db.accept_agreement(username, &agreement).await?;
+    }
+    Ok(())
+}
+
+fn is_gated(path: &str) -> bool {
+    path == crate::PAGES.panel.home || GATED_PATHS.iter().any(|p| path.starts_with(p))
+}
+
+/// Middleware that redirects signed-in users with pending agreements to the
+/// acceptance page. API requests are rejected with
+/// [ServiceError::AgreementsNotAccepted].
+///
+/// Must be registered before the identity middleware so that it runs after it.
+pub struct AgreementGate;
+
+impl<S, B> Transform<S, ServiceRequest> for AgreementGate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AgreementGateMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AgreementGateMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AgreementGateMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AgreementGateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let data = req.app_data::<AppData>().cloned();
+            let username = req.get_identity();
+            if let (Some(data), Some(username)) = (data, username) {
+                if is_gated(req.path()) && !pending(&data, &username).await?.is_empty() {
+                    let resp = if req.path().starts_with("/api/") {
+                        ServiceError::AgreementsNotAccepted.error_response()
+                    } else {
+                        HttpResponse::Found()
+                            .append_header((
+                                header::LOCATION,
+                                crate::PAGES.auth.agreements,
+                            ))
+                            .finish()
+                    };
+                    return Ok(req.into_response(resp).map_into_right_body());
+                }
+            }
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn gated_paths_work() {
+        assert!(is_gated(PAGES.panel.home));
+        assert!(is_gated(PAGES.panel.sitekey.list));
+        assert!(is_gated(V1_API_ROUTES.captcha.create));
+        assert!(!is_gated(PAGES.auth.agreements));
+        assert!(!is_gated(V1_API_ROUTES.pow.get_config));
+        assert!(!is_gated(V1_API_ROUTES.auth.logout));
+    }
+
+    #[actix_rt::test]
+    async fn agreements_work_pg() {
+        let data = pg::get_data().await;
+        agreements_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn agreements_work_maria() {
+        let data = maria::get_data().await;
+        agreements_work(data).await;
+    }
+
+    async fn agreements_work(data: ArcData) {
+        const NAME: &str = "agreementsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "agreementsuser@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.commercial = true;
+        settings.agreements.tos_version = Some("1".into());
+        settings.agreements.dpa_version = Some("1".into());
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = actix_web::web::Data::new(data.clone());
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        assert_eq!(pending(&app_data, NAME).await.unwrap().len(), 2);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.panel.home)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get(header::LOCATION).unwrap(),
+            PAGES.auth.agreements
+        );
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&(), V1_API_ROUTES.captcha.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        accept(&app_data, NAME, "127.0.0.1").await.unwrap();
+        assert!(pending(&app_data, NAME).await.unwrap().is_empty());
+        let agreements = data.db.fetch_agreements(NAME).await.unwrap();
+        assert_eq!(agreements.len(), 2);
+        assert_eq!(
+            agreements[0].ip_hash,
+            hash_ip(&settings.server.cookie_secret, "127.0.0.1")
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.panel.home)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // updated document needs to be accepted again
+        let mut settings = settings.clone();
+        settings.agreements.tos_version = Some("2".into());
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = actix_web::web::Data::new(data.clone());
+        let pending = pending(&app_data, NAME).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].name, TOS);
+        assert_eq!(pending[0].version, "2");
+    }
+}
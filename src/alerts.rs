@@ -0,0 +1,362 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Automatic notifications on traffic of sitekeys
+//!
+//! Owners of sitekeys that have alert thresholds set are notified, from the
+//! reserved [SYSTEM_USER] account, of the following alerts:
+//!
+//! - `level_crossed`: the visitor count crossed the visitor threshold of a
+//!   level, escalating difficulty
+//! - `highest_level`: difficulty escalated to the highest level
+//! - `failure_rate`: more than `failure_rate` percent of the solves attempted
+//!   within ten minutes failed verification, after at least `min_attempts`
+//!   were attempted
+//! - `fetch_flood`, `solve_spike` and `confirm_drop`: traffic departed from
+//!   its baseline by more than `anomaly_factor`; see [crate::anomalies]
+//!
+//! Each alert is sent at most once an hour per sitekey. Solve attempts are
+//! counted by the instance that received them.
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use db_core::errors::DBError;
+use db_core::{AlertThresholds, Level};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::AppData;
+
+/// reserved account that sends automatic notifications. The name can't be
+/// registered
+pub const SYSTEM_USER: &str = "system";
+
+/// seconds over which the failure rate of solves is measured
+const ATTEMPT_WINDOW: i64 = 600;
+/// seconds within which an alert isn't sent again for the same sitekey
+const REPORT_INTERVAL: i64 = 3600;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// automatic notifications
+pub enum Alert {
+    /// visitor count crossed the visitor threshold of a level
+    LevelCrossed,
+    /// difficulty escalated to the highest level
+    HighestLevel,
+    /// large share of solves failed verification
+    FailureRate,
+    /// configuration fetches far above their baseline
+    FetchFlood,
+    /// solves far above their baseline
+    SolveSpike,
+    /// share of solves that were confirmed far below its baseline
+    ConfirmDrop,
+}
+
+impl Alert {
+    /// heading of the notification
+    pub fn heading(&self) -> &'static str {
+        match self {
+            Self::LevelCrossed => "Traffic increased",
+            Self::HighestLevel => "Highest difficulty reached",
+            Self::FailureRate => "Solves are failing",
+            Self::FetchFlood => "Challenge fetches spiked",
+            Self::SolveSpike => "Solves spiked",
+            Self::ConfirmDrop => "Tokens aren't being confirmed",
+        }
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// alert raised by difficulty escalating to `difficulty_factor`
+pub fn escalation_alert(
+    thresholds: &AlertThresholds,
+    levels: &[Level],
+    difficulty_factor: u32,
+) -> Option<Alert> {
+    let highest = levels.iter().map(|l| l.difficulty_factor).max()?;
+    if thresholds.highest_level && difficulty_factor >= highest {
+        Some(Alert::HighestLevel)
+    } else if thresholds.levels {
+        Some(Alert::LevelCrossed)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// solves attempted on a sitekey within a window
+pub struct Attempts {
+    pub attempted: u32,
+    pub failed: u32,
+    /// start of the window
+    since: i64,
+}
+
+#[derive(Default)]
+pub struct Alerts {
+    /// solves attempted on sitekeys
+    attempts: RwLock<HashMap<String, Attempts>>,
+    /// time at which alerts were last sent for sitekeys
+    reported: RwLock<HashMap<(String, Alert), i64>>,
+}
+
+impl Alerts {
+    /// record a solve attempt and return the attempts of the window if their
+    /// failure rate is above the threshold
+    pub fn attempted(
+        &self,
+        thresholds: &AlertThresholds,
+        key: &str,
+        failed: bool,
+    ) -> Option<Attempts> {
+        if thresholds.failure_rate == 0 {
+            return None;
+        }
+        let now = now();
+        let mut w = self.attempts.write().unwrap();
+        let entry = w.entry(key.to_string()).or_insert(Attempts {
+            since: now,
+            ..Default::default()
+        });
+        if now - entry.since >= ATTEMPT_WINDOW {
+            *entry = Attempts {
+                since: now,
+                ..Default::default()
+            };
+        }
+        entry.attempted += 1;
+        if failed {
+            entry.failed += 1;
+        }
+        if entry.attempted >= thresholds.min_attempts.max(1)
+            && entry.failed as u64 * 100
+                > thresholds.failure_rate as u64 * entry.attempted as u64
+        {
+            Some(*entry)
+        } else {
+            None
+        }
+    }
+
+    /// check if alert can be sent for a sitekey, and mark it sent
+    fn should_report(&self, key: &str, alert: Alert) -> bool {
+        let now = now();
+        let mut w = self.reported.write().unwrap();
+        match w.get(&(key.to_string(), alert)) {
+            Some(last) if now - *last < REPORT_INTERVAL => false,
+            _ => {
+                w.insert((key.to_string(), alert), now);
+                true
+            }
+        }
+    }
+}
+
+/// alert thresholds of a sitekey, if they are set
+pub async fn thresholds(
+    data: &AppData,
+    key: &str,
+) -> ServiceResult<Option<AlertThresholds>> {
+    match data.db.get_alert_thresholds(key).await {
+        Ok(t) => Ok(Some(t)),
+        Err(DBError::AlertThresholdsNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// check for alerts when difficulty of a sitekey escalates. Failures are
+/// logged, so that alerting doesn't fail challenges
+pub async fn escalated(data: &AppData, key: &str, difficulty_factor: u32) {
+    if !data.settings.features.notifications {
+        return;
+    }
+    let alert = match thresholds(data, key).await {
+        Ok(Some(t)) => match data.db.get_captcha_levels(None, key).await {
+            Ok(levels) => escalation_alert(&t, &levels, difficulty_factor),
+            Err(e) => {
+                log::error!("Unable to check alerts of sitekey {key}: {e}");
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("Unable to check alerts of sitekey {key}: {e}");
+            None
+        }
+    };
+    let alert = match alert {
+        Some(alert) => alert,
+        None => return,
+    };
+    let message = match alert {
+        Alert::HighestLevel => format!(
+            "Difficulty of sitekey {key} escalated to the highest level, {difficulty_factor}. The site might be under attack."
+        ),
+        _ => format!(
+            "The visitor count of sitekey {key} crossed a level, raising difficulty to {difficulty_factor}."
+        ),
+    };
+    report(data, key, alert, &message).await;
+}
+
+/// record a solve attempt on a sitekey and check its failure rate. Failures
+/// are logged, so that alerting doesn't fail verification
+pub async fn attempted(data: &AppData, key: &str, failed: bool) {
+    if !data.settings.features.notifications {
+        return;
+    }
+    let attempts = match thresholds(data, key).await {
+        Ok(Some(t)) => data.alerts.attempted(&t, key, failed),
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("Unable to check alerts of sitekey {key}: {e}");
+            None
+        }
+    };
+    if let Some(a) = attempts {
+        let message = format!(
+            "{} of the {} solves attempted on sitekey {key} in the last ten minutes failed verification.",
+            a.failed, a.attempted
+        );
+        report(data, key, Alert::FailureRate, &message).await;
+    }
+}
+
+/// register the system account if it doesn't exist. Nobody knows its
+/// password, so it can't be signed into
+async fn register_system_user(data: &AppData) -> ServiceResult<()> {
+    if data.db.username_exists(SYSTEM_USER).await? {
+        return Ok(());
+    }
+    let hash = data.creds.password(&get_random(32))?;
+    loop {
+        let secret = get_random(32);
+        let p = db_core::Register {
+            username: SYSTEM_USER,
+            hash: &hash,
+            email: None,
+            secret: &secret,
+        };
+        match data.db.register(&p).await {
+            Ok(_) | Err(DBError::UsernameTaken) => return Ok(()),
+            Err(DBError::SecretTaken) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// notify the owner of a sitekey of an alert, returning whether it was sent.
+/// Alerts that were sent within the hour aren't sent again
+pub async fn report(data: &AppData, key: &str, alert: Alert, message: &str) -> bool {
+    if !data.alerts.should_report(key, alert) {
+        return false;
+    }
+    log::info!("Alert on sitekey {key}: {message}");
+    let owner = match data.db.get_captcha_owner(key).await {
+        Ok(owner) => owner,
+        Err(e) => {
+            log::error!("Unable to notify owner of sitekey {key} of alert: {e}");
+            return true;
+        }
+    };
+    if let Err(e) = register_system_user(data).await {
+        log::error!("Unable to register system user: {e}");
+        return true;
+    }
+    let message = format!("{message} This alert won't be sent again for an hour.");
+    let n = db_core::AddNotification {
+        to: &owner,
+        from: SYSTEM_USER,
+        heading: alert.heading(),
+        message: &message,
+    };
+    if let Err(e) = crate::api::v1::notifications::notify(data, &n).await {
+        log::error!("Unable to notify {owner} of alert: {e}");
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: AlertThresholds = AlertThresholds {
+        levels: true,
+        highest_level: true,
+        failure_rate: 50,
+        min_attempts: 4,
+        anomaly_factor: 0,
+    };
+
+    #[test]
+    fn escalation_alerts_work() {
+        let levels = [
+            Level {
+                visitor_threshold: 50,
+                difficulty_factor: 50,
+            },
+            Level {
+                visitor_threshold: 500,
+                difficulty_factor: 500,
+            },
+        ];
+        assert_eq!(
+            escalation_alert(&THRESHOLDS, &levels, 50),
+            Some(Alert::LevelCrossed)
+        );
+        assert_eq!(
+            escalation_alert(&THRESHOLDS, &levels, 500),
+            Some(Alert::HighestLevel)
+        );
+
+        let levels_only = AlertThresholds {
+            highest_level: false,
+            ..THRESHOLDS
+        };
+        assert_eq!(
+            escalation_alert(&levels_only, &levels, 500),
+            Some(Alert::LevelCrossed)
+        );
+        let highest_only = AlertThresholds {
+            levels: false,
+            ..THRESHOLDS
+        };
+        assert_eq!(escalation_alert(&highest_only, &levels, 50), None);
+        assert_eq!(escalation_alert(&THRESHOLDS, &[], 50), None);
+    }
+
+    #[test]
+    fn failure_rate_works() {
+        let alerts = Alerts::default();
+        assert!(alerts.attempted(&THRESHOLDS, "key", true).is_none());
+        assert!(alerts.attempted(&THRESHOLDS, "key", true).is_none());
+        assert!(alerts.attempted(&THRESHOLDS, "key", false).is_none());
+        let attempts = alerts.attempted(&THRESHOLDS, "key", true).unwrap();
+        assert_eq!((attempts.failed, attempts.attempted), (3, 4));
+        assert!(alerts.attempted(&THRESHOLDS, "key", false).is_some());
+        // 3 of 6 isn't more than half
+        assert!(alerts.attempted(&THRESHOLDS, "key", false).is_none());
+        assert!(alerts.attempted(&THRESHOLDS, "other", true).is_none());
+
+        let disabled = AlertThresholds::default();
+        assert!(alerts.attempted(&disabled, "key", true).is_none());
+    }
+
+    #[test]
+    fn alerts_are_rate_limited() {
+        let alerts = Alerts::default();
+        assert!(alerts.should_report("key", Alert::FailureRate));
+        assert!(!alerts.should_report("key", Alert::FailureRate));
+        assert!(alerts.should_report("key", Alert::HighestLevel));
+        assert!(alerts.should_report("other", Alert::FailureRate));
+    }
+}
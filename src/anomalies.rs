@@ -0,0 +1,287 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Anomaly detection on stats of sitekeys
+//!
+//! Every [WINDOW] seconds, [AnomalyDetector] compares the stats of the last
+//! complete window of sitekeys whose alert thresholds set `anomaly_factor`
+//! against their baseline, the average window of the preceding [BASELINE]
+//! seconds:
+//!
+//! - `fetch_flood`: configuration fetches exceed `anomaly_factor` times the
+//!   baseline
+//! - `solve_spike`: solves exceed `anomaly_factor` times the baseline
+//! - `confirm_drop`: the share of solves whose tokens were confirmed is below
+//!   the baseline share divided by `anomaly_factor`. Tokens that are solved
+//!   but never used point to farmed solutions
+//!
+//! A window needs at least [MIN_EVENTS] events of a kind for it to be
+//! anomalous, so that quiet sitekeys don't alert on a handful of visitors.
+//! Anomalies are reported like other [alerts](crate::alerts), delivered to the
+//! sitekey's webhook as `anomaly.detected` events and emailed to the owner.
+//! Stats are read from the database, so anomalies aren't detected when stats
+//! are written to a [stats sink](crate::stats_sink) or disabled.
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::{OwnedCaptcha, StatsBucket, TimeRange};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::alerts::{self, Alert};
+use crate::errors::*;
+use crate::jobs::ANOMALY_JOB;
+use crate::AppData;
+
+/// seconds over which stats are compared against their baseline
+pub const WINDOW: i64 = 300;
+/// seconds of stats before the window that its baseline is averaged over
+pub const BASELINE: i64 = 60 * 60 * 24;
+/// fewest events of a kind within the window for it to be anomalous
+pub const MIN_EVENTS: u64 = 20;
+
+/// average counts of the windows of `baseline`, which spans [BASELINE]
+/// seconds. Windows without events count as empty
+fn averages(baseline: &[StatsBucket]) -> (f64, f64) {
+    let windows = (BASELINE / WINDOW) as f64;
+    let fetches: u64 = baseline.iter().map(|b| b.config_fetches).sum();
+    let solves: u64 = baseline.iter().map(|b| b.solves).sum();
+    (fetches as f64 / windows, solves as f64 / windows)
+}
+
+/// share of `solves` that were confirmed
+fn confirm_share(solves: u64, confirms: u64) -> f64 {
+    confirms.min(solves) as f64 / solves as f64
+}
+
+/// anomalies of `current` against `baseline`. Baselines of less than one
+/// event per window count as one
+pub fn detect(
+    factor: u32,
+    current: &StatsBucket,
+    baseline: &[StatsBucket],
+) -> Vec<Alert> {
+    let mut anomalies = Vec::new();
+    if factor == 0 {
+        return anomalies;
+    }
+    let factor = factor as f64;
+    let (fetches, solves) = averages(baseline);
+
+    if current.config_fetches >= MIN_EVENTS
+        && current.config_fetches as f64 > factor * fetches.max(1.0)
+    {
+        anomalies.push(Alert::FetchFlood);
+    }
+    if current.solves >= MIN_EVENTS && current.solves as f64 > factor * solves.max(1.0) {
+        anomalies.push(Alert::SolveSpike);
+    }
+
+    let baseline_solves: u64 = baseline.iter().map(|b| b.solves).sum();
+    let baseline_confirms: u64 = baseline.iter().map(|b| b.confirms).sum();
+    if current.solves >= MIN_EVENTS && baseline_solves >= MIN_EVENTS {
+        let usual = confirm_share(baseline_solves, baseline_confirms);
+        let share = confirm_share(current.solves, current.confirms);
+        if share * factor < usual {
+            anomalies.push(Alert::ConfirmDrop);
+        }
+    }
+    anomalies
+}
+
+/// message of the notification of an anomaly
+fn describe(
+    alert: Alert,
+    key: &str,
+    current: &StatsBucket,
+    baseline: &[StatsBucket],
+) -> String {
+    let minutes = WINDOW / 60;
+    let (fetches, solves) = averages(baseline);
+    match alert {
+        Alert::FetchFlood => format!(
+            "Challenges of sitekey {key} were fetched {} times in the last {minutes} minutes, against {fetches:.1} times usually. The site might be under attack.",
+            current.config_fetches
+        ),
+        Alert::SolveSpike => format!(
+            "Challenges of sitekey {key} were solved {} times in the last {minutes} minutes, against {solves:.1} times usually. The site might be under attack.",
+            current.solves
+        ),
+        _ => format!(
+            "Only {} of the {} tokens issued for sitekey {key} in the last {minutes} minutes were confirmed. Solutions might be farmed.",
+            current.confirms, current.solves
+        ),
+    }
+}
+
+/// check a sitekey for anomalies in the window that ended at `end` and
+/// report them
+pub async fn check(
+    data: &AppData,
+    c: &OwnedCaptcha,
+    end: i64,
+) -> ServiceResult<Vec<Alert>> {
+    let factor = match alerts::thresholds(data, &c.key).await? {
+        Some(t) if t.anomaly_factor > 0 => t.anomaly_factor,
+        _ => return Ok(Vec::new()),
+    };
+    let start = end - WINDOW;
+    let range = TimeRange {
+        from: start - BASELINE,
+        to: end,
+    };
+    let buckets = data
+        .db
+        .fetch_stats_series(&c.owner, &c.key, &range, WINDOW as u32)
+        .await?;
+    let (current, baseline): (Vec<StatsBucket>, Vec<StatsBucket>) =
+        buckets.into_iter().partition(|b| b.time == start);
+    let current = current.into_iter().next().unwrap_or_default();
+
+    let anomalies = detect(factor, &current, &baseline);
+    for alert in anomalies.iter() {
+        let message = describe(*alert, &c.key, &current, &baseline);
+        if !alerts::report(data, &c.key, *alert, &message).await {
+            continue;
+        }
+        data.webhooks.enqueue_anomaly(&c.key, *alert);
+        if data.settings.features.email {
+            if let Err(e) =
+                crate::email::alert::send(data, &c.owner, &c.key, *alert, &message).await
+            {
+                log::error!("Unable to email {} of alert: {e}", c.owner);
+            }
+        }
+    }
+    Ok(anomalies)
+}
+
+pub struct AnomalyDetector {
+    tx: Sender<()>,
+}
+
+impl AnomalyDetector {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// check sitekeys for anomalies in the last complete window at `now` and
+    /// return the number of anomalies detected
+    pub async fn detect_all(data: &AppData, now: i64) -> ServiceResult<usize> {
+        let end = now - now.rem_euclid(WINDOW);
+        let mut detected = 0;
+        for c in data.db.get_captchas_with_anomaly_alerts().await? {
+            match check(data, &c, end).await {
+                Ok(anomalies) => detected += anomalies.len(),
+                Err(e) => {
+                    log::error!("Unable to check sitekey {} for anomalies: {e}", c.key)
+                }
+            }
+        }
+        Ok(detected)
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs.register(ANOMALY_JOB, WINDOW as u64);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                for _ in 0..WINDOW {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::detect_all(&data, started.unix_timestamp())
+                    .await
+                    .map(|_| ());
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while detecting anomalies: {:?}", err);
+                }
+                data.jobs
+                    .finished(ANOMALY_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(config_fetches: u64, solves: u64, confirms: u64) -> StatsBucket {
+        StatsBucket {
+            time: 0,
+            config_fetches,
+            solves,
+            confirms,
+        }
+    }
+
+    #[test]
+    fn detect_works() {
+        // 10 fetches, 8 solves and 8 confirms a window
+        let windows = (BASELINE / WINDOW) as u64;
+        let baseline = [bucket(10 * windows, 8 * windows, 8 * windows)];
+
+        assert!(detect(3, &bucket(30, 24, 24), &baseline).is_empty());
+        assert_eq!(
+            detect(3, &bucket(31, 24, 24), &baseline),
+            vec![Alert::FetchFlood]
+        );
+        assert_eq!(
+            detect(3, &bucket(100, 25, 25), &baseline),
+            vec![Alert::FetchFlood, Alert::SolveSpike]
+        );
+        assert_eq!(
+            detect(3, &bucket(20, 20, 6), &baseline),
+            vec![Alert::ConfirmDrop]
+        );
+        assert!(detect(3, &bucket(20, 20, 7), &baseline).is_empty());
+        assert!(detect(0, &bucket(100, 100, 0), &baseline).is_empty());
+    }
+
+    #[test]
+    fn quiet_sitekeys_need_min_events() {
+        // no traffic before
+        assert!(detect(3, &bucket(MIN_EVENTS - 1, 0, 0), &[]).is_empty());
+        assert_eq!(
+            detect(3, &bucket(MIN_EVENTS, MIN_EVENTS, 0), &[]),
+            vec![Alert::FetchFlood, Alert::SolveSpike]
+        );
+    }
+}
@@ -4,3 +4,4 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 pub mod v1;
+pub mod v2;
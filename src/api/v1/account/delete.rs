@@ -22,6 +22,7 @@ pub async fn delete_account(
     use argon2_creds::Config;
 
     let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
 
     let hash = data
         .db
@@ -29,6 +30,7 @@ pub async fn delete_account(
         .await?;
 
     if Config::verify(&hash.hash, &payload.password)? {
+        super::totp::runners::verify(&data, &username, payload.totp.as_deref()).await?;
         runners::delete_user(&username, &data).await?;
         id.forget();
         Ok(HttpResponse::Ok())
@@ -42,7 +44,9 @@ pub mod runners {
     use super::*;
 
     pub async fn delete_user(name: &str, data: &AppData) -> ServiceResult<()> {
+        crate::api::v1::orgs::runners::leave_all(data, name).await?;
         data.db.delete_user(name).await?;
+        data.maintenance.record_deletion();
         Ok(())
     }
 }
@@ -0,0 +1,139 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Stats digest subscription. See [crate::digest]
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::DigestFrequency;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Digest {
+        pub get: &'static str,
+        pub update: &'static str,
+    }
+
+    impl Digest {
+        pub const fn new() -> Self {
+            Self {
+                get: "/api/v1/account/digest/get",
+                update: "/api/v1/account/digest/update",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_digest);
+    cfg.service(update_digest);
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct DigestPreference {
+    /// how often digests are sent; they aren't sent when unset
+    pub frequency: Option<DigestFrequency>,
+}
+
+/// get stats digest subscription of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.account.digest.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn get_digest(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let frequency = data.db.get_digest_frequency(&username).await?;
+    Ok(HttpResponse::Ok().json(DigestPreference { frequency }))
+}
+
+/// subscribe to stats digests, change their frequency or unsubscribe
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.digest.update",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn update_digest(
+    payload: web::Json<DigestPreference>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
+    match payload.frequency {
+        Some(frequency) => {
+            if data.mailer.is_none() {
+                return Err(ServiceError::FeatureDisabled);
+            }
+            data.db.set_digest_frequency(&username, frequency).await?;
+        }
+        None => data.db.delete_digest_subscription(&username).await?,
+    }
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn digest_subscription_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        digest_subscription_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn digest_subscription_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        digest_subscription_works(data).await;
+    }
+
+    pub async fn digest_subscription_works(data: ArcData) {
+        const NAME: &str = "digestsubuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "digestsubuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let get = |cookies| async {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(V1_API_ROUTES.account.digest.get)
+                    .cookie(cookies)
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let pref: DigestPreference = test::read_body_json(resp).await;
+            pref.frequency
+        };
+        assert_eq!(get(cookies.clone()).await, None);
+
+        for frequency in [Some(DigestFrequency::Monthly), None] {
+            let resp = test::call_service(
+                &app,
+                post_request!(
+                    &DigestPreference { frequency },
+                    V1_API_ROUTES.account.digest.update
+                )
+                .cookie(cookies.clone())
+                .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(get(cookies.clone()).await, frequency);
+        }
+    }
+}
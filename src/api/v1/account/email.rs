@@ -9,6 +9,7 @@ use db_core::UpdateEmail;
 use serde::{Deserialize, Serialize};
 
 use super::{AccountCheckPayload, AccountCheckResp};
+use crate::email::{change, verification};
 use crate::errors::*;
 use crate::AppData;
 
@@ -40,8 +41,23 @@ async fn set_email(
     data: AppData,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
 
-    data.creds.email(&payload.email)?;
+    crate::api::v1::auth::runners::email(&data, &payload.email)?;
+
+    // confirmed from the new address, and cancellable from the current one,
+    // when emails can be sent
+    if data.mailer.is_some() {
+        let current = data.db.get_email(&username).await?;
+        if current.as_deref() == Some(payload.email.as_str()) {
+            return Ok(HttpResponse::Ok());
+        }
+        if data.db.email_exists(&payload.email).await? {
+            return Err(ServiceError::EmailTaken);
+        }
+        change::request(&data, &username, &payload.email).await?;
+        return Ok(HttpResponse::Ok());
+    }
 
     let update_email = UpdateEmail {
         username: &username,
@@ -49,11 +65,44 @@ async fn set_email(
     };
 
     data.db.update_email(&update_email).await?;
+    data.db.set_email_verified(&username, false).await?;
+    if data.settings.require_email_verification {
+        if let Err(e) = verification::send(&data, &username, &payload.email).await {
+            log::error!("Unable to send verification email to {username}: {e}");
+        }
+    }
+
+    Ok(HttpResponse::Ok())
+}
+
+/// (re)send verification link to the user's email address
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.verify_email",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn resend_verification(
+    id: Identity,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
+    if data.mailer.is_none() {
+        return Err(ServiceError::FeatureDisabled);
+    }
 
+    let email = data
+        .db
+        .get_email(&username)
+        .await?
+        .ok_or(ServiceError::NotAnEmail)?;
+    if !data.db.is_email_verified(&username).await? {
+        verification::send(&data, &username, &email).await?;
+    }
     Ok(HttpResponse::Ok())
 }
 
 pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(email_exists);
     cfg.service(set_email);
+    cfg.service(resend_verification);
 }
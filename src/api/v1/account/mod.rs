@@ -6,11 +6,15 @@
 use serde::{Deserialize, Serialize};
 
 pub mod delete;
+pub mod digest;
 pub mod email;
 pub mod password;
 pub mod secret;
+pub mod sessions;
 #[cfg(test)]
 pub mod test;
+pub mod tokens;
+pub mod totp;
 pub mod username;
 
 pub use super::auth;
@@ -23,10 +27,15 @@ pub mod routes {
         pub email_exists: &'static str,
         pub get_secret: &'static str,
         pub update_email: &'static str,
+        pub verify_email: &'static str,
         pub update_password: &'static str,
         pub update_secret: &'static str,
         pub username_exists: &'static str,
         pub update_username: &'static str,
+        pub totp: super::totp::routes::Totp,
+        pub sessions: super::sessions::routes::Sessions,
+        pub tokens: super::tokens::routes::Tokens,
+        pub digest: super::digest::routes::Digest,
     }
 
     impl Account {
@@ -38,16 +47,22 @@ pub mod routes {
             let username_exists = "/api/v1/account/username/exists";
             let update_username = "/api/v1/account/username/update";
             let update_email = "/api/v1/account/email/update";
+            let verify_email = "/api/v1/account/email/verify";
             let update_password = "/api/v1/account/password/update";
             Account {
                 delete,
                 email_exists,
                 get_secret,
                 update_email,
+                verify_email,
                 update_password,
                 update_secret,
                 username_exists,
                 update_username,
+                totp: super::totp::routes::Totp::new(),
+                sessions: super::sessions::routes::Sessions::new(),
+                tokens: super::tokens::routes::Tokens::new(),
+                digest: super::digest::routes::Digest::new(),
             }
         }
     }
@@ -69,4 +84,8 @@ pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     username::services(cfg);
     secret::services(cfg);
     password::services(cfg);
+    totp::services(cfg);
+    sessions::services(cfg);
+    tokens::services(cfg);
+    digest::services(cfg);
 }
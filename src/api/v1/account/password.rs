@@ -67,6 +67,7 @@ async fn update_user_password(
     }
 
     let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
 
     // TODO: verify behavior when account is not found
     let res = data.db.get_password(&Login::Username(&username)).await?;
@@ -4,10 +4,13 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_identity::Identity;
-use actix_web::{HttpResponse, Responder};
+use actix_web::{web, HttpResponse, Responder};
 use db_core::prelude::*;
 
+use super::auth::runners::Password;
+use super::totp;
 use crate::api::v1::mcaptcha::get_random;
+use crate::email::security;
 use crate::errors::*;
 use crate::AppData;
 
@@ -27,9 +30,26 @@ async fn get_secret(id: Identity, data: AppData) -> ServiceResult<impl Responder
 )]
 async fn update_user_secret(
     id: Identity,
+    payload: Option<web::Json<Password>>,
     data: AppData,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
+
+    // sudo mode: accounts with two-factor authentication must re-verify
+    if totp::runners::is_enabled(&data, &username).await? {
+        use argon2_creds::Config;
+
+        let payload = payload.ok_or(ServiceError::TotpRequired)?;
+        let hash = data
+            .db
+            .get_password(&db_core::Login::Username(&username))
+            .await?;
+        if !Config::verify(&hash.hash, &payload.password)? {
+            return Err(ServiceError::WrongPassword);
+        }
+        totp::runners::verify(&data, &username, payload.totp.as_deref()).await?;
+    }
 
     let mut secret;
 
@@ -43,6 +63,10 @@ async fn update_user_secret(
         }
     }
 
+    let event = security::SecurityEvent::SecretRotated;
+    if let Err(e) = security::send(&data, &username, &event).await {
+        log::error!("Unable to send security alert to {username}: {e}");
+    }
     Ok(HttpResponse::Ok())
 }
 
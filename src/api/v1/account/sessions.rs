@@ -0,0 +1,420 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! List and revoke sign-in sessions
+//!
+//! Sessions are identified by a hash of their identifier, so that the
+//! identifier, which is what the session cookie holds, is never exposed.
+use actix_identity::Identity;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::*;
+use crate::sessions::CurrentSession;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Sessions {
+        pub list: &'static str,
+        pub revoke: &'static str,
+    }
+
+    impl Sessions {
+        pub const fn new() -> Self {
+            Self {
+                list: "/api/v1/account/sessions",
+                revoke: "/api/v1/account/sessions/revoke",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(revoke);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// handle with which the session can be revoked
+    pub id: String,
+    /// device, as described by its user agent
+    pub device: String,
+    pub user_agent: String,
+    /// IP address from which the user signed in
+    pub ip: String,
+    /// time of sign-in
+    pub created_at: i64,
+    /// time of last activity
+    pub last_active: i64,
+    /// session of the current request
+    pub current: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevokeSession {
+    /// session to revoke; all sessions are revoked when unset
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+pub mod runners {
+    use super::*;
+
+    /// handle of a session
+    pub fn handle(session: &str) -> String {
+        hex::encode(Sha256::digest(session.as_bytes()))
+    }
+
+    /// human readable description of the device that a user agent belongs to
+    pub fn device(user_agent: &str) -> String {
+        const BROWSERS: [(&str, &str); 6] = [
+            ("Edg/", "Edge"),
+            ("OPR/", "Opera"),
+            ("Firefox/", "Firefox"),
+            ("Chrome/", "Chrome"),
+            ("Safari/", "Safari"),
+            ("curl/", "curl"),
+        ];
+        const SYSTEMS: [(&str, &str); 6] = [
+            ("Android", "Android"),
+            ("iPhone", "iOS"),
+            ("iPad", "iPadOS"),
+            ("Windows", "Windows"),
+            ("Mac OS X", "macOS"),
+            ("Linux", "Linux"),
+        ];
+        let find = |list: &[(&str, &'static str)]| {
+            list.iter()
+                .find(|(needle, _)| user_agent.contains(needle))
+                .map(|(_, name)| *name)
+        };
+
+        match (find(&BROWSERS), find(&SYSTEMS)) {
+            (Some(browser), Some(system)) => format!("{browser} on {system}"),
+            (Some(name), None) | (None, Some(name)) => name.to_string(),
+            (None, None) => "Unknown device".into(),
+        }
+    }
+
+    /// sessions of a user, most recently active first
+    pub async fn list(
+        data: &AppData,
+        username: &str,
+        current: Option<&str>,
+    ) -> ServiceResult<Vec<SessionInfo>> {
+        let current = current.map(handle);
+        let sessions = data.db.get_user_sessions(username).await?;
+        Ok(sessions
+            .into_iter()
+            .map(|s| {
+                let id = handle(&s.id);
+                SessionInfo {
+                    current: current.as_ref() == Some(&id),
+                    device: device(&s.user_agent),
+                    id,
+                    user_agent: s.user_agent,
+                    ip: s.ip,
+                    created_at: s.created_at,
+                    last_active: s.last_active,
+                }
+            })
+            .collect())
+    }
+
+    /// revoke session of a user, or all of their sessions when `id` is unset
+    pub async fn revoke(
+        data: &AppData,
+        username: &str,
+        id: Option<&str>,
+    ) -> ServiceResult<()> {
+        let id = match id {
+            Some(id) => id,
+            None => {
+                data.db.delete_user_sessions(username).await?;
+                return Ok(());
+            }
+        };
+        let session = data
+            .db
+            .get_user_sessions(username)
+            .await?
+            .into_iter()
+            .find(|s| handle(&s.id) == id)
+            .ok_or(ServiceError::SessionNotFound)?;
+        data.db.delete_session(username, &session.id).await?;
+        Ok(())
+    }
+}
+
+/// list sessions of the signed-in user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.account.sessions.list",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn list(
+    req: HttpRequest,
+    id: Identity,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let current = CurrentSession::get(&req);
+    let mut sessions = runners::list(&data, &username, current.as_deref()).await?;
+    // the demo account is shared, so its visitors only see their own session
+    if crate::demo::is_demo_user(&data, &username) {
+        sessions.retain(|s| s.current);
+    }
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// revoke session of the signed-in user, or sign out everywhere
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.sessions.revoke",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn revoke(
+    req: HttpRequest,
+    id: Identity,
+    payload: web::Json<RevokeSession>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let current = CurrentSession::get(&req).map(|s| runners::handle(&s));
+    // and can only sign themselves out
+    if crate::demo::is_demo_user(&data, &username)
+        && (payload.id.is_none() || payload.id != current)
+    {
+        return Err(ServiceError::DemoUserRestricted);
+    }
+    runners::revoke(&data, &username, payload.id.as_deref()).await?;
+
+    if payload.id.is_none() || payload.id == current {
+        id.forget();
+    }
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn device_works() {
+        const FIREFOX: &str =
+            "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0";
+        assert_eq!(runners::device(FIREFOX), "Firefox on Linux");
+        assert_eq!(runners::device("curl/8.5.0"), "curl");
+        assert_eq!(runners::device(""), "Unknown device");
+    }
+
+    #[actix_rt::test]
+    async fn sessions_work_pg() {
+        let data = crate::tests::pg::get_data().await;
+        sessions_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn sessions_work_maria() {
+        let data = crate::tests::maria::get_data().await;
+        sessions_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn demo_sessions_are_restricted_pg() {
+        let data = crate::tests::pg::get_data().await;
+        demo_sessions_are_restricted(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn demo_sessions_are_restricted_maria() {
+        let data = crate::tests::maria::get_data().await;
+        demo_sessions_are_restricted(data).await;
+    }
+
+    /// visitors of the demo account can't see, or revoke, each other's sessions
+    async fn demo_sessions_are_restricted(data: ArcData) {
+        use crate::demo::{DemoUser, DEMO_PASSWORD, DEMO_USER};
+
+        let mut settings = data.settings.clone();
+        settings.allow_demo = true;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        delete_user(data, DEMO_USER).await;
+        DemoUser::register_demo_user(&AppData::new(data.clone()))
+            .await
+            .unwrap();
+        let (_, signin_resp) = signin(data, DEMO_USER, DEMO_PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let (_, other_resp) = signin(data, DEMO_USER, DEMO_PASSWORD).await;
+        let other_cookies = get_cookie!(other_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let sessions: Vec<SessionInfo> = test::read_body_json(resp).await;
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions[0].current);
+        let own = sessions[0].id.clone();
+
+        let other = data
+            .db
+            .get_user_sessions(DEMO_USER)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| runners::handle(&s.id))
+            .find(|id| id != &own)
+            .unwrap();
+        for id in [Some(other), None] {
+            let resp = test::call_service(
+                &app,
+                post_request!(
+                    &RevokeSession { id },
+                    V1_API_ROUTES.account.sessions.revoke
+                )
+                .cookie(cookies.clone())
+                .to_request(),
+            )
+            .await;
+            assert_eq!(
+                resp.status(),
+                ServiceError::DemoUserRestricted.status_code()
+            );
+        }
+
+        // signing out works
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &RevokeSession { id: Some(own) },
+                V1_API_ROUTES.account.sessions.revoke
+            )
+            .cookie(cookies)
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .cookie(other_cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        delete_user(data, DEMO_USER).await;
+    }
+
+    pub async fn sessions_work(data: ArcData) {
+        const NAME: &str = "sessionsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "sessionsuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let (_, other_resp) = signin(data, NAME, PASSWORD).await;
+        let other_cookies = get_cookie!(other_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .insert_header((header::USER_AGENT, "curl/8.5.0"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let sessions: Vec<SessionInfo> = test::read_body_json(resp).await;
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions.iter().filter(|s| s.current).count(), 1);
+        let other = sessions.iter().find(|s| !s.current).unwrap();
+
+        // revoke other session
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &RevokeSession {
+                    id: Some(other.id.clone())
+                },
+                V1_API_ROUTES.account.sessions.revoke
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .cookie(other_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            V1_API_ROUTES.account.sessions.revoke,
+            &RevokeSession {
+                id: Some("nonexistent".into()),
+            },
+            ServiceError::SessionNotFound,
+        )
+        .await;
+
+        // sign out everywhere
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &RevokeSession { id: None },
+                V1_API_ROUTES.account.sessions.revoke
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.get_user_sessions(NAME).await.unwrap().is_empty());
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+    }
+}
@@ -169,6 +169,7 @@ pub async fn email_udpate_password_validation_del_userworks(data: ArcData) {
     // wrong password while deleting account
     let mut payload = Password {
         password: NAME.into(),
+        totp: None,
     };
     bad_post_req_test(
         data,
@@ -0,0 +1,134 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Create, list and revoke API tokens
+//!
+//! Tokens are only shown once, when they are created.
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::ApiToken;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api_tokens::{self, Scope, MAX_TOKEN_NAME_LEN};
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Tokens {
+        pub list: &'static str,
+        pub create: &'static str,
+        pub revoke: &'static str,
+    }
+
+    impl Tokens {
+        pub const fn new() -> Self {
+            Self {
+                list: "/api/v1/account/tokens",
+                create: "/api/v1/account/tokens/create",
+                revoke: "/api/v1/account/tokens/revoke",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(create);
+    cfg.service(revoke);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateToken {
+    pub name: String,
+    pub scope: Scope,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CreatedToken {
+    /// the token; it can't be retrieved again
+    pub token: String,
+    pub name: String,
+    pub scope: Scope,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevokeToken {
+    pub id: i32,
+}
+
+pub mod runners {
+    use super::*;
+
+    /// create API token of a user
+    pub async fn create(
+        data: &AppData,
+        username: &str,
+        payload: &CreateToken,
+    ) -> ServiceResult<CreatedToken> {
+        crate::demo::restrict_demo_user(data, username)?;
+        let name = payload.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LEN {
+            return Err(ServiceError::InvalidApiTokenName);
+        }
+
+        let token = api_tokens::generate();
+        let token_hash = api_tokens::hash(&token);
+        let p = db_core::AddApiToken {
+            username,
+            name,
+            token_hash: &token_hash,
+            scope: payload.scope.as_str(),
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        data.db.add_api_token(&p).await?;
+        Ok(CreatedToken {
+            token,
+            name: name.into(),
+            scope: payload.scope,
+        })
+    }
+}
+
+/// list API tokens of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.account.tokens.list",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn list(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let tokens: Vec<ApiToken> = data.db.get_api_tokens(&username).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// create an API token
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.tokens.create",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn create(
+    payload: web::Json<CreateToken>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let token = runners::create(&data, &username, &payload).await?;
+    Ok(HttpResponse::Ok().json(token))
+}
+
+/// revoke an API token
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.tokens.revoke",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn revoke(
+    payload: web::Json<RevokeToken>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    data.db.delete_api_token(&username, payload.id).await?;
+    Ok(HttpResponse::Ok())
+}
@@ -0,0 +1,408 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! TOTP two-factor authentication
+//!
+//! Enrollment is a two step process: [enroll] generates a secret and a
+//! provisioning URI that authenticator apps can import(usually as a QR code),
+//! and [confirm] enables two-factor authentication once the user proves that
+//! their authenticator works. Confirmation returns single-use recovery codes,
+//! which are stored hashed.
+//!
+//! Once enabled, sign-in and sensitive actions(account deletion, secret
+//! rotation, sitekey deletion) require a TOTP or a recovery code. Codes are
+//! accepted once: the time step of the last accepted code is stored, and codes
+//! of that step or earlier ones are rejected.
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::errors::DBError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::time::OffsetDateTime;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use super::auth::runners::Password;
+use super::mcaptcha::get_random;
+use crate::errors::*;
+use crate::AppData;
+
+/// number of recovery codes generated on enrollment
+pub const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LEN: usize = 12;
+
+pub mod routes {
+    pub struct Totp {
+        pub enroll: &'static str,
+        pub confirm: &'static str,
+        pub disable: &'static str,
+    }
+
+    impl Totp {
+        pub const fn new() -> Self {
+            Self {
+                enroll: "/api/v1/account/totp/enroll",
+                confirm: "/api/v1/account/totp/confirm",
+                disable: "/api/v1/account/totp/disable",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(enroll);
+    cfg.service(confirm);
+    cfg.service(disable);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Enrollment {
+    /// base32 encoded secret, for manual entry
+    pub secret: String,
+    /// otpauth:// provisioning URI
+    pub uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecoveryCodes {
+    pub codes: Vec<String>,
+}
+
+pub mod runners {
+    use super::*;
+
+    pub fn hash_recovery_code(code: &str) -> String {
+        hex::encode(Sha256::digest(code.trim().as_bytes()))
+    }
+
+    pub fn get_totp(username: &str, secret: &str) -> ServiceResult<TOTP> {
+        let secret = Secret::Encoded(secret.to_string())
+            .to_bytes()
+            .map_err(|_| ServiceError::InternalServerError)?;
+        TOTP::new(
+            Algorithm::SHA1,
+            6,
+            1,
+            30,
+            secret,
+            Some(crate::pages::NAME.to_string()),
+            username.to_string(),
+        )
+        .map_err(|_| ServiceError::InternalServerError)
+    }
+
+    /// time step that `code` belongs to, if it is valid at unix timestamp
+    /// `time`. Codes of the steps next to the current one are valid too, to
+    /// allow for clock drift
+    pub fn code_step(totp: &TOTP, code: &str, time: u64) -> Option<u64> {
+        let code = code.trim();
+        let current = time / totp.step;
+        let skew = totp.skew as u64;
+        (current.saturating_sub(skew)..=current + skew)
+            .find(|step| totp.generate(step * totp.step) == code)
+    }
+
+    /// check TOTP code of a user and use up its time step, so that neither it
+    /// nor codes of earlier steps are accepted again
+    pub async fn use_code(
+        data: &AppData,
+        username: &str,
+        totp: &TOTP,
+        code: &str,
+    ) -> ServiceResult<bool> {
+        let now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+        match code_step(totp, code, now) {
+            Some(step) => Ok(data.db.use_totp_step(username, step as i64).await?),
+            None => Ok(false),
+        }
+    }
+
+    /// is two-factor authentication enabled for the user
+    pub async fn is_enabled(data: &AppData, username: &str) -> ServiceResult<bool> {
+        match data.db.get_totp(username).await {
+            Ok(totp) => Ok(totp.enabled),
+            Err(DBError::TotpNotFound) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// verify second factor of a user. Succeeds without a code when two-factor
+    /// authentication isn't enabled. Recovery codes are consumed on use
+    pub async fn verify(
+        data: &AppData,
+        username: &str,
+        code: Option<&str>,
+    ) -> ServiceResult<()> {
+        let totp = match data.db.get_totp(username).await {
+            Ok(totp) if totp.enabled => totp,
+            Ok(_) | Err(DBError::TotpNotFound) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let code = code.ok_or(ServiceError::TotpRequired)?;
+
+        if use_code(data, username, &get_totp(username, &totp.secret)?, code).await? {
+            return Ok(());
+        }
+        if data
+            .db
+            .use_recovery_code(username, &hash_recovery_code(code))
+            .await?
+        {
+            return Ok(());
+        }
+        Err(ServiceError::WrongTotp)
+    }
+}
+
+/// start enrollment: generate secret and provisioning URI
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.totp.enroll",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn enroll(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
+    if runners::is_enabled(&data, &username).await? {
+        return Err(ServiceError::TotpAlreadyEnabled);
+    }
+
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let totp = runners::get_totp(&username, &secret)?;
+    data.db.set_totp(&username, &secret).await?;
+    Ok(HttpResponse::Ok().json(Enrollment {
+        secret,
+        uri: totp.get_url(),
+    }))
+}
+
+/// finish enrollment: enable two-factor authentication and issue recovery codes
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.totp.confirm",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn confirm(
+    payload: web::Json<TotpCode>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let totp = data.db.get_totp(&username).await?;
+    if totp.enabled {
+        return Err(ServiceError::TotpAlreadyEnabled);
+    }
+    let t = runners::get_totp(&username, &totp.secret)?;
+    if !runners::use_code(&data, &username, &t, &payload.code).await? {
+        return Err(ServiceError::WrongTotp);
+    }
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| get_random(RECOVERY_CODE_LEN))
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| runners::hash_recovery_code(c))
+        .collect();
+    data.db.enable_totp(&username, &hashes).await?;
+    Ok(HttpResponse::Ok().json(RecoveryCodes { codes }))
+}
+
+/// disable two-factor authentication; requires password and second factor
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.account.totp.disable",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn disable(
+    payload: web::Json<Password>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    use argon2_creds::Config;
+
+    let username = id.identity().unwrap();
+    let hash = data
+        .db
+        .get_password(&db_core::Login::Username(&username))
+        .await?;
+    if !Config::verify(&hash.hash, &payload.password)? {
+        return Err(ServiceError::WrongPassword);
+    }
+    runners::verify(&data, &username, payload.totp.as_deref()).await?;
+    data.db.delete_totp(&username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::auth::runners::Login;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn totp_works_pg() {
+        let data = pg::get_data().await;
+        totp_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn totp_works_maria() {
+        let data = maria::get_data().await;
+        totp_works(data).await;
+    }
+
+    async fn totp_works(data: ArcData) {
+        const NAME: &str = "totpuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "totpuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.account.totp;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(routes.enroll)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let enrollment: Enrollment = test::read_body_json(resp).await;
+        assert!(enrollment.uri.starts_with("otpauth://totp/"));
+
+        // not enabled until confirmed
+        signin(data, NAME, PASSWORD).await;
+
+        let wrong = TotpCode {
+            code: "000000".into(),
+        };
+        let app_data = actix_web::web::Data::new(data.clone());
+        let totp = runners::get_totp(NAME, &enrollment.secret).unwrap();
+        let step = OffsetDateTime::now_utc().unix_timestamp() as u64 / totp.step;
+        let code = TotpCode {
+            code: totp.generate(step * totp.step),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.confirm,
+            &wrong,
+            ServiceError::WrongTotp,
+        )
+        .await;
+        let resp = test::call_service(
+            &app,
+            post_request!(&code, routes.confirm)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let recovery: RecoveryCodes = test::read_body_json(resp).await;
+        assert_eq!(recovery.codes.len(), RECOVERY_CODES);
+
+        // sign-in requires second factor
+        let mut creds = Login {
+            login: NAME.into(),
+            password: PASSWORD.into(),
+            totp: None,
+            challenge: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.error, format!("{}", ServiceError::TotpRequired));
+
+        // codes are accepted once, and so are codes of earlier time steps
+        creds.totp = Some(code.code.clone());
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.error, format!("{}", ServiceError::WrongTotp));
+
+        creds.totp = Some(totp.generate((step + 1) * totp.step));
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            runners::code_step(&totp, &code.code, step * totp.step),
+            Some(step)
+        );
+        assert_eq!(
+            runners::code_step(&totp, &code.code, (step + 2) * totp.step),
+            None
+        );
+
+        // recovery codes are single use
+        creds.totp = Some(recovery.codes[0].clone());
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // sudo mode: secret rotation requires second factor
+        let mut payload = Password {
+            password: PASSWORD.into(),
+            totp: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.account.update_secret)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        payload.totp = Some(recovery.codes[1].clone());
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.disable)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!runners::is_enabled(&app_data, NAME).await.unwrap());
+        signin(data, NAME, PASSWORD).await;
+    }
+}
@@ -49,8 +49,10 @@ async fn set_username(
     data: AppData,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
 
-    let processed_uname = data.creds.username(&payload.username)?;
+    let processed_uname =
+        crate::api::v1::auth::runners::username(&data, &payload.username)?;
 
     data.db.update_username(&username, &processed_uname).await?;
 
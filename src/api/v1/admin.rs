@@ -0,0 +1,1846 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Instance administration. Access is restricted to administrators by
+//! [crate::admin::AdminGate]
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use libmcaptcha::master::messages::RemoveCaptcha;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use db_core::{OutboxEmail, UserQuota};
+
+use crate::api::v1::notifications::add::MAX_HEADING_LEN;
+use crate::api::v1::notifications::encrypted::{EncryptedMessage, NotificationKey};
+use crate::data::PoWTuning;
+use crate::email::queue::subject;
+use crate::email::registration;
+use crate::errors::*;
+use crate::quotas::{self, Quota, Usage};
+use crate::survey::NodeHealth;
+use crate::AppData;
+
+/// number of users listed per page
+pub const USERS_PER_PAGE: usize = 50;
+
+pub mod routes {
+    pub struct Admin {
+        pub users: &'static str,
+        pub stats: &'static str,
+        pub delete_user: &'static str,
+        pub notification_key: &'static str,
+        pub notify: &'static str,
+        pub quotas_get: &'static str,
+        pub quotas_set: &'static str,
+        pub residency: &'static str,
+        pub registrations: &'static str,
+        pub approve: &'static str,
+        pub reject: &'static str,
+        pub suspend: &'static str,
+        pub unsuspend: &'static str,
+        pub banned_domains: &'static str,
+        pub ban_domain: &'static str,
+        pub unban_domain: &'static str,
+        pub vanity_key: &'static str,
+        pub priority: &'static str,
+        pub config: &'static str,
+        pub survey_nodes: &'static str,
+        pub pow: &'static str,
+        pub pow_tune: &'static str,
+        pub dead_letters: &'static str,
+        pub retry_dead_letter: &'static str,
+    }
+
+    impl Admin {
+        pub const fn new() -> Self {
+            Self {
+                users: "/api/v1/admin/users",
+                stats: "/api/v1/admin/stats",
+                delete_user: "/api/v1/admin/users/delete",
+                notification_key: "/api/v1/admin/notifications/key",
+                notify: "/api/v1/admin/notifications/add",
+                quotas_get: "/api/v1/admin/quotas/get",
+                quotas_set: "/api/v1/admin/quotas/set",
+                residency: "/api/v1/admin/users/residency",
+                registrations: "/api/v1/admin/registrations",
+                approve: "/api/v1/admin/registrations/approve",
+                reject: "/api/v1/admin/registrations/reject",
+                suspend: "/api/v1/admin/users/suspend",
+                unsuspend: "/api/v1/admin/users/unsuspend",
+                banned_domains: "/api/v1/admin/bans/domains",
+                ban_domain: "/api/v1/admin/bans/domains/add",
+                unban_domain: "/api/v1/admin/bans/domains/delete",
+                vanity_key: "/api/v1/admin/sitekeys/vanity",
+                priority: "/api/v1/admin/sitekeys/priority",
+                config: "/api/v1/admin/config",
+                survey_nodes: "/api/v1/admin/survey/nodes",
+                pow: "/api/v1/admin/pow",
+                pow_tune: "/api/v1/admin/pow/tune",
+                dead_letters: "/api/v1/admin/mail/dead",
+                retry_dead_letter: "/api/v1/admin/mail/dead/retry",
+            }
+        }
+
+        pub fn get_users_route(&self, page: usize) -> String {
+            format!("{}?page={}", self.users, page)
+        }
+
+        pub fn get_registrations_route(&self, page: usize) -> String {
+            format!("{}?page={}", self.registrations, page)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(users);
+    cfg.service(stats);
+    cfg.service(delete_user);
+    cfg.service(notification_key);
+    cfg.service(notify);
+    cfg.service(quotas_get);
+    cfg.service(quotas_set);
+    cfg.service(residency);
+    cfg.service(registrations);
+    cfg.service(approve);
+    cfg.service(reject);
+    cfg.service(suspend);
+    cfg.service(unsuspend);
+    cfg.service(banned_domains);
+    cfg.service(ban_domain);
+    cfg.service(unban_domain);
+    cfg.service(vanity_key);
+    cfg.service(priority);
+    cfg.service(config);
+    cfg.service(survey_nodes);
+    cfg.service(pow);
+    cfg.service(pow_tune);
+    cfg.service(dead_letters);
+    cfg.service(retry_dead_letter);
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UsersQuery {
+    /// zero-indexed page number
+    #[serde(default)]
+    pub page: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteUser {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Registration {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Suspension {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// email domain that is banned from registration, along with its subdomains
+pub struct BannedDomain {
+    pub domain: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UserNotificationKey {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// notification whose message was encrypted to the receiver's notification
+/// key by the administrator
+pub struct EncryptedNotification {
+    pub to: String,
+    /// stored in plaintext
+    pub heading: String,
+    pub message: EncryptedMessage,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QuotaUser {
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// quota overrides of a user. Unset quotas follow the instance
+pub struct SetUserQuota {
+    pub username: String,
+    #[serde(flatten)]
+    pub quota: UserQuota,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UserQuotaStatus {
+    pub overrides: UserQuota,
+    /// quotas in effect
+    pub quota: Quota,
+    pub captchas: Usage,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// data residency region of a user; unset to clear it
+pub struct SetResidency {
+    pub username: String,
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+/// list users of the instance
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.users",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn users(
+    query: web::Query<UsersQuery>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let users = data.db.get_users(query.page, USERS_PER_PAGE).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// instance-wide counts of users and captchas, without the demo account
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.stats",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn stats(data: AppData) -> ServiceResult<impl Responder> {
+    let stats = crate::demo::instance_stats(&data).await?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+/// effective configuration of the instance, with secrets redacted
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.config",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn config(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(&data.settings.effective)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// status of a configured mCaptcha/survey node
+pub struct SurveyNodeStatus {
+    pub url: String,
+    pub region: Option<String>,
+    /// whether the node uploaded its secret to this instance
+    pub registered: bool,
+    #[serde(flatten)]
+    pub health: NodeHealth,
+}
+
+/// reachability and upload status of the configured survey nodes
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.survey_nodes",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn survey_nodes(data: AppData) -> impl Responder {
+    let nodes: Vec<SurveyNodeStatus> = data
+        .settings
+        .survey
+        .iter()
+        .flat_map(|survey| survey.nodes.iter())
+        .map(|node| {
+            let url = node.url.to_string();
+            SurveyNodeStatus {
+                registered: data.survey_secrets.get(&url).is_some(),
+                health: data.survey_health.get(&url),
+                region: node.region.clone(),
+                url,
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(nodes)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+/// tuning and utilization of PoW verification
+pub struct PoWStatus {
+    #[serde(flatten)]
+    pub tuning: PoWTuning,
+    /// number of PoW verifications in progress
+    pub inflight: usize,
+    /// verifications in progress against the queue length, from 0
+    pub utilization: f64,
+    /// verifications of high priority sitekeys waiting for runners
+    pub waiting_high_priority: usize,
+    /// other verifications waiting for runners
+    pub waiting: usize,
+}
+
+impl PoWStatus {
+    fn new(data: &AppData) -> Self {
+        let tuning = data.captcha.tuning();
+        let inflight = data.load.metrics().inflight;
+        let (waiting_high_priority, waiting) = data.captcha.waiting();
+        Self {
+            utilization: inflight as f64 / tuning.queue_length as f64,
+            tuning,
+            inflight,
+            waiting_high_priority,
+            waiting,
+        }
+    }
+}
+
+/// number of PoW runners, length of their queue and their utilization
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.pow",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn pow(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(PoWStatus::new(&data))
+}
+
+/// change the number of PoW runners and the length of their queue, until
+/// the next restart or configuration reload
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.pow_tune",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn pow_tune(
+    payload: web::Json<PoWTuning>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    data.captcha.tune(payload.into_inner())?;
+    log::info!(
+        "Administrator {username} retuned PoW verification: {:?}",
+        data.captcha.tuning()
+    );
+    Ok(HttpResponse::Ok().json(PoWStatus::new(&data)))
+}
+
+/// delete an account, along with its captchas
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.delete_user",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete_user(
+    payload: web::Json<DeleteUser>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::delete_user(&data, &username, &payload.username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get notification key of a user, to encrypt notifications to
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.notification_key",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn notification_key(
+    payload: web::Json<UserNotificationKey>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let key = data.db.get_notification_key(&payload.username).await?;
+    Ok(HttpResponse::Ok().json(NotificationKey { key }))
+}
+
+/// send an encrypted notification to a user
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.notify",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn notify(
+    payload: web::Json<EncryptedNotification>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let username = id.identity().unwrap();
+    if payload.heading.chars().count() > MAX_HEADING_LEN {
+        return Err(ServiceError::NotificationTooLong);
+    }
+    if data.db.get_notification_key(&payload.to).await?.is_none() {
+        return Err(ServiceError::NotificationKeyNotSet);
+    }
+    let message = payload.message.to_message()?;
+    let n = db_core::AddNotification {
+        to: &payload.to,
+        from: &username,
+        heading: &payload.heading,
+        message: &message,
+    };
+    data.db.create_encrypted_notification(&n).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get quotas of a user
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.quotas_get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn quotas_get(
+    payload: web::Json<QuotaUser>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let overrides = data.db.get_user_quota(&payload.username).await?;
+    let quota = quotas::get(&data, &payload.username).await?;
+    let captchas = quotas::captchas(&data, &payload.username).await?;
+    Ok(HttpResponse::Ok().json(UserQuotaStatus {
+        overrides,
+        quota,
+        captchas,
+    }))
+}
+
+/// override quotas of a user
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.quotas_set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn quotas_set(
+    payload: web::Json<SetUserQuota>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    if !data.db.username_exists(&payload.username).await? {
+        return Err(ServiceError::AccountNotFound);
+    }
+    data.db
+        .set_user_quota(&payload.username, &payload.quota)
+        .await?;
+    log::info!(
+        "Administrator {username} set quotas of {}: {:?}",
+        payload.username,
+        payload.quota
+    );
+    Ok(HttpResponse::Ok())
+}
+
+/// tag a user with a data residency region. See [crate::survey]
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.residency",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn residency(
+    payload: web::Json<SetResidency>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    if let Some(region) = payload.region.as_deref() {
+        runners::validate_region(region)?;
+    }
+    data.db
+        .set_residency(&payload.username, payload.region.as_deref())
+        .await?;
+    log::info!(
+        "Administrator {username} set data residency of {} to {:?}",
+        payload.username,
+        payload.region
+    );
+    Ok(HttpResponse::Ok())
+}
+
+/// list registrations that are pending approval
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.registrations",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn registrations(
+    query: web::Query<UsersQuery>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let registrations = data
+        .db
+        .get_pending_registrations(query.page, USERS_PER_PAGE)
+        .await?;
+    Ok(HttpResponse::Ok().json(registrations))
+}
+
+/// approve a registration and notify the applicant
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.approve",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn approve(
+    payload: web::Json<Registration>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::approve(&data, &username, &payload.username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// reject a registration, notify the applicant and delete their account
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.reject",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn reject(
+    payload: web::Json<Registration>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::reject(&data, &username, &payload.username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// suspend an account: the user is signed out and can't sign in, and their
+/// sitekeys answer as configured by `captcha.suspended_behavior`
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.suspend",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn suspend(
+    payload: web::Json<Suspension>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::suspend(&data, &username, &payload.username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// lift the suspension of an account
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.unsuspend",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn unsuspend(
+    payload: web::Json<Suspension>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    data.db.set_suspended(&payload.username, false).await?;
+    log::info!(
+        "Administrator {username} lifted suspension of {}",
+        payload.username
+    );
+    Ok(HttpResponse::Ok())
+}
+
+/// list email domains that are banned from registration
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.banned_domains",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn banned_domains(data: AppData) -> ServiceResult<impl Responder> {
+    let domains: Vec<BannedDomain> = data
+        .db
+        .get_banned_email_domains()
+        .await?
+        .into_iter()
+        .map(|domain| BannedDomain { domain })
+        .collect();
+    Ok(HttpResponse::Ok().json(domains))
+}
+
+/// ban an email domain, and its subdomains, from registration. Existing
+/// accounts are left as they are
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.ban_domain",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn ban_domain(
+    payload: web::Json<BannedDomain>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let domain = runners::email_domain(&payload.domain)?;
+    data.db.ban_email_domain(&domain).await?;
+    log::info!("Administrator {username} banned email domain {domain}");
+    Ok(HttpResponse::Ok())
+}
+
+/// lift the ban on an email domain
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.unban_domain",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn unban_domain(
+    payload: web::Json<BannedDomain>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let domain = runners::email_domain(&payload.domain)?;
+    data.db.unban_email_domain(&domain).await?;
+    log::info!("Administrator {username} lifted ban on email domain {domain}");
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VanityKey {
+    /// current key of the sitekey
+    pub key: String,
+    /// vanity key to give the sitekey
+    pub vanity: String,
+}
+
+/// give a sitekey a vanity key. Sites that embed the sitekey need to switch to
+/// the vanity key
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.vanity_key",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn vanity_key(
+    payload: web::Json<VanityKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    crate::keys::set_vanity(&data, &payload.key, &payload.vanity).await?;
+    log::info!(
+        "Administrator {username} gave sitekey {} vanity key {}",
+        payload.key,
+        payload.vanity
+    );
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SitekeyPriority {
+    pub key: String,
+    /// schedule PoW verifications of the sitekey ahead of others when runners
+    /// are busy
+    pub high_priority: bool,
+}
+
+/// set scheduling priority of PoW verifications of a sitekey. See
+/// [crate::priority]
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.priority",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn priority(
+    payload: web::Json<SitekeyPriority>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    if !data.db.captcha_exists(None, &payload.key).await? {
+        return Err(ServiceError::CaptchaNotFound);
+    }
+    data.db
+        .update_captcha_priority(&payload.key, payload.high_priority)
+        .await?;
+    log::info!(
+        "Administrator {username} set high priority of sitekey {} to {}",
+        payload.key,
+        payload.high_priority
+    );
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// email that was given up on. See [crate::email::queue]
+pub struct DeadLetter {
+    pub id: i32,
+    pub recipients: Vec<String>,
+    pub subject: Option<String>,
+    /// number of attempts at sending the email
+    pub attempts: u32,
+    /// error of the last attempt
+    pub last_error: Option<String>,
+    /// time at which the email was queued
+    pub created_at: i64,
+}
+
+impl From<OutboxEmail> for DeadLetter {
+    fn from(e: OutboxEmail) -> Self {
+        Self {
+            id: e.id,
+            subject: subject(&e.message),
+            recipients: e.recipients,
+            attempts: e.attempts,
+            last_error: e.last_error,
+            created_at: e.created_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RetryDeadLetter {
+    pub id: i32,
+}
+
+/// list emails that couldn't be sent, newest first
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.admin.dead_letters",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn dead_letters(data: AppData) -> ServiceResult<impl Responder> {
+    let letters: Vec<DeadLetter> = data
+        .db
+        .get_dead_letters()
+        .await?
+        .into_iter()
+        .map(DeadLetter::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(letters))
+}
+
+/// put an email that couldn't be sent back in the outbox, to be sent again
+/// with a fresh set of attempts
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.admin.retry_dead_letter",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn retry_dead_letter(
+    payload: web::Json<RetryDeadLetter>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    data.db.retry_dead_letter(payload.id, now).await?;
+    log::info!("Administrator {username} retried email {}", payload.id);
+    Ok(HttpResponse::Ok())
+}
+
+pub mod runners {
+    use super::*;
+
+    /// maximum length of data residency regions
+    const MAX_REGION_LEN: usize = 32;
+
+    /// regions are tags like `eu` or `us-east`: lowercase letters, digits and
+    /// '-'
+    pub fn validate_region(region: &str) -> ServiceResult<()> {
+        if region.is_empty()
+            || region.len() > MAX_REGION_LEN
+            || !region
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err(ServiceError::InvalidResidency);
+        }
+        Ok(())
+    }
+
+    /// delete account `target` on behalf of administrator `admin`.
+    /// Administrators can't be deleted, so that they can't lock each other out
+    pub async fn delete_user(
+        data: &AppData,
+        admin: &str,
+        target: &str,
+    ) -> ServiceResult<()> {
+        if data.db.is_admin(target).await? {
+            return Err(ServiceError::CannotDeleteAdmin);
+        }
+        let captchas = data.db.get_all_user_captchas(target).await?;
+        crate::api::v1::account::delete::runners::delete_user(target, data).await?;
+        log::info!("Administrator {admin} deleted account {target}");
+
+        for captcha in captchas.into_iter() {
+            if let Err(err) = data.captcha.remove(RemoveCaptcha(captcha.key)).await {
+                log::error!("Error while trying to remove captcha from cache {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// maximum length of email domains
+    const MAX_DOMAIN_LEN: usize = 100;
+
+    /// normalize an email domain: lowercase, without a leading '@'. Domains are
+    /// dot-separated labels of letters, digits and '-'
+    pub fn email_domain(domain: &str) -> ServiceResult<String> {
+        let domain = domain.trim().trim_start_matches('@').to_lowercase();
+        if domain.is_empty()
+            || domain.len() > MAX_DOMAIN_LEN
+            || domain.split('.').any(|label| {
+                label.is_empty()
+                    || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+        {
+            return Err(ServiceError::InvalidEmailDomain);
+        }
+        Ok(domain)
+    }
+
+    /// suspend account `target` on behalf of administrator `admin`, and
+    /// revoke its sessions. Administrators can't be suspended, so that they
+    /// can't lock each other out
+    pub async fn suspend(
+        data: &AppData,
+        admin: &str,
+        target: &str,
+    ) -> ServiceResult<()> {
+        if data.db.is_admin(target).await? {
+            return Err(ServiceError::CannotSuspendAdmin);
+        }
+        data.db.set_suspended(target, true).await?;
+        data.db.delete_user_sessions(target).await?;
+        log::info!("Administrator {admin} suspended account {target}");
+        Ok(())
+    }
+
+    async fn require_pending(data: &AppData, target: &str) -> ServiceResult<()> {
+        if !data.db.is_pending_approval(target).await? {
+            return Err(ServiceError::RegistrationNotPending);
+        }
+        Ok(())
+    }
+
+    /// approve the pending registration of `target`. Failing to notify the
+    /// applicant doesn't undo the decision
+    pub async fn approve(
+        data: &AppData,
+        admin: &str,
+        target: &str,
+    ) -> ServiceResult<()> {
+        require_pending(data, target).await?;
+        data.db.set_pending_approval(target, false).await?;
+        log::info!("Administrator {admin} approved registration of {target}");
+        if let Err(e) = registration::notify(data, target, true).await {
+            log::error!("Unable to notify {target} of approved registration: {e}");
+        }
+        Ok(())
+    }
+
+    /// reject the pending registration of `target` and delete their account.
+    /// The applicant is notified before the account, and with it their email
+    /// address, is deleted
+    pub async fn reject(data: &AppData, admin: &str, target: &str) -> ServiceResult<()> {
+        require_pending(data, target).await?;
+        if let Err(e) = registration::notify(data, target, false).await {
+            log::error!("Unable to notify {target} of rejected registration: {e}");
+        }
+        delete_user(data, admin, target).await?;
+        log::info!("Administrator {admin} rejected registration of {target}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::{InstanceStats, InstanceUser, PendingRegistration};
+
+    use super::*;
+    use crate::api::v1::auth::runners::Login;
+    use crate::settings::EffectiveConfig;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn admin_api_works_pg() {
+        let data = pg::get_data().await;
+        admin_api_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn admin_api_works_maria() {
+        let data = maria::get_data().await;
+        admin_api_works(data).await;
+    }
+
+    async fn admin_api_works(data: ArcData) {
+        const NAME: &str = "adminapiuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminapiuser@a.com";
+        const ABUSER: &str = "adminapiabuser";
+        const ABUSER_EMAIL: &str = "adminapiabuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        delete_user(data, ABUSER).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        register(data, ABUSER, ABUSER_EMAIL, PASSWORD).await;
+        add_levels_util(data, ABUSER, PASSWORD).await;
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        // unauthenticated
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get().uri(routes.stats).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        // not an administrator
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.stats)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), ServiceError::AdminRequired.status_code());
+        let payload = DeleteUser {
+            username: ABUSER.into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete_user,
+            &payload,
+            ServiceError::AdminRequired,
+        )
+        .await;
+
+        data.db.set_admin(NAME, true).await.unwrap();
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.stats)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let stats: InstanceStats = test::read_body_json(resp).await;
+        assert!(stats.users >= 2);
+        assert!(stats.captchas >= 1);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.config)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let config: EffectiveConfig = test::read_body_json(resp).await;
+        assert_eq!(config, data.settings.effective);
+        assert_ne!(
+            config.values["server"]["cookie_secret"],
+            data.settings.server.cookie_secret.as_str()
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.survey_nodes)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let nodes: Vec<SurveyNodeStatus> = test::read_body_json(resp).await;
+        assert_eq!(
+            nodes.len(),
+            data.settings.survey.as_ref().map_or(0, |s| s.nodes.len())
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.pow)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let status: PoWStatus = test::read_body_json(resp).await;
+        assert_eq!(status.tuning, PoWTuning::new(&data.settings));
+        let tuning = PoWTuning {
+            runners: 2,
+            queue_length: 100,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&tuning, routes.pow_tune)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let status: PoWStatus = test::read_body_json(resp).await;
+        assert_eq!(status.tuning, tuning);
+        assert_eq!(data.captcha.tuning(), tuning);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.pow_tune,
+            &PoWTuning {
+                runners: 0,
+                queue_length: 100,
+            },
+            ServiceError::InvalidPoWTuning,
+        )
+        .await;
+
+        let mut page = 0;
+        let abuser = loop {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(&routes.get_users_route(page))
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let users: Vec<InstanceUser> = test::read_body_json(resp).await;
+            assert!(!users.is_empty());
+            if let Some(user) = users.into_iter().find(|u| u.name == ABUSER) {
+                break user;
+            }
+            page += 1;
+        };
+        assert!(!abuser.is_admin);
+        assert_eq!(abuser.captchas, 1);
+
+        // administrators can't be deleted
+        let admin = DeleteUser {
+            username: NAME.into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete_user,
+            &admin,
+            ServiceError::CannotDeleteAdmin,
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.delete_user)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!data.db.username_exists(ABUSER).await.unwrap());
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete_user,
+            &payload,
+            ServiceError::AccountNotFound,
+        )
+        .await;
+    }
+
+    #[actix_rt::test]
+    async fn quotas_work_pg() {
+        let data = pg::get_data().await;
+        quotas_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn quotas_work_maria() {
+        let data = maria::get_data().await;
+        quotas_work(data).await;
+    }
+
+    async fn quotas_work(data: ArcData) {
+        const NAME: &str = "adminquotauser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminquotauser@a.com";
+        const USER: &str = "adminquotatarget";
+        const USER_EMAIL: &str = "adminquotatarget@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        delete_user(data, USER).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        register(data, USER, USER_EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, USER, PASSWORD).await;
+        data.db.set_admin(NAME, true).await.unwrap();
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        let mut payload = SetUserQuota {
+            username: "nonexistentquotauser".into(),
+            quota: UserQuota {
+                max_captchas: Some(1),
+                max_analytics_records: Some(1),
+            },
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.quotas_set,
+            &payload,
+            ServiceError::AccountNotFound,
+        )
+        .await;
+        payload.username = USER.into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.quotas_set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let user = QuotaUser {
+            username: USER.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&user, routes.quotas_get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let status: UserQuotaStatus = test::read_body_json(resp).await;
+        assert_eq!(status.overrides, payload.quota);
+        assert_eq!(status.quota.max_captchas, 1);
+        assert_eq!(status.captchas, Usage { used: 1, limit: 1 });
+
+        bad_post_req_test(
+            data,
+            USER,
+            PASSWORD,
+            V1_API_ROUTES.captcha.create,
+            &get_level_data(),
+            ServiceError::CaptchaQuotaReached,
+        )
+        .await;
+
+        let analytics = db_core::CreatePerformanceAnalytics {
+            time: 1,
+            difficulty_factor: 1,
+            worker_type: "wasm".into(),
+        };
+        assert!(!quotas::analytics(data, &token_key.key)
+            .await
+            .unwrap()
+            .reached());
+        let records = || data.analytics_quotas.records(data, &token_key.key);
+        assert!(records().await.unwrap());
+        data.db
+            .analysis_save(&token_key.key, &analytics)
+            .await
+            .unwrap();
+        assert!(quotas::analytics(data, &token_key.key)
+            .await
+            .unwrap()
+            .reached());
+        // verification trusts the cached quota state until it expires
+        assert!(records().await.unwrap());
+        data.analytics_quotas.invalidate(&token_key.key);
+        assert!(!records().await.unwrap());
+
+        // 0 disables quotas
+        payload.quota.max_captchas = Some(0);
+        payload.quota.max_analytics_records = Some(0);
+        data.db.set_user_quota(USER, &payload.quota).await.unwrap();
+        assert!(!quotas::analytics(data, &token_key.key)
+            .await
+            .unwrap()
+            .reached());
+        add_levels_util(data, USER, PASSWORD).await;
+    }
+
+    #[actix_rt::test]
+    async fn residency_works_pg() {
+        let data = pg::get_data().await;
+        residency_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn residency_works_maria() {
+        let data = maria::get_data().await;
+        residency_works(data).await;
+    }
+
+    async fn residency_works(data: ArcData) {
+        const NAME: &str = "adminresidencyuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminresidencyuser@a.com";
+        const USER: &str = "adminresidencytarget";
+        const USER_EMAIL: &str = "adminresidencytarget@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        delete_user(data, USER).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        register(data, USER, USER_EMAIL, PASSWORD).await;
+        data.db.set_admin(NAME, true).await.unwrap();
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        let mut payload = SetResidency {
+            username: USER.into(),
+            region: Some("EU West".into()),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.residency,
+            &payload,
+            ServiceError::InvalidResidency,
+        )
+        .await;
+        payload.region = Some("eu-west".into());
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.residency)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            data.db.get_residency(USER).await.unwrap().as_deref(),
+            Some("eu-west")
+        );
+
+        payload.region = None;
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.residency)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(data.db.get_residency(USER).await.unwrap(), None);
+    }
+
+    #[actix_rt::test]
+    async fn registration_approval_works_pg() {
+        let data = pg::get_data().await;
+        registration_approval_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn registration_approval_works_maria() {
+        let data = maria::get_data().await;
+        registration_approval_works(data).await;
+    }
+
+    async fn registration_approval_works(data: ArcData) {
+        const NAME: &str = "adminapprovaluser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminapprovaluser@a.com";
+        const USER: &str = "adminapprovaltarget";
+        const USER_EMAIL: &str = "adminapprovaltarget@a.com";
+        const REJECTED: &str = "adminapprovalrejected";
+        const REJECTED_EMAIL: &str = "adminapprovalrejected@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.require_registration_approval = true;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+
+        delete_user(data, NAME).await;
+        delete_user(data, USER).await;
+        delete_user(data, REJECTED).await;
+        register(data, NAME, EMAIL, PASSWORD).await;
+        data.db.set_pending_approval(NAME, false).await.unwrap();
+        data.db.set_admin(NAME, true).await.unwrap();
+        let (_, signin_resp) = signin(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        // applicants can't sign in until they are approved
+        register(data, USER, USER_EMAIL, PASSWORD).await;
+        register(data, REJECTED, REJECTED_EMAIL, PASSWORD).await;
+        let login = Login {
+            login: USER.into(),
+            password: PASSWORD.into(),
+            totp: None,
+            challenge: None,
+        };
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.auth.login,
+            &login,
+            ServiceError::RegistrationPendingApproval,
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_registrations_route(0))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let registrations: Vec<PendingRegistration> = test::read_body_json(resp).await;
+        assert_eq!(registrations.len(), 2);
+        assert!(registrations
+            .iter()
+            .any(|r| r.name == USER && r.email.as_deref() == Some(USER_EMAIL)));
+
+        let payload = Registration {
+            username: USER.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.approve)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        signin(data, USER, PASSWORD).await;
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.approve,
+            &payload,
+            ServiceError::RegistrationNotPending,
+        )
+        .await;
+        // approved accounts can't be rejected
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.reject,
+            &payload,
+            ServiceError::RegistrationNotPending,
+        )
+        .await;
+
+        let payload = Registration {
+            username: REJECTED.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.reject)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!data.db.username_exists(REJECTED).await.unwrap());
+        assert!(data
+            .db
+            .get_pending_registrations(0, USERS_PER_PAGE)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn suspensions_work_pg() {
+        let data = pg::get_data().await;
+        suspensions_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn suspensions_work_maria() {
+        let data = maria::get_data().await;
+        suspensions_work(data).await;
+    }
+
+    async fn suspensions_work(data: ArcData) {
+        use crate::api::v1::auth::runners::Register;
+        use crate::api::v1::pow::get_config::GetConfigPayload;
+        use crate::settings::SuspendedBehavior;
+
+        const NAME: &str = "adminsuspenduser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminsuspenduser@a.com";
+        const USER: &str = "adminsuspendtarget";
+        const USER_EMAIL: &str = "adminsuspendtarget@a.com";
+        const BANNED: &str = "adminsuspendbanned";
+        const BANNED_DOMAIN: &str = "adminsuspend.example";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        delete_user(data, USER).await;
+        delete_user(data, BANNED).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let (_, user_signin_resp) =
+            register_and_signin(data, USER, USER_EMAIL, PASSWORD).await;
+        let user_cookies = get_cookie!(user_signin_resp);
+        let (_, _, token_key) = add_levels_util(data, USER, PASSWORD).await;
+        data.db.set_admin(NAME, true).await.unwrap();
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        let payload = Suspension {
+            username: NAME.into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.suspend,
+            &payload,
+            ServiceError::CannotSuspendAdmin,
+        )
+        .await;
+
+        let payload = Suspension {
+            username: USER.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.suspend)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // suspended users are signed out and can't sign in
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.account.sessions.list)
+                .cookie(user_cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let login = Login {
+            login: USER.into(),
+            password: PASSWORD.into(),
+            totp: None,
+            challenge: None,
+        };
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.auth.login,
+            &login,
+            ServiceError::AccountSuspended,
+        )
+        .await;
+
+        // sitekeys of suspended users refuse to serve challenges by default
+        let config = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: None,
+        };
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.pow.get_config,
+            &config,
+            ServiceError::CaptchaSuspended,
+        )
+        .await;
+        let mut settings = data.settings.clone();
+        settings.captcha.suspended_behavior = SuspendedBehavior::Allow;
+        let allow =
+            &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let allow_app = get_app!(allow).await;
+        let resp = test::call_service(
+            &allow_app,
+            post_request!(&config, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.unsuspend)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        signin(data, USER, PASSWORD).await;
+        let resp = test::call_service(
+            &app,
+            post_request!(&config, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // banned email domains
+        let domain = BannedDomain {
+            domain: "a..example".into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.ban_domain,
+            &domain,
+            ServiceError::InvalidEmailDomain,
+        )
+        .await;
+        let domain = BannedDomain {
+            domain: format!("@{}", BANNED_DOMAIN.to_uppercase()),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&domain, routes.ban_domain)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.banned_domains)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let domains: Vec<BannedDomain> = test::read_body_json(resp).await;
+        assert!(domains.iter().any(|d| d.domain == BANNED_DOMAIN));
+
+        // bans cover subdomains
+        let register = Register {
+            username: BANNED.into(),
+            password: PASSWORD.into(),
+            confirm_password: PASSWORD.into(),
+            email: Some(format!("{BANNED}@mail.{BANNED_DOMAIN}")),
+            invite: None,
+        };
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.auth.register,
+            &register,
+            ServiceError::EmailDomainBanned,
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&domain, routes.unban_domain)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.unban_domain,
+            &domain,
+            ServiceError::BannedDomainNotFound,
+        )
+        .await;
+        let resp = test::call_service(
+            &app,
+            post_request!(&register, V1_API_ROUTES.auth.register).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn encrypted_notifications_work_pg() {
+        let data = pg::get_data().await;
+        encrypted_notifications_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn encrypted_notifications_work_maria() {
+        let data = maria::get_data().await;
+        encrypted_notifications_work(data).await;
+    }
+
+    async fn encrypted_notifications_work(data: ArcData) {
+        use crate::api::v1::notifications::encrypted::tests::{
+            dummy_key, dummy_message,
+        };
+
+        const NAME: &str = "adminencnotifuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminencnotifuser@a.com";
+        const RECEIVER: &str = "adminencnotifrx";
+        const RECEIVER_EMAIL: &str = "adminencnotifrx@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        delete_user(data, RECEIVER).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        register(data, RECEIVER, RECEIVER_EMAIL, PASSWORD).await;
+        data.db.set_admin(NAME, true).await.unwrap();
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        let payload = EncryptedNotification {
+            to: RECEIVER.into(),
+            heading: "Abuse warning".into(),
+            message: dummy_message(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.notify,
+            &payload,
+            ServiceError::NotificationKeyNotSet,
+        )
+        .await;
+
+        let key = dummy_key();
+        data.db
+            .set_notification_key(RECEIVER, Some(&key))
+            .await
+            .unwrap();
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &UserNotificationKey {
+                    username: RECEIVER.into()
+                },
+                routes.notification_key
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: NotificationKey = test::read_body_json(resp).await;
+        assert_eq!(resp.key, Some(key));
+
+        let plaintext = EncryptedNotification {
+            message: EncryptedMessage {
+                data: "plaintext evidence".into(),
+                ..dummy_message()
+            },
+            ..payload.clone()
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.notify,
+            &plaintext,
+            ServiceError::InvalidEncryptedMessage,
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.notify)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let notifications = data
+            .db
+            .get_all_unread_notifications(RECEIVER)
+            .await
+            .unwrap();
+        assert_eq!(notifications.len(), 1);
+        assert!(notifications[0].encrypted);
+        let stored: EncryptedMessage =
+            serde_json::from_str(notifications[0].message.as_ref().unwrap()).unwrap();
+        assert_eq!(stored, payload.message);
+    }
+
+    #[actix_rt::test]
+    async fn vanity_keys_work_pg() {
+        let data = pg::get_data().await;
+        vanity_keys_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn vanity_keys_work_maria() {
+        let data = maria::get_data().await;
+        vanity_keys_work(data).await;
+    }
+
+    async fn vanity_keys_work(data: ArcData) {
+        use crate::settings::KeyAlphabet;
+
+        const NAME: &str = "adminvanityuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminvanityuser@a.com";
+        const VANITY: &str = "acme-checkout";
+
+        // sitekeys are generated in the configured format
+        let mut settings = data.settings.clone();
+        settings.captcha.key_length = 16;
+        settings.captcha.key_alphabet = KeyAlphabet::Hex;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        data.db.set_admin(NAME, true).await.unwrap();
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        assert_eq!(token_key.key.len(), 16);
+        assert!(token_key
+            .key
+            .bytes()
+            .all(|c| KeyAlphabet::Hex.chars().contains(&c)));
+        let (_, _, other_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        let mut payload = VanityKey {
+            key: token_key.key.clone(),
+            vanity: "acme checkout".into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.vanity_key,
+            &payload,
+            ServiceError::InvalidVanityKey,
+        )
+        .await;
+        payload.key = "nonexistent".into();
+        payload.vanity = VANITY.into();
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.vanity_key,
+            &payload,
+            ServiceError::CaptchaNotFound,
+        )
+        .await;
+
+        payload.key = token_key.key.clone();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.vanity_key)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.captcha_exists(Some(NAME), VANITY).await.unwrap());
+        assert!(!data.db.captcha_exists(None, &token_key.key).await.unwrap());
+
+        payload.key = other_key.key.clone();
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.vanity_key,
+            &payload,
+            ServiceError::VanityKeyTaken,
+        )
+        .await;
+
+        // sitekeys can be made high priority
+        let mut priority = SitekeyPriority {
+            key: "nonexistent".into(),
+            high_priority: true,
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.priority,
+            &priority,
+            ServiceError::CaptchaNotFound,
+        )
+        .await;
+        priority.key = other_key.key.clone();
+        let resp = test::call_service(
+            &app,
+            post_request!(&priority, routes.priority)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.captcha_high_priority(&other_key.key).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn dead_letters_work_pg() {
+        let data = pg::get_data().await;
+        dead_letters_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn dead_letters_work_maria() {
+        let data = maria::get_data().await;
+        dead_letters_work(data).await;
+    }
+
+    async fn dead_letters_work(data: ArcData) {
+        use db_core::AddOutboxEmail;
+
+        const NAME: &str = "admindeadletteruser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "admindeadletteruser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        data.db.set_admin(NAME, true).await.unwrap();
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.admin;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let recipients = vec![EMAIL.to_string()];
+        let email = AddOutboxEmail {
+            sender: Some("admin@localhost"),
+            recipients: &recipients,
+            message: "Subject: dead letter\r\n\r\nbody",
+            next_attempt_at: now,
+            created_at: now,
+        };
+        let id = data.db.add_outbox_email(&email).await.unwrap();
+        assert!(data.db.claim_outbox_email(id, 0, now).await.unwrap());
+        data.db
+            .fail_outbox_email(id, "rejected", None)
+            .await
+            .unwrap();
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.dead_letters)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let letters: Vec<DeadLetter> = test::read_body_json(resp).await;
+        let letter = letters.iter().find(|l| l.id == id).unwrap();
+        assert_eq!(letter.subject.as_deref(), Some("dead letter"));
+        assert_eq!(letter.recipients, recipients);
+        assert_eq!(letter.last_error.as_deref(), Some("rejected"));
+
+        let payload = RetryDeadLetter { id };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.retry_dead_letter)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.retry_dead_letter,
+            &payload,
+            ServiceError::DeadLetterNotFound,
+        )
+        .await;
+        data.db.delete_outbox_email(id).await.unwrap();
+    }
+}
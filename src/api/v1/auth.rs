@@ -5,12 +5,15 @@
 
 use actix_identity::Identity;
 use actix_web::http::header;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use db_core::errors::DBError;
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 
 use super::mcaptcha::get_random;
+use crate::email::{invitation, verification};
 use crate::errors::*;
+use crate::login_protection::LoginWork;
 use crate::AppData;
 
 pub mod routes {
@@ -19,17 +22,20 @@ pub mod routes {
     pub struct Auth {
         pub logout: &'static str,
         pub login: &'static str,
+        pub login_challenge: &'static str,
         pub register: &'static str,
     }
 
     impl Auth {
         pub const fn new() -> Auth {
             let login = "/api/v1/signin";
+            let login_challenge = "/api/v1/signin/challenge";
             let logout = "/logout";
             let register = "/api/v1/signup";
             Auth {
                 logout,
                 login,
+                login_challenge,
                 register,
             }
         }
@@ -53,12 +59,19 @@ pub mod routes {
 pub mod runners {
     use super::*;
 
+    /// password hash of accounts that can't sign in with a password. It isn't
+    /// an argon2 hash, so no password matches it
+    pub const UNUSABLE_HASH: &str = "!";
+
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct Register {
         pub username: String,
         pub password: String,
         pub confirm_password: String,
         pub email: Option<String>,
+        /// invitation token; allows signup when registration is closed
+        #[serde(default)]
+        pub invite: Option<String>,
     }
 
     #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -67,19 +80,118 @@ pub mod runners {
         // TODO update all instances where login is used
         pub login: String,
         pub password: String,
+        /// TOTP or recovery code; required when two-factor authentication is enabled
+        #[serde(default)]
+        pub totp: Option<String>,
+        /// solution to the sign-in challenge; required after repeated failed
+        /// sign-ins
+        #[serde(default)]
+        pub challenge: Option<LoginWork>,
     }
 
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub struct Password {
         pub password: String,
+        /// TOTP or recovery code; required when two-factor authentication is enabled
+        #[serde(default)]
+        pub totp: Option<String>,
     }
 
     /// returns Ok(()) when everything checks out and the user is authenticated. Errors otherwise
-    pub async fn login_runner(payload: Login, data: &AppData) -> ServiceResult<String> {
+    ///
+    /// Failed attempts are counted per account and IP, and a solution to the
+    /// sign-in challenge is required once either crosses its threshold. Accounts
+    /// and IPs that keep failing are locked out for a while.
+    pub async fn login_runner(
+        payload: Login,
+        ip: &str,
+        data: &AppData,
+    ) -> ServiceResult<String> {
+        let failed_logins = &data.failed_logins;
+        // signing in with the username and with the email address count
+        // against the same account
+        let account = account(data, &payload.login).await;
+        if failed_logins.locked_until(&account, ip).is_some() {
+            return Err(ServiceError::LoginLocked);
+        }
+        if failed_logins.challenge_required(&account, ip) {
+            match &payload.challenge {
+                Some(work) if failed_logins.verify(work) => (),
+                _ => return Err(ServiceError::LoginChallengeRequired),
+            }
+        }
+
+        match check_credentials(&payload, data).await {
+            Ok(username) => {
+                failed_logins.reset(&account);
+                Ok(username)
+            }
+            Err(e) => {
+                if matches!(
+                    e,
+                    ServiceError::WrongPassword
+                        | ServiceError::WrongTotp
+                        | ServiceError::UsernameNotFound
+                        | ServiceError::AccountNotFound
+                ) {
+                    if let Some(duration) = failed_logins.record_failure(&account, ip) {
+                        notify_lockout(data, &account, duration).await;
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// username of the account that `login`, a username or an email address,
+    /// refers to. Logins that don't match an account are returned as they are,
+    /// so that their failed attempts are still counted
+    async fn account(data: &AppData, login: &str) -> String {
+        let username = if login.contains('@') {
+            data.db
+                .get_password(&db_core::Login::Email(login))
+                .await
+                .ok()
+                .map(|s| s.username)
+        } else {
+            data.creds.username(login).ok()
+        };
+        username.unwrap_or_else(|| login.to_string())
+    }
+
+    /// notify user that their account, as resolved by [account], was locked
+    /// out
+    async fn notify_lockout(data: &AppData, username: &str, duration: i64) {
+        if !data.settings.features.notifications
+            || !data.db.username_exists(username).await.unwrap_or(false)
+        {
+            return;
+        }
+
+        let message = format!(
+            "Sign-in to your account was locked for {} minutes after repeated failed attempts. \
+            If this wasn't you, consider changing your password and enabling two-factor authentication.",
+            (duration + 59) / 60
+        );
+        let n = db_core::AddNotification {
+            to: &username,
+            from: &username,
+            heading: "Sign-in locked",
+            message: &message,
+        };
+        if let Err(e) = crate::api::v1::notifications::notify(data, &n).await {
+            log::error!("Unable to notify {username} of sign-in lockout: {e}");
+        }
+    }
+
+    async fn check_credentials(
+        payload: &Login,
+        data: &AppData,
+    ) -> ServiceResult<String> {
         use argon2_creds::Config;
 
         let verify = |stored: &str, received: &str| {
-            if Config::verify(stored, received)? {
+            if stored != UNUSABLE_HASH && Config::verify(stored, received)? {
                 Ok(())
             } else {
                 Err(ServiceError::WrongPassword)
@@ -98,24 +210,103 @@ pub mod runners {
         };
 
         verify(&s.hash, &payload.password)?;
+        crate::api::v1::account::totp::runners::verify(
+            data,
+            &s.username,
+            payload.totp.as_deref(),
+        )
+        .await?;
+        if data.db.is_pending_approval(&s.username).await? {
+            return Err(ServiceError::RegistrationPendingApproval);
+        }
+        if data.db.is_suspended(&s.username).await? {
+            return Err(ServiceError::AccountSuspended);
+        }
         Ok(s.username)
     }
+
+    /// maximum length of usernames, in characters
+    pub const MAX_USERNAME_LEN: usize = 100;
+    /// maximum length of email addresses, in characters
+    pub const MAX_EMAIL_LEN: usize = 100;
+
+    /// process a new username, rejecting usernames that are too long to store
+    pub fn username(data: &AppData, username: &str) -> ServiceResult<String> {
+        let username = data.creds.username(username)?;
+        if username == crate::alerts::SYSTEM_USER {
+            return Err(ServiceError::BlacklistError);
+        }
+        if username.chars().count() > MAX_USERNAME_LEN {
+            return Err(ServiceError::UsernameTooLong);
+        }
+        Ok(username)
+    }
+
+    /// validate a new email address, rejecting addresses that are too long to
+    /// store
+    pub fn email(data: &AppData, email: &str) -> ServiceResult<()> {
+        data.creds.email(email)?;
+        if email.chars().count() > MAX_EMAIL_LEN {
+            return Err(ServiceError::NotAnEmail);
+        }
+        Ok(())
+    }
+
+    /// reject email addresses whose domain is banned from registration. Bans
+    /// cover subdomains of the banned domain
+    pub async fn email_domain(data: &AppData, email: &str) -> ServiceResult<()> {
+        let domain = match email.rsplit_once('@') {
+            Some((_, domain)) => domain.to_lowercase(),
+            None => return Ok(()),
+        };
+        let mut domain = domain.as_str();
+        loop {
+            if data.db.is_email_domain_banned(domain).await? {
+                return Err(ServiceError::EmailDomainBanned);
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return Ok(()),
+            }
+        }
+    }
+
     pub async fn register_runner(
         payload: &Register,
         data: &AppData,
     ) -> ServiceResult<()> {
-        if !data.settings.allow_registration {
+        let invite = match payload.invite.as_deref() {
+            Some(token) => Some(invitation::get_pending(data, token).await?),
+            None => None,
+        };
+        if !data.settings.allow_registration && invite.is_none() {
             return Err(ServiceError::ClosedForRegistration);
         }
 
         if payload.password != payload.confirm_password {
             return Err(ServiceError::PasswordsDontMatch);
         }
-        let username = data.creds.username(&payload.username)?;
+        let username = username(data, &payload.username)?;
         let hash = data.creds.password(&payload.password)?;
 
-        if let Some(email) = &payload.email {
-            data.creds.email(email)?;
+        // invitees sign up with the address that the invitation was sent to
+        let email = match &invite {
+            Some(invite) => {
+                if payload.email.as_ref().map_or(false, |e| e != &invite.email) {
+                    return Err(ServiceError::InviteEmailMismatch);
+                }
+                Some(invite.email.clone())
+            }
+            None => payload.email.clone(),
+        };
+        if let Some(email) = &email {
+            self::email(data, email)?;
+            email_domain(data, email).await?;
+        }
+
+        if let Some(token) = payload.invite.as_deref() {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            data.db.accept_invite(token, now).await?;
         }
 
         let mut secret;
@@ -126,14 +317,41 @@ pub mod runners {
             let p = db_core::Register {
                 username: &username,
                 hash: &hash,
-                email: payload.email.as_deref(),
+                email: email.as_deref(),
                 secret: &secret,
             };
 
             match data.db.register(&p).await {
                 Ok(_) => break,
                 Err(DBError::SecretTaken) => continue,
-                Err(e) => return Err(e.into()),
+                Err(e) => {
+                    if let Some(token) = payload.invite.as_deref() {
+                        data.db.reopen_invite(token).await?;
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        if let Some(invite) = invite {
+            // the invitation was delivered to the address
+            data.db.set_email_verified(&username, true).await?;
+            if let (Some(org), Some(role)) = (&invite.org, invite.role) {
+                if let Err(e) = data.db.set_org_member(org, &username, role).await {
+                    log::error!("Unable to add {username} to organization {org}: {e}");
+                }
+            }
+            return Ok(());
+        }
+
+        // invitees were vouched for by whoever invited them
+        if data.settings.require_registration_approval {
+            data.db.set_pending_approval(&username, true).await?;
+        }
+
+        if let (true, Some(email)) = (data.settings.require_email_verification, &email) {
+            if let Err(e) = verification::send(data, &username, email).await {
+                log::error!("Unable to send verification email to {username}: {e}");
             }
         }
 
@@ -144,6 +362,7 @@ pub mod runners {
 pub fn services(cfg: &mut web::ServiceConfig) {
     cfg.service(register);
     cfg.service(login);
+    cfg.service(login_challenge);
     cfg.service(signout);
 }
 #[my_codegen::post(path = "crate::V1_API_ROUTES.auth.register")]
@@ -158,11 +377,13 @@ async fn register(
 #[my_codegen::post(path = "crate::V1_API_ROUTES.auth.login")]
 async fn login(
     id: Identity,
+    req: HttpRequest,
     payload: web::Json<runners::Login>,
     query: web::Query<super::RedirectQuery>,
     data: AppData,
 ) -> ServiceResult<impl Responder> {
-    let username = runners::login_runner(payload.into_inner(), &data).await?;
+    let ip = data.trusted_proxies.client_ip(&req);
+    let username = runners::login_runner(payload.into_inner(), &ip, &data).await?;
     id.remember(username);
     //    Ok(HttpResponse::Ok())
 
@@ -176,6 +397,12 @@ async fn login(
     }
 }
 
+/// get challenge to solve when sign-in requires it
+#[my_codegen::get(path = "crate::V1_API_ROUTES.auth.login_challenge")]
+async fn login_challenge(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(data.failed_logins.challenge())
+}
+
 #[my_codegen::get(
     path = "crate::V1_API_ROUTES.auth.logout",
     wrap = "crate::api::v1::get_middleware()"
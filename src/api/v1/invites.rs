@@ -0,0 +1,358 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Invite people to sign up. Administrators can invite people to the
+//! instance and organization owners to their organizations. See
+//! [crate::email::invitation]
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{Invite, InviteStatus, OrgRole};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api::v1::orgs::runners::require_role;
+use crate::email::invitation;
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Invites {
+        pub create: &'static str,
+        pub list: &'static str,
+        pub delete: &'static str,
+    }
+
+    impl Invites {
+        pub const fn new() -> Self {
+            Self {
+                create: "/api/v1/invites/create",
+                list: "/api/v1/invites/list",
+                delete: "/api/v1/invites/delete",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(create);
+    cfg.service(list);
+    cfg.service(delete);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateInvite {
+    pub email: String,
+    /// organization that the invitee joins on signup
+    #[serde(default)]
+    pub org: Option<String>,
+    /// role of the invitee in `org`; defaults to viewer
+    #[serde(default)]
+    pub role: Option<OrgRole>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InviteLink {
+    /// signup link, as mailed to the invitee
+    pub link: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InviteInfo {
+    #[serde(flatten)]
+    pub invite: Invite,
+    pub status: InviteStatus,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InviteId {
+    pub id: i32,
+}
+
+/// invite someone to sign up, and mail them the signup link
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.invites.create",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn create(
+    payload: web::Json<CreateInvite>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    match payload.org.as_deref() {
+        Some(org) => {
+            require_role(&data, org, &username, OrgRole::Owner).await?;
+        }
+        None => {
+            if !data.db.is_admin(&username).await? {
+                return Err(ServiceError::AdminRequired);
+            }
+        }
+    }
+    crate::api::v1::auth::runners::email(&data, &payload.email)?;
+    crate::api::v1::auth::runners::email_domain(&data, &payload.email).await?;
+    if data.db.email_exists(&payload.email).await? {
+        return Err(ServiceError::EmailTaken);
+    }
+
+    let link = invitation::send(
+        &data,
+        &username,
+        &payload.email,
+        payload.org.as_deref(),
+        payload.role,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(InviteLink { link }))
+}
+
+/// list invitations issued by the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.invites.list",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn list(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let invites: Vec<InviteInfo> = data
+        .db
+        .get_user_invites(&username)
+        .await?
+        .into_iter()
+        .map(|invite| InviteInfo {
+            status: invite.status(now),
+            invite,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+/// revoke an invitation issued by the user
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.invites.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<InviteId>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    data.db.delete_invite(&username, payload.id).await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::auth::runners::Register;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn invites_work_pg() {
+        let data = pg::get_data().await;
+        invites_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn invites_work_maria() {
+        let data = maria::get_data().await;
+        invites_work(data).await;
+    }
+
+    async fn invites_work(data: ArcData) {
+        const NAME: &str = "inviteuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "inviteuser@a.com";
+        const INVITEE: &str = "inviteeuser";
+        const INVITEE_EMAIL: &str = "inviteeuser@a.com";
+        const ORG_INVITEE: &str = "orginviteeuser";
+        const ORG_INVITEE_EMAIL: &str = "orginviteeuser@a.com";
+        const ORG: &str = "invite-test";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        delete_user(data, INVITEE).await;
+        delete_user(data, ORG_INVITEE).await;
+        if data.db.org_exists(ORG).await.unwrap() {
+            data.db.delete_org(ORG).await.unwrap();
+        }
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.invites;
+
+        // only administrators can invite people to the instance
+        let mut payload = CreateInvite {
+            email: INVITEE_EMAIL.into(),
+            org: None,
+            role: None,
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.create,
+            &payload,
+            ServiceError::AdminRequired,
+        )
+        .await;
+        data.db.set_admin(NAME, true).await.unwrap();
+        payload.email = EMAIL.into();
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.create,
+            &payload,
+            ServiceError::EmailTaken,
+        )
+        .await;
+        payload.email = INVITEE_EMAIL.into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let link: InviteLink = test::read_body_json(resp).await;
+        let (_, token) = link.link.split_once("?invite=").unwrap();
+
+        // signup page locks the email address of the invitation
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&PAGES.auth.get_join_invite(token))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(INVITEE_EMAIL));
+
+        let mut register = Register {
+            username: INVITEE.into(),
+            password: PASSWORD.into(),
+            confirm_password: PASSWORD.into(),
+            email: Some(EMAIL.into()),
+            invite: Some(token.into()),
+        };
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.auth.register,
+            &register,
+            ServiceError::InviteEmailMismatch,
+        )
+        .await;
+        register.email = None;
+        let resp = test::call_service(
+            &app,
+            post_request!(&register, V1_API_ROUTES.auth.register).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            data.db.get_email(INVITEE).await.unwrap().as_deref(),
+            Some(INVITEE_EMAIL)
+        );
+        assert!(data.db.is_email_verified(INVITEE).await.unwrap());
+
+        // invitations can be used once
+        register.username = ORG_INVITEE.into();
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.auth.register,
+            &register,
+            ServiceError::InviteNotFound,
+        )
+        .await;
+
+        // organization owners can invite people to their organizations
+        data.db.set_admin(NAME, false).await.unwrap();
+        data.db.create_org(NAME, ORG).await.unwrap();
+        let payload = CreateInvite {
+            email: ORG_INVITEE_EMAIL.into(),
+            org: Some(ORG.into()),
+            role: Some(OrgRole::Editor),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let link: InviteLink = test::read_body_json(resp).await;
+        let (_, token) = link.link.split_once("?invite=").unwrap();
+        register.invite = Some(token.into());
+        let resp = test::call_service(
+            &app,
+            post_request!(&register, V1_API_ROUTES.auth.register).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            data.db.get_org_role(ORG, ORG_INVITEE).await.unwrap(),
+            OrgRole::Editor
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.list)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let invites: Vec<InviteInfo> = test::read_body_json(resp).await;
+        assert_eq!(invites.len(), 2);
+        assert!(invites.iter().all(|i| i.status == InviteStatus::Accepted));
+        assert_eq!(invites[0].invite.org.as_deref(), Some(ORG));
+
+        let id = InviteId {
+            id: invites[0].invite.id,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&id, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete,
+            &id,
+            ServiceError::InviteNotFound,
+        )
+        .await;
+
+        // invalid invitations are ignored by the signup page
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&PAGES.auth.get_join_invite("nonexistent"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("nonexistent"));
+        data.db.delete_org(ORG).await.unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Configure automatic notifications on traffic of sitekeys. See
+//! [crate::alerts] for the alerts
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{AlertThresholds, OrgRole};
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::orgs::runners::{require_sitekey_access, require_sitekey_role};
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Alerts {
+        pub set: &'static str,
+        pub get: &'static str,
+        pub delete: &'static str,
+    }
+
+    impl Alerts {
+        pub const fn new() -> Self {
+            Self {
+                set: "/api/v1/mcaptcha/alerts/set",
+                get: "/api/v1/mcaptcha/alerts/get",
+                delete: "/api/v1/mcaptcha/alerts/delete",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(set);
+    cfg.service(get);
+    cfg.service(delete);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetAlertThresholds {
+    pub key: String,
+    #[serde(flatten)]
+    pub thresholds: AlertThresholds,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AlertsKey {
+    pub key: String,
+}
+
+/// enable automatic notifications on traffic of a sitekey, or update their
+/// thresholds
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.alerts.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    payload: web::Json<SetAlertThresholds>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let username = id.identity().unwrap();
+    if payload.thresholds.failure_rate > 100 {
+        return Err(ServiceError::InvalidAlertThresholds);
+    }
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .set_alert_thresholds(&username, &payload.key, &payload.thresholds)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get thresholds of automatic notifications on traffic of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.alerts.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    payload: web::Json<AlertsKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let thresholds = data.db.get_alert_thresholds(&payload.key).await?;
+    Ok(HttpResponse::Ok().json(thresholds))
+}
+
+/// disable automatic notifications on traffic of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.alerts.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<AlertsKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .delete_alert_thresholds(&username, &payload.key)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::alerts::Alert;
+    use crate::api::v1::pow::get_config::{
+        get_config_runner, ApiPoWConfig, GetConfigPayload,
+    };
+    use crate::api::v1::pow::verify_pow::ApiWork;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn alerts_work_pg() {
+        let data = pg::get_data().await;
+        alerts_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn alerts_work_maria() {
+        let data = maria::get_data().await;
+        alerts_work(data).await;
+    }
+
+    async fn alerts_work(data: ArcData) {
+        const NAME: &str = "alertsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "alertsuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.alerts;
+
+        let key = AlertsKey {
+            key: token_key.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let payload = SetAlertThresholds {
+            key: token_key.key.clone(),
+            thresholds: AlertThresholds {
+                levels: false,
+                highest_level: true,
+                failure_rate: 101,
+                min_attempts: 1,
+                anomaly_factor: 5,
+            },
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::InvalidAlertThresholds,
+        )
+        .await;
+
+        let mut payload = SetAlertThresholds {
+            key: "nonexistent".into(),
+            ..payload
+        };
+        payload.thresholds.failure_rate = 50;
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::CaptchaNotFound,
+        )
+        .await;
+
+        let payload = SetAlertThresholds {
+            key: token_key.key.clone(),
+            ..payload
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let thresholds: AlertThresholds = test::read_body_json(resp).await;
+        assert_eq!(thresholds, payload.thresholds);
+
+        let has_alert = |alert: Alert| async move {
+            data.db
+                .get_all_unread_notifications(NAME)
+                .await
+                .unwrap()
+                .iter()
+                .any(|n| n.heading.as_deref() == Some(alert.heading()))
+        };
+
+        // failed solves
+        let get_config_payload = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        let config: ApiPoWConfig = test::read_body_json(resp).await;
+        let work = ApiWork {
+            string: config.string,
+            result: "wrong".into(),
+            nonce: 1,
+            key: token_key.key.clone(),
+            time: None,
+            worker_type: None,
+            correlation_id: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&work, V1_API_ROUTES.pow.verify_pow).to_request(),
+        )
+        .await;
+        assert_ne!(resp.status(), StatusCode::OK);
+        assert!(has_alert(Alert::FailureRate).await);
+        let sender = data
+            .db
+            .get_all_unread_notifications(NAME)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|n| n.heading.as_deref() == Some(Alert::FailureRate.heading()))
+            .unwrap()
+            .name;
+        assert_eq!(sender.as_deref(), Some(crate::alerts::SYSTEM_USER));
+
+        // visitors beyond the threshold of the first level escalate difficulty
+        // to the highest level
+        let app_data = AppData::new(data.clone());
+        assert!(!has_alert(Alert::HighestLevel).await);
+        for _ in 0..60 {
+            get_config_runner(&app_data, &token_key.key).await.unwrap();
+        }
+        assert!(has_alert(Alert::HighestLevel).await);
+
+        // the system user is reserved
+        let msg = crate::api::v1::auth::runners::Register {
+            username: crate::alerts::SYSTEM_USER.into(),
+            password: PASSWORD.into(),
+            confirm_password: PASSWORD.into(),
+            email: None,
+            invite: None,
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            V1_API_ROUTES.auth.register,
+            &msg,
+            ServiceError::BlacklistError,
+        )
+        .await;
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete,
+            &key,
+            ServiceError::AlertThresholdsNotFound,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Schedule traffic bursts of sitekeys. See [crate::bursts] for how they are
+//! applied
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{CreateBurst, OrgRole};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api::v1::orgs::runners::{require_sitekey_access, require_sitekey_role};
+use crate::errors::*;
+use crate::AppData;
+
+/// maximum duration of a burst, in seconds
+const MAX_BURST_DURATION: i64 = 60 * 60 * 24;
+
+pub mod routes {
+    pub struct Burst {
+        pub add: &'static str,
+        pub get: &'static str,
+        pub delete: &'static str,
+    }
+
+    impl Burst {
+        pub const fn new() -> Self {
+            Self {
+                add: "/api/v1/mcaptcha/burst/add",
+                get: "/api/v1/mcaptcha/burst/get",
+                delete: "/api/v1/mcaptcha/burst/delete",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(add);
+    cfg.service(get);
+    cfg.service(delete);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AddBurst {
+    pub key: String,
+    #[serde(flatten)]
+    pub burst: CreateBurst,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BurstKey {
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteBurst {
+    pub key: String,
+    pub id: i32,
+}
+
+/// check that a burst ends in the future, after it starts, and isn't longer
+/// than [MAX_BURST_DURATION]
+fn validate(burst: &CreateBurst, now: i64) -> ServiceResult<()> {
+    if burst.difficulty_factor == 0
+        || burst.ends <= burst.starts
+        || burst.ends <= now
+        || burst.ends - burst.starts > MAX_BURST_DURATION
+    {
+        return Err(ServiceError::InvalidBurst);
+    }
+    Ok(())
+}
+
+/// reload bursts of a sitekey into the schedule of this instance
+async fn reload(data: &AppData, key: &str) -> ServiceResult<()> {
+    let bursts = data.db.get_bursts(key).await?;
+    data.bursts.set(key, bursts);
+    Ok(())
+}
+
+/// schedule a traffic burst for a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.burst.add",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn add(
+    payload: web::Json<AddBurst>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    validate(&payload.burst, OffsetDateTime::now_utc().unix_timestamp())?;
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .add_burst(&username, &payload.key, &payload.burst)
+        .await?;
+    reload(&data, &payload.key).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get traffic bursts of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.burst.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    payload: web::Json<BurstKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let bursts = data.db.get_bursts(&payload.key).await?;
+    Ok(HttpResponse::Ok().json(bursts))
+}
+
+/// delete a traffic burst of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.burst.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<DeleteBurst>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .delete_burst(&username, &payload.key, payload.id)
+        .await?;
+    reload(&data, &payload.key).await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::Burst;
+
+    use super::*;
+    use crate::api::v1::pow::get_config::{ApiPoWConfig, GetConfigPayload};
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn validate_burst_works() {
+        let now = 1000;
+        let mut burst = CreateBurst {
+            starts: now + 60,
+            ends: now + 120,
+            difficulty_factor: 5000,
+        };
+        assert!(validate(&burst, now).is_ok());
+        // bursts can start right away
+        burst.starts = now - 60;
+        assert!(validate(&burst, now).is_ok());
+
+        burst.ends = burst.starts;
+        assert!(validate(&burst, now).is_err());
+        burst.ends = now;
+        assert!(validate(&burst, now).is_err());
+        burst.ends = burst.starts + MAX_BURST_DURATION + 1;
+        assert!(validate(&burst, now).is_err());
+        burst.ends = now + 120;
+        burst.difficulty_factor = 0;
+        assert!(validate(&burst, now).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn bursts_work_pg() {
+        let data = pg::get_data().await;
+        bursts_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn bursts_work_maria() {
+        let data = maria::get_data().await;
+        bursts_work(data).await;
+    }
+
+    async fn bursts_work(data: ArcData) {
+        const NAME: &str = "burstuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "burstuser@a.com";
+        const DIFFICULTY: u32 = 50_000;
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.burst;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut payload = AddBurst {
+            key: token_key.key.clone(),
+            burst: CreateBurst {
+                starts: now + 60,
+                ends: now,
+                difficulty_factor: DIFFICULTY,
+            },
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.add,
+            &payload,
+            ServiceError::InvalidBurst,
+        )
+        .await;
+        payload.burst.starts = now - 60;
+        payload.burst.ends = now + 600;
+
+        let get_config = || async {
+            let payload = GetConfigPayload {
+                key: token_key.key.clone(),
+                version: None,
+                origin: None,
+            };
+            let resp = test::call_service(
+                &app,
+                post_request!(&payload, V1_API_ROUTES.pow.get_config).to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let config: ApiPoWConfig = test::read_body_json(resp).await;
+            config.difficulty_factor
+        };
+        assert!(get_config().await < DIFFICULTY);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.add)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let key = BurstKey {
+            key: token_key.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let bursts: Vec<Burst> = test::read_body_json(resp).await;
+        assert_eq!(bursts.len(), 1);
+        assert_eq!(bursts[0].difficulty_factor, DIFFICULTY);
+
+        // difficulty is raised for the duration of the burst
+        assert!(get_config().await >= DIFFICULTY);
+
+        let delete = DeleteBurst {
+            key: token_key.key.clone(),
+            id: bursts[0].id,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&delete, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(get_config().await < DIFFICULTY);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete,
+            &delete,
+            ServiceError::BurstNotFound,
+        )
+        .await;
+    }
+}
@@ -4,15 +4,15 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_identity::Identity;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use libmcaptcha::defense::Level;
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 
-use db_core::errors::DBError;
 use db_core::CreateCaptcha as DBCreateCaptcha;
 
-use super::get_random;
 use crate::errors::*;
+use crate::sessions::CurrentSession;
 use crate::AppData;
 
 #[derive(Serialize, Deserialize)]
@@ -36,17 +36,15 @@ pub struct MCaptchaDetails {
     wrap = "crate::api::v1::get_middleware()"
 )]
 pub async fn create(
+    req: HttpRequest,
     payload: web::Json<CreateCaptcha>,
     data: AppData,
     id: Identity,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
-    let mcaptcha_config = runner::create(&payload, &data, &username).await?;
-    if payload.publish_benchmarks {
-        data.db
-            .analytics_create_psuedo_id_if_not_exists(&mcaptcha_config.key)
-            .await?;
-    }
+    let session = CurrentSession::get(&req);
+    let mcaptcha_config =
+        runner::create(&payload, &data, &username, session.as_deref()).await?;
     Ok(HttpResponse::Ok().json(mcaptcha_config))
 }
 
@@ -54,11 +52,108 @@ pub mod runner {
     use super::*;
     use libmcaptcha::DefenseBuilder;
 
+    /// maximum length of captcha descriptions, in characters
+    pub const MAX_DESCRIPTION_LEN: usize = 100;
+
+    /// reject descriptions that are too long to store
+    pub fn validate_description(description: &str) -> ServiceResult<()> {
+        if description.chars().count() > MAX_DESCRIPTION_LEN {
+            return Err(ServiceError::CaptchaDescriptionTooLong);
+        }
+        Ok(())
+    }
+
+    /// most levels that a captcha can have
+    pub const MAX_LEVELS: usize = 20;
+    /// longest duration of captchas, in seconds
+    pub const MAX_DURATION: u32 = 24 * 60 * 60;
+
+    /// check levels, duration and description of a captcha, reporting all
+    /// invalid fields at once. Levels must be ordered by visitor threshold,
+    /// and the difficulty factor of a level can't be lower than that of the
+    /// level before it
+    pub fn validate_captcha(
+        levels: &[Level],
+        duration: u32,
+        description: &str,
+    ) -> ServiceResult<()> {
+        let mut errors = FieldErrors::default();
+        if levels.is_empty() {
+            errors.add("levels", "at least one level is required");
+        } else if levels.len() > MAX_LEVELS {
+            errors.add("levels", format!("at most {MAX_LEVELS} levels are allowed"));
+        }
+        let mut previous: Option<&Level> = None;
+        for (i, level) in levels.iter().enumerate() {
+            if level.difficulty_factor == 0 {
+                errors.add(
+                    format!("levels[{i}].difficulty_factor"),
+                    "must be at least 1",
+                );
+            }
+            if let Some(previous) = previous {
+                if level.visitor_threshold <= previous.visitor_threshold {
+                    errors.add(
+                        format!("levels[{i}].visitor_threshold"),
+                        "must be greater than that of the previous level",
+                    );
+                }
+                if level.difficulty_factor < previous.difficulty_factor {
+                    errors.add(
+                        format!("levels[{i}].difficulty_factor"),
+                        "can't be lower than that of the previous level",
+                    );
+                }
+            }
+            previous = Some(level);
+        }
+        if duration == 0 || duration > MAX_DURATION {
+            errors.add("duration", format!("must be 1 to {MAX_DURATION} seconds"));
+        }
+        if description.chars().any(char::is_control) {
+            errors.add("description", "can't contain control characters");
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidCaptcha(errors))
+        }
+    }
+
+    /// reject descriptions that the user already uses on another captcha, when
+    /// descriptions are required to be unique
+    pub async fn check_unique_name(
+        data: &AppData,
+        username: &str,
+        description: &str,
+        except: Option<&str>,
+    ) -> ServiceResult<()> {
+        if data.settings.captcha.unique_names
+            && data
+                .db
+                .captcha_description_exists(username, description, except)
+                .await?
+        {
+            return Err(ServiceError::DuplicateCaptchaName);
+        }
+        Ok(())
+    }
+
+    /// create a sitekey. `session` is the session of the request that
+    /// creates it, which demo quotas are counted against
     pub async fn create(
         payload: &CreateCaptcha,
         data: &AppData,
         username: &str,
+        session: Option<&str>,
     ) -> ServiceResult<MCaptchaDetails> {
+        let demo = crate::demo::is_demo_user(data, username);
+        crate::quotas::check_captcha_quota(data, username, 1).await?;
+        crate::email::verification::require_verified(data, username).await?;
+        validate_description(&payload.description)?;
+        validate_captcha(&payload.levels, payload.duration, &payload.description)?;
+        check_unique_name(data, username, &payload.description, None).await?;
+
         let mut defense = DefenseBuilder::default();
         for level in payload.levels.iter() {
             defense.add_level(*level)?;
@@ -66,27 +161,36 @@ pub mod runner {
 
         defense.build()?;
 
-        let mut key;
         let duration = payload.duration as i32;
-        loop {
-            key = get_random(32);
+        let key = crate::keys::unique(data, |key| async move {
             let p = DBCreateCaptcha {
                 description: &payload.description,
                 key: &key,
                 duration,
             };
-
-            match data.db.create_captcha(username, &p).await {
-                Ok(_) => break,
-                Err(DBError::SecretTaken) => continue,
-                Err(e) => return Err(e.into()),
+            if demo {
+                crate::demo::create_sitekey(data, username, &p, session).await
+            } else {
+                data.db.create_captcha(username, &p).await
             }
-        }
+        })
+        .await?;
         data.db
             .add_captcha_levels(username, &key, &payload.levels)
             .await?;
 
-        if payload.publish_benchmarks {
+        // only sitekeys that existed when allowed domains were required are
+        // grandfathered
+        if data.settings.captcha.require_allowed_domains {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            data.db.set_allowed_domains_deadline(&key, now).await?;
+        }
+
+        // benchmarks from the shared demo account aren't trustworthy
+        if payload.publish_benchmarks
+            && data.settings.features.analytics
+            && !crate::demo::is_demo_user(data, username)
+        {
             data.db
                 .analytics_create_psuedo_id_if_not_exists(&key)
                 .await?;
@@ -100,3 +204,49 @@ pub mod runner {
         Ok(mcaptcha_config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::runner::*;
+    use super::*;
+    use crate::tests::{L1, L2};
+
+    #[test]
+    fn validate_captcha_works() {
+        let fields = |e: ServiceError| match e {
+            ServiceError::InvalidCaptcha(fields) => {
+                fields.0.into_iter().map(|f| f.field).collect::<Vec<_>>()
+            }
+            e => panic!("unexpected error {e}"),
+        };
+
+        assert!(validate_captcha(&[L1, L2], 30, "sitekey").is_ok());
+        // difficulty factors can repeat
+        let level = Level {
+            visitor_threshold: L2.visitor_threshold,
+            difficulty_factor: L1.difficulty_factor,
+        };
+        assert!(validate_captcha(&[L1, level], 30, "sitekey").is_ok());
+
+        let err = validate_captcha(&[], 0, "site\nkey").unwrap_err();
+        assert_eq!(fields(err), ["levels", "duration", "description"]);
+        let err =
+            validate_captcha(&[L1; MAX_LEVELS + 1], MAX_DURATION + 1, "").unwrap_err();
+        assert_eq!(fields(err)[..2], ["levels", "levels[1].visitor_threshold"]);
+
+        let zero = Level {
+            visitor_threshold: L2.visitor_threshold,
+            difficulty_factor: 0,
+        };
+        let err = validate_captcha(&[L2, L1, zero], 30, "sitekey").unwrap_err();
+        assert_eq!(
+            fields(err),
+            [
+                "levels[1].visitor_threshold",
+                "levels[1].difficulty_factor",
+                "levels[2].difficulty_factor",
+                "levels[2].difficulty_factor",
+            ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Configure difficulty decay of sitekeys and read their escalation logs. See
+//! [crate::decay] for how difficulty decays
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{DifficultyDecay, OrgRole};
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::orgs::runners::{require_sitekey_access, require_sitekey_role};
+use crate::errors::*;
+use crate::AppData;
+
+/// number of escalation log entries that are shown
+pub const ESCALATION_LOG_LIMIT: u32 = 100;
+
+pub mod routes {
+    pub struct Decay {
+        pub set: &'static str,
+        pub get: &'static str,
+        pub delete: &'static str,
+        pub log: &'static str,
+    }
+
+    impl Decay {
+        pub const fn new() -> Self {
+            Self {
+                set: "/api/v1/mcaptcha/decay/set",
+                get: "/api/v1/mcaptcha/decay/get",
+                delete: "/api/v1/mcaptcha/decay/delete",
+                log: "/api/v1/mcaptcha/decay/log",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(set);
+    cfg.service(get);
+    cfg.service(delete);
+    cfg.service(log);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetDifficultyDecay {
+    pub key: String,
+    #[serde(flatten)]
+    pub decay: DifficultyDecay,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecayKey {
+    pub key: String,
+}
+
+/// enable difficulty decay of a sitekey, or update it
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.decay.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    payload: web::Json<SetDifficultyDecay>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    if payload.decay.step == 0 || payload.decay.steps == 0 {
+        return Err(ServiceError::InvalidDifficultyDecay);
+    }
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .set_difficulty_decay(&username, &payload.key, &payload.decay)
+        .await?;
+    data.decay.invalidate(&payload.key);
+    Ok(HttpResponse::Ok())
+}
+
+/// get difficulty decay of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.decay.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    payload: web::Json<DecayKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let decay = data.db.get_difficulty_decay(&payload.key).await?;
+    Ok(HttpResponse::Ok().json(decay))
+}
+
+/// disable difficulty decay of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.decay.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<DecayKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .delete_difficulty_decay(&username, &payload.key)
+        .await?;
+    data.decay.invalidate(&payload.key);
+    Ok(HttpResponse::Ok())
+}
+
+/// get recent escalations and decay steps of a sitekey, newest first
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.decay.log",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn log(
+    payload: web::Json<DecayKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let log = data
+        .db
+        .get_escalation_log(&username, &payload.key, ESCALATION_LOG_LIMIT)
+        .await?;
+    Ok(HttpResponse::Ok().json(log))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::{DecayCurve, EscalationKind, EscalationLogEntry};
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn difficulty_decay_works_pg() {
+        let data = pg::get_data().await;
+        difficulty_decay_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn difficulty_decay_works_maria() {
+        let data = maria::get_data().await;
+        difficulty_decay_works(data).await;
+    }
+
+    async fn difficulty_decay_works(data: ArcData) {
+        const NAME: &str = "difficultydecayuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "difficultydecayuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.decay;
+
+        let key = DecayKey {
+            key: token_key.key.clone(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.get,
+            &key,
+            ServiceError::DifficultyDecayNotFound,
+        )
+        .await;
+
+        let payload = SetDifficultyDecay {
+            key: token_key.key.clone(),
+            decay: DifficultyDecay {
+                sustain: 300,
+                step: 60,
+                steps: 0,
+                curve: DecayCurve::Exponential,
+            },
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::InvalidDifficultyDecay,
+        )
+        .await;
+
+        let mut payload = SetDifficultyDecay {
+            key: "nonexistent".into(),
+            ..payload
+        };
+        payload.decay.steps = 5;
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::CaptchaNotFound,
+        )
+        .await;
+
+        payload.key = token_key.key.clone();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let decay: DifficultyDecay = test::read_body_json(resp).await;
+        assert_eq!(decay, payload.decay);
+
+        // escalation log
+        let entry = EscalationLogEntry {
+            kind: EscalationKind::Decay,
+            difficulty_factor: 500,
+            time: 1_700_000_000,
+        };
+        data.db
+            .add_escalation_log_entry(&token_key.key, &entry)
+            .await
+            .unwrap();
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.log)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let log: Vec<EscalationLogEntry> = test::read_body_json(resp).await;
+        assert_eq!(log, vec![entry]);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete,
+            &key,
+            ServiceError::DifficultyDecayNotFound,
+        )
+        .await;
+    }
+}
@@ -8,8 +8,9 @@ use actix_web::{web, HttpResponse, Responder};
 use libmcaptcha::master::messages::RemoveCaptcha;
 use serde::{Deserialize, Serialize};
 
-use db_core::Login;
+use db_core::{Login, OrgRole};
 
+use crate::api::v1::orgs::runners::require_sitekey_role;
 use crate::errors::*;
 use crate::AppData;
 
@@ -17,6 +18,9 @@ use crate::AppData;
 pub struct DeleteCaptcha {
     pub key: String,
     pub password: String,
+    /// TOTP or recovery code; required when two-factor authentication is enabled
+    #[serde(default)]
+    pub totp: Option<String>,
 }
 
 #[my_codegen::post(
@@ -37,8 +41,17 @@ async fn delete(
     if !Config::verify(&hash.hash, &payload.password)? {
         return Err(ServiceError::WrongPassword);
     }
+    crate::api::v1::account::totp::runners::verify(
+        &data,
+        &username,
+        payload.totp.as_deref(),
+    )
+    .await?;
     let payload = payload.into_inner();
+    // only owners can delete an organization's sitekeys
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Owner).await?;
     data.db.delete_captcha(&username, &payload.key).await?;
+    data.maintenance.record_deletion();
 
     if let Err(err) = data.captcha.remove(RemoveCaptcha(payload.key)).await {
         log::error!("Error while trying to remove captcha from cache {}", err);
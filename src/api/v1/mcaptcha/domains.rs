@@ -0,0 +1,299 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Configure domains that sitekeys can be embedded on. See [crate::domains]
+//! for how they are enforced
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::OrgRole;
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::orgs::runners::require_sitekey_access;
+use crate::domains::normalize_all;
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Domains {
+        pub set: &'static str,
+        pub get: &'static str,
+    }
+
+    impl Domains {
+        pub const fn new() -> Self {
+            Self {
+                set: "/api/v1/mcaptcha/domains/set",
+                get: "/api/v1/mcaptcha/domains/get",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(set);
+    cfg.service(get);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetAllowedDomains {
+    pub key: String,
+    /// domains that the sitekey can be embedded on, along with their
+    /// subdomains. Empty to allow any domain
+    pub domains: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DomainsKey {
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AllowedDomains {
+    pub domains: Vec<String>,
+    /// sitekeys need allowed domains to serve challenges
+    pub required: bool,
+    /// unix timestamp after which the sitekey needs allowed domains to serve
+    /// challenges, when they are required
+    pub deadline: Option<i64>,
+}
+
+/// replace domains that a sitekey can be embedded on
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.domains.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    payload: web::Json<SetAllowedDomains>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let domains = normalize_all(&payload.domains)?;
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .set_allowed_domains(&username, &payload.key, &domains)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get domains that a sitekey can be embedded on
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.domains.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    payload: web::Json<DomainsKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let resp = AllowedDomains {
+        domains: data.db.get_allowed_domains(&payload.key).await?,
+        required: data.settings.captcha.require_allowed_domains,
+        deadline: data.db.get_allowed_domains_deadline(&payload.key).await?,
+    };
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use sqlx::types::time::OffsetDateTime;
+
+    use super::*;
+    use crate::api::v1::pow::get_config::GetConfigPayload;
+    use crate::api::v1::pow::probe::{SitekeyProbe, ANY_ORIGIN};
+    use crate::domains::MAX_ALLOWED_DOMAINS;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn allowed_domains_work_pg() {
+        let data = pg::get_data().await;
+        allowed_domains_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn allowed_domains_work_maria() {
+        let data = maria::get_data().await;
+        allowed_domains_work(data).await;
+    }
+
+    async fn probe(data: &ArcData, key: &str) -> SitekeyProbe {
+        let app = get_app!(data).await;
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&V1_API_ROUTES.pow.get_probe(key))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        test::read_body_json(resp).await
+    }
+
+    async fn allowed_domains_work(data: ArcData) {
+        const NAME: &str = "alloweddomainsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "alloweddomainsuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.domains;
+
+        let mut payload = SetAllowedDomains {
+            key: token_key.key.clone(),
+            domains: vec!["not a domain".into()],
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::InvalidAllowedDomain,
+        )
+        .await;
+        payload.domains = vec!["example.com".into(); MAX_ALLOWED_DOMAINS + 1];
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::TooManyAllowedDomains,
+        )
+        .await;
+        payload.domains = vec!["https://Example.com/".into(), "example.org".into()];
+        payload.key = "nonexistent".into();
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::CaptchaNotFound,
+        )
+        .await;
+
+        // sitekeys without allowed domains can be embedded anywhere
+        let mut config = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: Some("https://unrelated.example.net".into()),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&config, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            probe(data, &token_key.key).await.allowed_origins,
+            vec![ANY_ORIGIN.to_string()]
+        );
+
+        payload.key = token_key.key.clone();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let key = DomainsKey {
+            key: token_key.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let domains: AllowedDomains = test::read_body_json(resp).await;
+        let expected = vec!["example.com".to_string(), "example.org".to_string()];
+        assert_eq!(domains.domains, expected);
+        assert!(!domains.required);
+        assert_eq!(probe(data, &token_key.key).await.allowed_origins, expected);
+
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.pow.get_config,
+            &config,
+            ServiceError::OriginNotAllowed,
+        )
+        .await;
+        config.origin = None;
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.pow.get_config,
+            &config,
+            ServiceError::OriginNotAllowed,
+        )
+        .await;
+        config.origin = Some("https://shop.example.com".into());
+        let resp = test::call_service(
+            &app,
+            post_request!(&config, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // new sitekeys need allowed domains when they are required
+        let mut settings = data.settings.clone();
+        settings.captcha.require_allowed_domains = true;
+        let required =
+            &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let (_, _, new_key) = add_levels_util(required, NAME, PASSWORD).await;
+        let mut config = GetConfigPayload {
+            key: new_key.key.clone(),
+            version: None,
+            origin: Some("https://example.com".into()),
+        };
+        bad_post_req_test_no_auth(
+            required,
+            V1_API_ROUTES.pow.get_config,
+            &config,
+            ServiceError::AllowedDomainsRequired,
+        )
+        .await;
+        assert!(probe(required, &new_key.key)
+            .await
+            .allowed_origins
+            .is_empty());
+
+        // grandfathered sitekeys serve challenges until their deadline
+        let deadline = OffsetDateTime::now_utc().unix_timestamp() + 3600;
+        data.db
+            .set_allowed_domains_deadline(&new_key.key, deadline)
+            .await
+            .unwrap();
+        let required_app = get_app!(required).await;
+        let resp = test::call_service(
+            &required_app,
+            post_request!(&config, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        config.key = "nonexistent".into();
+        bad_post_req_test_no_auth(
+            required,
+            V1_API_ROUTES.pow.get_config,
+            &config,
+            ServiceError::TokenNotFound,
+        )
+        .await;
+    }
+}
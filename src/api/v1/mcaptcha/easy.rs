@@ -4,15 +4,17 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_identity::Identity;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use libmcaptcha::{defense::Level, defense::LevelBuilder};
 use serde::{Deserialize, Serialize};
 
-use db_core::TrafficPattern;
+use db_core::{OrgRole, TrafficPattern};
 
 use super::create::{runner::create as create_runner, CreateCaptcha};
 use super::update::{runner::update_captcha as update_captcha_runner, UpdateCaptcha};
+use crate::api::v1::orgs::runners::require_sitekey_role;
 use crate::errors::*;
+use crate::sessions::CurrentSession;
 use crate::settings::DefaultDifficultyStrategy;
 use crate::AppData;
 
@@ -179,11 +181,13 @@ async fn calculate_with_percentile(
     wrap = "crate::api::v1::get_middleware()"
 )]
 async fn create(
+    req: HttpRequest,
     payload: web::Json<TrafficPatternRequest>,
     data: AppData,
     id: Identity,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
+    let session = CurrentSession::get(&req);
     let payload = payload.into_inner();
     let pattern = (&payload).into();
     let levels = if let Some(levels) = calculate_with_percentile(&data, &pattern).await?
@@ -199,7 +203,8 @@ async fn create(
         publish_benchmarks: payload.publish_benchmarks,
     };
 
-    let mcaptcha_config = create_runner(&msg, &data, &username).await?;
+    let mcaptcha_config =
+        create_runner(&msg, &data, &username, session.as_deref()).await?;
     data.db
         .add_traffic_pattern(&username, &mcaptcha_config.key, &pattern)
         .await?;
@@ -224,6 +229,7 @@ async fn update(
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
     let payload = payload.into_inner();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
     update_runner(&data, payload, username).await?;
     Ok(HttpResponse::Ok())
 }
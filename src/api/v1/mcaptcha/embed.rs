@@ -0,0 +1,110 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Snippets that embed the widget of a sitekey. See [crate::widget::embed]
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::OrgRole;
+
+use crate::api::v1::orgs::runners::require_sitekey_access;
+use crate::errors::*;
+use crate::widget::embed::EmbedSnippet;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Embed {
+        pub get: &'static str,
+    }
+
+    impl Embed {
+        pub const fn new() -> Self {
+            Self {
+                get: "/api/v1/mcaptcha/{key}/embed",
+            }
+        }
+
+        pub fn get_get_route(&self, key: &str) -> String {
+            self.get.replace("{key}", key)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
+
+/// snippet that embeds the widget of a sitekey
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.embed.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+    Ok(HttpResponse::Ok().json(EmbedSnippet::new(&data.settings, &key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn embed_works_pg() {
+        let data = pg::get_data().await;
+        embed_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn embed_works_maria() {
+        let data = maria::get_data().await;
+        embed_works(data).await;
+    }
+
+    async fn embed_works(data: ArcData) {
+        const NAME: &str = "embeduser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "embeduser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.embed;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_get_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let snippet: EmbedSnippet = test::read_body_json(resp).await;
+        assert_eq!(snippet, EmbedSnippet::new(&data.settings, &key.key));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_get_route("nonexistent"))
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
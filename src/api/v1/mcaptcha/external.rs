@@ -0,0 +1,410 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Idempotent sitekey management for infrastructure-as-code tools
+//!
+//! Sitekeys are addressed by an ID chosen by the caller. `PUT` creates the
+//! sitekey when it doesn't exist and updates it when it differs, so applying
+//! the same configuration twice is a no-op. Responses carry an `ETag` of the
+//! sitekey, which can be used with `If-Match` to guard against concurrent
+//! modification and with `If-None-Match: *` to only create.
+use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use db_core::OrgRole;
+use libmcaptcha::defense::Level;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::create::{runner as create_runner, CreateCaptcha};
+use super::update::{runner as update_runner, UpdateCaptcha};
+use crate::api::v1::orgs::runners::require_sitekey_role;
+use crate::errors::*;
+use crate::sessions::CurrentSession;
+use crate::AppData;
+
+/// maximum length of external IDs, in characters
+pub const MAX_EXTERNAL_ID_LEN: usize = 100;
+
+pub mod routes {
+    pub struct External {
+        pub by_id: &'static str,
+    }
+
+    impl External {
+        pub const fn new() -> Self {
+            Self {
+                by_id: "/api/v1/mcaptcha/by-external-id/{id}",
+            }
+        }
+
+        pub fn get_by_id(&self, id: &str) -> String {
+            self.by_id.replace("{id}", id)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(put);
+    cfg.service(get);
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// stable representation of a sitekey that is managed by external ID
+pub struct ExternalSitekey {
+    pub external_id: String,
+    pub key: String,
+    pub description: String,
+    pub duration: u32,
+    /// sorted by visitor threshold
+    pub levels: Vec<Level>,
+    pub publish_benchmarks: bool,
+}
+
+impl ExternalSitekey {
+    /// strong entity tag of the representation
+    pub fn etag(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap();
+        format!("\"{}\"", hex::encode(Sha256::digest(&json)))
+    }
+
+    /// does the sitekey match the desired configuration
+    fn matches(&self, data: &AppData, username: &str, payload: &CreateCaptcha) -> bool {
+        self.description == payload.description
+            && self.duration == payload.duration
+            && self.levels == sorted(&payload.levels)
+            && self.publish_benchmarks
+                == runner::publishes_benchmarks(data, username, payload)
+    }
+}
+
+fn sorted(levels: &[Level]) -> Vec<Level> {
+    let mut levels = levels.to_vec();
+    levels.sort_by_key(|l| l.visitor_threshold);
+    levels
+}
+
+/// does an `If-Match`/`If-None-Match` header value match `etag`
+fn etag_matches(
+    req: &HttpRequest,
+    name: header::HeaderName,
+    etag: Option<&str>,
+) -> Option<bool> {
+    let value = req.headers().get(name)?.to_str().unwrap_or_default();
+    Some(value.split(',').map(str::trim).any(|tag| {
+        (tag == "*" && etag.is_some()) || Some(tag.trim_start_matches("W/")) == etag
+    }))
+}
+
+fn respond(
+    mut resp: actix_web::HttpResponseBuilder,
+    sitekey: &ExternalSitekey,
+) -> HttpResponse {
+    resp.insert_header((header::ETAG, sitekey.etag()))
+        .json(sitekey)
+}
+
+/// create or update sitekey `id` to match the payload
+#[my_codegen::put(
+    path = "crate::V1_API_ROUTES.captcha.external.by_id",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn put(
+    path: web::Path<String>,
+    req: HttpRequest,
+    payload: web::Json<CreateCaptcha>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let external_id = path.into_inner();
+    runner::validate_external_id(&external_id)?;
+
+    let current = runner::get(&data, &username, &external_id).await?;
+    let etag = current.as_ref().map(|c| c.etag());
+    if etag_matches(&req, header::IF_MATCH, etag.as_deref()) == Some(false)
+        || etag_matches(&req, header::IF_NONE_MATCH, etag.as_deref()) == Some(true)
+    {
+        return Err(ServiceError::PreconditionFailed);
+    }
+
+    match current {
+        None => {
+            let session = CurrentSession::get(&req);
+            let sitekey = runner::create(
+                &data,
+                &username,
+                &external_id,
+                &payload,
+                session.as_deref(),
+            )
+            .await?;
+            Ok(respond(HttpResponse::Created(), &sitekey))
+        }
+        Some(current) if current.matches(&data, &username, &payload) => {
+            Ok(respond(HttpResponse::Ok(), &current))
+        }
+        Some(current) => {
+            let update = UpdateCaptcha {
+                levels: payload.levels.clone(),
+                duration: payload.duration,
+                description: payload.description.clone(),
+                key: current.key,
+                publish_benchmarks: payload.publish_benchmarks,
+            };
+            require_sitekey_role(&data, &update.key, &username, OrgRole::Editor).await?;
+            update_runner::update_captcha(&update, &data, &username).await?;
+            let sitekey = runner::get(&data, &username, &external_id)
+                .await?
+                .ok_or(ServiceError::CaptchaNotFound)?;
+            Ok(respond(HttpResponse::Ok(), &sitekey))
+        }
+    }
+}
+
+/// get sitekey `id`
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.external.by_id",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    path: web::Path<String>,
+    req: HttpRequest,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let external_id = path.into_inner();
+    runner::validate_external_id(&external_id)?;
+    let sitekey = runner::get(&data, &username, &external_id)
+        .await?
+        .ok_or(ServiceError::CaptchaNotFound)?;
+    let etag = sitekey.etag();
+    if etag_matches(&req, header::IF_NONE_MATCH, Some(&etag)) == Some(true) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header((header::ETAG, etag))
+            .finish());
+    }
+    Ok(respond(HttpResponse::Ok(), &sitekey))
+}
+
+pub mod runner {
+    use db_core::errors::DBError;
+
+    use super::*;
+
+    pub fn validate_external_id(external_id: &str) -> ServiceResult<()> {
+        let len = external_id.chars().count();
+        if len == 0 || len > MAX_EXTERNAL_ID_LEN {
+            return Err(ServiceError::InvalidExternalId);
+        }
+        Ok(())
+    }
+
+    /// benchmarks are published only when analytics are enabled, and never
+    /// for the shared demo account
+    pub fn publishes_benchmarks(
+        data: &AppData,
+        username: &str,
+        payload: &CreateCaptcha,
+    ) -> bool {
+        payload.publish_benchmarks
+            && data.settings.features.analytics
+            && !crate::demo::is_demo_user(data, username)
+    }
+
+    /// get sitekey by external ID, if it exists
+    pub async fn get(
+        data: &AppData,
+        username: &str,
+        external_id: &str,
+    ) -> ServiceResult<Option<ExternalSitekey>> {
+        let captcha = match data
+            .db
+            .get_captcha_by_external_id(username, external_id)
+            .await
+        {
+            Ok(captcha) => captcha,
+            Err(DBError::CaptchaNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let levels = data
+            .db
+            .get_captcha_levels(Some(username), &captcha.key)
+            .await?;
+        let publish_benchmarks =
+            data.db.analytics_captcha_is_published(&captcha.key).await?;
+        Ok(Some(ExternalSitekey {
+            external_id: external_id.to_string(),
+            description: captcha.description,
+            duration: captcha.duration as u32,
+            levels: sorted(&levels),
+            publish_benchmarks,
+            key: captcha.key,
+        }))
+    }
+
+    /// create sitekey with external ID. When a concurrent request claims the
+    /// ID first, the new sitekey is deleted again
+    pub async fn create(
+        data: &AppData,
+        username: &str,
+        external_id: &str,
+        payload: &CreateCaptcha,
+        session: Option<&str>,
+    ) -> ServiceResult<ExternalSitekey> {
+        let details = create_runner::create(payload, data, username, session).await?;
+        if let Err(e) = data
+            .db
+            .set_captcha_external_id(username, &details.key, Some(external_id))
+            .await
+        {
+            data.db.delete_captcha(username, &details.key).await?;
+            return Err(e.into());
+        }
+        get(data, username, external_id)
+            .await?
+            .ok_or(ServiceError::CaptchaNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn external_sitekeys_work_pg() {
+        let data = pg::get_data().await;
+        external_sitekeys_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn external_sitekeys_work_maria() {
+        let data = maria::get_data().await;
+        external_sitekeys_work(data).await;
+    }
+
+    async fn external_sitekeys_work(data: ArcData) {
+        const NAME: &str = "externalsitekeyuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "externalsitekeyuser@a.com";
+        const ID: &str = "prod-login-form";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let route = V1_API_ROUTES.captcha.external.get_by_id(ID);
+
+        let put = |payload: &CreateCaptcha| {
+            test::TestRequest::put()
+                .uri(&route)
+                .cookie(cookies.clone())
+                .set_json(payload)
+        };
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&route)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // created on first PUT
+        let mut payload = CreateCaptcha {
+            levels: vec![L2, L1],
+            duration: 30,
+            description: "external".into(),
+            publish_benchmarks: false,
+        };
+        let resp = test::call_service(&app, put(&payload).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        let created: ExternalSitekey = test::read_body_json(resp).await;
+        assert_eq!(created.external_id, ID);
+        assert_eq!(created.levels, vec![L1, L2]);
+        assert_eq!(etag.to_str().unwrap(), created.etag());
+
+        // reapplying is a no-op
+        let resp = test::call_service(&app, put(&payload).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &etag);
+        assert_eq!(data.db.get_all_user_captchas(NAME).await.unwrap().len(), 1);
+
+        // only create
+        let resp = test::call_service(
+            &app,
+            put(&payload)
+                .insert_header((header::IF_NONE_MATCH, "*"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&route)
+                .cookie(cookies.clone())
+                .insert_header((header::IF_NONE_MATCH, etag.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        // updated in place when the configuration changes
+        payload.duration = 45;
+        let resp = test::call_service(
+            &app,
+            put(&payload)
+                .insert_header((header::IF_MATCH, etag.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let updated: ExternalSitekey = test::read_body_json(resp).await;
+        assert_eq!(updated.key, created.key);
+        assert_eq!(updated.duration, 45);
+        assert_ne!(updated.etag(), created.etag());
+
+        // stale ETags are rejected
+        payload.duration = 60;
+        let resp = test::call_service(
+            &app,
+            put(&payload)
+                .insert_header((header::IF_MATCH, etag))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        let current = data
+            .db
+            .get_captcha_config(NAME, &updated.key)
+            .await
+            .unwrap();
+        assert_eq!(current.duration, 45);
+
+        let long = "a".repeat(MAX_EXTERNAL_ID_LEN + 1);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::put()
+                .uri(&V1_API_ROUTES.captcha.external.get_by_id(&long))
+                .cookie(cookies.clone())
+                .set_json(&payload)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
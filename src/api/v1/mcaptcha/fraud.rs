@@ -0,0 +1,253 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Configure fraud heuristics of sitekeys. See [crate::fraud] for the signals
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{FraudThresholds, OrgRole};
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::orgs::runners::{require_sitekey_access, require_sitekey_role};
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Fraud {
+        pub set: &'static str,
+        pub get: &'static str,
+        pub delete: &'static str,
+    }
+
+    impl Fraud {
+        pub const fn new() -> Self {
+            Self {
+                set: "/api/v1/mcaptcha/fraud/set",
+                get: "/api/v1/mcaptcha/fraud/get",
+                delete: "/api/v1/mcaptcha/fraud/delete",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(set);
+    cfg.service(get);
+    cfg.service(delete);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetFraudThresholds {
+    pub key: String,
+    #[serde(flatten)]
+    pub thresholds: FraudThresholds,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FraudKey {
+    pub key: String,
+}
+
+/// enable fraud heuristics of a sitekey, or update their thresholds
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.fraud.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    payload: web::Json<SetFraudThresholds>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .set_fraud_thresholds(&username, &payload.key, &payload.thresholds)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get thresholds of fraud heuristics of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.fraud.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    payload: web::Json<FraudKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let thresholds = data.db.get_fraud_thresholds(&payload.key).await?;
+    Ok(HttpResponse::Ok().json(thresholds))
+}
+
+/// disable fraud heuristics of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.fraud.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<FraudKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .delete_fraud_thresholds(&username, &payload.key)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use libmcaptcha::pow::PoWConfig;
+
+    use super::*;
+    use crate::api::v1::pow::get_config::GetConfigPayload;
+    use crate::api::v1::pow::verify_pow::ApiWork;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn fraud_heuristics_work_pg() {
+        let data = pg::get_data().await;
+        fraud_heuristics_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn fraud_heuristics_work_maria() {
+        let data = maria::get_data().await;
+        fraud_heuristics_work(data).await;
+    }
+
+    async fn fraud_heuristics_work(data: ArcData) {
+        const NAME: &str = "fraudheuristicsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "fraudheuristicsuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.fraud;
+
+        let key = FraudKey {
+            key: token_key.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let payload = SetFraudThresholds {
+            key: "nonexistent".into(),
+            thresholds: FraudThresholds {
+                max_hash_rate: 1,
+                nonce_outlier_factor: 0,
+                repeated_result_limit: 0,
+            },
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::CaptchaNotFound,
+        )
+        .await;
+
+        // one hash per second makes any solution implausibly fast
+        let payload = SetFraudThresholds {
+            key: token_key.key.clone(),
+            ..payload
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let thresholds: FraudThresholds = test::read_body_json(resp).await;
+        assert_eq!(thresholds, payload.thresholds);
+
+        let get_config_payload = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        let config: PoWConfig = test::read_body_json(resp).await;
+        let pow = mcaptcha_pow_sha256::ConfigBuilder::default()
+            .salt(config.salt)
+            .build()
+            .unwrap();
+        let work = pow
+            .prove_work(&config.string, config.difficulty_factor)
+            .unwrap();
+        let work = ApiWork {
+            string: config.string,
+            result: work.result,
+            nonce: work.nonce,
+            key: token_key.key.clone(),
+            time: Some(1),
+            worker_type: Some("wasm".into()),
+            correlation_id: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&work, V1_API_ROUTES.pow.verify_pow).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let notifications = data.db.get_all_unread_notifications(NAME).await.unwrap();
+        assert!(notifications
+            .iter()
+            .any(|n| n.heading.as_deref() == Some("Suspected fraud")));
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.delete,
+            &key,
+            ServiceError::FraudThresholdsNotFound,
+        )
+        .await;
+    }
+}
@@ -8,7 +8,11 @@ use actix_web::{web, HttpResponse, Responder};
 
 use serde::{Deserialize, Serialize};
 
+use db_core::OrgRole;
+
 use super::create::MCaptchaDetails;
+use crate::api::v1::orgs::runners::require_sitekey_role;
+use crate::api::v2::compat::SuccessorSitekey;
 use crate::errors::*;
 use crate::AppData;
 
@@ -22,11 +26,15 @@ pub async fn get_captcha(
     id: Identity,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Viewer).await?;
     let levels = data
         .db
         .get_captcha_levels(Some(&username), &payload.key)
         .await?;
-    Ok(HttpResponse::Ok().json(levels))
+    let mut resp = HttpResponse::Ok().json(levels);
+    resp.extensions_mut()
+        .insert(SuccessorSitekey(payload.into_inner().key));
+    Ok(resp)
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
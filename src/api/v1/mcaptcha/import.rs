@@ -0,0 +1,499 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Import sitekeys from other CAPTCHA providers' site exports
+use actix_identity::Identity;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use libmcaptcha::defense::Level;
+use serde::{Deserialize, Serialize};
+
+use db_core::TrafficPattern;
+
+use super::create::runner::{
+    create as create_runner, validate_captcha, MAX_DESCRIPTION_LEN,
+};
+use super::create::{CreateCaptcha, MCaptchaDetails};
+use crate::domains::normalize_all;
+use crate::errors::*;
+use crate::sessions::CurrentSession;
+use crate::settings::DefaultDifficultyStrategy;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Import {
+        pub import: &'static str,
+    }
+
+    impl Import {
+        pub const fn new() -> Self {
+            Self {
+                import: "/api/v1/mcaptcha/import",
+            }
+        }
+    }
+}
+
+/// sites that are imported at once
+pub const MAX_IMPORT_SITES: usize = 100;
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(import);
+}
+
+/// Difficulty preset that imported sites are mapped to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DifficultyPreset {
+    Easy,
+    Moderate,
+    Difficult,
+}
+
+impl DifficultyPreset {
+    /// multiplier applied to the instance's default difficulty strategy
+    fn multiplier(&self) -> u32 {
+        match self {
+            Self::Easy => 1,
+            Self::Moderate => 2,
+            Self::Difficult => 4,
+        }
+    }
+
+    /// reCAPTCHA v3 score threshold: higher thresholds reject more traffic
+    pub fn from_recaptcha_threshold(threshold: f32) -> Self {
+        if threshold < 0.4 {
+            Self::Easy
+        } else if threshold < 0.7 {
+            Self::Moderate
+        } else {
+            Self::Difficult
+        }
+    }
+
+    /// hCaptcha passive difficulty setting
+    pub fn from_hcaptcha_difficulty(difficulty: &str) -> Self {
+        match difficulty.to_lowercase().as_str() {
+            "moderate" => Self::Moderate,
+            "difficult" | "always_on" => Self::Difficult,
+            _ => Self::Easy,
+        }
+    }
+}
+
+/// Site entry from a reCAPTCHA admin console export
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecaptchaSite {
+    pub label: String,
+    pub domains: Vec<String>,
+    /// score threshold; defaults to Google's recommended 0.5 when absent
+    pub score_threshold: Option<f32>,
+}
+
+/// Site entry from an hCaptcha dashboard export
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HcaptchaSite {
+    pub name: String,
+    pub hostnames: Vec<String>,
+    pub difficulty: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "format", content = "sites", rename_all = "lowercase")]
+pub enum ImportSites {
+    Recaptcha(Vec<RecaptchaSite>),
+    Hcaptcha(Vec<HcaptchaSite>),
+}
+
+/// Site from an export, normalised to mCaptcha's vocabulary
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImportedSite {
+    pub description: String,
+    pub preset: DifficultyPreset,
+    /// domains of the site, which become the allowed domains of its sitekey
+    pub domains: Vec<String>,
+}
+
+impl ImportSites {
+    pub fn normalize(&self) -> Vec<ImportedSite> {
+        fn describe(label: &str, domains: &[String]) -> String {
+            let label = label.trim();
+            let description = if !label.is_empty() {
+                label
+            } else if let Some(domain) = domains.first() {
+                domain
+            } else {
+                "imported sitekey"
+            };
+            description.chars().take(MAX_DESCRIPTION_LEN).collect()
+        }
+
+        match self {
+            Self::Recaptcha(sites) => sites
+                .iter()
+                .map(|s| ImportedSite {
+                    description: describe(&s.label, &s.domains),
+                    preset: DifficultyPreset::from_recaptcha_threshold(
+                        s.score_threshold.unwrap_or(0.5),
+                    ),
+                    domains: s.domains.clone(),
+                })
+                .collect(),
+            Self::Hcaptcha(sites) => sites
+                .iter()
+                .map(|s| ImportedSite {
+                    description: describe(&s.name, &s.hostnames),
+                    preset: DifficultyPreset::from_hcaptcha_difficulty(
+                        s.difficulty.as_deref().unwrap_or_default(),
+                    ),
+                    domains: s.hostnames.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ImportRequest {
+    #[serde(flatten)]
+    pub sites: ImportSites,
+    /// traffic pattern applied to all imported sitekeys
+    pub avg_traffic: u32,
+    pub peak_sustainable_traffic: u32,
+    pub broke_my_site_traffic: Option<u32>,
+    pub publish_benchmarks: bool,
+}
+
+impl From<&ImportRequest> for TrafficPattern {
+    fn from(r: &ImportRequest) -> Self {
+        TrafficPattern {
+            avg_traffic: r.avg_traffic,
+            peak_sustainable_traffic: r.peak_sustainable_traffic,
+            broke_my_site_traffic: r.broke_my_site_traffic,
+        }
+    }
+}
+
+/// Compute levels for a preset by scaling the default difficulty strategy
+pub fn calculate(
+    tp: &TrafficPattern,
+    strategy: &DefaultDifficultyStrategy,
+    preset: DifficultyPreset,
+) -> ServiceResult<Vec<Level>> {
+    let m = preset.multiplier();
+    let mut scaled = strategy.clone();
+    scaled.avg_traffic_difficulty = strategy.avg_traffic_difficulty.saturating_mul(m);
+    scaled.peak_sustainable_traffic_difficulty = strategy
+        .peak_sustainable_traffic_difficulty
+        .saturating_mul(m);
+    scaled.broke_my_site_traffic_difficulty =
+        strategy.broke_my_site_traffic_difficulty.saturating_mul(m);
+    super::easy::calculate(tp, &scaled)
+}
+
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.import.import",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn import(
+    req: HttpRequest,
+    payload: web::Json<ImportRequest>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let session = CurrentSession::get(&req);
+    let resp = import_runner(&data, &payload, &username, session.as_deref()).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// sitekey of an imported site, ready to be created
+struct PreparedSite {
+    captcha: CreateCaptcha,
+    domains: Vec<String>,
+}
+
+/// create the sitekeys of all sites of an import, or none of them: sites are
+/// validated, and the captcha quota checked for all of them, before anything
+/// is created, and sitekeys that were created are deleted when creating a
+/// later one fails
+pub async fn import_runner(
+    data: &AppData,
+    payload: &ImportRequest,
+    username: &str,
+    session: Option<&str>,
+) -> ServiceResult<Vec<MCaptchaDetails>> {
+    let pattern: TrafficPattern = payload.into();
+    let strategy = &data.settings.captcha.default_difficulty_strategy;
+
+    let normalized = payload.sites.normalize();
+    if normalized.len() > MAX_IMPORT_SITES {
+        return Err(ServiceError::TooManyImportSites);
+    }
+
+    let mut sites = Vec::with_capacity(normalized.len());
+    for site in normalized {
+        let levels = calculate(&pattern, strategy, site.preset)?;
+        validate_captcha(&levels, strategy.duration, &site.description)?;
+        sites.push(PreparedSite {
+            captcha: CreateCaptcha {
+                levels,
+                duration: strategy.duration,
+                description: site.description,
+                publish_benchmarks: payload.publish_benchmarks,
+            },
+            domains: normalize_all(&site.domains)?,
+        });
+    }
+    crate::quotas::check_captcha_quota(data, username, sites.len()).await?;
+
+    let mut created = Vec::with_capacity(sites.len());
+    for site in sites.iter() {
+        let res = create_site(data, site, &pattern, username, session, &mut created);
+        if let Err(e) = res.await {
+            roll_back(data, username, &created).await;
+            return Err(e);
+        }
+    }
+
+    Ok(created)
+}
+
+/// create the sitekey of an imported site, adding it to `created` as soon as
+/// it exists
+async fn create_site(
+    data: &AppData,
+    site: &PreparedSite,
+    pattern: &TrafficPattern,
+    username: &str,
+    session: Option<&str>,
+    created: &mut Vec<MCaptchaDetails>,
+) -> ServiceResult<()> {
+    let mcaptcha_config = create_runner(&site.captcha, data, username, session).await?;
+    let key = mcaptcha_config.key.clone();
+    created.push(mcaptcha_config);
+    data.db.add_traffic_pattern(username, &key, pattern).await?;
+    if !site.domains.is_empty() {
+        data.db
+            .set_allowed_domains(username, &key, &site.domains)
+            .await?;
+    }
+    Ok(())
+}
+
+/// delete sitekeys that were created by an import that failed
+async fn roll_back(data: &AppData, username: &str, created: &[MCaptchaDetails]) {
+    for c in created.iter() {
+        if let Err(e) = data.db.delete_captcha(username, &c.key).await {
+            log::error!("Error while rolling back import of sitekey {}: {e}", c.key);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::ROUTES;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn import_presets_work() {
+        assert_eq!(
+            DifficultyPreset::from_recaptcha_threshold(0.1),
+            DifficultyPreset::Easy
+        );
+        assert_eq!(
+            DifficultyPreset::from_recaptcha_threshold(0.5),
+            DifficultyPreset::Moderate
+        );
+        assert_eq!(
+            DifficultyPreset::from_recaptcha_threshold(0.9),
+            DifficultyPreset::Difficult
+        );
+        assert_eq!(
+            DifficultyPreset::from_hcaptcha_difficulty("Always_On"),
+            DifficultyPreset::Difficult
+        );
+        assert_eq!(
+            DifficultyPreset::from_hcaptcha_difficulty(""),
+            DifficultyPreset::Easy
+        );
+
+        let sites = ImportSites::Hcaptcha(vec![HcaptchaSite {
+            name: "".into(),
+            hostnames: vec!["example.com".into()],
+            difficulty: Some("moderate".into()),
+        }]);
+        assert_eq!(
+            sites.normalize(),
+            vec![ImportedSite {
+                description: "example.com".into(),
+                preset: DifficultyPreset::Moderate,
+                domains: vec!["example.com".into()],
+            }]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn import_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        import_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn import_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        import_works(data).await;
+    }
+
+    pub async fn import_works(data: ArcData) {
+        const NAME: &str = "importsitekeyuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "importsitekeyuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+
+        let (_creds, signin_resp) =
+            register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let payload = ImportRequest {
+            sites: ImportSites::Recaptcha(vec![
+                RecaptchaSite {
+                    label: "blog".into(),
+                    domains: vec!["blog.example.com".into()],
+                    score_threshold: Some(0.2),
+                },
+                RecaptchaSite {
+                    label: "shop".into(),
+                    domains: vec!["shop.example.com".into()],
+                    score_threshold: Some(0.9),
+                },
+            ]),
+            avg_traffic: 100_000,
+            peak_sustainable_traffic: 1_000_000,
+            broke_my_site_traffic: Some(10_000_000),
+            publish_benchmarks: false,
+        };
+
+        let import_resp = test::call_service(
+            &app,
+            post_request!(&payload, ROUTES.captcha.import.import)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(import_resp.status(), StatusCode::OK);
+        let imported: Vec<MCaptchaDetails> = test::read_body_json(import_resp).await;
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].name, "blog");
+        assert_eq!(imported[1].name, "shop");
+
+        let strategy = &data.settings.captcha.default_difficulty_strategy;
+        let pattern: TrafficPattern = (&payload).into();
+        for (key, preset) in [
+            (&imported[0], DifficultyPreset::Easy),
+            (&imported[1], DifficultyPreset::Difficult),
+        ] {
+            let get_level_resp = test::call_service(
+                &app,
+                post_request!(key, ROUTES.captcha.get)
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(get_level_resp.status(), StatusCode::OK);
+            let res_levels: Vec<Level> = test::read_body_json(get_level_resp).await;
+            assert_eq!(res_levels, calculate(&pattern, strategy, preset).unwrap());
+            assert_eq!(
+                data.db.get_traffic_pattern(NAME, &key.key).await.unwrap(),
+                pattern
+            );
+        }
+        assert_eq!(
+            data.db.get_allowed_domains(&imported[0].key).await.unwrap(),
+            vec!["blog.example.com"]
+        );
+        assert_eq!(
+            data.db.get_allowed_domains(&imported[1].key).await.unwrap(),
+            vec!["shop.example.com"]
+        );
+
+        // invalid sites are caught before anything is created
+        let mut invalid = payload.clone();
+        invalid.sites = ImportSites::Hcaptcha(vec![
+            HcaptchaSite {
+                name: "valid".into(),
+                hostnames: vec!["valid.example.com".into()],
+                difficulty: None,
+            },
+            HcaptchaSite {
+                name: "invalid".into(),
+                hostnames: vec!["not a domain".into()],
+                difficulty: None,
+            },
+        ]);
+        let app_data = AppData::new(data.clone());
+        let err = import_runner(&app_data, &invalid, NAME, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServiceError::InvalidAllowedDomain);
+        assert_eq!(data.db.count_user_captchas(NAME).await.unwrap(), 2);
+
+        // imports are limited in size
+        let mut large = payload.clone();
+        let site = HcaptchaSite {
+            name: "large".into(),
+            hostnames: vec![],
+            difficulty: None,
+        };
+        large.sites = ImportSites::Hcaptcha(vec![site; MAX_IMPORT_SITES + 1]);
+        let err = import_runner(&app_data, &large, NAME, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServiceError::TooManyImportSites);
+
+        // and the quota has to hold all of their sitekeys
+        let mut settings = data.settings.clone();
+        settings.quotas.max_captchas = 3;
+        let quota = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let quota = AppData::new(quota);
+        let err = import_runner(&quota, &payload, NAME, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServiceError::CaptchaQuotaReached);
+        assert_eq!(data.db.count_user_captchas(NAME).await.unwrap(), 2);
+
+        // sitekeys created before a failure are deleted
+        let mut settings = data.settings.clone();
+        settings.captcha.unique_names = true;
+        let unique =
+            crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let unique = AppData::new(unique);
+        let mut clashing = payload.clone();
+        clashing.sites = ImportSites::Hcaptcha(vec![
+            HcaptchaSite {
+                name: "new".into(),
+                hostnames: vec![],
+                difficulty: None,
+            },
+            HcaptchaSite {
+                name: "blog".into(),
+                hostnames: vec![],
+                difficulty: None,
+            },
+        ]);
+        let err = import_runner(&unique, &clashing, NAME, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, ServiceError::DuplicateCaptchaName);
+        assert_eq!(data.db.count_user_captchas(NAME).await.unwrap(), 2);
+    }
+}
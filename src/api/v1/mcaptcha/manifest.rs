@@ -0,0 +1,197 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Machine-readable manifest of a user's sitekeys, for configuration
+//! management tools that templatize widget deployments across many sites.
+//! The manifest is generated on every request, so it always reflects the
+//! current sitekeys. Account secrets aren't included, and sitekeys aren't bound
+//! to domains, so none are listed.
+use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::{HttpResponse, Responder};
+use libmcaptcha::defense::Level;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Manifest {
+        pub manifest: &'static str,
+    }
+
+    impl Manifest {
+        pub const fn new() -> Self {
+            Self {
+                manifest: "/api/v1/mcaptcha/manifest",
+            }
+        }
+    }
+}
+
+/// name of the file that the manifest is downloaded as
+pub const MANIFEST_FILENAME: &str = "mcaptcha-sitekeys.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+/// endpoints that widgets and site backends talk to
+pub struct Endpoints {
+    /// PoW configuration, fetched by the widget
+    pub config: String,
+    /// PoW verification, called by the widget
+    pub verify: String,
+    /// validation token verification, called by site backends
+    pub siteverify: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ManifestSitekey {
+    pub key: String,
+    pub description: String,
+    /// cool down duration, in seconds
+    pub duration: i32,
+    /// validation tokens can only be verified once
+    pub strict_tokens: bool,
+    /// URL of the widget, to be embedded as an iframe
+    pub widget: String,
+    pub levels: Vec<Level>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SitekeyManifest {
+    /// public URL of the instance
+    pub instance: String,
+    /// unix timestamp at which the manifest was generated
+    pub generated: i64,
+    pub endpoints: Endpoints,
+    pub sitekeys: Vec<ManifestSitekey>,
+}
+
+/// generate manifest of all sitekeys of a user
+pub async fn generate(data: &AppData, username: &str) -> ServiceResult<SitekeyManifest> {
+    let instance = data.settings.server.url();
+    let routes = &crate::V1_API_ROUTES.pow;
+    let endpoints = Endpoints {
+        config: format!("{instance}{}", routes.get_config),
+        verify: format!("{instance}{}", routes.verify_pow),
+        siteverify: format!("{instance}{}", routes.validate_captcha_token),
+    };
+
+    let captchas = data.db.get_all_user_captchas(username).await?;
+    let mut sitekeys = Vec::with_capacity(captchas.len());
+    for c in captchas.into_iter() {
+        let levels = data.db.get_captcha_levels(Some(username), &c.key).await?;
+        let strict_tokens = data.db.captcha_strict_tokens(&c.key).await?;
+        let widget = format!(
+            "{instance}{}/?sitekey={}",
+            crate::WIDGET_ROUTES.verification_widget,
+            c.key
+        );
+        sitekeys.push(ManifestSitekey {
+            key: c.key,
+            description: c.description,
+            duration: c.duration,
+            strict_tokens,
+            widget,
+            levels,
+        });
+    }
+
+    Ok(SitekeyManifest {
+        instance,
+        generated: OffsetDateTime::now_utc().unix_timestamp(),
+        endpoints,
+        sitekeys,
+    })
+}
+
+/// download manifest of all sitekeys
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.manifest.manifest",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn manifest(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let manifest = generate(&data, &username).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{MANIFEST_FILENAME}\""),
+        ))
+        .json(manifest))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn manifest_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        manifest_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn manifest_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        manifest_works(data).await;
+    }
+
+    pub async fn manifest_works(data: ArcData) {
+        const NAME: &str = "manifestuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "manifestuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.captcha.manifest.manifest)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.captcha.manifest.manifest)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let disposition = resp.headers().get(header::CONTENT_DISPOSITION).unwrap();
+        assert!(disposition.to_str().unwrap().contains(MANIFEST_FILENAME));
+        let manifest: SitekeyManifest = test::read_body_json(resp).await;
+
+        let instance = data.settings.server.url();
+        assert_eq!(manifest.instance, instance);
+        assert_eq!(
+            manifest.endpoints.siteverify,
+            format!("{instance}{}", V1_API_ROUTES.pow.validate_captcha_token)
+        );
+        assert_eq!(manifest.sitekeys.len(), 1);
+        let sitekey = &manifest.sitekeys[0];
+        assert_eq!(sitekey.key, token_key.key);
+        assert!(sitekey
+            .widget
+            .ends_with(&format!("?sitekey={}", token_key.key)));
+        assert_eq!(sitekey.levels.len(), 2);
+        assert!(!sitekey.strict_tokens);
+    }
+}
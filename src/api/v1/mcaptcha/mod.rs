@@ -3,14 +3,27 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+pub mod alerts;
+pub mod burst;
 pub mod create;
+pub mod decay;
 pub mod delete;
+pub mod domains;
 pub mod easy;
+pub mod embed;
+pub mod external;
+pub mod fraud;
 pub mod get;
+pub mod import;
+pub mod manifest;
+pub mod publish;
+pub mod recommendation;
 pub mod stats;
 #[cfg(test)]
 pub mod test;
+pub mod theme;
 pub mod update;
+pub mod webhook;
 
 pub fn get_random(len: usize) -> String {
     use std::iter;
@@ -27,18 +40,50 @@ pub fn get_random(len: usize) -> String {
 }
 
 pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
+    alerts::services(cfg);
+    burst::services(cfg);
+    decay::services(cfg);
+    domains::services(cfg);
     easy::services(cfg);
+    embed::services(cfg);
+    external::services(cfg);
+    fraud::services(cfg);
+    import::services(cfg);
+    publish::services(cfg);
+    recommendation::services(cfg);
+    theme::services(cfg);
+    webhook::services(cfg);
     cfg.service(stats::get);
+    cfg.service(stats::export);
+    cfg.service(stats::series);
+    cfg.service(stats::percentiles);
+    cfg.service(manifest::manifest);
     cfg.service(create::create);
     cfg.service(get::get_captcha);
     cfg.service(update::update_key);
     cfg.service(update::update_captcha);
+    cfg.service(update::update_strict_tokens);
+    cfg.service(update::update_invisible_mode);
+    cfg.service(update::update_fallback);
     cfg.service(delete::delete);
 }
 
 pub mod routes {
+    use super::alerts::routes::Alerts;
+    use super::burst::routes::Burst;
+    use super::decay::routes::Decay;
+    use super::domains::routes::Domains;
     use super::easy::routes::Easy;
+    use super::embed::routes::Embed;
+    use super::external::routes::External;
+    use super::fraud::routes::Fraud;
+    use super::import::routes::Import;
+    use super::manifest::routes::Manifest;
+    use super::publish::routes::Publish;
+    use super::recommendation::routes::Recommendation;
     use super::stats::routes::Stats;
+    use super::theme::routes::Theme;
+    use super::webhook::routes::Webhook;
 
     pub struct Captcha {
         pub create: &'static str,
@@ -46,8 +91,24 @@ pub mod routes {
         pub get: &'static str,
         pub delete: &'static str,
         pub update_key: &'static str,
+        pub update_strict: &'static str,
+        pub update_invisible: &'static str,
+        pub update_fallback: &'static str,
+        pub alerts: Alerts,
+        pub burst: Burst,
+        pub decay: Decay,
+        pub domains: Domains,
         pub easy: Easy,
+        pub embed: Embed,
+        pub external: External,
+        pub fraud: Fraud,
+        pub import: Import,
+        pub manifest: Manifest,
+        pub publish: Publish,
+        pub recommendation: Recommendation,
         pub stats: Stats,
+        pub theme: Theme,
+        pub webhook: Webhook,
     }
 
     impl Captcha {
@@ -57,9 +118,25 @@ pub mod routes {
                 update: "/api/v1/mcaptcha/update",
                 get: "/api/v1/mcaptcha/get",
                 update_key: "/api/v1/mcaptcha/update/key",
+                update_strict: "/api/v1/mcaptcha/update/strict",
+                update_invisible: "/api/v1/mcaptcha/update/invisible",
+                update_fallback: "/api/v1/mcaptcha/update/fallback",
                 delete: "/api/v1/mcaptcha/delete",
+                alerts: Alerts::new(),
+                burst: Burst::new(),
+                decay: Decay::new(),
+                domains: Domains::new(),
                 easy: Easy::new(),
+                embed: Embed::new(),
+                external: External::new(),
+                fraud: Fraud::new(),
+                import: Import::new(),
+                manifest: Manifest::new(),
+                publish: Publish::new(),
+                recommendation: Recommendation::new(),
                 stats: Stats::new(),
+                theme: Theme::new(),
+                webhook: Webhook::new(),
             }
         }
     }
@@ -0,0 +1,299 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Publish benchmarks of sitekeys to mCaptcha/survey, stop publishing them and
+//! purge the ones already uploaded. See [crate::survey]
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::OrgRole;
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::orgs::runners::require_sitekey_access;
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Publish {
+        pub list: &'static str,
+        pub set: &'static str,
+        pub purge: &'static str,
+    }
+
+    impl Publish {
+        pub const fn new() -> Self {
+            Self {
+                list: "/api/v1/mcaptcha/publish",
+                set: "/api/v1/mcaptcha/{key}/publish",
+                purge: "/api/v1/mcaptcha/{key}/publish/purge",
+            }
+        }
+
+        pub fn get_set_route(&self, key: &str) -> String {
+            self.set.replace("{key}", key)
+        }
+
+        pub fn get_purge_route(&self, key: &str) -> String {
+            self.purge.replace("{key}", key)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(set);
+    cfg.service(purge);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// publication status of the benchmarks of a sitekey
+pub struct Publication {
+    pub key: String,
+    pub name: String,
+    pub published: bool,
+    /// ID that the benchmarks are published under
+    pub psuedo_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetPublish {
+    pub publish: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// survey nodes that deleted the benchmarks of a sitekey, and the ones that
+/// couldn't be asked to or failed. The sitekey stops publishing only when none
+/// failed
+pub struct PurgeReport {
+    pub purged: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+async fn psuedo_id(data: &AppData, key: &str) -> ServiceResult<Option<String>> {
+    match data.db.analytics_get_psuedo_id_from_capmaign_id(key).await {
+        Ok(id) => Ok(Some(id)),
+        Err(db_core::errors::DBError::CaptchaNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// stop publishing benchmarks of a sitekey. Its analytics are deleted along
+/// with its psuedo ID, like when publishing is turned off while editing it
+async fn unpublish(data: &AppData, key: &str) -> ServiceResult<()> {
+    if let Some(id) = psuedo_id(data, key).await? {
+        data.survey_secrets.rm_cursors(&id);
+    }
+    data.db
+        .analytics_delete_all_records_for_campaign(key)
+        .await?;
+    data.analytics_quotas.invalidate(key);
+    data.maintenance.record_deletion();
+    Ok(())
+}
+
+/// publication status of the benchmarks of all sitekeys of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.publish.list",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn list(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let mut publications = Vec::new();
+    for c in data.db.get_all_user_captchas(&username).await? {
+        let psuedo_id = psuedo_id(&data, &c.key).await?;
+        publications.push(Publication {
+            key: c.key,
+            name: c.description,
+            published: psuedo_id.is_some(),
+            psuedo_id,
+        });
+    }
+    Ok(HttpResponse::Ok().json(publications))
+}
+
+/// publish, or stop publishing, benchmarks of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.publish.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    path: web::Path<String>,
+    payload: web::Json<SetPublish>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Editor).await?;
+    if payload.publish {
+        if !data.settings.features.analytics {
+            return Err(ServiceError::FeatureDisabled);
+        }
+        // benchmarks from the shared demo account aren't trustworthy
+        if crate::demo::is_demo_user(&data, &username) {
+            return Err(ServiceError::DemoUserRestricted);
+        }
+        data.db
+            .analytics_create_psuedo_id_if_not_exists(&key)
+            .await?;
+    } else {
+        unpublish(&data, &key).await?;
+    }
+    Ok(HttpResponse::Ok())
+}
+
+/// ask survey nodes to delete the benchmarks of a sitekey uploaded to them,
+/// and stop publishing them
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.publish.purge",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn purge(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Editor).await?;
+    let mut report = PurgeReport::default();
+    if let Some(id) = psuedo_id(&data, &key).await? {
+        let client = crate::survey::client();
+        (report.purged, report.failed) =
+            data.purge_survey_campaign(&client, &id).await?;
+    }
+    if report.failed.is_empty() {
+        unpublish(&data, &key).await?;
+    }
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn publish_works_pg() {
+        let data = pg::get_data().await;
+        publish_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn publish_works_maria() {
+        let data = maria::get_data().await;
+        publish_works(data).await;
+    }
+
+    async fn publish_works(data: ArcData) {
+        const NAME: &str = "publishuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "publishuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.publish;
+
+        let list = || {
+            test::TestRequest::get()
+                .uri(routes.list)
+                .cookie(cookies.clone())
+                .to_request()
+        };
+        let resp = test::call_service(&app, list()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let publications: Vec<Publication> = test::read_body_json(resp).await;
+        assert_eq!(publications.len(), 1);
+        assert!(!publications[0].published);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &SetPublish { publish: true },
+                &routes.get_set_route(&key.key)
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, list()).await;
+        let publications: Vec<Publication> = test::read_body_json(resp).await;
+        assert!(publications[0].published);
+        assert_eq!(
+            publications[0].psuedo_id.as_ref().unwrap(),
+            &data
+                .db
+                .analytics_get_psuedo_id_from_capmaign_id(&key.key)
+                .await
+                .unwrap()
+        );
+
+        // no survey nodes to purge from
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&routes.get_purge_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let report: PurgeReport = test::read_body_json(resp).await;
+        assert_eq!(report, PurgeReport::default());
+        assert!(!data
+            .db
+            .analytics_captcha_is_published(&key.key)
+            .await
+            .unwrap());
+
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &SetPublish { publish: true },
+                &routes.get_set_route(&key.key)
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &SetPublish { publish: false },
+                &routes.get_set_route(&key.key)
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!data
+            .db
+            .analytics_captcha_is_published(&key.key)
+            .await
+            .unwrap());
+
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &SetPublish { publish: true },
+                &routes.get_set_route("nonexistent")
+            )
+            .cookie(cookies)
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Recommend levels of sitekeys from their performance analytics, and apply
+//! them. See [crate::recommendation] for how levels are recommended
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::OrgRole;
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::orgs::runners::require_sitekey_access;
+use crate::errors::*;
+use crate::recommendation;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Recommendation {
+        pub get: &'static str,
+        pub apply: &'static str,
+        pub auto: &'static str,
+    }
+
+    impl Recommendation {
+        pub const fn new() -> Self {
+            Self {
+                get: "/api/v1/mcaptcha/{key}/recommendation",
+                apply: "/api/v1/mcaptcha/{key}/recommendation/apply",
+                auto: "/api/v1/mcaptcha/{key}/recommendation/auto",
+            }
+        }
+
+        pub fn get_get_route(&self, key: &str) -> String {
+            self.get.replace("{key}", key)
+        }
+
+        pub fn get_apply_route(&self, key: &str) -> String {
+            self.apply.replace("{key}", key)
+        }
+
+        pub fn get_auto_route(&self, key: &str) -> String {
+            self.auto.replace("{key}", key)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(apply);
+    cfg.service(auto);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AutoRecommendation {
+    pub enabled: bool,
+}
+
+/// recommend levels of a sitekey
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.recommendation.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+    let recommendation = recommendation::recommend(&data, &username, &key).await?;
+    Ok(HttpResponse::Ok().json(recommendation))
+}
+
+/// replace the levels of a sitekey with the recommended ones
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.recommendation.apply",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn apply(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Editor).await?;
+    recommendation::check_not_easy_mode(&data, &username, &key).await?;
+    let r = recommendation::recommend(&data, &username, &key).await?;
+    recommendation::apply(&data, &username, &key, &r).await?;
+    Ok(HttpResponse::Ok().json(r))
+}
+
+/// opt in to, or out of, applying recommendations to a sitekey automatically
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.recommendation.auto",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn auto(
+    path: web::Path<String>,
+    payload: web::Json<AutoRecommendation>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Editor).await?;
+    if payload.enabled {
+        recommendation::check_not_easy_mode(&data, &username, &key).await?;
+    }
+    data.db
+        .update_captcha_auto_recommendation(&username, &key, payload.enabled)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::CreatePerformanceAnalytics;
+
+    use super::*;
+    use crate::recommendation::{Recommendation, MIN_SAMPLES};
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn recommendation_works_pg() {
+        let data = pg::get_data().await;
+        recommendation_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn recommendation_works_maria() {
+        let data = maria::get_data().await;
+        recommendation_works(data).await;
+    }
+
+    async fn recommendation_works(data: ArcData) {
+        const NAME: &str = "recommendationuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "recommendationuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.recommendation;
+        let levels = data
+            .db
+            .get_captcha_levels(Some(NAME), &key.key)
+            .await
+            .unwrap();
+
+        let get = |cookies| {
+            test::TestRequest::get()
+                .uri(&routes.get_get_route(&key.key))
+                .cookie(cookies)
+                .to_request()
+        };
+
+        // no analytics yet
+        let resp = test::call_service(&app, get(cookies.clone())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let r: Recommendation = test::read_body_json(resp).await;
+        assert_eq!(r.samples, 0);
+        assert!(r.solve_rate.is_none());
+        assert_eq!(r.levels.len(), levels.len());
+        assert!(r
+            .levels
+            .iter()
+            .all(|l| l.difficulty_factor == l.recommended_difficulty_factor));
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&routes.get_apply_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // visitors solve 10 difficulty per millisecond
+        for _ in 0..MIN_SAMPLES {
+            let analytics = CreatePerformanceAnalytics {
+                time: 100,
+                difficulty_factor: levels[0].difficulty_factor,
+                worker_type: "wasm".into(),
+            };
+            data.db.analysis_save(&key.key, &analytics).await.unwrap();
+        }
+        let resp = test::call_service(&app, get(cookies.clone())).await;
+        let r: Recommendation = test::read_body_json(resp).await;
+        assert_eq!(r.samples, MIN_SAMPLES);
+        let rate = levels[0].difficulty_factor as f64 / 100.0;
+        assert!((r.solve_rate.unwrap() - rate).abs() < 1e-9);
+        assert_eq!(r.levels[0].p90, Some(100));
+        assert!(r.levels.windows(2).all(|w| {
+            w[0].recommended_difficulty_factor < w[1].recommended_difficulty_factor
+        }));
+
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &AutoRecommendation { enabled: true },
+                &routes.get_auto_route(&key.key)
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.captcha_auto_recommendation(&key.key).await.unwrap());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&routes.get_apply_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let applied: Vec<u32> = data
+            .db
+            .get_captcha_levels(Some(NAME), &key.key)
+            .await
+            .unwrap()
+            .iter()
+            .map(|l| l.difficulty_factor)
+            .collect();
+        let recommended: Vec<u32> = r
+            .levels
+            .iter()
+            .map(|l| l.recommended_difficulty_factor)
+            .collect();
+        assert_eq!(applied, recommended);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_get_route("nonexistent"))
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
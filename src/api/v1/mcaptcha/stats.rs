@@ -4,28 +4,88 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::web::Bytes;
 use actix_web::{web, HttpResponse, Responder};
+use db_core::{OrgRole, StatsBucket, TimeRange};
+use futures::Stream;
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 
+use crate::api::v1::orgs::runners::require_sitekey_access;
 use crate::errors::*;
+use crate::stats::{
+    StatsQuery, DEFAULT_STATS_LIMIT, DEFAULT_STATS_RANGE, MAX_STATS_LIMIT,
+};
 use crate::AppData;
 
 pub mod routes {
     pub struct Stats {
         pub get: &'static str,
+        pub export: &'static str,
+        pub series: &'static str,
+        pub percentiles: &'static str,
     }
 
     impl Stats {
         pub const fn new() -> Self {
             Self {
                 get: "/api/v1/mcaptcha/stats",
+                export: "/api/v1/mcaptcha/stats/{key}/export",
+                series: "/api/v1/mcaptcha/stats/{key}/series",
+                percentiles: "/api/v1/mcaptcha/stats/{key}/percentiles",
             }
         }
+
+        pub fn get_export_route(&self, key: &str) -> String {
+            self.export.replace("{key}", key)
+        }
+
+        pub fn get_series_route(&self, key: &str) -> String {
+            self.series.replace("{key}", key)
+        }
+
+        pub fn get_percentiles_route(&self, key: &str) -> String {
+            self.percentiles.replace("{key}", key)
+        }
     }
 }
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+/// filters of the stats of a sitekey
+pub struct StatsFilter {
+    /// start of the range, as a unix timestamp; defaults to 30 days before
+    /// `to`
+    pub from: Option<i64>,
+    /// end of the range, exclusive; defaults to now
+    pub to: Option<i64>,
+    /// latest records of each kind to fetch; defaults to 100, at most 1000
+    pub limit: Option<usize>,
+}
+
+impl StatsFilter {
+    /// stats to fetch, with defaults filled in and the limit capped
+    pub fn query(&self) -> ServiceResult<StatsQuery> {
+        let now = OffsetDateTime::now_utc().unix_timestamp() + 1;
+        let to = self.to.unwrap_or(now);
+        let from = self
+            .from
+            .unwrap_or_else(|| to.saturating_sub(DEFAULT_STATS_RANGE));
+        Ok(StatsQuery {
+            range: time_range(from, Some(to))?,
+            limit: self
+                .limit
+                .unwrap_or(DEFAULT_STATS_LIMIT)
+                .clamp(1, MAX_STATS_LIMIT),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StatsPayload {
     pub key: String,
+    #[serde(flatten)]
+    pub filter: StatsFilter,
 }
 
 #[my_codegen::post(
@@ -38,6 +98,760 @@ pub async fn get(
     id: Identity,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
-    let stats = data.stats.fetch(&data, &username, &payload.key).await?;
+    let query = payload.filter.query()?;
+    let stats = data
+        .stats
+        .fetch(&data, &username, &payload.key, &query)
+        .await?;
     Ok(HttpResponse::Ok().json(&stats))
 }
+
+/// number of records read from the database at a time while exporting
+const EXPORT_BATCH: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// start of the range, as a unix timestamp; defaults to the oldest record
+    pub from: Option<i64>,
+    /// end of the range, exclusive; defaults to now
+    pub to: Option<i64>,
+    /// also export performance analytics. They aren't timestamped, so all of
+    /// them are exported regardless of the range
+    #[serde(default)]
+    pub analytics: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Event {
+    Fetch,
+    Solve,
+    Confirm,
+    Analytics,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Fetch => "fetch",
+            Self::Solve => "solve",
+            Self::Confirm => "confirm",
+            Self::Analytics => "analytics",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// exported stats record
+pub struct ExportRecord {
+    /// fetch, solve, confirm or analytics
+    pub event: String,
+    /// time of fetches, solves and confirms, as a unix timestamp
+    pub time: Option<i64>,
+    /// difficulty factor of analytics
+    pub difficulty_factor: Option<u32>,
+    /// time taken to generate the proof, of analytics
+    pub solve_time: Option<u32>,
+    /// worker type of analytics
+    pub worker_type: Option<String>,
+}
+
+impl ExportRecord {
+    const CSV_HEADER: &'static str =
+        "event,time,difficulty_factor,solve_time,worker_type\n";
+
+    fn csv(&self) -> String {
+        fn field<T: ToString>(v: &Option<T>) -> String {
+            v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+        }
+        let worker_type = self
+            .worker_type
+            .as_ref()
+            .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+            .unwrap_or_default();
+        format!(
+            "{},{},{},{},{worker_type}\n",
+            self.event,
+            field(&self.time),
+            field(&self.difficulty_factor),
+            field(&self.solve_time),
+        )
+    }
+}
+
+/// state of a stats export, read from the database a batch at a time
+struct Export {
+    data: AppData,
+    username: String,
+    key: String,
+    range: TimeRange,
+    format: ExportFormat,
+    analytics: bool,
+    /// records being read; `None` once all are read
+    event: Option<Event>,
+    offset: usize,
+    /// has the header been sent
+    started: bool,
+    /// have records been sent
+    sent_records: bool,
+    /// has the footer been sent
+    finished: bool,
+}
+
+impl Export {
+    fn next_event(&self, event: Event) -> Option<Event> {
+        match event {
+            Event::Fetch => Some(Event::Solve),
+            Event::Solve => Some(Event::Confirm),
+            Event::Confirm if self.analytics => Some(Event::Analytics),
+            _ => None,
+        }
+    }
+
+    async fn batch(&self, event: Event) -> ServiceResult<Vec<ExportRecord>> {
+        let (db, user, key) = (&self.data.db, &self.username, &self.key);
+        let (range, offset) = (&self.range, self.offset);
+        let times = match event {
+            Event::Fetch => {
+                db.fetch_config_fetched_range(user, key, range, EXPORT_BATCH, offset)
+                    .await?
+            }
+            Event::Solve => {
+                db.fetch_solve_range(user, key, range, EXPORT_BATCH, offset)
+                    .await?
+            }
+            Event::Confirm => {
+                db.fetch_confirm_range(user, key, range, EXPORT_BATCH, offset)
+                    .await?
+            }
+            Event::Analytics => {
+                let records = db.analytics_fetch(key, EXPORT_BATCH, offset).await?;
+                return Ok(records
+                    .into_iter()
+                    .map(|a| ExportRecord {
+                        event: event.name().into(),
+                        difficulty_factor: Some(a.difficulty_factor),
+                        solve_time: Some(a.time),
+                        worker_type: Some(a.worker_type),
+                        ..Default::default()
+                    })
+                    .collect());
+            }
+        };
+        Ok(times
+            .into_iter()
+            .map(|time| ExportRecord {
+                event: event.name().into(),
+                time: Some(time),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn render(&mut self, records: &[ExportRecord]) -> String {
+        let mut chunk = String::new();
+        for r in records.iter() {
+            match self.format {
+                ExportFormat::Csv => chunk.push_str(&r.csv()),
+                ExportFormat::Json => {
+                    if self.sent_records {
+                        chunk.push(',');
+                    }
+                    chunk.push_str(&serde_json::to_string(r).unwrap());
+                }
+            }
+            self.sent_records = true;
+        }
+        chunk
+    }
+
+    /// next chunk of the export, or `None` once it is complete
+    async fn next(mut self) -> Option<(Result<Bytes, actix_web::Error>, Self)> {
+        if !self.started {
+            self.started = true;
+            let header = match self.format {
+                ExportFormat::Csv => ExportRecord::CSV_HEADER,
+                ExportFormat::Json => "[",
+            };
+            return Some((Ok(Bytes::from_static(header.as_bytes())), self));
+        }
+        while let Some(event) = self.event {
+            match self.batch(event).await {
+                Ok(records) if records.is_empty() => {
+                    self.event = self.next_event(event);
+                    self.offset = 0;
+                }
+                Ok(records) => {
+                    self.offset += records.len();
+                    let chunk = self.render(&records);
+                    return Some((Ok(Bytes::from(chunk)), self));
+                }
+                Err(e) => {
+                    log::error!("Unable to export stats of sitekey {}: {e}", self.key);
+                    self.event = None;
+                    return Some((Err(e.into()), self));
+                }
+            }
+        }
+        if self.finished {
+            return None;
+        }
+        self.finished = true;
+        match self.format {
+            ExportFormat::Csv => None,
+            ExportFormat::Json => Some((Ok(Bytes::from_static(b"]\n")), self)),
+        }
+    }
+}
+
+fn export_stream(export: Export) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    futures::stream::unfold(export, |export| export.next())
+}
+
+/// range from `from` to `to`, which defaults to now. Records can't be from the
+/// future; clamping also keeps timestamps within the range that the database
+/// accepts
+fn time_range(from: i64, to: Option<i64>) -> ServiceResult<TimeRange> {
+    let now = OffsetDateTime::now_utc().unix_timestamp() + 1;
+    let range = TimeRange {
+        from: from.clamp(0, now),
+        to: to.unwrap_or(now).clamp(0, now),
+    };
+    if range.from >= range.to {
+        return Err(ServiceError::InvalidTimeRange);
+    }
+    Ok(range)
+}
+
+/// export fetch, solve and confirm records of a sitekey within a time range,
+/// as CSV or JSON
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.stats.export",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn export(
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+
+    let range = time_range(query.from.unwrap_or(0), query.to)?;
+    let (content_type, extension) = match query.format {
+        ExportFormat::Json => ("application/json", "json"),
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+    };
+    let disposition = format!("attachment; filename=\"{key}-stats.{extension}\"");
+    let export = Export {
+        data: data.clone(),
+        username,
+        key,
+        range,
+        format: query.format,
+        analytics: query.analytics,
+        event: Some(Event::Fetch),
+        offset: 0,
+        started: false,
+        sent_records: false,
+        finished: false,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((header::CONTENT_DISPOSITION, disposition))
+        .streaming(export_stream(export)))
+}
+
+/// most buckets served in a stats series
+pub const MAX_SERIES_BUCKETS: i64 = 50_000;
+/// range of a stats series when `from` isn't set, in seconds
+const DEFAULT_SERIES_RANGE: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Minute,
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Granularity {
+    /// length of a bucket, in seconds
+    pub fn seconds(&self) -> u32 {
+        match self {
+            Self::Minute => 60,
+            Self::Hour => 60 * 60,
+            Self::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SeriesQuery {
+    #[serde(default)]
+    pub granularity: Granularity,
+    /// start of the range, as a unix timestamp; defaults to 30 days before `to`
+    pub from: Option<i64>,
+    /// end of the range, exclusive; defaults to now
+    pub to: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Series {
+    /// length of the buckets, in seconds
+    pub bucket: u32,
+    pub from: i64,
+    pub to: i64,
+    /// buckets that have records, oldest first
+    pub buckets: Vec<StatsBucket>,
+}
+
+/// count fetches, solves and confirms of a sitekey within a time range, in
+/// minute, hour or day buckets
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.stats.series",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn series(
+    path: web::Path<String>,
+    query: web::Query<SeriesQuery>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+
+    let to = query
+        .to
+        .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp() + 1);
+    let from = query
+        .from
+        .unwrap_or_else(|| to.saturating_sub(DEFAULT_SERIES_RANGE));
+    let range = time_range(from, Some(to))?;
+    let bucket = query.granularity.seconds();
+    if (range.to - range.from) / bucket as i64 > MAX_SERIES_BUCKETS {
+        return Err(ServiceError::TooManyStatsBuckets);
+    }
+
+    let buckets = data
+        .db
+        .fetch_stats_series(&username, &key, &range, bucket)
+        .await?;
+    Ok(HttpResponse::Ok().json(Series {
+        bucket,
+        from: range.from,
+        to: range.to,
+        buckets,
+    }))
+}
+
+/// p50, p90 and p99 solve times of a sitekey for each difficulty factor and
+/// worker type, from performance analytics
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.stats.percentiles",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn percentiles(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+    let percentiles = data.db.analytics_fetch_percentiles(&username, &key).await?;
+    Ok(HttpResponse::Ok().json(percentiles))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Bytes;
+    use db_core::StatsRecord;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn stats_export_works_pg() {
+        let data = pg::get_data().await;
+        stats_export_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn stats_export_works_maria() {
+        let data = maria::get_data().await;
+        stats_export_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn stats_filters_work_pg() {
+        let data = pg::get_data().await;
+        stats_filters_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn stats_filters_work_maria() {
+        let data = maria::get_data().await;
+        stats_filters_work(data).await;
+    }
+
+    async fn stats_filters_work(data: ArcData) {
+        const NAME: &str = "statsfiltersuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "statsfiltersuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let records: Vec<StatsRecord> = [1_700_000_000, 1_700_000_001, 1_700_000_002]
+            .into_iter()
+            .map(|time| StatsRecord {
+                key: key.key.clone(),
+                time,
+                country: None,
+            })
+            .collect();
+        data.db.record_fetches(&records).await.unwrap();
+        data.db.record_solves(&records).await.unwrap();
+
+        let payload = |from, to, limit| StatsPayload {
+            key: key.key.clone(),
+            filter: StatsFilter { from, to, limit },
+        };
+
+        // older than the default range
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload(None, None, None), V1_API_ROUTES.captcha.stats.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let stats: crate::stats::CaptchaStats = test::read_body_json(resp).await;
+        assert!(stats.solves.is_empty());
+
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &payload(Some(1_700_000_000), Some(1_700_000_002), Some(1)),
+                V1_API_ROUTES.captcha.stats.get
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let stats: crate::stats::CaptchaStats = test::read_body_json(resp).await;
+        assert_eq!(stats.config_fetches, vec![1_700_000_001]);
+        assert_eq!(stats.solves, vec![1_700_000_001]);
+        assert!(stats.confirms.is_empty());
+
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &payload(Some(1_700_000_002), Some(1_700_000_000), None),
+                V1_API_ROUTES.captcha.stats.get
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn stats_series_works_pg() {
+        let data = pg::get_data().await;
+        stats_series_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn stats_series_works_maria() {
+        let data = maria::get_data().await;
+        stats_series_works(data).await;
+    }
+
+    async fn stats_series_works(data: ArcData) {
+        const NAME: &str = "statsseriesuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "statsseriesuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        // 1_699_999_200 is the start of an hour
+        let records: Vec<StatsRecord> = [1_699_999_200, 1_699_999_260, 1_700_002_800]
+            .into_iter()
+            .map(|time| StatsRecord {
+                key: key.key.clone(),
+                time,
+                country: None,
+            })
+            .collect();
+        data.db.record_fetches(&records).await.unwrap();
+        data.db.record_solves(&records[..1]).await.unwrap();
+        data.db.record_confirms(&records[2..]).await.unwrap();
+        let route = V1_API_ROUTES.captcha.stats.get_series_route(&key.key);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{route}?from=1699999200&to=1700006400"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let series: Series = test::read_body_json(resp).await;
+        assert_eq!(series.bucket, 3600);
+        assert_eq!(
+            series.buckets,
+            vec![
+                StatsBucket {
+                    time: 1_699_999_200,
+                    config_fetches: 2,
+                    solves: 1,
+                    confirms: 0,
+                },
+                StatsBucket {
+                    time: 1_700_002_800,
+                    config_fetches: 1,
+                    solves: 0,
+                    confirms: 1,
+                },
+            ]
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!(
+                    "{route}?granularity=minute&from=1699999200&to=1699999300"
+                ))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let series: Series = test::read_body_json(resp).await;
+        let times: Vec<i64> = series.buckets.iter().map(|b| b.time).collect();
+        assert_eq!(times, vec![1_699_999_200, 1_699_999_260]);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{route}?granularity=minute&from=0"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&V1_API_ROUTES.captcha.stats.get_series_route("nonexistent"))
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn stats_percentiles_works_pg() {
+        let data = pg::get_data().await;
+        stats_percentiles_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn stats_percentiles_works_maria() {
+        let data = maria::get_data().await;
+        stats_percentiles_works(data).await;
+    }
+
+    async fn stats_percentiles_works(data: ArcData) {
+        const NAME: &str = "statspercentilesuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "statspercentilesuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        for time in 1..=100 {
+            let analytics = db_core::CreatePerformanceAnalytics {
+                time,
+                difficulty_factor: 500,
+                worker_type: "wasm".into(),
+            };
+            data.db.analysis_save(&key.key, &analytics).await.unwrap();
+        }
+        let analytics = db_core::CreatePerformanceAnalytics {
+            time: 7,
+            difficulty_factor: 500,
+            worker_type: "js".into(),
+        };
+        data.db.analysis_save(&key.key, &analytics).await.unwrap();
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&V1_API_ROUTES.captcha.stats.get_percentiles_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let percentiles: Vec<db_core::SolveTimePercentiles> =
+            test::read_body_json(resp).await;
+        assert_eq!(
+            percentiles,
+            vec![
+                db_core::SolveTimePercentiles {
+                    difficulty_factor: 500,
+                    worker_type: "js".into(),
+                    samples: 1,
+                    p50: 7,
+                    p90: 7,
+                    p99: 7,
+                },
+                db_core::SolveTimePercentiles {
+                    difficulty_factor: 500,
+                    worker_type: "wasm".into(),
+                    samples: 100,
+                    p50: 50,
+                    p90: 90,
+                    p99: 99,
+                },
+            ]
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(
+                    &V1_API_ROUTES
+                        .captcha
+                        .stats
+                        .get_percentiles_route("nonexistent"),
+                )
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    async fn stats_export_works(data: ArcData) {
+        const NAME: &str = "statsexportuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "statsexportuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let records = vec![
+            StatsRecord {
+                key: key.key.clone(),
+                time: 1_700_000_000,
+                country: None,
+            },
+            StatsRecord {
+                key: key.key.clone(),
+                time: 1_700_000_001,
+                country: None,
+            },
+        ];
+        data.db.record_fetches(&records).await.unwrap();
+        data.db.record_solves(&records[..1]).await.unwrap();
+        let route = V1_API_ROUTES.captcha.stats.get_export_route(&key.key);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{route}?format=csv&from=1700000000&to=1700000002"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Bytes = test::read_body(resp).await;
+        assert_eq!(
+            String::from_utf8(body.to_vec()).unwrap(),
+            format!(
+                "{}fetch,1700000000,,,\nfetch,1700000001,,,\nsolve,1700000000,,,\n",
+                ExportRecord::CSV_HEADER
+            )
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{route}?from=1700000000&to=1700000001"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let exported: Vec<ExportRecord> = test::read_body_json(resp).await;
+        let record = |event: &str| ExportRecord {
+            event: event.into(),
+            time: Some(1_700_000_000),
+            ..Default::default()
+        };
+        assert_eq!(exported, vec![record("fetch"), record("solve")]);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{route}?from=1700000001&to=1700000001"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&V1_API_ROUTES.captcha.stats.get_export_route("nonexistent"))
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -97,6 +97,7 @@ pub async fn level_routes_work(data: ArcData) {
     let mut delete_payload = DeleteCaptcha {
         key: key.key,
         password: format!("worongpass{}", PASSWORD),
+        totp: None,
     };
 
     bad_post_req_test(
@@ -120,3 +121,62 @@ pub async fn level_routes_work(data: ArcData) {
     .await;
     assert_eq!(del_resp.status(), StatusCode::OK);
 }
+
+#[actix_rt::test]
+async fn unique_names_work_pg() {
+    let data = crate::tests::pg::get_data().await;
+    unique_names_work(data).await;
+}
+
+#[actix_rt::test]
+async fn unique_names_work_maria() {
+    let data = crate::tests::maria::get_data().await;
+    unique_names_work(data).await;
+}
+
+pub async fn unique_names_work(data: ArcData) {
+    use crate::api::v1::mcaptcha::create::runner::create;
+    use crate::api::v1::mcaptcha::update::runner::update_captcha;
+
+    const NAME: &str = "testuseruniquenames";
+    const PASSWORD: &str = "longpassworddomain";
+    const EMAIL: &str = "testuseruniquenames@a.com";
+
+    // duplicates are allowed by default
+    let data = &data;
+    delete_user(data, NAME).await;
+    register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+    let payload = get_level_data();
+    create(&payload, data, NAME, None).await.unwrap();
+    let first = create(&payload, data, NAME, None).await.unwrap();
+
+    let mut settings = data.settings.clone();
+    settings.captcha.unique_names = true;
+    let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+    assert!(matches!(
+        create(&payload, data, NAME, None).await,
+        Err(ServiceError::DuplicateCaptchaName)
+    ));
+
+    let mut other = get_level_data();
+    other.description = "unique".into();
+    let second = create(&other, data, NAME, None).await.unwrap();
+
+    // renaming to a description in use is rejected, keeping it isn't
+    let mut update = UpdateCaptcha {
+        levels: vec![L1, L2],
+        duration: 30,
+        description: payload.description.clone(),
+        key: second.key.clone(),
+        publish_benchmarks: false,
+    };
+    assert!(matches!(
+        update_captcha(&update, data, NAME).await,
+        Err(ServiceError::DuplicateCaptchaName)
+    ));
+    update.description = other.description.clone();
+    update_captcha(&update, data, NAME).await.unwrap();
+    update.key = first.key;
+    update.description = "renamed".into();
+    update_captcha(&update, data, NAME).await.unwrap();
+}
@@ -0,0 +1,258 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Themes of the widgets of sitekeys. Widgets fetch them from
+//! [crate::widget::config]
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{OrgRole, WidgetTheme};
+
+use crate::api::v1::orgs::runners::require_sitekey_access;
+use crate::errors::*;
+use crate::AppData;
+
+/// roundest corners of a widget, in pixels
+pub const MAX_BORDER_RADIUS: u32 = 40;
+
+pub mod routes {
+    pub struct Theme {
+        pub get: &'static str,
+        pub set: &'static str,
+        pub reset: &'static str,
+    }
+
+    impl Theme {
+        pub const fn new() -> Self {
+            Self {
+                get: "/api/v1/mcaptcha/{key}/theme",
+                set: "/api/v1/mcaptcha/{key}/theme/set",
+                reset: "/api/v1/mcaptcha/{key}/theme/reset",
+            }
+        }
+
+        pub fn get_get_route(&self, key: &str) -> String {
+            self.get.replace("{key}", key)
+        }
+
+        pub fn get_set_route(&self, key: &str) -> String {
+            self.set.replace("{key}", key)
+        }
+
+        pub fn get_reset_route(&self, key: &str) -> String {
+            self.reset.replace("{key}", key)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(set);
+    cfg.service(reset);
+}
+
+/// whether `color` is `#rrggbb`
+fn is_color(color: &str) -> bool {
+    color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// check that the colors of a theme are `#rrggbb` and that its corners are at
+/// most [MAX_BORDER_RADIUS] round
+pub fn validate(theme: &WidgetTheme) -> ServiceResult<()> {
+    let colors = [
+        Some(&theme.accent_color),
+        theme.background_color.as_ref(),
+        theme.text_color.as_ref(),
+    ];
+    if !colors.iter().flatten().all(|c| is_color(c))
+        || theme.border_radius > MAX_BORDER_RADIUS
+    {
+        return Err(ServiceError::InvalidWidgetTheme);
+    }
+    Ok(())
+}
+
+/// theme of the widget of a sitekey; the default theme when it wasn't set
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.captcha.theme.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+    let theme = crate::widget::config::theme(&data, &key).await?;
+    Ok(HttpResponse::Ok().json(theme))
+}
+
+/// set the theme of the widget of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.theme.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    path: web::Path<String>,
+    payload: web::Json<WidgetTheme>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    validate(&payload)?;
+    require_sitekey_access(&data, &key, &username, OrgRole::Editor).await?;
+    data.db.set_widget_theme(&username, &key, &payload).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// restore the default theme of the widget of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.theme.reset",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn reset(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Editor).await?;
+    data.db.delete_widget_theme(&username, &key).await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+    use db_core::ThemeMode;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::widget::config::WidgetConfig;
+    use crate::*;
+
+    #[test]
+    fn validate_works() {
+        let mut theme = WidgetTheme::default();
+        assert!(validate(&theme).is_ok());
+        theme.background_color = Some("#FFFFFF".into());
+        assert!(validate(&theme).is_ok());
+        theme.text_color = Some("red".into());
+        assert!(validate(&theme).is_err());
+        theme.text_color = Some("#12345g".into());
+        assert!(validate(&theme).is_err());
+        theme.text_color = None;
+        theme.border_radius = MAX_BORDER_RADIUS + 1;
+        assert!(validate(&theme).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn theme_works_pg() {
+        let data = pg::get_data().await;
+        theme_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn theme_works_maria() {
+        let data = maria::get_data().await;
+        theme_works(data).await;
+    }
+
+    async fn theme_works(data: ArcData) {
+        const NAME: &str = "themeuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "themeuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.theme;
+
+        let widget_config = || {
+            test::TestRequest::get()
+                .uri(&crate::WIDGET_ROUTES.get_config(&key.key))
+                .to_request()
+        };
+
+        // default theme
+        let resp = test::call_service(&app, widget_config()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::CACHE_CONTROL).is_some());
+        let config: WidgetConfig = test::read_body_json(resp).await;
+        assert_eq!(config.theme, WidgetTheme::default());
+
+        let theme = WidgetTheme {
+            mode: ThemeMode::Dark,
+            accent_color: "#ff8800".into(),
+            background_color: Some("#101010".into()),
+            text_color: Some("#eeeeee".into()),
+            border_radius: 8,
+            show_logo: false,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&theme, &routes.get_set_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_get_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        let got: WidgetTheme = test::read_body_json(resp).await;
+        assert_eq!(got, theme);
+        let resp = test::call_service(&app, widget_config()).await;
+        let config: WidgetConfig = test::read_body_json(resp).await;
+        assert_eq!(config.theme, theme);
+
+        let mut invalid = theme.clone();
+        invalid.accent_color = "orange".into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&invalid, &routes.get_set_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&routes.get_reset_route(&key.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, widget_config()).await;
+        let config: WidgetConfig = test::read_body_json(resp).await;
+        assert_eq!(config.theme, WidgetTheme::default());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&crate::WIDGET_ROUTES.get_config("nonexistent"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
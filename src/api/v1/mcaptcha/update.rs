@@ -6,14 +6,14 @@
 use actix_identity::Identity;
 use actix_web::{web, HttpResponse, Responder};
 use libmcaptcha::defense::Level;
-use libmcaptcha::master::messages::RenameBuilder;
 use serde::{Deserialize, Serialize};
 
-use db_core::errors::DBError;
-use db_core::CreateCaptcha;
+use db_core::{CreateCaptcha, Fallback, InvisibleMode, OrgRole};
 
+use super::create::runner::{check_unique_name, validate_captcha, validate_description};
 use super::create::MCaptchaDetails;
-use super::get_random;
+use crate::api::v1::orgs::runners::{require_sitekey_access, require_sitekey_role};
+use crate::email::security::{self, SecurityEvent};
 use crate::errors::*;
 use crate::AppData;
 
@@ -27,30 +27,18 @@ pub async fn update_key(
     id: Identity,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
-    let mut key;
-
-    loop {
-        key = get_random(32);
+    let payload = payload.into_inner();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    let key = crate::keys::rotate(&data, &username, &payload.key).await?;
 
-        match data
-            .db
-            .update_captcha_key(&username, &payload.key, &key)
-            .await
-        {
-            Ok(_) => break,
-            Err(DBError::SecretTaken) => continue,
-            Err(e) => return Err(e.into()),
-        }
+    let event = SecurityEvent::SitekeyRotated {
+        description: &payload.name,
+        key: &key,
+    };
+    if let Err(e) = security::send(&data, &username, &event).await {
+        log::error!("Unable to send security alert to {username}: {e}");
     }
 
-    let payload = payload.into_inner();
-    let rename = RenameBuilder::default()
-        .name(payload.key)
-        .rename_to(key.clone())
-        .build()
-        .unwrap();
-    data.captcha.rename(rename).await?;
-
     let resp = MCaptchaDetails {
         key,
         name: payload.name,
@@ -78,12 +66,98 @@ pub async fn update_captcha(
     id: Identity,
 ) -> ServiceResult<impl Responder> {
     let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
     runner::update_captcha(&payload, &data, &username).await?;
     Ok(HttpResponse::Ok())
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpdateStrictTokens {
+    pub key: String,
+    pub strict: bool,
+}
+
+/// route handler that toggles strict single-use validation tokens
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.update_strict",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn update_strict_tokens(
+    payload: web::Json<UpdateStrictTokens>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db
+        .update_captcha_strict_tokens(&username, &payload.key, payload.strict)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpdateInvisibleMode {
+    pub key: String,
+    pub enabled: bool,
+    /// highest difficulty factor of challenges that are solved in the
+    /// background
+    pub threshold: u32,
+}
+
+/// route handler that sets invisible mode of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.update_invisible",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn update_invisible_mode(
+    payload: web::Json<UpdateInvisibleMode>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    let mode = InvisibleMode {
+        enabled: payload.enabled,
+        threshold: payload.threshold,
+    };
+    data.db
+        .update_captcha_invisible_mode(&username, &payload.key, &mode)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpdateFallback {
+    pub key: String,
+    pub enabled: bool,
+    /// seconds that visitors wait before they are issued validation tokens
+    pub wait: u32,
+}
+
+/// route handler that sets the fallback challenge of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.update_fallback",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn update_fallback(
+    payload: web::Json<UpdateFallback>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    let fallback = Fallback {
+        enabled: payload.enabled,
+        wait: payload.wait,
+    };
+    data.db
+        .update_captcha_fallback(&username, &payload.key, &fallback)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
 pub mod runner {
-    use libmcaptcha::{master::messages::RemoveCaptcha, DefenseBuilder};
+    use libmcaptcha::DefenseBuilder;
 
     use super::*;
 
@@ -92,6 +166,9 @@ pub mod runner {
         data: &AppData,
         username: &str,
     ) -> ServiceResult<()> {
+        validate_description(&payload.description)?;
+        validate_captcha(&payload.levels, payload.duration, &payload.description)?;
+
         let mut defense = DefenseBuilder::default();
 
         for level in payload.levels.iter() {
@@ -103,6 +180,9 @@ pub mod runner {
         // still, needs to be benchmarked
         defense.build()?;
 
+        check_unique_name(data, username, &payload.description, Some(&payload.key))
+            .await?;
+
         data.db
             .delete_captcha_levels(username, &payload.key)
             .await?;
@@ -118,19 +198,23 @@ pub mod runner {
         data.db
             .add_captcha_levels(username, &payload.key, &payload.levels)
             .await?;
-        if let Err(ServiceError::CaptchaError(e)) = data
-            .captcha
-            .remove(RemoveCaptcha(payload.key.clone()))
-            .await
+        // issue challenges with the new levels right away, without waiting
+        // for the old configuration to expire from the master. The update is
+        // saved by now, and a sitekey that couldn't be added back to the
+        // master is added from the database with its next challenge
+        if let Err(e) =
+            crate::api::v1::pow::get_config::refresh_mcaptcha(data, &payload.key).await
         {
             log::error!(
-                "Deleting captcha key {} while updating it, error: {:?}",
-                &payload.key,
-                e
+                "Unable to refresh sitekey {} in the master: {e}",
+                payload.key
             );
         }
 
-        if payload.publish_benchmarks {
+        if payload.publish_benchmarks
+            && data.settings.features.analytics
+            && !crate::demo::is_demo_user(data, username)
+        {
             data.db
                 .analytics_create_psuedo_id_if_not_exists(&payload.key)
                 .await?;
@@ -138,6 +222,8 @@ pub mod runner {
             data.db
                 .analytics_delete_all_records_for_campaign(&payload.key)
                 .await?;
+            data.analytics_quotas.invalidate(&payload.key);
+            data.maintenance.record_deletion();
         }
         Ok(())
     }
@@ -149,7 +235,7 @@ mod tests {
     use actix_web::test;
 
     use crate::api::v1::mcaptcha::create::MCaptchaDetails;
-    use crate::api::v1::mcaptcha::stats::StatsPayload;
+    use crate::api::v1::mcaptcha::stats::{StatsFilter, StatsPayload};
     use crate::api::v1::ROUTES;
     use crate::tests::*;
     use crate::*;
@@ -203,7 +289,10 @@ mod tests {
         assert_eq!(get_token_resp.status(), StatusCode::OK);
 
         // get stats
-        let paylod = StatsPayload { key: token_key.key };
+        let paylod = StatsPayload {
+            key: token_key.key,
+            filter: StatsFilter::default(),
+        };
         let get_statis_resp = test::call_service(
             &app,
             post_request!(&paylod, ROUTES.captcha.stats.get)
@@ -214,4 +303,69 @@ mod tests {
         // if updated key doesn't exist in database, a non 200 result will bereturned
         assert_eq!(get_statis_resp.status(), StatusCode::OK);
     }
+
+    #[actix_rt::test]
+    async fn level_edits_reach_master_pg() {
+        let data = crate::tests::pg::get_data().await;
+        level_edits_reach_master(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn level_edits_reach_master_maria() {
+        let data = crate::tests::maria::get_data().await;
+        level_edits_reach_master(data).await;
+    }
+
+    async fn level_edits_reach_master(data: ArcData) {
+        use libmcaptcha::defense::Level;
+
+        use super::{runner, UpdateCaptcha};
+        use crate::api::v1::pow::get_config::get_config_runner;
+
+        const NAME: &str = "updatemastermcaptcha";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "testupdatemastermcaptcha@a.com";
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app_data = AppData::new(data.clone());
+
+        let config = get_config_runner(&app_data, &token_key.key).await.unwrap();
+        assert_eq!(config.difficulty_factor, L1.difficulty_factor);
+
+        let levels = vec![
+            Level {
+                visitor_threshold: 1000,
+                difficulty_factor: 5000,
+            },
+            Level {
+                visitor_threshold: 5000,
+                difficulty_factor: 50000,
+            },
+        ];
+        let payload = UpdateCaptcha {
+            levels: levels.clone(),
+            duration: 30,
+            description: "dummy".into(),
+            key: token_key.key.clone(),
+            publish_benchmarks: false,
+        };
+
+        // challenges are issued while the sitekey is edited
+        let fetches = futures::future::join_all(
+            (0..20).map(|_| get_config_runner(&app_data, &token_key.key)),
+        );
+        let (fetches, update) =
+            futures::join!(fetches, runner::update_captcha(&payload, &app_data, NAME));
+        update.unwrap();
+        for config in fetches {
+            config.unwrap();
+        }
+
+        // and the new levels apply without waiting for the old ones to expire
+        let config = get_config_runner(&app_data, &token_key.key).await.unwrap();
+        assert_eq!(config.difficulty_factor, levels[0].difficulty_factor);
+    }
 }
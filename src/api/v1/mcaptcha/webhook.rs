@@ -0,0 +1,301 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{OrgRole, Webhook};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::get_random;
+use crate::api::v1::orgs::runners::{require_sitekey_access, require_sitekey_role};
+use crate::errors::*;
+use crate::AppData;
+
+/// number of delivery logs that are shown
+pub const DELIVERY_LOG_LIMIT: u32 = 50;
+
+pub mod routes {
+    pub struct Webhook {
+        pub set: &'static str,
+        pub get: &'static str,
+        pub delete: &'static str,
+        pub deliveries: &'static str,
+    }
+
+    impl Webhook {
+        pub const fn new() -> Self {
+            Self {
+                set: "/api/v1/mcaptcha/webhook/set",
+                get: "/api/v1/mcaptcha/webhook/get",
+                delete: "/api/v1/mcaptcha/webhook/delete",
+                deliveries: "/api/v1/mcaptcha/webhook/deliveries",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(set);
+    cfg.service(get);
+    cfg.service(delete);
+    cfg.service(deliveries);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetWebhook {
+    pub key: String,
+    pub url: String,
+    #[serde(default)]
+    pub on_escalation: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebhookSecret {
+    pub secret: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebhookKey {
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebhookInfo {
+    pub url: String,
+    pub on_escalation: bool,
+}
+
+/// set webhook of a sitekey. A new signing secret is generated on every call
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.webhook.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    payload: web::Json<SetWebhook>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let url = Url::parse(payload.url.trim())?;
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Editor).await?;
+    crate::webhooks::check_url(&url).await?;
+
+    let webhook = Webhook {
+        url: url.to_string(),
+        secret: get_random(32),
+        on_escalation: payload.on_escalation,
+    };
+    data.db
+        .set_webhook(&username, &payload.key, &webhook)
+        .await?;
+    Ok(HttpResponse::Ok().json(WebhookSecret {
+        secret: webhook.secret,
+    }))
+}
+
+/// get webhook of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.webhook.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    payload: web::Json<WebhookKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_access(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let webhook = data.db.get_webhook(&payload.key).await?;
+    Ok(HttpResponse::Ok().json(WebhookInfo {
+        url: webhook.url,
+        on_escalation: webhook.on_escalation,
+    }))
+}
+
+/// delete webhook of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.webhook.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<WebhookKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Editor).await?;
+    data.db.delete_webhook(&username, &payload.key).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get recent webhook delivery logs of a sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.captcha.webhook.deliveries",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn deliveries(
+    payload: web::Json<WebhookKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    require_sitekey_role(&data, &payload.key, &username, OrgRole::Viewer).await?;
+    let deliveries = data
+        .db
+        .fetch_webhook_deliveries(&username, &payload.key, DELIVERY_LOG_LIMIT)
+        .await?;
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn webhook_works_pg() {
+        let data = pg::get_data().await;
+        webhook_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn webhook_works_maria() {
+        let data = maria::get_data().await;
+        webhook_works(data).await;
+    }
+
+    async fn webhook_works(data: ArcData) {
+        const NAME: &str = "webhookuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "webhookuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.captcha.webhook;
+
+        let key = WebhookKey {
+            key: token_key.key.clone(),
+        };
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut payload = SetWebhook {
+            key: token_key.key.clone(),
+            url: "ftp://example.com".into(),
+            on_escalation: true,
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::NotAUrl,
+        )
+        .await;
+
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/hook",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.1.1]/hook",
+        ] {
+            payload.url = url.into();
+            bad_post_req_test(
+                data,
+                NAME,
+                PASSWORD,
+                routes.set,
+                &payload,
+                ServiceError::WebhookAddressNotAllowed,
+            )
+            .await;
+        }
+
+        payload.url = "https://1.1.1.1/hook".into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let secret: WebhookSecret = test::read_body_json(resp).await;
+        assert_eq!(
+            data.db.get_webhook(&token_key.key).await.unwrap().secret,
+            secret.secret
+        );
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let info: WebhookInfo = test::read_body_json(resp).await;
+        assert_eq!(info.url, payload.url);
+        assert!(info.on_escalation);
+
+        let log = db_core::CreateWebhookDelivery {
+            event: "confirm",
+            status: Some(500),
+            attempt: 1,
+            success: false,
+        };
+        data.db
+            .record_webhook_delivery(&token_key.key, &log)
+            .await
+            .unwrap();
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.deliveries)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let deliveries: Vec<db_core::WebhookDelivery> = test::read_body_json(resp).await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].status, Some(500));
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
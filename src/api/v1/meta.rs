@@ -3,12 +3,20 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
 use actix_web::{web, HttpResponse, Responder};
 use derive_builder::Builder;
+use libmcaptcha::errors::CaptchaError;
 use libmcaptcha::redis::{Redis, RedisConfig};
 use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
 
+use crate::api::v1::mcaptcha::get_random;
 use crate::data::SystemGroup;
+use crate::memcached;
 use crate::AppData;
 use crate::{GIT_COMMIT_HASH, VERSION};
 
@@ -22,6 +30,12 @@ pub mod routes {
     pub struct Meta {
         pub build_details: &'static str,
         pub health: &'static str,
+        pub health_live: &'static str,
+        pub health_ready: &'static str,
+        pub jobs: &'static str,
+        pub load: &'static str,
+        pub mail_queue: &'static str,
+        pub stats_queue: &'static str,
     }
 
     impl Meta {
@@ -29,6 +43,12 @@ pub mod routes {
             Self {
                 build_details: "/api/v1/meta/build",
                 health: "/api/v1/meta/health",
+                health_live: "/api/v1/meta/health/live",
+                health_ready: "/api/v1/meta/health/ready",
+                jobs: "/api/v1/meta/jobs",
+                load: "/api/v1/meta/load",
+                mail_queue: "/api/v1/meta/mail_queue",
+                stats_queue: "/api/v1/meta/stats_queue",
             }
         }
     }
@@ -68,7 +88,7 @@ async fn health(data: AppData) -> impl Responder {
 
     if let SystemGroup::Redis(_) = data.captcha {
         if let Ok(r) = Redis::new(RedisConfig::Single(
-            data.settings.redis.as_ref().unwrap().url.clone(),
+            data.settings.redis.as_ref().unwrap().connection_url(),
         ))
         .await
         {
@@ -82,9 +102,214 @@ async fn health(data: AppData) -> impl Responder {
     HttpResponse::Ok().json(resp_builder.build().unwrap())
 }
 
+/// time within which a component must answer a probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// prefix of keys written to Redis by readiness probes
+const PROBE_KEY_PREFIX: &str = "mcaptcha_health_probe:";
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// status of a component checked by a probe
+pub struct ComponentStatus {
+    pub healthy: bool,
+    /// time taken by the check, in milliseconds
+    pub latency: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// result of a liveness or readiness probe
+pub struct Probe {
+    /// are all checked components healthy
+    pub healthy: bool,
+    /// status of checked components, by name
+    pub components: BTreeMap<String, ComponentStatus>,
+}
+
+impl Probe {
+    async fn check<F>(&mut self, component: &str, check: F)
+    where
+        F: Future<Output = Result<(), String>>,
+    {
+        let timer = Instant::now();
+        let res = match timeout(PROBE_TIMEOUT, check).await {
+            Ok(res) => res,
+            Err(_) => Err("timed out".into()),
+        };
+        let status = ComponentStatus {
+            healthy: res.is_ok(),
+            latency: timer.elapsed().as_millis() as u64,
+            error: res.err(),
+        };
+        self.components.insert(component.into(), status);
+    }
+
+    fn respond(mut self) -> HttpResponse {
+        self.healthy = self.components.values().all(|c| c.healthy);
+        if self.healthy {
+            HttpResponse::Ok().json(self)
+        } else {
+            HttpResponse::ServiceUnavailable().json(self)
+        }
+    }
+}
+
+/// is the master answering. Sitekeys that aren't registered are answered
+/// with `None`, so a random one is asked for
+async fn check_captcha(data: &AppData) -> Result<(), String> {
+    match data.captcha.get_pow(get_random(32)).await {
+        Err(CaptchaError::MailboxError) => Err(CaptchaError::MailboxError.to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// write a key to Redis and read it back
+async fn check_redis(url: &str) -> Result<(), String> {
+    let round_trip = async {
+        let client = redis::Client::open(url)?;
+        let mut con = client.get_async_connection().await?;
+        let key = format!("{PROBE_KEY_PREFIX}{}", get_random(16));
+        let value = get_random(16);
+        redis::cmd("SET")
+            .arg(&key)
+            .arg(&value)
+            .arg("EX")
+            .arg(PROBE_TIMEOUT.as_secs())
+            .query_async::<_, ()>(&mut con)
+            .await?;
+        let read: Option<String> =
+            redis::cmd("GET").arg(&key).query_async(&mut con).await?;
+        redis::cmd("DEL")
+            .arg(&key)
+            .query_async::<_, ()>(&mut con)
+            .await?;
+        redis::RedisResult::Ok(read == Some(value))
+    };
+    match round_trip.await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("read back a different value".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// ask every memcached server for its version
+async fn check_memcached(data: &AppData) -> Result<(), String> {
+    let settings = data.settings.clone();
+    // the memcached client blocks
+    web::block(move || memcached::client(&settings).and_then(|c| memcached::ping(&c)))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+/// connect to the SMTP relays. Healthy when any of them can be reached, as
+/// emails fail over to the others
+async fn check_smtp(data: &AppData) -> Result<(), String> {
+    let mailer = match data.mailer.as_ref() {
+        Some(mailer) => mailer,
+        None => return Err("SMTP isn't configured".into()),
+    };
+    let mut errors = Vec::default();
+    for (relay, res) in mailer.test_connections().await {
+        match res {
+            Ok(true) => return Ok(()),
+            Ok(false) => errors.push(format!("{relay}: unreachable")),
+            Err(e) => errors.push(format!("{relay}: {e}")),
+        }
+    }
+    Err(errors.join(", "))
+}
+
+/// is the process able to serve requests: answers with 503 when the master
+/// is stuck, which a restart fixes
+#[my_codegen::get(path = "crate::V1_API_ROUTES.meta.health_live")]
+async fn health_live(data: AppData) -> impl Responder {
+    let mut probe = Probe::default();
+    probe.check("captcha", check_captcha(&data)).await;
+    probe.respond()
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ReadyQuery {
+    /// connect to the SMTP relays too
+    #[serde(default)]
+    pub smtp: bool,
+}
+
+/// can the instance take traffic: answers with 503 when the database, Redis or
+/// memcached, the master or, when `smtp` is set, all SMTP relays are unavailable
+#[my_codegen::get(path = "crate::V1_API_ROUTES.meta.health_ready")]
+async fn health_ready(data: AppData, q: web::Query<ReadyQuery>) -> impl Responder {
+    let mut probe = Probe::default();
+    probe
+        .check("db", async {
+            if data.db.ping().await {
+                Ok(())
+            } else {
+                Err("unable to reach the database".into())
+            }
+        })
+        .await;
+    if let (SystemGroup::Redis(_), Some(redis)) = (&data.captcha, &data.settings.redis) {
+        probe
+            .check("redis", check_redis(&redis.connection_url()))
+            .await;
+    }
+    if let SystemGroup::Memcached(_) = data.captcha {
+        probe.check("memcached", check_memcached(&data)).await;
+    }
+    probe.check("captcha", check_captcha(&data)).await;
+    if q.smtp {
+        probe.check("smtp", check_smtp(&data)).await;
+    }
+    probe.respond()
+}
+
+/// status of background jobs
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.meta.jobs",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn jobs(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(data.jobs.list())
+}
+
+/// state of the buffered stats queue
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.meta.stats_queue",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn stats_queue(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(data.stats_queue.metrics())
+}
+
+/// state of the load shedder
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.meta.load",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn load(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(data.load.metrics())
+}
+
+/// state of the outgoing email queue
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.meta.mail_queue",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn mail_queue(data: AppData) -> impl Responder {
+    HttpResponse::Ok().json(data.mail_queue.metrics())
+}
+
 pub fn services(cfg: &mut web::ServiceConfig) {
     cfg.service(build_details);
     cfg.service(health);
+    cfg.service(health_live);
+    cfg.service(health_ready);
+    cfg.service(jobs);
+    cfg.service(load);
+    cfg.service(mail_queue);
+    cfg.service(stats_queue);
 }
 
 #[cfg(test)]
@@ -138,5 +363,140 @@ pub mod tests {
         let health_resp: Health = test::read_body_json(resp).await;
         assert!(health_resp.db);
         assert_eq!(health_resp.redis, Some(true));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.health_live)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let probe: Probe = test::read_body_json(resp).await;
+        assert!(probe.healthy);
+        assert_eq!(probe.components.len(), 1);
+        assert!(probe.components["captcha"].healthy);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.health_ready)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let probe: Probe = test::read_body_json(resp).await;
+        assert!(probe.healthy);
+        for component in ["db", "redis", "captcha"] {
+            assert!(probe.components[component].healthy);
+        }
+        // SMTP is only checked when asked for
+        assert!(!probe.components.contains_key("smtp"));
+    }
+
+    #[actix_rt::test]
+    async fn jobs_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        jobs_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn jobs_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        jobs_works(data).await;
+    }
+
+    pub async fn jobs_works(data: ArcData) {
+        use crate::jobs::{JobStatus, EASY_CAPTCHA_JOB};
+        use crate::tests::*;
+
+        const NAME: &str = "metajobsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "metajobsuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.jobs)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        // the state of jobs and queues is only shown to administrators
+        for route in [
+            V1_API_ROUTES.meta.jobs,
+            V1_API_ROUTES.meta.stats_queue,
+            V1_API_ROUTES.meta.load,
+            V1_API_ROUTES.meta.mail_queue,
+        ] {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(route)
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), ServiceError::AdminRequired.status_code());
+        }
+        data.db.set_admin(NAME, true).await.unwrap();
+
+        data.jobs.register(EASY_CAPTCHA_JOB, 10);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.jobs)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let jobs: Vec<JobStatus> = test::read_body_json(resp).await;
+        assert_eq!(jobs, data.jobs.list());
+        assert!(jobs.iter().any(|j| j.name == EASY_CAPTCHA_JOB));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.stats_queue)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let metrics: crate::stats::StatsQueueMetrics = test::read_body_json(resp).await;
+        assert_eq!(metrics, data.stats_queue.metrics());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.load)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let metrics: crate::overload::LoadMetrics = test::read_body_json(resp).await;
+        assert_eq!(metrics, data.load.metrics());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.meta.mail_queue)
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let metrics: crate::email::queue::MailQueueMetrics =
+            test::read_body_json(resp).await;
+        assert_eq!(metrics, data.mail_queue.metrics());
     }
 }
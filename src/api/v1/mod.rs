@@ -8,26 +8,38 @@ use actix_web::web::ServiceConfig;
 use serde::Deserialize;
 
 pub mod account;
+pub mod admin;
 pub mod auth;
+pub mod invites;
 pub mod mcaptcha;
 pub mod meta;
+pub mod monitoring;
 pub mod notifications;
+pub mod oidc;
+pub mod orgs;
 pub mod pow;
 mod routes;
 pub mod stats;
 pub mod survey;
+pub mod trial;
 
 pub use routes::ROUTES;
 
 pub fn services(cfg: &mut ServiceConfig) {
     meta::services(cfg);
+    monitoring::services(cfg);
     pow::services(cfg);
     auth::services(cfg);
     account::services(cfg);
+    admin::services(cfg);
+    invites::services(cfg);
     mcaptcha::services(cfg);
     notifications::services(cfg);
+    oidc::services(cfg);
+    orgs::services(cfg);
     survey::services(cfg);
     stats::services(cfg);
+    trial::services(cfg);
 }
 
 #[derive(Deserialize)]
@@ -0,0 +1,203 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Read-only API for monitoring integrations
+//!
+//! These are the only endpoints that accept `read:stats` API tokens. They
+//! also accept the session cookie.
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::OrgRole;
+
+use super::mcaptcha::create::MCaptchaDetails;
+use super::mcaptcha::stats::StatsFilter;
+use crate::api::v1::orgs::runners::require_sitekey_access;
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Monitoring {
+        /// prefix of the monitoring endpoints
+        pub scope: &'static str,
+        pub sitekeys: &'static str,
+        pub stats: &'static str,
+    }
+
+    impl Monitoring {
+        pub const fn new() -> Self {
+            Self {
+                scope: "/api/v1/monitoring/",
+                sitekeys: "/api/v1/monitoring/sitekeys",
+                stats: "/api/v1/monitoring/sitekeys/{key}/stats",
+            }
+        }
+
+        pub fn get_stats_route(&self, key: &str) -> String {
+            self.stats.replace("{key}", key)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(sitekeys);
+    cfg.service(stats);
+}
+
+/// list sitekeys of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.monitoring.sitekeys",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn sitekeys(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let sitekeys: Vec<MCaptchaDetails> = data
+        .db
+        .get_all_user_captchas(&username)
+        .await?
+        .into_iter()
+        .map(|c| MCaptchaDetails {
+            name: c.description,
+            key: c.key,
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(sitekeys))
+}
+
+/// get stats of a sitekey of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.monitoring.stats",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+async fn stats(
+    path: web::Path<String>,
+    query: web::Query<StatsFilter>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    require_sitekey_access(&data, &key, &username, OrgRole::Viewer).await?;
+    let query = query.query()?;
+    let stats = data.stats.fetch(&data, &username, &key, &query).await?;
+    Ok(HttpResponse::Ok().json(&stats))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+
+    use crate::api::v1::account::tokens::{CreateToken, CreatedToken, RevokeToken};
+    use crate::api::v1::mcaptcha::create::MCaptchaDetails;
+    use crate::api_tokens::Scope;
+    use crate::stats::CaptchaStats;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn read_stats_tokens_work_pg() {
+        let data = pg::get_data().await;
+        read_stats_tokens_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn read_stats_tokens_work_maria() {
+        let data = maria::get_data().await;
+        read_stats_tokens_work(data).await;
+    }
+
+    async fn read_stats_tokens_work(data: ArcData) {
+        const NAME: &str = "monitoringtokenuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "monitoringtokenuser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let payload = CreateToken {
+            name: "monitoring".into(),
+            scope: Scope::ReadStats,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.account.tokens.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let token: CreatedToken = test::read_body_json(resp).await;
+        let bearer = format!("Bearer {}", token.token);
+
+        // stats can be read with the token
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.monitoring.sitekeys)
+                .insert_header((header::AUTHORIZATION, bearer.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let sitekeys: Vec<MCaptchaDetails> = test::read_body_json(resp).await;
+        assert!(sitekeys.iter().any(|s| s.key == key.key));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&V1_API_ROUTES.monitoring.get_stats_route(&key.key))
+                .insert_header((header::AUTHORIZATION, bearer.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let _: CaptchaStats = test::read_body_json(resp).await;
+
+        // but nothing else
+        let resp = test::call_service(
+            &app,
+            post_request!(&key, V1_API_ROUTES.captcha.delete)
+                .insert_header((header::AUTHORIZATION, bearer.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.notifications.get)
+                .insert_header((header::AUTHORIZATION, bearer.clone()))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(data.db.captcha_exists(Some(NAME), &key.key).await.unwrap());
+
+        // revoked tokens are rejected
+        let tokens = data.db.get_api_tokens(NAME).await.unwrap();
+        let revoke = RevokeToken { id: tokens[0].id };
+        let resp = test::call_service(
+            &app,
+            post_request!(&revoke, V1_API_ROUTES.account.tokens.revoke)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.monitoring.sitekeys)
+                .insert_header((header::AUTHORIZATION, bearer))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -12,6 +12,11 @@ use crate::AppData;
 
 use db_core::AddNotification;
 
+/// maximum length of notification headings, in characters
+pub const MAX_HEADING_LEN: usize = 100;
+/// maximum length of notification messages, in characters
+pub const MAX_MESSAGE_LEN: usize = 250;
+
 #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct AddNotificationRequest {
     pub to: String,
@@ -29,8 +34,16 @@ pub async fn add_notification(
     data: AppData,
     id: Identity,
 ) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
     let sender = id.identity().unwrap();
     // TODO handle error where payload.to doesn't exist
+    if payload.heading.chars().count() > MAX_HEADING_LEN
+        || payload.message.chars().count() > MAX_MESSAGE_LEN
+    {
+        return Err(ServiceError::NotificationTooLong);
+    }
 
     let p = AddNotification {
         from: &sender,
@@ -39,7 +52,7 @@ pub async fn add_notification(
         heading: &payload.heading,
     };
 
-    data.db.create_notification(&p).await?;
+    super::notify(&data, &p).await?;
 
     Ok(HttpResponse::Ok())
 }
@@ -98,4 +111,57 @@ pub mod tests {
         .await;
         assert_eq!(send_notification_resp.status(), StatusCode::OK);
     }
+
+    #[actix_rt::test]
+    async fn notification_disabled_works_pg() {
+        let data = pg::get_data().await;
+        notification_disabled_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn notification_disabled_works_maria() {
+        let data = maria::get_data().await;
+        notification_disabled_works(data).await;
+    }
+
+    pub async fn notification_disabled_works(data: ArcData) {
+        const NAME: &str = "notifdisableduser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "notifdisableduser@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.features.notifications = false;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+
+        let msg = AddNotificationRequest {
+            to: NAME.into(),
+            heading: "Test notification".into(),
+            message: "Testing notifications with a dummy message".into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            V1_API_ROUTES.notifications.add,
+            &msg,
+            ServiceError::FeatureDisabled,
+        )
+        .await;
+
+        let (_creds, signin_resp) = signin(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.panel.notifications)
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -0,0 +1,133 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+
+use crate::errors::*;
+use crate::AppData;
+
+/// route handler that deletes a notification
+#[my_codegen::delete(
+    path = "crate::V1_API_ROUTES.notifications.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete_notification(
+    data: AppData,
+    path: web::Path<i32>,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let receiver = id.identity().unwrap();
+    data.db
+        .delete_notification(&receiver, path.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::api::v1::notifications::add::AddNotificationRequest;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn notification_delete_works_pg() {
+        let data = pg::get_data().await;
+        notification_delete_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn notification_delete_works_maria() {
+        let data = maria::get_data().await;
+        notification_delete_works(data).await;
+    }
+
+    pub async fn notification_delete_works(data: ArcData) {
+        const NAME1: &str = "notifdeleteuser1";
+        const NAME2: &str = "notifdeleteuser2";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL1: &str = "testnotificationdelete1@a.com";
+        const EMAIL2: &str = "testnotificationdelete2@a.com";
+        let data = &data;
+
+        delete_user(data, NAME1).await;
+        delete_user(data, NAME2).await;
+
+        register_and_signin(data, NAME1, EMAIL1, PASSWORD).await;
+        register_and_signin(data, NAME2, EMAIL2, PASSWORD).await;
+        let (_creds, signin_resp) = signin(data, NAME1, PASSWORD).await;
+        let (_creds2, signin_resp2) = signin(data, NAME2, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let cookies2 = get_cookie!(signin_resp2);
+        let app = get_app!(data).await;
+
+        let msg = AddNotificationRequest {
+            to: NAME2.into(),
+            heading: "testing notifications delete".into(),
+            message: "testing notifications delete message".into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&msg, V1_API_ROUTES.notifications.add)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let notification = data
+            .db
+            .get_all_unread_notifications(NAME2)
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let route = V1_API_ROUTES
+            .notifications
+            .get_delete_route(notification.id.unwrap());
+
+        // only the receiver can delete a notification
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri(&route)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri(&route)
+                .cookie(cookies2.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data
+            .db
+            .get_all_unread_notifications(NAME2)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::delete()
+                .uri(&route)
+                .cookie(cookies2)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! End-to-end encrypted notifications
+//!
+//! Users can register an RSA-OAEP (SHA-256) public key, generated in the
+//! panel, to which administrators encrypt sensitive notifications. Messages
+//! are encrypted in the administrator's browser with a random AES-256-GCM key,
+//! which is wrapped with the public key. The server only stores the resulting
+//! [EncryptedMessage], and the private key never leaves the receiver's browser.
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+use crate::AppData;
+
+/// version of the [EncryptedMessage] format
+pub const ENCRYPTED_MESSAGE_VERSION: u8 = 1;
+/// maximum length of a serialized [EncryptedMessage]
+pub const MAX_ENCRYPTED_MESSAGE_LEN: usize = 16 * 1024;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NotificationKey {
+    /// base64 encoded SPKI public key; the key is removed when unset
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// notification message encrypted to a notification key. All fields except
+/// `v` are base64 encoded
+pub struct EncryptedMessage {
+    /// format version
+    pub v: u8,
+    /// AES-256-GCM key, wrapped with the receiver's public key
+    pub key: String,
+    /// AES-GCM nonce
+    pub iv: String,
+    /// message, encrypted with `key`
+    pub data: String,
+}
+
+impl EncryptedMessage {
+    /// check that the message is well-formed, so that plaintext isn't stored
+    /// by mistake
+    pub fn validate(&self) -> ServiceResult<()> {
+        let decode = |v: &str| {
+            STANDARD
+                .decode(v)
+                .map_err(|_| ServiceError::InvalidEncryptedMessage)
+        };
+        let key = decode(&self.key)?;
+        let iv = decode(&self.iv)?;
+        let data = decode(&self.data)?;
+        // wrapped keys are as long as the RSA modulus: 2048 to 8192 bits
+        if self.v != ENCRYPTED_MESSAGE_VERSION
+            || !(256..=1024).contains(&key.len())
+            || iv.len() != 12
+            || data.is_empty()
+        {
+            return Err(ServiceError::InvalidEncryptedMessage);
+        }
+        Ok(())
+    }
+
+    /// serialize message for storage, as the notification's message
+    pub fn to_message(&self) -> ServiceResult<String> {
+        self.validate()?;
+        let message = serde_json::to_string(self).unwrap();
+        if message.len() > MAX_ENCRYPTED_MESSAGE_LEN {
+            return Err(ServiceError::InvalidEncryptedMessage);
+        }
+        Ok(message)
+    }
+}
+
+pub mod runners {
+    use super::*;
+
+    /// check that a notification key looks like a DER encoded SPKI key
+    pub fn validate_key(key: &str) -> ServiceResult<()> {
+        let der = STANDARD
+            .decode(key)
+            .map_err(|_| ServiceError::InvalidNotificationKey)?;
+        // SEQUENCE tag; RSA keys of 2048 to 8192 bits
+        if der.first() != Some(&0x30) || !(128..=2048).contains(&der.len()) {
+            return Err(ServiceError::InvalidNotificationKey);
+        }
+        Ok(())
+    }
+}
+
+/// get the user's notification key
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.notifications.get_key",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get_key(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let username = id.identity().unwrap();
+    let key = data.db.get_notification_key(&username).await?;
+    Ok(HttpResponse::Ok().json(NotificationKey { key }))
+}
+
+/// set or remove the user's notification key
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.notifications.update_key",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn update_key(
+    payload: web::Json<NotificationKey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let username = id.identity().unwrap();
+    if let Some(key) = &payload.key {
+        runners::validate_key(key)?;
+    }
+    data.db
+        .set_notification_key(&username, payload.key.as_deref())
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    /// base64 encoded stand-in for a 2048-bit SPKI key
+    pub fn dummy_key() -> String {
+        let mut der = vec![0x30];
+        der.resize(294, 1);
+        STANDARD.encode(der)
+    }
+
+    pub fn dummy_message() -> EncryptedMessage {
+        EncryptedMessage {
+            v: ENCRYPTED_MESSAGE_VERSION,
+            key: STANDARD.encode([1; 256]),
+            iv: STANDARD.encode([2; 12]),
+            data: STANDARD.encode(b"ciphertext"),
+        }
+    }
+
+    #[test]
+    fn validation_works() {
+        assert!(runners::validate_key(&dummy_key()).is_ok());
+        assert!(runners::validate_key("not base64!").is_err());
+        assert!(runners::validate_key(&STANDARD.encode([0x30; 10])).is_err());
+
+        let message = dummy_message();
+        assert!(message.to_message().is_ok());
+        let plaintext = EncryptedMessage {
+            data: "you have been warned".into(),
+            ..message.clone()
+        };
+        assert!(plaintext.validate().is_err());
+        let bad_iv = EncryptedMessage {
+            iv: STANDARD.encode([2; 16]),
+            ..message.clone()
+        };
+        assert!(bad_iv.validate().is_err());
+        let bad_version = EncryptedMessage { v: 2, ..message };
+        assert!(bad_version.validate().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn notification_key_works_pg() {
+        let data = pg::get_data().await;
+        notification_key_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn notification_key_works_maria() {
+        let data = maria::get_data().await;
+        notification_key_works(data).await;
+    }
+
+    async fn notification_key_works(data: ArcData) {
+        const NAME: &str = "notifkeyuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "notifkeyuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.notifications;
+
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.update_key,
+            &NotificationKey {
+                key: Some("plaintext".into()),
+            },
+            ServiceError::InvalidNotificationKey,
+        )
+        .await;
+
+        let payload = NotificationKey {
+            key: Some(dummy_key()),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.update_key)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.get_key)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let key: NotificationKey = test::read_body_json(resp).await;
+        assert_eq!(key, payload);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&NotificationKey::default(), routes.update_key)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(data.db.get_notification_key(NAME).await.unwrap(), None);
+    }
+}
@@ -4,13 +4,46 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_identity::Identity;
-use actix_web::{HttpResponse, Responder};
+use actix_web::{web, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
 
 use crate::errors::*;
 use crate::AppData;
 
-use db_core::Notification;
+use db_core::{Notification, NotificationFilter, NotificationStatus};
+
+/// maximum, and default, number of notifications in a page
+pub const MAX_NOTIFICATIONS_LIMIT: usize = 100;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// query parameters of [get_notification]; pages are numbered from zero
+pub struct NotificationQuery {
+    /// `unread` (the default), `read` or `all`
+    #[serde(default)]
+    pub status: NotificationStatus,
+    /// only notifications received at or after this unix timestamp
+    pub after: Option<i64>,
+    /// only notifications received before this unix timestamp
+    pub before: Option<i64>,
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl NotificationQuery {
+    fn filter(&self) -> NotificationFilter {
+        NotificationFilter {
+            status: self.status,
+            after: self.after,
+            before: self.before,
+        }
+    }
+
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(MAX_NOTIFICATIONS_LIMIT)
+            .clamp(1, MAX_NOTIFICATIONS_LIMIT)
+    }
+}
 
 #[derive(Default, PartialEq, Clone, Deserialize, Serialize)]
 pub struct NotificationResp {
@@ -19,6 +52,9 @@ pub struct NotificationResp {
     pub message: String,
     pub received: i64,
     pub id: i32,
+    /// is the message an encrypted message
+    #[serde(default)]
+    pub encrypted: bool,
 }
 
 impl From<Notification> for NotificationResp {
@@ -29,6 +65,7 @@ impl From<Notification> for NotificationResp {
             received: n.received.unwrap(),
             id: n.id.unwrap(),
             message: n.message.unwrap(),
+            encrypted: n.encrypted,
         }
     }
 }
@@ -46,7 +83,8 @@ impl NotificationResp {
     }
 }
 
-/// route handler that gets all unread notifications
+/// route handler that gets a page of notifications, newest first. Unread
+/// notifications are returned unless `status` says otherwise
 #[my_codegen::get(
     path = "crate::V1_API_ROUTES.notifications.get",
     wrap = "crate::api::v1::get_middleware()"
@@ -54,11 +92,23 @@ impl NotificationResp {
 pub async fn get_notification(
     data: AppData,
     id: Identity,
+    query: web::Query<NotificationQuery>,
 ) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
     let receiver = id.identity().unwrap();
     // TODO handle error where payload.to doesn't exist
 
-    let notifications = data.db.get_all_unread_notifications(&receiver).await?;
+    let notifications = data
+        .db
+        .get_notifications(
+            &receiver,
+            &query.filter(),
+            query.page.unwrap_or(0),
+            query.limit(),
+        )
+        .await?;
     let notifications = NotificationResp::from_notifications(notifications);
     Ok(HttpResponse::Ok().json(notifications))
 }
@@ -70,6 +120,7 @@ pub mod tests {
 
     use super::*;
     use crate::api::v1::notifications::add::AddNotificationRequest;
+    use crate::api::v1::notifications::mark_read::MarkReadReq;
     use crate::tests::*;
     use crate::*;
 
@@ -138,5 +189,51 @@ pub mod tests {
         assert_eq!(notification.name, NAME1);
         assert_eq!(notification.message, MESSAGE);
         assert_eq!(notification.heading, HEADING);
+
+        // read notifications are only listed on request, in pages
+        let mark_read = MarkReadReq {
+            id: notification.id,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&mark_read, V1_API_ROUTES.notifications.mark_read)
+                .cookie(cookies2.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        for _ in 0..2 {
+            let resp = test::call_service(
+                &app,
+                post_request!(&msg, V1_API_ROUTES.notifications.add)
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let get = |query: &str| {
+            test::TestRequest::get()
+                .uri(&format!("{}?{query}", V1_API_ROUTES.notifications.get))
+                .cookie(cookies2.clone())
+                .to_request()
+        };
+        for (query, count) in [
+            ("", 2),
+            ("status=unread&limit=1", 1),
+            ("status=unread&page=1&limit=1", 1),
+            ("status=unread&page=1&limit=2", 0),
+            ("status=read", 1),
+            ("status=all", 3),
+            ("status=all&before=1000", 0),
+        ] {
+            let resp = test::call_service(&app, get(query)).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let notifications: Vec<NotificationResp> = test::read_body_json(resp).await;
+            assert_eq!(notifications.len(), count, "{query}");
+        }
+        let resp = test::call_service(&app, get("status=none")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 }
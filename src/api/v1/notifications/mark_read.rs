@@ -25,6 +25,9 @@ pub async fn mark_read(
     payload: web::Json<MarkReadReq>,
     id: Identity,
 ) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
     let receiver = id.identity().unwrap();
     // TODO handle error where payload.to doesn't exist
 
@@ -36,6 +39,23 @@ pub async fn mark_read(
     Ok(HttpResponse::Ok())
 }
 
+/// route handler that marks all notifications read
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.notifications.mark_all_read",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn mark_all_read(
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let receiver = id.identity().unwrap();
+    data.db.mark_all_notifications_read(&receiver).await?;
+    Ok(HttpResponse::Ok())
+}
+
 #[cfg(test)]
 pub mod tests {
     use actix_web::http::StatusCode;
@@ -136,5 +156,40 @@ pub mod tests {
         let mut notifications: Vec<NotificationResp> =
             test::read_body_json(get_notifications_resp).await;
         assert!(notifications.pop().is_none());
+
+        // mark all read
+        for _ in 0..2 {
+            let resp = test::call_service(
+                &app,
+                post_request!(&msg, V1_API_ROUTES.notifications.add)
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_eq!(
+            data.db
+                .get_all_unread_notifications(NAME2)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(V1_API_ROUTES.notifications.mark_all_read)
+                .cookie(cookies2.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data
+            .db
+            .get_all_unread_notifications(NAME2)
+            .await
+            .unwrap()
+            .is_empty());
     }
 }
@@ -4,15 +4,31 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 pub mod add;
+pub mod delete;
+pub mod encrypted;
 pub mod get;
 pub mod mark_read;
+pub mod stream;
+pub mod webhook;
+
+use db_core::AddNotification;
+
+use crate::errors::*;
+use crate::webhooks::NotificationPayload;
+use crate::Data;
 
 pub mod routes {
 
     pub struct Notifications {
         pub add: &'static str,
         pub mark_read: &'static str,
+        pub mark_all_read: &'static str,
+        pub delete: &'static str,
         pub get: &'static str,
+        pub get_key: &'static str,
+        pub update_key: &'static str,
+        pub stream: &'static str,
+        pub webhook: super::webhook::routes::Webhook,
     }
 
     impl Notifications {
@@ -20,14 +36,39 @@ pub mod routes {
             Notifications {
                 add: "/api/v1/notifications/add",
                 mark_read: "/api/v1/notifications/read",
+                mark_all_read: "/api/v1/notifications/mark-all-read",
+                delete: "/api/v1/notifications/{id}",
                 get: "/api/v1/notifications/get",
+                get_key: "/api/v1/notifications/key/get",
+                update_key: "/api/v1/notifications/key/update",
+                stream: "/api/v1/notifications/stream",
+                webhook: super::webhook::routes::Webhook::new(),
             }
         }
+
+        pub fn get_delete_route(&self, id: i32) -> String {
+            self.delete.replace("{id}", &id.to_string())
+        }
     }
 }
 
+/// Create a notification, send it to the receiver's open notification streams
+/// and queue it for delivery to their webhook, if they set one
+pub async fn notify(data: &Data, n: &AddNotification<'_>) -> ServiceResult<()> {
+    data.db.create_notification(n).await?;
+    crate::notification_stream::publish(data, NotificationPayload::new(n)).await;
+    data.webhooks.enqueue_notification(n);
+    Ok(())
+}
+
 pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(add::add_notification);
     cfg.service(get::get_notification);
     cfg.service(mark_read::mark_read);
+    cfg.service(mark_read::mark_all_read);
+    cfg.service(delete::delete_notification);
+    cfg.service(encrypted::get_key);
+    cfg.service(encrypted::update_key);
+    cfg.service(stream::stream);
+    webhook::services(cfg);
 }
@@ -0,0 +1,149 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Server-Sent Events stream of new notifications
+use std::time::Duration;
+
+use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{HttpResponse, Responder};
+use futures::Stream;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::timeout;
+
+use crate::errors::*;
+use crate::webhooks::NotificationPayload;
+use crate::AppData;
+
+/// interval at which comments are sent to keep idle streams open through
+/// proxies
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// render notification as an SSE event
+fn event(n: &NotificationPayload) -> Bytes {
+    Bytes::from(format!(
+        "event: notification\ndata: {}\n\n",
+        serde_json::to_string(n).unwrap()
+    ))
+}
+
+/// SSE events of notifications received on `rx` that are addressed to
+/// `username`
+pub fn events(
+    rx: Receiver<NotificationPayload>,
+    username: String,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    futures::stream::unfold((rx, username), |(mut rx, username)| async move {
+        loop {
+            let chunk = match timeout(KEEP_ALIVE, rx.recv()).await {
+                Err(_) => Bytes::from_static(b": keep-alive\n\n"),
+                Ok(Ok(n)) if n.to == username => event(&n),
+                // a slow stream misses notifications; they are still listed
+                Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => return None,
+            };
+            return Some((Ok(chunk), (rx, username)));
+        }
+    })
+}
+
+/// stream notifications of the user as they are created
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.notifications.stream",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn stream(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let username = id.identity().unwrap();
+    let rx = data.notification_stream.subscribe();
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // events would be held back by compression
+        .insert_header((header::CONTENT_ENCODING, "identity"))
+        .streaming(events(rx, username)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::api::v1::notifications::notify;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn notification_stream_works_pg() {
+        let data = pg::get_data().await;
+        notification_stream_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn notification_stream_works_maria() {
+        let data = maria::get_data().await;
+        notification_stream_works(data).await;
+    }
+
+    async fn notification_stream_works(data: ArcData) {
+        const NAME1: &str = "notifstreamuser1";
+        const NAME2: &str = "notifstreamuser2";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL1: &str = "notifstreamuser1@a.com";
+        const EMAIL2: &str = "notifstreamuser2@a.com";
+        let data = &data;
+
+        delete_user(data, NAME1).await;
+        delete_user(data, NAME2).await;
+        register_and_signin(data, NAME1, EMAIL1, PASSWORD).await;
+        register_and_signin(data, NAME2, EMAIL2, PASSWORD).await;
+        let (_, signin_resp) = signin(data, NAME1, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(V1_API_ROUTES.notifications.stream)
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+
+        // only notifications of the user are streamed
+        let app_data = AppData::new(data.clone());
+        let mut events =
+            Box::pin(events(data.notification_stream.subscribe(), NAME1.into()));
+        for to in [NAME2, NAME1] {
+            let n = db_core::AddNotification {
+                to,
+                from: NAME2,
+                heading: "streamed",
+                message: "streamed notification",
+            };
+            notify(&app_data, &n).await.unwrap();
+        }
+        let chunk = events.next().await.unwrap().unwrap();
+        let chunk = String::from_utf8(chunk.to_vec()).unwrap();
+        let json = chunk
+            .strip_prefix("event: notification\ndata: ")
+            .unwrap()
+            .trim_end();
+        let n: NotificationPayload = serde_json::from_str(json).unwrap();
+        assert_eq!(n.to, NAME1);
+        assert_eq!(n.from, NAME2);
+        assert_eq!(n.heading, "streamed");
+    }
+}
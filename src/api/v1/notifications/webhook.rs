@@ -0,0 +1,297 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Webhook that receives the notifications of a user, for instance to relay
+//! them to a chat room
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::NotificationWebhook;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::api::v1::mcaptcha::webhook::{WebhookSecret, DELIVERY_LOG_LIMIT};
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Webhook {
+        pub set: &'static str,
+        pub get: &'static str,
+        pub delete: &'static str,
+        pub deliveries: &'static str,
+    }
+
+    impl Webhook {
+        pub const fn new() -> Self {
+            Self {
+                set: "/api/v1/notifications/webhook/set",
+                get: "/api/v1/notifications/webhook/get",
+                delete: "/api/v1/notifications/webhook/delete",
+                deliveries: "/api/v1/notifications/webhook/deliveries",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(set);
+    cfg.service(get);
+    cfg.service(delete);
+    cfg.service(deliveries);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetNotificationWebhook {
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NotificationWebhookInfo {
+    pub url: String,
+}
+
+/// set notification webhook of the user. A new signing secret is generated on
+/// every call
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.notifications.webhook.set",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set(
+    payload: web::Json<SetNotificationWebhook>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let username = id.identity().unwrap();
+    crate::demo::restrict_demo_user(&data, &username)?;
+    let url = Url::parse(payload.url.trim())?;
+    crate::webhooks::check_url(&url).await?;
+
+    let webhook = NotificationWebhook {
+        url: url.to_string(),
+        secret: get_random(32),
+    };
+    data.db
+        .set_notification_webhook(&username, &webhook)
+        .await?;
+    Ok(HttpResponse::Ok().json(WebhookSecret {
+        secret: webhook.secret,
+    }))
+}
+
+/// get notification webhook of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.notifications.webhook.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let webhook = data.db.get_notification_webhook(&username).await?;
+    Ok(HttpResponse::Ok().json(NotificationWebhookInfo { url: webhook.url }))
+}
+
+/// delete notification webhook of the user
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.notifications.webhook.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    data.db.delete_notification_webhook(&username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get recent notification webhook delivery logs of the user
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.notifications.webhook.deliveries",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn deliveries(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let deliveries = data
+        .db
+        .fetch_notification_webhook_deliveries(&username, DELIVERY_LOG_LIMIT)
+        .await?;
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use reqwest::Client;
+
+    use super::*;
+    use crate::api::v1::notifications::notify;
+    use crate::tests::*;
+    use crate::webhooks::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn notification_webhook_works_pg() {
+        let data = pg::get_data().await;
+        notification_webhook_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn notification_webhook_works_maria() {
+        let data = maria::get_data().await;
+        notification_webhook_works(data).await;
+    }
+
+    async fn notification_webhook_works(data: ArcData) {
+        const NAME: &str = "notifwebhookuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "notifwebhookuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp) = signin(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V1_API_ROUTES.notifications.webhook;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut payload = SetNotificationWebhook {
+            url: "ftp://example.com".into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::NotAUrl,
+        )
+        .await;
+
+        payload.url = "http://127.0.0.1:1/".into();
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set,
+            &payload,
+            ServiceError::WebhookAddressNotAllowed,
+        )
+        .await;
+
+        payload.url = "https://1.1.1.1/hook".into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.set)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let secret: WebhookSecret = test::read_body_json(resp).await;
+        assert_eq!(
+            data.db.get_notification_webhook(NAME).await.unwrap().secret,
+            secret.secret
+        );
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let info: NotificationWebhookInfo = test::read_body_json(resp).await;
+        assert_eq!(info.url, payload.url);
+
+        // webhook that was set before addresses were checked, so that the
+        // delivery fails
+        let webhook = db_core::NotificationWebhook {
+            url: "http://127.0.0.1:1/".into(),
+            secret: secret.secret.clone(),
+        };
+        data.db
+            .set_notification_webhook(NAME, &webhook)
+            .await
+            .unwrap();
+
+        // created notifications are delivered, and every attempt is logged
+        let app_data = AppData::new(data.clone());
+        let n = db_core::AddNotification {
+            to: NAME,
+            from: NAME,
+            heading: "webhook",
+            message: "notification webhook",
+        };
+        notify(&app_data, &n).await.unwrap();
+        assert_eq!(
+            data.db
+                .get_all_unread_notifications(NAME)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        let delivery = NotificationDelivery {
+            payload: NotificationPayload {
+                event: WebhookEvent::Notification,
+                to: NAME.into(),
+                from: NAME.into(),
+                heading: n.heading.into(),
+                message: n.message.into(),
+                time: 0,
+            },
+            attempt: MAX_ATTEMPTS,
+        };
+        WebhookDispatcher::deliver_notification(&app_data, &Client::new(), delivery)
+            .await
+            .unwrap();
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.deliveries)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let deliveries: Vec<db_core::WebhookDelivery> = test::read_body_json(resp).await;
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event, "notification");
+        assert_eq!(deliveries[0].status, None);
+        assert!(!deliveries[0].success);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.delete)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
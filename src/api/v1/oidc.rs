@@ -0,0 +1,482 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! OpenID Connect single sign-on routes
+//!
+//! Starting single sign-on while signed in links the provider's account to
+//! the current user. Otherwise, the user linked to the provider's account is
+//! signed in, and first-time users get an account when auto-provisioning is
+//! enabled. Second factors are left to the provider.
+use actix_identity::Identity;
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use db_core::errors::DBError;
+use serde::Deserialize;
+use url::Url;
+
+use super::auth::runners::MAX_USERNAME_LEN;
+use super::mcaptcha::get_random;
+use crate::errors::*;
+use crate::oidc::{Claims, PendingLogin, LOGIN_TTL};
+use crate::AppData;
+
+/// cookie that binds a login attempt to the browser that started it
+pub const BROWSER_COOKIE: &str = "mcaptcha-oidc";
+
+pub mod routes {
+    pub struct Oidc {
+        pub login: &'static str,
+        pub callback: &'static str,
+    }
+
+    impl Oidc {
+        pub const fn new() -> Self {
+            Self {
+                login: "/api/v1/auth/oidc/login",
+                callback: "/api/v1/auth/oidc/callback",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(login);
+    cfg.service(callback);
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub state: String,
+    pub code: Option<String>,
+    pub error: Option<String>,
+}
+
+pub mod runners {
+    use super::*;
+
+    /// username for a first-time user: derived from the provider's username
+    /// or email address, when acceptable
+    fn base_username(data: &AppData, claims: &Claims) -> String {
+        claims
+            .preferred_username
+            .as_deref()
+            .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+            .and_then(|u| data.creds.username(u).ok())
+            // leave room for the suffix of taken usernames
+            .filter(|u| !u.is_empty() && u.chars().count() <= MAX_USERNAME_LEN - 4)
+            .unwrap_or_else(|| "user".into())
+    }
+
+    /// create account for a first-time user. Its password is random, so that
+    /// it can only be signed into through single sign-on
+    pub async fn provision(data: &AppData, claims: &Claims) -> ServiceResult<String> {
+        let base = base_username(data, claims);
+        let email = claims.verified_email();
+        let hash = data.creds.password(&get_random(32))?;
+
+        let mut username = base.clone();
+        loop {
+            let secret = get_random(32);
+            let p = db_core::Register {
+                username: &username,
+                hash: &hash,
+                email,
+                secret: &secret,
+            };
+
+            match data.db.register(&p).await {
+                Ok(_) => break,
+                Err(DBError::SecretTaken) => continue,
+                Err(DBError::UsernameTaken) => {
+                    username = format!("{base}{}", get_random(4).to_lowercase());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if email.is_some() {
+            data.db.set_email_verified(&username, true).await?;
+        }
+        if data.settings.require_registration_approval {
+            data.db.set_pending_approval(&username, true).await?;
+        }
+        Ok(username)
+    }
+
+    /// user to sign in as, linking or provisioning the account as necessary
+    pub async fn resolve_user(
+        data: &AppData,
+        pending: &PendingLogin,
+        claims: &Claims,
+    ) -> ServiceResult<String> {
+        let identity = db_core::OidcIdentity {
+            issuer: &claims.iss,
+            subject: &claims.sub,
+        };
+
+        match data.db.get_oidc_user(&identity).await {
+            Ok(username) => {
+                return match &pending.link_to {
+                    Some(link_to) if link_to != &username => {
+                        Err(ServiceError::OidcIdentityTaken)
+                    }
+                    _ => Ok(username),
+                };
+            }
+            Err(DBError::OidcIdentityNotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Some(username) = &pending.link_to {
+            crate::demo::restrict_demo_user(data, username)?;
+            data.db.link_oidc_identity(username, &identity).await?;
+            return Ok(username.clone());
+        }
+
+        // linking by email address would let anyone who controls an account
+        // with the provider take over accounts on this instance
+        if let Some(email) = claims.verified_email() {
+            if data.db.email_exists(email).await? {
+                return Err(ServiceError::OidcAccountNotLinked);
+            }
+        }
+
+        let auto_provision = data.oidc.as_ref().map(|o| o.auto_provision());
+        if !data.settings.allow_registration || auto_provision != Some(true) {
+            return Err(ServiceError::ClosedForRegistration);
+        }
+        if let Some(email) = claims.verified_email() {
+            crate::api::v1::auth::runners::email_domain(data, email).await?;
+        }
+        let username = provision(data, claims).await?;
+        data.db.link_oidc_identity(&username, &identity).await?;
+        Ok(username)
+    }
+}
+
+/// only redirect within the instance after sign-in. Browsers treat `\` like
+/// `/`, so paths with backslashes or control characters, which could be read
+/// as another host, are dropped too
+fn local_redirect(redirect_to: Option<String>) -> Option<String> {
+    let base = Url::parse("http://localhost/").unwrap();
+    redirect_to.filter(|r| {
+        r.starts_with('/')
+            && !r.starts_with("//")
+            && !r.chars().any(|c| c == '\\' || c.is_control())
+            && base
+                .join(r)
+                .map_or(false, |url| url.origin() == base.origin())
+    })
+}
+
+/// cookie holding the value that binds a login attempt to the browser; it's
+/// only sent to the callback
+fn browser_cookie(data: &AppData, value: String, max_age: i64) -> Cookie<'static> {
+    Cookie::build(BROWSER_COOKIE, value)
+        .path(crate::V1_API_ROUTES.oidc.callback)
+        .http_only(true)
+        .secure(data.settings.server.has_tls())
+        // sent along with the provider's redirect to the callback
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(max_age))
+        .finish()
+}
+
+/// start single sign-on: redirect to the identity provider
+#[my_codegen::get(path = "crate::V1_API_ROUTES.oidc.login")]
+async fn login(
+    req: HttpRequest,
+    id: Identity,
+    query: web::Query<super::RedirectQuery>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let oidc = data.oidc.as_ref().ok_or(ServiceError::FeatureDisabled)?;
+    let redirect_to = local_redirect(query.into_inner().redirect_to);
+    let ip = data.trusted_proxies.client_ip(&req);
+    let (url, browser) = oidc.begin(&ip, id.identity(), redirect_to).await?;
+    Ok(HttpResponse::Found()
+        .cookie(browser_cookie(&data, browser, LOGIN_TTL))
+        .append_header((header::LOCATION, url.as_str()))
+        .finish())
+}
+
+/// complete single sign-on and sign the user in
+#[my_codegen::get(path = "crate::V1_API_ROUTES.oidc.callback")]
+async fn callback(
+    req: HttpRequest,
+    id: Identity,
+    query: web::Query<CallbackQuery>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let oidc = data.oidc.as_ref().ok_or(ServiceError::FeatureDisabled)?;
+    if let Some(error) = &query.error {
+        log::info!("OpenID Connect provider returned error: {error}");
+    }
+    let browser = req.cookie(BROWSER_COOKIE);
+    let browser = browser.as_ref().map(|c| c.value());
+    let (pending, claims) = oidc
+        .finish(&query.state, browser, query.code.as_deref())
+        .await?;
+    let username = runners::resolve_user(&data, &pending, &claims).await?;
+    if data.db.is_pending_approval(&username).await? {
+        return Err(ServiceError::RegistrationPendingApproval);
+    }
+    if data.db.is_suspended(&username).await? {
+        return Err(ServiceError::AccountSuspended);
+    }
+    id.remember(username);
+
+    let location = pending
+        .redirect_to
+        .unwrap_or_else(|| crate::PAGES.panel.home.to_string());
+    Ok(HttpResponse::Found()
+        .cookie(browser_cookie(&data, String::new(), 0))
+        .append_header((header::LOCATION, location))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::{App, HttpServer};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use serde_json::json;
+    use url::Url;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    /// identity provider that issues tokens for `claims`
+    struct MockProvider {
+        issuer: String,
+        claims: Mutex<serde_json::Value>,
+    }
+
+    async fn discovery(idp: web::Data<MockProvider>) -> HttpResponse {
+        HttpResponse::Ok().json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+        }))
+    }
+
+    async fn token(idp: web::Data<MockProvider>) -> HttpResponse {
+        let claims = idp.claims.lock().unwrap().to_string();
+        let id_token = format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims));
+        HttpResponse::Ok().json(json!({
+            "access_token": "accesstoken",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        }))
+    }
+
+    fn start_provider() -> web::Data<MockProvider> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let idp = web::Data::new(MockProvider {
+            issuer: format!("http://127.0.0.1:{port}"),
+            claims: Mutex::new(json!({})),
+        });
+        let app_idp = idp.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_idp.clone())
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(discovery),
+                )
+                .route("/token", web::post().to(token))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_rt::spawn(server);
+        idp
+    }
+
+    #[actix_rt::test]
+    async fn oidc_works_pg() {
+        let data = pg::get_data().await;
+        oidc_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn oidc_works_maria() {
+        let data = maria::get_data().await;
+        oidc_works(data).await;
+    }
+
+    async fn oidc_works(data: ArcData) {
+        const NAME: &str = "oidcuser";
+        const EMAIL: &str = "oidcuser@a.com";
+        const PASSWORD_USER: &str = "oidcpassworduser";
+        const PASSWORD_EMAIL: &str = "oidcpassworduser@a.com";
+        const PASSWORD: &str = "longpassworddomain";
+
+        let idp = start_provider();
+        let mut settings = data.settings.clone();
+        settings.oidc = Some(crate::settings::Oidc {
+            issuer: Url::parse(&idp.issuer).unwrap(),
+            client_id: "mcaptcha".into(),
+            client_secret: "oidcsecret".into(),
+            scopes: "email profile".into(),
+            auto_provision: true,
+        });
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app = get_app!(data).await;
+
+        delete_user(data, NAME).await;
+        delete_user(data, PASSWORD_USER).await;
+
+        // run single sign-on as the provider's user `sub`
+        macro_rules! sso {
+            ($cookies:expr, $sub:expr, $email:expr, $bound:expr) => {{
+                let mut req = test::TestRequest::get().uri(V1_API_ROUTES.oidc.login);
+                if let Some(cookies) = $cookies {
+                    req = req.cookie(cookies);
+                }
+                let resp = test::call_service(&app, req.to_request()).await;
+                assert_eq!(resp.status(), StatusCode::FOUND);
+                let browser = resp
+                    .response()
+                    .cookies()
+                    .find(|c| c.name() == BROWSER_COOKIE)
+                    .unwrap()
+                    .into_owned();
+                assert_eq!(browser.path(), Some(V1_API_ROUTES.oidc.callback));
+                assert!(browser.http_only().unwrap());
+                let location = resp.headers().get(header::LOCATION).unwrap();
+                let location = Url::parse(location.to_str().unwrap()).unwrap();
+                let query: std::collections::HashMap<_, _> =
+                    location.query_pairs().into_owned().collect();
+                assert_eq!(query["scope"], "openid email profile");
+                assert_eq!(query["code_challenge_method"], "S256");
+                assert!(query["redirect_uri"].ends_with(V1_API_ROUTES.oidc.callback));
+
+                *idp.claims.lock().unwrap() = json!({
+                    "iss": idp.issuer,
+                    "sub": $sub,
+                    "aud": "mcaptcha",
+                    "nonce": query["nonce"],
+                    "email": $email,
+                    "email_verified": true,
+                    "preferred_username": NAME,
+                });
+                let uri = format!(
+                    "{}?code=authcode&state={}",
+                    V1_API_ROUTES.oidc.callback, query["state"]
+                );
+                let mut req = test::TestRequest::get().uri(&uri);
+                if let Some(cookies) = $cookies {
+                    req = req.cookie(cookies);
+                }
+                if $bound {
+                    req = req.cookie(browser);
+                }
+                test::call_service(&app, req.to_request()).await
+            }};
+            ($cookies:expr, $sub:expr, $email:expr) => {
+                sso!($cookies, $sub, $email, true)
+            };
+        }
+        let no_cookies: Option<actix_web::cookie::Cookie> = None;
+        let identity = |subject: &'static str| db_core::OidcIdentity {
+            issuer: idp.issuer.as_str(),
+            subject,
+        };
+
+        // callbacks from other browsers are rejected
+        let resp = sso!(no_cookies.clone(), "sub1", EMAIL, false);
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(data.db.get_oidc_user(&identity("sub1")).await.is_err());
+
+        // first sign-in provisions an account
+        let resp = sso!(no_cookies.clone(), "sub1", EMAIL);
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(
+            resp.headers().get(header::LOCATION).unwrap(),
+            PAGES.panel.home
+        );
+        assert!(resp
+            .response()
+            .cookies()
+            .any(|c| c.name() != BROWSER_COOKIE));
+        assert!(resp
+            .response()
+            .cookies()
+            .any(|c| c.name() == BROWSER_COOKIE && c.value().is_empty()));
+        assert_eq!(
+            data.db.get_oidc_user(&identity("sub1")).await.unwrap(),
+            NAME
+        );
+        assert!(data.db.is_email_verified(NAME).await.unwrap());
+
+        // subsequent sign-ins use the same account
+        let resp = sso!(no_cookies.clone(), "sub1", EMAIL);
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        // existing accounts aren't linked by email address
+        let (_, signin_resp) =
+            register_and_signin(data, PASSWORD_USER, PASSWORD_EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let resp = sso!(no_cookies.clone(), "sub2", PASSWORD_EMAIL);
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.error, format!("{}", ServiceError::OidcAccountNotLinked));
+
+        // signed-in users link the provider's account
+        let resp = sso!(Some(cookies.clone()), "sub2", PASSWORD_EMAIL);
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(
+            data.db.get_oidc_user(&identity("sub2")).await.unwrap(),
+            PASSWORD_USER
+        );
+        let resp = sso!(no_cookies.clone(), "sub2", PASSWORD_EMAIL);
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        // provider's account can't be linked to two users
+        let resp = sso!(Some(cookies.clone()), "sub1", EMAIL);
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // unknown login attempts are rejected
+        let uri = format!("{}?code=authcode&state=foo", V1_API_ROUTES.oidc.callback);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        delete_user(data, NAME).await;
+        delete_user(data, PASSWORD_USER).await;
+    }
+
+    #[test]
+    fn local_redirect_works() {
+        let redirect = |r: &str| local_redirect(Some(r.into()));
+        for r in ["/", "/dashboard", "/sitekey/1?a=b#c", "/a//b"] {
+            assert_eq!(redirect(r).as_deref(), Some(r), "{r}");
+        }
+        for r in [
+            "//evil.com",
+            "/\\evil.com",
+            "/\\/evil.com",
+            "\\evil.com",
+            "/\t/evil.com",
+            "https://evil.com",
+            "javascript:alert(1)",
+            "dashboard",
+            "",
+        ] {
+            assert_eq!(redirect(r), None, "{r}");
+        }
+        assert_eq!(local_redirect(None), None);
+    }
+}
@@ -0,0 +1,1099 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Organizations share ownership of sitekeys between their members
+//!
+//! Members have one of the roles of [OrgRole]: viewers can view the
+//! organization's sitekeys, editors can also update them and move their own
+//! sitekeys into the organization, and owners can also manage members, move
+//! sitekeys out of the organization and delete it.
+//!
+//! Every sitekey of an organization is held by one of its editors or owners,
+//! whose account it is stored under. When the holder leaves the organization
+//! or is made a viewer, their sitekeys are handed over to an owner. Routes for
+//! the holder's own sitekeys are limited to the holder's role too, so that
+//! editors can't delete the sitekeys that they hold.
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::errors::DBError;
+use db_core::{OrgMember, OrgRole};
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::mcaptcha::update::UpdateCaptcha;
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Orgs {
+        pub create: &'static str,
+        pub list: &'static str,
+        pub delete: &'static str,
+        pub members: &'static str,
+        pub set_member: &'static str,
+        pub remove_member: &'static str,
+        pub sitekeys: &'static str,
+        pub add_sitekey: &'static str,
+        pub remove_sitekey: &'static str,
+        pub get_sitekey: &'static str,
+        pub update_sitekey: &'static str,
+    }
+
+    impl Orgs {
+        pub const fn new() -> Self {
+            Self {
+                create: "/api/v1/orgs/create",
+                list: "/api/v1/orgs/list",
+                delete: "/api/v1/orgs/delete",
+                members: "/api/v1/orgs/members/get",
+                set_member: "/api/v1/orgs/members/set",
+                remove_member: "/api/v1/orgs/members/remove",
+                sitekeys: "/api/v1/orgs/sitekeys/get",
+                add_sitekey: "/api/v1/orgs/sitekeys/add",
+                remove_sitekey: "/api/v1/orgs/sitekeys/remove",
+                get_sitekey: "/api/v1/orgs/sitekeys/levels/get",
+                update_sitekey: "/api/v1/orgs/sitekeys/update",
+            }
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(create);
+    cfg.service(list);
+    cfg.service(delete);
+    cfg.service(members);
+    cfg.service(set_member);
+    cfg.service(remove_member);
+    cfg.service(sitekeys);
+    cfg.service(add_sitekey);
+    cfg.service(remove_sitekey);
+    cfg.service(get_sitekey);
+    cfg.service(update_sitekey);
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OrgName {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Org {
+    pub org: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetMember {
+    pub org: String,
+    pub username: String,
+    pub role: OrgRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OrgUser {
+    pub org: String,
+    pub username: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OrgSitekey {
+    pub org: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateOrgSitekey {
+    pub org: String,
+    #[serde(flatten)]
+    pub captcha: UpdateCaptcha,
+}
+
+pub mod runners {
+    use super::*;
+
+    /// maximum length of organization names
+    const MAX_NAME_LEN: usize = 100;
+
+    /// organization names are used in URLs, so they are restricted to
+    /// letters, digits, '-' and '_'
+    pub fn validate_name(name: &str) -> ServiceResult<()> {
+        if name.is_empty()
+            || name.len() > MAX_NAME_LEN
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ServiceError::InvalidOrgName);
+        }
+        Ok(())
+    }
+
+    /// check that user has at least role `min` in the organization. Users that
+    /// aren't members get [ServiceError::OrgNotFound], so that they can't
+    /// probe for organizations
+    pub async fn require_role(
+        data: &AppData,
+        org: &str,
+        username: &str,
+        min: OrgRole,
+    ) -> ServiceResult<OrgRole> {
+        let role = match data.db.get_org_role(org, username).await {
+            Ok(role) => role,
+            Err(DBError::OrgMemberNotFound) => return Err(ServiceError::OrgNotFound),
+            Err(e) => return Err(e.into()),
+        };
+        if role < min {
+            return Err(ServiceError::OrgRoleRequired);
+        }
+        Ok(role)
+    }
+
+    /// check that sitekey belongs to the organization
+    pub async fn require_sitekey(
+        data: &AppData,
+        org: &str,
+        key: &str,
+    ) -> ServiceResult<()> {
+        if data.db.get_captcha_org(key).await?.as_deref() != Some(org) {
+            return Err(ServiceError::CaptchaNotFound);
+        }
+        Ok(())
+    }
+
+    /// check that `username` has at least role `min` in the organization that
+    /// their sitekey `key` belongs to. Routes that act on the user's own
+    /// sitekeys check this, so that holding an organization's sitekey doesn't
+    /// let the holder do more than their role allows. Sitekeys that don't
+    /// belong to an organization, or to the user, are left to the route
+    pub async fn require_sitekey_role(
+        data: &AppData,
+        key: &str,
+        username: &str,
+        min: OrgRole,
+    ) -> ServiceResult<()> {
+        let org = match data.db.get_captcha_org(key).await {
+            Ok(Some(org)) => org,
+            Ok(None) | Err(DBError::CaptchaNotFound) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if data.db.captcha_exists(Some(username), key).await? {
+            require_role(data, &org, username, min).await?;
+        }
+        Ok(())
+    }
+
+    /// check that `username` holds sitekey `key` and, when it belongs to an
+    /// organization, has at least role `min` in it. This is the ownership
+    /// check of sitekey routes
+    pub async fn require_sitekey_access(
+        data: &AppData,
+        key: &str,
+        username: &str,
+        min: OrgRole,
+    ) -> ServiceResult<()> {
+        if !data.db.captcha_exists(Some(username), key).await? {
+            return Err(ServiceError::CaptchaNotFound);
+        }
+        require_sitekey_role(data, key, username, min).await
+    }
+
+    /// is `username` the only owner of the organization
+    fn is_last_owner(members: &[OrgMember], username: &str) -> bool {
+        let mut owners = members.iter().filter(|m| m.role == OrgRole::Owner);
+        owners.all(|m| m.username == username)
+            && members
+                .iter()
+                .any(|m| m.username == username && m.role == OrgRole::Owner)
+    }
+
+    /// add member to organization, or change their role, on behalf of owner
+    /// `by`
+    pub async fn set_member(
+        data: &AppData,
+        by: &str,
+        payload: &SetMember,
+    ) -> ServiceResult<()> {
+        require_role(data, &payload.org, by, OrgRole::Owner).await?;
+        if !data.db.username_exists(&payload.username).await? {
+            return Err(ServiceError::AccountNotFound);
+        }
+        let members = data.db.get_org_members(&payload.org).await?;
+        if payload.role != OrgRole::Owner && is_last_owner(&members, &payload.username) {
+            return Err(ServiceError::LastOrgOwner);
+        }
+        if payload.role == OrgRole::Viewer {
+            data.db
+                .reassign_org_captchas(&payload.org, &payload.username, by)
+                .await?;
+        }
+        data.db
+            .set_org_member(&payload.org, &payload.username, payload.role)
+            .await?;
+        Ok(())
+    }
+
+    /// remove member from organization on behalf of `by`. Owners can remove
+    /// any member, and members can leave on their own
+    pub async fn remove_member(
+        data: &AppData,
+        by: &str,
+        org: &str,
+        username: &str,
+    ) -> ServiceResult<()> {
+        if by == username {
+            require_role(data, org, by, OrgRole::Viewer).await?;
+        } else {
+            require_role(data, org, by, OrgRole::Owner).await?;
+        }
+        let members = data.db.get_org_members(org).await?;
+        if !members.iter().any(|m| m.username == username) {
+            return Err(ServiceError::OrgMemberNotFound);
+        }
+        if is_last_owner(&members, username) {
+            return Err(ServiceError::LastOrgOwner);
+        }
+        // an owner remains, as the last owner can't leave
+        let receiver = if by == username {
+            members
+                .iter()
+                .find(|m| m.role == OrgRole::Owner && m.username != username)
+                .map(|m| m.username.as_str())
+                .unwrap()
+        } else {
+            by
+        };
+        data.db
+            .reassign_org_captchas(org, username, receiver)
+            .await?;
+        data.db.remove_org_member(org, username).await?;
+        Ok(())
+    }
+
+    /// hand over organization sitekeys held by a user whose account is being
+    /// deleted. When the user is the last owner of an organization, the
+    /// member with the highest role is made an owner. Organizations without
+    /// other members are deleted, and their sitekeys with the account
+    pub async fn leave_all(data: &AppData, username: &str) -> ServiceResult<()> {
+        for membership in data.db.get_user_orgs(username).await?.iter() {
+            let org = &membership.org;
+            let members = data.db.get_org_members(org).await?;
+            let others = members.iter().filter(|m| m.username != username);
+            let owner = match others.clone().find(|m| m.role == OrgRole::Owner) {
+                Some(owner) => owner,
+                None => match others.max_by_key(|m| m.role) {
+                    Some(successor) => {
+                        data.db
+                            .set_org_member(org, &successor.username, OrgRole::Owner)
+                            .await?;
+                        successor
+                    }
+                    None => {
+                        data.db.delete_org(org).await?;
+                        continue;
+                    }
+                },
+            };
+            data.db
+                .reassign_org_captchas(org, username, &owner.username)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// create organization; the user creating it becomes its owner
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.create",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn create(
+    payload: web::Json<OrgName>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::validate_name(&payload.name)?;
+    data.db.create_org(&username, &payload.name).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// list organizations of which the user is a member
+#[my_codegen::get(
+    path = "crate::V1_API_ROUTES.orgs.list",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn list(data: AppData, id: Identity) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let orgs = data.db.get_user_orgs(&username).await?;
+    Ok(HttpResponse::Ok().json(orgs))
+}
+
+/// delete organization. Its sitekeys are kept by the members that hold them
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.delete",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn delete(
+    payload: web::Json<OrgName>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.name, &username, OrgRole::Owner).await?;
+    data.db.delete_org(&payload.name).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// list members of an organization
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.members",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn members(
+    payload: web::Json<Org>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.org, &username, OrgRole::Viewer).await?;
+    let members = data.db.get_org_members(&payload.org).await?;
+    Ok(HttpResponse::Ok().json(members))
+}
+
+/// add member to an organization, or change their role
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.set_member",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn set_member(
+    payload: web::Json<SetMember>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::set_member(&data, &username, &payload).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// remove member from an organization, or leave it
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.remove_member",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn remove_member(
+    payload: web::Json<OrgUser>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::remove_member(&data, &username, &payload.org, &payload.username).await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// list sitekeys of an organization
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.sitekeys",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn sitekeys(
+    payload: web::Json<Org>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.org, &username, OrgRole::Viewer).await?;
+    let captchas = data.db.get_org_captchas(&payload.org).await?;
+    Ok(HttpResponse::Ok().json(captchas))
+}
+
+/// move one of the user's sitekeys into an organization
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.add_sitekey",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn add_sitekey(
+    payload: web::Json<OrgSitekey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.org, &username, OrgRole::Editor).await?;
+    if !data
+        .db
+        .captcha_exists(Some(&username), &payload.key)
+        .await?
+    {
+        return Err(ServiceError::CaptchaNotFound);
+    }
+    if data.db.get_captcha_org(&payload.key).await?.is_some() {
+        return Err(ServiceError::CaptchaInOrg);
+    }
+    data.db
+        .set_captcha_org(&username, &payload.key, Some(&payload.org))
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// move sitekey out of an organization, to the owner moving it
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.remove_sitekey",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn remove_sitekey(
+    payload: web::Json<OrgSitekey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.org, &username, OrgRole::Owner).await?;
+    runners::require_sitekey(&data, &payload.org, &payload.key).await?;
+    data.db
+        .set_captcha_org(&username, &payload.key, None)
+        .await?;
+    Ok(HttpResponse::Ok())
+}
+
+/// get levels of an organization's sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.get_sitekey",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get_sitekey(
+    payload: web::Json<OrgSitekey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.org, &username, OrgRole::Viewer).await?;
+    runners::require_sitekey(&data, &payload.org, &payload.key).await?;
+    let levels = data.db.get_captcha_levels(None, &payload.key).await?;
+    Ok(HttpResponse::Ok().json(levels))
+}
+
+/// update an organization's sitekey
+#[my_codegen::post(
+    path = "crate::V1_API_ROUTES.orgs.update_sitekey",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn update_sitekey(
+    payload: web::Json<UpdateOrgSitekey>,
+    data: AppData,
+    id: Identity,
+) -> ServiceResult<impl Responder> {
+    let username = id.identity().unwrap();
+    runners::require_role(&data, &payload.org, &username, OrgRole::Editor).await?;
+    runners::require_sitekey(&data, &payload.org, &payload.captcha.key).await?;
+    let holder = data.db.get_captcha_owner(&payload.captcha.key).await?;
+    crate::api::v1::mcaptcha::update::runner::update_captcha(
+        &payload.captcha,
+        &data,
+        &holder,
+    )
+    .await?;
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::{Captcha, OrgMembership};
+
+    use super::*;
+    use crate::api::v1::mcaptcha::delete::DeleteCaptcha;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn validate_name_works() {
+        assert!(runners::validate_name("acme-corp_2").is_ok());
+        assert!(runners::validate_name("").is_err());
+        assert!(runners::validate_name("acme corp").is_err());
+        assert!(runners::validate_name("acme/corp").is_err());
+        assert!(runners::validate_name(&"a".repeat(101)).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn orgs_work_pg() {
+        let data = pg::get_data().await;
+        orgs_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn orgs_work_maria() {
+        let data = maria::get_data().await;
+        orgs_work(data).await;
+    }
+
+    async fn orgs_work(data: ArcData) {
+        const NAME: &str = "orgowneruser";
+        const MEMBER: &str = "orgmemberuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "orgowneruser@a.com";
+        const MEMBER_EMAIL: &str = "orgmemberuser@a.com";
+        const ORG: &str = "orgs-work-test";
+        let data = &data;
+        let routes = &V1_API_ROUTES.orgs;
+
+        delete_user(data, NAME).await;
+        delete_user(data, MEMBER).await;
+        if data.db.org_exists(ORG).await.unwrap() {
+            data.db.delete_org(ORG).await.unwrap();
+        }
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let (_, signin_resp) =
+            register_and_signin(data, MEMBER, MEMBER_EMAIL, PASSWORD).await;
+        let member_cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.create,
+            &OrgName {
+                name: "acme corp".into(),
+            },
+            ServiceError::InvalidOrgName,
+        )
+        .await;
+        let org_name = OrgName { name: ORG.into() };
+        let resp = test::call_service(
+            &app,
+            post_request!(&org_name, routes.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            MEMBER,
+            PASSWORD,
+            routes.create,
+            &org_name,
+            ServiceError::OrgNameTaken,
+        )
+        .await;
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(routes.list)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let orgs: Vec<OrgMembership> = test::read_body_json(resp).await;
+        assert_eq!(
+            orgs,
+            vec![OrgMembership {
+                org: ORG.into(),
+                role: OrgRole::Owner
+            }]
+        );
+
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let sitekey = OrgSitekey {
+            org: ORG.into(),
+            key: token_key.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&sitekey, routes.add_sitekey)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // users that aren't members can't see the organization
+        let org = Org { org: ORG.into() };
+        bad_post_req_test(
+            data,
+            MEMBER,
+            PASSWORD,
+            routes.sitekeys,
+            &org,
+            ServiceError::OrgNotFound,
+        )
+        .await;
+
+        let viewer = SetMember {
+            org: ORG.into(),
+            username: MEMBER.into(),
+            role: OrgRole::Viewer,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&viewer, routes.set_member)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&org, routes.sitekeys)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let sitekeys: Vec<Captcha> = test::read_body_json(resp).await;
+        assert_eq!(sitekeys.len(), 1);
+        assert_eq!(sitekeys[0].key, token_key.key);
+
+        let update = UpdateOrgSitekey {
+            org: ORG.into(),
+            captcha: UpdateCaptcha {
+                levels: vec![L1, L2],
+                duration: 60,
+                description: "shared sitekey".into(),
+                key: token_key.key.clone(),
+                publish_benchmarks: false,
+            },
+        };
+        bad_post_req_test(
+            data,
+            MEMBER,
+            PASSWORD,
+            routes.update_sitekey,
+            &update,
+            ServiceError::OrgRoleRequired,
+        )
+        .await;
+        bad_post_req_test(
+            data,
+            MEMBER,
+            PASSWORD,
+            routes.set_member,
+            &viewer,
+            ServiceError::OrgRoleRequired,
+        )
+        .await;
+
+        let editor = SetMember {
+            role: OrgRole::Editor,
+            ..viewer.clone()
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&editor, routes.set_member)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&update, routes.update_sitekey)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let sitekeys = data.db.get_org_captchas(ORG).await.unwrap();
+        assert_eq!(sitekeys[0].description, "shared sitekey");
+        assert_eq!(sitekeys[0].duration, 60);
+        // sitekey is still held by the member that added it
+        assert_eq!(
+            data.db.get_captcha_owner(&token_key.key).await.unwrap(),
+            NAME
+        );
+
+        // the last owner can't leave or be demoted
+        let owner = OrgUser {
+            org: ORG.into(),
+            username: NAME.into(),
+        };
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.remove_member,
+            &owner,
+            ServiceError::LastOrgOwner,
+        )
+        .await;
+        bad_post_req_test(
+            data,
+            NAME,
+            PASSWORD,
+            routes.set_member,
+            &SetMember {
+                org: ORG.into(),
+                username: NAME.into(),
+                role: OrgRole::Editor,
+            },
+            ServiceError::LastOrgOwner,
+        )
+        .await;
+
+        let member = OrgUser {
+            org: ORG.into(),
+            username: MEMBER.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&member, routes.remove_member)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            MEMBER,
+            PASSWORD,
+            routes.members,
+            &org,
+            ServiceError::OrgNotFound,
+        )
+        .await;
+
+        // sitekeys are handed over when their holder's account is deleted
+        data.db
+            .set_org_member(ORG, MEMBER, OrgRole::Viewer)
+            .await
+            .unwrap();
+        runners::leave_all(data, NAME).await.unwrap();
+        assert_eq!(
+            data.db.get_org_role(ORG, MEMBER).await.unwrap(),
+            OrgRole::Owner
+        );
+        assert_eq!(
+            data.db.get_captcha_owner(&token_key.key).await.unwrap(),
+            MEMBER
+        );
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&sitekey, routes.remove_sitekey)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.get_org_captchas(ORG).await.unwrap().is_empty());
+        let resp = test::call_service(
+            &app,
+            post_request!(&org_name, routes.delete)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!data.db.org_exists(ORG).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn org_sitekey_roles_work_pg() {
+        let data = pg::get_data().await;
+        org_sitekey_roles_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn org_sitekey_roles_work_maria() {
+        let data = maria::get_data().await;
+        org_sitekey_roles_work(data).await;
+    }
+
+    /// sitekey routes of the holder of an organization's sitekey are limited
+    /// to the holder's role
+    async fn org_sitekey_roles_work(data: ArcData) {
+        const NAME: &str = "orgroleowneruser";
+        const MEMBER: &str = "orgrolememberuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "orgroleowneruser@a.com";
+        const MEMBER_EMAIL: &str = "orgrolememberuser@a.com";
+        const ORG: &str = "org-sitekey-roles-test";
+        let data = &data;
+        let routes = &V1_API_ROUTES.orgs;
+
+        delete_user(data, NAME).await;
+        delete_user(data, MEMBER).await;
+        if data.db.org_exists(ORG).await.unwrap() {
+            data.db.delete_org(ORG).await.unwrap();
+        }
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp) =
+            register_and_signin(data, MEMBER, MEMBER_EMAIL, PASSWORD).await;
+        let member_cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        data.db.create_org(NAME, ORG).await.unwrap();
+        data.db
+            .set_org_member(ORG, MEMBER, OrgRole::Editor)
+            .await
+            .unwrap();
+        let (_, _, token_key) = add_levels_util(data, MEMBER, PASSWORD).await;
+        let sitekey = OrgSitekey {
+            org: ORG.into(),
+            key: token_key.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&sitekey, routes.add_sitekey)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // a sitekey managed by an external id, also held for the organization
+        let external = crate::api::v1::mcaptcha::create::CreateCaptcha {
+            levels: vec![L1, L2],
+            duration: 30,
+            description: "held external sitekey".into(),
+            publish_benchmarks: false,
+        };
+        let external_route = V1_API_ROUTES.captcha.external.get_by_id("org-held");
+        let put_external = |payload| {
+            test::TestRequest::put()
+                .uri(&external_route)
+                .cookie(member_cookies.clone())
+                .set_json(payload)
+                .to_request()
+        };
+        let resp = test::call_service(&app, put_external(&external)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let created: crate::api::v1::mcaptcha::external::ExternalSitekey =
+            test::read_body_json(resp).await;
+        let external_sitekey = OrgSitekey {
+            org: ORG.into(),
+            key: created.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&external_sitekey, routes.add_sitekey)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let update = UpdateCaptcha {
+            levels: vec![L1, L2],
+            duration: 60,
+            description: "held sitekey".into(),
+            key: token_key.key.clone(),
+            publish_benchmarks: false,
+        };
+        let delete = DeleteCaptcha {
+            key: token_key.key.clone(),
+            password: PASSWORD.into(),
+            totp: None,
+        };
+
+        // editors update, but don't delete, the sitekeys they hold
+        let resp = test::call_service(
+            &app,
+            post_request!(&update, V1_API_ROUTES.captcha.update)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        bad_post_req_test(
+            data,
+            MEMBER,
+            PASSWORD,
+            V1_API_ROUTES.captcha.delete,
+            &delete,
+            ServiceError::OrgRoleRequired,
+        )
+        .await;
+        assert!(data
+            .db
+            .captcha_exists(Some(MEMBER), &token_key.key)
+            .await
+            .unwrap());
+
+        // holders that lost their role can't update sitekeys either
+        data.db
+            .set_org_member(ORG, MEMBER, OrgRole::Viewer)
+            .await
+            .unwrap();
+        for (url, payload) in [
+            (V1_API_ROUTES.captcha.update, serde_json::to_value(&update)),
+            (
+                V1_API_ROUTES.captcha.update_key,
+                serde_json::to_value(&token_key),
+            ),
+        ] {
+            bad_post_req_test(
+                data,
+                MEMBER,
+                PASSWORD,
+                url,
+                &payload.unwrap(),
+                ServiceError::OrgRoleRequired,
+            )
+            .await;
+        }
+
+        // nor any of their other settings
+        let key = &token_key.key;
+        let captcha = &V1_API_ROUTES.captcha;
+        let now = sqlx::types::time::OffsetDateTime::now_utc().unix_timestamp();
+        let key_only = serde_json::json!({ "key": key });
+        let pattern = serde_json::json!({
+            "avg_traffic": 500,
+            "peak_sustainable_traffic": 5_000,
+            "broke_my_site_traffic": null,
+            "description": "held sitekey",
+            "publish_benchmarks": false,
+        });
+        let publish = serde_json::json!({ "publish": false });
+        let theme = serde_json::to_value(db_core::WidgetTheme::default()).unwrap();
+        let mutating = [
+            (
+                captcha.update_strict.to_string(),
+                serde_json::json!({ "key": key, "strict": true }),
+            ),
+            (
+                captcha.update_invisible.to_string(),
+                serde_json::json!({ "key": key, "enabled": true, "threshold": 50 }),
+            ),
+            (
+                captcha.update_fallback.to_string(),
+                serde_json::json!({ "key": key, "enabled": true, "wait": 5 }),
+            ),
+            (
+                captcha.easy.update.to_string(),
+                serde_json::json!({ "key": key, "pattern": pattern }),
+            ),
+            (
+                captcha.domains.set.to_string(),
+                serde_json::json!({ "key": key, "domains": ["example.com"] }),
+            ),
+            (
+                captcha.alerts.set.to_string(),
+                serde_json::json!({
+                    "key": key,
+                    "levels": true,
+                    "highest_level": true,
+                    "failure_rate": 50,
+                    "min_attempts": 1,
+                    "anomaly_factor": 5,
+                }),
+            ),
+            (captcha.alerts.delete.to_string(), key_only.clone()),
+            (
+                captcha.burst.add.to_string(),
+                serde_json::json!({
+                    "key": key,
+                    "starts": now + 60,
+                    "ends": now + 120,
+                    "difficulty_factor": 500,
+                }),
+            ),
+            (
+                captcha.burst.delete.to_string(),
+                serde_json::json!({ "key": key, "id": 1 }),
+            ),
+            (
+                captcha.decay.set.to_string(),
+                serde_json::json!({
+                    "key": key,
+                    "sustain": 300,
+                    "step": 60,
+                    "steps": 5,
+                    "curve": db_core::DecayCurve::Exponential,
+                }),
+            ),
+            (captcha.decay.delete.to_string(), key_only.clone()),
+            (
+                captcha.fraud.set.to_string(),
+                serde_json::json!({
+                    "key": key,
+                    "max_hash_rate": 1_000_000,
+                    "nonce_outlier_factor": 10,
+                    "repeated_result_limit": 3,
+                }),
+            ),
+            (captcha.fraud.delete.to_string(), key_only.clone()),
+            (
+                captcha.webhook.set.to_string(),
+                serde_json::json!({
+                    "key": key,
+                    "url": "https://example.com/hook",
+                    "on_escalation": true,
+                }),
+            ),
+            (captcha.webhook.delete.to_string(), key_only.clone()),
+            (captcha.publish.get_set_route(key), publish.clone()),
+            (captcha.publish.get_purge_route(key), publish),
+            (captcha.theme.get_set_route(key), theme.clone()),
+            (captcha.theme.get_reset_route(key), theme),
+            (
+                captcha.recommendation.get_apply_route(key),
+                key_only.clone(),
+            ),
+            (
+                captcha.recommendation.get_auto_route(key),
+                serde_json::json!({ "enabled": false }),
+            ),
+        ];
+        for (url, payload) in mutating.iter() {
+            let resp = test::call_service(
+                &app,
+                post_request!(payload, url)
+                    .cookie(member_cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(
+                resp.status(),
+                ServiceError::OrgRoleRequired.status_code(),
+                "{url}"
+            );
+            let err: ErrorToResponse = test::read_body_json(resp).await;
+            assert_eq!(err.code, ServiceError::OrgRoleRequired.code(), "{url}");
+        }
+        let external = crate::api::v1::mcaptcha::create::CreateCaptcha {
+            duration: 45,
+            ..external
+        };
+        let resp = test::call_service(&app, put_external(&external)).await;
+        assert_eq!(resp.status(), ServiceError::OrgRoleRequired.status_code());
+        assert_eq!(
+            data.db
+                .get_captcha_config(MEMBER, &created.key)
+                .await
+                .unwrap()
+                .duration,
+            30
+        );
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&token_key, V1_API_ROUTES.captcha.get)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // owners delete them
+        data.db
+            .set_org_member(ORG, MEMBER, OrgRole::Owner)
+            .await
+            .unwrap();
+        let resp = test::call_service(
+            &app,
+            post_request!(&delete, V1_API_ROUTES.captcha.delete)
+                .cookie(member_cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        data.db.delete_org(ORG).await.unwrap();
+        delete_user(data, NAME).await;
+        delete_user(data, MEMBER).await;
+    }
+}
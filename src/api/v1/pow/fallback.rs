@@ -0,0 +1,248 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Fallback challenges, for clients that can't solve PoW. See [crate::fallback]
+
+use actix_web::HttpRequest;
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use super::verify_pow::ValidationToken;
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::fallback::{self, CHALLENGE_ID_LEN};
+use crate::AppData;
+use crate::V1_API_ROUTES;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StartFallbackPayload {
+    pub key: String,
+    /// origin of the page that embeds the widget, checked against allowed
+    /// domains of the sitekey
+    #[serde(default)]
+    pub origin: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FallbackChallenge {
+    /// ID of the challenge
+    pub challenge: String,
+    /// seconds to wait before completing the challenge
+    pub wait: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CompleteFallbackPayload {
+    pub key: String,
+    /// ID of the challenge, as issued in [FallbackChallenge]
+    pub challenge: String,
+}
+
+/// route handler that starts a fallback challenge
+#[my_codegen::post(path = "V1_API_ROUTES.pow.start_fallback()")]
+pub async fn start_fallback(
+    req: HttpRequest,
+    payload: web::Json<StartFallbackPayload>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    crate::domains::check(&data, &payload.key, payload.origin.as_deref()).await?;
+    let mode = data.db.captcha_fallback(&payload.key).await?;
+    if !mode.enabled {
+        return Err(ServiceError::FallbackDisabled);
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let ip = data.trusted_proxies.client_ip(&req);
+    if !fallback::allow(&data, &ip, now).await? {
+        return Err(ServiceError::TooManyFallbacks);
+    }
+
+    let challenge = get_random(CHALLENGE_ID_LEN);
+    fallback::start(&data, &challenge, &payload.key, mode.wait, now).await?;
+    Ok(HttpResponse::Ok().json(FallbackChallenge {
+        challenge,
+        wait: mode.wait,
+    }))
+}
+
+/// route handler that completes a fallback challenge and issues a validation
+/// token once its wait is over
+#[my_codegen::post(path = "V1_API_ROUTES.pow.complete_fallback()")]
+pub async fn complete_fallback(
+    req: HttpRequest,
+    payload: web::Json<CompleteFallbackPayload>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    fallback::complete(&data, &payload.challenge, &payload.key, now).await?;
+
+    let ip = data.trusted_proxies.client_ip(&req);
+    let token = fallback::issue_token(&data, &payload.key, &ip).await?;
+    Ok(HttpResponse::Ok().json(ValidationToken { token }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::Fallback;
+
+    use super::*;
+    use crate::api::v1::mcaptcha::update::UpdateFallback;
+    use crate::api::v1::pow::verify_token::{
+        CaptchaValidateResp, VerifyCaptchaResultPayload,
+    };
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn fallback_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        fallback_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn fallback_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        fallback_works(data).await;
+    }
+
+    pub async fn fallback_works(data: ArcData) {
+        const NAME: &str = "powfallbackusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "powfallbackusr@a.com";
+
+        let data = &data;
+
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let start = StartFallbackPayload {
+            key: token_key.key.clone(),
+            origin: None,
+        };
+        let set_fallback = |wait| UpdateFallback {
+            key: token_key.key.clone(),
+            enabled: true,
+            wait,
+        };
+
+        // fallback is disabled by default
+        let resp = test::call_service(
+            &app,
+            post_request!(&start, V1_API_ROUTES.pow.start_fallback).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // challenges can't be completed before their wait is over
+        let resp = test::call_service(
+            &app,
+            post_request!(&set_fallback(60), V1_API_ROUTES.captcha.update_fallback)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&start, V1_API_ROUTES.pow.start_fallback).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let challenge: FallbackChallenge = test::read_body_json(resp).await;
+        assert_eq!(challenge.wait, 60);
+        let complete = CompleteFallbackPayload {
+            key: token_key.key.clone(),
+            challenge: challenge.challenge,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&complete, V1_API_ROUTES.pow.complete_fallback).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // tokens of completed challenges are valid
+        let resp = test::call_service(
+            &app,
+            post_request!(&set_fallback(0), V1_API_ROUTES.captcha.update_fallback)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            data.db.captcha_fallback(&token_key.key).await.unwrap(),
+            Fallback {
+                enabled: true,
+                wait: 0
+            }
+        );
+        let resp = test::call_service(
+            &app,
+            post_request!(&start, V1_API_ROUTES.pow.start_fallback).to_request(),
+        )
+        .await;
+        let challenge: FallbackChallenge = test::read_body_json(resp).await;
+        let complete = CompleteFallbackPayload {
+            key: token_key.key.clone(),
+            challenge: challenge.challenge,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&complete, V1_API_ROUTES.pow.complete_fallback).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let token: ValidationToken = test::read_body_json(resp).await;
+        // tokens are issued without solving PoW
+        assert!(data.tokens.get(&token.token).unwrap().fallback);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&complete, V1_API_ROUTES.pow.complete_fallback).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let secret = data.db.get_secret(NAME).await.unwrap();
+        let validate = VerifyCaptchaResultPayload {
+            secret: secret.secret,
+            key: token_key.key.clone(),
+            token: token.token,
+            ip: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&validate, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(resp.valid);
+
+        // challenges are rate limited by IP address
+        for _ in 2..fallback::FALLBACKS_PER_WINDOW {
+            let resp = test::call_service(
+                &app,
+                post_request!(&start, V1_API_ROUTES.pow.start_fallback).to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        let resp = test::call_service(
+            &app,
+            post_request!(&start, V1_API_ROUTES.pow.start_fallback).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
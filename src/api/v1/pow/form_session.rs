@@ -0,0 +1,301 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Form session tokens
+//!
+//! Validation tokens are single-use and short-lived, which doesn't suit forms
+//! that span several pages. Backends can exchange a validation token for a
+//! form session token, which is valid for one scope, such as "checkout", until
+//! it expires, and check it on every step of the form.
+//!
+//! Form session tokens are signed with the secret of the sitekey's owner, so
+//! they don't need to be stored and are valid across instances. Updating the
+//! secret revokes them.
+use actix_web::{web, HttpResponse, Responder};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::types::time::OffsetDateTime;
+
+use super::verify_token::{runners, CaptchaValidateResp, VerifyCaptchaResultPayload};
+use crate::errors::*;
+use crate::AppData;
+use crate::V1_API_ROUTES;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// maximum length of form session scopes
+const MAX_SCOPE_LEN: usize = 100;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// claims of a form session token
+pub struct FormSessionClaims {
+    /// sitekey for which the session was issued
+    pub key: String,
+    /// scope for which the session is valid
+    pub scope: String,
+    /// unix timestamp at which the session expires
+    pub exp: i64,
+}
+
+impl FormSessionClaims {
+    /// sign claims with the account secret
+    pub fn sign(&self, secret: &str) -> String {
+        let claims = URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap());
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(claims.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{claims}.{signature}")
+    }
+
+    /// verify signature of a form session token and decode its claims.
+    /// Expiry isn't checked
+    pub fn verify(secret: &str, session: &str) -> Option<Self> {
+        let (claims, signature) = session.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(claims.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+        let claims = URL_SAFE_NO_PAD.decode(claims).ok()?;
+        serde_json::from_slice(&claims).ok()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateFormSession {
+    #[serde(flatten)]
+    pub token: VerifyCaptchaResultPayload,
+    /// scope for which the session is valid
+    pub scope: String,
+    /// validity of the session in seconds; defaults to, and is capped at,
+    /// `captcha.form_session_max_ttl`
+    #[serde(default)]
+    pub ttl: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FormSessionResp {
+    /// was the validation token valid
+    pub valid: bool,
+    /// form session token; set when the validation token was valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// unix timestamp at which the session expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CheckFormSession {
+    pub secret: String,
+    pub key: String,
+    pub session: String,
+    pub scope: String,
+}
+
+/// route handler that exchanges a validation token for a form session token
+#[my_codegen::post(path = "V1_API_ROUTES.pow.create_form_session()")]
+#[tracing::instrument(skip_all, fields(key = %payload.token.key))]
+pub async fn create_form_session(
+    payload: web::Json<CreateFormSession>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let payload = payload.into_inner();
+    if payload.scope.is_empty() || payload.scope.len() > MAX_SCOPE_LEN {
+        return Err(ServiceError::InvalidFormSessionScope);
+    }
+    let secret = payload.token.secret.clone();
+    let key = payload.token.key.clone();
+    if !runners::validate(&data, payload.token).await? {
+        let resp = FormSessionResp {
+            valid: false,
+            session: None,
+            expires: None,
+        };
+        return Ok(HttpResponse::Ok().json(resp));
+    }
+
+    let max_ttl = data.settings.captcha.form_session_max_ttl;
+    let ttl = payload.ttl.unwrap_or(max_ttl).clamp(1, max_ttl);
+    let claims = FormSessionClaims {
+        key,
+        scope: payload.scope,
+        exp: OffsetDateTime::now_utc().unix_timestamp() + ttl as i64,
+    };
+    let resp = FormSessionResp {
+        valid: true,
+        session: Some(claims.sign(&secret)),
+        expires: Some(claims.exp),
+    };
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// route handler that checks a form session token. Sessions can be checked
+/// any number of times until they expire
+#[my_codegen::post(path = "V1_API_ROUTES.pow.check_form_session()")]
+#[tracing::instrument(skip_all, fields(key = %payload.key))]
+pub async fn check_form_session(
+    payload: web::Json<CheckFormSession>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let secret = data.db.get_secret_from_captcha(&payload.key).await?;
+    if secret.secret != payload.secret {
+        return Err(ServiceError::WrongPassword);
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let valid = match FormSessionClaims::verify(&secret.secret, &payload.session) {
+        Some(claims) => {
+            claims.key == payload.key
+                && claims.scope == payload.scope
+                && claims.exp > now
+        }
+        None => false,
+    };
+    Ok(HttpResponse::Ok().json(CaptchaValidateResp { valid }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn form_session_signature_works() {
+        let claims = FormSessionClaims {
+            key: "key".into(),
+            scope: "checkout".into(),
+            exp: 100,
+        };
+        let session = claims.sign("secret");
+        assert_eq!(FormSessionClaims::verify("secret", &session), Some(claims));
+        assert_eq!(FormSessionClaims::verify("other", &session), None);
+        assert_eq!(FormSessionClaims::verify("secret", "garbage"), None);
+
+        // claims can't be altered without invalidating the signature
+        let (_, signature) = session.split_once('.').unwrap();
+        let forged = FormSessionClaims {
+            key: "key".into(),
+            scope: "checkout".into(),
+            exp: i64::MAX,
+        };
+        let forged_claims = forged.sign("secret");
+        let (forged_claims, _) = forged_claims.split_once('.').unwrap();
+        let forged = format!("{forged_claims}.{signature}");
+        assert_eq!(FormSessionClaims::verify("secret", &forged), None);
+    }
+
+    #[actix_rt::test]
+    async fn form_sessions_work_pg() {
+        let data = crate::tests::pg::get_data().await;
+        form_sessions_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn form_sessions_work_maria() {
+        let data = crate::tests::maria::get_data().await;
+        form_sessions_work(data).await;
+    }
+
+    pub async fn form_sessions_work(data: ArcData) {
+        const NAME: &str = "formsessionuser";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "formsessionuser@a.com";
+        const SCOPE: &str = "checkout";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+        let secret = data.db.get_secret(NAME).await.unwrap().secret;
+        let routes = &V1_API_ROUTES.pow;
+
+        let mut payload = CreateFormSession {
+            token: VerifyCaptchaResultPayload {
+                token: get_validation_token(data, &token_key.key).await,
+                key: token_key.key.clone(),
+                secret: NAME.into(),
+                ip: None,
+            },
+            scope: SCOPE.into(),
+            ttl: Some(600),
+        };
+        bad_post_req_test_no_auth(
+            data,
+            routes.create_form_session,
+            &payload,
+            ServiceError::WrongPassword,
+        )
+        .await;
+        payload.token.secret = secret.clone();
+        payload.scope = String::new();
+        bad_post_req_test_no_auth(
+            data,
+            routes.create_form_session,
+            &payload,
+            ServiceError::InvalidFormSessionScope,
+        )
+        .await;
+        payload.scope = SCOPE.into();
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.create_form_session).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let session: FormSessionResp = test::read_body_json(resp).await;
+        assert!(session.valid);
+        let expires = session.expires.unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        assert!(expires > now && expires <= now + 600);
+
+        // validation tokens can only be exchanged once
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.create_form_session).to_request(),
+        )
+        .await;
+        let replayed: FormSessionResp = test::read_body_json(resp).await;
+        assert!(!replayed.valid);
+        assert!(replayed.session.is_none());
+
+        let mut check = CheckFormSession {
+            secret,
+            key: token_key.key.clone(),
+            session: session.session.unwrap(),
+            scope: SCOPE.into(),
+        };
+        // sessions can be checked repeatedly
+        for _ in 0..2 {
+            let resp = test::call_service(
+                &app,
+                post_request!(&check, routes.check_form_session).to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+            assert!(resp.valid);
+        }
+
+        check.scope = "login".into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&check, routes.check_form_session).to_request(),
+        )
+        .await;
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(!resp.valid);
+    }
+}
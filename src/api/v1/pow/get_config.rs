@@ -4,22 +4,45 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 //use actix::prelude::*;
+use std::time::Instant;
+
 use actix_web::{web, HttpResponse, Responder};
+use db_core::EscalationKind;
+use libmcaptcha::cache::messages::CachePoW;
 use libmcaptcha::pow::PoWConfig;
 use libmcaptcha::{
-    defense::LevelBuilder, master::messages::AddSiteBuilder, DefenseBuilder,
-    MCaptchaBuilder,
+    defense::LevelBuilder,
+    master::messages::{AddSiteBuilder, RemoveCaptcha},
+    DefenseBuilder, MCaptchaBuilder,
 };
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 
+use super::protocol;
+use crate::alerts;
+use crate::api::v1::mcaptcha::get_random;
+use crate::cluster;
+use crate::decay;
 use crate::errors::*;
+use crate::settings::SuspendedBehavior;
 //use crate::stats::record::record_fetch;
+use crate::webhooks::WebhookEvent;
 use crate::AppData;
 use crate::V1_API_ROUTES;
 
+/// length of challenge correlation IDs
+pub const CORRELATION_ID_LEN: usize = 10;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct GetConfigPayload {
     pub key: String,
+    /// highest [protocol][super::protocol] version supported by the client
+    #[serde(default)]
+    pub version: Option<u32>,
+    /// origin of the page that embeds the widget, checked against allowed
+    /// domains of the sitekey
+    #[serde(default)]
+    pub origin: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -28,54 +51,171 @@ pub struct ApiPoWConfig {
     pub difficulty_factor: u32,
     pub salt: String,
     pub max_recorded_nonce: u32,
+    /// short ID that identifies this challenge in server logs; shown by the
+    /// widget when verification fails
+    pub correlation_id: String,
+    /// challenge can be solved in the background, without visitor
+    /// interaction. See [InvisibleMode][db_core::InvisibleMode]
+    #[serde(default)]
+    pub invisible: bool,
 }
 
-/// get PoW configuration for an mcaptcha key
+/// get PoW configuration for an mcaptcha key. Clients that send a protocol
+/// version receive the versioned response of the negotiated version
 #[my_codegen::post(path = "V1_API_ROUTES.pow.get_config()")]
 pub async fn get_config(
     payload: web::Json<GetConfigPayload>,
     data: AppData,
 ) -> ServiceResult<impl Responder> {
+    let version = payload.version.map(protocol::negotiate).transpose()?;
+    crate::domains::check(&data, &payload.key, payload.origin.as_deref()).await?;
+    let config = get_config_runner(&data, &payload.key).await?;
+    match version {
+        Some(_) => Ok(HttpResponse::Ok().json(protocol::v1::Config::from(config))),
+        None => Ok(HttpResponse::Ok().json(config)),
+    }
+}
+
+/// get PoW configuration for an mcaptcha key and record the fetch
+#[tracing::instrument(skip(data))]
+pub async fn get_config_runner(
+    data: &AppData,
+    key: &str,
+) -> ServiceResult<ApiPoWConfig> {
+    // turned away before its challenge would wait for free runners
+    data.load.check_capacity(data.captcha.tuning().capacity())?;
     //if res.exists.is_none() {
-    if !data.db.captcha_exists(None, &payload.key).await? {
+    if !data.db.captcha_exists(None, key).await? {
         return Err(ServiceError::TokenNotFound);
     }
-    let payload = payload.into_inner();
+    if data.settings.captcha.suspended_behavior == SuspendedBehavior::Reject
+        && data.db.is_captcha_owner_suspended(key).await?
+    {
+        return Err(ServiceError::CaptchaSuspended);
+    }
 
     let config: ServiceResult<PoWConfig> =
-        match data.captcha.get_pow(payload.key.clone()).await {
+        match data.captcha.get_pow(key.to_string()).await {
             Ok(Some(config)) => Ok(config),
             Ok(None) => {
-                init_mcaptcha(&data, &payload.key).await?;
-                let config = data
-                    .captcha
-                    .get_pow(payload.key.clone())
-                    .await
-                    .expect("mcaptcha should be initialized and ready to go");
-                Ok(config.unwrap())
+                init_mcaptcha(data, key).await?;
+                // the sitekey can be refreshed again in between, when it is
+                // edited mid-flight
+                match data.captcha.get_pow(key.to_string()).await? {
+                    Some(config) => Ok(config),
+                    None => {
+                        init_mcaptcha(data, key).await?;
+                        data.captcha
+                            .get_pow(key.to_string())
+                            .await?
+                            .ok_or(ServiceError::CaptchaNotFound)
+                    }
+                }
             }
             Err(e) => Err(e.into()),
         };
     let config = config?;
+    let timer = Instant::now();
     let max_nonce = data
         .db
-        .get_max_nonce_for_level(&payload.key, config.difficulty_factor)
+        .get_max_nonce_for_level(key, config.difficulty_factor)
         .await?;
-    data.stats.record_fetch(&data, &payload.key).await?;
+    data.load.record_db_latency(timer.elapsed());
+    if !data.load.shed() {
+        data.stats.record_fetch(data, key).await?;
+    }
+    if data.webhooks.escalated(key, config.difficulty_factor) {
+        data.webhooks.enqueue(
+            WebhookEvent::Escalation,
+            key,
+            Some(config.difficulty_factor),
+        );
+        decay::record(
+            data,
+            key,
+            EscalationKind::Escalation,
+            config.difficulty_factor,
+        )
+        .await;
+        alerts::escalated(data, key, config.difficulty_factor).await;
+    }
+
+    // step difficulty down gradually after sustained attacks
+    let difficulty_factor = data
+        .decay
+        .difficulty(data, key, config.difficulty_factor)
+        .await?;
+
+    // pre-scale difficulty during scheduled bursts
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let difficulty_factor = match data.bursts.difficulty(key, now) {
+        Some(burst) => burst.max(difficulty_factor),
+        None => difficulty_factor,
+    };
+
+    // slow down solves when overloaded
+    let difficulty_factor = data.load.difficulty(difficulty_factor);
+
+    // challenges are verified against the difficulty that they are served at,
+    // not against that of the defense
+    if cluster::shares_challenges(data) {
+        cluster::add_challenge(data, key, &config.string, difficulty_factor).await?;
+    } else if difficulty_factor != config.difficulty_factor {
+        let duration = data.db.get_captcha_cooldown(key).await? as u64;
+        data.captcha
+            .cache_pow(CachePoW {
+                string: config.string.clone(),
+                difficulty_factor,
+                duration,
+                key: key.into(),
+            })
+            .await?;
+    }
+
+    // escalate to visible challenges once difficulty crosses the threshold
+    let invisible = data.db.captcha_invisible_mode(key).await?;
+    let invisible = invisible.enabled && difficulty_factor <= invisible.threshold;
 
-    let config = ApiPoWConfig {
+    let correlation_id = get_random(CORRELATION_ID_LEN);
+    log::info!("Issued challenge for sitekey {key} [correlation_id: {correlation_id}]");
+
+    Ok(ApiPoWConfig {
         string: config.string,
-        difficulty_factor: config.difficulty_factor,
+        difficulty_factor,
         salt: config.salt,
         max_recorded_nonce: max_nonce,
-    };
-    Ok(HttpResponse::Ok().json(config))
+        correlation_id,
+        invisible,
+    })
 }
+
 /// Call this when [MCaptcha][libmcaptcha::MCaptcha] is not in master.
 ///
 /// This fn gets mcaptcha config from database, builds [Defense][libmcaptcha::Defense],
 /// creates [MCaptcha][libmcaptcha::MCaptcha] and adds it to [Master][libmcaptcha::Defense]
 pub async fn init_mcaptcha(data: &AppData, key: &str) -> ServiceResult<()> {
+    let _sync = data.master_sync.key(key).await;
+    add_mcaptcha(data, key).await
+}
+
+/// Call this after the levels or duration of a sitekey change in the
+/// database.
+///
+/// Replaces the [MCaptcha][libmcaptcha::MCaptcha] of the sitekey in the
+/// [Master][libmcaptcha::Defense] with one built from the new configuration,
+/// so that challenges are issued with the new levels right away. The visitor
+/// count of the sitekey is reset.
+pub async fn refresh_mcaptcha(data: &AppData, key: &str) -> ServiceResult<()> {
+    let _sync = data.master_sync.key(key).await;
+    if let Err(e) = data.captcha.remove(RemoveCaptcha(key.to_string())).await {
+        log::error!("Removing captcha key {key} while refreshing it, error: {e:?}");
+    }
+    add_mcaptcha(data, key).await
+}
+
+/// build [MCaptcha][libmcaptcha::MCaptcha] of a sitekey from the database and
+/// add it to the master
+async fn add_mcaptcha(data: &AppData, key: &str) -> ServiceResult<()> {
     println!("Initializing captcha");
     // get levels
     let levels = data.db.get_captcha_levels(None, key).await?;
@@ -155,6 +295,8 @@ pub mod tests {
 
         let get_config_payload = GetConfigPayload {
             key: token_key.key.clone(),
+            version: None,
+            origin: None,
         };
 
         // update and check changes
@@ -168,8 +310,208 @@ pub mod tests {
         )
         .await;
         assert_eq!(get_config_resp.status(), StatusCode::OK);
-        let config: PoWConfig = test::read_body_json(get_config_resp).await;
+        let config: ApiPoWConfig = test::read_body_json(get_config_resp).await;
         assert_eq!(config.difficulty_factor, L1.difficulty_factor);
+        assert_eq!(config.correlation_id.len(), CORRELATION_ID_LEN);
+        assert!(!config.invisible);
+    }
+
+    #[actix_rt::test]
+    async fn saturated_pow_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        saturated_pow_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn saturated_pow_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        saturated_pow_works(data).await;
+    }
+
+    pub async fn saturated_pow_works(data: ArcData) {
+        use super::*;
+        use crate::api::v1::pow::verify_pow::ApiWork;
+        use crate::data::PoWTuning;
+        use crate::errors::ErrorToResponse;
+        use crate::overload::RETRY_AFTER;
+        use crate::tests::*;
+        use crate::*;
+        use actix_web::http::header;
+        use actix_web::test;
+
+        const NAME: &str = "powsaturatedusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "powsaturatedusr@a.com";
+
+        let data = &data;
+
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+
+        let get_config_payload = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let config: ApiPoWConfig = test::read_body_json(resp).await;
+
+        // verifications that fill all runners and the queue
+        data.captcha
+            .tune(PoWTuning {
+                runners: 1,
+                queue_length: 1,
+            })
+            .unwrap();
+        let _inflight = [data.load.verification(), data.load.verification()];
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let retry_after = resp.headers().get(header::RETRY_AFTER).unwrap();
+        assert_eq!(retry_after.to_str().unwrap(), RETRY_AFTER.to_string());
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.retry_after, Some(RETRY_AFTER));
+
+        let work = ApiWork {
+            string: config.string,
+            result: "".into(),
+            nonce: 0,
+            key: token_key.key.clone(),
+            time: None,
+            worker_type: None,
+            correlation_id: Some(config.correlation_id.clone()),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&work, V1_API_ROUTES.pow.verify_pow).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.correlation_id, Some(config.correlation_id));
+        assert_eq!(data.load.metrics().saturated, 2);
+    }
+
+    #[actix_rt::test]
+    async fn invisible_mode_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        invisible_mode_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn invisible_mode_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        invisible_mode_works(data).await;
+    }
+
+    pub async fn invisible_mode_works(data: ArcData) {
+        use super::*;
+        use crate::api::v1::mcaptcha::update::UpdateInvisibleMode;
+        use crate::tests::*;
+        use crate::widget::config::WidgetConfig;
+        use crate::*;
+        use actix_web::test;
+
+        const NAME: &str = "powinvisibleusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "powinvisibleusr@a.com";
+
+        let data = &data;
+
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let get_config_payload = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: None,
+        };
+        let set_invisible = |threshold| UpdateInvisibleMode {
+            key: token_key.key.clone(),
+            enabled: true,
+            threshold,
+        };
+
+        // challenges within the threshold are solved in the background
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &set_invisible(L1.difficulty_factor),
+                V1_API_ROUTES.captcha.update_invisible
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&crate::WIDGET_ROUTES.get_config(&token_key.key))
+                .to_request(),
+        )
+        .await;
+        let widget_config: WidgetConfig = test::read_body_json(resp).await;
+        assert!(widget_config.invisible);
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        let config: ApiPoWConfig = test::read_body_json(resp).await;
+        assert!(config.invisible);
+
+        // and need visitor interaction above it
+        let resp = test::call_service(
+            &app,
+            post_request!(
+                &set_invisible(L1.difficulty_factor - 1),
+                V1_API_ROUTES.captcha.update_invisible
+            )
+            .cookie(cookies.clone())
+            .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        let config: ApiPoWConfig = test::read_body_json(resp).await;
+        assert!(!config.invisible);
+
+        // only owners set invisible mode
+        let mut other = set_invisible(L1.difficulty_factor);
+        other.key = "nonexistent".into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&other, V1_API_ROUTES.captcha.update_invisible)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 
     #[actix_rt::test]
@@ -243,6 +585,8 @@ pub mod tests {
 
         let get_config_payload = GetConfigPayload {
             key: token_key.key.clone(),
+            version: None,
+            origin: None,
         };
 
         let _url = V1_API_ROUTES.pow.get_config;
@@ -0,0 +1,152 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Validation token introspection
+
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use super::verify_token::VerifyCaptchaResultPayload;
+use crate::errors::*;
+use crate::AppData;
+use crate::V1_API_ROUTES;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TokenIntrospection {
+    /// unix timestamp of issuance
+    pub issued: i64,
+    /// seconds until the token expires
+    pub ttl: u64,
+    /// whether the token has been verified
+    pub consumed: bool,
+    /// whether the token was issued for a completed fallback challenge,
+    /// without PoW
+    #[serde(default)]
+    pub fallback: bool,
+}
+
+/// route handler that reports the state of a validation token without
+/// consuming it
+#[my_codegen::post(path = "V1_API_ROUTES.pow.introspect()")]
+pub async fn introspect(
+    payload: web::Json<VerifyCaptchaResultPayload>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let secret = data.db.get_secret_from_captcha(&payload.key).await?;
+    if secret.secret != payload.secret {
+        return Err(ServiceError::WrongPassword);
+    }
+
+    match data.tokens.get(&payload.token) {
+        Some(info) if info.key == payload.key => {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            let resp = TokenIntrospection {
+                issued: info.issued,
+                ttl: info.remaining_ttl(now),
+                consumed: info.consumed.is_some(),
+                fallback: info.fallback,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        _ => Err(ServiceError::ValidationTokenNotFound),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::pow::verify_token::CaptchaValidateResp;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn introspect_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        introspect_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn introspect_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        introspect_works(data).await;
+    }
+
+    pub async fn introspect_works(data: ArcData) {
+        const NAME: &str = "introspectuser";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "introspectuser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+        let secret = data.db.get_secret(NAME).await.unwrap().secret;
+
+        let token = get_validation_token(data, &token_key.key).await;
+
+        let mut payload = VerifyCaptchaResultPayload {
+            token: token.clone(),
+            key: token_key.key.clone(),
+            secret: NAME.into(),
+            ip: None,
+        };
+
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.pow.introspect,
+            &payload,
+            ServiceError::WrongPassword,
+        )
+        .await;
+
+        payload.secret = secret;
+        payload.token = "nonexistent".into();
+        bad_post_req_test_no_auth(
+            data,
+            V1_API_ROUTES.pow.introspect,
+            &payload,
+            ServiceError::ValidationTokenNotFound,
+        )
+        .await;
+        payload.token = token;
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.introspect).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: TokenIntrospection = test::read_body_json(resp).await;
+        assert!(!resp.consumed);
+        assert!(!resp.fallback);
+        assert!(resp.ttl > 0);
+
+        // introspection doesn't consume the token
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(resp.valid);
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.introspect).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: TokenIntrospection = test::read_body_json(resp).await;
+        assert!(resp.consumed);
+    }
+}
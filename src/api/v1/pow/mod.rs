@@ -5,7 +5,13 @@
 
 use actix_web::web;
 
+pub mod fallback;
+pub mod form_session;
 pub mod get_config;
+pub mod introspect;
+pub mod probe;
+pub mod protocol;
+pub mod stream;
 pub mod verify_pow;
 pub mod verify_token;
 
@@ -14,7 +20,7 @@ pub use super::mcaptcha::get::I32Levels;
 pub fn services(cfg: &mut web::ServiceConfig) {
     let cors = actix_cors::Cors::default()
         .allow_any_origin()
-        .allowed_methods(vec!["POST", "GET"])
+        .allowed_methods(vec!["POST", "GET", "OPTIONS"])
         .allow_any_header()
         .max_age(3600)
         .send_wildcard();
@@ -25,7 +31,15 @@ pub fn services(cfg: &mut web::ServiceConfig) {
             .wrap(cors)
             .service(verify_pow::verify_pow)
             .service(get_config::get_config)
-            .service(verify_token::validate_captcha_token),
+            .service(stream::stream)
+            .service(introspect::introspect)
+            .service(probe::probe)
+            .service(probe::probe_options)
+            .service(verify_token::validate_captcha_token)
+            .service(form_session::create_form_session)
+            .service(form_session::check_form_session)
+            .service(fallback::start_fallback)
+            .service(fallback::complete_fallback),
     );
 }
 
@@ -34,6 +48,15 @@ pub mod routes {
         pub get_config: &'static str,
         pub verify_pow: &'static str,
         pub validate_captcha_token: &'static str,
+        pub create_form_session: &'static str,
+        pub check_form_session: &'static str,
+        pub stream: &'static str,
+        pub introspect: &'static str,
+        pub probe: &'static str,
+        /// start a fallback challenge
+        pub start_fallback: &'static str,
+        /// complete a fallback challenge
+        pub complete_fallback: &'static str,
         pub scope: &'static str,
     }
 
@@ -60,6 +83,13 @@ pub mod routes {
                 get_config: "/api/v1/pow/config",
                 verify_pow: "/api/v1/pow/verify",
                 validate_captcha_token: "/api/v1/pow/siteverify",
+                create_form_session: "/api/v1/pow/siteverify/session",
+                check_form_session: "/api/v1/pow/siteverify/session/check",
+                stream: "/api/v1/pow/stream",
+                introspect: "/api/v1/pow/introspect",
+                probe: "/api/v1/pow/probe/{key}",
+                start_fallback: "/api/v1/pow/fallback/start",
+                complete_fallback: "/api/v1/pow/fallback/complete",
                 scope,
             }
         }
@@ -67,6 +97,17 @@ pub mod routes {
         rm_scope!(get_config);
         rm_scope!(verify_pow);
         rm_scope!(validate_captcha_token);
+        rm_scope!(create_form_session);
+        rm_scope!(check_form_session);
+        rm_scope!(stream);
+        rm_scope!(introspect);
+        rm_scope!(probe);
+        rm_scope!(start_fallback);
+        rm_scope!(complete_fallback);
+
+        pub fn get_probe(&self, key: &str) -> String {
+            self.probe.replace("{key}", key)
+        }
     }
 }
 
@@ -80,5 +121,12 @@ mod tests {
         assert_eq!(pow.get_config(), "/config");
         assert_eq!(pow.verify_pow(), "/verify");
         assert_eq!(pow.validate_captcha_token(), "/siteverify");
+        assert_eq!(pow.create_form_session(), "/siteverify/session");
+        assert_eq!(pow.check_form_session(), "/siteverify/session/check");
+        assert_eq!(pow.stream(), "/stream");
+        assert_eq!(pow.introspect(), "/introspect");
+        assert_eq!(pow.probe(), "/probe/{key}");
+        assert_eq!(pow.start_fallback(), "/fallback/start");
+        assert_eq!(pow.complete_fallback(), "/fallback/complete");
     }
 }
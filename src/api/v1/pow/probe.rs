@@ -0,0 +1,125 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Sitekey pre-flight probe
+//!
+//! Lets the widget find out why it can't fetch a challenge: the sitekey may
+//! not exist, or it may not be allowed on the embedding page's origin.
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+use crate::AppData;
+use crate::V1_API_ROUTES;
+
+/// allowed origins of sitekeys that accept any origin
+pub const ANY_ORIGIN: &str = "*";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SitekeyProbe {
+    pub exists: bool,
+    /// domains that the sitekey can be embedded on, along with their
+    /// subdomains; [ANY_ORIGIN] when the sitekey accepts any origin. Empty
+    /// when the sitekey doesn't exist or needs allowed domains to be set
+    pub allowed_origins: Vec<String>,
+}
+
+/// report whether a sitekey exists and where it can be embedded
+#[my_codegen::get(path = "V1_API_ROUTES.pow.probe()")]
+pub async fn probe(
+    path: web::Path<String>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let resp = runner::probe(&data, &path).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+/// same as [probe], for clients that probe with `OPTIONS`. CORS pre-flight
+/// requests are answered by the CORS middleware before reaching this handler
+#[my_codegen::options(path = "V1_API_ROUTES.pow.probe()")]
+pub async fn probe_options(
+    path: web::Path<String>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let resp = runner::probe(&data, &path).await?;
+    Ok(HttpResponse::Ok().json(resp))
+}
+
+pub mod runner {
+    use super::*;
+
+    pub async fn probe(data: &AppData, key: &str) -> ServiceResult<SitekeyProbe> {
+        if !data.db.captcha_exists(None, key).await? {
+            return Ok(SitekeyProbe::default());
+        }
+        Ok(SitekeyProbe {
+            exists: true,
+            allowed_origins: crate::domains::allowed_origins(data, key).await?,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::{header, Method, StatusCode};
+    use actix_web::test;
+
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn probe_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        probe_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn probe_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        probe_works(data).await;
+    }
+
+    pub async fn probe_works(data: ArcData) {
+        const NAME: &str = "probeuser";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "probeuser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+
+        for method in [Method::GET, Method::OPTIONS] {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(&V1_API_ROUTES.pow.get_probe(&token_key.key))
+                    .insert_header((header::ORIGIN, "https://example.com"))
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK, "{method}");
+            let resp: SitekeyProbe = test::read_body_json(resp).await;
+            assert!(resp.exists);
+            assert_eq!(resp.allowed_origins, vec![ANY_ORIGIN.to_string()]);
+
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::default()
+                    .method(method.clone())
+                    .uri(&V1_API_ROUTES.pow.get_probe("nonexistent"))
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK, "{method}");
+            let resp: SitekeyProbe = test::read_body_json(resp).await;
+            assert_eq!(resp, SitekeyProbe::default());
+        }
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Versioned PoW protocol
+//!
+//! Stable JSON contract for widget implementations: the bundled widget,
+//! native mobile SDKs and other custom frontends. Messages of a released
+//! version never change; incompatible changes go in a new version module that
+//! is added to [SUPPORTED_VERSIONS].
+//!
+//! # Negotiation
+//!
+//! Clients send the highest protocol version they implement in the `version`
+//! field of the config request. The server answers with the highest version
+//! supported by both sides, in the `version` field of the response, and the
+//! client must use the messages of that version for the rest of the challenge.
+//! Clients that only implement versions older than the oldest supported
+//! version are rejected with `400 Unsupported protocol version`. Requests without
+//! `version` receive the legacy, unversioned response.
+//!
+//! # Version 1
+//!
+//! 1. `POST /api/v1/pow/config` with [v1::ConfigRequest]; answered with
+//!    [v1::Config]
+//! 2. `POST /api/v1/pow/verify` with [v1::Work]; answered with [v1::Token]
+//! 3. the token is sent to the sitekey owner's backend, which validates it
+//!    with `POST /api/v1/pow/siteverify`
+//!
+//! Failed requests are answered with a non-2xx status and [v1::Error].
+use crate::errors::*;
+
+/// latest protocol version
+pub const PROTOCOL_VERSION: u32 = v1::VERSION;
+/// protocol versions supported by the server, in ascending order
+pub const SUPPORTED_VERSIONS: [u32; 1] = [v1::VERSION];
+
+/// pick the highest protocol version supported by both the client and the server
+pub fn negotiate(client: u32) -> ServiceResult<u32> {
+    SUPPORTED_VERSIONS
+        .iter()
+        .rev()
+        .find(|v| **v <= client)
+        .copied()
+        .ok_or(ServiceError::UnsupportedProtocolVersion)
+}
+
+pub mod v1 {
+    //! Version 1 of the PoW protocol
+    use serde::{Deserialize, Serialize};
+
+    use crate::api::v1::pow::get_config::ApiPoWConfig;
+    use crate::api::v1::pow::verify_pow::{ApiWork, ValidationToken};
+    use crate::errors::ErrorToResponse;
+
+    /// protocol version
+    pub const VERSION: u32 = 1;
+
+    #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+    /// request PoW configuration of a sitekey
+    pub struct ConfigRequest {
+        /// sitekey
+        pub key: String,
+        /// highest protocol version supported by the client
+        pub version: u32,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+    /// PoW challenge
+    pub struct Config {
+        /// negotiated protocol version
+        pub version: u32,
+        /// challenge string
+        pub string: String,
+        /// difficulty factor that the solution must meet
+        pub difficulty_factor: u32,
+        /// salt used in hashing
+        pub salt: String,
+        /// highest nonce recorded for this difficulty factor; useful for progress
+        /// estimation
+        pub max_recorded_nonce: u32,
+    }
+
+    impl From<ApiPoWConfig> for Config {
+        fn from(c: ApiPoWConfig) -> Self {
+            Self {
+                version: VERSION,
+                string: c.string,
+                difficulty_factor: c.difficulty_factor,
+                salt: c.salt,
+                max_recorded_nonce: c.max_recorded_nonce,
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+    /// solution to a PoW challenge
+    pub struct Work {
+        /// sitekey
+        pub key: String,
+        /// challenge string from [Config]
+        pub string: String,
+        /// hash of the solution
+        pub result: String,
+        /// nonce that solves the challenge
+        pub nonce: u64,
+        /// time taken to solve the challenge, in milliseconds
+        pub time: Option<u32>,
+        /// kind of worker that solved the challenge, e.g. "wasm"
+        pub worker_type: Option<String>,
+    }
+
+    impl From<Work> for ApiWork {
+        fn from(w: Work) -> Self {
+            Self {
+                key: w.key,
+                string: w.string,
+                result: w.result,
+                nonce: w.nonce,
+                time: w.time,
+                worker_type: w.worker_type,
+                correlation_id: None,
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+    /// validation token issued for a valid solution
+    pub struct Token {
+        pub token: String,
+    }
+
+    impl From<ValidationToken> for Token {
+        fn from(t: ValidationToken) -> Self {
+            Self { token: t.token }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+    /// error response
+    pub struct Error {
+        /// human readable error message
+        pub error: String,
+    }
+
+    impl From<ErrorToResponse> for Error {
+        fn from(e: ErrorToResponse) -> Self {
+            Self { error: e.error }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::pow::get_config::GetConfigPayload;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn negotiation_works() {
+        assert_eq!(
+            negotiate(0).err(),
+            Some(ServiceError::UnsupportedProtocolVersion)
+        );
+        assert_eq!(negotiate(1).unwrap(), 1);
+        assert_eq!(negotiate(PROTOCOL_VERSION + 1).unwrap(), PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn v1_contract_works() {
+        let config = v1::Config {
+            version: 1,
+            string: "a".into(),
+            difficulty_factor: 2,
+            salt: "b".into(),
+            max_recorded_nonce: 3,
+        };
+        assert_eq!(
+            serde_json::to_value(config).unwrap(),
+            serde_json::json!({
+                "version": 1,
+                "string": "a",
+                "difficulty_factor": 2,
+                "salt": "b",
+                "max_recorded_nonce": 3
+            })
+        );
+
+        let work: v1::Work = serde_json::from_value(serde_json::json!({
+            "key": "k",
+            "string": "a",
+            "result": "r",
+            "nonce": 4,
+            "time": null,
+            "worker_type": null
+        }))
+        .unwrap();
+        assert_eq!(work.nonce, 4);
+    }
+
+    #[actix_rt::test]
+    async fn protocol_negotiation_works_pg() {
+        let data = pg::get_data().await;
+        protocol_negotiation_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn protocol_negotiation_works_maria() {
+        let data = maria::get_data().await;
+        protocol_negotiation_works(data).await;
+    }
+
+    async fn protocol_negotiation_works(data: ArcData) {
+        const NAME: &str = "protocolnegotiationuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "protocolnegotiationuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+
+        let mut payload = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: Some(0),
+            origin: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let err: v1::Error = test::read_body_json(resp).await;
+        assert_eq!(
+            err.error,
+            format!("{}", ServiceError::UnsupportedProtocolVersion)
+        );
+
+        payload.version = Some(PROTOCOL_VERSION + 1);
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let config: v1::Config = test::read_body_json(resp).await;
+        assert_eq!(config.version, PROTOCOL_VERSION);
+        assert_eq!(config.difficulty_factor, L1.difficulty_factor);
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! WebSocket channel for PoW challenges
+//!
+//! Widgets that render several challenges (or retry often) can keep a single
+//! connection open instead of doing a config fetch and a verification
+//! round-trip per challenge. Every successful solve is answered with the
+//! validation token followed by a fresh config for the same sitekey.
+//!
+//! The server only sends configs in response to messages: difficulty changes,
+//! like those of bursts or overload, apply from the next config that is
+//! requested or that follows a solve.
+//!
+//! Messages are held to the PoW limits of `[server.limits]`: connections that
+//! send larger messages are closed, and messages that nest too deeply are
+//! answered with [ServiceError::JsonTooDeep]. Each message counts towards the
+//! rate limits of the PoW routes, like a request to them would, and is
+//! answered with [ServiceError::RateLimited] when it's over a limit.
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use super::get_config::{get_config_runner, ApiPoWConfig};
+use super::verify_pow::{verify_pow_runner, ApiWork};
+use crate::errors::*;
+use crate::rate_limit::RouteClass;
+use crate::AppData;
+use crate::V1_API_ROUTES;
+
+/// error code of messages that can't be parsed
+pub const INVALID_MESSAGE: &str = "invalid_message";
+
+/// Messages sent by the widget
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StreamRequest {
+    /// request PoW configuration for a sitekey. `origin` is the origin of the
+    /// page that embeds the widget
+    Config {
+        key: String,
+        #[serde(default)]
+        origin: Option<String>,
+    },
+    /// submit a solution
+    Solve(ApiWork),
+}
+
+/// Messages sent by the server
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StreamResponse {
+    Config(ApiPoWConfig),
+    Token { token: String },
+    Error(ErrorToResponse),
+}
+
+impl From<ServiceError> for StreamResponse {
+    fn from(e: ServiceError) -> Self {
+        Self::Error(ErrorToResponse::new(&e, None))
+    }
+}
+
+/// Process a single message; a solve is followed by a fresh config so that
+/// the next challenge doesn't need another request
+pub async fn handle_request(
+    data: &AppData,
+    req: StreamRequest,
+    ip: String,
+) -> Vec<StreamResponse> {
+    match req {
+        StreamRequest::Config { key, origin } => {
+            if let Err(e) = crate::domains::check(data, &key, origin.as_deref()).await {
+                return vec![e.into()];
+            }
+            match get_config_runner(data, &key).await {
+                Ok(config) => vec![StreamResponse::Config(config)],
+                Err(e) => vec![e.into()],
+            }
+        }
+        StreamRequest::Solve(work) => {
+            let key = work.key.clone();
+            let correlation_id = work.correlation_id.clone();
+            match verify_pow_runner(data, work, ip).await {
+                Ok(token) => {
+                    let mut resp = vec![StreamResponse::Token { token: token.token }];
+                    match get_config_runner(data, &key).await {
+                        Ok(config) => resp.push(StreamResponse::Config(config)),
+                        Err(e) => resp.push(e.into()),
+                    }
+                    resp
+                }
+                Err(e) => vec![StreamResponse::Error(ErrorToResponse::new(
+                    &e,
+                    correlation_id,
+                ))],
+            }
+        }
+    }
+}
+
+/// parse a message of the widget that nests at most `depth` levels deep
+fn parse_request(text: &str, depth: usize) -> Result<StreamRequest, ErrorToResponse> {
+    crate::body_limits::check_depth(text.as_bytes(), depth)
+        .map_err(|e| ErrorToResponse::new(&e, None))?;
+    serde_json::from_str(text).map_err(|e| ErrorToResponse {
+        error: e.to_string(),
+        code: INVALID_MESSAGE.into(),
+        correlation_id: None,
+        retry_after: None,
+        request_id: None,
+        fields: None,
+    })
+}
+
+/// count a message from `ip` towards the rate limits of the PoW routes
+async fn check_rate_limit(data: &AppData, ip: &str) -> ServiceResult<()> {
+    let s = &data.settings.rate_limit;
+    if !s.enabled {
+        return Ok(());
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let class = Some(RouteClass::Pow);
+    match data
+        .rate_limits
+        .consume(s, class, ip, None, false, now)
+        .await
+    {
+        Some(quota) if quota.exceeded => Err(ServiceError::RateLimited(quota.reset)),
+        _ => Ok(()),
+    }
+}
+
+/// upgrade connection to WebSocket and serve PoW challenges over it
+#[my_codegen::get(path = "V1_API_ROUTES.pow.stream()")]
+pub async fn stream(
+    req: HttpRequest,
+    body: web::Payload,
+    data: AppData,
+) -> Result<HttpResponse, actix_web::Error> {
+    let ip = data.trusted_proxies.client_ip(&req);
+
+    let (response, mut session, msg_stream) = actix_ws::handle(&req, body)?;
+    let mut msg_stream = msg_stream.max_frame_size(data.settings.server.limits.pow);
+
+    actix_rt::spawn(async move {
+        while let Some(Ok(msg)) = msg_stream.next().await {
+            match msg {
+                Message::Ping(bytes) => {
+                    if session.pong(&bytes).await.is_err() {
+                        return;
+                    }
+                }
+                Message::Text(text) => {
+                    let depth = data.settings.server.limits.json_depth;
+                    let resp = match check_rate_limit(&data, &ip).await {
+                        Err(e) => vec![e.into()],
+                        Ok(_) => match parse_request(&text, depth) {
+                            Ok(req) => handle_request(&data, req, ip.clone()).await,
+                            Err(e) => vec![StreamResponse::Error(e)],
+                        },
+                    };
+                    for r in resp.iter() {
+                        let r = serde_json::to_string(r).unwrap();
+                        if session.text(r).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Message::Close(reason) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                _ => (),
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn pow_stream_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        pow_stream_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn pow_stream_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        pow_stream_works(data).await;
+    }
+
+    pub async fn pow_stream_works(data: ArcData) {
+        const NAME: &str = "powstreamuser";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "powstreamuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app_data = actix_web::web::Data::new(data.clone());
+
+        let req: StreamRequest = serde_json::from_str(&format!(
+            r#"{{"type": "config", "key": "{}"}}"#,
+            token_key.key
+        ))
+        .unwrap();
+        let mut resp = handle_request(&app_data, req, "127.0.1.1".into()).await;
+        assert_eq!(resp.len(), 1);
+        let config = match resp.pop().unwrap() {
+            StreamResponse::Config(config) => config,
+            r => panic!("unexpected response {r:?}"),
+        };
+        assert_eq!(config.difficulty_factor, L1.difficulty_factor);
+
+        let pow = mcaptcha_pow_sha256::ConfigBuilder::default()
+            .salt(config.salt)
+            .build()
+            .unwrap();
+        let work = pow
+            .prove_work(&config.string.clone(), config.difficulty_factor)
+            .unwrap();
+        let work = ApiWork {
+            string: config.string.clone(),
+            result: work.result,
+            nonce: work.nonce,
+            key: token_key.key.clone(),
+            time: None,
+            worker_type: None,
+            correlation_id: None,
+        };
+
+        let resp = handle_request(
+            &app_data,
+            StreamRequest::Solve(work.clone()),
+            "127.0.1.1".into(),
+        )
+        .await;
+        assert_eq!(resp.len(), 2);
+        assert!(matches!(resp[0], StreamResponse::Token { .. }));
+        match &resp[1] {
+            StreamResponse::Config(c) => assert_ne!(c.string, config.string),
+            r => panic!("unexpected response {r:?}"),
+        }
+
+        // replaying a solved challenge is rejected
+        let resp =
+            handle_request(&app_data, StreamRequest::Solve(work), "127.0.1.1".into())
+                .await;
+        assert_eq!(resp.len(), 1);
+        assert!(matches!(resp[0], StreamResponse::Error(_)));
+
+        // messages count towards the rate limits of the PoW routes
+        assert!(check_rate_limit(&app_data, "127.0.1.1").await.is_ok());
+        let mut settings = data.settings.clone();
+        // count in memory, so that runs don't share counters
+        settings.redis = None;
+        settings.rate_limit.enabled = true;
+        settings.rate_limit.pow.per_ip = 1;
+        let data = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = actix_web::web::Data::new(data);
+        assert!(check_rate_limit(&app_data, "127.0.1.1").await.is_ok());
+        assert!(matches!(
+            check_rate_limit(&app_data, "127.0.1.1").await,
+            Err(ServiceError::RateLimited(_))
+        ));
+    }
+}
@@ -5,15 +5,25 @@
 
 //! PoW Verification module
 
+use std::time::Instant;
+
 use actix_web::HttpRequest;
 use actix_web::{web, HttpResponse, Responder};
 use libmcaptcha::pow::Work;
 use serde::{Deserialize, Serialize};
 
+use crate::alerts;
+use crate::cluster;
 use crate::errors::*;
+use crate::fraud;
+use crate::memcached::BindToken;
+use crate::webhooks::WebhookEvent;
 use crate::AppData;
 use crate::V1_API_ROUTES;
 
+/// maximum length of worker types recorded in analytics, in characters
+const WORKER_TYPE_LEN: usize = 100;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 /// validation token that clients receive as proof for submiting
 /// valid PoW
@@ -29,6 +39,10 @@ pub struct ApiWork {
     pub key: String,
     pub time: Option<u32>,
     pub worker_type: Option<String>,
+    /// correlation ID of the challenge, as issued in
+    /// [ApiPoWConfig][super::get_config::ApiPoWConfig]
+    #[serde(default)]
+    pub correlation_id: Option<String>,
 }
 
 impl From<ApiWork> for Work {
@@ -52,35 +66,134 @@ pub async fn verify_pow(
     payload: web::Json<ApiWork>,
     data: AppData,
 ) -> ServiceResult<impl Responder> {
-    #[cfg(not(test))]
-    let ip = req.connection_info().peer_addr().unwrap().to_string();
-    // From actix-web docs:
-    //  Will only return None when called in unit tests unless TestRequest::peer_addr is used.
-    //
-    // ref: https://docs.rs/actix-web/latest/actix_web/struct.HttpRequest.html#method.peer_addr
-    #[cfg(test)]
-    let ip = "127.0.1.1".into();
+    let ip = data.trusted_proxies.client_ip(&req);
+
+    let correlation_id = payload.correlation_id.clone();
+    match verify_pow_runner(&data, payload.into_inner(), ip).await {
+        Ok(token) => Ok(HttpResponse::Ok().json(token)),
+        Err(e) => Ok(e.correlated_response(correlation_id)),
+    }
+}
 
+/// verify PoW, record solve statistics and issue a validation token
+#[tracing::instrument(
+    skip_all,
+    fields(key = %payload.key, correlation_id = ?payload.correlation_id)
+)]
+pub async fn verify_pow_runner(
+    data: &AppData,
+    payload: ApiWork,
+    ip: String,
+) -> ServiceResult<ValidationToken> {
     let key = payload.key.clone();
-    let payload = payload.into_inner();
     let worker_type = payload.worker_type.clone();
     let time = payload.time;
     let nonce = payload.nonce;
-    let (res, difficulty_factor) = data.captcha.verify_pow(payload.into(), ip).await?;
-    data.stats.record_solve(&data, &key).await?;
-    if let (Some(time), Some(worker_type)) = (time, worker_type) {
-        let analytics = db_core::CreatePerformanceAnalytics {
-            difficulty_factor,
-            time,
-            worker_type,
-        };
-        data.db.analysis_save(&key, &analytics).await?;
+    let correlation_id = payload.correlation_id.clone();
+    // fraud heuristics are skipped along with stats when overloaded
+    let fraud_thresholds = if data.load.shed() {
+        None
+    } else {
+        fraud::thresholds(data, &key).await?
+    };
+    let repeated = fraud_thresholds
+        .as_ref()
+        .map_or(false, |t| data.fraud.repeated(t, &key, &payload.result));
+    let inflight = data
+        .load
+        .try_verification(data.captcha.tuning().capacity())?;
+    // priority only matters when verifications have to wait for runners
+    let high_priority =
+        data.captcha.contended() && data.db.captcha_high_priority(&key).await?;
+    // instances of a cluster verify challenges issued by the others
+    let res = if cluster::shares_challenges(data) {
+        let _permit = data.captcha.schedule(high_priority).await;
+        cluster::verify_pow(data, payload.into(), &ip).await
+    } else {
+        data.captcha
+            .verify_pow(payload.into(), ip.clone(), high_priority)
+            .await
+            .map_err(ServiceError::from)
+    };
+    drop(inflight);
+    let (res, difficulty_factor) = match res {
+        Ok(val) => val,
+        Err(e) => {
+            if repeated {
+                fraud::report(data, &key, fraud::FraudSignal::RepeatedResult, None)
+                    .await;
+            }
+            if !data.load.shed() {
+                alerts::attempted(data, &key, true).await;
+            }
+            let id = correlation_id.as_deref().unwrap_or("-");
+            log::warn!("PoW verification failed for {key} [correlation_id: {id}]: {e}");
+            return Err(e);
+        }
+    };
+    // stats and analytics are the first to go when overloaded
+    let country = data.geoip.as_ref().and_then(|g| g.country(&ip));
+    if !data.load.shed() {
+        data.stats
+            .record_solve(data, &key, country.as_deref())
+            .await?;
+        alerts::attempted(data, &key, false).await;
+        if let (true, Some(time), Some(worker_type)) =
+            (data.settings.features.analytics, time, worker_type)
+        {
+            // analytics are best-effort: failing to record them doesn't fail
+            // the solve
+            let analytics = db_core::CreatePerformanceAnalytics {
+                difficulty_factor,
+                time,
+                // reported by clients, so it's truncated to fit
+                worker_type: worker_type.chars().take(WORKER_TYPE_LEN).collect(),
+            };
+            if let Err(e) = record_analytics(data, &key, &analytics).await {
+                log::error!("Unable to record analytics of {key}: {e}");
+            }
+        }
     }
+    let timer = Instant::now();
     data.db
         .update_max_nonce_for_level(&key, difficulty_factor, nonce as u32)
         .await?;
-    let payload = ValidationToken { token: res };
-    Ok(HttpResponse::Ok().json(payload))
+    let ttl = data.db.get_captcha_cooldown(&key).await?;
+    data.load.record_db_latency(timer.elapsed());
+    if !cluster::shares_challenges(data) {
+        let msg = BindToken {
+            token: res.clone(),
+            ip_hash: data.tokens.hash_ip(&ip),
+            duration: ttl as u64,
+        };
+        data.captcha.bind_token(msg).await?;
+    }
+    data.tokens.issue(&res, &key, ttl as u64, &ip, country);
+    data.webhooks
+        .enqueue(WebhookEvent::Solve, &key, Some(difficulty_factor));
+    if let Some(t) = fraud_thresholds {
+        let mut signals = fraud::solve_signals(&t, nonce, time, difficulty_factor);
+        if repeated {
+            signals.push(fraud::FraudSignal::RepeatedResult);
+        }
+        for signal in signals {
+            fraud::report(data, &key, signal, Some(difficulty_factor)).await;
+        }
+    }
+    Ok(ValidationToken { token: res })
+}
+
+/// record analytics of a solve. Records beyond the quota are dropped and demo
+/// sitekeys are kept out of analytics
+async fn record_analytics(
+    data: &AppData,
+    key: &str,
+    analytics: &db_core::CreatePerformanceAnalytics,
+) -> ServiceResult<()> {
+    if data.analytics_quotas.records(data, key).await? {
+        data.db.analysis_save(key, analytics).await?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -132,6 +245,8 @@ pub mod tests {
 
         let get_config_payload = GetConfigPayload {
             key: token_key.key.clone(),
+            version: None,
+            origin: None,
         };
 
         // update and check changes
@@ -160,6 +275,7 @@ pub mod tests {
             key: token_key.key.clone(),
             time: Some(100),
             worker_type: Some("wasm".into()),
+            correlation_id: None,
         };
 
         let pow_verify_resp = test::call_service(
@@ -181,6 +297,109 @@ pub mod tests {
         assert_eq!(a.worker_type, work.worker_type.unwrap());
     }
 
+    #[actix_rt::test]
+    async fn served_difficulty_is_enforced_pg() {
+        let data = crate::tests::pg::get_data().await;
+        served_difficulty_is_enforced(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn served_difficulty_is_enforced_maria() {
+        let data = crate::tests::maria::get_data().await;
+        served_difficulty_is_enforced(data).await;
+    }
+
+    /// challenges served above the difficulty of the defense, here during a
+    /// burst, can't be solved at the difficulty of the defense
+    pub async fn served_difficulty_is_enforced(data: ArcData) {
+        use crate::api::v1::pow::get_config::ApiPoWConfig;
+
+        const NAME: &str = "powserveddifficultyusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "powserveddifficultyusr@a.com";
+        const DIFFICULTY: u32 = 5000;
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+
+        let now = sqlx::types::time::OffsetDateTime::now_utc().unix_timestamp();
+        data.bursts.set(
+            &token_key.key,
+            vec![db_core::Burst {
+                id: 0,
+                key: token_key.key.clone(),
+                starts: now - 60,
+                ends: now + 600,
+                difficulty_factor: DIFFICULTY,
+            }],
+        );
+        let get_config_payload = GetConfigPayload {
+            key: token_key.key.clone(),
+            version: None,
+            origin: None,
+        };
+        let pow = mcaptcha_pow_sha256::ConfigBuilder::default()
+            .salt(data.settings.captcha.salt.clone())
+            .build()
+            .unwrap();
+        let submit = |config: &ApiPoWConfig, difficulty_factor| {
+            let work = pow.prove_work(&config.string, difficulty_factor).unwrap();
+            let sufficient =
+                pow.is_sufficient_difficulty(&work, config.difficulty_factor);
+            let work = ApiWork {
+                string: config.string.clone(),
+                result: work.result,
+                nonce: work.nonce,
+                key: token_key.key.clone(),
+                time: None,
+                worker_type: None,
+                correlation_id: None,
+            };
+            (
+                post_request!(&work, V1_API_ROUTES.pow.verify_pow).to_request(),
+                sufficient,
+            )
+        };
+
+        // proofs at the difficulty of the defense are rejected
+        loop {
+            let resp = test::call_service(
+                &app,
+                post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                    .to_request(),
+            )
+            .await;
+            let config: ApiPoWConfig = test::read_body_json(resp).await;
+            assert_eq!(config.difficulty_factor, DIFFICULTY);
+            let (req, sufficient) = submit(&config, L1.difficulty_factor);
+            // proofs can meet a higher difficulty than they were mined for
+            if sufficient {
+                continue;
+            }
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let err: ErrorToResponse = test::read_body_json(resp).await;
+            assert_eq!(err.code, "insufficient_difficulty");
+            break;
+        }
+
+        // proofs at the served difficulty are accepted
+        let resp = test::call_service(
+            &app,
+            post_request!(&get_config_payload, V1_API_ROUTES.pow.get_config)
+                .to_request(),
+        )
+        .await;
+        let config: ApiPoWConfig = test::read_body_json(resp).await;
+        let (req, _) = submit(&config, config.difficulty_factor);
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        data.bursts.set(&token_key.key, vec![]);
+    }
+
     pub async fn verify_pow_works(data: ArcData) {
         const NAME: &str = "powverifyusr";
         const PASSWORD: &str = "testingpas";
@@ -195,6 +414,8 @@ pub mod tests {
 
         let get_config_payload = GetConfigPayload {
             key: token_key.key.clone(),
+            version: None,
+            origin: None,
         };
 
         // update and check changes
@@ -244,6 +465,27 @@ pub mod tests {
         assert_eq!(string_not_found.status(), StatusCode::BAD_REQUEST);
         let err: ErrorToResponse = test::read_body_json(string_not_found).await;
         assert_eq!(err.error, "Challenge: not found");
+        assert_eq!(err.code, "challenge_not_found");
+        assert_eq!(err.correlation_id, None);
+
+        // correlation ID of the challenge is echoed in verification errors
+        let work = ApiWork {
+            string: work.string,
+            result: work.result,
+            nonce: work.nonce,
+            key: work.key,
+            time: None,
+            worker_type: None,
+            correlation_id: Some("correlationid".into()),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&work, V1_API_ROUTES.pow.verify_pow).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.correlation_id, work.correlation_id);
 
         // let pow_config_resp = test::call_service(
         //     &app,
@@ -9,7 +9,9 @@ use actix_web::{web, HttpResponse, Responder};
 use libmcaptcha::cache::messages::VerifyCaptchaResult;
 use serde::{Deserialize, Serialize};
 
+use crate::cluster;
 use crate::errors::*;
+use crate::webhooks::WebhookEvent;
 use crate::AppData;
 use crate::V1_API_ROUTES;
 
@@ -23,6 +25,10 @@ pub struct VerifyCaptchaResultPayload {
     pub secret: String,
     pub key: String,
     pub token: String,
+    /// IP address of the client that presented the token to the protected
+    /// backend. When set, tokens solved from a different address are rejected
+    #[serde(default)]
+    pub ip: Option<String>,
 }
 
 impl From<VerifyCaptchaResultPayload> for VerifyCaptchaResult {
@@ -38,23 +44,70 @@ impl From<VerifyCaptchaResultPayload> for VerifyCaptchaResult {
 
 /// route handler that validates a PoW solution token
 #[my_codegen::post(path = "V1_API_ROUTES.pow.validate_captcha_token()")]
+#[tracing::instrument(skip_all, fields(key = %payload.key))]
 pub async fn validate_captcha_token(
     payload: web::Json<VerifyCaptchaResultPayload>,
     data: AppData,
 ) -> ServiceResult<impl Responder> {
-    let secret = data.db.get_secret_from_captcha(&payload.key).await?;
-    if secret.secret != payload.secret {
-        return Err(ServiceError::WrongPassword);
-    }
-    let payload: VerifyCaptchaResult = payload.into_inner().into();
-    let key = payload.key.clone();
-    let res = data.captcha.validate_verification_tokens(payload).await?;
-    let resp = CaptchaValidateResp { valid: res };
-    data.stats.record_confirm(&data, &key).await?;
-    //println!("{:?}", &payload);
+    let valid = runners::validate(&data, payload.into_inner()).await?;
+    let resp = CaptchaValidateResp { valid };
     Ok(HttpResponse::Ok().json(resp))
 }
 
+pub mod runners {
+    use super::*;
+
+    /// validate and consume a PoW solution token
+    pub async fn validate(
+        data: &AppData,
+        payload: VerifyCaptchaResultPayload,
+    ) -> ServiceResult<bool> {
+        let secret = data.db.get_secret_from_captcha(&payload.key).await?;
+        if secret.secret != payload.secret {
+            return Err(ServiceError::WrongPassword);
+        }
+        if let Some(ip) = &payload.ip {
+            if !crate::tokens::ip_matches(data, &payload.key, &payload.token, ip).await?
+            {
+                return Ok(false);
+            }
+        }
+        let payload: VerifyCaptchaResult = payload.into();
+        let key = payload.key.clone();
+        let token = payload.token.clone();
+        let strict = data.db.captcha_strict_tokens(&key).await?;
+        let mut res = if cluster::shares_challenges(data) {
+            cluster::validate(data, &key, &token).await?
+        } else {
+            data.captcha.validate_verification_tokens(payload).await?
+        };
+        if strict {
+            // the cache may hand out the same token more than once when it is shared
+            // between instances, so the database is the source of truth in strict mode
+            let replayed = if res {
+                !data.db.consume_token(&key, &token).await?
+            } else {
+                data.db.token_is_consumed(&key, &token).await?
+            };
+            if replayed {
+                res = false;
+                data.db.record_token_replay(&key).await?;
+            }
+        }
+        if res {
+            data.tokens.consume(&token);
+            data.webhooks.enqueue(WebhookEvent::Confirm, &key, None);
+        }
+        if !data.load.shed() {
+            let country = data.tokens.get(&token).and_then(|t| t.country);
+            data.stats
+                .record_confirm(data, &key, country.as_deref())
+                .await?;
+        }
+        Ok(res)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use actix_web::http::StatusCode;
@@ -111,6 +164,8 @@ pub mod tests {
 
         let get_config_payload = GetConfigPayload {
             key: token_key.key.clone(),
+            version: None,
+            origin: None,
         };
 
         // update and check changes
@@ -150,6 +205,7 @@ pub mod tests {
             token: client_token.token.clone(),
             key: token_key.key.clone(),
             secret: NAME.to_string(),
+            ip: None,
         };
 
         // siteverify authentication failure
@@ -194,4 +250,157 @@ pub mod tests {
         let resp: CaptchaValidateResp = test::read_body_json(string_not_found).await;
         assert!(!resp.valid);
     }
+
+    #[actix_rt::test]
+    async fn strict_tokens_work_pg() {
+        let data = crate::tests::pg::get_data().await;
+        strict_tokens_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn strict_tokens_work_maria() {
+        let data = crate::tests::maria::get_data().await;
+        strict_tokens_work(data).await;
+    }
+
+    pub async fn strict_tokens_work(data: ArcData) {
+        use crate::api::v1::mcaptcha::update::UpdateStrictTokens;
+
+        const NAME: &str = "stricttokenuser";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "stricttokenuser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+        let cookies = get_cookie!(signin_resp);
+        let secret = data.db.get_secret(NAME).await.unwrap().secret;
+
+        let strict = UpdateStrictTokens {
+            key: token_key.key.clone(),
+            strict: true,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&strict, V1_API_ROUTES.captcha.update_strict)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.captcha_strict_tokens(&token_key.key).await.unwrap());
+
+        let payload = VerifyCaptchaResultPayload {
+            token: get_validation_token(data, &token_key.key).await,
+            key: token_key.key.clone(),
+            secret,
+            ip: None,
+        };
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(resp.valid);
+        assert!(data
+            .db
+            .fetch_token_replays(NAME, &token_key.key)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // replay is rejected and recorded
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(!resp.valid);
+        assert_eq!(
+            data.db
+                .fetch_token_replays(NAME, &token_key.key)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[actix_rt::test]
+    async fn ip_binding_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        ip_binding_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn ip_binding_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        ip_binding_works(data).await;
+    }
+
+    pub async fn ip_binding_works(data: ArcData) {
+        const NAME: &str = "ipbindinguser";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "ipbindinguser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+        let secret = data.db.get_secret(NAME).await.unwrap().secret;
+
+        // PoW is solved from 127.0.1.1 in tests
+        let mut payload = VerifyCaptchaResultPayload {
+            token: get_validation_token(data, &token_key.key).await,
+            key: token_key.key.clone(),
+            secret,
+            ip: Some("192.0.2.1".into()),
+        };
+
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(!resp.valid);
+
+        // rejected tokens aren't burnt
+        payload.ip = Some("127.0.1.1".into());
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(resp.valid);
+
+        // tokens whose binding is unknown don't match any address
+        payload.token = "nonexistent".into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, V1_API_ROUTES.pow.validate_captcha_token)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp: CaptchaValidateResp = test::read_body_json(resp).await;
+        assert!(!resp.valid);
+    }
 }
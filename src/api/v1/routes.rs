@@ -6,25 +6,37 @@
 use actix_auth_middleware::GetLoginRoute;
 
 use super::account::routes::Account;
+use super::admin::routes::Admin;
 use super::auth::routes::Auth;
+use super::invites::routes::Invites;
 use super::mcaptcha::routes::Captcha;
 use super::meta::routes::Meta;
+use super::monitoring::routes::Monitoring;
 use super::notifications::routes::Notifications;
+use super::oidc::routes::Oidc;
+use super::orgs::routes::Orgs;
 use super::pow::routes::PoW;
 use super::stats::routes::Stats;
 use super::survey::routes::Survey;
+use super::trial::routes::Trial;
 
 pub const ROUTES: Routes = Routes::new();
 
 pub struct Routes {
     pub auth: Auth,
     pub account: Account,
+    pub admin: Admin,
     pub captcha: Captcha,
+    pub invites: Invites,
     pub meta: Meta,
+    pub monitoring: Monitoring,
     pub pow: PoW,
     pub survey: Survey,
     pub notifications: Notifications,
+    pub oidc: Oidc,
+    pub orgs: Orgs,
     pub stats: Stats,
+    pub trial: Trial,
 }
 
 impl Routes {
@@ -32,12 +44,18 @@ impl Routes {
         Routes {
             auth: Auth::new(),
             account: Account::new(),
+            admin: Admin::new(),
             captcha: Captcha::new(),
+            invites: Invites::new(),
             meta: Meta::new(),
+            monitoring: Monitoring::new(),
             pow: PoW::new(),
             notifications: Notifications::new(),
+            oidc: Oidc::new(),
+            orgs: Orgs::new(),
             survey: Survey::new(),
             stats: Stats::new(),
+            trial: Trial::new(),
         }
     }
 }
@@ -14,21 +14,27 @@
  * You should have received a copy of the GNU Affero General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
+use actix_web::http::header;
 use actix_web::web::ServiceConfig;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 
+use super::mcaptcha::stats::ExportFormat;
 use crate::errors::*;
+use crate::survey::UploadRecord;
 use crate::AppData;
 
 pub fn services(cfg: &mut ServiceConfig) {
     cfg.service(download);
+    cfg.service(benchmarks);
     cfg.service(secret);
 }
 
 pub mod routes {
     pub struct Survey {
         pub download: &'static str,
+        pub benchmarks: &'static str,
         pub secret: &'static str,
     }
 
@@ -36,6 +42,7 @@ pub mod routes {
         pub const fn new() -> Self {
             Self {
                 download: "/api/v1/survey/takeout/{survey_id}/get",
+                benchmarks: "/api/v1/survey/benchmarks/{psuedo_id}",
                 secret: "/api/v1/survey/secret",
             }
         }
@@ -47,6 +54,10 @@ pub mod routes {
                 page
             )
         }
+
+        pub fn get_benchmarks_route(&self, psuedo_id: &str) -> String {
+            self.benchmarks.replace("{psuedo_id}", psuedo_id)
+        }
     }
 }
 
@@ -62,6 +73,9 @@ async fn download(
     page: web::Query<Page>,
     psuedo_id: web::Path<uuid::Uuid>,
 ) -> ServiceResult<impl Responder> {
+    if !data.settings.features.analytics || !data.settings.features.survey {
+        return Err(ServiceError::FeatureDisabled);
+    }
     const LIMIT: usize = 50;
     let offset = LIMIT as isize * ((page.page as isize) - 1);
     let offset = if offset < 0 { 0 } else { offset };
@@ -77,6 +91,89 @@ async fn download(
     Ok(HttpResponse::Ok().json(data))
 }
 
+/// benchmarks served on a page by [benchmarks]
+pub const BENCHMARKS_PAGE: usize = 100;
+
+fn first_page() -> usize {
+    1
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct BenchmarksQuery {
+    /// page of [BENCHMARKS_PAGE] records, starting at 1
+    #[serde(default = "first_page")]
+    pub page: usize,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+/// published benchmarks of a sitekey, by its psuedo ID, as JSON or CSV. Full
+/// pages link to the next one in the `Link` header
+#[my_codegen::get(path = "crate::V1_API_ROUTES.survey.benchmarks")]
+async fn benchmarks(
+    req: HttpRequest,
+    data: AppData,
+    query: web::Query<BenchmarksQuery>,
+    psuedo_id: web::Path<uuid::Uuid>,
+) -> ServiceResult<impl Responder> {
+    if !data.settings.features.analytics {
+        return Err(ServiceError::FeatureDisabled);
+    }
+    let ip = data.trusted_proxies.client_ip(&req);
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if !data.benchmark_downloads.allow(&ip, now) {
+        return Err(ServiceError::TooManyDownloads);
+    }
+
+    let psuedo_id = psuedo_id.into_inner().to_string();
+    let campaign_id = data
+        .db
+        .analytics_get_capmaign_id_from_psuedo_id(&psuedo_id)
+        .await?;
+    let offset = BENCHMARKS_PAGE * query.page.saturating_sub(1);
+    let anonymization = data
+        .settings
+        .survey
+        .as_ref()
+        .map(|survey| survey.anonymization.clone())
+        .unwrap_or_default();
+    let mut records: Vec<UploadRecord> = Vec::new();
+    if anonymization.min_records == 0
+        || anonymization.allows(data.db.analytics_count(&campaign_id).await?)
+    {
+        records = data
+            .db
+            .analytics_fetch(&campaign_id, BENCHMARKS_PAGE, offset)
+            .await?
+            .into_iter()
+            .map(|a| a.into())
+            .collect();
+        anonymization.apply(&mut records);
+    }
+
+    let mut resp = HttpResponse::Ok();
+    if records.len() == BENCHMARKS_PAGE {
+        let format = match query.format {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        };
+        let next = format!(
+            "<{}?page={}&format={format}>; rel=\"next\"",
+            crate::V1_API_ROUTES.survey.get_benchmarks_route(&psuedo_id),
+            query.page.max(1) + 1
+        );
+        resp.insert_header((header::LINK, next));
+    }
+    match query.format {
+        ExportFormat::Json => Ok(resp.json(records)),
+        ExportFormat::Csv => {
+            let mut body = UploadRecord::CSV_HEADER.to_owned();
+            records.iter().for_each(|r| body.push_str(&r.csv()));
+            Ok(resp.content_type("text/csv; charset=utf-8").body(body))
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct SurveySecretUpload {
     secret: String,
@@ -89,9 +186,13 @@ async fn secret(
     data: AppData,
     payload: web::Json<SurveySecretUpload>,
 ) -> ServiceResult<impl Responder> {
+    if !data.settings.features.survey {
+        return Err(ServiceError::FeatureDisabled);
+    }
     match data.survey_secrets.get(&payload.auth_token) {
         Some(survey_instance_url) => {
             let payload = payload.into_inner();
+            data.survey_secrets.rm_pending(&survey_instance_url);
             data.survey_secrets.set(survey_instance_url, payload.secret);
             data.survey_secrets.rm(&payload.auth_token);
             Ok(HttpResponse::Ok())
@@ -123,6 +224,94 @@ pub mod tests {
         survey_works(data).await;
     }
 
+    #[actix_rt::test]
+    async fn benchmarks_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        benchmarks_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn benchmarks_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        benchmarks_works(data).await;
+    }
+
+    async fn benchmarks_works(data: ArcData) {
+        const NAME: &str = "benchmarksuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "benchmarksuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _signin_resp, key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app = get_app!(data).await;
+        let get = |uri: &str, ip: &str| {
+            test::TestRequest::get()
+                .uri(uri)
+                .peer_addr(format!("{ip}:1234").parse().unwrap())
+                .to_request()
+        };
+
+        // unpublished
+        let route = V1_API_ROUTES
+            .survey
+            .get_benchmarks_route(&uuid::Uuid::new_v4().to_string());
+        let resp = test::call_service(&app, get(&route, "10.0.0.1")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        data.db
+            .analytics_create_psuedo_id_if_not_exists(&key.key)
+            .await
+            .unwrap();
+        let psuedo_id = data
+            .db
+            .analytics_get_psuedo_id_from_capmaign_id(&key.key)
+            .await
+            .unwrap();
+        for _ in 0..(BENCHMARKS_PAGE + 20) {
+            let analytics = db_core::CreatePerformanceAnalytics {
+                time: 1200,
+                difficulty_factor: 500,
+                worker_type: "wasm".into(),
+            };
+            data.db.analysis_save(&key.key, &analytics).await.unwrap();
+        }
+        let route = V1_API_ROUTES.survey.get_benchmarks_route(&psuedo_id);
+
+        let resp = test::call_service(&app, get(&route, "10.0.0.1")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let next = resp.headers().get(header::LINK).unwrap().to_str().unwrap();
+        assert_eq!(next, format!("<{route}?page=2&format=json>; rel=\"next\""));
+        let records: Vec<UploadRecord> = test::read_body_json(resp).await;
+        assert_eq!(records.len(), BENCHMARKS_PAGE);
+        assert_eq!(records[0].time, 1200);
+
+        let resp = test::call_service(
+            &app,
+            get(&format!("{route}?page=2&format=csv"), "10.0.0.1"),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::LINK).is_none());
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.starts_with(UploadRecord::CSV_HEADER));
+        assert_eq!(body.lines().count(), 21);
+        assert!(body.contains("1200,500,\"wasm\""));
+
+        // downloads are rate limited per IP
+        for _ in 3..crate::survey::DOWNLOADS_PER_WINDOW {
+            test::call_service(&app, get(&route, "10.0.0.1")).await;
+        }
+        let resp = test::call_service(&app, get(&route, "10.0.0.1")).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = test::call_service(&app, get(&route, "10.0.0.2")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        delete_user(data, NAME).await;
+    }
+
     pub async fn survey_registration_works(data: ArcData) {
         let data = &data;
         let app = get_app!(data).await;
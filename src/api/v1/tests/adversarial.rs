@@ -0,0 +1,222 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Endpoints fed SQL meta-characters and overlong strings. Inputs must be
+//! rejected with client errors or treated as data, never surface as internal
+//! errors. Along with fixed inputs, the routes below are fed strings generated
+//! by `db_core::tests::sql_meta_strategy`. Database functions are covered by
+//! `db_core::tests::adversarial_inputs_work`
+use actix_web::http::StatusCode;
+use actix_web::test;
+use db_core::tests::{generate, sql_meta_strategy, GENERATED_INPUTS};
+
+use crate::api::v1::account::AccountCheckPayload;
+use crate::api::v1::auth::runners::{Login, Register, MAX_USERNAME_LEN};
+use crate::api::v1::mcaptcha::create::runner::MAX_DESCRIPTION_LEN;
+use crate::api::v1::mcaptcha::create::CreateCaptcha;
+use crate::api::v1::notifications::add::{AddNotificationRequest, MAX_HEADING_LEN};
+use crate::api::v1::pow::get_config::GetConfigPayload;
+use crate::api::v1::pow::verify_token::VerifyCaptchaResultPayload;
+use crate::errors::*;
+use crate::*;
+
+use crate::tests::*;
+
+const SQL_META_INPUTS: [&str; 6] = [
+    "'",
+    "\"",
+    "' OR '1'='1",
+    "'; DROP TABLE mcaptcha_users; --",
+    "%",
+    "_",
+];
+
+#[actix_rt::test]
+async fn adversarial_inputs_work_pg() {
+    let data = pg::get_data().await;
+    adversarial_inputs_work(data).await;
+}
+
+#[actix_rt::test]
+async fn adversarial_inputs_work_maria() {
+    let data = maria::get_data().await;
+    adversarial_inputs_work(data).await;
+}
+
+async fn adversarial_inputs_work(data: ArcData) {
+    const NAME: &str = "adversarialuser";
+    const PASSWORD: &str = "longpassworddomain";
+    const EMAIL: &str = "adversarialuser@a.com";
+    let data = &data;
+
+    delete_user(data, NAME).await;
+    let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+    let cookies = get_cookie!(signin_resp);
+    let app = get_app!(data).await;
+
+    let inputs: Vec<String> = SQL_META_INPUTS
+        .iter()
+        .map(|s| s.to_string())
+        .chain(generate(sql_meta_strategy(), GENERATED_INPUTS))
+        .collect();
+    for input in inputs.iter() {
+        let check = AccountCheckPayload { val: input.into() };
+        let resp = test::call_service(
+            &app,
+            post_request!(&check, V1_API_ROUTES.account.username_exists).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            post_request!(&check, V1_API_ROUTES.account.email_exists).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let login = Login {
+            login: input.into(),
+            password: PASSWORD.into(),
+            totp: None,
+            challenge: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&login, V1_API_ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert!(resp.status().is_client_error(), "{input}");
+
+        let config = GetConfigPayload {
+            key: input.into(),
+            version: None,
+            origin: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&config, V1_API_ROUTES.pow.get_config).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), ServiceError::TokenNotFound.status_code());
+
+        let token = VerifyCaptchaResultPayload {
+            secret: input.into(),
+            key: input.into(),
+            token: input.into(),
+            ip: None,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&token, V1_API_ROUTES.pow.validate_captcha_token).to_request(),
+        )
+        .await;
+        assert!(resp.status().is_client_error(), "{input}");
+
+        // stored as is
+        let captcha = CreateCaptcha {
+            description: input.into(),
+            ..get_level_data()
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&captcha, V1_API_ROUTES.captcha.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let captchas = data.db.get_all_user_captchas(NAME).await.unwrap();
+    for input in inputs.iter() {
+        assert!(captchas.iter().any(|c| c.description == *input));
+    }
+
+    // inputs that are too long to store
+    let long = "a".repeat(MAX_USERNAME_LEN + 1);
+    let register = Register {
+        username: long.clone(),
+        password: PASSWORD.into(),
+        confirm_password: PASSWORD.into(),
+        email: None,
+        invite: None,
+    };
+    bad_post_req_test_no_auth(
+        data,
+        V1_API_ROUTES.auth.register,
+        &register,
+        ServiceError::UsernameTooLong,
+    )
+    .await;
+    let register = Register {
+        username: "adversariallongemail".into(),
+        email: Some(format!("{long}@a.com")),
+        ..register
+    };
+    bad_post_req_test_no_auth(
+        data,
+        V1_API_ROUTES.auth.register,
+        &register,
+        ServiceError::NotAnEmail,
+    )
+    .await;
+
+    let captcha = CreateCaptcha {
+        description: "🦀".repeat(MAX_DESCRIPTION_LEN + 1),
+        ..get_level_data()
+    };
+    bad_post_req_test(
+        data,
+        NAME,
+        PASSWORD,
+        V1_API_ROUTES.captcha.create,
+        &captcha,
+        ServiceError::CaptchaDescriptionTooLong,
+    )
+    .await;
+
+    // every invalid field is reported
+    let captcha = CreateCaptcha {
+        levels: vec![L2, L1],
+        description: "'\0; --".into(),
+        ..get_level_data()
+    };
+    let mut fields = FieldErrors::default();
+    fields.add(
+        "levels[1].visitor_threshold",
+        "must be greater than that of the previous level",
+    );
+    fields.add(
+        "levels[1].difficulty_factor",
+        "can't be lower than that of the previous level",
+    );
+    fields.add("description", "can't contain control characters");
+    bad_post_req_test(
+        data,
+        NAME,
+        PASSWORD,
+        V1_API_ROUTES.captcha.create,
+        &captcha,
+        ServiceError::InvalidCaptcha(fields),
+    )
+    .await;
+
+    let notification = AddNotificationRequest {
+        to: NAME.into(),
+        heading: "'".repeat(MAX_HEADING_LEN + 1),
+        message: "message".into(),
+    };
+    bad_post_req_test(
+        data,
+        NAME,
+        PASSWORD,
+        V1_API_ROUTES.notifications.add,
+        &notification,
+        ServiceError::NotificationTooLong,
+    )
+    .await;
+
+    // nothing was dropped along the way
+    assert!(data.db.username_exists(NAME).await.unwrap());
+}
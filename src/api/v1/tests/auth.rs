@@ -42,6 +42,7 @@ pub async fn auth_works(data: ArcData) {
         password: PASSWORD.into(),
         confirm_password: PASSWORD.into(),
         email: None,
+        invite: None,
     };
     let resp =
         test::call_service(&app, post_request!(&msg, ROUTES.auth.register).to_request())
@@ -63,6 +64,7 @@ pub async fn auth_works(data: ArcData) {
         password: PASSWORD.into(),
         confirm_password: PASSWORD.into(),
         email: Some(EMAIL.into()),
+        invite: None,
     };
     bad_post_req_test(
         data,
@@ -90,6 +92,8 @@ pub async fn auth_works(data: ArcData) {
     let mut creds = Login {
         login: "nonexistantuser".into(),
         password: msg.password.clone(),
+        totp: None,
+        challenge: None,
     };
     bad_post_req_test(
         data,
@@ -168,6 +172,7 @@ pub async fn serverside_password_validation_works(data: ArcData) {
         password: PASSWORD.into(),
         confirm_password: NAME.into(),
         email: None,
+        invite: None,
     };
     let resp = test::call_service(
         &app,
@@ -178,3 +183,157 @@ pub async fn serverside_password_validation_works(data: ArcData) {
     let txt: ErrorToResponse = test::read_body_json(resp).await;
     assert_eq!(txt.error, format!("{}", ServiceError::PasswordsDontMatch));
 }
+
+#[actix_rt::test]
+async fn login_challenge_works_pg() {
+    let data = pg::get_data().await;
+    login_challenge_works(data).await;
+}
+
+#[actix_rt::test]
+async fn login_challenge_works_maria() {
+    let data = maria::get_data().await;
+    login_challenge_works(data).await;
+}
+
+pub async fn login_challenge_works(data: ArcData) {
+    use crate::login_protection::{LoginChallenge, LoginWork};
+
+    const NAME: &str = "challengeuser";
+    const PASSWORD: &str = "longpassword";
+    const EMAIL: &str = "challengeuser@a.com";
+
+    let mut settings = data.settings.clone();
+    settings.login_protection.max_account_failures = 2;
+    settings.login_protection.difficulty_factor = 500;
+    let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+
+    delete_user(data, NAME).await;
+    register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+    let app = get_app!(data).await;
+
+    let mut creds = Login {
+        login: NAME.into(),
+        password: EMAIL.into(),
+        totp: None,
+        challenge: None,
+    };
+    // failures with the username and the email address add up
+    for login in [EMAIL, NAME] {
+        creds.login = login.into();
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // further attempts require a challenge, even with the right password
+    creds.password = PASSWORD.into();
+    let resp =
+        test::call_service(&app, post_request!(&creds, ROUTES.auth.login).to_request())
+            .await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let err: ErrorToResponse = test::read_body_json(resp).await;
+    assert_eq!(
+        err.error,
+        format!("{}", ServiceError::LoginChallengeRequired)
+    );
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri(ROUTES.auth.login_challenge)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let challenge: LoginChallenge = test::read_body_json(resp).await;
+    assert_eq!(challenge.difficulty_factor, 500);
+
+    let pow = mcaptcha_pow_sha256::ConfigBuilder::default()
+        .salt(challenge.salt.clone())
+        .build()
+        .unwrap();
+    let proof = pow
+        .prove_work(&challenge.string, challenge.difficulty_factor)
+        .unwrap();
+    creds.challenge = Some(LoginWork {
+        string: challenge.string,
+        nonce: proof.nonce,
+        result: proof.result,
+    });
+    let resp =
+        test::call_service(&app, post_request!(&creds, ROUTES.auth.login).to_request())
+            .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // successful sign-in resets the account's failed attempts
+    creds.challenge = None;
+    let resp =
+        test::call_service(&app, post_request!(&creds, ROUTES.auth.login).to_request())
+            .await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn login_lockout_works_pg() {
+    let data = pg::get_data().await;
+    login_lockout_works(data).await;
+}
+
+#[actix_rt::test]
+async fn login_lockout_works_maria() {
+    let data = maria::get_data().await;
+    login_lockout_works(data).await;
+}
+
+pub async fn login_lockout_works(data: ArcData) {
+    const NAME: &str = "lockoutuser";
+    const PASSWORD: &str = "longpassword";
+    const EMAIL: &str = "lockoutuser@a.com";
+
+    let mut settings = data.settings.clone();
+    settings.features.notifications = true;
+    settings.login_protection.max_account_failures = 100;
+    settings.login_protection.lockout_threshold = 2;
+    let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+
+    delete_user(data, NAME).await;
+    register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+    let app = get_app!(data).await;
+
+    let mut creds = Login {
+        login: EMAIL.into(),
+        password: NAME.into(),
+        totp: None,
+        challenge: None,
+    };
+    for _ in 0..2 {
+        let resp = test::call_service(
+            &app,
+            post_request!(&creds, ROUTES.auth.login).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // locked out, even with the right password
+    creds.password = PASSWORD.into();
+    let resp =
+        test::call_service(&app, post_request!(&creds, ROUTES.auth.login).to_request())
+            .await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    let err: ErrorToResponse = test::read_body_json(resp).await;
+    assert_eq!(err.error, format!("{}", ServiceError::LoginLocked));
+    // signing in with the username is locked out too
+    creds.login = NAME.into();
+    let resp =
+        test::call_service(&app, post_request!(&creds, ROUTES.auth.login).to_request())
+            .await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    let notifications = data.db.get_all_unread_notifications(NAME).await.unwrap();
+    assert_eq!(notifications.len(), 1);
+}
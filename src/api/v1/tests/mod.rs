@@ -3,5 +3,6 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+mod adversarial;
 mod auth;
 mod protected;
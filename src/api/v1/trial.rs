@@ -0,0 +1,124 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{HttpResponse, Responder};
+
+use crate::errors::*;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Trial {
+        pub create: &'static str,
+    }
+
+    impl Trial {
+        pub const fn new() -> Self {
+            Self {
+                create: "/api/v1/trial/create",
+            }
+        }
+    }
+}
+
+/// create a trial sitekey; available without an account
+#[my_codegen::post(path = "crate::V1_API_ROUTES.trial.create")]
+async fn create(data: AppData) -> ServiceResult<impl Responder> {
+    let trial = crate::trial::create(&data).await?;
+    Ok(HttpResponse::Ok().json(trial))
+}
+
+pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(create);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::api::v1::auth::runners::{Login, UNUSABLE_HASH};
+    use crate::trial::{TrialCleanup, TrialSitekey};
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn trial_sitekeys_work_pg() {
+        let data = crate::tests::pg::get_data().await;
+        trial_sitekeys_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn trial_sitekeys_work_maria() {
+        let data = crate::tests::maria::get_data().await;
+        trial_sitekeys_work(data).await;
+    }
+
+    async fn trial_sitekeys_work(data: ArcData) {
+        let route = V1_API_ROUTES.trial.create;
+
+        // disabled by default
+        let data = &data;
+        let app = get_app!(data).await;
+        let resp =
+            test::call_service(&app, test::TestRequest::post().uri(route).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut settings = data.settings.clone();
+        settings.trial.enabled = true;
+        settings.trial.ttl = 0;
+        settings.trial.max_active = 1;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = AppData::new(data.clone());
+        TrialCleanup::cleanup(&app_data).await.unwrap();
+        let app = get_app!(data).await;
+
+        let resp =
+            test::call_service(&app, test::TestRequest::post().uri(route).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let trial: TrialSitekey = test::read_body_json(resp).await;
+        assert!(data.db.captcha_exists(None, &trial.key).await.unwrap());
+        let owner = data.db.get_captcha_owner(&trial.key).await.unwrap();
+        assert_eq!(
+            data.db.get_secret(&owner).await.unwrap().secret,
+            trial.secret
+        );
+        // nobody can sign in to trial accounts
+        let login = db_core::Login::Username(&owner);
+        let hash = data.db.get_password(&login).await.unwrap().hash;
+        assert_eq!(hash, UNUSABLE_HASH);
+        let creds = Login {
+            login: owner.clone(),
+            password: UNUSABLE_HASH.into(),
+            totp: None,
+            challenge: None,
+        };
+        let req = post_request!(&creds, V1_API_ROUTES.auth.login).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(!data
+            .db
+            .get_captcha_levels(None, &trial.key)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // only max_active trial sitekeys can exist at once
+        let resp =
+            test::call_service(&app, test::TestRequest::post().uri(route).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // expired trial sitekeys are deleted along with their accounts
+        assert_eq!(TrialCleanup::cleanup(&app_data).await.unwrap(), 1);
+        assert!(!data.db.captcha_exists(None, &trial.key).await.unwrap());
+        assert!(!data.db.username_exists(&owner).await.unwrap());
+        let resp =
+            test::call_service(&app, test::TestRequest::post().uri(route).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        TrialCleanup::cleanup(&app_data).await.unwrap();
+    }
+}
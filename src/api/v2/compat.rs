@@ -0,0 +1,146 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Compatibility between v1 and v2
+//!
+//! v1 stays functional, but routes that have a v2 successor respond with
+//! `Deprecation`, `Sunset`([RFC 8594](https://www.rfc-editor.org/rfc/rfc8594))
+//! and `Link: <...>; rel="successor-version"` headers so that clients know to
+//! migrate. The sunset date is `server.v1_sunset`.
+use std::future::{ready, Ready};
+
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+
+use crate::AppData;
+use crate::V1_API_ROUTES;
+
+/// v1 routes that have a v2 successor, and their successor. `{key}` in a
+/// successor is the sitekey that the request is about
+pub const DEPRECATED: [(&str, &str); 2] = [
+    (V1_API_ROUTES.captcha.create, super::ROUTES.captcha.create),
+    (V1_API_ROUTES.captcha.get, super::ROUTES.captcha.get),
+];
+
+/// Sitekey that a response of a deprecated v1 route is about. Handlers of
+/// routes whose successor is specific to a sitekey set it in the extensions of
+/// the response
+#[derive(Clone, Debug)]
+pub struct SuccessorSitekey(pub String);
+
+/// successor of a deprecated v1 route
+pub fn successor(path: &str) -> Option<&'static str> {
+    DEPRECATED
+        .iter()
+        .find(|(v1, _)| *v1 == path)
+        .map(|(_, v2)| *v2)
+}
+
+/// link to `successor`, for responses about sitekey `key`. Successors that are
+/// specific to a sitekey can't be linked to when the sitekey isn't known
+fn successor_link(successor: &str, key: Option<&str>) -> Option<String> {
+    let successor = match (successor.contains("{key}"), key) {
+        (false, _) => successor.to_string(),
+        (true, Some(key)) => successor.replace("{key}", key),
+        (true, None) => return None,
+    };
+    Some(format!("<{successor}>; rel=\"successor-version\""))
+}
+
+/// Middleware that adds deprecation headers to responses of deprecated v1 routes
+pub struct DeprecationHeaders;
+
+impl<S, B> Transform<S, ServiceRequest> for DeprecationHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = DeprecationHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DeprecationHeadersMiddleware { service }))
+    }
+}
+
+pub struct DeprecationHeadersMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for DeprecationHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let successor = successor(req.path());
+        let sunset = req
+            .app_data::<AppData>()
+            .map(|data| data.settings.server.v1_sunset.clone())
+            .unwrap_or_default();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(successor) = successor {
+                let key = res
+                    .response()
+                    .extensions()
+                    .get::<SuccessorSitekey>()
+                    .map(|k| k.0.clone());
+                let headers = res.headers_mut();
+                headers.insert(
+                    HeaderName::from_static("deprecation"),
+                    HeaderValue::from_static("true"),
+                );
+                if let Ok(sunset) = HeaderValue::from_str(&sunset) {
+                    if !sunset.is_empty() {
+                        headers.insert(HeaderName::from_static("sunset"), sunset);
+                    }
+                }
+                if let Some(link) = successor_link(successor, key.as_deref()) {
+                    headers.insert(
+                        actix_web::http::header::LINK,
+                        HeaderValue::from_str(&link).unwrap(),
+                    );
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn successor_link_works() {
+        let create = successor(V1_API_ROUTES.captcha.create).unwrap();
+        assert_eq!(
+            successor_link(create, None).unwrap(),
+            "</api/v2/mcaptcha>; rel=\"successor-version\""
+        );
+        let get = successor(V1_API_ROUTES.captcha.get).unwrap();
+        assert_eq!(
+            successor_link(get, Some("abc")).unwrap(),
+            "</api/v2/mcaptcha/abc>; rel=\"successor-version\""
+        );
+        assert_eq!(successor_link(get, None), None);
+        assert_eq!(successor(V1_API_ROUTES.captcha.update_key), None);
+    }
+}
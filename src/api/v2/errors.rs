@@ -0,0 +1,80 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Structured errors of the v2 API
+//!
+//! v1 responds with `{"error": "<message>", "code": "<code>"}`. v2 wraps the
+//! same [ServiceError] in an object that also carries the HTTP status, so
+//! that fields can be added without breaking clients.
+use actix_web::{
+    error::ResponseError,
+    http::{header, StatusCode},
+    HttpResponse, HttpResponseBuilder,
+};
+use db_core::errors::DBError;
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{FieldError, ServiceError};
+
+#[derive(Debug, Display, Error, PartialEq)]
+/// [ServiceError] rendered as a v2 error
+pub struct ApiError(pub ServiceError);
+
+pub type ApiResult<V> = std::result::Result<V, ApiError>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ErrorBody {
+    /// HTTP status code
+    pub status: u16,
+    /// human readable message
+    pub message: String,
+    /// stable identifier of the error, see [ServiceError::code]
+    pub code: String,
+    /// ID of the request, see [crate::request_id]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// invalid fields of the request, for validation errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldError>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ErrorEnvelope {
+    pub error: ErrorBody,
+}
+
+impl From<ServiceError> for ApiError {
+    fn from(e: ServiceError) -> Self {
+        Self(e)
+    }
+}
+
+impl From<DBError> for ApiError {
+    fn from(e: DBError) -> Self {
+        Self(e.into())
+    }
+}
+
+impl ResponseError for ApiError {
+    fn error_response(&self) -> HttpResponse {
+        let envelope = ErrorEnvelope {
+            error: ErrorBody {
+                status: self.status_code().as_u16(),
+                message: self.0.to_string(),
+                code: self.0.code().into(),
+                request_id: crate::request_id::current(),
+                fields: self.0.fields(),
+            },
+        };
+        HttpResponseBuilder::new(self.status_code())
+            .append_header((header::CONTENT_TYPE, "application/json; charset=UTF-8"))
+            .body(serde_json::to_string(&envelope).unwrap())
+    }
+
+    fn status_code(&self) -> StatusCode {
+        self.0.status_code()
+    }
+}
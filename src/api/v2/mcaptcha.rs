@@ -0,0 +1,268 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Sitekey management
+use actix_identity::Identity;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use db_core::OrgRole;
+use libmcaptcha::defense::Level;
+use serde::{Deserialize, Serialize};
+
+use super::errors::*;
+use super::{Page, PageQuery};
+use crate::api::v1::mcaptcha::create::{runner, CreateCaptcha};
+use crate::api::v1::orgs::runners::require_sitekey_role;
+use crate::sessions::CurrentSession;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Captcha {
+        pub list: &'static str,
+        pub create: &'static str,
+        pub get: &'static str,
+    }
+
+    impl Captcha {
+        pub const fn new() -> Self {
+            Self {
+                list: "/api/v2/mcaptcha",
+                create: "/api/v2/mcaptcha",
+                get: "/api/v2/mcaptcha/{key}",
+            }
+        }
+
+        pub fn get_sitekey(&self, key: &str) -> String {
+            self.get.replace("{key}", key)
+        }
+    }
+}
+
+pub fn services(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(create);
+    cfg.service(get);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Sitekey {
+    pub key: String,
+    pub description: String,
+    /// cooldown duration, in seconds
+    pub duration: i32,
+}
+
+impl From<db_core::Captcha> for Sitekey {
+    fn from(c: db_core::Captcha) -> Self {
+        Self {
+            key: c.key,
+            description: c.description,
+            duration: c.duration,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SitekeyDetails {
+    #[serde(flatten)]
+    pub sitekey: Sitekey,
+    pub levels: Vec<Level>,
+}
+
+/// list sitekeys of the user
+#[my_codegen::get(
+    path = "crate::V2_API_ROUTES.captcha.list",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn list(
+    query: web::Query<PageQuery>,
+    data: AppData,
+    id: Identity,
+) -> ApiResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let total = data.db.count_user_captchas(&username).await?;
+    let sitekeys: Vec<Sitekey> = data
+        .db
+        .get_user_captchas_page(&username, query.offset(), query.limit())
+        .await?
+        .into_iter()
+        .map(Sitekey::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(Page::new(sitekeys, &query, total)))
+}
+
+/// create sitekey; shares validation and limits with v1
+#[my_codegen::post(
+    path = "crate::V2_API_ROUTES.captcha.create",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn create(
+    req: HttpRequest,
+    payload: web::Json<CreateCaptcha>,
+    data: AppData,
+    id: Identity,
+) -> ApiResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let session = CurrentSession::get(&req);
+    let details = runner::create(&payload, &data, &username, session.as_deref()).await?;
+    let sitekey = data.db.get_captcha_config(&username, &details.key).await?;
+    Ok(HttpResponse::Created().json(Sitekey::from(sitekey)))
+}
+
+/// get sitekey and its levels
+#[my_codegen::get(
+    path = "crate::V2_API_ROUTES.captcha.get",
+    wrap = "crate::api::v1::get_middleware()"
+)]
+pub async fn get(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+) -> ApiResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let key = path.into_inner();
+    let sitekey = data.db.get_captcha_config(&username, &key).await?;
+    require_sitekey_role(&data, &key, &username, OrgRole::Viewer).await?;
+    let levels = data.db.get_captcha_levels(Some(&username), &key).await?;
+    Ok(HttpResponse::Ok().json(SitekeyDetails {
+        sitekey: sitekey.into(),
+        levels,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::mcaptcha::create::MCaptchaDetails;
+    use crate::errors::ServiceError;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn v2_sitekeys_work_pg() {
+        let data = pg::get_data().await;
+        v2_sitekeys_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn v2_sitekeys_work_maria() {
+        let data = maria::get_data().await;
+        v2_sitekeys_work(data).await;
+    }
+
+    async fn v2_sitekeys_work(data: ArcData) {
+        const NAME: &str = "v2sitekeyuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "v2sitekeyuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+        let routes = &V2_API_ROUTES.captcha;
+
+        let payload = CreateCaptcha {
+            levels: vec![L1, L2],
+            duration: 30,
+            description: "v2".into(),
+            publish_benchmarks: false,
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&payload, routes.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let sitekey: Sitekey = test::read_body_json(resp).await;
+        assert_eq!(sitekey.description, payload.description);
+        assert_eq!(sitekey.duration, 30);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{}?page=0&limit=1", routes.list))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let page: Page<Sitekey> = test::read_body_json(resp).await;
+        assert_eq!(page.total, 1);
+        assert_eq!(page.items, vec![sitekey.clone()]);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{}?page=1&limit=1", routes.list))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        let page: Page<Sitekey> = test::read_body_json(resp).await;
+        assert_eq!(page.total, 1);
+        assert!(page.items.is_empty());
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_sitekey(&sitekey.key))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let details: SitekeyDetails = test::read_body_json(resp).await;
+        assert_eq!(details.sitekey, sitekey);
+        assert_eq!(details.levels, vec![L1, L2]);
+
+        // structured errors
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&routes.get_sitekey("nonexistent"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let err: ErrorEnvelope = test::read_body_json(resp).await;
+        assert_eq!(err.error.status, 404);
+        assert_eq!(err.error.message, ServiceError::CaptchaNotFound.to_string());
+        assert_eq!(err.error.code, "captcha_not_found");
+
+        // v1 still works, but is marked deprecated
+        let details = MCaptchaDetails {
+            name: sitekey.description.clone(),
+            key: sitekey.key.clone(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&details, V1_API_ROUTES.captcha.get)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let headers = resp.headers();
+        assert_eq!(headers.get("deprecation").unwrap(), "true");
+        assert_eq!(
+            headers.get("sunset").unwrap(),
+            data.settings.server.v1_sunset.as_str()
+        );
+        assert_eq!(
+            headers.get(header::LINK).unwrap(),
+            format!(
+                "<{}>; rel=\"successor-version\"",
+                routes.get_sitekey(&sitekey.key)
+            )
+            .as_str()
+        );
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Version 2 of the API
+//!
+//! v2 endpoints return paginated lists and [structured errors][errors::ApiError].
+//! Business logic is shared with v1: handlers call the same runners and only
+//! differ in the shape of requests and responses. v1 routes that have a v2
+//! successor are marked deprecated by [compat::DeprecationHeaders].
+use actix_web::web::ServiceConfig;
+use serde::{Deserialize, Serialize};
+
+pub mod compat;
+pub mod errors;
+pub mod mcaptcha;
+mod routes;
+
+pub use routes::ROUTES;
+
+/// default number of items in a page
+pub const DEFAULT_PAGE_LIMIT: usize = 20;
+/// maximum number of items in a page
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+pub fn services(cfg: &mut ServiceConfig) {
+    mcaptcha::services(cfg);
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// pagination query parameters; pages are numbered from zero
+pub struct PageQuery {
+    pub page: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl PageQuery {
+    /// requested page
+    pub fn page(&self) -> usize {
+        self.page.unwrap_or(0)
+    }
+
+    /// requested number of items in a page, up to [MAX_PAGE_LIMIT]
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    /// number of items before the requested page. Kept within what databases
+    /// take as an offset
+    pub fn offset(&self) -> usize {
+        self.page()
+            .saturating_mul(self.limit())
+            .min(i64::MAX as usize)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// a page of items
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: usize,
+    pub limit: usize,
+    /// total number of items across all pages
+    pub total: usize,
+}
+
+impl<T> Page<T> {
+    /// the requested page, whose `items` were fetched with the offset and
+    /// limit of `query`
+    pub fn new(items: Vec<T>, query: &PageQuery, total: usize) -> Self {
+        Self {
+            items,
+            page: query.page(),
+            limit: query.limit(),
+            total,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_query_works() {
+        let query = PageQuery::default();
+        assert_eq!(query.page(), 0);
+        assert_eq!(query.limit(), DEFAULT_PAGE_LIMIT);
+        assert_eq!(query.offset(), 0);
+
+        let query = PageQuery {
+            page: Some(2),
+            limit: Some(20),
+        };
+        assert_eq!(query.offset(), 40);
+        let page = Page::new(vec![40, 41], &query, 42);
+        assert_eq!(page.page, 2);
+        assert_eq!(page.limit, 20);
+        assert_eq!(page.total, 42);
+
+        let query = PageQuery {
+            page: Some(5),
+            limit: Some(1000),
+        };
+        assert_eq!(query.limit(), MAX_PAGE_LIMIT);
+        assert_eq!(query.offset(), 500);
+
+        let query = PageQuery {
+            page: Some(usize::MAX),
+            limit: Some(0),
+        };
+        assert_eq!(query.limit(), 1);
+        assert_eq!(query.offset(), i64::MAX as usize);
+    }
+}
@@ -0,0 +1,20 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use super::mcaptcha::routes::Captcha;
+
+pub const ROUTES: Routes = Routes::new();
+
+pub struct Routes {
+    pub captcha: Captcha,
+}
+
+impl Routes {
+    const fn new() -> Routes {
+        Routes {
+            captcha: Captcha::new(),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Scoped API tokens
+//!
+//! Integrations, like monitoring systems, authenticate with an API token in
+//! the `Authorization: Bearer` header instead of the session cookie. Tokens
+//! are scoped: the identity policy only accepts a token on the requests that
+//! its scope allows, and treats it as unauthenticated otherwise. Only a hash
+//! of the token is stored.
+use actix_web::dev::ServiceRequest;
+use actix_web::http::{header, Method};
+use db_core::errors::DBError;
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::AppData;
+
+/// length of generated tokens
+const TOKEN_LEN: usize = 40;
+/// maximum length of token names, in characters
+pub const MAX_TOKEN_NAME_LEN: usize = 100;
+
+#[derive(Deserialize, Serialize, Display, Eq, PartialEq, Clone, Copy, Debug)]
+/// What an API token can be used for
+pub enum Scope {
+    /// read per-sitekey stats through the monitoring API; no mutations
+    #[serde(rename = "read:stats")]
+    #[display(fmt = "read:stats")]
+    ReadStats,
+}
+
+impl Scope {
+    /// name of the scope, as stored with tokens
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReadStats => "read:stats",
+        }
+    }
+
+    /// parse the name of a scope
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "read:stats" => Some(Self::ReadStats),
+            _ => None,
+        }
+    }
+
+    /// can a token of this scope be used for a request
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        match self {
+            Self::ReadStats => {
+                (method == Method::GET || method == Method::HEAD)
+                    && path.starts_with(crate::V1_API_ROUTES.monitoring.scope)
+            }
+        }
+    }
+}
+
+/// hash of a token, as stored in the database
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// generate a new token
+pub fn generate() -> String {
+    get_random(TOKEN_LEN)
+}
+
+/// token in the `Authorization: Bearer` header of a request, if any
+pub fn bearer(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(|t| t.trim().to_string())
+}
+
+/// user that `token` authenticates, for a `method` request to `path`
+pub async fn authenticate(
+    data: &AppData,
+    token: &str,
+    method: &Method,
+    path: &str,
+) -> ServiceResult<String> {
+    let owner = match data.db.get_api_token_owner(&hash(token)).await {
+        Ok(owner) => owner,
+        Err(DBError::ApiTokenNotFound) => return Err(ServiceError::InvalidApiToken),
+        Err(e) => return Err(e.into()),
+    };
+    let scope = Scope::from_name(&owner.scope).ok_or(ServiceError::InvalidApiToken)?;
+    if !scope.allows(method, path) {
+        return Err(ServiceError::ApiTokenScopeMissing(scope.as_str()));
+    }
+    if data.db.is_suspended(&owner.username).await? {
+        return Err(ServiceError::InvalidApiToken);
+    }
+    Ok(owner.username)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_stats_is_read_only() {
+        let scope = Scope::ReadStats;
+        let stats = crate::V1_API_ROUTES.monitoring.get_stats_route("sitekey");
+        assert!(scope.allows(&Method::GET, &stats));
+        assert!(scope.allows(&Method::HEAD, crate::V1_API_ROUTES.monitoring.sitekeys));
+        assert!(!scope.allows(&Method::POST, &stats));
+        assert!(!scope.allows(&Method::DELETE, &stats));
+        assert!(!scope.allows(&Method::GET, crate::V1_API_ROUTES.notifications.get));
+        assert!(!scope.allows(&Method::GET, crate::V1_API_ROUTES.account.tokens.list));
+        assert_eq!(Scope::from_name(scope.as_str()), Some(scope));
+        assert_eq!(scope.to_string(), scope.as_str());
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Request body limits
+//!
+//! JSON request bodies are limited in size and in nesting depth by
+//! `[server.limits]`. Sitekey routes, whose bodies hold difficulty levels, and
+//! PoW routes have their own size limits; other routes share `json`. Bodies
+//! over the size limit are rejected with [ServiceError::PayloadTooLarge] as
+//! soon as the limit is crossed, without reading the rest of the body, and
+//! bodies that nest too deeply with [ServiceError::JsonTooDeep], before they
+//! are deserialized.
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::web::BytesMut;
+use actix_web::{Error, ResponseError};
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+
+use crate::errors::*;
+use crate::settings::Limits;
+use crate::AppData;
+
+/// paths of sitekey routes of the v1 and v2 APIs
+const CAPTCHA_PATHS: [&str; 2] = ["/api/v1/mcaptcha", "/api/v2/mcaptcha"];
+const POW_PATH: &str = "/api/v1/pow";
+
+fn is_under(path: &str, prefix: &str) -> bool {
+    path == prefix || path.starts_with(&format!("{prefix}/"))
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|t| t.to_str().ok())
+        .map_or(false, |t| {
+            let t = t.split(';').next().unwrap_or_default().trim();
+            t == "application/json" || t.ends_with("+json")
+        })
+}
+
+/// size limit of JSON bodies of requests to `path`
+pub fn size_limit(s: &Limits, path: &str) -> usize {
+    if CAPTCHA_PATHS.iter().any(|p| is_under(path, p)) {
+        s.captcha
+    } else if is_under(path, POW_PATH) {
+        s.pow
+    } else {
+        s.json
+    }
+}
+
+/// largest size limit, which is used to configure JSON extractors
+pub fn max_size_limit(s: &Limits) -> usize {
+    s.json.max(s.captcha).max(s.pow)
+}
+
+/// check that arrays and objects in `json` nest at most `max` levels deep.
+/// Brackets in strings aren't counted
+pub fn check_depth(json: &[u8], max: usize) -> ServiceResult<()> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for b in json.iter() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match b {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max {
+                    return Err(ServiceError::JsonTooDeep(max));
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Middleware that enforces the limits of `[server.limits]` on JSON request
+/// bodies
+pub struct BodyLimits;
+
+impl<S, B> Transform<S, ServiceRequest> for BodyLimits
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BodyLimitsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BodyLimitsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct BodyLimitsMiddleware<S> {
+    service: Rc<S>,
+}
+
+/// read the JSON body of `req` and check it against `s`; the body is put back
+/// into `req` when it's within the limits
+async fn check(req: &mut ServiceRequest, s: &Limits) -> ServiceResult<()> {
+    let limit = size_limit(s, req.path());
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse::<usize>().ok());
+    if length.map_or(false, |l| l > limit) {
+        return Err(ServiceError::PayloadTooLarge(limit));
+    }
+
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::with_capacity(length.unwrap_or_default());
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| ServiceError::InternalServerError)?;
+        if body.len() + chunk.len() > limit {
+            return Err(ServiceError::PayloadTooLarge(limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    check_depth(&body, s.json_depth)?;
+
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body.freeze());
+    req.set_payload(payload.into());
+    Ok(())
+}
+
+impl<S, B> Service<ServiceRequest> for BodyLimitsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let data = req.app_data::<AppData>().cloned();
+            if let (Some(data), true) = (data, is_json(req.headers())) {
+                if let Err(e) = check(&mut req, &data.settings.server.limits).await {
+                    let resp = e.error_response();
+                    return Ok(req.into_response(resp).map_into_right_body());
+                }
+            }
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use libmcaptcha::defense::Level;
+
+    use super::*;
+    use crate::api::v1::mcaptcha::create::CreateCaptcha;
+    use crate::errors::ErrorToResponse;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn limits_work() {
+        let s = crate::tests::get_settings().server.limits;
+        assert_eq!(size_limit(&s, V1_API_ROUTES.captcha.create), s.captcha);
+        assert_eq!(size_limit(&s, "/api/v2/mcaptcha"), s.captcha);
+        assert_eq!(size_limit(&s, V1_API_ROUTES.pow.verify_pow), s.pow);
+        assert_eq!(size_limit(&s, V1_API_ROUTES.auth.login), s.json);
+        assert_eq!(size_limit(&s, "/api/v1/mcaptchas"), s.json);
+        assert_eq!(max_size_limit(&s), s.captcha);
+
+        assert!(check_depth(br#"{"a": [[1], {"b": 2}]}"#, 3).is_ok());
+        assert_eq!(
+            check_depth(br#"{"a": [[1], {"b": [2]}]}"#, 3),
+            Err(ServiceError::JsonTooDeep(3))
+        );
+        // brackets in strings don't count
+        assert!(check_depth(br#"{"a": "[[[{\"[[["}"#, 1).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn body_limits_work_pg() {
+        let data = crate::tests::pg::get_data().await;
+        body_limits_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn body_limits_work_maria() {
+        let data = crate::tests::maria::get_data().await;
+        body_limits_work(data).await;
+    }
+
+    async fn body_limits_work(data: ArcData) {
+        const NAME: &str = "bodylimitsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "bodylimitsuser@a.com";
+
+        delete_user(&data, NAME).await;
+        let (_, signin_resp) = register_and_signin(&data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+
+        let mut settings = data.settings.clone();
+        settings.server.limits.captcha = 512;
+        settings.server.limits.json_depth = 4;
+        let data = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app = get_app!(data).await;
+
+        let create = |levels| CreateCaptcha {
+            levels,
+            duration: 30,
+            description: "body limits".into(),
+            publish_benchmarks: false,
+        };
+
+        // too many levels
+        let levels = (1..100)
+            .map(|i| Level {
+                visitor_threshold: i,
+                difficulty_factor: i * 10,
+            })
+            .collect();
+        let resp = test::call_service(
+            &app,
+            post_request!(&create(levels), V1_API_ROUTES.captcha.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.code, "payload_too_large");
+
+        // too deep
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(V1_API_ROUTES.captcha.create)
+                .insert_header((header::CONTENT_TYPE, "application/json"))
+                .set_payload(r#"{"levels": [[[[[]]]]]}"#)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.code, "json_too_deep");
+
+        // within limits
+        let resp = test::call_service(
+            &app,
+            post_request!(&create(L1.to_vec()), V1_API_ROUTES.captcha.create)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
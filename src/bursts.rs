@@ -0,0 +1,235 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Scheduled traffic bursts
+//!
+//! The defense only raises difficulty after visitor thresholds are crossed,
+//! which is too late for traffic that is known in advance, like a ticket
+//! drop. Owners can schedule a burst for a sitekey: between its start and end,
+//! challenges are served at no less than the burst's difficulty factor,
+//! whatever the visitor count.
+//!
+//! Bursts are loaded from the database every minute, so challenges don't query
+//! for them. Sitekeys are registered with the master shortly before their
+//! bursts start, so that the first visitors of the burst don't wait for it.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::Burst;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::api::v1::pow::get_config::init_mcaptcha;
+use crate::errors::*;
+use crate::jobs::BURST_SCHEDULE_JOB;
+use crate::AppData;
+
+/// seconds between reloads of the schedule
+const REFRESH_INTERVAL: u64 = 60;
+/// seconds before the start of a burst within which its sitekey is registered
+/// with the master
+pub const PREWARM_WINDOW: i64 = 300;
+
+/// Bursts that are active or about to start, by sitekey
+#[derive(Clone, Debug, Default)]
+pub struct BurstSchedule {
+    bursts: Arc<RwLock<HashMap<String, Vec<Burst>>>>,
+    /// bursts whose sitekeys were registered with the master
+    warmed: Arc<RwLock<HashSet<i32>>>,
+}
+
+impl BurstSchedule {
+    /// minimum difficulty factor of a sitekey at unix timestamp `now`, if a
+    /// burst is active
+    pub fn difficulty(&self, key: &str, now: i64) -> Option<u32> {
+        let r = self.bursts.read().unwrap();
+        r.get(key)?
+            .iter()
+            .filter(|b| b.starts <= now && now < b.ends)
+            .map(|b| b.difficulty_factor)
+            .max()
+    }
+
+    /// replace bursts of a sitekey
+    pub fn set(&self, key: &str, bursts: Vec<Burst>) {
+        let mut w = self.bursts.write().unwrap();
+        if bursts.is_empty() {
+            w.remove(key);
+        } else {
+            w.insert(key.into(), bursts);
+        }
+    }
+
+    /// replace all bursts
+    fn replace(&self, bursts: Vec<Burst>) {
+        let mut map: HashMap<String, Vec<Burst>> = HashMap::new();
+        for burst in bursts {
+            map.entry(burst.key.clone()).or_default().push(burst);
+        }
+        let mut w = self.bursts.write().unwrap();
+        *w = map;
+        drop(w);
+
+        // forget bursts that ended
+        let r = self.bursts.read().unwrap();
+        let ids: HashSet<i32> = r.values().flatten().map(|b| b.id).collect();
+        drop(r);
+        let mut w = self.warmed.write().unwrap();
+        w.retain(|id| ids.contains(id));
+    }
+
+    /// mark a burst as warmed; returns false if it already was
+    fn warm(&self, id: i32) -> bool {
+        let mut w = self.warmed.write().unwrap();
+        w.insert(id)
+    }
+}
+
+pub struct BurstScheduler {
+    tx: Sender<()>,
+}
+
+impl BurstScheduler {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// reload bursts that are active or start within [PREWARM_WINDOW] and
+    /// register sitekeys of upcoming bursts with the master
+    pub async fn refresh(data: &AppData) -> ServiceResult<()> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        data.db.delete_ended_bursts(now).await?;
+        let bursts = data
+            .db
+            .get_scheduled_bursts(now, now + PREWARM_WINDOW)
+            .await?;
+        // sitekeys of bursts that already started are in use, and registering
+        // them again would reset their visitor count
+        let keys: Vec<(i32, String)> = bursts
+            .iter()
+            .filter(|b| b.starts > now)
+            .map(|b| (b.id, b.key.clone()))
+            .collect();
+        data.bursts.replace(bursts);
+
+        for (id, key) in keys {
+            if !data.bursts.warm(id) {
+                continue;
+            }
+            log::info!("Registering sitekey {key} with master ahead of burst {id}");
+            if let Err(e) = init_mcaptcha(data, &key).await {
+                log::error!("Tried to register sitekey {key} ahead of burst: {:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs.register(BURST_SCHEDULE_JOB, REFRESH_INTERVAL);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::refresh(&data).await;
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Tried to refresh burst schedule {:?}", err);
+                }
+                data.jobs
+                    .finished(BURST_SCHEDULE_JOB, started, timer.elapsed(), &res);
+
+                for _ in 0..REFRESH_INTERVAL {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn burst(
+        id: i32,
+        key: &str,
+        starts: i64,
+        ends: i64,
+        difficulty_factor: u32,
+    ) -> Burst {
+        Burst {
+            id,
+            key: key.into(),
+            starts,
+            ends,
+            difficulty_factor,
+        }
+    }
+
+    #[test]
+    fn burst_schedule_works() {
+        let schedule = BurstSchedule::default();
+        assert_eq!(schedule.difficulty("key", 100), None);
+
+        schedule.replace(vec![
+            burst(1, "key", 100, 200, 5000),
+            burst(2, "key", 150, 300, 8000),
+            burst(3, "other", 100, 200, 100),
+        ]);
+        assert_eq!(schedule.difficulty("key", 99), None);
+        assert_eq!(schedule.difficulty("key", 100), Some(5000));
+        // overlapping bursts serve the highest difficulty
+        assert_eq!(schedule.difficulty("key", 150), Some(8000));
+        assert_eq!(schedule.difficulty("key", 250), Some(8000));
+        assert_eq!(schedule.difficulty("key", 300), None);
+        assert_eq!(schedule.difficulty("other", 150), Some(100));
+
+        assert!(schedule.warm(1));
+        assert!(!schedule.warm(1));
+
+        schedule.set("key", Vec::default());
+        assert_eq!(schedule.difficulty("key", 150), None);
+        assert_eq!(schedule.difficulty("other", 150), Some(100));
+
+        // bursts no longer scheduled are forgotten
+        schedule.replace(Vec::default());
+        assert!(schedule.warm(1));
+    }
+}
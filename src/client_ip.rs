@@ -0,0 +1,295 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Client IP addresses
+//!
+//! `Forwarded` and `X-Forwarded-For` are set by whoever sends the request, so
+//! they are only read when the peer is one of `server.trusted_proxies`, the
+//! reverse proxies in front of mCaptcha. The client is the nearest hop that
+//! isn't a trusted proxy. Requests from other peers are attributed to the peer
+//! address.
+//!
+//! Peers on Unix domain sockets have no address. Only the reverse proxy can
+//! connect to the socket, so they are trusted.
+//!
+//! Addresses that are stored to be matched or attributed later are stored as
+//! [hash_ip] of the address.
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::HttpRequest;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+/// separates IP address hashes from other uses of the key
+const IP_HASH_CONTEXT: &[u8] = b"mcaptcha ip hash:";
+
+/// keyed hash of IP address `ip`. `key` has to be a secret that never leaves
+/// the server, like `server.cookie_secret`, or addresses can be recovered from
+/// their hashes by trying all of them
+pub fn hash_ip(key: &str, ip: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(IP_HASH_CONTEXT);
+    mac.update(ip.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// address of clients that can't be determined
+// From actix-web docs:
+//  Will only return None when called in unit tests unless TestRequest::peer_addr is used.
+//
+// ref: https://docs.rs/actix-web/latest/actix_web/struct.HttpRequest.html#method.peer_addr
+#[cfg(test)]
+const UNKNOWN_CLIENT: &str = "127.0.1.1";
+#[cfg(not(test))]
+const UNKNOWN_CLIENT: &str = "";
+
+/// IPv4-mapped IPv6 addresses are compared and reported as IPv4 addresses
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        ip => ip,
+    }
+}
+
+/// IP address or CIDR range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("{s} isn't an IP address or CIDR range");
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| err())?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= bits)
+                .ok_or_else(err)?,
+            None => bits,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (range, ip, bits) = match (self.addr, canonical(ip)) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                (u32::from(range) as u128, u32::from(ip) as u128, 32)
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                (u128::from(range), u128::from(ip), 128)
+            }
+            _ => return false,
+        };
+        if self.prefix == 0 {
+            return true;
+        }
+        let shift = bits - self.prefix;
+        range >> shift == ip >> shift
+    }
+}
+
+/// parse a hop of `Forwarded` or `X-Forwarded-For`, which may have a port
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim().trim_matches('"');
+    if let Ok(ip) = hop.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = hop.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    hop.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .and_then(|h| h.parse().ok())
+}
+
+/// hops of the forwarded headers of a request, from the client to the nearest
+/// proxy. `Forwarded` is preferred over `X-Forwarded-For`. Hops that aren't
+/// addresses, like `unknown`, are `None`
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect::<Vec<&str>>()
+    };
+
+    let forwarded = values(header::FORWARDED.as_str());
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim().eq_ignore_ascii_case("for").then_some(value)
+                })
+            })
+            .map(parse_hop)
+            .collect();
+    }
+    values(X_FORWARDED_FOR).into_iter().map(parse_hop).collect()
+}
+
+/// Trusted reverse proxies, as configured in `server.trusted_proxies`
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Vec<IpRange>);
+
+impl TrustedProxies {
+    pub fn new(ranges: &[String]) -> Result<Self, String> {
+        let ranges = ranges
+            .iter()
+            .map(|r| r.parse())
+            .collect::<Result<Vec<IpRange>, String>>()?;
+        Ok(Self(ranges))
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|r| r.contains(ip))
+    }
+
+    /// IP address of the client that sent `req`
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        self.resolve(req.peer_addr().map(|a| a.ip()), req.headers())
+    }
+
+    fn resolve(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> String {
+        let mut client = match peer {
+            Some(ip) if !self.is_trusted(ip) => return canonical(ip).to_string(),
+            peer => peer,
+        };
+        // walk back from the nearest proxy. A hop that isn't an address can't
+        // be checked, so the client is the last hop that could be
+        for hop in forwarded_hops(headers).into_iter().rev() {
+            match hop {
+                Some(ip) => {
+                    client = Some(ip);
+                    if !self.is_trusted(ip) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        client.map_or_else(|| UNKNOWN_CLIENT.into(), |ip| canonical(ip).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs.iter() {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn proxy_range_works() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.1.2.3".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let range: IpRange = "::1".parse().unwrap();
+        assert!(range.contains("::1".parse().unwrap()));
+        assert!(!range.contains("::2".parse().unwrap()));
+
+        let range: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(range.contains("1.2.3.4".parse().unwrap()));
+
+        for range in ["10.0.0.0/33", "::/129", "10.0.0", "localhost", "10.0.0.0/"] {
+            assert!(range.parse::<IpRange>().is_err(), "{range}");
+        }
+    }
+
+    #[test]
+    fn hash_ip_works() {
+        let hash = hash_ip("secret", "192.0.2.1");
+        assert_eq!(hash, hash_ip("secret", "192.0.2.1"));
+        assert_ne!(hash, hash_ip("secret", "192.0.2.2"));
+        assert_ne!(hash, hash_ip("other", "192.0.2.1"));
+    }
+
+    #[test]
+    fn client_ip_works() {
+        let proxies =
+            TrustedProxies::new(&["127.0.0.1".into(), "10.0.0.0/8".into()]).unwrap();
+        let proxy = Some("127.0.0.1".parse().unwrap());
+        let client = Some("1.2.3.4".parse().unwrap());
+        let xff = |v| headers(&[(X_FORWARDED_FOR, v)]);
+
+        // headers of untrusted peers are ignored
+        assert_eq!(proxies.resolve(client, &xff("5.6.7.8")), "1.2.3.4");
+        assert_eq!(proxies.resolve(client, &HeaderMap::new()), "1.2.3.4");
+
+        // the nearest untrusted hop is the client
+        assert_eq!(proxies.resolve(proxy, &xff("5.6.7.8")), "5.6.7.8");
+        assert_eq!(
+            proxies.resolve(proxy, &xff("9.9.9.9, 5.6.7.8, 10.0.0.2")),
+            "5.6.7.8"
+        );
+        let two = headers(&[(X_FORWARDED_FOR, "9.9.9.9"), (X_FORWARDED_FOR, "5.6.7.8")]);
+        assert_eq!(proxies.resolve(proxy, &two), "5.6.7.8");
+        // all hops are trusted
+        assert_eq!(
+            proxies.resolve(proxy, &xff("10.0.0.3, 10.0.0.2")),
+            "10.0.0.3"
+        );
+        // hops that aren't addresses stop the walk
+        assert_eq!(
+            proxies.resolve(proxy, &xff("9.9.9.9, unknown, 10.0.0.2")),
+            "10.0.0.2"
+        );
+        assert_eq!(proxies.resolve(proxy, &HeaderMap::new()), "127.0.0.1");
+
+        // Forwarded is preferred
+        let forwarded = headers(&[
+            (
+                "forwarded",
+                r#"for=9.9.9.9, for="[2001:db8::1]:4711";proto=https"#,
+            ),
+            (X_FORWARDED_FOR, "5.6.7.8"),
+        ]);
+        assert_eq!(proxies.resolve(proxy, &forwarded), "2001:db8::1");
+        let forwarded = headers(&[("forwarded", r#"for="5.6.7.8:1234""#)]);
+        assert_eq!(proxies.resolve(proxy, &forwarded), "5.6.7.8");
+
+        // peers on Unix domain sockets
+        assert_eq!(proxies.resolve(None, &xff("5.6.7.8")), "5.6.7.8");
+        assert_eq!(proxies.resolve(None, &HeaderMap::new()), UNKNOWN_CLIENT);
+
+        // nothing is trusted
+        let none = TrustedProxies::new(&[]).unwrap();
+        assert_eq!(none.resolve(proxy, &xff("5.6.7.8")), "127.0.0.1");
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Instances that share state through the database instead of Redis
+//!
+//! Instances with the embedded master keep challenges, validation tokens and
+//! visitor counts in memory, so a visitor whose requests are balanced across
+//! instances can't verify a challenge issued by another one, and each instance
+//! sets difficulty from its share of the traffic alone. When `cluster.enabled`
+//! is set and Redis isn't configured:
+//!
+//! - challenges are recorded in the database when they are issued, and
+//!   verified against it, so any instance can verify them
+//! - validation tokens are recorded in the database and validated against it,
+//!   so any instance can validate them, once. Challenges and validation tokens
+//!   that are kept in [memcached](crate::memcached) are shared through it
+//!   instead
+//! - pending [fallback challenges](crate::fallback) and the counts of IP
+//!   addresses that start them are recorded in the database, so that a
+//!   challenge started on one instance can be completed on any other
+//! - every `cluster.sync_interval` seconds, [ClusterSync] reports the visitor
+//!   counts of this instance and adds those of the other instances to the
+//!   master, so that all instances serve the difficulty of the whole traffic.
+//!   Instances that didn't report for [STALE_SYNCS] intervals are left out
+//!
+//! Visitor counts are eventually consistent: visitors counted while a sync is
+//! in progress may be missed. Validation tokens are recorded along with the
+//! IP address they are bound to, so that any instance can check the binding.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::VisitorCount;
+use libmcaptcha::errors::CaptchaError;
+use libmcaptcha::pow::{ConfigBuilder, Work};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::jobs::CLUSTER_SYNC_JOB;
+use crate::settings::{CacheBackend, Settings};
+use crate::AppData;
+
+/// sync intervals after which instances that didn't report are left out
+pub const STALE_SYNCS: u64 = 3;
+/// length of validation tokens
+const TOKEN_LEN: usize = 32;
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+pub struct Cluster {
+    /// ID of the instance within the cluster
+    pub instance_id: String,
+    /// interval, in seconds, at which visitor counts are synced
+    pub sync_interval: u64,
+    /// challenges and validation tokens are shared through the database
+    pub share_challenges: bool,
+    /// visitor counts of other instances, by sitekey, as added to the master
+    /// at the last sync
+    remote: Mutex<HashMap<String, u32>>,
+}
+
+impl Cluster {
+    /// cluster membership, when clustering is enabled and Redis isn't
+    /// configured
+    pub fn new(s: &Settings) -> Option<Self> {
+        if !s.cluster.enabled {
+            return None;
+        }
+        if s.redis.is_some() {
+            log::warn!("Redis is configured, so cluster.enabled is ignored");
+            return None;
+        }
+        let instance_id = s
+            .cluster
+            .instance_id
+            .clone()
+            .unwrap_or_else(|| get_random(16));
+        log::info!("Joining cluster as instance {instance_id}");
+        Some(Self {
+            instance_id,
+            sync_interval: s.cluster.sync_interval,
+            share_challenges: s.cache.backend != CacheBackend::Memcached,
+            remote: Mutex::default(),
+        })
+    }
+}
+
+/// are challenges and validation tokens shared through the database
+pub fn shares_challenges(data: &AppData) -> bool {
+    data.cluster.as_ref().map_or(false, |c| c.share_challenges)
+}
+
+/// record challenge `string` of sitekey `key`, so that any instance can
+/// verify it until the sitekey's cooldown is over
+pub async fn add_challenge(
+    data: &AppData,
+    key: &str,
+    string: &str,
+    difficulty_factor: u32,
+) -> ServiceResult<()> {
+    let ttl = data.db.get_captcha_cooldown(key).await?;
+    data.db
+        .add_cluster_challenge(key, string, difficulty_factor, now() + ttl as i64)
+        .await?;
+    Ok(())
+}
+
+/// verify PoW against a challenge issued by any instance and record the
+/// validation token, bound to `ip`. Returns the token and the difficulty
+/// factor of the challenge
+pub async fn verify_pow(
+    data: &AppData,
+    work: Work,
+    ip: &str,
+) -> ServiceResult<(String, u32)> {
+    let now = now();
+    let difficulty_factor = data
+        .db
+        .take_cluster_challenge(&work.key, &work.string, now)
+        .await?
+        .ok_or(CaptchaError::StringNotFound)?;
+
+    let config = ConfigBuilder::default()
+        .salt(data.settings.captcha.salt.clone())
+        .build()
+        .unwrap();
+    let key = work.key.clone();
+    let string = work.string.clone();
+    let pow = work.into();
+    if !config.is_valid_proof(&pow, &string) {
+        return Err(CaptchaError::InvalidPoW.into());
+    }
+    if !config.is_sufficient_difficulty(&pow, difficulty_factor) {
+        return Err(CaptchaError::InsuffiencientDifficulty.into());
+    }
+
+    let token = get_random(TOKEN_LEN);
+    let ttl = data.db.get_captcha_cooldown(&key).await?;
+    data.db
+        .add_cluster_token(&key, &token, &data.tokens.hash_ip(ip), now + ttl as i64)
+        .await?;
+    Ok((token, difficulty_factor))
+}
+
+/// validate a token issued by any instance. Tokens can only be validated once
+pub async fn validate(data: &AppData, key: &str, token: &str) -> ServiceResult<bool> {
+    Ok(data.db.take_cluster_token(key, token, now()).await?)
+}
+
+/// report visitor counts of this instance and add those of the other
+/// instances to the master
+pub async fn sync(data: &AppData, now: i64) -> ServiceResult<()> {
+    let cluster = match data.cluster.as_ref() {
+        Some(cluster) => cluster,
+        None => return Ok(()),
+    };
+    // held so that sitekeys registered in the meantime aren't overwritten
+    let _sync = data.master_sync.all().await;
+    let mut sitekeys = match data.captcha.get_internal_data().await? {
+        Some(sitekeys) => sitekeys,
+        None => return Ok(()),
+    };
+
+    // visitors of other instances were added at the last sync
+    let remote = cluster.remote.lock().unwrap().clone();
+    let own: Vec<VisitorCount> = sitekeys
+        .iter()
+        .map(|(key, mcaptcha)| {
+            let added = remote.get(key).copied().unwrap_or_default();
+            VisitorCount {
+                key: key.clone(),
+                visitors: mcaptcha.get_visitors().saturating_sub(added),
+            }
+        })
+        .collect();
+    data.db
+        .report_cluster_visitors(&cluster.instance_id, &own, now)
+        .await?;
+
+    let stale_before = now - (STALE_SYNCS * cluster.sync_interval) as i64;
+    let mut others: HashMap<String, u32> = data
+        .db
+        .get_cluster_visitors(&cluster.instance_id, stale_before)
+        .await?
+        .into_iter()
+        .map(|v| (v.key, v.visitors))
+        .collect();
+    others.retain(|key, _| sitekeys.contains_key(key));
+    for v in own.iter() {
+        let added = others.get(&v.key).copied().unwrap_or_default();
+        if let Some(mcaptcha) = sitekeys.get_mut(&v.key) {
+            mcaptcha.set_visitor_count(v.visitors.saturating_add(added));
+        }
+    }
+    data.captcha.set_internal_data(sitekeys).await?;
+    *cluster.remote.lock().unwrap() = others;
+
+    data.db.purge_cluster_state(now, stale_before).await?;
+    Ok(())
+}
+
+pub struct ClusterSync {
+    tx: Sender<()>,
+}
+
+impl ClusterSync {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let interval = data.cluster.as_ref().map_or(0, |c| c.sync_interval);
+        data.jobs.register(CLUSTER_SYNC_JOB, interval);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                for _ in 0..interval {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = sync(&data, started.unix_timestamp()).await;
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while syncing with the cluster: {:?}", err);
+                }
+                data.jobs
+                    .finished(CLUSTER_SYNC_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::v1::pow::get_config::get_config_runner;
+    use crate::api::v1::pow::verify_pow::{verify_pow_runner, ApiWork};
+    use crate::api::v1::pow::verify_token::{runners, VerifyCaptchaResultPayload};
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn cluster_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        cluster_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn cluster_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        cluster_works(data).await;
+    }
+
+    async fn instance(data: &ArcData, id: &str) -> AppData {
+        let mut settings = data.settings.clone();
+        settings.redis = None;
+        settings.cluster.enabled = true;
+        settings.cluster.instance_id = Some(id.into());
+        let data = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        AppData::new(data)
+    }
+
+    async fn visitors(data: &AppData, key: &str) -> u32 {
+        let sitekeys = data.captcha.get_internal_data().await.unwrap().unwrap();
+        sitekeys.get(key).unwrap().get_visitors()
+    }
+
+    async fn cluster_works(data: ArcData) {
+        const NAME: &str = "clusterusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "clusterusr@a.com";
+
+        delete_user(&data, NAME).await;
+        register_and_signin(&data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(&data, NAME, PASSWORD).await;
+        let key = token_key.key.clone();
+        let a = instance(&data, "cluster-test-a").await;
+        let b = instance(&data, "cluster-test-b").await;
+
+        // challenges issued by an instance are verified by the other, and its
+        // tokens validated by the first one, once
+        let config = get_config_runner(&a, &key).await.unwrap();
+        let (nonce, result) = solve(&config);
+        let work = ApiWork {
+            string: config.string.clone(),
+            result,
+            nonce,
+            key: key.clone(),
+            time: None,
+            worker_type: None,
+            correlation_id: None,
+        };
+        let token = verify_pow_runner(&b, work.clone(), "127.0.1.1".into())
+            .await
+            .unwrap();
+        assert!(verify_pow_runner(&a, work, "127.0.1.1".into())
+            .await
+            .is_err());
+        // along with the IP address they are bound to
+        let mut payload = VerifyCaptchaResultPayload {
+            secret: data.db.get_secret(NAME).await.unwrap().secret,
+            key: key.clone(),
+            token: token.token,
+            ip: Some("192.0.2.1".into()),
+        };
+        assert!(!runners::validate(&a, payload.clone()).await.unwrap());
+        payload.ip = Some("127.0.1.1".into());
+        assert!(runners::validate(&a, payload.clone()).await.unwrap());
+        assert!(!runners::validate(&b, payload).await.unwrap());
+
+        // fallback challenges started on an instance are completed on the other
+        let id = get_random(crate::fallback::CHALLENGE_ID_LEN);
+        let now = super::now();
+        crate::fallback::start(&a, &id, &key, 0, now).await.unwrap();
+        crate::fallback::complete(&b, &id, &key, now).await.unwrap();
+        assert_eq!(
+            crate::fallback::complete(&a, &id, &key, now).await.err(),
+            Some(ServiceError::FallbackNotFound)
+        );
+
+        // visitors counted by an instance are added to the other
+        for _ in 0..L1.visitor_threshold {
+            get_config_runner(&a, &key).await.unwrap();
+        }
+        get_config_runner(&b, &key).await.unwrap();
+        let now = super::now();
+        sync(&a, now).await.unwrap();
+        sync(&b, now).await.unwrap();
+        assert_eq!(visitors(&b, &key).await, L1.visitor_threshold + 2);
+        // and aren't reported back as counted by the other
+        sync(&a, now).await.unwrap();
+        assert_eq!(visitors(&a, &key).await, L1.visitor_threshold + 2);
+    }
+}
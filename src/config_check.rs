@@ -0,0 +1,407 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! `mcaptcha check-config`
+//!
+//! Loads the settings like the server does, without connecting to the
+//! database, and reports problems that would otherwise only show on startup
+//! or in production: invalid settings, weak secrets and inconsistent default
+//! difficulty strategies. With `--smtp`, the SMTP server is also connected
+//! to. Exits with status 1 if errors are found; warnings don't fail the check.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use config::{Config, File, FileFormat};
+use url::Url;
+
+use crate::data::Data;
+use crate::email::templates::{self, Template};
+use crate::settings::{MailTransport, Settings};
+
+/// usage of the subcommand
+pub const USAGE: &str = "Usage: mcaptcha check-config [--smtp]";
+
+/// configuration file shipped with mCaptcha, whose secrets are public
+const DEFAULT_CONFIG: &str = include_str!("../config/default.toml");
+
+/// shortest salt and cookie secret, in characters. Cookie keys are derived
+/// from at least 32 bytes
+const MIN_SECRET_LEN: usize = 32;
+/// estimated entropy below which secrets are reported as weak, in bits
+const MIN_SECRET_ENTROPY: f64 = 128.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// mCaptcha won't start or work correctly
+    Error,
+    /// mCaptcha works, but the setting is likely a mistake or insecure
+    Warning,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// problem found in a setting
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// setting that the problem was found in
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(key: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            key,
+            message: message.into(),
+        }
+    }
+
+    fn warning(key: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            key,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}: {}", self.key, self.message)
+    }
+}
+
+/// Shannon entropy of `secret`, estimated from its character frequencies
+fn entropy_bits(secret: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in secret.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = secret.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| -(n as f64) * (n as f64 / len).log2())
+        .sum()
+}
+
+fn check_secret(
+    key: &'static str,
+    secret: &str,
+    defaults: &Config,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let len = secret.chars().count();
+    if len < MIN_SECRET_LEN {
+        diagnostics.push(Diagnostic::error(
+            key,
+            format!("must be at least {MIN_SECRET_LEN} characters long, got {len}"),
+        ));
+    } else if entropy_bits(secret) < MIN_SECRET_ENTROPY {
+        diagnostics.push(Diagnostic::warning(
+            key,
+            "has low entropy; use a long, random value",
+        ));
+    }
+    if defaults.get_string(key).ok().as_deref() == Some(secret) {
+        diagnostics.push(Diagnostic::warning(
+            key,
+            "is the public value from config/default.toml; set a unique value",
+        ));
+    }
+}
+
+fn defaults() -> Config {
+    Config::builder()
+        .add_source(File::from_str(DEFAULT_CONFIG, FileFormat::Toml))
+        .build()
+        .expect("config/default.toml is valid")
+}
+
+/// problems in `settings` that [Settings::new] doesn't reject
+pub fn lint(settings: &Settings) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let defaults = defaults();
+
+    check_secret(
+        "server.cookie_secret",
+        &settings.server.cookie_secret,
+        &defaults,
+        &mut diagnostics,
+    );
+    check_secret(
+        "captcha.salt",
+        &settings.captcha.salt,
+        &defaults,
+        &mut diagnostics,
+    );
+
+    let url = settings.server.url();
+    match Url::parse(&url) {
+        Ok(url) if url.host_str().is_none() => diagnostics.push(Diagnostic::error(
+            "server.domain",
+            "must be a hostname, like example.com",
+        )),
+        Ok(url) => {
+            let local = matches!(url.host_str(), Some("localhost" | "127.0.0.1"));
+            if url.scheme() == "http" && !local {
+                diagnostics.push(Diagnostic::warning(
+                    "server.proxy_has_tls",
+                    format!("{url} is served over plain HTTP; set proxy_has_tls if a reverse proxy terminates TLS"),
+                ));
+            }
+        }
+        Err(e) => diagnostics.push(Diagnostic::error(
+            "server.domain",
+            format!("{url} is not a valid URL: {e}"),
+        )),
+    }
+
+    if let Some(smtp) = &settings.smtp {
+        for (i, relay) in smtp.all_relays().iter().enumerate() {
+            if smtp.transport == MailTransport::Smtp && relay.url.contains("://") {
+                let key = if i == 0 { "smtp.url" } else { "smtp.relays" };
+                diagnostics.push(Diagnostic::error(
+                    key,
+                    "must be the hostname of the SMTP server, without a scheme",
+                ));
+            }
+        }
+        if let Some(dir) = &smtp.templates_dir {
+            lint_templates(dir, &mut diagnostics);
+        }
+    }
+
+    let s = &settings.captcha.default_difficulty_strategy;
+    let difficulties = [
+        s.avg_traffic_difficulty,
+        s.peak_sustainable_traffic_difficulty,
+        s.broke_my_site_traffic_difficulty,
+    ];
+    if difficulties.windows(2).any(|w| w[0] >= w[1]) {
+        diagnostics.push(Diagnostic::error(
+            "captcha.default_difficulty_strategy",
+            "avg_traffic_difficulty, peak_sustainable_traffic_difficulty and broke_my_site_traffic_difficulty must increase; easy sitekeys can't be created otherwise",
+        ));
+    }
+    if let (Some(avg), Some(peak), Some(broke)) = (
+        s.avg_traffic_time,
+        s.peak_sustainable_traffic_time,
+        s.broke_my_site_traffic_time,
+    ) {
+        if avg > peak || peak > broke {
+            diagnostics.push(Diagnostic::warning(
+                "captcha.default_difficulty_strategy",
+                "avg_traffic_time, peak_sustainable_traffic_time and broke_my_site_traffic_time should increase",
+            ));
+        }
+    }
+    if s.duration == 0 {
+        diagnostics.push(Diagnostic::error(
+            "captcha.default_difficulty_strategy.duration",
+            "must be greater than 0",
+        ));
+    }
+
+    diagnostics
+}
+
+/// check email templates that override the embedded ones
+fn lint_templates(dir: &str, diagnostics: &mut Vec<Diagnostic>) {
+    const KEY: &str = "smtp.templates_dir";
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            diagnostics.push(Diagnostic::error(KEY, format!("can't read {dir}: {e}")));
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !Template::ALL.iter().any(|t| t.name() == name) {
+            let path = Path::new(dir).join(&name);
+            diagnostics.push(Diagnostic::warning(
+                KEY,
+                format!("{} doesn't override any email", path.display()),
+            ));
+        }
+    }
+    for (path, problem) in templates::check(dir) {
+        diagnostics.push(Diagnostic::error(KEY, format!("{path}: {problem}")));
+    }
+}
+
+/// connect to the mail relays, if emails are configured
+pub async fn check_smtp(settings: &Settings) -> Vec<Diagnostic> {
+    let mailer = match Data::get_mailer(settings) {
+        Some(mailer) => mailer,
+        None => return Vec::new(),
+    };
+    let is_smtp = settings
+        .smtp
+        .as_ref()
+        .map_or(true, |s| s.transport == MailTransport::Smtp);
+    let mut diagnostics = Vec::new();
+    for (i, (relay, result)) in mailer.test_connections().await.into_iter().enumerate() {
+        let (key, server) = match (is_smtp, i) {
+            (true, 0) => ("smtp.url", "SMTP server"),
+            (true, _) => ("smtp.relays", "SMTP server"),
+            (false, _) => ("smtp.transport", "mail transport"),
+        };
+        match result {
+            Ok(true) => (),
+            Ok(false) => diagnostics.push(Diagnostic::error(
+                key,
+                format!("{server} {relay} didn't accept the connection"),
+            )),
+            Err(e) => diagnostics.push(Diagnostic::error(
+                key,
+                format!("unable to connect to {server} {relay}: {e}"),
+            )),
+        }
+    }
+    diagnostics
+}
+
+/// run the subcommand with its arguments and return the exit status
+pub async fn run(args: &[String]) -> i32 {
+    let smtp = match args {
+        [] => false,
+        [flag] if flag == "--smtp" => true,
+        _ => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+
+    let settings = match Settings::new() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return 1;
+        }
+    };
+    let mut diagnostics = lint(&settings);
+    if smtp {
+        diagnostics.extend(check_smtp(&settings).await);
+    }
+
+    for d in diagnostics.iter() {
+        println!("{d}");
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    println!(
+        "{errors} error(s), {} warning(s)",
+        diagnostics.len() - errors
+    );
+    if errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has(diagnostics: &[Diagnostic], severity: Severity, key: &str) -> bool {
+        diagnostics
+            .iter()
+            .any(|d| d.severity == severity && d.key == key)
+    }
+
+    #[test]
+    fn entropy_works() {
+        assert_eq!(entropy_bits("aaaaaaaa"), 0.0);
+        assert_eq!(entropy_bits("abcd"), 8.0);
+    }
+
+    #[test]
+    fn lint_works() {
+        let mut settings = crate::tests::get_settings();
+        settings.server.cookie_secret = "a".repeat(MIN_SECRET_LEN);
+        settings.captcha.salt = "short".into();
+        settings.server.domain = "example.org".into();
+        settings.server.proxy_has_tls = false;
+        settings.server.tls = None;
+        let strategy = &mut settings.captcha.default_difficulty_strategy;
+        strategy.avg_traffic_difficulty = 50000;
+        strategy.peak_sustainable_traffic_difficulty = 3000000;
+        strategy.broke_my_site_traffic_difficulty = 5000000;
+        strategy.duration = 0;
+
+        let diagnostics = lint(&settings);
+        assert!(has(&diagnostics, Severity::Warning, "server.cookie_secret"));
+        assert!(has(&diagnostics, Severity::Error, "captcha.salt"));
+        assert!(has(&diagnostics, Severity::Warning, "server.proxy_has_tls"));
+        assert!(has(
+            &diagnostics,
+            Severity::Error,
+            "captcha.default_difficulty_strategy.duration"
+        ));
+        assert!(!has(
+            &diagnostics,
+            Severity::Error,
+            "captcha.default_difficulty_strategy"
+        ));
+
+        let strategy = &mut settings.captcha.default_difficulty_strategy;
+        strategy.peak_sustainable_traffic_difficulty = strategy.avg_traffic_difficulty;
+        assert!(has(
+            &lint(&settings),
+            Severity::Error,
+            "captcha.default_difficulty_strategy"
+        ));
+
+        let dir = std::env::temp_dir().join(format!(
+            "mcaptcha-check-config-{}",
+            crate::api::v1::mcaptcha::get_random(16)
+        ));
+        settings.smtp.as_mut().unwrap().templates_dir =
+            Some(dir.to_str().unwrap().into());
+        assert!(has(&lint(&settings), Severity::Error, "smtp.templates_dir"));
+        fs::create_dir_all(dir.join("verification")).unwrap();
+        fs::write(
+            dir.join("verification/index.txt"),
+            "{{ verification_link }}",
+        )
+        .unwrap();
+        assert!(!has(
+            &lint(&settings),
+            Severity::Error,
+            "smtp.templates_dir"
+        ));
+        fs::create_dir_all(dir.join("password_reset")).unwrap();
+        assert!(has(
+            &lint(&settings),
+            Severity::Warning,
+            "smtp.templates_dir"
+        ));
+        fs::write(dir.join("verification/index.txt"), "{{ username }}").unwrap();
+        assert!(has(&lint(&settings), Severity::Error, "smtp.templates_dir"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn default_secrets_are_reported() {
+        let settings = crate::tests::get_settings();
+        let diagnostics = lint(&settings);
+        if defaults().get_string("captcha.salt").unwrap() == settings.captcha.salt {
+            assert!(diagnostics
+                .iter()
+                .any(|d| d.key == "captcha.salt"
+                    && d.message.contains("config/default.toml")));
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Startup consistency check between the Redis master and the database
+//!
+//! Captchas are registered with the master lazily, when their configuration is
+//! first requested, and removed when they are deleted. A deletion that fails
+//! halfway leaves a stale captcha in Redis, which outlives restarts. On boot,
+//! captchas in Redis are compared against the database: captchas that no
+//! longer exist are removed and captchas that are missing are registered.
+//!
+//! The embedded master starts empty, so only the Redis master is checked.
+use std::collections::HashSet;
+
+use libmcaptcha::master::messages::RemoveCaptcha;
+use redis::AsyncCommands;
+
+use crate::api::v1::pow::get_config::init_mcaptcha;
+use crate::data::SystemGroup;
+use crate::errors::*;
+use crate::AppData;
+
+/// prefix of keys under which the mCaptcha Redis module stores captchas
+const CAPTCHA_KEY_PREFIX: &str = "mcaptcha_cache:captcha:";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// differences between the Redis master and the database
+pub struct DriftReport {
+    /// captchas in the database that weren't registered with the master
+    pub missing: Vec<String>,
+    /// captchas registered with the master that aren't in the database
+    pub orphaned: Vec<String>,
+    /// captchas that couldn't be reconciled
+    pub failed: Vec<String>,
+}
+
+impl DriftReport {
+    /// compare captchas registered with the master against the database
+    pub fn new(db: &[String], cached: &[String]) -> Self {
+        let db_keys: HashSet<&String> = db.iter().collect();
+        let cached_keys: HashSet<&String> = cached.iter().collect();
+        let mut missing: Vec<String> = db
+            .iter()
+            .filter(|k| !cached_keys.contains(k))
+            .cloned()
+            .collect();
+        let mut orphaned: Vec<String> = cached
+            .iter()
+            .filter(|k| !db_keys.contains(k))
+            .cloned()
+            .collect();
+        missing.sort();
+        missing.dedup();
+        orphaned.sort();
+        orphaned.dedup();
+        Self {
+            missing,
+            orphaned,
+            failed: Vec::default(),
+        }
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.orphaned.is_empty()
+    }
+
+    fn log(&self) {
+        if self.is_clean() {
+            log::info!("Redis master is consistent with the database");
+            return;
+        }
+        log::warn!(
+            "Redis master drifted from the database: {} missing, {} orphaned",
+            self.missing.len(),
+            self.orphaned.len()
+        );
+        let reconciled = |key: &&String| !self.failed.contains(key);
+        for key in self.missing.iter().filter(reconciled) {
+            log::warn!("Registered missing captcha {key}");
+        }
+        for key in self.orphaned.iter().filter(reconciled) {
+            log::warn!("Removed orphaned captcha {key}");
+        }
+        for key in self.failed.iter() {
+            log::error!("Unable to reconcile captcha {key}");
+        }
+    }
+}
+
+/// captcha key from the Redis key under which it is stored
+fn captcha_key(redis_key: &str) -> Option<String> {
+    let key = redis_key.strip_prefix(CAPTCHA_KEY_PREFIX)?;
+    // the module may wrap keys in cluster hash tags
+    let key = key.trim_start_matches('{').trim_end_matches('}');
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+/// keys of captchas registered with the Redis master
+pub async fn cached_captchas(url: &str) -> redis::RedisResult<Vec<String>> {
+    let client = redis::Client::open(url)?;
+    let mut con = client.get_async_connection().await?;
+    let mut iter = con
+        .scan_match::<_, String>(format!("{CAPTCHA_KEY_PREFIX}*"))
+        .await?;
+    let mut keys = Vec::default();
+    while let Some(key) = iter.next_item().await {
+        if let Some(key) = captcha_key(&key) {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+/// reconcile captchas registered with the Redis master with the database
+pub async fn reconcile(data: &AppData) -> ServiceResult<DriftReport> {
+    let redis = match (&data.captcha, &data.settings.redis) {
+        (SystemGroup::Redis(_), Some(redis)) => redis,
+        _ => return Ok(DriftReport::default()),
+    };
+
+    let cached = cached_captchas(&redis.connection_url())
+        .await
+        .map_err(|e| {
+            log::error!("Unable to list captchas in Redis: {e}");
+            ServiceError::InternalServerError
+        })?;
+    let db = data.db.get_all_captcha_keys().await?;
+
+    let mut report = DriftReport::new(&db, &cached);
+    for key in report.orphaned.iter() {
+        if data
+            .captcha
+            .remove(RemoveCaptcha(key.clone()))
+            .await
+            .is_err()
+        {
+            report.failed.push(key.clone());
+        }
+    }
+    for key in report.missing.iter() {
+        if init_mcaptcha(data, key).await.is_err() {
+            report.failed.push(key.clone());
+        }
+    }
+    report.log();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_report_works() {
+        let db = vec!["a".to_string(), "b".into(), "c".into()];
+        let cached = vec!["b".to_string(), "c".into(), "d".into()];
+        let report = DriftReport::new(&db, &cached);
+        assert_eq!(report.missing, vec!["a".to_string()]);
+        assert_eq!(report.orphaned, vec!["d".to_string()]);
+        assert!(!report.is_clean());
+        assert!(DriftReport::new(&db, &db).is_clean());
+    }
+
+    #[test]
+    fn captcha_key_works() {
+        assert_eq!(
+            captcha_key("mcaptcha_cache:captcha:abc"),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            captcha_key("mcaptcha_cache:captcha:{abc}"),
+            Some("abc".to_string())
+        );
+        assert_eq!(captcha_key("mcaptcha_cache:captcha:"), None);
+        assert_eq!(captcha_key("other:abc"), None);
+    }
+}
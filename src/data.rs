@@ -5,51 +5,82 @@
 
 //! App data: redis cache, database connections, etc.
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 
 use actix::prelude::*;
 use argon2_creds::{Config, ConfigBuilder, PasswordPolicy};
-use lettre::transport::smtp::authentication::Mechanism;
-use lettre::{
-    transport::smtp::authentication::Credentials, AsyncSmtpTransport, Tokio1Executor,
-};
 use libmcaptcha::cache::hashcache::HashCache;
 use libmcaptcha::cache::redis::RedisCache;
 use libmcaptcha::master::redis::master::Master as RedisMaster;
 use libmcaptcha::redis::RedisConfig;
 use libmcaptcha::{
-    cache::messages::VerifyCaptchaResult,
+    cache::messages::{CachePoW, CacheResult, VerifyCaptchaResult},
     cache::Save,
     errors::CaptchaResult,
-    master::messages::{AddSite, RemoveCaptcha, Rename},
+    master::messages::{
+        AddSite, GetInternalData, RemoveCaptcha, Rename, SetInternalData,
+    },
     master::{embedded::master::Master as EmbeddedMaster, Master as MasterTrait},
     pow::ConfigBuilder as PoWConfigBuilder,
     pow::PoWConfig,
     pow::Work,
     system::{System, SystemBuilder},
+    MCaptcha,
 };
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
+use tracing::Instrument;
 
+use crate::alerts::Alerts;
+use crate::bursts::BurstSchedule;
+use crate::client_ip::TrustedProxies;
+use crate::cluster::Cluster;
 use crate::db::{self, BoxDB};
-use crate::errors::ServiceResult;
-use crate::settings::Settings;
-use crate::stats::{Dummy, Real, Stats};
-use crate::survey::SecretsStore;
+use crate::decay::DifficultyDecayTracker;
+use crate::email::queue::MailQueue;
+use crate::email::relays::Mailer;
+use crate::errors::{ServiceError, ServiceResult};
+use crate::fallback::FallbackChallenges;
+use crate::fraud::FraudDetector;
+use crate::geoip::GeoIp;
+use crate::jobs::JobStatusStore;
+use crate::login_protection::FailedLogins;
+use crate::maintenance::PendingMaintenance;
+use crate::master_sync::MasterSync;
+use crate::memcached::{self, BindToken, GetTokenBinding, MemcachedCache};
+use crate::notification_stream::NotificationStream;
+use crate::oidc::OidcClient;
+use crate::overload::LoadShedder;
+use crate::priority::{Permit, PriorityGate};
+use crate::quotas::AnalyticsQuotas;
+use crate::rate_limit::RateLimits;
+use crate::settings::{CacheBackend, Settings};
+use crate::stats::{Buffered, Dummy, External, Real, Stats, StatsQueue};
+use crate::stats_sink::StatsSink;
+use crate::survey::{DownloadLimiter, NodeHealthStore, SecretsStore};
+use crate::tokens::TokenLedger;
+use crate::webhooks::WebhookQueue;
 use crate::AppData;
 
 macro_rules! enum_system_actor {
     ($name:ident, $type:ident) => {
         pub async fn $name(&self, msg: $type) -> ServiceResult<()> {
-            match self {
-                Self::Embedded(val) => val.master.send(msg).await?.await??,
-                Self::Redis(val) => val.master.send(msg).await?.await??,
-            };
-            Ok(())
+            let span = tracing::info_span!(concat!("captcha.", stringify!($name)));
+            async {
+                match self {
+                    Self::Embedded(val) => val.master.send(msg).await?.await??,
+                    Self::Redis(val) => val.master.send(msg).await?.await??,
+                    Self::Memcached(val) => val.master.send(msg).await?.await??,
+                };
+                Ok(())
+            }
+            .instrument(span)
+            .await
         }
     };
 }
@@ -57,20 +88,115 @@ macro_rules! enum_system_actor {
 macro_rules! enum_system_wrapper {
     ($name:ident, $type:ty, $return_type:ty) => {
         pub async fn $name(&self, msg: $type) -> $return_type {
-            match self {
-                Self::Embedded(val) => val.$name(msg).await,
-                Self::Redis(val) => val.$name(msg).await,
+            let span = tracing::info_span!(concat!("captcha.", stringify!($name)));
+            async {
+                match self {
+                    Self::Embedded(val) => val.get().$name(msg).await,
+                    Self::Redis(val) => val.get().$name(msg).await,
+                    Self::Memcached(val) => val.get().$name(msg).await,
+                }
             }
+            .instrument(span)
+            .await
         }
     };
 }
 
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// number of PoW runners and length of their verification queue
+pub struct PoWTuning {
+    pub runners: usize,
+    pub queue_length: usize,
+}
+
+impl PoWTuning {
+    /// tuning configured in `captcha.runners` and `captcha.queue_length`.
+    /// Runners default to the number of CPUs
+    pub fn new(s: &Settings) -> Self {
+        Self {
+            runners: s.captcha.runners.unwrap_or_else(num_cpus::get_physical),
+            queue_length: s.captcha.queue_length,
+        }
+    }
+
+    /// number of verifications that can be in-flight at once: one per runner
+    /// and those waiting in the queue
+    pub fn capacity(&self) -> usize {
+        self.runners + self.queue_length
+    }
+}
+
+/// mCaptcha [System] that can be retuned at runtime. Retuning rebuilds the
+/// system around the same master and cache, so that visitor counts and
+/// issued challenges are kept. Verifications in progress finish on the
+/// system they were started on
+pub struct TunableSystem<A: Save, B: MasterTrait> {
+    system: RwLock<Arc<System<A, B>>>,
+    tuning: RwLock<PoWTuning>,
+    /// admits one verification per runner into the system
+    gate: PriorityGate,
+    salt: String,
+    pub master: Addr<B>,
+    cache: Addr<A>,
+}
+
+impl<A: Save, B: MasterTrait> TunableSystem<A, B> {
+    fn new(s: &Settings, master: Addr<B>, cache: Addr<A>) -> Self {
+        let tuning = PoWTuning::new(s);
+        let salt = s.captcha.salt.clone();
+        let system = Self::build(&salt, tuning, master.clone(), cache.clone());
+        Self {
+            system: RwLock::new(Arc::new(system)),
+            tuning: RwLock::new(tuning),
+            gate: PriorityGate::new(tuning.runners),
+            salt,
+            master,
+            cache,
+        }
+    }
+
+    fn build(salt: &str, tuning: PoWTuning, m: Addr<B>, c: Addr<A>) -> System<A, B> {
+        let pow = PoWConfigBuilder::default()
+            .salt(salt.into())
+            .build()
+            .unwrap();
+
+        SystemBuilder::default()
+            .pow(pow)
+            .cache(c)
+            .master(m)
+            .runners(tuning.runners)
+            .queue_length(tuning.queue_length)
+            .build()
+    }
+
+    /// current system
+    pub fn get(&self) -> Arc<System<A, B>> {
+        self.system.read().unwrap().clone()
+    }
+
+    pub fn tuning(&self) -> PoWTuning {
+        *self.tuning.read().unwrap()
+    }
+
+    /// rebuild the system with `tuning`
+    fn tune(&self, tuning: PoWTuning) {
+        let system =
+            Self::build(&self.salt, tuning, self.master.clone(), self.cache.clone());
+        *self.system.write().unwrap() = Arc::new(system);
+        *self.tuning.write().unwrap() = tuning;
+        self.gate.set_limit(tuning.runners);
+    }
+}
+
 /// Represents mCaptcha cache and master system.
 /// When Redis is configured, [SystemGroup::Redis] is used and
-/// in its absence, [SystemGroup::Embedded] is used
+/// in its absence, [SystemGroup::Embedded] is used. [SystemGroup::Memcached]
+/// is used when `cache.backend` is `memcached`
 pub enum SystemGroup {
-    Embedded(System<HashCache, EmbeddedMaster>),
-    Redis(System<RedisCache, RedisMaster>),
+    Embedded(TunableSystem<HashCache, EmbeddedMaster>),
+    Redis(TunableSystem<RedisCache, RedisMaster>),
+    Memcached(TunableSystem<MemcachedCache, EmbeddedMaster>),
 }
 
 #[allow(unused_doc_comments)]
@@ -80,18 +206,48 @@ impl SystemGroup {
     // utility function to get difficulty factor of site `id` and cache it
     enum_system_wrapper!(get_pow, String, CaptchaResult<Option<PoWConfig>>);
 
-    // utility function to verify [Work]
+    // utility function to verify [Work], once admitted by priority
+    #[tracing::instrument(name = "captcha.verify_pow", skip_all)]
     pub async fn verify_pow(
         &self,
         msg: Work,
         ip: String,
+        high_priority: bool,
     ) -> CaptchaResult<(String, u32)> {
+        let _permit = self.schedule(high_priority).await;
         match self {
-            Self::Embedded(val) => val.verify_pow(msg, ip).await,
-            Self::Redis(val) => val.verify_pow(msg, ip).await,
+            Self::Embedded(val) => val.get().verify_pow(msg, ip).await,
+            Self::Redis(val) => val.get().verify_pow(msg, ip).await,
+            Self::Memcached(val) => val.get().verify_pow(msg, ip).await,
         }
     }
 
+    /// wait until a PoW verification can be admitted to the runners. High
+    /// priority verifications are admitted ahead of others. See
+    /// [crate::priority]
+    pub async fn schedule(&self, high_priority: bool) -> Permit<'_> {
+        self.gate().acquire(high_priority).await
+    }
+
+    fn gate(&self) -> &PriorityGate {
+        match self {
+            Self::Embedded(val) => &val.gate,
+            Self::Redis(val) => &val.gate,
+            Self::Memcached(val) => &val.gate,
+        }
+    }
+
+    /// are all runners busy, so that verifications are admitted by priority
+    pub fn contended(&self) -> bool {
+        self.gate().contended()
+    }
+
+    /// verifications of high priority sitekeys and others waiting to be
+    /// admitted to the runners
+    pub fn waiting(&self) -> (usize, usize) {
+        self.gate().waiting()
+    }
+
     // utility function to validate verification tokens
     enum_system_wrapper!(
         validate_verification_tokens,
@@ -99,6 +255,51 @@ impl SystemGroup {
         CaptchaResult<bool>
     );
 
+    /// cache challenge `msg.string` again with `msg.difficulty_factor`. Used
+    /// when challenges are served at a higher difficulty than that of the
+    /// defense, which they were cached with, so that they are verified
+    /// against the difficulty that they were served at
+    pub async fn cache_pow(&self, msg: CachePoW) -> ServiceResult<()> {
+        match self {
+            Self::Embedded(val) => val.cache.send(msg).await?.await??,
+            Self::Redis(val) => val.cache.send(msg).await?.await??,
+            Self::Memcached(val) => val.cache.send(msg).await?.await??,
+        };
+        Ok(())
+    }
+
+    /// cache a validation token that wasn't issued for solved PoW, like those
+    /// of [fallback challenges](crate::fallback)
+    pub async fn cache_result(&self, msg: CacheResult) -> ServiceResult<()> {
+        match self {
+            Self::Embedded(val) => val.cache.send(msg).await?.await??,
+            Self::Redis(val) => val.cache.send(msg).await?.await??,
+            Self::Memcached(val) => val.cache.send(msg).await?.await??,
+        };
+        Ok(())
+    }
+
+    /// record the IP address that a validation token is bound to next to the
+    /// token, when the cache is shared with other instances. See
+    /// [crate::tokens]
+    pub async fn bind_token(&self, msg: BindToken) -> ServiceResult<()> {
+        if let Self::Memcached(val) = self {
+            val.cache.send(msg).await??;
+        }
+        Ok(())
+    }
+
+    /// hash of the IP address that a validation token is bound to, as
+    /// recorded by [Self::bind_token]
+    pub async fn token_binding(&self, token: &str) -> ServiceResult<Option<String>> {
+        match self {
+            Self::Memcached(val) => {
+                Ok(val.cache.send(GetTokenBinding(token.into())).await??)
+            }
+            _ => Ok(None),
+        }
+    }
+
     // utility function to AddSite
     enum_system_actor!(add_site, AddSite);
 
@@ -108,51 +309,108 @@ impl SystemGroup {
     // utility function to remove captcha
     enum_system_actor!(remove, RemoveCaptcha);
 
-    fn new_system<A: Save, B: MasterTrait>(
-        s: &Settings,
-        m: Addr<B>,
-        c: Addr<A>,
-    ) -> System<A, B> {
-        let pow = PoWConfigBuilder::default()
-            .salt(s.captcha.salt.clone())
-            .build()
-            .unwrap();
+    /// visitor counts and difficulty state of sitekeys in the embedded master.
+    /// Returns `None` with Redis, which keeps them itself
+    pub async fn get_internal_data(
+        &self,
+    ) -> ServiceResult<Option<HashMap<String, MCaptcha>>> {
+        match self {
+            Self::Embedded(val) => {
+                let data = val.master.send(GetInternalData).await?.await??;
+                Ok(Some(data))
+            }
+            Self::Memcached(val) => {
+                let data = val.master.send(GetInternalData).await?.await??;
+                Ok(Some(data))
+            }
+            Self::Redis(_) => Ok(None),
+        }
+    }
 
-        let runners = if let Some(runners) = s.captcha.runners {
-            runners
-        } else {
-            num_cpus::get_physical()
-        };
-        SystemBuilder::default()
-            .pow(pow)
-            .cache(c)
-            .master(m)
-            .runners(runners)
-            .queue_length(s.captcha.queue_length)
-            .build()
+    /// load visitor counts and difficulty state of sitekeys, saved with
+    /// [SystemGroup::get_internal_data], into the embedded master. Does
+    /// nothing with Redis
+    pub async fn set_internal_data(
+        &self,
+        mcaptcha: HashMap<String, MCaptcha>,
+    ) -> ServiceResult<()> {
+        match self {
+            Self::Embedded(val) => {
+                val.master
+                    .send(SetInternalData { mcaptcha })
+                    .await?
+                    .await??;
+            }
+            Self::Memcached(val) => {
+                val.master
+                    .send(SetInternalData { mcaptcha })
+                    .await?
+                    .await??;
+            }
+            Self::Redis(_) => (),
+        }
+        Ok(())
+    }
+
+    /// current number of PoW runners and length of their queue
+    pub fn tuning(&self) -> PoWTuning {
+        match self {
+            Self::Embedded(val) => val.tuning(),
+            Self::Redis(val) => val.tuning(),
+            Self::Memcached(val) => val.tuning(),
+        }
+    }
+
+    /// change the number of PoW runners and the length of their queue
+    pub fn tune(&self, tuning: PoWTuning) -> ServiceResult<()> {
+        if tuning.runners == 0 || tuning.queue_length == 0 {
+            return Err(ServiceError::InvalidPoWTuning);
+        }
+        if tuning == self.tuning() {
+            return Ok(());
+        }
+        match self {
+            Self::Embedded(val) => val.tune(tuning),
+            Self::Redis(val) => val.tune(tuning),
+            Self::Memcached(val) => val.tune(tuning),
+        }
+        log::info!(
+            "Retuned PoW verification: {} runners, queue length {}",
+            tuning.runners,
+            tuning.queue_length
+        );
+        Ok(())
     }
 
     // read settings, if Redis is configured then produce a Redis mCaptcha cache
     // based SystemGroup
     async fn new(s: &Settings) -> Self {
+        if s.cache.backend == CacheBackend::Memcached {
+            let client = memcached::client(s).expect("unable to connect to memcached");
+            let master = EmbeddedMaster::new(s.captcha.gc).start();
+            let cache = MemcachedCache::start(client, s.cache.memcached_pool as usize);
+            let captcha = TunableSystem::new(s, master, cache);
+
+            return SystemGroup::Memcached(captcha);
+        }
         match &s.redis {
             Some(val) => {
-                let master = RedisMaster::new(RedisConfig::Single(val.url.clone()))
+                let master = RedisMaster::new(RedisConfig::Single(val.connection_url()))
                     .await
                     .unwrap()
                     .start();
-                let cache = RedisCache::new(RedisConfig::Single(val.url.clone()))
+                let cache = RedisCache::new(RedisConfig::Single(val.connection_url()))
                     .await
                     .unwrap()
                     .start();
-                let captcha = Self::new_system(s, master, cache);
+                let captcha = TunableSystem::new(s, master, cache);
 
                 SystemGroup::Redis(captcha)
             }
             None => {
                 let master = EmbeddedMaster::new(s.captcha.gc).start();
                 let cache = HashCache::default().start();
-                let captcha = Self::new_system(s, master, cache);
+                let captcha = TunableSystem::new(s, master, cache);
 
                 SystemGroup::Embedded(captcha)
             }
@@ -170,12 +428,61 @@ pub struct Data {
     pub captcha: SystemGroup,
     /// email client
     pub mailer: Option<Mailer>,
+    /// outgoing email throttling
+    pub mail_queue: MailQueue,
     /// app settings
     pub settings: Settings,
     /// stats recorder
     pub stats: Box<dyn Stats>,
+    /// stats entries awaiting flush
+    pub stats_queue: StatsQueue,
     /// survey secret store
     pub survey_secrets: SecretsStore,
+    /// health of survey nodes
+    pub survey_health: NodeHealthStore,
+    /// downloads of published benchmarks
+    pub benchmark_downloads: DownloadLimiter,
+    /// background job status
+    pub jobs: JobStatusStore,
+    /// issued validation tokens
+    pub tokens: TokenLedger,
+    /// pending fallback challenges
+    pub fallbacks: FallbackChallenges,
+    /// whether sitekeys record analytics
+    pub analytics_quotas: AnalyticsQuotas,
+    /// deletions awaiting database maintenance
+    pub maintenance: PendingMaintenance,
+    /// webhook events awaiting delivery
+    pub webhooks: WebhookQueue,
+    /// notifications for open notification streams
+    pub notification_stream: NotificationStream,
+    /// fraud heuristics state
+    pub fraud: FraudDetector,
+    /// state of automatic notifications
+    pub alerts: Alerts,
+    /// overload detection and load shedding
+    pub load: LoadShedder,
+    /// scheduled traffic bursts
+    pub bursts: BurstSchedule,
+    /// difficulty decay after sustained attacks
+    pub decay: DifficultyDecayTracker,
+    /// OpenID Connect client, when single sign-on is configured
+    pub oidc: Option<OidcClient>,
+    /// country database, when countries of solvers are recorded
+    pub geoip: Option<GeoIp>,
+    /// time-series store that stats are written to instead of the database
+    pub stats_sink: Option<Box<dyn StatsSink>>,
+    /// failed sign-in attempts and issued sign-in challenges
+    pub failed_logins: FailedLogins,
+    /// request counts of rate limits
+    pub rate_limits: RateLimits,
+    /// reverse proxies whose forwarded headers are read
+    pub trusted_proxies: TrustedProxies,
+    /// serializes registration of sitekeys with the master, so that a
+    /// registration from stale configuration can't overwrite a refresh
+    pub master_sync: MasterSync,
+    /// cluster membership, when instances share state through the database
+    pub cluster: Option<Cluster>,
 }
 
 impl Data {
@@ -206,10 +513,14 @@ impl Data {
             crate::settings::DBType::Postgres => db::pg::get_data(Some(s.clone())).await,
         };
 
-        let stats: Box<dyn Stats> = if s.captcha.enable_stats {
-            Box::<Real>::default()
-        } else {
+        let stats: Box<dyn Stats> = if !s.captcha.enable_stats {
             Box::<Dummy>::default()
+        } else if s.stats_sink.is_some() {
+            Box::<External>::default()
+        } else if s.captcha.stats_buffer_size > 0 {
+            Box::<Buffered>::default()
+        } else {
+            Box::<Real>::default()
         };
 
         let data = Data {
@@ -217,9 +528,38 @@ impl Data {
             db,
             captcha: SystemGroup::new(s).await,
             mailer: Self::get_mailer(s),
+            mail_queue: MailQueue::new(s.smtp.as_ref()),
             settings: s.clone(),
             stats,
+            stats_queue: StatsQueue::new(s.captcha.stats_buffer_size),
             survey_secrets,
+            survey_health: NodeHealthStore::default(),
+            benchmark_downloads: DownloadLimiter::default(),
+            jobs: JobStatusStore::default(),
+            tokens: TokenLedger::new(&s.server.cookie_secret),
+            fallbacks: FallbackChallenges::default(),
+            analytics_quotas: AnalyticsQuotas::default(),
+            maintenance: PendingMaintenance::default(),
+            webhooks: WebhookQueue::default(),
+            notification_stream: NotificationStream::default(),
+            fraud: FraudDetector::default(),
+            alerts: Alerts::default(),
+            load: LoadShedder::new(&s.load_shedding),
+            bursts: BurstSchedule::default(),
+            decay: DifficultyDecayTracker::default(),
+            oidc: OidcClient::new(s),
+            geoip: GeoIp::new(s),
+            stats_sink: if s.captcha.enable_stats {
+                crate::stats_sink::connect(s).await
+            } else {
+                None
+            },
+            failed_logins: FailedLogins::new(s),
+            rate_limits: RateLimits::new(s),
+            trusted_proxies: TrustedProxies::new(&s.server.trusted_proxies)
+                .expect("server.trusted_proxies is checked by Settings::new"),
+            master_sync: MasterSync::default(),
+            cluster: Cluster::new(s),
         };
 
         #[cfg(not(debug_assertions))]
@@ -228,39 +568,251 @@ impl Data {
         Arc::new(data)
     }
 
-    fn get_mailer(s: &Settings) -> Option<Mailer> {
-        if let Some(smtp) = s.smtp.as_ref() {
-            let creds =
-                Credentials::new(smtp.username.to_string(), smtp.password.to_string()); // "smtp_username".to_string(), "smtp_password".to_string());
-
-            let mailer: Mailer =
-                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&smtp.url)
-                    .port(smtp.port)
-                    .credentials(creds)
-                    .authentication(vec![
-                        Mechanism::Login,
-                        Mechanism::Xoauth2,
-                        Mechanism::Plain,
-                    ])
-                    .build();
-
-            //            let mailer: Mailer = AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.url) //"smtp.gmail.com")
-            //                .unwrap()
-            //                .credentials(creds)
-            //                .build();
-            Some(mailer)
-        } else {
-            None
+    /// mail transport, if emails are enabled and `[smtp]` is configured
+    pub fn get_mailer(s: &Settings) -> Option<Mailer> {
+        if !s.features.email {
+            return None;
         }
+        s.smtp.as_ref().map(Mailer::new)
     }
 
-    async fn upload_survey_job(&self) -> ServiceResult<()> {
-        unimplemented!()
+    /// register with the configured mCaptcha/survey nodes that this instance
+    /// doesn't have a secret of. Nodes upload their secrets to
+    /// [V1_API_ROUTES.survey.secret](crate::V1_API_ROUTES), authenticating with
+    /// the auth token sent here; nodes that don't answer are registered with
+    /// again once their backoff expires
+    pub async fn register_survey(&self, client: &Client) -> ServiceResult<()> {
+        let survey = match self.settings.survey.as_ref() {
+            Some(survey) => survey,
+            None => return Ok(()),
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        for node in survey.nodes.iter() {
+            let node_url = node.url.as_str();
+            if self.survey_secrets.get(node_url).is_some()
+                || !self.survey_health.is_available(node_url, now)
+            {
+                continue;
+            }
+            // SecretsStore will store auth tokens generated by both mCaptcha/mCaptcha and
+            // mCaptcha/survey
+            //
+            // Storage schema:
+            // - mCaptcha/mCaptcha generated auth token: (<auth_token>, <survey_instance_url>)
+            // - mCaptcha/survey generated auth token (<survey_instance_url>, <auth_token)
+            let auth_token = crate::api::v1::mcaptcha::get_random(20);
+            if let Some(stale) = self
+                .survey_secrets
+                .set_pending(node_url, auth_token.clone())
+            {
+                self.survey_secrets.rm(&stale);
+            }
+            self.survey_secrets
+                .set(auth_token.clone(), node_url.to_owned());
+
+            let payload = crate::survey::Registration {
+                url: survey.instance_root_url.clone(),
+                auth_token,
+            };
+            let mut url = node.url.clone();
+            url.set_path("/mcaptcha/api/v1/register");
+            match client.post(url).json(&payload).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    log::info!("Registered with survey instance {node_url}");
+                    self.survey_health.reached(node_url);
+                }
+                Ok(resp) => {
+                    let error = format!("registration rejected: {}", resp.status());
+                    log::warn!("Survey instance {node_url} {error}");
+                    self.survey_health.failed(node_url, now, error, true);
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Unable to register with survey instance {node_url}: {e}"
+                    );
+                    self.survey_health
+                        .failed(node_url, now, e.to_string(), false);
+                }
+            }
+        }
+        Ok(())
     }
-    async fn register_survey(&self) -> ServiceResult<()> {
-        unimplemented!()
+
+    /// upload analytics of published campaigns to the mCaptcha/survey nodes
+    /// that may process them, picking up after the last record that each node
+    /// received. Nodes that fail are skipped until their backoff expires
+    pub async fn upload_survey_job(&self, client: &Client) -> ServiceResult<()> {
+        let survey = match self.settings.survey.as_ref() {
+            Some(survey) => survey,
+            None => return Ok(()),
+        };
+        let mut page = 0;
+        loop {
+            let psuedo_ids = self.db.analytics_get_all_psuedo_ids(page).await?;
+            if psuedo_ids.is_empty() {
+                log::debug!("upload job complete, no more IDs to upload");
+                break;
+            }
+            for id in psuedo_ids {
+                let key = self
+                    .db
+                    .analytics_get_capmaign_id_from_psuedo_id(&id)
+                    .await?;
+                let records = self.db.analytics_count(&key).await?;
+                if !survey.anonymization.allows(records) {
+                    log::debug!("Campaign {id} has too few records to upload");
+                    continue;
+                }
+                let owner = self.db.get_captcha_owner(&key).await?;
+                let residency = self.db.get_residency(&owner).await?;
+                for node in survey.nodes_for(residency.as_deref()) {
+                    self.upload_campaign(
+                        client,
+                        &node.url,
+                        &id,
+                        &key,
+                        &survey.anonymization,
+                    )
+                    .await?;
+                }
+            }
+            page += 1;
+        }
+        Ok(())
     }
-}
 
-/// Mailer data type AsyncSmtpTransport<Tokio1Executor>
-pub type Mailer = AsyncSmtpTransport<Tokio1Executor>;
+    /// ask the mCaptcha/survey nodes that this instance has a secret of to
+    /// delete the analytics uploaded of campaign `psuedo_id`. Returns the
+    /// nodes that did and the ones that couldn't be asked or failed
+    pub async fn purge_survey_campaign(
+        &self,
+        client: &Client,
+        psuedo_id: &str,
+    ) -> ServiceResult<(Vec<String>, Vec<String>)> {
+        let (mut purged, mut failed) = (Vec::new(), Vec::new());
+        let survey = match self.settings.survey.as_ref() {
+            Some(survey) => survey,
+            None => return Ok((purged, failed)),
+        };
+        for node in survey.nodes.iter() {
+            let node_url = node.url.to_string();
+            let secret = match self.survey_secrets.get(&node_url) {
+                Some(secret) => secret,
+                None => {
+                    failed.push(node_url);
+                    continue;
+                }
+            };
+            let mut url = node.url.clone();
+            url.set_path(&format!("/mcaptcha/api/v1/{psuedo_id}/purge"));
+            let resp = client
+                .post(url)
+                .header(
+                    crate::webhooks::SIGNATURE_HEADER,
+                    crate::webhooks::sign(&secret, &[]),
+                )
+                .send()
+                .await;
+            match resp {
+                Ok(resp) if resp.status().is_success() => {
+                    self.survey_health.reached(&node_url);
+                    purged.push(node_url);
+                }
+                Ok(resp) => {
+                    log::warn!(
+                        "Survey instance {node_url} rejected purge: {}",
+                        resp.status()
+                    );
+                    failed.push(node_url);
+                }
+                Err(e) => {
+                    log::warn!("Unable to purge from survey instance {node_url}: {e}");
+                    failed.push(node_url);
+                }
+            }
+        }
+        Ok((purged, failed))
+    }
+
+    /// upload analytics of campaign `psuedo_id` that `node` hasn't received,
+    /// in batches anonymized with `anonymization` and signed with the secret of
+    /// the node. Nodes that reject their secret are registered with again
+    async fn upload_campaign(
+        &self,
+        client: &Client,
+        node: &url::Url,
+        psuedo_id: &str,
+        key: &str,
+        anonymization: &crate::settings::Anonymization,
+    ) -> ServiceResult<()> {
+        let node_url = node.as_str();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if !self.survey_health.is_available(node_url, now) {
+            return Ok(());
+        }
+        let secret = match self.survey_secrets.get(node_url) {
+            Some(secret) => secret,
+            None => return Ok(()),
+        };
+        let mut url = node.clone();
+        url.set_path(&format!("/mcaptcha/api/v1/{psuedo_id}/upload"));
+
+        loop {
+            let after = self.survey_secrets.cursor(node_url, psuedo_id);
+            let analytics = self
+                .db
+                .analytics_fetch_after(key, after, crate::survey::UPLOAD_BATCH)
+                .await?;
+            let last = match analytics.last() {
+                Some(last) => last.id,
+                None => return Ok(()),
+            };
+            let done = analytics.len() < crate::survey::UPLOAD_BATCH;
+            let mut records: Vec<crate::survey::UploadRecord> =
+                analytics.into_iter().map(|a| a.into()).collect();
+            anonymization.apply(&mut records);
+            let upload = crate::survey::Upload { analytics: records };
+            let body = serde_json::to_vec(&upload).unwrap();
+
+            log::info!("Uploading to survey instance {node_url} campaign {psuedo_id}");
+            let resp = client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(
+                    crate::webhooks::SIGNATURE_HEADER,
+                    crate::webhooks::sign(&secret, &body),
+                )
+                .body(body)
+                .send()
+                .await;
+            let status = match resp {
+                Ok(resp) => resp.status(),
+                Err(e) => {
+                    log::warn!("Unable to upload to survey instance {node_url}: {e}");
+                    self.survey_health
+                        .failed(node_url, now, e.to_string(), false);
+                    return Ok(());
+                }
+            };
+            if status == reqwest::StatusCode::UNAUTHORIZED
+                || status == reqwest::StatusCode::FORBIDDEN
+            {
+                log::warn!("Survey instance {node_url} rejected its secret");
+                self.survey_secrets.rm(node_url);
+                self.survey_health.reached(node_url);
+                return Ok(());
+            }
+            if !status.is_success() {
+                let error = format!("upload rejected: {status}");
+                log::warn!("Survey instance {node_url} {error}");
+                self.survey_health.failed(node_url, now, error, true);
+                return Ok(());
+            }
+            self.survey_health.uploaded(node_url, now);
+            self.survey_secrets.set_cursor(node_url, psuedo_id, last);
+            if done {
+                return Ok(());
+            }
+        }
+    }
+}
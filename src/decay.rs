@@ -0,0 +1,301 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Gradual difficulty decay
+//!
+//! The defense serves difficulty by visitor count, so difficulty drops as soon
+//! as an attack pauses and climbs back only after visitor thresholds are
+//! crossed again. Owners can set a decay for a sitekey: once difficulty stayed
+//! raised for `sustain` seconds, it doesn't drop instantly when visitor counts
+//! do. Instead, it steps down from its peak every `step` seconds, and falls to
+//! the difficulty of the visitor count after `steps` steps:
+//!
+//! - `linear`: difficulty falls by the same amount each step
+//! - `exponential`: difficulty halves each step
+//!
+//! Difficulty never decays below that of the visitor count.
+//!
+//! Each step down is recorded in the sitekey's escalation log, alongside
+//! escalations by visitor count. Decays are cached for a minute, and decay
+//! state is kept by the instance that serves challenges.
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use db_core::errors::DBError;
+use db_core::{DecayCurve, DifficultyDecay, EscalationKind, EscalationLogEntry};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::errors::*;
+use crate::AppData;
+
+/// duration for which decays are cached
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// decay state of a sitekey
+#[derive(Clone, Debug)]
+struct DecayState {
+    /// highest difficulty factor since difficulty was last at rest
+    peak: u32,
+    /// when difficulty rose from rest
+    raised_at: Instant,
+    /// when difficulty was last at its peak
+    last_high: Instant,
+    /// difficulty wasn't raised after it last dropped
+    resting: bool,
+    /// last difficulty factor that was served
+    served: u32,
+}
+
+impl DecayState {
+    fn rest(difficulty_factor: u32, now: Instant) -> Self {
+        Self {
+            peak: difficulty_factor,
+            raised_at: now,
+            last_high: now,
+            resting: true,
+            served: difficulty_factor,
+        }
+    }
+}
+
+/// difficulty factor that decay holds difficulty at, `elapsed` after
+/// difficulty was last at `peak`. None once difficulty decayed
+fn floor(decay: &DifficultyDecay, peak: u32, elapsed: Duration) -> Option<u32> {
+    let step = elapsed.as_secs() / decay.step.max(1) as u64;
+    let steps = decay.steps as u64;
+    if step >= steps {
+        return None;
+    }
+    let floor = match decay.curve {
+        DecayCurve::Linear => peak as u64 * (steps - step) / steps,
+        DecayCurve::Exponential => (peak as u64).checked_shr(step as u32).unwrap_or(0),
+    };
+    Some(floor as u32)
+}
+
+/// Decays of sitekeys and their state
+#[derive(Debug, Default)]
+pub struct DifficultyDecayTracker {
+    decays: RwLock<HashMap<String, (Instant, Option<DifficultyDecay>)>>,
+    state: RwLock<HashMap<String, DecayState>>,
+}
+
+impl DifficultyDecayTracker {
+    /// decay of a sitekey, if it's enabled
+    async fn decay(
+        &self,
+        data: &AppData,
+        key: &str,
+    ) -> ServiceResult<Option<DifficultyDecay>> {
+        if let Some((cached_at, decay)) = self.decays.read().unwrap().get(key) {
+            if cached_at.elapsed() < CACHE_TTL {
+                return Ok(decay.clone());
+            }
+        }
+        let decay = match data.db.get_difficulty_decay(key).await {
+            Ok(d) => Some(d),
+            Err(DBError::DifficultyDecayNotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut w = self.decays.write().unwrap();
+        w.insert(key.to_string(), (Instant::now(), decay.clone()));
+        Ok(decay)
+    }
+
+    /// forget decay and decay state of a sitekey, after its decay was changed
+    pub fn invalidate(&self, key: &str) {
+        self.decays.write().unwrap().remove(key);
+        self.state.write().unwrap().remove(key);
+    }
+
+    /// record difficulty factor of the defense of a sitekey at `now` and get
+    /// the difficulty factor to serve, and the decay step it falls on, if
+    /// difficulty stepped down
+    fn observe(
+        &self,
+        key: &str,
+        decay: &DifficultyDecay,
+        difficulty_factor: u32,
+        now: Instant,
+    ) -> (u32, Option<u32>) {
+        let mut w = self.state.write().unwrap();
+        let state = match w.get_mut(key) {
+            Some(state) => state,
+            None => {
+                w.insert(key.to_string(), DecayState::rest(difficulty_factor, now));
+                return (difficulty_factor, None);
+            }
+        };
+
+        if difficulty_factor >= state.peak {
+            if difficulty_factor > state.peak && state.resting {
+                state.raised_at = now;
+                state.resting = false;
+            }
+            state.peak = difficulty_factor;
+            state.last_high = now;
+            state.served = difficulty_factor;
+            return (difficulty_factor, None);
+        }
+
+        let sustained = state.last_high.duration_since(state.raised_at)
+            >= Duration::from_secs(decay.sustain as u64);
+        if state.resting || !sustained {
+            *state = DecayState::rest(difficulty_factor, now);
+            return (difficulty_factor, None);
+        }
+
+        let previous = state.served;
+        let served = match floor(decay, state.peak, now.duration_since(state.last_high))
+        {
+            Some(floor) => {
+                state.served = floor.max(difficulty_factor);
+                state.served
+            }
+            None => {
+                *state = DecayState::rest(difficulty_factor, now);
+                difficulty_factor
+            }
+        };
+        let step = if served < previous {
+            Some(served)
+        } else {
+            None
+        };
+        (served, step)
+    }
+
+    /// difficulty factor to serve for a sitekey whose defense is at
+    /// `difficulty_factor`. Steps down are recorded in the escalation log
+    pub async fn difficulty(
+        &self,
+        data: &AppData,
+        key: &str,
+        difficulty_factor: u32,
+    ) -> ServiceResult<u32> {
+        let decay = match self.decay(data, key).await? {
+            Some(decay) => decay,
+            None => return Ok(difficulty_factor),
+        };
+        let (difficulty_factor, step) =
+            self.observe(key, &decay, difficulty_factor, Instant::now());
+        if let Some(step) = step {
+            record(data, key, EscalationKind::Decay, step).await;
+        }
+        Ok(difficulty_factor)
+    }
+}
+
+/// record a change of difficulty in the escalation log of a sitekey. Failures
+/// are logged, so that recording doesn't fail challenges
+pub async fn record(
+    data: &AppData,
+    key: &str,
+    kind: EscalationKind,
+    difficulty_factor: u32,
+) {
+    let entry = EscalationLogEntry {
+        kind,
+        difficulty_factor,
+        time: OffsetDateTime::now_utc().unix_timestamp(),
+    };
+    if let Err(e) = data.db.add_escalation_log_entry(key, &entry).await {
+        log::error!("Unable to record escalation of sitekey {key}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decay(curve: DecayCurve) -> DifficultyDecay {
+        DifficultyDecay {
+            sustain: 300,
+            step: 60,
+            steps: 4,
+            curve,
+        }
+    }
+
+    #[test]
+    fn floor_works() {
+        let secs = Duration::from_secs;
+        let linear = decay(DecayCurve::Linear);
+        assert_eq!(floor(&linear, 500, secs(0)), Some(500));
+        assert_eq!(floor(&linear, 500, secs(59)), Some(500));
+        assert_eq!(floor(&linear, 500, secs(60)), Some(375));
+        assert_eq!(floor(&linear, 500, secs(180)), Some(125));
+        assert_eq!(floor(&linear, 500, secs(240)), None);
+
+        let exponential = decay(DecayCurve::Exponential);
+        assert_eq!(floor(&exponential, 500, secs(60)), Some(250));
+        assert_eq!(floor(&exponential, 500, secs(120)), Some(125));
+        assert_eq!(floor(&exponential, 500, secs(180)), Some(62));
+        assert_eq!(floor(&exponential, 500, secs(240)), None);
+    }
+
+    #[test]
+    fn decay_works() {
+        let secs = Duration::from_secs;
+        let tracker = DifficultyDecayTracker::default();
+        let decay = decay(DecayCurve::Linear);
+        let now = Instant::now();
+
+        // short surges drop instantly
+        assert_eq!(tracker.observe("key", &decay, 100, now), (100, None));
+        assert_eq!(tracker.observe("key", &decay, 500, now), (500, None));
+        assert_eq!(
+            tracker.observe("key", &decay, 100, now + secs(10)),
+            (100, None)
+        );
+
+        // sustained ones step down
+        let start = now + secs(20);
+        assert_eq!(tracker.observe("key", &decay, 500, start), (500, None));
+        assert_eq!(
+            tracker.observe("key", &decay, 500, start + secs(300)),
+            (500, None)
+        );
+        let high = start + secs(300);
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(30)),
+            (500, None)
+        );
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(60)),
+            (375, Some(375))
+        );
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(90)),
+            (375, None)
+        );
+        // visitor counts above the floor are served as is
+        assert_eq!(
+            tracker.observe("key", &decay, 450, high + secs(100)),
+            (450, None)
+        );
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(120)),
+            (250, Some(250))
+        );
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(180)),
+            (125, Some(125))
+        );
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(240)),
+            (100, Some(100))
+        );
+        // and rest after they decayed
+        assert_eq!(
+            tracker.observe("key", &decay, 100, high + secs(300)),
+            (100, None)
+        );
+
+        tracker.invalidate("key");
+        assert_eq!(tracker.observe("key", &decay, 50, now), (50, None));
+    }
+}
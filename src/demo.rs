@@ -3,17 +3,40 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use std::time::Duration;
+//! Demo account
+//!
+//! When `allow_demo` is set, visitors can sign in to a shared demo account,
+//! which is deleted and recreated every `demo.reset_interval` seconds. To keep
+//! public demo instances from accumulating junk in between:
+//!
+//! - the demo account can hold at most `demo.sitekey_limit` sitekeys, and each
+//!   demo session can create at most `demo.session_sitekey_limit` of them
+//! - sitekeys created from the demo account are deleted after
+//!   `demo.sitekey_ttl` seconds by [DemoCleanup]
+//! - the demo account and its sitekeys are left out of instance stats, and PoW
+//!   analytics of demo sitekeys aren't recorded
+//!
+//! With `demo.test_page`, a page that embeds the widget with a sitekey of the
+//! demo account is served at [WIDGET_ROUTES.demo][crate::widget::routes::Widget],
+//! so that the whole flow can be tried out without a website to protect.
+use std::time::{Duration, Instant};
 //use std::sync::atomicBool
 
 use actix::clock::sleep;
 use actix::spawn;
+use db_core::errors::DBError;
+use db_core::{CreateCaptcha as DBCreateCaptcha, InstanceStats, TrafficPattern};
+use libmcaptcha::master::messages::RemoveCaptcha;
+use sqlx::types::time::OffsetDateTime;
 use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
 use tokio::task::JoinHandle;
 
 use crate::api::v1::account::delete::runners::delete_user;
 use crate::api::v1::account::{username::runners::username_exists, AccountCheckPayload};
 use crate::api::v1::auth::runners::{register_runner, Register};
+use crate::api::v1::mcaptcha::create::{runner::create, CreateCaptcha};
+use crate::api::v1::mcaptcha::easy::calculate;
+use crate::jobs::{DEMO_CLEANUP_JOB, DEMO_USER_JOB};
 use crate::*;
 
 use errors::*;
@@ -22,6 +45,97 @@ use errors::*;
 pub const DEMO_USER: &str = "aaronsw";
 /// Demo password
 pub const DEMO_PASSWORD: &str = "password";
+/// description of the sitekey that the test page uses
+pub const TEST_PAGE_SITEKEY: &str = "Test page";
+
+/// traffic pattern that the difficulty of the test page sitekey is computed from
+const TEST_PAGE_TRAFFIC: TrafficPattern = TrafficPattern {
+    avg_traffic: 50,
+    peak_sustainable_traffic: 500,
+    broke_my_site_traffic: None,
+};
+
+/// Check if `username` is the demo account
+pub fn is_demo_user(data: &Data, username: &str) -> bool {
+    data.settings.allow_demo && username == DEMO_USER
+}
+
+/// Reject actions that aren't available to the demo account, like changing
+/// its credentials: the account is shared by all demo visitors
+pub fn restrict_demo_user(data: &Data, username: &str) -> ServiceResult<()> {
+    if is_demo_user(data, username) {
+        Err(ServiceError::DemoUserRestricted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Create sitekey `p` of the demo account in the demo session `session`. The
+/// database rejects it beyond the limits of the demo account and of the
+/// session, so that concurrent visitors can't go over them together
+pub async fn create_sitekey(
+    data: &Data,
+    username: &str,
+    p: &DBCreateCaptcha<'_>,
+    session: Option<&str>,
+) -> Result<(), DBError> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let demo = &data.settings.demo;
+    data.db
+        .create_demo_captcha(
+            username,
+            p,
+            session,
+            now,
+            demo.sitekey_limit,
+            demo.session_sitekey_limit,
+        )
+        .await
+}
+
+/// Check if sitekey `key` was created from the demo account
+pub async fn is_demo_sitekey(data: &Data, key: &str) -> ServiceResult<bool> {
+    if !data.settings.allow_demo {
+        return Ok(false);
+    }
+    Ok(data.db.is_demo_sitekey(key).await?)
+}
+
+/// Instance-wide counts, without the demo account and its sitekeys
+pub async fn instance_stats(data: &Data) -> ServiceResult<InstanceStats> {
+    let mut stats = data.db.get_instance_stats().await?;
+    if data.settings.allow_demo && data.db.username_exists(DEMO_USER).await? {
+        let sitekeys = data.db.get_all_user_captchas(DEMO_USER).await?;
+        stats.users = stats.users.saturating_sub(1);
+        stats.captchas = stats.captchas.saturating_sub(sitekeys.len() as u32);
+    }
+    Ok(stats)
+}
+
+/// Sitekey of the demo account that the test page embeds. It is created when
+/// it doesn't exist, since it goes away when the demo account is reset
+pub async fn test_page_sitekey(data: &AppData) -> ServiceResult<String> {
+    let mut sitekeys = data.db.get_all_user_captchas(DEMO_USER).await?;
+    if let Some(c) = sitekeys.iter().find(|c| c.description == TEST_PAGE_SITEKEY) {
+        return Ok(c.key.clone());
+    }
+
+    let strategy = &data.settings.captcha.default_difficulty_strategy;
+    let payload = CreateCaptcha {
+        levels: calculate(&TEST_PAGE_TRAFFIC, strategy)?,
+        duration: strategy.duration,
+        description: TEST_PAGE_SITEKEY.into(),
+        publish_benchmarks: false,
+    };
+    match create(&payload, data, DEMO_USER, None).await {
+        Ok(c) => Ok(c.key),
+        // visitors used up the demo account's sitekeys; borrow one of them
+        Err(ServiceError::DemoSitekeyLimitReached) if !sitekeys.is_empty() => {
+            Ok(sitekeys.swap_remove(0).key)
+        }
+        Err(e) => Err(e),
+    }
+}
 
 pub struct DemoUser {
     tx: Sender<()>,
@@ -45,7 +159,7 @@ impl DemoUser {
     }
 
     /// register demo user runner
-    async fn register_demo_user(data: &AppData) -> ServiceResult<()> {
+    pub async fn register_demo_user(data: &AppData) -> ServiceResult<()> {
         let user_exists_payload = AccountCheckPayload {
             val: DEMO_USER.into(),
         };
@@ -56,13 +170,17 @@ impl DemoUser {
                 password: DEMO_PASSWORD.into(),
                 confirm_password: DEMO_PASSWORD.into(),
                 email: None,
+                invite: None,
             };
 
             log::info!("Registering demo user");
             match register_runner(&register_payload, data).await {
-                Err(ServiceError::UsernameTaken) | Ok(_) => Ok(()),
-                Err(e) => Err(e),
+                Err(ServiceError::UsernameTaken) | Ok(_) => (),
+                Err(e) => return Err(e),
             }
+            // the demo account is open to everyone
+            data.db.set_pending_approval(DEMO_USER, false).await?;
+            Ok(())
         } else {
             Ok(())
         }
@@ -80,6 +198,7 @@ impl DemoUser {
         mut rx: Receiver<()>,
     ) -> ServiceResult<JoinHandle<()>> {
         Self::register_demo_user(&data).await?;
+        data.jobs.register(DEMO_USER_JOB, duration as u64);
 
         fn can_run(rx: &mut Receiver<()>) -> bool {
             match rx.try_recv() {
@@ -104,13 +223,108 @@ impl DemoUser {
                     }
                 }
 
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let mut res = Ok(());
+
                 if let Err(e) = Self::delete_demo_user(&data).await {
                     log::error!("Error while deleting demo user: {:?}", e);
+                    res = Err(e);
                 }
 
                 if let Err(e) = Self::register_demo_user(&data).await {
                     log::error!("Error while registering demo user: {:?}", e);
+                    res = Err(e);
+                }
+                data.jobs
+                    .finished(DEMO_USER_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+/// Deletes sitekeys of the demo account once they are `demo.sitekey_ttl`
+/// seconds old
+pub struct DemoCleanup {
+    tx: Sender<()>,
+}
+
+impl DemoCleanup {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// delete expired demo sitekeys and return their number
+    pub async fn cleanup(data: &AppData) -> ServiceResult<usize> {
+        let ttl = data.settings.demo.sitekey_ttl;
+        if ttl == 0 {
+            return Ok(0);
+        }
+        let created_before = OffsetDateTime::now_utc().unix_timestamp() - ttl as i64;
+        let keys = data.db.get_expired_demo_sitekeys(created_before).await?;
+        for key in keys.iter() {
+            match data.db.delete_captcha(DEMO_USER, key).await {
+                Ok(_) | Err(db_core::errors::DBError::CaptchaNotFound) => (),
+                Err(e) => return Err(e.into()),
+            }
+            if let Err(err) = data.captcha.remove(RemoveCaptcha(key.clone())).await {
+                log::error!("Error while trying to remove captcha from cache {}", err);
+            }
+        }
+        if !keys.is_empty() {
+            log::info!("Deleted {} expired demo sitekeys", keys.len());
+        }
+        Ok(keys.len())
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let interval = data.settings.demo.cleanup_interval;
+        data.jobs.register(DEMO_CLEANUP_JOB, interval as u64);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                if exit {
+                    break;
+                }
+                for _ in 0..interval {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::cleanup(&data).await.map(|_| ());
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while deleting expired demo sitekeys: {:?}", err);
                 }
+                data.jobs
+                    .finished(DEMO_CLEANUP_JOB, started, timer.elapsed(), &res);
             }
         };
         let handle = spawn(fut);
@@ -141,6 +355,79 @@ mod tests {
         demo_account_works(data).await;
     }
 
+    #[actix_rt::test]
+    async fn demo_account_is_restricted_pg() {
+        let data = crate::tests::pg::get_data().await;
+        demo_account_is_restricted(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn demo_account_is_restricted_maria() {
+        let data = crate::tests::maria::get_data().await;
+        demo_account_is_restricted(data).await;
+    }
+
+    async fn demo_account_is_restricted(data_inner: ArcData) {
+        use crate::api::v1::account::password::ChangePasswordReqest;
+        use crate::api::v1::mcaptcha::create::runner::create;
+
+        const SESSION: &str = "demosessionquota";
+        let mut settings = data_inner.settings.clone();
+        settings.allow_demo = true;
+        settings.demo.session_sitekey_limit = 1;
+        settings.demo.sitekey_ttl = 1;
+        let data_inner =
+            &crate::data::Data::new(&settings, data_inner.survey_secrets.clone()).await;
+        let data = AppData::new(data_inner.clone());
+        crate::tests::delete_user(data_inner, DEMO_USER).await;
+        DemoUser::register_demo_user(&data).await.unwrap();
+
+        let payload = ChangePasswordReqest {
+            password: DEMO_PASSWORD.into(),
+            new_password: "newdemopassword".into(),
+            confirm_new_password: "newdemopassword".into(),
+        };
+        bad_post_req_test(
+            data_inner,
+            DEMO_USER,
+            DEMO_PASSWORD,
+            crate::V1_API_ROUTES.account.update_password,
+            &payload,
+            ServiceError::DemoUserRestricted,
+        )
+        .await;
+
+        // demo sessions have their own quota
+        let sitekey = get_level_data();
+        let first = create(&sitekey, &data, DEMO_USER, Some(SESSION))
+            .await
+            .unwrap();
+        assert_eq!(
+            create(&sitekey, &data, DEMO_USER, Some(SESSION))
+                .await
+                .err(),
+            Some(ServiceError::DemoSessionSitekeyLimitReached)
+        );
+        assert!(is_demo_sitekey(&data, &first.key).await.unwrap());
+
+        for _ in 1..data.settings.demo.sitekey_limit {
+            create(&sitekey, &data, DEMO_USER, None).await.unwrap();
+        }
+        assert_eq!(
+            create(&sitekey, &data, DEMO_USER, Some("otherdemosession"))
+                .await
+                .err(),
+            Some(ServiceError::DemoSitekeyLimitReached)
+        );
+
+        // and demo sitekeys expire
+        sleep(Duration::from_secs(2)).await;
+        assert!(DemoCleanup::cleanup(&data).await.unwrap() > 0);
+        assert!(!data.db.captcha_exists(None, &first.key).await.unwrap());
+
+        crate::tests::delete_user(data_inner, DEMO_USER).await;
+    }
+
     async fn demo_account_works(data_inner: ArcData) {
         let data_inner = &data_inner;
         let data = AppData::new(data_inner.clone());
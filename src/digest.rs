@@ -0,0 +1,362 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Stats digest emails
+//!
+//! Users can subscribe to a daily, weekly or monthly digest of the traffic of
+//! their sitekeys. Every [CHECK_INTERVAL] seconds, [DigestSender] emails
+//! subscribers whose last digest is a period old a summary of the period:
+//!
+//! - challenges fetched, solved and confirmed
+//! - the [TOP_SITEKEYS] sitekeys with the most solves
+//! - escalations of difficulty by visitor count, from the escalation log
+//! - anomalies of sitekeys that have `anomaly_factor` set, detected in the
+//!   windows of the period like [crate::anomalies] does
+//!
+//! Digests are claimed in the database before they are sent, so that
+//! instances sharing it send each digest once. Digests that fail to send
+//! aren't sent again until the next period. Stats are read from the database,
+//! so the digest is empty when stats are written to a
+//! [stats sink](crate::stats_sink) or disabled.
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::{DigestFrequency, StatsBucket, TimeRange};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::anomalies::{self, BASELINE, WINDOW};
+use crate::errors::*;
+use crate::jobs::DIGEST_JOB;
+use crate::AppData;
+
+/// interval, in seconds, at which due digests are sent
+pub const CHECK_INTERVAL: u64 = 60 * 60;
+/// number of sitekeys listed in digests
+pub const TOP_SITEKEYS: usize = 5;
+
+/// length of the period that a digest covers, in seconds
+pub fn period(frequency: DigestFrequency) -> i64 {
+    const DAY: i64 = 60 * 60 * 24;
+    match frequency {
+        DigestFrequency::Daily => DAY,
+        DigestFrequency::Weekly => 7 * DAY,
+        DigestFrequency::Monthly => 30 * DAY,
+    }
+}
+
+/// name of the period that a digest covers
+pub fn period_name(frequency: DigestFrequency) -> &'static str {
+    match frequency {
+        DigestFrequency::Daily => "day",
+        DigestFrequency::Weekly => "week",
+        DigestFrequency::Monthly => "month",
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// traffic of a sitekey over the period of a digest
+pub struct SitekeySummary {
+    pub key: String,
+    pub description: String,
+    pub fetches: u64,
+    pub solves: u64,
+    pub confirms: u64,
+    pub escalations: u64,
+    pub anomalies: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// traffic of the sitekeys of a user over the period of a digest
+pub struct Digest {
+    pub frequency: DigestFrequency,
+    pub fetches: u64,
+    pub solves: u64,
+    pub confirms: u64,
+    pub escalations: u64,
+    pub anomalies: usize,
+    /// sitekeys with the most solves, most solves first
+    pub top: Vec<SitekeySummary>,
+}
+
+/// anomalies detected in the windows of `buckets` within `since..until`,
+/// against the [BASELINE] before each window. `buckets` are [WINDOW] second
+/// buckets, oldest first, from [BASELINE] seconds before `since`
+pub fn count_anomalies(
+    factor: u32,
+    buckets: &[StatsBucket],
+    since: i64,
+    until: i64,
+) -> usize {
+    // windows without events aren't anomalous
+    buckets
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| b.time >= since && b.time + WINDOW <= until)
+        .map(|(i, current)| {
+            let start = buckets.partition_point(|b| b.time < current.time - BASELINE);
+            anomalies::detect(factor, current, &buckets[start..i]).len()
+        })
+        .sum()
+}
+
+/// traffic of sitekey `key` of `username` within `since..until`
+async fn summarize(
+    data: &AppData,
+    username: &str,
+    key: &str,
+    since: i64,
+    until: i64,
+) -> ServiceResult<SitekeySummary> {
+    let range = TimeRange {
+        from: since,
+        to: until,
+    };
+    let buckets = data
+        .db
+        .fetch_stats_series(username, key, &range, (until - since) as u32)
+        .await?;
+    let mut summary = SitekeySummary {
+        key: key.to_string(),
+        fetches: buckets.iter().map(|b| b.config_fetches).sum(),
+        solves: buckets.iter().map(|b| b.solves).sum(),
+        confirms: buckets.iter().map(|b| b.confirms).sum(),
+        escalations: data.db.count_escalations(key, since).await?,
+        ..Default::default()
+    };
+
+    if let Some(t) = crate::alerts::thresholds(data, key).await? {
+        if t.anomaly_factor > 0 {
+            let range = TimeRange {
+                from: since - BASELINE,
+                to: until,
+            };
+            let buckets = data
+                .db
+                .fetch_stats_series(username, key, &range, WINDOW as u32)
+                .await?;
+            summary.anomalies =
+                count_anomalies(t.anomaly_factor, &buckets, since, until);
+        }
+    }
+    Ok(summary)
+}
+
+/// digest of the traffic of the sitekeys of `username` over the period of
+/// `frequency` that ends at `until`
+pub async fn compose(
+    data: &AppData,
+    username: &str,
+    frequency: DigestFrequency,
+    until: i64,
+) -> ServiceResult<Digest> {
+    let since = until - period(frequency);
+    let mut digest = Digest {
+        frequency,
+        ..Default::default()
+    };
+    let mut summaries = Vec::new();
+    for c in data.db.get_all_user_captchas(username).await? {
+        let mut summary = summarize(data, username, &c.key, since, until).await?;
+        summary.description = c.description;
+        digest.fetches += summary.fetches;
+        digest.solves += summary.solves;
+        digest.confirms += summary.confirms;
+        digest.escalations += summary.escalations;
+        digest.anomalies += summary.anomalies;
+        summaries.push(summary);
+    }
+    summaries.sort_by(|a, b| b.solves.cmp(&a.solves));
+    summaries.truncate(TOP_SITEKEYS);
+    digest.top = summaries;
+    Ok(digest)
+}
+
+/// send the digests that are due at `now` and return their number. Users
+/// without an email address or sitekeys aren't emailed
+pub async fn send_due(data: &AppData, now: i64) -> ServiceResult<usize> {
+    let mut sent = 0;
+    for s in data.db.get_digest_subscriptions().await? {
+        let due_before = now - period(s.frequency);
+        if s.last_sent > due_before {
+            continue;
+        }
+        let email = match data.db.get_email(&s.username).await? {
+            Some(email) => email,
+            None => continue,
+        };
+        if !data.db.claim_digest(&s.username, due_before, now).await? {
+            continue;
+        }
+        let res = match compose(data, &s.username, s.frequency, now).await {
+            Ok(digest) if digest.top.is_empty() => continue,
+            Ok(digest) => crate::email::digest::send(data, &email, &s.username, &digest)
+                .await
+                .map(|_| sent += 1),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            log::error!("Unable to send stats digest to {}: {e}", s.username);
+        }
+    }
+    Ok(sent)
+}
+
+pub struct DigestSender {
+    tx: Sender<()>,
+}
+
+impl DigestSender {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs.register(DIGEST_JOB, CHECK_INTERVAL);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                for _ in 0..CHECK_INTERVAL {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = send_due(&data, started.unix_timestamp()).await.map(|_| ());
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while sending stats digests: {:?}", err);
+                }
+                data.jobs
+                    .finished(DIGEST_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    fn bucket(time: i64, config_fetches: u64) -> StatsBucket {
+        StatsBucket {
+            time,
+            config_fetches,
+            solves: 0,
+            confirms: 0,
+        }
+    }
+
+    #[test]
+    fn count_anomalies_works() {
+        let since = 10 * BASELINE;
+        let until = since + period(DigestFrequency::Daily);
+        // fetches flood a window of the period, and one before it
+        let buckets = [
+            bucket(since - WINDOW, 100),
+            bucket(since + WINDOW, 100),
+            bucket(since + 2 * WINDOW, 1),
+            // window that hasn't ended yet
+            bucket(until - WINDOW / 2, 100),
+        ];
+        assert_eq!(count_anomalies(3, &buckets, since, until), 1);
+        assert_eq!(count_anomalies(0, &buckets, since, until), 0);
+
+        // usual traffic isn't anomalous
+        let windows = (BASELINE / WINDOW) as u64;
+        let busy = [
+            bucket(since - WINDOW, 100 * windows),
+            bucket(since + WINDOW, 100),
+        ];
+        assert_eq!(count_anomalies(3, &busy, since, until), 0);
+    }
+
+    #[actix_rt::test]
+    async fn digests_work_pg() {
+        let data = pg::get_data().await;
+        digests_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn digests_work_maria() {
+        let data = maria::get_data().await;
+        digests_work(data).await;
+    }
+
+    async fn digests_work(data: ArcData) {
+        const NAME: &str = "digestuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "digestuser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app_data = &AppData::new(data.clone());
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let digest = compose(app_data, NAME, DigestFrequency::Weekly, now)
+            .await
+            .unwrap();
+        assert_eq!(digest.top.len(), 1);
+        assert_eq!(digest.top[0].key, token_key.key);
+
+        // digests are sent once a period has passed since subscribing, once
+        data.db
+            .set_digest_frequency(NAME, DigestFrequency::Daily)
+            .await
+            .unwrap();
+        let due = now + period(DigestFrequency::Daily) + 60;
+        let sent = |now| async move {
+            let s = data.db.get_digest_subscriptions().await.unwrap();
+            let s = s.into_iter().find(|s| s.username == NAME).unwrap();
+            send_due(app_data, now).await.unwrap();
+            let after = data.db.get_digest_subscriptions().await.unwrap();
+            after
+                .into_iter()
+                .find(|a| a.username == NAME)
+                .unwrap()
+                .last_sent
+                != s.last_sent
+        };
+        assert!(!sent(now).await);
+        assert!(sent(due).await);
+        assert!(!sent(due).await);
+        data.db.delete_digest_subscription(NAME).await.unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Allowed domains of sitekeys
+//!
+//! Sitekeys that have allowed domains only serve challenges to widgets that
+//! are embedded on one of those domains or their subdomains. The widget
+//! reports the origin of the page that embeds it, as disclosed by the browser,
+//! so stolen sitekeys can't be embedded on unrelated sites. Clients that lie
+//! about the origin still have to solve the PoW.
+//!
+//! When `captcha.require_allowed_domains` is set, sitekeys without allowed
+//! domains don't serve challenges. Sitekeys that exist when the requirement is
+//! turned on are grandfathered: they keep serving challenges for
+//! `captcha.allowed_domains_grace_period` seconds, and their owners are
+//! notified of the deadline.
+use db_core::errors::DBError;
+use db_core::UnrestrictedCaptcha;
+use sqlx::types::time::OffsetDateTime;
+use url::Url;
+
+use crate::api::v1::pow::probe::ANY_ORIGIN;
+use crate::date::DAY;
+use crate::errors::*;
+use crate::AppData;
+
+/// maximum number of allowed domains of a sitekey
+pub const MAX_ALLOWED_DOMAINS: usize = 50;
+/// maximum length of a domain name
+const MAX_DOMAIN_LEN: usize = 255;
+
+/// normalize an allowed domain: lowercase host name, without scheme, port or
+/// path. Domains are dot-separated labels of letters, digits and '-'
+pub fn normalize(domain: &str) -> ServiceResult<String> {
+    let domain = domain.trim().to_lowercase();
+    let domain = if domain.contains("://") {
+        Url::parse(&domain)
+            .ok()
+            .and_then(|u| u.host_str().map(|h| h.to_string()))
+            .ok_or(ServiceError::InvalidAllowedDomain)?
+    } else {
+        domain
+    };
+    let domain = domain.trim_end_matches('.');
+    if domain.is_empty()
+        || domain.len() > MAX_DOMAIN_LEN
+        || domain.split('.').any(|label| {
+            label.is_empty()
+                || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    {
+        return Err(ServiceError::InvalidAllowedDomain);
+    }
+    Ok(domain.to_string())
+}
+
+/// normalize allowed domains of a sitekey, without duplicates, sorted by name
+pub fn normalize_all(domains: &[String]) -> ServiceResult<Vec<String>> {
+    if domains.len() > MAX_ALLOWED_DOMAINS {
+        return Err(ServiceError::TooManyAllowedDomains);
+    }
+    let mut domains = domains
+        .iter()
+        .map(|d| normalize(d))
+        .collect::<ServiceResult<Vec<String>>>()?;
+    domains.sort();
+    domains.dedup();
+    Ok(domains)
+}
+
+/// check if `origin` is on one of `domains` or their subdomains
+pub fn is_allowed(domains: &[String], origin: &str) -> bool {
+    let host = match Url::parse(origin)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_lowercase))
+    {
+        Some(host) => host,
+        None => return false,
+    };
+    domains.iter().any(|d| {
+        host == *d
+            || host
+                .strip_suffix(d.as_str())
+                .map_or(false, |sub| sub.ends_with('.'))
+    })
+}
+
+/// check if a sitekey that has no allowed domains can serve challenges
+async fn unrestricted(data: &AppData, key: &str) -> ServiceResult<bool> {
+    if !data.settings.captcha.require_allowed_domains {
+        return Ok(true);
+    }
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    match data.db.get_allowed_domains_deadline(key).await {
+        Ok(deadline) => Ok(deadline.map_or(false, |d| now < d)),
+        Err(DBError::CaptchaNotFound) => Err(ServiceError::TokenNotFound),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// check if the sitekey serves challenges to a widget embedded on `origin`
+pub async fn check(
+    data: &AppData,
+    key: &str,
+    origin: Option<&str>,
+) -> ServiceResult<()> {
+    let domains = data.db.get_allowed_domains(key).await?;
+    if domains.is_empty() {
+        if unrestricted(data, key).await? {
+            return Ok(());
+        }
+        return Err(ServiceError::AllowedDomainsRequired);
+    }
+    match origin {
+        Some(origin) if is_allowed(&domains, origin) => Ok(()),
+        _ => Err(ServiceError::OriginNotAllowed),
+    }
+}
+
+/// domains that a sitekey serves challenges on; [ANY_ORIGIN] when it serves
+/// them on any domain
+pub async fn allowed_origins(data: &AppData, key: &str) -> ServiceResult<Vec<String>> {
+    let domains = data.db.get_allowed_domains(key).await?;
+    if domains.is_empty() && unrestricted(data, key).await? {
+        return Ok(vec![ANY_ORIGIN.into()]);
+    }
+    Ok(domains)
+}
+
+/// give sitekeys that have no allowed domains until the end of the grace
+/// period to set them, and notify their owners. Sitekeys that were already
+/// given a deadline keep it
+pub async fn grandfather(data: &AppData) -> ServiceResult<()> {
+    if !data.settings.captcha.require_allowed_domains {
+        return Ok(());
+    }
+    let grace_period = data.settings.captcha.allowed_domains_grace_period as i64;
+    let deadline = OffsetDateTime::now_utc().unix_timestamp() + grace_period;
+    let captchas = data.db.get_captchas_without_allowed_domains().await?;
+    for captcha in captchas.iter() {
+        data.db
+            .set_allowed_domains_deadline(&captcha.key, deadline)
+            .await?;
+        notify(data, captcha, grace_period).await;
+    }
+    if !captchas.is_empty() {
+        log::info!(
+            "Sitekeys without allowed domains: {} given {grace_period} seconds to set them",
+            captchas.len()
+        );
+    }
+    Ok(())
+}
+
+/// notify the owner of a grandfathered sitekey of its deadline. Failures are
+/// logged, so that notifying doesn't hold up grandfathering
+async fn notify(data: &AppData, captcha: &UnrestrictedCaptcha, grace_period: i64) {
+    if !data.settings.features.notifications {
+        return;
+    }
+    let message = format!(
+        "Sitekey \"{}\" ({}) can be embedded on any site. Add the domains that it is used on within {} days, after which it will stop serving challenges until you do.",
+        captcha.description,
+        captcha.key,
+        grace_period / DAY
+    );
+    let n = db_core::AddNotification {
+        to: &captcha.owner,
+        from: &captcha.owner,
+        heading: "Allowed domains required",
+        message: &message,
+    };
+    if let Err(e) = crate::api::v1::notifications::notify(data, &n).await {
+        log::error!(
+            "Unable to notify {} of allowed domains deadline: {e}",
+            captcha.owner
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_works() {
+        assert_eq!(normalize(" Example.COM ").unwrap(), "example.com");
+        assert_eq!(
+            normalize("https://shop.example.com:8443/checkout").unwrap(),
+            "shop.example.com"
+        );
+        assert_eq!(normalize("example.com.").unwrap(), "example.com");
+        assert_eq!(normalize("localhost").unwrap(), "localhost");
+        for domain in ["", "exa mple.com", "example..com", "*.example.com", "a/b"] {
+            assert!(matches!(
+                normalize(domain),
+                Err(ServiceError::InvalidAllowedDomain)
+            ));
+        }
+
+        let domains = vec!["b.com".into(), "A.com".into(), "https://b.com/".into()];
+        assert_eq!(normalize_all(&domains).unwrap(), vec!["a.com", "b.com"]);
+        assert!(matches!(
+            normalize_all(&vec!["a.com".into(); MAX_ALLOWED_DOMAINS + 1]),
+            Err(ServiceError::TooManyAllowedDomains)
+        ));
+    }
+
+    #[test]
+    fn is_allowed_works() {
+        let domains = vec!["example.com".to_string()];
+        assert!(is_allowed(&domains, "https://example.com"));
+        assert!(is_allowed(&domains, "https://shop.example.com:8443"));
+        assert!(is_allowed(&domains, "http://EXAMPLE.com"));
+        assert!(!is_allowed(&domains, "https://notexample.com"));
+        assert!(!is_allowed(&domains, "https://example.com.evil.org"));
+        assert!(!is_allowed(&domains, "example.com"));
+        assert!(!is_allowed(&[], "https://example.com"));
+    }
+}
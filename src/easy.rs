@@ -3,17 +3,19 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 //use std::sync::atomicBool
 
 use actix::clock::sleep;
 use actix::spawn;
+use sqlx::types::time::OffsetDateTime;
 use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
 use tokio::task::JoinHandle;
 
 use crate::api::v1::mcaptcha::easy::{
     update_runner, TrafficPatternRequest, UpdateTrafficPattern,
 };
+use crate::jobs::EASY_CAPTCHA_JOB;
 use crate::*;
 
 use errors::*;
@@ -103,6 +105,7 @@ impl UpdateEasyCaptcha {
         duration: u32,
         mut rx: Receiver<()>,
     ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs.register(EASY_CAPTCHA_JOB, duration as u64);
         let mut exit = false;
         let fut = async move {
             loop {
@@ -119,15 +122,17 @@ impl UpdateEasyCaptcha {
                     }
                 }
 
-                if let Some(err) = Self::update_captcha_configurations(&data, &mut rx)
-                    .await
-                    .err()
-                {
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::update_captcha_configurations(&data, &mut rx).await;
+                if let Some(err) = res.as_ref().err() {
                     log::error!(
                         "Tried to update easy captcha configurations in background {:?}",
                         err
                     );
                 }
+                data.jobs
+                    .finished(EASY_CAPTCHA_JOB, started, timer.elapsed(), &res);
             }
         };
         let handle = spawn(fut);
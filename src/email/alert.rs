@@ -0,0 +1,134 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Alerts on traffic of sitekeys, emailed to their owners. See
+//! [crate::anomalies] for the alerts that are emailed
+use sailfish::TemplateOnce;
+
+use super::templates::{Email, Template};
+use crate::alerts::Alert;
+use crate::errors::*;
+use crate::Data;
+
+const PAGE: &str = "Alert";
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "email/alert/index.html")]
+struct IndexPage<'a> {
+    heading: &'a str,
+    message: &'a str,
+    sitekey_link: &'a str,
+}
+
+async fn alert(
+    data: &Data,
+    to: &str,
+    key: &str,
+    alert: Alert,
+    message: &str,
+) -> ServiceResult<()> {
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
+        let domain = &data.settings.server.domain;
+        let sitekey_link =
+            super::instance_url(data, &crate::PAGES.panel.sitekey.get_view(key));
+        let heading = alert.heading();
+
+        let plain_text = format!(
+            "
+{message}
+
+SITEKEY: {sitekey_link}
+
+With best regards,
+Admin
+instance: {domain}
+project website: {}",
+            crate::PKG_HOMEPAGE
+        );
+
+        let html = IndexPage {
+            heading,
+            message,
+            sitekey_link: &sitekey_link,
+        }
+        .render_once()
+        .unwrap();
+
+        let vars = [
+            ("heading", heading),
+            ("message", message),
+            ("sitekey_link", sitekey_link.as_str()),
+        ];
+        let email = Email {
+            subject: format!("[mCaptcha] {heading}"),
+            text: plain_text,
+            html,
+        }
+        .customize(smtp, domain, Template::Alert, &vars)
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+/// email an alert on a sitekey to its owner. Owners without an email address
+/// aren't emailed
+pub async fn send(
+    data: &Data,
+    owner: &str,
+    key: &str,
+    a: Alert,
+    message: &str,
+) -> ServiceResult<()> {
+    if let Some(email) = data.db.get_email(owner).await? {
+        alert(data, &email, key, a, message).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use awc::Client;
+
+    #[actix_rt::test]
+    async fn alert_email_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        alert_email_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn alert_email_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        alert_email_works(data).await;
+    }
+
+    async fn alert_email_works(data: crate::ArcData) {
+        const TO_ADDR: &str = "Hello <realaravinth@localhost>";
+        const KEY: &str = "alertemailsitekey";
+        alert(&data, TO_ADDR, KEY, Alert::FetchFlood, "fetches spiked")
+            .await
+            .unwrap();
+
+        let client = Client::default();
+        let mut resp = client
+            .get("http://localhost:1080/email")
+            .send()
+            .await
+            .unwrap();
+        let emails: serde_json::Value = resp.json().await.unwrap();
+        let emails = emails.as_array().unwrap();
+        let body = emails
+            .iter()
+            .map(|e| e["html"].to_string())
+            .find(|html| html.contains(KEY))
+            .unwrap();
+        assert!(body.contains(Alert::FetchFlood.heading()));
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Email address changes
+//!
+//! When email is enabled, email addresses aren't changed when users ask for
+//! it, so that a hijacked session can't take the account over by changing its
+//! address. The new address is mailed a confirmation link, and the current
+//! address a notice with a link that cancels the change. The address is only
+//! changed once the change is confirmed; cancelling it signs out all sessions
+//! of the account.
+use sailfish::TemplateOnce;
+use sqlx::types::time::OffsetDateTime;
+
+use super::templates::{Email, Template};
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::Data;
+
+const PAGE: &str = "Email Change";
+
+/// validity of email change links, in seconds
+pub const EMAIL_CHANGE_TTL: i64 = 60 * 60 * 24;
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "email/email-change/index.html")]
+struct IndexPage<'a> {
+    username: &'a str,
+    domain: &'a str,
+    new_email: &'a str,
+    link: &'a str,
+    confirm: bool,
+}
+
+/// mail confirmation link to the new address when `confirm` is set, and the
+/// notice with the cancellation link to the current address otherwise
+async fn mail(
+    data: &Data,
+    to: &str,
+    username: &str,
+    new_email: &str,
+    link: &str,
+    confirm: bool,
+) -> ServiceResult<()> {
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
+        let domain = &data.settings.server.domain;
+
+        let (subject, text) = if confirm {
+            (
+                "[mCaptcha] Please confirm your new email address",
+                format!(
+                    "A request was made to change the email address of {username} on {domain} to this address.
+The address is changed once you confirm it.
+
+CONFIRMATION LINK: {link}
+
+The link expires in 24 hours. Please ignore this email if you weren't expecting it."
+                ),
+            )
+        } else {
+            (
+                "[mCaptcha] Your email address is being changed",
+                format!(
+                    "A request was made to change the email address of {username} on {domain} to {new_email}.
+The address is changed once the new address is confirmed.
+
+If you didn't request this, cancel the change. Cancelling signs out all sessions of your account; please change your password too.
+
+CANCELLATION LINK: {link}"
+                ),
+            )
+        };
+        let plain_text = format!(
+            "
+{text}
+
+With best regards,
+Admin
+instance: {domain}
+project website: {}",
+            crate::PKG_HOMEPAGE
+        );
+
+        let html = IndexPage {
+            username,
+            domain,
+            new_email,
+            link,
+            confirm,
+        }
+        .render_once()
+        .unwrap();
+
+        let (template, link_variable) = if confirm {
+            (Template::EmailChange, "confirmation_link")
+        } else {
+            (Template::EmailChangeNotice, "cancellation_link")
+        };
+        let vars = [
+            ("username", username),
+            ("new_email", new_email),
+            (link_variable, link),
+        ];
+        let email = Email {
+            subject: subject.into(),
+            text: plain_text,
+            html,
+        }
+        .customize(smtp, domain, template, &vars)
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+/// record a pending change of the email address of `username` to `new_email`
+/// and mail the confirmation and cancellation links. Replaces pending change
+/// of the user, if any
+pub async fn request(data: &Data, username: &str, new_email: &str) -> ServiceResult<()> {
+    let token = get_random(32);
+    let cancel_token = get_random(32);
+    let c = db_core::AddEmailChange {
+        token: &token,
+        cancel_token: &cancel_token,
+        email: new_email,
+    };
+    data.db.add_email_change(username, &c).await?;
+
+    let confirm_link =
+        super::instance_url(data, &crate::PAGES.auth.get_confirm_email_change(&token));
+    mail(data, new_email, username, new_email, &confirm_link, true).await?;
+    if let Some(current) = data.db.get_email(username).await? {
+        let cancel_link = super::instance_url(
+            data,
+            &crate::PAGES.auth.get_cancel_email_change(&cancel_token),
+        );
+        mail(data, &current, username, new_email, &cancel_link, false).await?;
+    }
+    Ok(())
+}
+
+/// consume confirmation token and change the email address of its user. The
+/// new address is marked as verified, since the link was mailed to it
+pub async fn confirm(data: &Data, token: &str) -> ServiceResult<()> {
+    let c = data.db.consume_email_change(token).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if now - c.created_at > EMAIL_CHANGE_TTL {
+        return Err(ServiceError::EmailChangeNotFound);
+    }
+    // taken by another account since the change was requested
+    if data.db.email_exists(&c.email).await? {
+        return Err(ServiceError::EmailTaken);
+    }
+    let update_email = db_core::UpdateEmail {
+        username: &c.username,
+        new_email: &c.email,
+    };
+    data.db.update_email(&update_email).await?;
+    data.db.set_email_verified(&c.username, true).await?;
+    Ok(())
+}
+
+/// discard pending change with cancellation token `cancel_token` and sign out
+/// all sessions of its user, since the session that requested it may have
+/// been hijacked
+pub async fn cancel(data: &Data, cancel_token: &str) -> ServiceResult<()> {
+    let c = data.db.cancel_email_change(cancel_token).await?;
+    data.db.delete_user_sessions(&c.username).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use awc::Client;
+
+    #[actix_rt::test]
+    async fn email_change_email_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        email_change_email_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn email_change_email_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        email_change_email_works(data).await;
+    }
+
+    async fn email_change_email_works(data: crate::ArcData) {
+        const TO_ADDR: &str = "Hello <realaravinth@localhost>";
+        const USERNAME: &str = "emailchangeemailuser";
+        const LINK: &str = "https://localhost/email-change/cancel/abc";
+        mail(&data, TO_ADDR, USERNAME, "new@localhost", LINK, false)
+            .await
+            .unwrap();
+
+        let client = Client::default();
+        let mut resp = client
+            .get("http://localhost:1080/email")
+            .send()
+            .await
+            .unwrap();
+        let emails: serde_json::Value = resp.json().await.unwrap();
+        let emails = emails.as_array().unwrap();
+        let body = emails
+            .iter()
+            .map(|e| e["html"].to_string())
+            .find(|html| html.contains(USERNAME))
+            .unwrap();
+        assert!(body.contains(LINK));
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Stats digests, emailed to subscribers. See [crate::digest]
+use sailfish::TemplateOnce;
+
+use super::templates::{Email, Template};
+use crate::digest::{period_name, Digest};
+use crate::errors::*;
+use crate::Data;
+
+const PAGE: &str = "Digest";
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "email/digest/index.html")]
+struct IndexPage<'a> {
+    username: &'a str,
+    period: &'a str,
+    digest: &'a Digest,
+    settings_link: &'a str,
+}
+
+/// plain text summary of a digest
+fn summary(digest: &Digest) -> String {
+    let mut summary = format!(
+        "Challenges fetched: {}
+Challenges solved: {}
+Tokens confirmed: {}
+Difficulty escalations: {}
+Anomalies: {}
+
+Top sitekeys:",
+        digest.fetches,
+        digest.solves,
+        digest.confirms,
+        digest.escalations,
+        digest.anomalies
+    );
+    for s in digest.top.iter() {
+        summary.push_str(&format!(
+            "\n- {} ({}): {} solves, {} escalations, {} anomalies",
+            s.description, s.key, s.solves, s.escalations, s.anomalies
+        ));
+    }
+    summary
+}
+
+/// email digest of the sitekeys of `username` to `to`
+pub async fn send(
+    data: &Data,
+    to: &str,
+    username: &str,
+    digest: &Digest,
+) -> ServiceResult<()> {
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
+        let domain = &data.settings.server.domain;
+        let settings_link = super::instance_url(data, crate::PAGES.panel.settings.home);
+        let period = period_name(digest.frequency);
+        let summary = summary(digest);
+
+        let plain_text = format!(
+            "
+Traffic of the sitekeys of {username} over the last {period}:
+
+{summary}
+
+You can change how often you receive this digest, or stop receiving it, in your settings: {settings_link}
+
+With best regards,
+Admin
+instance: {domain}
+project website: {}",
+            crate::PKG_HOMEPAGE
+        );
+
+        let html = IndexPage {
+            username,
+            period,
+            digest,
+            settings_link: &settings_link,
+        }
+        .render_once()
+        .unwrap();
+
+        let vars = [
+            ("username", username),
+            ("period", period),
+            ("summary", summary.as_str()),
+            ("settings_link", settings_link.as_str()),
+        ];
+        let email = Email {
+            subject: format!("[mCaptcha] Your {} digest", digest.frequency.as_str()),
+            text: plain_text,
+            html,
+        }
+        .customize(smtp, domain, Template::Digest, &vars)
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digest::SitekeySummary;
+
+    use awc::Client;
+
+    #[actix_rt::test]
+    async fn digest_email_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        digest_email_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn digest_email_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        digest_email_works(data).await;
+    }
+
+    async fn digest_email_works(data: crate::ArcData) {
+        const TO_ADDR: &str = "Hello <realaravinth@localhost>";
+        const DESCRIPTION: &str = "digestemailsitekey";
+        let digest = Digest {
+            solves: 42,
+            top: vec![SitekeySummary {
+                key: "digestemailkey".into(),
+                description: DESCRIPTION.into(),
+                solves: 42,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        send(&data, TO_ADDR, "digestemailuser", &digest)
+            .await
+            .unwrap();
+
+        let client = Client::default();
+        let mut resp = client
+            .get("http://localhost:1080/email")
+            .send()
+            .await
+            .unwrap();
+        let emails: serde_json::Value = resp.json().await.unwrap();
+        let emails = emails.as_array().unwrap();
+        let email = emails
+            .iter()
+            .find(|e| e["html"].to_string().contains(DESCRIPTION))
+            .unwrap();
+        assert!(email["text"].to_string().contains("Challenges solved: 42"));
+    }
+}
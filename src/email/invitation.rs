@@ -0,0 +1,192 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Invitations to sign up
+//!
+//! Administrators can invite people to the instance and organization owners
+//! can invite people to their organizations. Invitees receive a signup link,
+//! which works even when registration is closed and fixes the email address of
+//! the new account to that of the invitation.
+use db_core::{CreateInvite, Invite, InviteStatus, OrgRole};
+use sailfish::TemplateOnce;
+use sqlx::types::time::OffsetDateTime;
+
+use super::templates::{Email, Template};
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::Data;
+
+const PAGE: &str = "Invitation";
+
+/// validity of invitations, in seconds
+pub const INVITE_TTL: i64 = 60 * 60 * 24 * 7;
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "email/invitation/index.html")]
+struct IndexPage<'a> {
+    invitation_link: &'a str,
+    invited_by: &'a str,
+    org: Option<&'a str>,
+    domain: &'a str,
+    days: i64,
+}
+
+async fn invitation(
+    data: &Data,
+    to: &str,
+    invited_by: &str,
+    org: Option<&str>,
+    invitation_link: &str,
+) -> ServiceResult<()> {
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
+        const SUBJECT: &str = "[mCaptcha] You're invited to mCaptcha";
+        let domain = &data.settings.server.domain;
+        let days = INVITE_TTL / (60 * 60 * 24);
+
+        let org_text = org
+            .map(|org| format!(" and join the organization {org}"))
+            .unwrap_or_default();
+        let plain_text = format!(
+            "
+Welcome to mCaptcha!
+
+{invited_by} invited you to create an account on {domain}{org_text}.
+
+INVITATION LINK: {invitation_link}
+
+The invitation expires in {days} days. Please ignore this email if you weren't expecting it.
+
+With best regards,
+Admin
+instance: {domain}
+project website: {}",
+            crate::PKG_HOMEPAGE
+        );
+
+        let html = IndexPage {
+            invitation_link,
+            invited_by,
+            org,
+            domain,
+            days,
+        }
+        .render_once()
+        .unwrap();
+
+        let days = days.to_string();
+        let vars = [
+            ("invitation_link", invitation_link),
+            ("invited_by", invited_by),
+            ("org", org.unwrap_or_default()),
+            ("days", days.as_str()),
+        ];
+        let email = Email {
+            subject: SUBJECT.into(),
+            text: plain_text,
+            html,
+        }
+        .customize(smtp, domain, Template::Invitation, &vars)
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+/// signup link of an invitation
+pub fn invitation_link(data: &Data, token: &str) -> String {
+    super::instance_url(data, &crate::PAGES.auth.get_join_invite(token))
+}
+
+/// issue an invitation to `email`, optionally to join `org` with `role`.
+/// Returns the invitation token
+pub async fn issue(
+    data: &Data,
+    username: &str,
+    email: &str,
+    org: Option<&str>,
+    role: Option<OrgRole>,
+) -> ServiceResult<String> {
+    let token = get_random(32);
+    let invite = CreateInvite {
+        token: &token,
+        email,
+        org,
+        role: org.map(|_| role.unwrap_or_default()),
+        expires_at: OffsetDateTime::now_utc().unix_timestamp() + INVITE_TTL,
+    };
+    data.db.create_invite(username, &invite).await?;
+    Ok(token)
+}
+
+/// issue an invitation and mail its signup link to `email`. Returns the signup
+/// link
+pub async fn send(
+    data: &Data,
+    username: &str,
+    email: &str,
+    org: Option<&str>,
+    role: Option<OrgRole>,
+) -> ServiceResult<String> {
+    let token = issue(data, username, email, org, role).await?;
+    let link = invitation_link(data, &token);
+    invitation(data, email, username, org, &link).await?;
+    Ok(link)
+}
+
+/// get an invitation that is pending
+pub async fn get_pending(data: &Data, token: &str) -> ServiceResult<Invite> {
+    let invite = data.db.get_invite(token).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if invite.status(now) != InviteStatus::Pending {
+        return Err(ServiceError::InviteNotFound);
+    }
+    Ok(invite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use awc::Client;
+
+    #[actix_rt::test]
+    async fn invitation_email_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        invitation_email_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn invitation_email_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        invitation_email_works(data).await;
+    }
+
+    async fn invitation_email_works(data: crate::ArcData) {
+        const TO_ADDR: &str = "Hello <realaravinth@localhost>";
+        const INVITATION_LINK: &str = "https://localhost/join?invite=abc";
+        const ORG: &str = "invitationemailorg";
+        invitation(&data, TO_ADDR, "admin", Some(ORG), INVITATION_LINK)
+            .await
+            .unwrap();
+
+        let client = Client::default();
+        let mut resp = client
+            .get("http://localhost:1080/email")
+            .send()
+            .await
+            .unwrap();
+        let emails: serde_json::Value = resp.json().await.unwrap();
+        let emails = emails.as_array().unwrap();
+        let body = emails
+            .iter()
+            .map(|e| e["html"].to_string())
+            .find(|html| html.contains(INVITATION_LINK))
+            .unwrap();
+        assert!(body.contains(ORG));
+    }
+}
@@ -3,4 +3,27 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+pub mod alert;
+pub mod change;
+pub mod digest;
+pub mod invitation;
+pub mod queue;
+pub mod registration;
+pub mod relays;
+pub mod security;
+pub mod templates;
+pub mod transports;
 pub mod verification;
+
+use crate::Data;
+
+/// absolute URL of `path` on this instance, for links in emails
+fn instance_url(data: &Data, path: &str) -> String {
+    let scheme = if data.settings.server.has_tls() {
+        "https"
+    } else {
+        "http"
+    };
+    let domain = data.settings.server.domain.trim_end_matches('/');
+    format!("{scheme}://{domain}{path}")
+}
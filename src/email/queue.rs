@@ -0,0 +1,591 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Outgoing email queue
+//!
+//! Emails are written to an outbox in the database before they are sent, so
+//! that failures of the SMTP relays don't fail the requests that send them and
+//! emails aren't lost on restarts. [MailQueue] sends emails right away when a
+//! send slot is free; [MailSender] sends the others and retries failed
+//! attempts with exponential backoff, starting at [BACKOFF]. Emails that
+//! couldn't be sent in `smtp.max_attempts` attempts, or that a relay rejected
+//! for good, are moved to the dead letters, where administrators can retry
+//! them.
+//!
+//! SMTP providers blacklist senders that burst, so sends are spaced out to at
+//! most `smtp.max_per_minute`, and emails are rejected once `smtp.max_queue`
+//! emails are in the outbox. Emails to a recipient that was mailed less than
+//! `smtp.recipient_cooldown` seconds ago are rejected, so that a single
+//! address can't be flooded.
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::{AddOutboxEmail, OutboxEmail};
+use lettre::address::Envelope;
+use lettre::{Address, Message};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::email::relays::Mailer;
+use crate::errors::*;
+use crate::jobs::MAIL_QUEUE_JOB;
+use crate::settings::Smtp;
+use crate::{AppData, Data};
+
+/// window over which `max_per_minute` is enforced
+const WINDOW: Duration = Duration::from_secs(60);
+/// delay before the second attempt at sending an email. It doubles with every
+/// attempt after that
+pub const BACKOFF: Duration = Duration::from_secs(30);
+/// longest delay between attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+/// time after which an attempt that didn't finish, because the instance went
+/// down, is given up on and the email is attempted again
+const LEASE: Duration = Duration::from_secs(5 * 60);
+/// interval, in seconds, at which the outbox is checked for due emails
+const POLL_INTERVAL: u64 = 5;
+/// maximum number of emails attempted per check
+const BATCH: usize = 50;
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// delay after failed attempt number `attempts`
+fn backoff(attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+/// envelope of an email of the outbox
+fn envelope(email: &OutboxEmail) -> Result<Envelope, String> {
+    let from = email
+        .sender
+        .as_deref()
+        .map(str::parse::<Address>)
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let to = email
+        .recipients
+        .iter()
+        .map(|r| r.parse::<Address>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Envelope::new(from, to).map_err(|e| e.to_string())
+}
+
+/// subject of formatted email `message`, as it appears in its headers
+pub fn subject(message: &str) -> Option<String> {
+    let mut headers = message.lines().take_while(|l| !l.is_empty());
+    let mut subject = headers
+        .find_map(|l| l.strip_prefix("Subject: "))?
+        .to_string();
+    // long subjects are folded over several lines
+    for line in headers.take_while(|l| l.starts_with([' ', '\t'])) {
+        subject.push_str(line);
+    }
+    Some(subject)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// state of the mail queue
+pub struct MailQueueMetrics {
+    /// maximum number of emails sent per minute; 0 when unlimited
+    pub max_per_minute: usize,
+    /// number of emails waiting in the outbox, as of the last check
+    pub depth: usize,
+    /// number of emails sent
+    pub sent: usize,
+    /// number of attempts that the SMTP server didn't accept
+    pub failed: usize,
+    /// number of emails that had to wait for a send slot
+    pub throttled: usize,
+    /// number of emails rejected because the recipient was in cooldown
+    pub suppressed: usize,
+    /// number of emails rejected because the queue was full
+    pub rejected: usize,
+    /// number of emails that were given up on and moved to the dead letters
+    pub dead: usize,
+}
+
+pub struct MailQueue {
+    max_per_minute: usize,
+    max_queue: usize,
+    max_attempts: u32,
+    cooldown: Duration,
+    /// send slots taken within the last minute
+    slots: Mutex<VecDeque<Instant>>,
+    /// last time each recipient was mailed
+    recipients: Mutex<HashMap<String, Instant>>,
+    depth: AtomicUsize,
+    sent: AtomicUsize,
+    failed: AtomicUsize,
+    throttled: AtomicUsize,
+    suppressed: AtomicUsize,
+    rejected: AtomicUsize,
+    dead: AtomicUsize,
+}
+
+impl MailQueue {
+    pub fn new(smtp: Option<&Smtp>) -> Self {
+        let (max_per_minute, max_queue, max_attempts, cooldown) = smtp
+            .map(|s| {
+                (
+                    s.max_per_minute,
+                    s.max_queue,
+                    s.max_attempts,
+                    s.recipient_cooldown,
+                )
+            })
+            .unwrap_or_default();
+        Self {
+            max_per_minute,
+            max_queue,
+            max_attempts,
+            cooldown: Duration::from_secs(cooldown),
+            slots: Mutex::new(VecDeque::new()),
+            recipients: Mutex::new(HashMap::new()),
+            depth: AtomicUsize::new(0),
+            sent: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            throttled: AtomicUsize::new(0),
+            suppressed: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            dead: AtomicUsize::new(0),
+        }
+    }
+
+    /// mark `recipients` mailed at `now`. Fails without marking any of them
+    /// when one is in cooldown
+    fn reserve_recipients(&self, recipients: &[String], now: Instant) -> bool {
+        if self.cooldown.is_zero() {
+            return true;
+        }
+        let mut mailed = self.recipients.lock().unwrap();
+        mailed.retain(|_, at| now.duration_since(*at) < self.cooldown);
+        if recipients.iter().any(|r| mailed.contains_key(r)) {
+            return false;
+        }
+        for r in recipients.iter() {
+            mailed.insert(r.clone(), now);
+        }
+        true
+    }
+
+    /// take a send slot at `now`, or get the time until one frees up
+    fn next_slot(&self, now: Instant) -> Option<Duration> {
+        if self.max_per_minute == 0 {
+            return None;
+        }
+        let mut slots = self.slots.lock().unwrap();
+        while slots
+            .front()
+            .map_or(false, |at| now.duration_since(*at) >= WINDOW)
+        {
+            slots.pop_front();
+        }
+        if slots.len() < self.max_per_minute {
+            slots.push_back(now);
+            None
+        } else {
+            Some(WINDOW - now.duration_since(slots[0]))
+        }
+    }
+
+    /// add `email` to the outbox, and send it if a send slot is free. Failed
+    /// attempts are retried by [MailSender] instead of being returned
+    pub async fn send(
+        &self,
+        data: &Data,
+        mailer: &Mailer,
+        email: Message,
+    ) -> ServiceResult<()> {
+        let depth = data.db.count_outbox_emails().await?;
+        self.depth.store(depth, Ordering::SeqCst);
+        if self.max_queue > 0 && depth >= self.max_queue {
+            self.rejected.fetch_add(1, Ordering::SeqCst);
+            log::warn!("Mail queue is full, rejecting email");
+            return Err(ServiceError::MailQueueFull);
+        }
+
+        let to: Vec<String> = email
+            .envelope()
+            .to()
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        let recipients: Vec<String> = to.iter().map(|r| r.to_lowercase()).collect();
+        if !self.reserve_recipients(&recipients, Instant::now()) {
+            self.suppressed.fetch_add(1, Ordering::SeqCst);
+            return Err(ServiceError::MailRecipientCooldown);
+        }
+
+        let now = now();
+        let send_now = self.next_slot(Instant::now()).is_none();
+        let sender = email.envelope().from().map(|a| a.to_string());
+        let message = String::from_utf8_lossy(&email.formatted()).into_owned();
+        let add = AddOutboxEmail {
+            sender: sender.as_deref(),
+            recipients: &to,
+            message: &message,
+            // emails that are sent right away are claimed below
+            next_attempt_at: if send_now {
+                now + LEASE.as_secs() as i64
+            } else {
+                now
+            },
+            created_at: now,
+        };
+        let id = data.db.add_outbox_email(&add).await?;
+        if !send_now {
+            self.throttled.fetch_add(1, Ordering::SeqCst);
+            self.depth.fetch_add(1, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        let email = OutboxEmail {
+            id,
+            sender,
+            recipients: to,
+            message,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+        };
+        self.deliver(data, mailer, &email).await
+    }
+
+    /// attempt to send `email` from the outbox. Failed attempts are recorded
+    /// on the email
+    async fn deliver(
+        &self,
+        data: &Data,
+        mailer: &Mailer,
+        email: &OutboxEmail,
+    ) -> ServiceResult<()> {
+        let now = now();
+        let lease_until = now + LEASE.as_secs() as i64;
+        if !data
+            .db
+            .claim_outbox_email(email.id, email.attempts, lease_until)
+            .await?
+        {
+            // attempted by another instance in the meantime
+            return Ok(());
+        }
+        let attempts = email.attempts + 1;
+
+        let res = match envelope(email) {
+            Ok(envelope) => mailer
+                .send(&envelope, email.message.as_bytes())
+                .await
+                .map_err(|e| (e.to_string(), e.is_transient())),
+            Err(e) => Err((e, false)),
+        };
+        let (error, transient) = match res {
+            Ok(_) => {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+                data.db.delete_outbox_email(email.id).await?;
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        self.failed.fetch_add(1, Ordering::SeqCst);
+        if transient && attempts < self.max_attempts {
+            let next_attempt_at = now + backoff(attempts).as_secs() as i64;
+            log::warn!(
+                "Unable to send email {}, attempt {attempts} of {}: {error}",
+                email.id,
+                self.max_attempts
+            );
+            data.db
+                .fail_outbox_email(email.id, &error, Some(next_attempt_at))
+                .await?;
+        } else {
+            self.dead.fetch_add(1, Ordering::SeqCst);
+            log::error!(
+                "Giving up on email {} after {attempts} attempts: {error}",
+                email.id
+            );
+            data.db.fail_outbox_email(email.id, &error, None).await?;
+        }
+        Ok(())
+    }
+
+    /// send emails of the outbox that are due by unix timestamp `now`, while
+    /// send slots are free
+    pub async fn process(
+        &self,
+        data: &Data,
+        mailer: &Mailer,
+        now: i64,
+    ) -> ServiceResult<()> {
+        let depth = data.db.count_outbox_emails().await?;
+        self.depth.store(depth, Ordering::SeqCst);
+        for email in data.db.get_due_outbox_emails(now, BATCH).await? {
+            if self.next_slot(Instant::now()).is_some() {
+                break;
+            }
+            self.deliver(data, mailer, &email).await?;
+        }
+        Ok(())
+    }
+
+    /// get state of the queue
+    pub fn metrics(&self) -> MailQueueMetrics {
+        MailQueueMetrics {
+            max_per_minute: self.max_per_minute,
+            depth: self.depth.load(Ordering::SeqCst),
+            sent: self.sent.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            throttled: self.throttled.load(Ordering::SeqCst),
+            suppressed: self.suppressed.load(Ordering::SeqCst),
+            rejected: self.rejected.load(Ordering::SeqCst),
+            dead: self.dead.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// sends emails of the outbox that weren't sent right away, and retries
+/// failed attempts
+pub struct MailSender {
+    tx: Sender<()>,
+}
+
+impl MailSender {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs.register(MAIL_QUEUE_JOB, POLL_INTERVAL);
+        let mut exit = false;
+        let fut = async move {
+            let mailer = match data.mailer.as_ref() {
+                Some(mailer) => mailer,
+                None => return,
+            };
+            loop {
+                for _ in 0..POLL_INTERVAL {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = data
+                    .mail_queue
+                    .process(&data, mailer, started.unix_timestamp())
+                    .await;
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while sending emails of the outbox: {:?}", err);
+                }
+                data.jobs
+                    .finished(MAIL_QUEUE_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smtp() -> Smtp {
+        Smtp {
+            from: "admin@localhost".into(),
+            reply: "admin@localhost".into(),
+            transport: crate::settings::MailTransport::Smtp,
+            url: "127.0.0.1".into(),
+            username: "admin".into(),
+            password: "password".into(),
+            port: 10025,
+            max_per_minute: 2,
+            max_queue: 10,
+            recipient_cooldown: 30,
+            max_attempts: 8,
+            templates_dir: None,
+            sendmail_command: None,
+            api_key: None,
+            api_secret: None,
+            api_region: None,
+            api_domain: None,
+            api_url: None,
+            relays: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn backoff_works() {
+        assert_eq!(backoff(1), BACKOFF);
+        assert_eq!(backoff(3), BACKOFF * 4);
+        assert_eq!(backoff(30), MAX_BACKOFF);
+
+        let email = Message::builder()
+            .from("admin@localhost".parse().unwrap())
+            .to("user@localhost".parse().unwrap())
+            .subject("a subject ".repeat(10))
+            .body(String::from("Subject: body"))
+            .unwrap();
+        let message = String::from_utf8(email.formatted()).unwrap();
+        assert_eq!(
+            subject(&message).unwrap().trim(),
+            "a subject ".repeat(10).trim()
+        );
+    }
+
+    #[test]
+    fn rate_limit_works() {
+        let queue = MailQueue::new(Some(&smtp()));
+        let now = Instant::now();
+        assert!(queue.next_slot(now).is_none());
+        assert!(queue.next_slot(now + Duration::from_secs(10)).is_none());
+        assert_eq!(
+            queue.next_slot(now + Duration::from_secs(20)),
+            Some(Duration::from_secs(40))
+        );
+        assert!(queue.next_slot(now + WINDOW).is_none());
+
+        let queue = MailQueue::new(None);
+        for _ in 0..100 {
+            assert!(queue.next_slot(now).is_none());
+        }
+    }
+
+    #[test]
+    fn recipient_cooldown_works() {
+        let queue = MailQueue::new(Some(&smtp()));
+        let now = Instant::now();
+        let a = vec!["a@localhost".to_string()];
+        let ab = vec!["a@localhost".to_string(), "b@localhost".to_string()];
+        assert!(queue.reserve_recipients(&a, now));
+        assert!(!queue.reserve_recipients(&a, now + Duration::from_secs(29)));
+        // none of the recipients are marked when one is in cooldown
+        assert!(!queue.reserve_recipients(&ab, now + Duration::from_secs(29)));
+        assert!(queue.reserve_recipients(&["b@localhost".to_string()], now));
+        assert!(queue.reserve_recipients(&a, now + Duration::from_secs(30)));
+    }
+
+    #[actix_rt::test]
+    async fn outbox_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        outbox_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn outbox_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        outbox_works(data).await;
+    }
+
+    /// email of the test in the outbox
+    async fn queued(data: &Data, to: &str) -> Option<OutboxEmail> {
+        let emails = data
+            .db
+            .get_due_outbox_emails(now() + 7 * 24 * 60 * 60, 1000)
+            .await
+            .unwrap();
+        emails.into_iter().find(|e| e.recipients == [to])
+    }
+
+    async fn outbox_works(data: crate::ArcData) {
+        const TO: &str = "outboxuser@localhost";
+
+        // left over by earlier runs
+        while let Some(email) = queued(&data, TO).await {
+            data.db.delete_outbox_email(email.id).await.unwrap();
+        }
+        for email in data.db.get_dead_letters().await.unwrap() {
+            if email.recipients == [TO] {
+                data.db.delete_outbox_email(email.id).await.unwrap();
+            }
+        }
+
+        // relay that can't be reached
+        let mut settings = data.settings.clone();
+        let smtp = settings.smtp.as_mut().unwrap();
+        smtp.port = 1;
+        smtp.relays = Vec::new();
+        smtp.max_attempts = 2;
+        let down = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let down_mailer = down.mailer.as_ref().unwrap();
+
+        let email = Message::builder()
+            .from("admin@localhost".parse().unwrap())
+            .to(TO.parse().unwrap())
+            .subject("outbox")
+            .body(String::from("outbox"))
+            .unwrap();
+        // failures aren't returned, and the email is kept to be retried
+        down.mail_queue
+            .send(&down, down_mailer, email)
+            .await
+            .unwrap();
+        let email = queued(&down, TO).await.unwrap();
+        assert_eq!(email.attempts, 1);
+        assert!(email.last_error.is_some());
+        assert_eq!(subject(&email.message).as_deref(), Some("outbox"));
+
+        // and given up on after `max_attempts`
+        down.mail_queue
+            .deliver(&down, down_mailer, &email)
+            .await
+            .unwrap();
+        assert!(queued(&down, TO).await.is_none());
+        let metrics = down.mail_queue.metrics();
+        assert_eq!((metrics.failed, metrics.dead), (2, 1));
+        let dead = data.db.get_dead_letters().await.unwrap();
+        let dead = dead.iter().find(|e| e.id == email.id).unwrap();
+        assert_eq!(dead.attempts, 2);
+
+        // dead letters that are retried are sent again
+        data.db.retry_dead_letter(email.id, now()).await.unwrap();
+        let email = queued(&data, TO).await.unwrap();
+        assert_eq!(email.attempts, 0);
+        let mailer = data.mailer.as_ref().unwrap();
+        data.mail_queue
+            .deliver(&data, mailer, &email)
+            .await
+            .unwrap();
+        assert!(queued(&data, TO).await.is_none());
+        assert_eq!(data.mail_queue.metrics().sent, 1);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Registration approval
+//!
+//! When `require_registration_approval` is set, new accounts can't sign in
+//! until an administrator approves them. Applicants are notified of the
+//! decision; rejected accounts are deleted.
+use sailfish::TemplateOnce;
+
+use super::templates::{Email, Template};
+use crate::errors::*;
+use crate::Data;
+
+const PAGE: &str = "Registration";
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "email/registration/index.html")]
+struct IndexPage<'a> {
+    username: &'a str,
+    domain: &'a str,
+    login_link: &'a str,
+    approved: bool,
+}
+
+async fn decision(
+    data: &Data,
+    to: &str,
+    username: &str,
+    approved: bool,
+) -> ServiceResult<()> {
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
+        let domain = &data.settings.server.domain;
+        let login_link = super::instance_url(data, crate::PAGES.auth.login);
+
+        let (subject, text) = if approved {
+            (
+                "[mCaptcha] Your registration was approved",
+                format!(
+                    "Your registration as {username} on {domain} was approved. You can sign in now.
+
+SIGN IN: {login_link}"
+                ),
+            )
+        } else {
+            (
+                "[mCaptcha] Your registration was declined",
+                format!(
+                    "An administrator of {domain} declined your registration as {username} and your account was deleted.
+Please contact the administrator if you think this was a mistake."
+                ),
+            )
+        };
+        let plain_text = format!(
+            "
+{text}
+
+With best regards,
+Admin
+instance: {domain}
+project website: {}",
+            crate::PKG_HOMEPAGE
+        );
+
+        let html = IndexPage {
+            username,
+            domain,
+            login_link: &login_link,
+            approved,
+        }
+        .render_once()
+        .unwrap();
+
+        let (template, vars) = if approved {
+            (
+                Template::RegistrationApproved,
+                vec![("username", username), ("login_link", login_link.as_str())],
+            )
+        } else {
+            (Template::RegistrationDeclined, vec![("username", username)])
+        };
+        let email = Email {
+            subject: subject.into(),
+            text: plain_text,
+            html,
+        }
+        .customize(smtp, domain, template, &vars)
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+/// notify an applicant of the decision on their registration. Applicants
+/// without an email address aren't notified
+pub async fn notify(data: &Data, username: &str, approved: bool) -> ServiceResult<()> {
+    if let Some(email) = data.db.get_email(username).await? {
+        decision(data, &email, username, approved).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use awc::Client;
+
+    #[actix_rt::test]
+    async fn registration_email_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        registration_email_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn registration_email_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        registration_email_works(data).await;
+    }
+
+    async fn registration_email_works(data: crate::ArcData) {
+        const TO_ADDR: &str = "Hello <realaravinth@localhost>";
+        const USERNAME: &str = "registrationemailuser";
+        decision(&data, TO_ADDR, USERNAME, true).await.unwrap();
+
+        let client = Client::default();
+        let mut resp = client
+            .get("http://localhost:1080/email")
+            .send()
+            .await
+            .unwrap();
+        let emails: serde_json::Value = resp.json().await.unwrap();
+        let emails = emails.as_array().unwrap();
+        let body = emails
+            .iter()
+            .map(|e| e["html"].to_string())
+            .find(|html| html.contains(USERNAME))
+            .unwrap();
+        assert!(body.contains(crate::PAGES.auth.login));
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Relays with failover
+//!
+//! Emails are sent through the relays of the [transport](super::transports).
+//! With SMTP, they are the primary relay, `smtp.url`, and the relays in
+//! `smtp.relays`, tried in order when the relays before them can't be reached
+//! or reject their credentials; other transports are a single relay. Relays
+//! that failed are tried last for [RETRY_AFTER], so that an outage doesn't
+//! delay every email by a connection timeout. Emails that a relay rejects for
+//! other reasons, like an invalid recipient, aren't retried on the other
+//! relays.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lettre::address::Envelope;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use super::transports::{self, Transport, TransportError};
+use crate::settings::Smtp;
+
+/// time for which a relay that failed is tried after the others
+pub const RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// health of a relay
+pub struct RelayHealth {
+    /// host and port of SMTP relays, name of the transport otherwise
+    pub relay: String,
+    /// did the last email sent through the relay go through
+    pub healthy: bool,
+    /// number of emails that the relay accepted
+    pub sent: usize,
+    /// number of emails that couldn't be sent through the relay
+    pub failed: usize,
+    pub last_error: Option<String>,
+    /// time of the last failure, as a unix timestamp
+    pub last_failure: Option<i64>,
+}
+
+struct Relay {
+    transport: Box<dyn Transport>,
+    health: Mutex<RelayHealth>,
+    failed_at: Mutex<Option<Instant>>,
+}
+
+impl Relay {
+    fn new(name: String, transport: Box<dyn Transport>) -> Self {
+        Self {
+            transport,
+            health: Mutex::new(RelayHealth {
+                relay: name,
+                healthy: true,
+                ..Default::default()
+            }),
+            failed_at: Mutex::new(None),
+        }
+    }
+
+    fn name(&self) -> String {
+        self.health.lock().unwrap().relay.clone()
+    }
+
+    /// did the relay fail within [RETRY_AFTER] of `now`
+    fn is_backing_off(&self, now: Instant) -> bool {
+        self.failed_at
+            .lock()
+            .unwrap()
+            .map_or(false, |at| now.duration_since(at) < RETRY_AFTER)
+    }
+
+    fn succeeded(&self) {
+        *self.failed_at.lock().unwrap() = None;
+        let mut health = self.health.lock().unwrap();
+        health.healthy = true;
+        health.sent += 1;
+    }
+
+    fn failed(&self, e: &TransportError) {
+        *self.failed_at.lock().unwrap() = Some(Instant::now());
+        let mut health = self.health.lock().unwrap();
+        health.healthy = false;
+        health.failed += 1;
+        health.last_error = Some(e.to_string());
+        health.last_failure = Some(OffsetDateTime::now_utc().unix_timestamp());
+    }
+}
+
+/// transport that sends emails through the configured relays
+pub struct Mailer {
+    relays: Vec<Relay>,
+}
+
+impl Mailer {
+    pub fn new(smtp: &Smtp) -> Self {
+        let relays = transports::from_settings(smtp)
+            .into_iter()
+            .map(|(name, transport)| Relay::new(name, transport))
+            .collect();
+        Self { relays }
+    }
+
+    /// relays in the order in which they are tried at `now`: relays that
+    /// failed recently go last
+    fn order(&self, now: Instant) -> Vec<&Relay> {
+        let (mut up, down): (Vec<&Relay>, Vec<&Relay>) =
+            self.relays.iter().partition(|r| !r.is_backing_off(now));
+        up.extend(down);
+        up
+    }
+
+    /// send formatted email `message` to the recipients of `envelope`, failing
+    /// over to the next relay on connection and authentication failures. The
+    /// error of the last relay tried is returned when none of them accept the
+    /// email
+    pub async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError> {
+        let relays = self.order(Instant::now());
+        let mut last = None;
+        for relay in relays {
+            match relay.transport.send(envelope, message).await {
+                Ok(_) => {
+                    relay.succeeded();
+                    return Ok(());
+                }
+                Err(e) => {
+                    relay.failed(&e);
+                    let fail_over = e.is_transient();
+                    log::warn!("Unable to send email through {}: {e}", relay.name());
+                    last = Some(e);
+                    if !fail_over {
+                        break;
+                    }
+                }
+            }
+        }
+        Err(last.expect("at least the primary relay is configured"))
+    }
+
+    /// connect to each relay, returning the relay and whether it accepted the
+    /// connection
+    pub async fn test_connections(&self) -> Vec<(String, Result<bool, TransportError>)> {
+        let mut results = Vec::with_capacity(self.relays.len());
+        for relay in self.relays.iter() {
+            results.push((relay.name(), relay.transport.test_connection().await));
+        }
+        results
+    }
+
+    /// health of the relays, in order of priority
+    pub fn health(&self) -> Vec<RelayHealth> {
+        self.relays
+            .iter()
+            .map(|r| r.health.lock().unwrap().clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{MailTransport, SmtpRelay};
+
+    fn smtp() -> Smtp {
+        Smtp {
+            from: "admin@localhost".into(),
+            reply: "admin@localhost".into(),
+            transport: MailTransport::Smtp,
+            url: "127.0.0.1".into(),
+            username: "admin".into(),
+            password: "password".into(),
+            port: 10025,
+            max_per_minute: 60,
+            max_queue: 500,
+            recipient_cooldown: 30,
+            max_attempts: 8,
+            templates_dir: None,
+            sendmail_command: None,
+            api_key: None,
+            api_secret: None,
+            api_region: None,
+            api_domain: None,
+            api_url: None,
+            relays: vec![SmtpRelay {
+                url: "127.0.0.2".into(),
+                port: 10025,
+                username: "admin".into(),
+                password: "password".into(),
+            }],
+        }
+    }
+
+    #[actix_rt::test]
+    async fn failed_relays_are_tried_last() {
+        let mailer = Mailer::new(&smtp());
+        let now = Instant::now();
+        let names = |relays: Vec<&Relay>| -> Vec<String> {
+            relays.iter().map(|r| r.name()).collect()
+        };
+        assert_eq!(
+            names(mailer.order(now)),
+            vec!["127.0.0.1:10025", "127.0.0.2:10025"]
+        );
+
+        *mailer.relays[0].failed_at.lock().unwrap() = Some(now);
+        assert_eq!(
+            names(mailer.order(now + Duration::from_secs(1))),
+            vec!["127.0.0.2:10025", "127.0.0.1:10025"]
+        );
+        // failed relays are back in order once they are retried
+        assert_eq!(
+            names(mailer.order(now + RETRY_AFTER)),
+            vec!["127.0.0.1:10025", "127.0.0.2:10025"]
+        );
+
+        let health = mailer.health();
+        assert_eq!(health.len(), 2);
+        assert!(health.iter().all(|h| h.healthy));
+
+        // other transports are a single relay
+        let mut smtp = smtp();
+        smtp.transport = MailTransport::Sendmail;
+        let names: Vec<String> = Mailer::new(&smtp)
+            .health()
+            .into_iter()
+            .map(|h| h.relay)
+            .collect();
+        assert_eq!(names, vec!["sendmail"]);
+    }
+}
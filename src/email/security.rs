@@ -0,0 +1,252 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Security alerts
+//!
+//! Users are emailed when their account is signed into from a device that it
+//! wasn't signed into from before, and when the account secret or the key of
+//! a sitekey is rotated. Devices are told apart by the IP address and user
+//! agent of the sign-in; the first sign-in of an account doesn't raise an
+//! alert. Alerts link to a page that signs out all sessions of the account
+//! and forgets its devices, so that the link works without signing in.
+use sailfish::TemplateOnce;
+use sha2::{Digest, Sha256};
+use sqlx::types::time::OffsetDateTime;
+
+use super::templates::{Email, Template};
+use crate::api::v1::account::sessions::runners::device;
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::Data;
+
+const PAGE: &str = "Security Alert";
+
+/// validity of session revocation links, in seconds
+pub const SESSION_REVOCATION_TTL: i64 = 60 * 60 * 24 * 7;
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "email/security-alert/index.html")]
+struct IndexPage<'a> {
+    heading: &'a str,
+    message: &'a str,
+    revocation_link: &'a str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// Event that users are alerted of
+pub enum SecurityEvent<'a> {
+    /// sign-in from a device that the account wasn't signed into from before
+    NewSignIn { ip: &'a str, user_agent: &'a str },
+    /// account secret was rotated
+    SecretRotated,
+    /// key of a sitekey was rotated
+    SitekeyRotated { description: &'a str, key: &'a str },
+}
+
+impl<'a> SecurityEvent<'a> {
+    pub fn heading(&self) -> &'static str {
+        match self {
+            Self::NewSignIn { .. } => "New sign-in to your account",
+            Self::SecretRotated => "Your account secret was changed",
+            Self::SitekeyRotated { .. } => "The key of your sitekey was changed",
+        }
+    }
+
+    pub fn message(&self, username: &str, domain: &str) -> String {
+        match self {
+            Self::NewSignIn { ip, user_agent } => format!(
+                "{username} on {domain} was signed into from {} at {ip}.",
+                device(user_agent)
+            ),
+            Self::SecretRotated => format!(
+                "The account secret of {username} on {domain} was changed. Servers that verify tokens with the previous secret will fail until they are updated."
+            ),
+            Self::SitekeyRotated { description, key } => format!(
+                "Sitekey {description} of {username} on {domain} was given the new key {key}. Widgets that use the previous key will fail until they are updated."
+            ),
+        }
+    }
+}
+
+/// identifier under which the device of a sign-in is stored
+pub fn device_id(ip: &str, user_agent: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(ip.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(user_agent.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+async fn mail(
+    data: &Data,
+    to: &str,
+    username: &str,
+    event: &SecurityEvent<'_>,
+    revocation_link: &str,
+) -> ServiceResult<()> {
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
+        let domain = &data.settings.server.domain;
+        let heading = event.heading();
+        let message = event.message(username, domain);
+
+        let plain_text = format!(
+            "
+{message}
+
+If this wasn't you, sign out all sessions of your account and change your password.
+
+SIGN OUT ALL SESSIONS: {revocation_link}
+
+With best regards,
+Admin
+instance: {domain}
+project website: {}",
+            crate::PKG_HOMEPAGE
+        );
+
+        let html = IndexPage {
+            heading,
+            message: &message,
+            revocation_link,
+        }
+        .render_once()
+        .unwrap();
+
+        let vars = [
+            ("username", username),
+            ("heading", heading),
+            ("message", message.as_str()),
+            ("revocation_link", revocation_link),
+        ];
+        let email = Email {
+            subject: format!("[mCaptcha] {heading}"),
+            text: plain_text,
+            html,
+        }
+        .customize(smtp, domain, Template::SecurityAlert, &vars)
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+/// email alert of `event` to `username`, with a link that signs out all
+/// sessions of the account. Users without an email address aren't emailed
+pub async fn send(
+    data: &Data,
+    username: &str,
+    event: &SecurityEvent<'_>,
+) -> ServiceResult<()> {
+    if data.mailer.is_none() {
+        return Ok(());
+    }
+    let email = match data.db.get_email(username).await? {
+        Some(email) => email,
+        None => return Ok(()),
+    };
+    let token = get_random(32);
+    data.db.add_session_revocation(username, &token).await?;
+    let revocation_link =
+        super::instance_url(data, &crate::PAGES.auth.get_revoke_sessions(&token));
+    mail(data, &email, username, event, &revocation_link).await
+}
+
+/// record device of a sign-in of `username` and alert the user when the
+/// account wasn't signed into from it before
+pub async fn signed_in(
+    data: &Data,
+    username: &str,
+    ip: &str,
+    user_agent: &str,
+) -> ServiceResult<()> {
+    // first sign-in of the account
+    let known = data.db.count_known_devices(username).await? > 0;
+    let new = data
+        .db
+        .add_known_device(username, &device_id(ip, user_agent))
+        .await?;
+    if known && new {
+        send(data, username, &SecurityEvent::NewSignIn { ip, user_agent }).await?;
+    }
+    Ok(())
+}
+
+/// consume session revocation token, sign out all sessions of its user and
+/// forget their devices
+pub async fn revoke_sessions(data: &Data, token: &str) -> ServiceResult<()> {
+    let r = data.db.consume_session_revocation(token).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if now - r.created_at > SESSION_REVOCATION_TTL {
+        return Err(ServiceError::SessionRevocationNotFound);
+    }
+    data.db.delete_user_sessions(&r.username).await?;
+    data.db.delete_known_devices(&r.username).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use awc::Client;
+
+    #[test]
+    fn device_id_works() {
+        assert_eq!(
+            device_id("127.0.0.1", "curl"),
+            device_id("127.0.0.1", "curl")
+        );
+        assert_ne!(
+            device_id("127.0.0.1", "curl"),
+            device_id("127.0.0.2", "curl")
+        );
+        assert_ne!(
+            device_id("127.0.0.1", "curl"),
+            device_id("127.0.0.1", "wget")
+        );
+    }
+
+    #[actix_rt::test]
+    async fn security_alert_email_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        security_alert_email_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn security_alert_email_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        security_alert_email_works(data).await;
+    }
+
+    async fn security_alert_email_works(data: crate::ArcData) {
+        const TO_ADDR: &str = "Hello <realaravinth@localhost>";
+        const USERNAME: &str = "securityalertemailuser";
+        const LINK: &str = "https://localhost/sessions/revoke/abc";
+        let event = SecurityEvent::SitekeyRotated {
+            description: "securityalertsitekey",
+            key: "securityalertkey",
+        };
+        mail(&data, TO_ADDR, USERNAME, &event, LINK).await.unwrap();
+
+        let client = Client::default();
+        let mut resp = client
+            .get("http://localhost:1080/email")
+            .send()
+            .await
+            .unwrap();
+        let emails: serde_json::Value = resp.json().await.unwrap();
+        let emails = emails.as_array().unwrap();
+        let body = emails
+            .iter()
+            .map(|e| e["html"].to_string())
+            .find(|html| html.contains(USERNAME))
+            .unwrap();
+        assert!(body.contains(LINK));
+        assert!(body.contains(event.heading()));
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Email templates that operators can override
+//!
+//! Emails are rendered from templates that are embedded at build time. When
+//! `smtp.templates_dir` is set, files in the directory of an email replace
+//! the parts of the email that they are named after:
+//!
+//! - `<email>/subject.txt`: subject
+//! - `<email>/index.txt`: plain text body
+//! - `<email>/index.html`: HTML body
+//!
+//! `{{ name }}` in the files is replaced by variable `name` of the email,
+//! HTML-escaped in `index.html`; [Template::variables] lists the variables of
+//! each email. Files are read when emails are sent, so that they can be
+//! changed without a restart. Parts without a file, or whose file can't be
+//! read or uses unknown variables, are rendered from the embedded templates.
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use lettre::message::{header, MultiPart, SinglePart};
+use lettre::Message;
+
+use crate::settings::Smtp;
+
+/// variables of all emails
+const COMMON_VARIABLES: [(&str, &str); 2] = [
+    ("domain", "domain of the instance"),
+    ("project_website", "website of the mCaptcha project"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// emails whose templates can be overridden
+pub enum Template {
+    Verification,
+    RegistrationApproved,
+    RegistrationDeclined,
+    Invitation,
+    Alert,
+    EmailChange,
+    EmailChangeNotice,
+    Digest,
+    SecurityAlert,
+}
+
+impl Template {
+    pub const ALL: [Self; 9] = [
+        Self::Verification,
+        Self::RegistrationApproved,
+        Self::RegistrationDeclined,
+        Self::Invitation,
+        Self::Alert,
+        Self::EmailChange,
+        Self::EmailChangeNotice,
+        Self::Digest,
+        Self::SecurityAlert,
+    ];
+
+    /// name of the directory of the email in `smtp.templates_dir`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Verification => "verification",
+            Self::RegistrationApproved => "registration_approved",
+            Self::RegistrationDeclined => "registration_declined",
+            Self::Invitation => "invitation",
+            Self::Alert => "alert",
+            Self::EmailChange => "email_change",
+            Self::EmailChangeNotice => "email_change_notice",
+            Self::Digest => "digest",
+            Self::SecurityAlert => "security_alert",
+        }
+    }
+
+    /// variables of the email and what they hold, besides those of all emails:
+    /// `domain` and `project_website`
+    pub fn variables(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Verification => {
+                &[("verification_link", "link that verifies the address")]
+            }
+            Self::RegistrationApproved => &[
+                ("username", "username of the account"),
+                ("login_link", "link to the sign in page"),
+            ],
+            Self::RegistrationDeclined => &[("username", "username of the account")],
+            Self::Invitation => &[
+                ("invitation_link", "signup link of the invitation"),
+                ("invited_by", "user that sent the invitation"),
+                ("org", "organization that the invitee joins; empty if none"),
+                ("days", "number of days until the invitation expires"),
+            ],
+            Self::Alert => &[
+                ("heading", "title of the alert"),
+                (
+                    "message",
+                    "description of the traffic that raised the alert",
+                ),
+                ("sitekey_link", "link to the sitekey"),
+            ],
+            Self::EmailChange => &[
+                ("username", "username of the account"),
+                ("new_email", "address that the account is changed to"),
+                ("confirmation_link", "link that confirms the change"),
+            ],
+            Self::EmailChangeNotice => &[
+                ("username", "username of the account"),
+                ("new_email", "address that the account is changed to"),
+                ("cancellation_link", "link that cancels the change"),
+            ],
+            Self::Digest => &[
+                ("username", "username of the subscriber"),
+                (
+                    "period",
+                    "period that the digest covers: day, week or month",
+                ),
+                ("summary", "plain text summary of the traffic of the period"),
+                ("settings_link", "link to the settings page"),
+            ],
+            Self::SecurityAlert => &[
+                ("username", "username of the account"),
+                ("heading", "title of the alert"),
+                ("message", "description of the event that raised the alert"),
+                ("revocation_link", "link that signs out all sessions"),
+            ],
+        }
+    }
+
+    fn has_variable(&self, name: &str) -> bool {
+        COMMON_VARIABLES
+            .iter()
+            .chain(self.variables().iter())
+            .any(|(n, _)| *n == name)
+    }
+}
+
+/// escape `s` for use in HTML text and attribute values
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// replace `{{ name }}` in `template` with the values of `vars`, HTML-escaped
+/// when `html` is set. Fails on unknown variables and unclosed placeholders
+fn substitute(
+    template: &str,
+    vars: &[(&str, &str)],
+    html: bool,
+) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "placeholder isn't closed with }}".to_string())?;
+        let name = after[..end].trim();
+        let value = vars
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| *v)
+            .ok_or_else(|| format!("unknown variable {name}"))?;
+        if html {
+            rendered.push_str(&escape_html(value));
+        } else {
+            rendered.push_str(value);
+        }
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// check the overrides in `dir` against the variables of their emails.
+/// Returns the problems found, by file
+pub fn check(dir: &str) -> Vec<(String, String)> {
+    let mut problems = Vec::new();
+    for template in Template::ALL.iter() {
+        let vars: Vec<(&str, &str)> = COMMON_VARIABLES
+            .iter()
+            .chain(template.variables().iter())
+            .map(|(name, _)| (*name, ""))
+            .collect();
+        for part in ["subject.txt", "index.txt", "index.html"] {
+            let path = Path::new(dir).join(template.name()).join(part);
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    if let Err(e) = substitute(&contents, &vars, false) {
+                        problems.push((path.display().to_string(), e));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => problems.push((path.display().to_string(), e.to_string())),
+            }
+        }
+    }
+    problems
+}
+
+/// parts of an email
+pub struct Email {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl Email {
+    /// replace the parts of the email that are overridden in
+    /// `smtp.templates_dir`. `vars` are the variables of `template`, without
+    /// those of all emails
+    pub fn customize(
+        mut self,
+        smtp: &Smtp,
+        domain: &str,
+        template: Template,
+        vars: &[(&str, &str)],
+    ) -> Self {
+        let dir = match smtp.templates_dir.as_ref() {
+            Some(dir) => Path::new(dir).join(template.name()),
+            None => return self,
+        };
+        debug_assert!(vars.iter().all(|(name, _)| template.has_variable(name)));
+        let mut all = vec![("domain", domain), ("project_website", crate::PKG_HOMEPAGE)];
+        all.extend_from_slice(vars);
+
+        for (part, file, html) in [
+            (&mut self.subject, "subject.txt", false),
+            (&mut self.text, "index.txt", false),
+            (&mut self.html, "index.html", true),
+        ] {
+            let path = dir.join(file);
+            let contents = match fs::read_to_string(&path) {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    log::error!("Unable to read email template {}: {e}", path.display());
+                    continue;
+                }
+            };
+            match substitute(&contents, &all, html) {
+                Ok(rendered) => *part = rendered,
+                Err(e) => log::error!(
+                    "Unable to render email template {}: {e}",
+                    path.display()
+                ),
+            }
+        }
+        self.subject = self.subject.trim().to_string();
+        self
+    }
+
+    /// message to `to`, sent from the instance's address
+    pub fn message(self, smtp: &Smtp, to: &str) -> Message {
+        let from = format!("mCaptcha Admin <{}>", smtp.from);
+        let reply_to = format!("mCaptcha Admin <{}>", smtp.reply);
+        Message::builder()
+            .from(from.parse().unwrap())
+            .reply_to(reply_to.parse().unwrap())
+            .to(to.parse().unwrap())
+            .subject(self.subject)
+            .multipart(
+                MultiPart::alternative() // This is composed of two parts.
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(header::ContentType::TEXT_PLAIN)
+                            .body(self.text), // Every message should have a plain text fallback.
+                    )
+                    .singlepart(
+                        SinglePart::builder()
+                            .header(header::ContentType::TEXT_HTML)
+                            .body(self.html),
+                    ),
+            )
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::v1::mcaptcha::get_random;
+
+    #[test]
+    fn substitute_works() {
+        let vars = [("username", "<b>alice</b>"), ("domain", "example.org")];
+        assert_eq!(
+            substitute("Hi {{ username }} of {{domain}}!", &vars, false).unwrap(),
+            "Hi <b>alice</b> of example.org!"
+        );
+        assert_eq!(
+            substitute("<p>{{ username }}</p>", &vars, true).unwrap(),
+            "<p>&lt;b&gt;alice&lt;/b&gt;</p>"
+        );
+        assert!(substitute("{{ password }}", &vars, false).is_err());
+        assert!(substitute("{{ username", &vars, false).is_err());
+    }
+
+    #[test]
+    fn overrides_work() {
+        let dir =
+            std::env::temp_dir().join(format!("mcaptcha-email-{}", get_random(16)));
+        let verification = dir.join(Template::Verification.name());
+        fs::create_dir_all(&verification).unwrap();
+        fs::write(verification.join("subject.txt"), "Verify {{ domain }}\n").unwrap();
+        fs::write(
+            verification.join("index.html"),
+            "<a href=\"{{ verification_link }}\">Verify</a>",
+        )
+        .unwrap();
+        // unknown variables fall back to the embedded template
+        fs::write(verification.join("index.txt"), "{{ password }}").unwrap();
+
+        let mut smtp = crate::tests::get_settings().smtp.unwrap();
+        let email = || Email {
+            subject: "subject".into(),
+            text: "text".into(),
+            html: "html".into(),
+        };
+        let vars = [("verification_link", "https://example.org/verify?a=1&b=2")];
+        let rendered =
+            email().customize(&smtp, "example.org", Template::Verification, &vars);
+        assert_eq!(rendered.subject, "subject");
+
+        smtp.templates_dir = Some(dir.to_str().unwrap().into());
+        let rendered =
+            email().customize(&smtp, "example.org", Template::Verification, &vars);
+        assert_eq!(rendered.subject, "Verify example.org");
+        assert_eq!(
+            rendered.html,
+            "<a href=\"https://example.org/verify?a=1&amp;b=2\">Verify</a>"
+        );
+        assert_eq!(rendered.text, "text");
+        // emails without overrides are left as they are
+        let rendered = email().customize(&smtp, "example.org", Template::Alert, &[]);
+        assert_eq!(rendered.subject, "subject");
+
+        let problems = check(dir.to_str().unwrap());
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].0.ends_with("index.txt"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
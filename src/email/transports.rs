@@ -0,0 +1,765 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Transports that emails are sent through
+//!
+//! `smtp.transport` picks how emails leave the instance:
+//!
+//! - `smtp`: SMTP relays, with [failover](super::relays)
+//! - `sendmail`: the local sendmail command, `smtp.sendmail_command`
+//! - `ses`, `mailgun` and `postmark`: the HTTP APIs of Amazon SES, Mailgun and
+//!   Postmark, for hosts that block outbound SMTP. They authenticate with
+//!   `smtp.api_key`, and SES with `smtp.api_secret` in `smtp.api_region` too
+//!
+//! SES and Mailgun take emails as they are formatted. Postmark takes their
+//! parts, so emails are [parsed](parse) back into them.
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::{Credentials, Mechanism};
+use lettre::{
+    AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use sha2::{Digest, Sha256};
+use sqlx::types::time::OffsetDateTime;
+use url::Url;
+
+use crate::settings::{MailTransport, Smtp, SmtpRelay};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// SMTP replies to failed authentication
+const AUTH_FAILURE_CODES: [&str; 3] = ["530", "534", "535"];
+/// timeout of requests to HTTP APIs
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+/// sendmail command used when `smtp.sendmail_command` is unset
+const SENDMAIL: &str = "sendmail";
+
+const MAILGUN_URL: &str = "https://api.mailgun.net";
+const POSTMARK_URL: &str = "https://api.postmarkapp.com";
+
+#[derive(Debug)]
+/// email that a transport couldn't send
+pub struct TransportError {
+    message: String,
+    transient: bool,
+}
+
+impl TransportError {
+    fn new(message: impl Into<String>, transient: bool) -> Self {
+        Self {
+            message: message.into(),
+            transient,
+        }
+    }
+
+    /// can the email be sent through the next relay, or later. Permanent
+    /// errors other than authentication failures are about the email itself
+    /// and would recur on every relay
+    pub fn is_transient(&self) -> bool {
+        self.transient
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for TransportError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        let transient = !e.is_permanent()
+            || e.status().map_or(false, |code| {
+                AUTH_FAILURE_CODES.contains(&code.to_string().as_str())
+            });
+        Self::new(e.to_string(), transient)
+    }
+}
+
+impl From<lettre::transport::sendmail::Error> for TransportError {
+    fn from(e: lettre::transport::sendmail::Error) -> Self {
+        Self::new(e.to_string(), true)
+    }
+}
+
+impl From<reqwest::Error> for TransportError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::new(e.to_string(), true)
+    }
+}
+
+/// send `req` and fail on unsuccessful responses. Rejected credentials and
+/// rate limits are transient, like server errors
+async fn send_request(
+    provider: &str,
+    req: RequestBuilder,
+) -> Result<(), TransportError> {
+    let resp = req.send().await?;
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = resp.text().await.unwrap_or_default();
+    let transient = status.is_server_error()
+        || matches!(
+            status,
+            StatusCode::UNAUTHORIZED
+                | StatusCode::FORBIDDEN
+                | StatusCode::TOO_MANY_REQUESTS
+        );
+    Err(TransportError::new(
+        format!("{provider} answered {status}: {}", body.trim()),
+        transient,
+    ))
+}
+
+fn http_client() -> Client {
+    Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+        .expect("HTTP client can be built")
+}
+
+#[async_trait]
+/// way of sending emails
+pub trait Transport: Send + Sync {
+    /// send formatted email `message` to the recipients of `envelope`
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError>;
+
+    /// check that the transport can be reached with its credentials
+    async fn test_connection(&self) -> Result<bool, TransportError>;
+}
+
+/// transports configured in `smtp`, by name, in order of priority
+pub fn from_settings(smtp: &Smtp) -> Vec<(String, Box<dyn Transport>)> {
+    let only =
+        |transport: Box<dyn Transport>| vec![(smtp.transport.to_string(), transport)];
+    match smtp.transport {
+        MailTransport::Smtp => smtp
+            .all_relays()
+            .iter()
+            .map(|relay| {
+                let name = format!("{}:{}", relay.url, relay.port);
+                let transport: Box<dyn Transport> = Box::new(SmtpTransport::new(relay));
+                (name, transport)
+            })
+            .collect(),
+        MailTransport::Sendmail => only(Box::new(SendmailTransport::new(smtp))),
+        MailTransport::Ses => only(Box::new(SesTransport::new(smtp))),
+        MailTransport::Mailgun => only(Box::new(MailgunTransport::new(smtp))),
+        MailTransport::Postmark => only(Box::new(PostmarkTransport::new(smtp))),
+    }
+}
+
+/// SMTP relay
+pub struct SmtpTransport(AsyncSmtpTransport<Tokio1Executor>);
+
+impl SmtpTransport {
+    pub fn new(relay: &SmtpRelay) -> Self {
+        let creds = Credentials::new(relay.username.clone(), relay.password.clone());
+        let transport =
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&relay.url)
+                .port(relay.port)
+                .credentials(creds)
+                .authentication(vec![
+                    Mechanism::Login,
+                    Mechanism::Xoauth2,
+                    Mechanism::Plain,
+                ])
+                .build();
+        Self(transport)
+    }
+}
+
+#[async_trait]
+impl Transport for SmtpTransport {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError> {
+        self.0.send_raw(envelope, message).await?;
+        Ok(())
+    }
+
+    async fn test_connection(&self) -> Result<bool, TransportError> {
+        Ok(self.0.test_connection().await?)
+    }
+}
+
+/// local sendmail command
+pub struct SendmailTransport {
+    command: String,
+    transport: AsyncSendmailTransport<Tokio1Executor>,
+}
+
+impl SendmailTransport {
+    pub fn new(smtp: &Smtp) -> Self {
+        let command = smtp
+            .sendmail_command
+            .clone()
+            .unwrap_or_else(|| SENDMAIL.into());
+        let transport = AsyncSendmailTransport::new_with_command(&command);
+        Self { command, transport }
+    }
+}
+
+/// is `command` an executable file, looked up in `PATH` when it isn't a path
+fn find_program(command: &str) -> bool {
+    if command.contains('/') {
+        return Path::new(command).is_file();
+    }
+    env::var_os("PATH").map_or(false, |path| {
+        env::split_paths(&path).any(|dir| dir.join(command).is_file())
+    })
+}
+
+#[async_trait]
+impl Transport for SendmailTransport {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError> {
+        self.transport.send_raw(envelope, message).await?;
+        Ok(())
+    }
+
+    /// sendmail can't be checked without sending an email, so the command is
+    /// only looked up
+    async fn test_connection(&self) -> Result<bool, TransportError> {
+        Ok(find_program(&self.command))
+    }
+}
+
+/// Amazon SES, through its v2 API
+pub struct SesTransport {
+    client: Client,
+    url: String,
+    region: String,
+    key: String,
+    secret: String,
+}
+
+impl SesTransport {
+    pub fn new(smtp: &Smtp) -> Self {
+        let region = smtp.api_region.clone().unwrap_or_default();
+        let url = smtp
+            .api_url
+            .clone()
+            .unwrap_or_else(|| format!("https://email.{region}.amazonaws.com"));
+        Self {
+            client: http_client(),
+            url: url.trim_end_matches('/').into(),
+            region,
+            key: smtp.api_key.clone().unwrap_or_default(),
+            secret: smtp.api_secret.clone().unwrap_or_default(),
+        }
+    }
+
+    /// request to `path`, signed with AWS Signature Version 4 at `now`
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Vec<u8>,
+        now: OffsetDateTime,
+    ) -> RequestBuilder {
+        let url = Url::parse(&format!("{}{path}", self.url)).unwrap();
+        let mut host = url.host_str().unwrap_or_default().to_string();
+        if let Some(port) = url.port() {
+            host = format!("{host}:{port}");
+        }
+        let (authorization, date) = sign(
+            &self.key,
+            &self.secret,
+            &self.region,
+            method,
+            &host,
+            url.path(),
+            &body,
+            now,
+        );
+        let req = if method == "POST" {
+            self.client.post(url)
+        } else {
+            self.client.get(url)
+        };
+        req.header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("x-amz-date", date)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+            .body(body)
+    }
+}
+
+/// AWS Signature Version 4 signing key of `secret` for `service` on `date`,
+/// as `YYYYMMDD`
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let hmac = |key: &[u8], data: &str| {
+        let mut mac =
+            HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+        mac.update(data.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    };
+    let key = hmac(format!("AWS4{secret}").as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+/// `Authorization` and `x-amz-date` headers of an SES request, signed with
+/// AWS Signature Version 4. Only the host and date are signed
+#[allow(clippy::too_many_arguments)]
+fn sign(
+    key: &str,
+    secret: &str,
+    region: &str,
+    method: &str,
+    host: &str,
+    path: &str,
+    body: &[u8],
+    now: OffsetDateTime,
+) -> (String, String) {
+    const SERVICE: &str = "ses";
+    const SIGNED_HEADERS: &str = "host;x-amz-date";
+    let date = format!(
+        "{:04}{:02}{:02}",
+        now.year(),
+        u8::from(now.month()),
+        now.day()
+    );
+    let timestamp = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        now.hour(),
+        now.minute(),
+        now.second()
+    );
+
+    let canonical_request = format!(
+        "{method}\n{path}\n\nhost:{host}\nx-amz-date:{timestamp}\n\n{SIGNED_HEADERS}\n{}",
+        hex::encode(Sha256::digest(body))
+    );
+    let scope = format!("{date}/{region}/{SERVICE}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut mac =
+        HmacSha256::new_from_slice(&signing_key(secret, &date, region, SERVICE))
+            .expect("HMAC can take key of any size");
+    mac.update(string_to_sign.as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={key}/{scope}, SignedHeaders={SIGNED_HEADERS}, Signature={signature}"
+    );
+    (authorization, timestamp)
+}
+
+#[async_trait]
+impl Transport for SesTransport {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError> {
+        let to: Vec<String> = envelope.to().iter().map(|a| a.to_string()).collect();
+        let mut body = serde_json::json!({
+            "Content": { "Raw": { "Data": STANDARD.encode(message) } },
+            "Destination": { "ToAddresses": to },
+        });
+        if let Some(from) = envelope.from() {
+            body["FromEmailAddress"] = from.to_string().into();
+        }
+        let body = serde_json::to_vec(&body).unwrap();
+        let req = self.request(
+            "POST",
+            "/v2/email/outbound-emails",
+            body,
+            OffsetDateTime::now_utc(),
+        );
+        send_request("SES", req).await
+    }
+
+    async fn test_connection(&self) -> Result<bool, TransportError> {
+        let req = self.request(
+            "GET",
+            "/v2/email/account",
+            Vec::new(),
+            OffsetDateTime::now_utc(),
+        );
+        send_request("SES", req).await?;
+        Ok(true)
+    }
+}
+
+/// Mailgun
+pub struct MailgunTransport {
+    client: Client,
+    url: String,
+    domain: String,
+    key: String,
+}
+
+impl MailgunTransport {
+    pub fn new(smtp: &Smtp) -> Self {
+        let url = smtp.api_url.as_deref().unwrap_or(MAILGUN_URL);
+        // the sending domain is usually that of the instance's address
+        let domain = smtp.api_domain.clone().unwrap_or_else(|| {
+            smtp.from
+                .rsplit_once('@')
+                .map(|(_, domain)| domain.trim_end_matches('>').into())
+                .unwrap_or_default()
+        });
+        Self {
+            client: http_client(),
+            url: url.trim_end_matches('/').into(),
+            domain,
+            key: smtp.api_key.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for MailgunTransport {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError> {
+        let mut form = Form::new();
+        for to in envelope.to() {
+            form = form.text("to", to.to_string());
+        }
+        let message = Part::bytes(message.to_vec()).file_name("message.mime");
+        let req = self
+            .client
+            .post(format!("{}/v3/{}/messages.mime", self.url, self.domain))
+            .basic_auth("api", Some(&self.key))
+            .multipart(form.part("message", message));
+        send_request("Mailgun", req).await
+    }
+
+    async fn test_connection(&self) -> Result<bool, TransportError> {
+        let req = self
+            .client
+            .get(format!("{}/v3/domains/{}", self.url, self.domain))
+            .basic_auth("api", Some(&self.key));
+        send_request("Mailgun", req).await?;
+        Ok(true)
+    }
+}
+
+/// Postmark
+pub struct PostmarkTransport {
+    client: Client,
+    url: String,
+    token: String,
+}
+
+impl PostmarkTransport {
+    pub fn new(smtp: &Smtp) -> Self {
+        let url = smtp.api_url.as_deref().unwrap_or(POSTMARK_URL);
+        Self {
+            client: http_client(),
+            url: url.trim_end_matches('/').into(),
+            token: smtp.api_key.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for PostmarkTransport {
+    async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &[u8],
+    ) -> Result<(), TransportError> {
+        let parts = std::str::from_utf8(message)
+            .ok()
+            .and_then(parse)
+            .ok_or_else(|| TransportError::new("unable to parse email", false))?;
+        let to: Vec<String> = envelope.to().iter().map(|a| a.to_string()).collect();
+        let body = serde_json::json!({
+            "From": parts.from,
+            "ReplyTo": parts.reply_to,
+            "To": to.join(","),
+            "Subject": parts.subject,
+            "TextBody": parts.text,
+            "HtmlBody": parts.html,
+        });
+        let req = self
+            .client
+            .post(format!("{}/email", self.url))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .header("X-Postmark-Server-Token", &self.token)
+            .json(&body);
+        send_request("Postmark", req).await
+    }
+
+    async fn test_connection(&self) -> Result<bool, TransportError> {
+        let req = self
+            .client
+            .get(format!("{}/server", self.url))
+            .header(reqwest::header::ACCEPT, "application/json")
+            .header("X-Postmark-Server-Token", &self.token);
+        send_request("Postmark", req).await?;
+        Ok(true)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// parts of a formatted email
+pub struct Parts {
+    pub from: Option<String>,
+    pub reply_to: Option<String>,
+    pub subject: String,
+    pub text: Option<String>,
+    pub html: Option<String>,
+}
+
+/// headers, by lowercase name, and body of MIME entity `entity`
+fn split_entity(entity: &str) -> (Vec<(String, String)>, &str) {
+    let (head, body) = entity
+        .split_once("\r\n\r\n")
+        .or_else(|| entity.split_once("\n\n"))
+        .unwrap_or((entity, ""));
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        // long headers are folded over several lines
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().into()));
+        }
+    }
+    (headers, body)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+/// parameter `name` of header value `value`, like the boundary of a
+/// `Content-Type`
+fn param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|p| {
+        let (n, v) = p.trim().split_once('=')?;
+        n.eq_ignore_ascii_case(name)
+            .then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+fn decode_quoted_printable(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'=' {
+            decoded.push(bytes[i]);
+            i += 1;
+        } else if bytes[i + 1..].starts_with(b"\r\n") {
+            // soft line break
+            i += 3;
+        } else if bytes[i + 1..].starts_with(b"\n") {
+            i += 2;
+        } else {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        }
+    }
+    Some(decoded)
+}
+
+/// RFC 2047 encoded word `word`, decoded
+fn decode_word(word: &str) -> Option<String> {
+    let inner = word.strip_prefix("=?")?.strip_suffix("?=")?;
+    let mut parts = inner.splitn(3, '?');
+    let (charset, encoding, text) = (parts.next()?, parts.next()?, parts.next()?);
+    if !charset.eq_ignore_ascii_case("utf-8")
+        && !charset.eq_ignore_ascii_case("us-ascii")
+    {
+        return None;
+    }
+    let bytes = match encoding {
+        "b" | "B" => STANDARD.decode(text).ok()?,
+        "q" | "Q" => decode_quoted_printable(&text.replace('_', " "))?,
+        _ => return None,
+    };
+    String::from_utf8(bytes).ok()
+}
+
+/// header value `value` with its encoded words decoded. Whitespace between
+/// adjacent encoded words isn't part of the value
+fn decode_header(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut last_encoded = false;
+    for (i, word) in value
+        .split([' ', '\t'])
+        .filter(|w| !w.is_empty())
+        .enumerate()
+    {
+        let (text, encoded) = match decode_word(word) {
+            Some(text) => (text, true),
+            None => (word.to_string(), false),
+        };
+        if i > 0 && !(encoded && last_encoded) {
+            decoded.push(' ');
+        }
+        decoded.push_str(&text);
+        last_encoded = encoded;
+    }
+    decoded
+}
+
+/// body of a part, decoded with its `Content-Transfer-Encoding`
+fn decode_body(headers: &[(String, String)], body: &str) -> Option<String> {
+    let encoding = header(headers, "content-transfer-encoding").unwrap_or("7bit");
+    let bytes = if encoding.eq_ignore_ascii_case("base64") {
+        let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+        STANDARD.decode(body).ok()?
+    } else if encoding.eq_ignore_ascii_case("quoted-printable") {
+        decode_quoted_printable(body)?
+    } else {
+        body.as_bytes().to_vec()
+    };
+    String::from_utf8(bytes)
+        .ok()
+        .map(|s| s.replace("\r\n", "\n"))
+}
+
+/// sender, subject and bodies of formatted email `message`, as sent by
+/// mCaptcha: a plain text or HTML body, or an alternative of both
+pub fn parse(message: &str) -> Option<Parts> {
+    let (headers, body) = split_entity(message);
+    let mut parts = Parts {
+        from: header(&headers, "from").map(decode_header),
+        reply_to: header(&headers, "reply-to").map(decode_header),
+        subject: header(&headers, "subject")
+            .map(decode_header)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let content_type = header(&headers, "content-type").unwrap_or("text/plain");
+    let entities = if content_type.to_ascii_lowercase().starts_with("multipart/") {
+        let delimiter = format!("--{}", param(content_type, "boundary")?);
+        body.split(delimiter.as_str())
+            .skip(1)
+            .take_while(|part| !part.starts_with("--"))
+            .map(|part| {
+                let part = part.strip_prefix("\r\n").unwrap_or(part);
+                let part = part.strip_suffix("\r\n").unwrap_or(part);
+                let (headers, body) = split_entity(part);
+                let body = decode_body(&headers, body)?;
+                Some((headers, body))
+            })
+            .collect::<Option<Vec<_>>>()?
+    } else {
+        vec![(headers.clone(), decode_body(&headers, body)?)]
+    };
+    for (headers, body) in entities {
+        let content_type = header(&headers, "content-type")
+            .unwrap_or("text/plain")
+            .to_ascii_lowercase();
+        if content_type.starts_with("text/html") {
+            parts.html = Some(body);
+        } else if content_type.starts_with("text/plain") {
+            parts.text = Some(body);
+        }
+    }
+    Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::templates::Email;
+
+    #[test]
+    fn signing_key_works() {
+        // example of the AWS Signature Version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let (authorization, date) = sign(
+            "AKID",
+            "secret",
+            "eu-west-1",
+            "GET",
+            "email.eu-west-1.amazonaws.com",
+            "/v2/email/account",
+            b"",
+            now,
+        );
+        assert_eq!(date, "20231114T221320Z");
+        assert!(authorization.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKID/20231114/eu-west-1/ses/aws4_request, SignedHeaders=host;x-amz-date, Signature="
+        ));
+    }
+
+    #[test]
+    fn parse_works() {
+        let smtp = crate::tests::get_settings().smtp.unwrap();
+        let email = Email {
+            subject: "[mCaptcha] Vérifiez votre adresse électronique pour continuer à utiliser mCaptcha".into(),
+            text: "Bonjour à vous,\n\nVeuillez vérifier votre adresse.".into(),
+            html: "<p>Bonjour à vous</p>".into(),
+        };
+        let expected = (
+            email.subject.clone(),
+            email.text.clone(),
+            email.html.clone(),
+        );
+        let message = email.message(&smtp, "Hello <realaravinth@localhost>");
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+
+        let parts = parse(&formatted).unwrap();
+        assert_eq!(parts.subject, expected.0);
+        assert_eq!(parts.text.as_deref(), Some(expected.1.as_str()));
+        assert_eq!(parts.html.as_deref(), Some(expected.2.as_str()));
+        assert!(parts.from.unwrap().contains(&smtp.from));
+
+        assert_eq!(
+            decode_header("=?utf-8?q?caf=C3=A9_cr=C3=A8me?= brûlée"),
+            "café crème brûlée"
+        );
+        assert!(parse("Content-Type: multipart/alternative\r\n\r\nbody").is_none());
+    }
+
+    #[test]
+    fn find_program_works() {
+        assert!(find_program("sh"));
+        assert!(!find_program("/nonexistent/sendmail"));
+    }
+}
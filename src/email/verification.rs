@@ -4,17 +4,19 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 //! Email operations: verification, notification, etc
-use lettre::{
-    message::{header, MultiPart, SinglePart},
-    AsyncTransport, Message,
-};
 use sailfish::TemplateOnce;
+use sqlx::types::time::OffsetDateTime;
 
+use super::templates::{Email, Template};
+use crate::api::v1::mcaptcha::get_random;
 use crate::errors::*;
 use crate::Data;
 
 const PAGE: &str = "Login";
 
+/// validity of verification links, in seconds
+pub const VERIFICATION_LINK_TTL: i64 = 60 * 60 * 24;
+
 #[derive(Clone, TemplateOnce)]
 #[template(path = "email/verification/index.html")]
 struct IndexPage<'a> {
@@ -32,9 +34,9 @@ async fn verification(
     to: &str,
     verification_link: &str,
 ) -> ServiceResult<()> {
-    if let Some(smtp) = data.settings.smtp.as_ref() {
-        let from = format!("mCaptcha Admin <{}>", smtp.from);
-        let reply_to = format!("mCaptcha Admin <{}>", smtp.reply);
+    if let (Some(smtp), Some(mailer)) =
+        (data.settings.smtp.as_ref(), data.mailer.as_ref())
+    {
         const SUBJECT: &str = "[mCaptcha] Please verify your email";
 
         let plain_text = format!(
@@ -58,31 +60,74 @@ project website: {}",
 
         let html = IndexPage::new(verification_link).render_once().unwrap();
 
-        let email = Message::builder()
-            .from(from.parse().unwrap())
-            .reply_to(reply_to.parse().unwrap())
-            .to(to.parse().unwrap())
-            .subject(SUBJECT)
-            .multipart(
-                MultiPart::alternative() // This is composed of two parts.
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(header::ContentType::TEXT_PLAIN)
-                            .body(plain_text), // Every message should have a plain text fallback.
-                    )
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(header::ContentType::TEXT_HTML)
-                            .body(html),
-                    ),
-            )
-            .unwrap();
+        let email = Email {
+            subject: SUBJECT.into(),
+            text: plain_text,
+            html,
+        }
+        .customize(
+            smtp,
+            &data.settings.server.domain,
+            Template::Verification,
+            &[("verification_link", verification_link)],
+        )
+        .message(smtp, to);
+
+        data.mail_queue.send(data, mailer, email).await?;
+    }
+    Ok(())
+}
+
+fn verification_link(data: &Data, token: &str) -> String {
+    super::instance_url(data, &crate::PAGES.auth.get_verify_email(token))
+}
 
-        data.mailer.as_ref().unwrap().send(email).await?;
+/// issue verification token for `email`. Replaces pending token of the user, if any
+pub async fn issue(data: &Data, username: &str, email: &str) -> ServiceResult<String> {
+    let token = get_random(32);
+    let v = db_core::AddEmailVerification {
+        token: &token,
+        email,
+    };
+    data.db.add_email_verification(username, &v).await?;
+    Ok(token)
+}
+
+/// issue verification token and mail verification link to `email`
+pub async fn send(data: &Data, username: &str, email: &str) -> ServiceResult<()> {
+    let token = issue(data, username, email).await?;
+    verification(data, email, &verification_link(data, &token)).await
+}
+
+/// consume verification token and mark email address of its user as verified.
+/// Fails when the token has expired or the user has since changed their email
+pub async fn verify(data: &Data, token: &str) -> ServiceResult<()> {
+    let v = data.db.consume_email_verification(token).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    if now - v.created_at > VERIFICATION_LINK_TTL {
+        return Err(ServiceError::EmailVerificationNotFound);
+    }
+    if data.db.get_email(&v.username).await?.as_deref() != Some(v.email.as_str()) {
+        return Err(ServiceError::EmailVerificationNotFound);
     }
+    data.db.set_email_verified(&v.username, true).await?;
     Ok(())
 }
 
+/// rejects users with unverified email addresses when verification is required
+pub async fn require_verified(data: &Data, username: &str) -> ServiceResult<()> {
+    if !data.settings.require_email_verification
+        || crate::demo::is_demo_user(data, username)
+    {
+        return Ok(());
+    }
+    if data.db.is_email_verified(username).await? {
+        Ok(())
+    } else {
+        Err(ServiceError::EmailNotVerified)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
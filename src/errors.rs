@@ -39,6 +39,44 @@ impl std::cmp::PartialEq for SmtpErrorWrapper {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// invalid field of a request
+pub struct FieldError {
+    /// path of the field, like `levels[1].difficulty_factor`
+    pub field: String,
+    /// what is wrong with the value of the field
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// invalid fields of a request
+pub struct FieldErrors(pub Vec<FieldError>);
+
+impl FieldErrors {
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}: {}", e.field, e.message)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Display, PartialEq, Error)]
 #[cfg(not(tarpaulin_include))]
 pub enum ServiceError {
@@ -74,6 +112,8 @@ pub enum ServiceError {
     /// profile
     #[display(fmt = "username_case_mapped violation")]
     UsernameCaseMappedError,
+    #[display(fmt = "Username too long")]
+    UsernameTooLong,
 
     #[display(fmt = "Passsword too short")]
     PasswordTooShort,
@@ -108,29 +148,608 @@ pub enum ServiceError {
     #[display(fmt = "Captcha not found.")]
     CaptchaNotFound,
 
+    /// user already has a captcha with the same description
+    #[display(fmt = "You already have a sitekey with this description")]
+    DuplicateCaptchaName,
+
     /// Traffic pattern not found
     #[display(fmt = "Traffic pattern not found")]
     TrafficPatternNotFound,
+
+    /// webhook not found
+    #[display(fmt = "Webhook not found")]
+    WebhookNotFound,
+
+    /// webhook URL points to an address that isn't public
+    #[display(fmt = "Webhook URL must point to a public address")]
+    WebhookAddressNotAllowed,
+
+    /// fraud heuristics aren't enabled on the sitekey
+    #[display(fmt = "Fraud heuristics are not enabled on this sitekey")]
+    FraudThresholdsNotFound,
+
+    /// failure rate threshold of automatic notifications is above 100 percent
+    #[display(fmt = "Failure rate threshold can't exceed 100 percent")]
+    InvalidAlertThresholds,
+
+    /// automatic notifications aren't enabled on the sitekey
+    #[display(fmt = "Automatic notifications are not enabled on this sitekey")]
+    AlertThresholdsNotFound,
+
+    /// widget of the sitekey uses the default theme
+    #[display(fmt = "The widget of this sitekey uses the default theme")]
+    WidgetThemeNotFound,
+
+    /// colors of a widget theme aren't `#rrggbb`, or its corners are too round
+    #[display(
+        fmt = "Invalid widget theme: colors must be #rrggbb and border radius at most 40"
+    )]
+    InvalidWidgetTheme,
+
+    /// start of a time range isn't before its end
+    #[display(fmt = "Invalid time range: from must be before to")]
+    InvalidTimeRange,
+
+    /// stats series would have more buckets than are served at once
+    #[display(
+        fmt = "Time range is too long for the granularity: at most {} buckets are served",
+        "crate::api::v1::mcaptcha::stats::MAX_SERIES_BUCKETS"
+    )]
+    TooManyStatsBuckets,
+
+    /// difficulty decay isn't enabled on the sitekey
+    #[display(fmt = "Difficulty decay is not enabled on this sitekey")]
+    DifficultyDecayNotFound,
+
+    /// difficulty decay steps must be positive
+    #[display(
+        fmt = "Difficulty decay needs a positive step duration and number of steps"
+    )]
+    InvalidDifficultyDecay,
+
+    /// too few solves were analyzed to recommend levels
+    #[display(fmt = "Not enough performance analytics to recommend levels")]
+    NotEnoughAnalytics,
+
+    /// levels of sitekeys in easy mode are computed from their traffic pattern
+    #[display(
+        fmt = "Levels of sitekeys in easy mode are computed from their traffic pattern"
+    )]
+    EasyModeLevels,
+
+    /// widget is embedded on a domain that the sitekey doesn't allow
+    #[display(fmt = "Domain not allow-listed for this sitekey")]
+    OriginNotAllowed,
+
+    /// sitekey needs allowed domains to serve challenges
+    #[display(
+        fmt = "Sitekey has no allowed domains. Add allowed domains to serve challenges"
+    )]
+    AllowedDomainsRequired,
+
+    /// allowed domain isn't a domain name
+    #[display(fmt = "Invalid allowed domain")]
+    InvalidAllowedDomain,
+
+    /// sitekey can't have this many allowed domains
+    #[display(fmt = "Too many allowed domains")]
+    TooManyAllowedDomains,
+
+    /// validation token not found
+    #[display(fmt = "Validation token not found")]
+    ValidationTokenNotFound,
+
+    /// form session scope is empty or too long
+    #[display(fmt = "Form session scope must be 1 to 100 characters long")]
+    InvalidFormSessionScope,
+
+    /// action is not available to the demo account
+    #[display(fmt = "This action is not available to the demo account")]
+    DemoUserRestricted,
+
+    /// demo account has reached its sitekey limit
+    #[display(fmt = "The demo account can't create more sitekeys")]
+    DemoSitekeyLimitReached,
+
+    /// demo session has reached its sitekey limit
+    #[display(fmt = "This demo session can't create more sitekeys")]
+    DemoSessionSitekeyLimitReached,
+
+    /// trial sitekeys are disabled on this instance
+    #[display(fmt = "Trial sitekeys are not available on this instance")]
+    TrialsDisabled,
+
+    /// too many trial sitekeys exist
+    #[display(fmt = "Too many trial sitekeys, please try again later")]
+    TrialLimitReached,
+
+    /// user has reached their captcha quota
+    #[display(fmt = "You have reached your sitekey quota")]
+    CaptchaQuotaReached,
+
+    /// captcha description is too long to store
+    #[display(fmt = "Sitekey description is too long")]
+    CaptchaDescriptionTooLong,
+
+    /// import holds more sites than are imported at once
+    #[display(
+        fmt = "Too many sites to import: at most {} are imported at once",
+        "crate::api::v1::mcaptcha::import::MAX_IMPORT_SITES"
+    )]
+    TooManyImportSites,
+
+    /// data residency regions are lowercase letters, digits and '-'
+    #[display(fmt = "Data residency region must be lowercase letters, digits and '-'")]
+    InvalidResidency,
+
+    /// registration hasn't been approved by an administrator yet
+    #[display(fmt = "Your registration is pending approval by an administrator")]
+    RegistrationPendingApproval,
+
+    #[display(fmt = "No registration of this user is pending approval")]
+    RegistrationNotPending,
+
+    /// too many emails are waiting to be sent
+    #[display(fmt = "Too many emails are waiting to be sent, please try again later")]
+    MailQueueFull,
+
+    /// recipient was mailed moments ago
+    #[display(
+        fmt = "An email was sent to this address recently, please try again later"
+    )]
+    MailRecipientCooldown,
+    #[display(fmt = "Too many downloads, please try again later")]
+    TooManyDownloads,
+    #[display(fmt = "Fallback challenges aren't enabled for this sitekey")]
+    FallbackDisabled,
+    #[display(fmt = "Fallback challenge not found or expired")]
+    FallbackNotFound,
+    #[display(fmt = "Fallback challenge was completed before its wait was over")]
+    FallbackTooEarly,
+    #[display(fmt = "Too many fallback challenges, please try again later")]
+    TooManyFallbacks,
+
+    #[display(fmt = "PoW runners and queue length must be at least 1")]
+    InvalidPoWTuning,
+    #[display(fmt = "Too many challenges are being verified, please try again later")]
+    PoWSaturated,
+
+    #[display(fmt = "External IDs must be 1 to 100 characters long")]
+    InvalidExternalId,
+
+    /// external ID was claimed by a concurrent request
+    #[display(fmt = "External ID is used by another sitekey")]
+    ExternalIdTaken,
+
+    /// vanity key isn't made of allowed characters, or has an invalid length
+    #[display(fmt = "Vanity keys must be 4 to 100 letters, digits, '-' or '_'")]
+    InvalidVanityKey,
+
+    /// vanity key is used by another sitekey
+    #[display(fmt = "Vanity key is used by another sitekey")]
+    VanityKeyTaken,
+
+    /// no free sitekey was generated; the key format is too short
+    #[display(fmt = "Unable to generate a free sitekey")]
+    KeyGenerationFailed,
+
+    /// `If-Match` or `If-None-Match` condition of the request doesn't hold
+    #[display(fmt = "Sitekey was modified, fetch it and try again")]
+    PreconditionFailed,
+
+    /// account was suspended by an administrator
+    #[display(fmt = "Your account has been suspended")]
+    AccountSuspended,
+
+    /// administrators can't be suspended
+    #[display(fmt = "Administrators can't be suspended. Revoke their role first")]
+    CannotSuspendAdmin,
+
+    /// owner of the sitekey is suspended
+    #[display(fmt = "This sitekey has been suspended")]
+    CaptchaSuspended,
+
+    /// domain of the email address is banned from registration
+    #[display(fmt = "Email addresses of this domain can't be used to sign up")]
+    EmailDomainBanned,
+
+    #[display(fmt = "Email domain isn't banned")]
+    BannedDomainNotFound,
+
+    #[display(fmt = "Not a valid email domain")]
+    InvalidEmailDomain,
+
+    /// client doesn't support any of the protocol versions supported by the server
+    #[display(fmt = "Unsupported protocol version")]
+    UnsupportedProtocolVersion,
+
+    /// subsystem is disabled on this instance
+    #[display(fmt = "This feature is disabled on this instance")]
+    FeatureDisabled,
+
+    /// user hasn't accepted the current version of the instance's legal documents
+    #[display(fmt = "Please review and accept the updated agreements")]
+    AgreementsNotAccepted,
+
+    /// action is restricted to instance administrators
+    #[display(fmt = "Only instance administrators can do this")]
+    AdminRequired,
+
+    /// notification key isn't a base64 encoded public key
+    #[display(fmt = "Notification key must be a base64 encoded SPKI public key")]
+    InvalidNotificationKey,
+
+    /// encrypted notification message is malformed
+    #[display(fmt = "Encrypted message is malformed")]
+    InvalidEncryptedMessage,
+
+    /// receiver of an encrypted notification hasn't set a notification key
+    #[display(fmt = "User hasn't set up a notification key")]
+    NotificationKeyNotSet,
+
+    /// notification heading or message is too long to store
+    #[display(fmt = "Notification is too long")]
+    NotificationTooLong,
+
+    /// administrators can't be deleted through the admin API
+    #[display(fmt = "Administrators can't be deleted. Revoke their role first")]
+    CannotDeleteAdmin,
+
+    /// organization name contains characters that aren't allowed
+    #[display(fmt = "Organization names can only contain letters, digits, '-' and '_'")]
+    InvalidOrgName,
+
+    /// organization name is taken
+    #[display(fmt = "Organization name is taken")]
+    OrgNameTaken,
+
+    /// organization doesn't exist or user isn't a member
+    #[display(fmt = "Organization not found")]
+    OrgNotFound,
+
+    /// user isn't a member of the organization
+    #[display(fmt = "User isn't a member of the organization")]
+    OrgMemberNotFound,
+
+    /// member's role doesn't permit the action
+    #[display(fmt = "Your role in the organization doesn't permit this")]
+    OrgRoleRequired,
+
+    /// the last owner of an organization can't leave or be demoted
+    #[display(fmt = "An organization must have at least one owner")]
+    LastOrgOwner,
+
+    /// captcha already belongs to an organization
+    #[display(fmt = "Sitekey already belongs to an organization")]
+    CaptchaInOrg,
+
+    /// traffic burst not found
+    #[display(fmt = "Burst not found")]
+    BurstNotFound,
+
+    /// traffic burst window or difficulty is invalid
+    #[display(
+        fmt = "Bursts must end in the future, after they start, last at most a day and have a difficulty factor"
+    )]
+    InvalidBurst,
+
+    /// invitation not found, already accepted or expired
+    #[display(fmt = "Invitation is invalid or has expired")]
+    InviteNotFound,
+
+    /// email address of signup doesn't match that of the invitation
+    #[display(fmt = "Email doesn't match the invitation")]
+    InviteEmailMismatch,
+
+    /// two-factor authentication is not set up
+    #[display(fmt = "Two-factor authentication is not set up")]
+    TotpNotFound,
+
+    /// two-factor authentication is already enabled
+    #[display(fmt = "Two-factor authentication is already enabled")]
+    TotpAlreadyEnabled,
+
+    /// TOTP or recovery code is required to complete the action
+    #[display(fmt = "Two-factor authentication code required")]
+    TotpRequired,
+
+    /// TOTP or recovery code is invalid
+    #[display(fmt = "Invalid two-factor authentication code")]
+    WrongTotp,
+
+    /// email address has to be verified to complete the action
+    #[display(fmt = "Please verify your email address to continue")]
+    EmailNotVerified,
+
+    /// email verification link doesn't exist or has expired
+    #[display(fmt = "Email verification link is invalid or has expired")]
+    EmailVerificationNotFound,
+
+    /// email change link doesn't exist or has expired
+    #[display(fmt = "Email change link is invalid or has expired")]
+    EmailChangeNotFound,
+
+    /// session revocation link doesn't exist or has expired
+    #[display(fmt = "Session revocation link is invalid or has expired")]
+    SessionRevocationNotFound,
+
+    /// single sign-on attempt doesn't exist or has expired
+    #[display(fmt = "Single sign-on session is invalid or has expired")]
+    OidcStateInvalid,
+
+    /// identity provider rejected the login or returned an invalid response
+    #[display(fmt = "Unable to sign in with the identity provider")]
+    OidcProviderError,
+
+    /// identity provider account uses the email address of an unlinked account
+    #[display(
+        fmt = "An account with this email already exists. Sign in with your password and link single sign-on from settings"
+    )]
+    OidcAccountNotLinked,
+
+    /// identity provider account is linked to a different user
+    #[display(fmt = "This single sign-on account is linked to another user")]
+    OidcIdentityTaken,
+
+    /// too many failed sign-ins; the sign-in challenge has to be solved
+    #[display(
+        fmt = "Too many failed sign-in attempts, please solve the challenge to continue"
+    )]
+    LoginChallengeRequired,
+
+    /// too many failed sign-ins; sign-in is locked out for a while
+    #[display(fmt = "Too many failed sign-in attempts, please try again later")]
+    LoginLocked,
+
+    #[display(fmt = "Session not found")]
+    SessionNotFound,
+
+    #[display(fmt = "API token not found")]
+    ApiTokenNotFound,
+
+    #[display(fmt = "Notification not found")]
+    NotificationNotFound,
+
+    /// email isn't among the emails that couldn't be sent
+    #[display(fmt = "Dead letter not found")]
+    DeadLetterNotFound,
+
+    /// API token is missing, malformed or revoked
+    #[display(fmt = "Invalid API token")]
+    InvalidApiToken,
+
+    /// API token name is empty or too long
+    #[display(fmt = "API token names must be 1 to 100 characters long")]
+    InvalidApiTokenName,
+
+    /// API token lacks the scope that the request requires
+    #[display(fmt = "API token doesn't have the scope {}", _0)]
+    ApiTokenScopeMissing(#[error(not(source))] &'static str),
+
+    /// request is over a rate limit; holds the seconds until the limit resets
+    #[display(fmt = "Too many requests, please try again in {} seconds", _0)]
+    RateLimited(#[error(not(source))] u32),
+
+    /// request body is over its size limit; holds the limit in bytes
+    #[display(fmt = "Request body is too large, the limit is {} bytes", _0)]
+    PayloadTooLarge(#[error(not(source))] usize),
+
+    /// request body nests too deeply; holds the maximum depth
+    #[display(fmt = "JSON nests too deeply, the maximum depth is {}", _0)]
+    JsonTooDeep(#[error(not(source))] usize),
+
+    /// levels, duration or description of a captcha are invalid
+    #[display(fmt = "Invalid sitekey: {}", _0)]
+    InvalidCaptcha(#[error(not(source))] FieldErrors),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[cfg(not(tarpaulin_include))]
 pub struct ErrorToResponse {
+    /// human readable message; may change between releases
     pub error: String,
+    /// stable identifier of the error, see [ServiceError::code]
+    pub code: String,
+    /// correlation ID of the PoW challenge that the error is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// seconds to wait before retrying, when the server is saturated; also
+    /// sent in the `Retry-After` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u32>,
+    /// ID of the request, see [crate::request_id]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// invalid fields of the request, for validation errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<FieldError>>,
+}
+
+impl ErrorToResponse {
+    pub fn new(e: &ServiceError, correlation_id: Option<String>) -> Self {
+        Self {
+            error: e.to_string(),
+            code: e.code().into(),
+            correlation_id,
+            retry_after: e.retry_after(),
+            request_id: crate::request_id::current(),
+            fields: e.fields(),
+        }
+    }
+}
+
+impl ServiceError {
+    /// seconds that clients should wait for before retrying, if the error is
+    /// transient
+    pub fn retry_after(&self) -> Option<u32> {
+        match self {
+            ServiceError::PoWSaturated => Some(crate::overload::RETRY_AFTER),
+            ServiceError::RateLimited(reset) => Some(*reset),
+            _ => None,
+        }
+    }
+
+    /// invalid fields of the request, if the error is a validation error
+    pub fn fields(&self) -> Option<Vec<FieldError>> {
+        match self {
+            ServiceError::InvalidCaptcha(fields) => Some(fields.0.clone()),
+            _ => None,
+        }
+    }
+
+    /// stable, machine-readable identifier of the error, sent in the `code`
+    /// field of error responses. Codes aren't changed once released; see
+    /// docs/ERROR_CODES.md
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServiceError::InternalServerError => "internal_server_error",
+            ServiceError::ClosedForRegistration => "closed_for_registration",
+            ServiceError::NotAnEmail => "invalid_email",
+            ServiceError::NotAUrl => "invalid_url",
+            ServiceError::WrongPassword => "wrong_password",
+            ServiceError::UsernameNotFound => "username_not_found",
+            ServiceError::AccountNotFound => "account_not_found",
+            ServiceError::ProfainityError => "username_profanity",
+            ServiceError::BlacklistError => "username_blacklisted",
+            ServiceError::UsernameCaseMappedError => "username_case_mapped",
+            ServiceError::UsernameTooLong => "username_too_long",
+            ServiceError::PasswordTooShort => "password_too_short",
+            ServiceError::PasswordTooLong => "password_too_long",
+            ServiceError::PasswordsDontMatch => "passwords_dont_match",
+            ServiceError::UsernameTaken => "username_taken",
+            ServiceError::EmailTaken => "email_taken",
+            ServiceError::UnableToSendEmail(_) => "email_send_failed",
+            ServiceError::TokenNotFound => "token_not_found",
+            ServiceError::CaptchaError(e) => match e {
+                CaptchaError::StringNotFound => "challenge_not_found",
+                CaptchaError::InvalidPoW => "invalid_pow",
+                CaptchaError::InsuffiencientDifficulty => "insufficient_difficulty",
+                CaptchaError::MailboxError => "internal_server_error",
+                _ => "captcha_error",
+            },
+            ServiceError::DBError(_) => "database_error",
+            ServiceError::CaptchaNotFound => "captcha_not_found",
+            ServiceError::DuplicateCaptchaName => "duplicate_captcha_name",
+            ServiceError::TrafficPatternNotFound => "traffic_pattern_not_found",
+            ServiceError::WebhookNotFound => "webhook_not_found",
+            ServiceError::WebhookAddressNotAllowed => "webhook_address_not_allowed",
+            ServiceError::FraudThresholdsNotFound => "fraud_thresholds_not_found",
+            ServiceError::InvalidAlertThresholds => "invalid_alert_thresholds",
+            ServiceError::AlertThresholdsNotFound => "alert_thresholds_not_found",
+            ServiceError::WidgetThemeNotFound => "widget_theme_not_found",
+            ServiceError::InvalidWidgetTheme => "invalid_widget_theme",
+            ServiceError::InvalidTimeRange => "invalid_time_range",
+            ServiceError::TooManyStatsBuckets => "too_many_stats_buckets",
+            ServiceError::DifficultyDecayNotFound => "difficulty_decay_not_found",
+            ServiceError::InvalidDifficultyDecay => "invalid_difficulty_decay",
+            ServiceError::NotEnoughAnalytics => "not_enough_analytics",
+            ServiceError::EasyModeLevels => "easy_mode_levels",
+            ServiceError::OriginNotAllowed => "origin_not_allowed",
+            ServiceError::AllowedDomainsRequired => "allowed_domains_required",
+            ServiceError::InvalidAllowedDomain => "invalid_allowed_domain",
+            ServiceError::TooManyAllowedDomains => "too_many_allowed_domains",
+            ServiceError::ValidationTokenNotFound => "validation_token_not_found",
+            ServiceError::InvalidFormSessionScope => "invalid_form_session_scope",
+            ServiceError::DemoUserRestricted => "demo_user_restricted",
+            ServiceError::DemoSitekeyLimitReached => "demo_sitekey_limit_reached",
+            ServiceError::DemoSessionSitekeyLimitReached => {
+                "demo_session_sitekey_limit_reached"
+            }
+            ServiceError::TrialsDisabled => "trials_disabled",
+            ServiceError::TrialLimitReached => "trial_limit_reached",
+            ServiceError::CaptchaQuotaReached => "captcha_quota_reached",
+            ServiceError::CaptchaDescriptionTooLong => "captcha_description_too_long",
+            ServiceError::TooManyImportSites => "too_many_import_sites",
+            ServiceError::InvalidResidency => "invalid_residency",
+            ServiceError::RegistrationPendingApproval => "registration_pending_approval",
+            ServiceError::RegistrationNotPending => "registration_not_pending",
+            ServiceError::MailQueueFull => "mail_queue_full",
+            ServiceError::MailRecipientCooldown => "mail_recipient_cooldown",
+            ServiceError::TooManyDownloads => "too_many_downloads",
+            ServiceError::FallbackDisabled => "fallback_disabled",
+            ServiceError::FallbackNotFound => "fallback_not_found",
+            ServiceError::FallbackTooEarly => "fallback_too_early",
+            ServiceError::TooManyFallbacks => "too_many_fallbacks",
+            ServiceError::InvalidPoWTuning => "invalid_pow_tuning",
+            ServiceError::PoWSaturated => "pow_saturated",
+            ServiceError::InvalidExternalId => "invalid_external_id",
+            ServiceError::ExternalIdTaken => "external_id_taken",
+            ServiceError::InvalidVanityKey => "invalid_vanity_key",
+            ServiceError::VanityKeyTaken => "vanity_key_taken",
+            ServiceError::KeyGenerationFailed => "key_generation_failed",
+            ServiceError::PreconditionFailed => "precondition_failed",
+            ServiceError::AccountSuspended => "account_suspended",
+            ServiceError::CannotSuspendAdmin => "cannot_suspend_admin",
+            ServiceError::CaptchaSuspended => "captcha_suspended",
+            ServiceError::EmailDomainBanned => "email_domain_banned",
+            ServiceError::BannedDomainNotFound => "banned_domain_not_found",
+            ServiceError::InvalidEmailDomain => "invalid_email_domain",
+            ServiceError::UnsupportedProtocolVersion => "unsupported_protocol_version",
+            ServiceError::FeatureDisabled => "feature_disabled",
+            ServiceError::AgreementsNotAccepted => "agreements_not_accepted",
+            ServiceError::AdminRequired => "admin_required",
+            ServiceError::InvalidNotificationKey => "invalid_notification_key",
+            ServiceError::InvalidEncryptedMessage => "invalid_encrypted_message",
+            ServiceError::NotificationKeyNotSet => "notification_key_not_set",
+            ServiceError::NotificationTooLong => "notification_too_long",
+            ServiceError::CannotDeleteAdmin => "cannot_delete_admin",
+            ServiceError::InvalidOrgName => "invalid_org_name",
+            ServiceError::OrgNameTaken => "org_name_taken",
+            ServiceError::OrgNotFound => "org_not_found",
+            ServiceError::OrgMemberNotFound => "org_member_not_found",
+            ServiceError::OrgRoleRequired => "org_role_required",
+            ServiceError::LastOrgOwner => "last_org_owner",
+            ServiceError::CaptchaInOrg => "captcha_in_org",
+            ServiceError::BurstNotFound => "burst_not_found",
+            ServiceError::InvalidBurst => "invalid_burst",
+            ServiceError::InviteNotFound => "invite_not_found",
+            ServiceError::InviteEmailMismatch => "invite_email_mismatch",
+            ServiceError::TotpNotFound => "totp_not_found",
+            ServiceError::TotpAlreadyEnabled => "totp_already_enabled",
+            ServiceError::TotpRequired => "totp_required",
+            ServiceError::WrongTotp => "wrong_totp",
+            ServiceError::EmailNotVerified => "email_not_verified",
+            ServiceError::EmailVerificationNotFound => "email_verification_not_found",
+            ServiceError::EmailChangeNotFound => "email_change_not_found",
+            ServiceError::SessionRevocationNotFound => "session_revocation_not_found",
+            ServiceError::OidcStateInvalid => "oidc_state_invalid",
+            ServiceError::OidcProviderError => "oidc_provider_error",
+            ServiceError::OidcAccountNotLinked => "oidc_account_not_linked",
+            ServiceError::OidcIdentityTaken => "oidc_identity_taken",
+            ServiceError::LoginChallengeRequired => "login_challenge_required",
+            ServiceError::LoginLocked => "login_locked",
+            ServiceError::SessionNotFound => "session_not_found",
+            ServiceError::ApiTokenNotFound => "api_token_not_found",
+            ServiceError::NotificationNotFound => "notification_not_found",
+            ServiceError::DeadLetterNotFound => "dead_letter_not_found",
+            ServiceError::InvalidApiToken => "invalid_api_token",
+            ServiceError::InvalidApiTokenName => "invalid_api_token_name",
+            ServiceError::ApiTokenScopeMissing(_) => "api_token_scope_missing",
+            ServiceError::RateLimited(_) => "rate_limited",
+            ServiceError::PayloadTooLarge(_) => "payload_too_large",
+            ServiceError::JsonTooDeep(_) => "json_too_deep",
+            ServiceError::InvalidCaptcha(_) => "invalid_captcha",
+        }
+    }
+
+    /// error response that echoes the correlation ID of a PoW challenge
+    pub fn correlated_response(&self, correlation_id: Option<String>) -> HttpResponse {
+        let mut resp = HttpResponseBuilder::new(self.status_code());
+        resp.append_header((header::CONTENT_TYPE, "application/json; charset=UTF-8"));
+        if let Some(retry_after) = self.retry_after() {
+            resp.append_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        resp.body(
+            serde_json::to_string(&ErrorToResponse::new(self, correlation_id)).unwrap(),
+        )
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
 impl ResponseError for ServiceError {
     #[cfg(not(tarpaulin_include))]
     fn error_response(&self) -> HttpResponse {
-        HttpResponseBuilder::new(self.status_code())
-            .append_header((header::CONTENT_TYPE, "application/json; charset=UTF-8"))
-            .body(
-                serde_json::to_string(&ErrorToResponse {
-                    error: self.to_string(),
-                })
-                .unwrap(),
-            )
+        self.correlated_response(None)
     }
 
     #[cfg(not(tarpaulin_include))]
@@ -147,6 +766,7 @@ impl ResponseError for ServiceError {
             ServiceError::ProfainityError => StatusCode::BAD_REQUEST,
             ServiceError::BlacklistError => StatusCode::BAD_REQUEST,
             ServiceError::UsernameCaseMappedError => StatusCode::BAD_REQUEST,
+            ServiceError::UsernameTooLong => StatusCode::BAD_REQUEST,
 
             ServiceError::PasswordTooShort => StatusCode::BAD_REQUEST,
             ServiceError::PasswordTooLong => StatusCode::BAD_REQUEST,
@@ -171,7 +791,104 @@ impl ResponseError for ServiceError {
 
             ServiceError::DBError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ServiceError::CaptchaNotFound => StatusCode::NOT_FOUND,
+            ServiceError::DuplicateCaptchaName => StatusCode::CONFLICT,
             ServiceError::TrafficPatternNotFound => StatusCode::NOT_FOUND,
+            ServiceError::WebhookNotFound => StatusCode::NOT_FOUND,
+            ServiceError::WebhookAddressNotAllowed => StatusCode::BAD_REQUEST,
+            ServiceError::FraudThresholdsNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidAlertThresholds => StatusCode::BAD_REQUEST,
+            ServiceError::AlertThresholdsNotFound => StatusCode::NOT_FOUND,
+            ServiceError::WidgetThemeNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidWidgetTheme => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidTimeRange => StatusCode::BAD_REQUEST,
+            ServiceError::TooManyStatsBuckets => StatusCode::BAD_REQUEST,
+            ServiceError::DifficultyDecayNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidDifficultyDecay => StatusCode::BAD_REQUEST,
+            ServiceError::NotEnoughAnalytics => StatusCode::BAD_REQUEST,
+            ServiceError::EasyModeLevels => StatusCode::BAD_REQUEST,
+            ServiceError::OriginNotAllowed => StatusCode::FORBIDDEN,
+            ServiceError::AllowedDomainsRequired => StatusCode::FORBIDDEN,
+            ServiceError::InvalidAllowedDomain => StatusCode::BAD_REQUEST,
+            ServiceError::TooManyAllowedDomains => StatusCode::BAD_REQUEST,
+            ServiceError::ValidationTokenNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidFormSessionScope => StatusCode::BAD_REQUEST,
+            ServiceError::DemoUserRestricted => StatusCode::FORBIDDEN,
+            ServiceError::DemoSitekeyLimitReached => StatusCode::FORBIDDEN,
+            ServiceError::DemoSessionSitekeyLimitReached => StatusCode::FORBIDDEN,
+            ServiceError::TrialsDisabled => StatusCode::NOT_FOUND,
+            ServiceError::TrialLimitReached => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::CaptchaQuotaReached => StatusCode::FORBIDDEN,
+            ServiceError::CaptchaDescriptionTooLong => StatusCode::BAD_REQUEST,
+            ServiceError::TooManyImportSites => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidResidency => StatusCode::BAD_REQUEST,
+            ServiceError::RegistrationPendingApproval => StatusCode::FORBIDDEN,
+            ServiceError::RegistrationNotPending => StatusCode::NOT_FOUND,
+            ServiceError::MailQueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::MailRecipientCooldown => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::TooManyDownloads => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::FallbackDisabled => StatusCode::FORBIDDEN,
+            ServiceError::FallbackNotFound => StatusCode::NOT_FOUND,
+            ServiceError::FallbackTooEarly => StatusCode::BAD_REQUEST,
+            ServiceError::TooManyFallbacks => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::InvalidPoWTuning => StatusCode::BAD_REQUEST,
+            ServiceError::PoWSaturated => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::InvalidExternalId => StatusCode::BAD_REQUEST,
+            ServiceError::ExternalIdTaken => StatusCode::CONFLICT,
+            ServiceError::InvalidVanityKey => StatusCode::BAD_REQUEST,
+            ServiceError::VanityKeyTaken => StatusCode::CONFLICT,
+            ServiceError::KeyGenerationFailed => StatusCode::INTERNAL_SERVER_ERROR,
+            ServiceError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ServiceError::AccountSuspended => StatusCode::FORBIDDEN,
+            ServiceError::CannotSuspendAdmin => StatusCode::BAD_REQUEST,
+            ServiceError::CaptchaSuspended => StatusCode::FORBIDDEN,
+            ServiceError::EmailDomainBanned => StatusCode::BAD_REQUEST,
+            ServiceError::BannedDomainNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidEmailDomain => StatusCode::BAD_REQUEST,
+            ServiceError::UnsupportedProtocolVersion => StatusCode::BAD_REQUEST,
+            ServiceError::FeatureDisabled => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceError::AgreementsNotAccepted => StatusCode::FORBIDDEN,
+            ServiceError::AdminRequired => StatusCode::FORBIDDEN,
+            ServiceError::InvalidNotificationKey => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidEncryptedMessage => StatusCode::BAD_REQUEST,
+            ServiceError::NotificationKeyNotSet => StatusCode::NOT_FOUND,
+            ServiceError::NotificationTooLong => StatusCode::BAD_REQUEST,
+            ServiceError::CannotDeleteAdmin => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidOrgName => StatusCode::BAD_REQUEST,
+            ServiceError::OrgNameTaken => StatusCode::BAD_REQUEST,
+            ServiceError::OrgNotFound => StatusCode::NOT_FOUND,
+            ServiceError::OrgMemberNotFound => StatusCode::NOT_FOUND,
+            ServiceError::OrgRoleRequired => StatusCode::FORBIDDEN,
+            ServiceError::LastOrgOwner => StatusCode::BAD_REQUEST,
+            ServiceError::CaptchaInOrg => StatusCode::BAD_REQUEST,
+            ServiceError::BurstNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidBurst => StatusCode::BAD_REQUEST,
+            ServiceError::InviteNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InviteEmailMismatch => StatusCode::BAD_REQUEST,
+            ServiceError::TotpNotFound => StatusCode::NOT_FOUND,
+            ServiceError::TotpAlreadyEnabled => StatusCode::BAD_REQUEST,
+            ServiceError::TotpRequired => StatusCode::UNAUTHORIZED,
+            ServiceError::WrongTotp => StatusCode::UNAUTHORIZED,
+            ServiceError::EmailNotVerified => StatusCode::FORBIDDEN,
+            ServiceError::EmailVerificationNotFound => StatusCode::NOT_FOUND,
+            ServiceError::EmailChangeNotFound => StatusCode::NOT_FOUND,
+            ServiceError::SessionRevocationNotFound => StatusCode::NOT_FOUND,
+            ServiceError::OidcStateInvalid => StatusCode::BAD_REQUEST,
+            ServiceError::OidcProviderError => StatusCode::BAD_GATEWAY,
+            ServiceError::OidcAccountNotLinked => StatusCode::CONFLICT,
+            ServiceError::OidcIdentityTaken => StatusCode::CONFLICT,
+            ServiceError::LoginChallengeRequired => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::LoginLocked => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::SessionNotFound => StatusCode::NOT_FOUND,
+            ServiceError::ApiTokenNotFound => StatusCode::NOT_FOUND,
+            ServiceError::NotificationNotFound => StatusCode::NOT_FOUND,
+            ServiceError::DeadLetterNotFound => StatusCode::NOT_FOUND,
+            ServiceError::InvalidApiToken => StatusCode::UNAUTHORIZED,
+            ServiceError::InvalidApiTokenName => StatusCode::BAD_REQUEST,
+            ServiceError::ApiTokenScopeMissing(_) => StatusCode::FORBIDDEN,
+            ServiceError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ServiceError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ServiceError::JsonTooDeep(_) => StatusCode::BAD_REQUEST,
+            ServiceError::InvalidCaptcha(_) => StatusCode::BAD_REQUEST,
         }
     }
 }
@@ -202,6 +919,34 @@ impl From<DBError> for ServiceError {
             DBError::AccountNotFound => ServiceError::AccountNotFound,
             DBError::CaptchaNotFound => ServiceError::CaptchaNotFound,
             DBError::TrafficPatternNotFound => ServiceError::TrafficPatternNotFound,
+            DBError::WebhookNotFound => ServiceError::WebhookNotFound,
+            DBError::FraudThresholdsNotFound => ServiceError::FraudThresholdsNotFound,
+            DBError::AlertThresholdsNotFound => ServiceError::AlertThresholdsNotFound,
+            DBError::WidgetThemeNotFound => ServiceError::WidgetThemeNotFound,
+            DBError::DifficultyDecayNotFound => ServiceError::DifficultyDecayNotFound,
+            DBError::TotpNotFound => ServiceError::TotpNotFound,
+            DBError::EmailVerificationNotFound => {
+                ServiceError::EmailVerificationNotFound
+            }
+            DBError::EmailChangeNotFound => ServiceError::EmailChangeNotFound,
+            DBError::SessionRevocationNotFound => {
+                ServiceError::SessionRevocationNotFound
+            }
+            DBError::SessionNotFound => ServiceError::SessionNotFound,
+            DBError::OrgNotFound => ServiceError::OrgNotFound,
+            DBError::OrgNameTaken => ServiceError::OrgNameTaken,
+            DBError::OrgMemberNotFound => ServiceError::OrgMemberNotFound,
+            DBError::BurstNotFound => ServiceError::BurstNotFound,
+            DBError::InviteNotFound => ServiceError::InviteNotFound,
+            DBError::ExternalIdTaken => ServiceError::ExternalIdTaken,
+            DBError::BannedDomainNotFound => ServiceError::BannedDomainNotFound,
+            DBError::ApiTokenNotFound => ServiceError::ApiTokenNotFound,
+            DBError::NotificationNotFound => ServiceError::NotificationNotFound,
+            DBError::DeadLetterNotFound => ServiceError::DeadLetterNotFound,
+            DBError::SitekeyLimitReached => ServiceError::DemoSitekeyLimitReached,
+            DBError::SessionSitekeyLimitReached => {
+                ServiceError::DemoSessionSitekeyLimitReached
+            }
             _ => ServiceError::DBError(DBErrorWrapper(e)),
         }
     }
@@ -314,6 +1059,25 @@ mod tests {
     use super::*;
     use crate::PAGES;
 
+    #[test]
+    fn error_codes_work() {
+        assert_eq!(ServiceError::CaptchaNotFound.code(), "captcha_not_found");
+        assert_eq!(ServiceError::MailQueueFull.code(), "mail_queue_full");
+        assert_eq!(
+            ServiceError::ApiTokenScopeMissing("read:stats").code(),
+            "api_token_scope_missing"
+        );
+        assert_eq!(
+            ServiceError::CaptchaError(CaptchaError::StringNotFound).code(),
+            "challenge_not_found"
+        );
+
+        let resp = ErrorToResponse::new(&ServiceError::PoWSaturated, None);
+        assert_eq!(resp.code, "pow_saturated");
+        let resp: serde_json::Value = serde_json::to_value(resp).unwrap();
+        assert_eq!(resp["code"], "pow_saturated");
+    }
+
     #[test]
     fn error_works() {
         let resp: HttpResponse = PageError::InternalServerError.error_response();
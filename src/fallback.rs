@@ -0,0 +1,258 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Fallback challenges, for visitors who can't solve PoW
+//!
+//! Screen reader setups and old browsers can't always run the WASM worker of
+//! the widget, which locks their users out of sites that are protected by PoW
+//! alone. Visitors of sitekeys that enable the fallback wait instead: they
+//! start a fallback challenge, wait the [fallback wait](db_core::Fallback) of
+//! the sitekey, and are then issued a validation token, which site backends
+//! validate like those of solved PoW. No PoW is solved for them, neither by
+//! the visitor nor by the server, and the token is marked as a fallback token
+//! in the [ledger](crate::tokens).
+//!
+//! An IP address can start [FALLBACKS_PER_WINDOW] fallback challenges every
+//! [FALLBACK_WINDOW] seconds, and challenges expire [FALLBACK_TTL] seconds
+//! after their wait is over. Completed challenges count as solves in stats,
+//! but aren't recorded in performance analytics, since no PoW was solved.
+//!
+//! Pending challenges and the counts of IP addresses are kept in
+//! [FallbackChallenges], in memory. Instances of a [cluster](crate::cluster)
+//! keep them in the database instead, so that a challenge started on one
+//! instance can be completed on another, and the limit applies across them.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use libmcaptcha::cache::messages::CacheResult;
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::cluster;
+use crate::errors::*;
+use crate::memcached::BindToken;
+use crate::webhooks::WebhookEvent;
+use crate::AppData;
+
+/// fallback challenges that an IP address can start within a window
+pub const FALLBACKS_PER_WINDOW: u32 = 5;
+/// seconds over which fallback challenges are counted by IP address
+pub const FALLBACK_WINDOW: i64 = 600;
+/// seconds after their wait is over within which fallback challenges must
+/// be completed
+pub const FALLBACK_TTL: i64 = 120;
+/// length of IDs of fallback challenges
+pub const CHALLENGE_ID_LEN: usize = 32;
+/// length of validation tokens of completed fallback challenges
+const TOKEN_LEN: usize = 32;
+
+#[derive(Clone, Debug)]
+/// fallback challenge that is waiting to be completed
+pub struct PendingFallback {
+    /// sitekey of the challenge
+    pub key: String,
+    /// unix timestamp from which the challenge can be completed
+    pub ready: i64,
+}
+
+impl PendingFallback {
+    fn expired(&self, now: i64) -> bool {
+        now >= self.ready + FALLBACK_TTL
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FallbackChallenges {
+    /// pending challenges, by ID
+    pending: Arc<RwLock<HashMap<String, PendingFallback>>>,
+    /// start of the current window and challenges started within it, by IP
+    windows: Arc<RwLock<HashMap<String, (i64, u32)>>>,
+}
+
+impl FallbackChallenges {
+    /// count a fallback challenge started by `ip` at `now`. Returns false when
+    /// the IP started [FALLBACKS_PER_WINDOW] challenges already in the current
+    /// window
+    pub fn allow(&self, ip: &str, now: i64) -> bool {
+        let mut w = self.windows.write().unwrap();
+        w.retain(|_, (start, _)| now - *start < FALLBACK_WINDOW);
+        let (_, count) = w.entry(ip.to_owned()).or_insert((now, 0));
+        if *count >= FALLBACKS_PER_WINDOW {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// add a fallback challenge of `key` that can be completed `wait` seconds
+    /// after `now`
+    pub fn start(&self, id: &str, key: &str, wait: u32, now: i64) {
+        let challenge = PendingFallback {
+            key: key.into(),
+            ready: now + wait as i64,
+        };
+        let mut w = self.pending.write().unwrap();
+        w.retain(|_, c| !c.expired(now));
+        w.insert(id.into(), challenge);
+    }
+
+    /// remove a fallback challenge of `key` to complete it. Challenges whose
+    /// wait isn't over yet are kept
+    pub fn complete(
+        &self,
+        id: &str,
+        key: &str,
+        now: i64,
+    ) -> ServiceResult<PendingFallback> {
+        let mut w = self.pending.write().unwrap();
+        match w.get(id) {
+            Some(c) if c.key != key || c.expired(now) => {
+                Err(ServiceError::FallbackNotFound)
+            }
+            Some(c) if now < c.ready => Err(ServiceError::FallbackTooEarly),
+            Some(_) => Ok(w.remove(id).unwrap()),
+            None => Err(ServiceError::FallbackNotFound),
+        }
+    }
+}
+
+/// count a fallback challenge started by `ip` at `now`, across the cluster
+/// when there is one. See [FallbackChallenges::allow]
+pub async fn allow(data: &AppData, ip: &str, now: i64) -> ServiceResult<bool> {
+    if data.cluster.is_none() {
+        return Ok(data.fallbacks.allow(ip, now));
+    }
+    let ip_hash = data.tokens.hash_ip(ip);
+    let started = data
+        .db
+        .count_cluster_fallback(&ip_hash, now, now + FALLBACK_WINDOW)
+        .await?;
+    Ok(started <= FALLBACKS_PER_WINDOW)
+}
+
+/// add fallback challenge `id` of `key`, across the cluster when there is
+/// one. See [FallbackChallenges::start]
+pub async fn start(
+    data: &AppData,
+    id: &str,
+    key: &str,
+    wait: u32,
+    now: i64,
+) -> ServiceResult<()> {
+    if data.cluster.is_none() {
+        data.fallbacks.start(id, key, wait, now);
+        return Ok(());
+    }
+    let ready = now + wait as i64;
+    data.db
+        .add_cluster_fallback(key, id, ready, ready + FALLBACK_TTL)
+        .await?;
+    Ok(())
+}
+
+/// complete fallback challenge `id` of `key`, started on any instance of the
+/// cluster when there is one. See [FallbackChallenges::complete]
+pub async fn complete(
+    data: &AppData,
+    id: &str,
+    key: &str,
+    now: i64,
+) -> ServiceResult<()> {
+    if data.cluster.is_none() {
+        data.fallbacks.complete(id, key, now)?;
+        return Ok(());
+    }
+    if data.db.take_cluster_fallback(key, id, now).await? {
+        return Ok(());
+    }
+    match data.db.get_cluster_fallback(key, id, now).await? {
+        Some(ready) if now < ready => Err(ServiceError::FallbackTooEarly),
+        _ => Err(ServiceError::FallbackNotFound),
+    }
+}
+
+/// issue a validation token of `key` to `ip` for a completed fallback
+/// challenge. The token is stored where those of solved PoW are, so that it is
+/// validated the same way, and valid for the cooldown of the sitekey
+pub async fn issue_token(data: &AppData, key: &str, ip: &str) -> ServiceResult<String> {
+    let token = get_random(TOKEN_LEN);
+    let ttl = data.db.get_captcha_cooldown(key).await?;
+    let ip_hash = data.tokens.hash_ip(ip);
+    if cluster::shares_challenges(data) {
+        let expires = OffsetDateTime::now_utc().unix_timestamp() + ttl as i64;
+        data.db
+            .add_cluster_token(key, &token, &ip_hash, expires)
+            .await?;
+    } else {
+        let msg = CacheResult {
+            token: token.clone(),
+            key: key.into(),
+            duration: ttl as u64,
+        };
+        data.captcha.cache_result(msg).await?;
+        let msg = BindToken {
+            token: token.clone(),
+            ip_hash,
+            duration: ttl as u64,
+        };
+        data.captcha.bind_token(msg).await?;
+    }
+
+    let country = data.geoip.as_ref().and_then(|g| g.country(ip));
+    if !data.load.shed() {
+        data.stats
+            .record_solve(data, key, country.as_deref())
+            .await?;
+    }
+    data.tokens
+        .issue_fallback(&token, key, ttl as u64, ip, country);
+    data.webhooks.enqueue(WebhookEvent::Solve, key, None);
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_challenges_work() {
+        let challenges = FallbackChallenges::default();
+        challenges.start("id", "key", 10, 100);
+
+        assert_eq!(
+            challenges.complete("id", "key", 109).err(),
+            Some(ServiceError::FallbackTooEarly)
+        );
+        assert_eq!(
+            challenges.complete("id", "otherkey", 110).err(),
+            Some(ServiceError::FallbackNotFound)
+        );
+        let c = challenges.complete("id", "key", 110).unwrap();
+        assert_eq!(c.key, "key");
+        assert_eq!(
+            challenges.complete("id", "key", 110).err(),
+            Some(ServiceError::FallbackNotFound)
+        );
+
+        challenges.start("expired", "key", 10, 100);
+        assert_eq!(
+            challenges
+                .complete("expired", "key", 110 + FALLBACK_TTL)
+                .err(),
+            Some(ServiceError::FallbackNotFound)
+        );
+    }
+
+    #[test]
+    fn fallbacks_are_rate_limited() {
+        let challenges = FallbackChallenges::default();
+        for _ in 0..FALLBACKS_PER_WINDOW {
+            assert!(challenges.allow("1.1.1.1", 100));
+        }
+        assert!(!challenges.allow("1.1.1.1", 100 + FALLBACK_WINDOW - 1));
+        assert!(challenges.allow("2.2.2.2", 100));
+        assert!(challenges.allow("1.1.1.1", 100 + FALLBACK_WINDOW));
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Fraud heuristics on PoW submissions
+//!
+//! Submissions to sitekeys that have fraud thresholds set are checked for the
+//! following signals:
+//!
+//! - `implausible_solve_time`: the nonce, which is the number of hashes that
+//!   were computed, couldn't have been reached within the solve time reported
+//!   by the widget at `max_hash_rate` hashes per second
+//! - `nonce_outlier`: the nonce is more than `nonce_outlier_factor` times the
+//!   difficulty factor, which is the expected number of hashes. An honest
+//!   client exceeds ten times the difficulty factor about once in 22,000
+//!   solves
+//! - `repeated_result`: the same result was submitted more than
+//!   `repeated_result_limit` times within an hour, which points to replayed
+//!   solutions
+//!
+//! Suspected fraud is delivered to the sitekey's webhook as a `fraud.suspected`
+//! event and posted to the owner's notifications. Each signal is reported at
+//! most once an hour per sitekey. Submitted results are remembered by the
+//! instance that received them.
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use db_core::errors::DBError;
+use db_core::FraudThresholds;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::errors::*;
+use crate::AppData;
+
+/// seconds for which submitted results are remembered
+const RESULT_WINDOW: i64 = 3600;
+/// seconds within which a signal isn't reported again for the same sitekey
+const REPORT_INTERVAL: i64 = 3600;
+/// maximum number of submitted results that are remembered
+const MAX_RESULTS: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// fraud heuristics
+pub enum FraudSignal {
+    /// nonce is too large for the reported solve time
+    ImplausibleSolveTime,
+    /// nonce is far larger than the difficulty factor
+    NonceOutlier,
+    /// identical result was submitted repeatedly
+    RepeatedResult,
+}
+
+impl FraudSignal {
+    /// human readable description of the signal
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::ImplausibleSolveTime => {
+                "a solution was reported to be solved faster than is plausible"
+            }
+            Self::NonceOutlier => {
+                "a solution took far more attempts than the difficulty factor requires"
+            }
+            Self::RepeatedResult => "an identical solution was submitted repeatedly",
+        }
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// signals raised by a solution. `time` is the solve time reported by the
+/// widget, in milliseconds
+pub fn solve_signals(
+    thresholds: &FraudThresholds,
+    nonce: u64,
+    time: Option<u32>,
+    difficulty_factor: u32,
+) -> Vec<FraudSignal> {
+    let mut signals = Vec::new();
+    if let (true, Some(time)) = (thresholds.max_hash_rate > 0, time) {
+        let max_hashes = thresholds.max_hash_rate as u64 * (time as u64).max(1) / 1000;
+        if nonce > max_hashes {
+            signals.push(FraudSignal::ImplausibleSolveTime);
+        }
+    }
+    if thresholds.nonce_outlier_factor > 0
+        && nonce > thresholds.nonce_outlier_factor as u64 * difficulty_factor as u64
+    {
+        signals.push(FraudSignal::NonceOutlier);
+    }
+    signals
+}
+
+#[derive(Default)]
+pub struct FraudDetector {
+    /// number of submissions and time of first submission of results
+    results: RwLock<HashMap<String, (u32, i64)>>,
+    /// time at which signals were last reported for sitekeys
+    reported: RwLock<HashMap<(String, FraudSignal), i64>>,
+}
+
+impl FraudDetector {
+    /// record submitted result and check if it was submitted more times than
+    /// the limit allows
+    pub fn repeated(
+        &self,
+        thresholds: &FraudThresholds,
+        key: &str,
+        result: &str,
+    ) -> bool {
+        if thresholds.repeated_result_limit == 0 {
+            return false;
+        }
+        let now = now();
+        let mut w = self.results.write().unwrap();
+        if w.len() >= MAX_RESULTS {
+            w.retain(|_, (_, first)| now - *first < RESULT_WINDOW);
+            if w.len() >= MAX_RESULTS {
+                w.clear();
+            }
+        }
+        let entry = w.entry(format!("{key}:{result}")).or_insert((0, now));
+        if now - entry.1 >= RESULT_WINDOW {
+            *entry = (0, now);
+        }
+        entry.0 += 1;
+        entry.0 > thresholds.repeated_result_limit
+    }
+
+    /// check if signal can be reported for a sitekey, and mark it reported
+    fn should_report(&self, key: &str, signal: FraudSignal) -> bool {
+        let now = now();
+        let mut w = self.reported.write().unwrap();
+        match w.get(&(key.to_string(), signal)) {
+            Some(last) if now - *last < REPORT_INTERVAL => false,
+            _ => {
+                w.insert((key.to_string(), signal), now);
+                true
+            }
+        }
+    }
+}
+
+/// thresholds of a sitekey's fraud heuristics, if they are enabled
+pub async fn thresholds(
+    data: &AppData,
+    key: &str,
+) -> ServiceResult<Option<FraudThresholds>> {
+    match data.db.get_fraud_thresholds(key).await {
+        Ok(t) => Ok(Some(t)),
+        Err(DBError::FraudThresholdsNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// report suspected fraud to the sitekey's webhook and owner. Failures are
+/// logged, so that reporting doesn't fail verification
+pub async fn report(
+    data: &AppData,
+    key: &str,
+    signal: FraudSignal,
+    difficulty_factor: Option<u32>,
+) {
+    if !data.fraud.should_report(key, signal) {
+        return;
+    }
+    log::warn!("Suspected fraud on sitekey {key}: {}", signal.description());
+    data.webhooks.enqueue_fraud(key, signal, difficulty_factor);
+
+    if !data.settings.features.notifications {
+        return;
+    }
+    let owner = match data.db.get_captcha_owner(key).await {
+        Ok(owner) => owner,
+        Err(e) => {
+            log::error!(
+                "Unable to notify owner of sitekey {key} of suspected fraud: {e}"
+            );
+            return;
+        }
+    };
+    let message = format!(
+        "Suspected fraud on sitekey {key}: {}. This signal won't be reported again for an hour.",
+        signal.description()
+    );
+    let n = db_core::AddNotification {
+        to: &owner,
+        from: &owner,
+        heading: "Suspected fraud",
+        message: &message,
+    };
+    if let Err(e) = crate::api::v1::notifications::notify(data, &n).await {
+        log::error!("Unable to notify {owner} of suspected fraud: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhooks::WebhookEvent;
+
+    const THRESHOLDS: FraudThresholds = FraudThresholds {
+        max_hash_rate: 1_000_000,
+        nonce_outlier_factor: 10,
+        repeated_result_limit: 2,
+    };
+
+    #[test]
+    fn solve_signals_work() {
+        assert!(solve_signals(&THRESHOLDS, 50_000, Some(100), 50_000).is_empty());
+        assert!(solve_signals(&THRESHOLDS, 50_000, None, 50_000).is_empty());
+        assert_eq!(
+            solve_signals(&THRESHOLDS, 50_000, Some(10), 50_000),
+            vec![FraudSignal::ImplausibleSolveTime]
+        );
+        assert_eq!(
+            solve_signals(&THRESHOLDS, 600_000, Some(1000), 50_000),
+            vec![FraudSignal::NonceOutlier]
+        );
+
+        let disabled = FraudThresholds::default();
+        assert!(solve_signals(&disabled, 600_000, Some(0), 50_000).is_empty());
+    }
+
+    #[test]
+    fn repeated_result_works() {
+        let detector = FraudDetector::default();
+        assert!(!detector.repeated(&THRESHOLDS, "key", "result"));
+        assert!(!detector.repeated(&THRESHOLDS, "key", "result"));
+        assert!(detector.repeated(&THRESHOLDS, "key", "result"));
+        assert!(!detector.repeated(&THRESHOLDS, "other", "result"));
+        assert!(!detector.repeated(&FraudThresholds::default(), "key", "result"));
+    }
+
+    #[test]
+    fn reports_are_rate_limited() {
+        let detector = FraudDetector::default();
+        assert!(detector.should_report("key", FraudSignal::NonceOutlier));
+        assert!(!detector.should_report("key", FraudSignal::NonceOutlier));
+        assert!(detector.should_report("key", FraudSignal::RepeatedResult));
+        assert!(detector.should_report("other", FraudSignal::NonceOutlier));
+    }
+
+    #[test]
+    fn event_names_work() {
+        assert_eq!(
+            serde_json::to_value(WebhookEvent::FraudSuspected).unwrap(),
+            WebhookEvent::FraudSuspected.name()
+        );
+        assert_eq!(
+            serde_json::to_value(FraudSignal::ImplausibleSolveTime).unwrap(),
+            "implausible_solve_time"
+        );
+    }
+}
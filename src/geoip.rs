@@ -0,0 +1,58 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Country of solvers, for stats
+//!
+//! When `captcha.geoip_database` is set, the IP address of a client that
+//! solves a challenge is looked up in the MaxMind database and only the
+//! resulting country code is kept. It is recorded with the solve, and with
+//! the confirm of the token that was issued for it; the address itself is
+//! never written to the database. Confirms are only attributed to a country
+//! on the instance that issued the token.
+use std::net::IpAddr;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+use crate::settings::Settings;
+
+/// MaxMind country database
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    /// open the database at `path`
+    pub fn open(path: &str) -> Result<Self, MaxMindDBError> {
+        let reader = Reader::open_readfile(path)?;
+        Ok(Self { reader })
+    }
+
+    /// database configured in `captcha.geoip_database`, if any. Panics when
+    /// it can't be opened, like other invalid settings do on startup
+    pub fn new(s: &Settings) -> Option<Self> {
+        let path = s.captcha.geoip_database.as_ref()?;
+        let geoip = Self::open(path)
+            .unwrap_or_else(|e| panic!("Unable to open GeoIP database {path}: {e}"));
+        log::info!("Recording countries of solvers from GeoIP database {path}");
+        Some(geoip)
+    }
+
+    /// ISO 3166-1 alpha-2 code of the country of `ip`, if it is known
+    pub fn country(&self, ip: &str) -> Option<String> {
+        let ip: IpAddr = ip.parse().ok()?;
+        let record: geoip2::Country = self.reader.lookup(ip).ok()?;
+        record.country?.iso_code.map(|c| c.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_fails_on_missing_database() {
+        assert!(GeoIp::open("/nonexistent/GeoLite2-Country.mmdb").is_err());
+    }
+}
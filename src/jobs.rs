@@ -0,0 +1,161 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Status of background jobs
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+/// Demo user reset job
+pub const DEMO_USER_JOB: &str = "demo_user";
+/// Expired demo sitekey cleanup job
+pub const DEMO_CLEANUP_JOB: &str = "demo_cleanup";
+/// Expired trial sitekey cleanup job
+pub const TRIAL_CLEANUP_JOB: &str = "trial_cleanup";
+/// Easy captcha configuration update job
+pub const EASY_CAPTCHA_JOB: &str = "update_easy_captcha";
+/// Survey benchmark upload job
+pub const SURVEY_UPLOAD_JOB: &str = "survey_upload";
+/// Database maintenance job
+pub const DB_MAINTENANCE_JOB: &str = "db_maintenance";
+/// Buffered stats flush job
+pub const STATS_FLUSH_JOB: &str = "stats_flush";
+/// Webhook delivery job
+pub const WEBHOOK_JOB: &str = "webhook_delivery";
+/// Traffic burst schedule refresh job
+pub const BURST_SCHEDULE_JOB: &str = "burst_schedule";
+/// Read notification cleanup job
+pub const NOTIFICATION_RETENTION_JOB: &str = "notification_retention";
+/// Automatic application of recommended levels job
+pub const RECOMMENDATION_JOB: &str = "apply_recommendations";
+/// Anomaly detection on stats of sitekeys job
+pub const ANOMALY_JOB: &str = "detect_anomalies";
+/// Cluster visitor count sync job
+pub const CLUSTER_SYNC_JOB: &str = "cluster_sync";
+/// Outgoing email delivery job
+pub const MAIL_QUEUE_JOB: &str = "mail_queue";
+/// Stats digest email job
+pub const DIGEST_JOB: &str = "stats_digest";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// status of a single background job
+pub struct JobStatus {
+    /// job name
+    pub name: String,
+    /// seconds between runs
+    pub interval: u64,
+    /// unix timestamp of the start of the last run
+    pub last_run: Option<i64>,
+    /// duration of the last run, in milliseconds
+    pub last_duration: Option<u64>,
+    /// whether the last run succeeded
+    pub last_success: Option<bool>,
+    /// error reported by the last run
+    pub last_error: Option<String>,
+    /// unix timestamp of the next scheduled run
+    pub next_run: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct JobStatusStore {
+    store: Arc<RwLock<HashMap<String, JobStatus>>>,
+}
+
+impl JobStatusStore {
+    /// start tracking a job; the first run is expected after `interval` seconds
+    pub fn register(&self, name: &str, interval: u64) {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let status = JobStatus {
+            name: name.into(),
+            interval,
+            next_run: Some(now + interval as i64),
+            ..Default::default()
+        };
+        let mut w = self.store.write().unwrap();
+        w.insert(name.into(), status);
+        drop(w);
+    }
+
+    /// record the outcome of a job run that started at `started`
+    pub fn finished<E: std::fmt::Display>(
+        &self,
+        name: &str,
+        started: OffsetDateTime,
+        elapsed: Duration,
+        res: &Result<(), E>,
+    ) {
+        let mut w = self.store.write().unwrap();
+        if let Some(status) = w.get_mut(name) {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            status.last_run = Some(started.unix_timestamp());
+            status.last_duration = Some(elapsed.as_millis() as u64);
+            status.last_success = Some(res.is_ok());
+            status.last_error = res.as_ref().err().map(|e| e.to_string());
+            status.next_run = Some(now + status.interval as i64);
+        }
+        drop(w);
+    }
+
+    /// stop tracking a job
+    pub fn rm(&self, name: &str) {
+        let mut w = self.store.write().unwrap();
+        w.remove(name);
+        drop(w);
+    }
+
+    /// get status of a job
+    pub fn get(&self, name: &str) -> Option<JobStatus> {
+        let r = self.store.read().unwrap();
+        r.get(name).cloned()
+    }
+
+    /// get status of all jobs, sorted by name
+    pub fn list(&self) -> Vec<JobStatus> {
+        let r = self.store.read().unwrap();
+        let mut jobs: Vec<JobStatus> = r.values().cloned().collect();
+        drop(r);
+        jobs.sort_by(|a, b| a.name.cmp(&b.name));
+        jobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_status_store_works() {
+        let store = JobStatusStore::default();
+        store.register(EASY_CAPTCHA_JOB, 30);
+        store.register(DEMO_USER_JOB, 60);
+
+        let jobs = store.list();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].name, DEMO_USER_JOB);
+        assert!(jobs[0].last_run.is_none());
+        assert!(jobs[0].next_run.is_some());
+
+        let started = OffsetDateTime::now_utc();
+        let res: Result<(), &str> = Err("failed");
+        store.finished(DEMO_USER_JOB, started, Duration::from_millis(20), &res);
+        let status = store.get(DEMO_USER_JOB).unwrap();
+        assert_eq!(status.last_run, Some(started.unix_timestamp()));
+        assert_eq!(status.last_duration, Some(20));
+        assert_eq!(status.last_success, Some(false));
+        assert_eq!(status.last_error, Some("failed".into()));
+
+        let res: Result<(), &str> = Ok(());
+        store.finished(DEMO_USER_JOB, started, Duration::from_millis(5), &res);
+        let status = store.get(DEMO_USER_JOB).unwrap();
+        assert_eq!(status.last_success, Some(true));
+        assert!(status.last_error.is_none());
+
+        store.rm(DEMO_USER_JOB);
+        assert!(store.get(DEMO_USER_JOB).is_none());
+    }
+}
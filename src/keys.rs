@@ -0,0 +1,162 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Sitekey generation
+//!
+//! Sitekeys are generated in the format set by `captcha.key_length` and
+//! `captcha.key_alphabet`. Changing the format only affects new and rotated
+//! sitekeys. Administrators can also give a sitekey a vanity key, like
+//! `acme-checkout`, so that it is recognizable.
+//!
+//! Every flow that gives a sitekey a new key goes through this module, so that
+//! collisions are handled the same way everywhere: generated keys are retried
+//! until one is free, and vanity keys that are taken are rejected.
+use std::future::Future;
+
+use db_core::errors::DBError;
+use libmcaptcha::master::messages::RenameBuilder;
+use rand::{thread_rng, Rng};
+
+use crate::errors::*;
+use crate::settings::KeyAlphabet;
+use crate::AppData;
+
+/// minimum length of generated sitekeys; short formats run out of free keys
+pub const MIN_KEY_LEN: usize = 16;
+/// maximum length of sitekeys, as stored in the database
+pub const MAX_KEY_LEN: usize = 100;
+/// minimum length of vanity keys
+pub const MIN_VANITY_KEY_LEN: usize = 4;
+/// number of generated keys that are tried before giving up
+const ATTEMPTS: usize = 10;
+
+impl KeyAlphabet {
+    /// characters of the alphabet
+    pub fn chars(&self) -> &'static [u8] {
+        match self {
+            Self::Alphanumeric => {
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"
+            }
+            Self::Lowercase => b"abcdefghijklmnopqrstuvwxyz0123456789",
+            Self::Hex => b"0123456789abcdef",
+        }
+    }
+}
+
+/// generate a key of `len` characters of `alphabet`
+pub fn generate(alphabet: KeyAlphabet, len: usize) -> String {
+    let chars = alphabet.chars();
+    let mut rng = thread_rng();
+    (0..len)
+        .map(|_| chars[rng.gen_range(0..chars.len())] as char)
+        .collect()
+}
+
+/// check that a vanity key is 4 to 100 letters, digits, '-' or '_'
+pub fn validate_vanity(key: &str) -> ServiceResult<()> {
+    if key.len() < MIN_VANITY_KEY_LEN
+        || key.len() > MAX_KEY_LEN
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(ServiceError::InvalidVanityKey);
+    }
+    Ok(())
+}
+
+/// generate keys in the instance's format and `claim` them until a key that
+/// isn't taken is claimed
+pub async fn unique<F, Fut>(data: &AppData, mut claim: F) -> ServiceResult<String>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<(), DBError>>,
+{
+    let captcha = &data.settings.captcha;
+    for _ in 0..ATTEMPTS {
+        let key = generate(captcha.key_alphabet, captcha.key_length);
+        match claim(key.clone()).await {
+            Ok(_) => return Ok(key),
+            Err(DBError::CaptchaKeyTaken) | Err(DBError::SecretTaken) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    log::error!("Unable to generate a free sitekey in {ATTEMPTS} attempts");
+    Err(ServiceError::KeyGenerationFailed)
+}
+
+/// rename sitekey `key` to `new_key` in the master, after its key was changed
+async fn rename(data: &AppData, key: &str, new_key: &str) -> ServiceResult<()> {
+    let rename = RenameBuilder::default()
+        .name(key.into())
+        .rename_to(new_key.into())
+        .build()
+        .unwrap();
+    data.captcha.rename(rename).await?;
+    Ok(())
+}
+
+/// give sitekey `key` of `username` a generated key
+pub async fn rotate(data: &AppData, username: &str, key: &str) -> ServiceResult<String> {
+    let new_key = unique(data, |new_key| async move {
+        data.db.update_captcha_key(username, key, &new_key).await
+    })
+    .await?;
+    rename(data, key, &new_key).await?;
+    Ok(new_key)
+}
+
+/// give sitekey `key` the vanity key `vanity`
+pub async fn set_vanity(data: &AppData, key: &str, vanity: &str) -> ServiceResult<()> {
+    validate_vanity(vanity)?;
+    let owner = data.db.get_captcha_owner(key).await?;
+    match data.db.update_captcha_key(&owner, key, vanity).await {
+        Ok(_) => (),
+        Err(DBError::CaptchaKeyTaken) => return Err(ServiceError::VanityKeyTaken),
+        Err(e) => return Err(e.into()),
+    }
+    rename(data, key, vanity).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_works() {
+        for alphabet in [
+            KeyAlphabet::Alphanumeric,
+            KeyAlphabet::Lowercase,
+            KeyAlphabet::Hex,
+        ] {
+            let key = generate(alphabet, 24);
+            assert_eq!(key.len(), 24);
+            assert!(key.bytes().all(|c| alphabet.chars().contains(&c)));
+        }
+        assert!(generate(KeyAlphabet::Hex, 64)
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+    }
+
+    #[test]
+    fn validate_vanity_works() {
+        for key in ["acme", "acme-checkout", "Acme_2024"] {
+            assert!(validate_vanity(key).is_ok());
+        }
+        let long = "a".repeat(MAX_KEY_LEN + 1);
+        for key in [
+            "acm",
+            "acme checkout",
+            "acme/checkout",
+            "äcme",
+            long.as_str(),
+        ] {
+            assert!(matches!(
+                validate_vanity(key),
+                Err(ServiceError::InvalidVanityKey)
+            ));
+        }
+    }
+}
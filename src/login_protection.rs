@@ -0,0 +1,351 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Brute-force protection for sign-in
+//!
+//! Failed sign-in attempts are counted per account and per IP address. Once
+//! either count crosses its challenge threshold, further attempts have to
+//! include the solution to a PoW challenge issued by the instance. Accounts and
+//! IP addresses that keep failing are locked out for a while, and each
+//! subsequent lockout lasts twice as long as the previous one, up to a limit.
+//!
+//! Counts are forgotten once no attempt has failed for the configured window,
+//! and an account's count is reset when it signs in successfully. Counts and
+//! challenges are local to the instance. Challenges can be requested without
+//! signing in, so at most [MAX_CHALLENGES] are kept; beyond that, the oldest
+//! challenge is dropped.
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use libmcaptcha::pow::{ConfigBuilder, Work};
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::settings::{LoginProtection, Settings};
+
+/// seconds within which a challenge has to be solved
+pub const CHALLENGE_TTL: i64 = 300;
+/// challenges that can be issued and unsolved at once
+pub const MAX_CHALLENGES: usize = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// PoW challenge that has to be solved to sign in
+pub struct LoginChallenge {
+    pub string: String,
+    pub difficulty_factor: u32,
+    pub salt: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// solution to a [LoginChallenge]
+pub struct LoginWork {
+    pub string: String,
+    pub nonce: u64,
+    pub result: String,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Failures {
+    count: u32,
+    /// time of last failed attempt
+    last: i64,
+    /// number of times that lockout was triggered
+    lockouts: u32,
+    locked_until: i64,
+}
+
+impl Failures {
+    fn expired(&self, now: i64, window: i64) -> bool {
+        now >= self.last.max(self.locked_until) + window
+    }
+}
+
+pub struct FailedLogins {
+    settings: LoginProtection,
+    salt: String,
+    failures: RwLock<HashMap<String, Failures>>,
+    /// issued challenges and their time of issue
+    challenges: RwLock<HashMap<String, i64>>,
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// key of an account's failed attempts. `account` is the username that the
+/// login resolved to, so that signing in with the username and with the email
+/// address share a count
+fn account_key(account: &str) -> String {
+    format!("account:{}", account.trim().to_lowercase())
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{ip}")
+}
+
+impl FailedLogins {
+    pub fn new(s: &Settings) -> Self {
+        Self {
+            settings: s.login_protection.clone(),
+            salt: s.captcha.salt.clone(),
+            failures: RwLock::new(HashMap::default()),
+            challenges: RwLock::new(HashMap::default()),
+        }
+    }
+
+    fn get(&self, key: &str, now: i64) -> Failures {
+        match self.failures.read().unwrap().get(key) {
+            Some(f) if !f.expired(now, self.settings.window as i64) => *f,
+            _ => Failures::default(),
+        }
+    }
+
+    /// does signing into `login` from `ip` require solving a challenge
+    pub fn challenge_required(&self, login: &str, ip: &str) -> bool {
+        if !self.settings.enabled {
+            return false;
+        }
+        let now = now();
+        self.get(&account_key(login), now).count >= self.settings.max_account_failures
+            || self.get(&ip_key(ip), now).count >= self.settings.max_ip_failures
+    }
+
+    /// time until which signing into `login` from `ip` is locked out, if it is
+    pub fn locked_until(&self, login: &str, ip: &str) -> Option<i64> {
+        if !self.settings.enabled {
+            return None;
+        }
+        let now = now();
+        let until = self
+            .get(&account_key(login), now)
+            .locked_until
+            .max(self.get(&ip_key(ip), now).locked_until);
+        (until > now).then_some(until)
+    }
+
+    /// duration of `lockouts`th lockout
+    fn lockout_duration(&self, lockouts: u32) -> i64 {
+        let base = self.settings.lockout_duration as i64;
+        let max = self.settings.max_lockout_duration as i64;
+        base.saturating_mul(1 << lockouts.saturating_sub(1).min(30))
+            .min(max)
+    }
+
+    /// record failed sign-in attempt. Returns the duration of the account's
+    /// lockout, when this attempt triggered one
+    pub fn record_failure(&self, login: &str, ip: &str) -> Option<i64> {
+        if !self.settings.enabled {
+            return None;
+        }
+        let now = now();
+        let window = self.settings.window as i64;
+        let mut w = self.failures.write().unwrap();
+        w.retain(|_, f| !f.expired(now, window));
+
+        let mut account_lockout = None;
+        for (key, threshold, is_account) in [
+            (account_key(login), self.settings.lockout_threshold, true),
+            (ip_key(ip), self.settings.ip_lockout_threshold, false),
+        ] {
+            let f = w.entry(key).or_default();
+            f.count += 1;
+            f.last = now;
+            if threshold > 0 && f.count >= threshold * (f.lockouts + 1) {
+                f.lockouts += 1;
+                let duration = self.lockout_duration(f.lockouts);
+                f.locked_until = now + duration;
+                if is_account {
+                    account_lockout = Some(duration);
+                }
+            }
+        }
+        account_lockout
+    }
+
+    /// forget failed attempts on an account, after it signs in successfully
+    pub fn reset(&self, login: &str) {
+        self.failures.write().unwrap().remove(&account_key(login));
+    }
+
+    /// issue new challenge. Expired challenges are dropped, as is the oldest
+    /// one when too many are unsolved
+    pub fn challenge(&self) -> LoginChallenge {
+        let string = get_random(32);
+        let now = now();
+        let mut w = self.challenges.write().unwrap();
+        w.retain(|_, issued| now - *issued < CHALLENGE_TTL);
+        if w.len() >= MAX_CHALLENGES {
+            let oldest = w
+                .iter()
+                .min_by_key(|(_, issued)| **issued)
+                .map(|(string, _)| string.clone());
+            if let Some(oldest) = oldest {
+                w.remove(&oldest);
+            }
+        }
+        w.insert(string.clone(), now);
+        drop(w);
+        LoginChallenge {
+            string,
+            difficulty_factor: self.settings.difficulty_factor,
+            salt: self.salt.clone(),
+        }
+    }
+
+    /// verify solution to a challenge. Challenges can only be used once
+    pub fn verify(&self, work: &LoginWork) -> bool {
+        let issued = self.challenges.write().unwrap().remove(&work.string);
+        match issued {
+            Some(issued) if now() - issued < CHALLENGE_TTL => (),
+            _ => return false,
+        }
+
+        let config = ConfigBuilder::default()
+            .salt(self.salt.clone())
+            .build()
+            .unwrap();
+        let work = Work {
+            string: work.string.clone(),
+            nonce: work.nonce,
+            result: work.result.clone(),
+            key: String::default(),
+        };
+        let string = work.string.clone();
+        let pow = work.into();
+        config.is_valid_proof(&pow, &string)
+            && config.is_sufficient_difficulty(&pow, self.settings.difficulty_factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed_logins() -> FailedLogins {
+        let mut settings = crate::tests::get_settings();
+        settings.login_protection = LoginProtection {
+            enabled: true,
+            max_account_failures: 2,
+            max_ip_failures: 3,
+            window: 60,
+            difficulty_factor: 500,
+            lockout_threshold: 4,
+            ip_lockout_threshold: 6,
+            lockout_duration: 60,
+            max_lockout_duration: 200,
+        };
+        FailedLogins::new(&settings)
+    }
+
+    #[test]
+    fn escalation_works() {
+        let f = failed_logins();
+        assert!(!f.challenge_required("user", "1.1.1.1"));
+        f.record_failure("user", "1.1.1.1");
+        assert!(!f.challenge_required("user", "1.1.1.1"));
+        f.record_failure("User", "2.2.2.2");
+        assert!(f.challenge_required("user", "3.3.3.3"));
+        f.reset("user");
+        assert!(!f.challenge_required("user", "1.1.1.1"));
+
+        // IP threshold applies across accounts
+        f.record_failure("a", "1.1.1.1");
+        f.record_failure("b", "1.1.1.1");
+        assert!(f.challenge_required("c", "1.1.1.1"));
+        assert!(!f.challenge_required("c", "2.2.2.2"));
+    }
+
+    #[test]
+    fn lockout_works() {
+        let f = failed_logins();
+        for _ in 0..3 {
+            assert_eq!(f.record_failure("user", "1.1.1.1"), None);
+        }
+        assert!(f.locked_until("user", "2.2.2.2").is_none());
+        assert_eq!(f.record_failure("user", "1.1.1.1"), Some(60));
+        assert!(f.locked_until("user", "2.2.2.2").is_some());
+        assert!(f.locked_until("other", "2.2.2.2").is_none());
+
+        // subsequent lockouts last longer, up to the limit
+        for _ in 0..3 {
+            f.record_failure("user", "2.2.2.2");
+        }
+        assert_eq!(f.record_failure("user", "3.3.3.3"), Some(120));
+        for _ in 0..3 {
+            f.record_failure("user", "4.4.4.4");
+        }
+        assert_eq!(f.record_failure("user", "5.5.5.5"), Some(200));
+
+        // IP lockout applies across accounts
+        for i in 0..6 {
+            assert_eq!(f.record_failure(&format!("ipuser{i}"), "6.6.6.6"), None);
+        }
+        assert!(f.locked_until("another", "6.6.6.6").is_some());
+    }
+
+    #[test]
+    fn challenge_works() {
+        let f = failed_logins();
+        let challenge = f.challenge();
+        let pow = mcaptcha_pow_sha256::ConfigBuilder::default()
+            .salt(challenge.salt.clone())
+            .build()
+            .unwrap();
+        let proof = pow
+            .prove_work(&challenge.string, challenge.difficulty_factor)
+            .unwrap();
+        let work = LoginWork {
+            string: challenge.string.clone(),
+            nonce: proof.nonce,
+            result: proof.result,
+        };
+        assert!(f.verify(&work));
+        // single use
+        assert!(!f.verify(&work));
+
+        let unknown = LoginWork {
+            string: "unknown".into(),
+            ..work
+        };
+        assert!(!f.verify(&unknown));
+    }
+
+    #[test]
+    fn challenges_are_capped() {
+        let f = failed_logins();
+        let now = now();
+        {
+            let mut w = f.challenges.write().unwrap();
+            w.insert("expired".into(), now - CHALLENGE_TTL);
+            w.insert("oldest".into(), now - 2);
+            for i in w.len()..MAX_CHALLENGES {
+                w.insert(i.to_string(), now - 1);
+            }
+        }
+        let challenge = f.challenge();
+        let w = f.challenges.read().unwrap();
+        assert_eq!(w.len(), MAX_CHALLENGES);
+        assert!(!w.contains_key("expired"));
+        drop(w);
+
+        f.challenge();
+        let w = f.challenges.read().unwrap();
+        assert_eq!(w.len(), MAX_CHALLENGES);
+        assert!(!w.contains_key("oldest"));
+        assert!(w.contains_key(&challenge.string));
+    }
+
+    #[test]
+    fn disabled_protection_never_escalates() {
+        let mut settings = crate::tests::get_settings();
+        settings.login_protection.enabled = false;
+        settings.login_protection.max_account_failures = 1;
+        let f = FailedLogins::new(&settings);
+        f.record_failure("user", "1.1.1.1");
+        f.record_failure("user", "1.1.1.1");
+        assert!(!f.challenge_required("user", "1.1.1.1"));
+    }
+}
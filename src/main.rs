@@ -9,45 +9,94 @@ use std::sync::Arc;
 
 use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_web::{
-    error::InternalError, http::StatusCode, middleware as actix_middleware,
-    web::JsonConfig, App, HttpServer,
+    error::{InternalError, JsonPayloadError},
+    http::StatusCode,
+    middleware as actix_middleware,
+    web::JsonConfig,
+    App, HttpServer,
 };
 use lazy_static::lazy_static;
 use log::info;
 use tokio::task::JoinHandle;
 
+mod admin;
+mod agreements;
+mod alerts;
+mod anomalies;
 mod api;
+mod api_tokens;
+mod body_limits;
+mod bursts;
+mod client_ip;
+mod cluster;
+mod config_check;
+mod consistency;
 mod data;
 mod date;
 mod db;
+mod decay;
 mod demo;
+mod digest;
 mod docs;
+mod domains;
 mod easy;
 mod email;
 mod errors;
+mod fallback;
+mod fraud;
+mod geoip;
+mod jobs;
+mod keys;
+mod login_protection;
+mod maintenance;
+mod master_sync;
+mod memcached;
+mod notification_retention;
+mod notification_stream;
+mod oidc;
+mod overload;
 #[macro_use]
 mod pages;
+mod priority;
+mod quotas;
+mod rate_limit;
+mod recommendation;
+mod reload;
+mod request_id;
 #[macro_use]
 mod routes;
+mod security_headers;
+mod sessions;
 mod settings;
+mod shutdown;
 mod static_assets;
 mod stats;
+mod stats_sink;
 mod survey;
 #[cfg(test)]
 #[macro_use]
 mod tests;
+mod telemetry;
+mod tls;
+mod tokens;
+mod trial;
+mod warm_up;
+mod webhooks;
 mod widget;
 
 pub use crate::data::Data;
 pub use crate::static_assets::static_files::assets::*;
 pub use api::v1::ROUTES as V1_API_ROUTES;
+pub use api::v2::ROUTES as V2_API_ROUTES;
 pub use docs::DOCS;
 pub use pages::routes::ROUTES as PAGES;
 pub use settings::Settings;
 use static_assets::FileMap;
 pub use widget::WIDGET_ROUTES;
 
-use crate::demo::DemoUser;
+use crate::demo::{DemoCleanup, DemoUser};
+use crate::notification_retention::NotificationRetention;
+use crate::trial::TrialCleanup;
 use survey::SurveyClientTrait;
 
 lazy_static! {
@@ -65,6 +114,9 @@ lazy_static! {
         FILES.get("./static/cache/bundle/verificationWidget.js").unwrap();
     pub static ref VERIFICATIN_WIDGET_CSS: &'static str =
         FILES.get("./static/cache/bundle/css/widget.css").unwrap();
+    /// script that sites embed the widget with
+    pub static ref EMBED_JS: &'static str =
+        FILES.get("./static/cache/bundle/embed.js").unwrap();
 
     /// points to source files matching build commit
     pub static ref SOURCE_FILES_OF_INSTANCE: String = {
@@ -101,21 +153,89 @@ async fn main() -> std::io::Result<()> {
         env::set_var("RUST_LOG", "info");
     }
 
-    pretty_env_logger::init();
-    info!(
-        "{}: {}.\nFor more information, see: {}\nBuild info:\nVersion: {} commit: {}",
-        PKG_NAME, PKG_DESCRIPTION, PKG_HOMEPAGE, VERSION, GIT_COMMIT_HASH
-    );
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("check-config") {
+        std::process::exit(config_check::run(&args[1..]).await);
+    }
+    if args == ["--print-config"] {
+        let settings = Settings::new().unwrap();
+        let config = serde_json::to_string_pretty(&settings.effective).unwrap();
+        println!("{config}");
+        return Ok(());
+    }
+    // `mcaptcha demo` runs a throwaway instance with the demo profile
+    let demo = args == ["demo"];
+    let command = if args.is_empty() || demo {
+        None
+    } else {
+        match admin::Command::parse(&args) {
+            Some(command) => Some(command),
+            None => {
+                eprintln!("{}", admin::USAGE);
+                std::process::exit(2);
+            }
+        }
+    };
 
+    if demo {
+        // also applies to settings that are loaded lazily
+        env::set_var("MCAPTCHA_PROFILE", settings::Profile::Demo.to_string());
+    }
     let settings = Settings::new().unwrap();
+    telemetry::init(&settings);
+    info!("{}", banner(&settings));
+
     let secrets = survey::SecretsStore::default();
     let data = Data::new(&settings, secrets.clone()).await;
     let data = actix_web::web::Data::new(data);
 
+    if let Some(command) = command {
+        if let Err(e) = command.run(&data).await {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    admin::bootstrap(&data).await;
+    if let Err(e) = domains::grandfather(&data).await {
+        log::error!("Unable to grandfather sitekeys without allowed domains: {e}");
+    }
+
+    if let Err(e) = consistency::reconcile(&data).await {
+        log::error!("Unable to check Redis master for drift: {e}");
+    }
+
+    let state_file = settings.captcha.state_file.clone();
+    if let Some(path) = &state_file {
+        if let Err(e) = shutdown::restore(&data, path).await {
+            log::error!("Unable to restore state of the embedded master: {e}");
+        }
+    }
+    if settings.captcha.warm_up {
+        if let Err(e) = warm_up::warm_up(&data, settings.captcha.warm_up_limit).await {
+            log::error!("Unable to warm up captchas: {e}");
+        }
+    }
+
     let mut demo_user: Option<(DemoUser, JoinHandle<()>)> = None;
+    let mut demo_cleanup: Option<(DemoCleanup, JoinHandle<()>)> = None;
 
     if settings.allow_demo && settings.allow_registration {
-        demo_user = Some(DemoUser::spawn(data.clone(), 60 * 30).await.unwrap());
+        demo_user = Some(
+            DemoUser::spawn(data.clone(), settings.demo.reset_interval)
+                .await
+                .unwrap(),
+        );
+        demo_cleanup = Some(DemoCleanup::spawn(data.clone()).await.unwrap());
+    }
+
+    // trial sitekeys are deleted even after trials are disabled
+    let trial_cleanup = TrialCleanup::spawn(data.clone()).await.unwrap();
+
+    let mut notification_retention = None;
+    if settings.notifications.retention > 0 {
+        notification_retention =
+            Some(NotificationRetention::spawn(data.clone()).await.unwrap());
     }
 
     let mut update_easy_captcha: Option<(easy::UpdateEasyCaptcha, JoinHandle<()>)> =
@@ -133,36 +253,140 @@ async fn main() -> std::io::Result<()> {
         );
     }
 
+    let mut anomaly_detector = None;
+    if settings.features.notifications && settings.captcha.enable_stats {
+        anomaly_detector = Some(
+            anomalies::AnomalyDetector::spawn(data.clone())
+                .await
+                .unwrap(),
+        );
+    }
+
+    let mut apply_recommendations = None;
+    if settings.features.analytics {
+        apply_recommendations = Some(
+            recommendation::ApplyRecommendations::spawn(data.clone())
+                .await
+                .unwrap(),
+        );
+    }
+
+    let db_maintenance = maintenance::DbMaintenance::spawn(data.clone())
+        .await
+        .unwrap();
+
+    let webhook_dispatcher = webhooks::WebhookDispatcher::spawn(data.clone())
+        .await
+        .unwrap();
+
+    let burst_scheduler = bursts::BurstScheduler::spawn(data.clone()).await.unwrap();
+
+    // relays notifications between instances when Redis is configured
+    let notification_relay = notification_stream::NotificationRelay::spawn(data.clone())
+        .await
+        .unwrap();
+
+    let config_reloader = reload::ConfigReloader::spawn(data.clone()).await.unwrap();
+
+    let mut cluster_sync = None;
+    if data.cluster.is_some() {
+        cluster_sync = Some(cluster::ClusterSync::spawn(data.clone()).await.unwrap());
+    }
+
+    let mut stats_flusher = None;
+    if settings.captcha.enable_stats && settings.captcha.stats_buffer_size > 0 {
+        stats_flusher = Some(stats::StatsFlusher::spawn(data.clone()).await.unwrap());
+    }
+
+    let mut mail_sender = None;
+    let mut digest_sender = None;
+    if data.mailer.is_some() {
+        mail_sender = Some(email::queue::MailSender::spawn(data.clone()).await.unwrap());
+        digest_sender = Some(digest::DigestSender::spawn(data.clone()).await.unwrap());
+    }
+
     let (mut survey_upload_tx, mut survey_upload_handle) = (None, None);
-    if settings.survey.is_some() {
+    if settings.survey.is_some()
+        && settings.features.survey
+        && settings.features.analytics
+    {
         let survey_runner_ctx = survey::Survey::new(data.clone());
         let (x, y) = survey_runner_ctx.start_job().await.unwrap();
         (survey_upload_tx, survey_upload_handle) = (Some(x), Some(y));
     }
 
     let ip = settings.server.get_ip();
-    println!("Starting server on: http://{ip}");
+    let tls_config = tls::server_config(&settings.server);
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let unix_socket = settings.server.unix_socket.clone();
+    let unix_socket_mode = settings.server.unix_socket_permissions().unwrap();
+    match &unix_socket {
+        Some(path) => println!("Starting server on: unix:{path}"),
+        None => println!("Starting server on: {scheme}://{ip}"),
+    }
+    if settings.allow_demo && settings.demo.test_page {
+        match demo::test_page_sitekey(&data).await {
+            Ok(key) => println!(
+                "Demo account: {} / {}\nTest page, with sitekey {key}: {scheme}://{ip}{}",
+                demo::DEMO_USER,
+                demo::DEMO_PASSWORD,
+                WIDGET_ROUTES.demo
+            ),
+            Err(e) => log::error!("Unable to create sitekey of the test page: {e}"),
+        }
+    }
 
-    HttpServer::new(move || {
+    let shutdown_timeout = settings.captcha.shutdown_timeout;
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(actix_middleware::Logger::default())
+            .wrap(actix_middleware::Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}i"#,
+            ))
+            .wrap(actix_middleware::Condition::new(
+                settings.tracing.enabled,
+                tracing_actix_web::TracingLogger::default(),
+            ))
             .wrap(
                 actix_middleware::DefaultHeaders::new()
                     .add(("Permissions-Policy", "interest-cohort=()")),
             )
+            .wrap(security_headers::SecurityHeaders::new(&settings))
+            .wrap(api::v2::compat::DeprecationHeaders)
+            .wrap(agreements::AgreementGate)
+            .wrap(admin::AdminGate)
+            .wrap(body_limits::BodyLimits)
+            .wrap(rate_limit::RateLimiter)
             .wrap(get_identity_service(&settings))
             .wrap(actix_middleware::Compress::default())
             .app_data(data.clone())
             .wrap(actix_middleware::NormalizePath::new(
                 actix_middleware::TrailingSlash::Trim,
             ))
+            .wrap(request_id::RequestIds)
             .configure(routes::services)
-            .app_data(get_json_err())
+            .app_data(get_json_err(&settings))
     })
-    .bind(&ip)
-    .unwrap()
-    .run()
-    .await?;
+    // pending verifications are drained before the server stops
+    .shutdown_timeout(shutdown_timeout);
+    let server = match (&unix_socket, tls_config) {
+        (Some(path), _) => {
+            use std::os::unix::fs::PermissionsExt;
+
+            remove_stale_socket(path)?;
+            server.bind_uds(path).and_then(|server| {
+                let permissions = std::fs::Permissions::from_mode(unix_socket_mode);
+                std::fs::set_permissions(path, permissions)?;
+                Ok(server)
+            })
+        }
+        (None, Some(config)) => server.bind_rustls_021(&ip, config),
+        (None, None) => server.bind(&ip),
+    };
+    server.unwrap().run().await?;
 
     if let Some(survey_upload_tx) = survey_upload_tx {
         survey_upload_tx.send(()).unwrap();
@@ -173,39 +397,168 @@ async fn main() -> std::io::Result<()> {
         demo_user.1.await.unwrap();
     }
 
+    if let Some(demo_cleanup) = demo_cleanup {
+        demo_cleanup.0.abort();
+        demo_cleanup.1.await.unwrap();
+    }
+
+    trial_cleanup.0.abort();
+    trial_cleanup.1.await.unwrap();
+
+    if let Some(notification_retention) = notification_retention {
+        notification_retention.0.abort();
+        notification_retention.1.await.unwrap();
+    }
+
     if let Some(update_easy_captcha) = update_easy_captcha {
         update_easy_captcha.0.abort();
         update_easy_captcha.1.await.unwrap();
     }
 
+    if let Some(anomaly_detector) = anomaly_detector {
+        anomaly_detector.0.abort();
+        anomaly_detector.1.await.unwrap();
+    }
+
+    if let Some(apply_recommendations) = apply_recommendations {
+        apply_recommendations.0.abort();
+        apply_recommendations.1.await.unwrap();
+    }
+
+    db_maintenance.0.abort();
+    db_maintenance.1.await.unwrap();
+
+    webhook_dispatcher.0.abort();
+    webhook_dispatcher.1.await.unwrap();
+
+    burst_scheduler.0.abort();
+    burst_scheduler.1.await.unwrap();
+
+    notification_relay.0.abort();
+    notification_relay.1.await.unwrap();
+
+    config_reloader.0.abort();
+    config_reloader.1.await.unwrap();
+
+    if let Some(cluster_sync) = cluster_sync {
+        cluster_sync.0.abort();
+        cluster_sync.1.await.unwrap();
+    }
+
+    if let Some(stats_flusher) = stats_flusher {
+        stats_flusher.0.abort();
+        stats_flusher.1.await.unwrap();
+    }
+
+    if let Some(digest_sender) = digest_sender {
+        digest_sender.0.abort();
+        digest_sender.1.await.unwrap();
+    }
+
+    if let Some(mail_sender) = mail_sender {
+        mail_sender.0.abort();
+        mail_sender.1.await.unwrap();
+    }
+
+    // saved once verifications are drained, so that their visitors are counted
+    if let Some(path) = &state_file {
+        match shutdown::save(&data, path).await {
+            Ok(Some(saved)) => log::info!("Saved state of {saved} sitekeys to {path}"),
+            Ok(None) => (),
+            Err(e) => log::error!("Unable to save state of the embedded master: {e}"),
+        }
+    }
+
     if let Some(survey_upload_handle) = survey_upload_handle {
         survey_upload_handle.await.unwrap();
     }
 
+    telemetry::shutdown();
     Ok(())
 }
 
 #[cfg(not(tarpaulin_include))]
-pub fn get_json_err() -> JsonConfig {
-    JsonConfig::default().error_handler(|err, _| {
-        //debug!("JSON deserialization error: {:?}", &err);
-        InternalError::new(err, StatusCode::BAD_REQUEST).into()
-    })
+pub fn get_json_err(s: &Settings) -> JsonConfig {
+    // per-route limits are enforced by body_limits::BodyLimits
+    JsonConfig::default()
+        .limit(body_limits::max_size_limit(&s.server.limits))
+        .error_handler(|err, _| match err {
+            JsonPayloadError::Overflow { limit }
+            | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                errors::ServiceError::PayloadTooLarge(limit).into()
+            }
+            //debug!("JSON deserialization error: {:?}", &err);
+            err => InternalError::new(err, StatusCode::BAD_REQUEST).into(),
+        })
+}
+
+/// startup summary of the build and of where the configuration came from
+#[cfg(not(tarpaulin_include))]
+fn banner(settings: &Settings) -> String {
+    let effective = &settings.effective;
+    let profile = settings
+        .profile
+        .map_or_else(|| "none".to_string(), |p| p.to_string());
+    let sources = if effective.sources.is_empty() {
+        "defaults only".to_string()
+    } else {
+        effective.sources.join(", ")
+    };
+    let cache = if settings.redis.is_some() {
+        "redis"
+    } else {
+        "embedded"
+    };
+    let listen = match &settings.server.unix_socket {
+        Some(path) => format!("unix:{path}"),
+        None => settings.server.get_ip(),
+    };
+    let mut banner = format!(
+        "{PKG_NAME}: {PKG_DESCRIPTION}.\nFor more information, see: {PKG_HOMEPAGE}\n\
+         Version: {VERSION} commit: {GIT_COMMIT_HASH}\n\
+         Profile: {profile}\n\
+         Configuration: {sources}\n\
+         Database: {}\n\
+         Cache: {cache}\n\
+         Listening on: {listen}",
+        settings.database.database_type
+    );
+    for o in effective.overrides.iter() {
+        let deprecated = if o.deprecated { " (deprecated)" } else { "" };
+        banner.push_str(&format!(
+            "\nOverride: {} from {}{deprecated}",
+            o.key, o.env_var
+        ));
+    }
+    banner
+}
+
+/// remove the Unix domain socket of a previous run at `path`, which would fail
+/// the bind. Only sockets are removed, so that a misconfigured path doesn't
+/// delete other files
+#[cfg(not(tarpaulin_include))]
+fn remove_stale_socket(path: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
 pub fn get_identity_service(
     settings: &Settings,
-) -> IdentityService<CookieIdentityPolicy> {
+) -> IdentityService<sessions::SessionIdentityPolicy> {
     let cookie_secret = &settings.server.cookie_secret;
-    IdentityService::new(
+    IdentityService::new(sessions::SessionIdentityPolicy::new(
         CookieIdentityPolicy::new(cookie_secret.as_bytes())
             .name("Authorization")
             //TODO change cookie age
             .max_age_secs(216000)
             .domain(&settings.server.domain)
             .secure(false),
-    )
+    ))
 }
 
 #[cfg(test)]
@@ -0,0 +1,216 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Database maintenance after large deletions
+//!
+//! Deleting sitekeys, accounts or campaign analytics leaves dead rows behind.
+//! Deletions are counted and, when some are pending, the scheduler runs
+//! VACUUM ANALYZE (Postgres) or OPTIMIZE TABLE (MariaDB) during the
+//! configured maintenance window.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::errors::*;
+use crate::jobs::DB_MAINTENANCE_JOB;
+use crate::settings::Maintenance;
+use crate::AppData;
+
+/// Number of large deletions since the last maintenance run
+#[derive(Clone, Debug, Default)]
+pub struct PendingMaintenance {
+    deletions: Arc<AtomicUsize>,
+}
+
+impl PendingMaintenance {
+    /// record a large deletion
+    pub fn record_deletion(&self) {
+        self.deletions.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// number of deletions since the last maintenance run
+    pub fn pending(&self) -> usize {
+        self.deletions.load(Ordering::SeqCst)
+    }
+
+    /// reset counter and return the number of deletions that were pending
+    fn take(&self) -> usize {
+        self.deletions.swap(0, Ordering::SeqCst)
+    }
+}
+
+impl Maintenance {
+    /// check if `hour`(UTC) falls within the maintenance window. Windows may
+    /// wrap around midnight; equal bounds mean the window is always open
+    pub fn in_window(&self, hour: u8) -> bool {
+        let (start, end) = (self.window_start, self.window_end);
+        if start == end {
+            true
+        } else if start < end {
+            hour >= start && hour < end
+        } else {
+            hour >= start || hour < end
+        }
+    }
+}
+
+pub struct DbMaintenance {
+    tx: Sender<()>,
+}
+
+impl DbMaintenance {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// run database maintenance if deletions are pending and the maintenance
+    /// window is open
+    pub async fn maintain(data: &AppData) -> ServiceResult<()> {
+        let hour = OffsetDateTime::now_utc().hour();
+        if !data.settings.maintenance.in_window(hour) {
+            return Ok(());
+        }
+
+        let pending = data.maintenance.take();
+        if pending == 0 {
+            return Ok(());
+        }
+
+        log::info!("Running database maintenance after {pending} deletions");
+        if let Err(e) = data.db.run_maintenance().await {
+            // retry in the next run
+            data.maintenance
+                .deletions
+                .fetch_add(pending, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let duration = data.settings.maintenance.interval;
+        data.jobs.register(DB_MAINTENANCE_JOB, duration as u64);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                if exit {
+                    break;
+                }
+                for _ in 0..duration {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::maintain(&data).await;
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Tried to run database maintenance {:?}", err);
+                }
+                data.jobs
+                    .finished(DB_MAINTENANCE_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn maintenance_window_works() {
+        let mut m = Maintenance {
+            interval: 60,
+            window_start: 2,
+            window_end: 5,
+        };
+        assert!(!m.in_window(1));
+        assert!(m.in_window(2));
+        assert!(m.in_window(4));
+        assert!(!m.in_window(5));
+
+        m.window_start = 22;
+        m.window_end = 3;
+        assert!(m.in_window(23));
+        assert!(m.in_window(0));
+        assert!(!m.in_window(3));
+        assert!(!m.in_window(12));
+
+        m.window_end = 22;
+        assert!(m.in_window(12));
+    }
+
+    #[actix_rt::test]
+    async fn db_maintenance_works_pg() {
+        let data = pg::get_data().await;
+        db_maintenance_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn db_maintenance_works_maria() {
+        let data = maria::get_data().await;
+        db_maintenance_works(data).await;
+    }
+
+    async fn db_maintenance_works(data: ArcData) {
+        const NAME: &str = "dbmaintenanceuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "dbmaintenanceuser@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.maintenance.window_start = 0;
+        settings.maintenance.window_end = 0;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = actix_web::web::Data::new(data.clone());
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        crate::api::v1::account::delete::runners::delete_user(NAME, &app_data)
+            .await
+            .unwrap();
+        assert_eq!(data.maintenance.pending(), 1);
+
+        DbMaintenance::maintain(&app_data).await.unwrap();
+        assert_eq!(data.maintenance.pending(), 0);
+    }
+}
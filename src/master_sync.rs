@@ -0,0 +1,114 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Serialization of sitekey registrations with the master
+//!
+//! Sitekeys are registered with the master when their first challenge is
+//! requested and again when their configuration changes. A registration from
+//! stale configuration must not overwrite a refresh, so registrations of the
+//! same sitekey are serialized. Registrations of different sitekeys don't wait
+//! on each other, while operations on all sitekeys at once, like cluster sync,
+//! wait for registrations in progress and hold off new ones.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedMutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// serializes registrations with the master. See [crate::master_sync]
+#[derive(Default)]
+pub struct MasterSync {
+    /// held exclusively by operations on all sitekeys
+    all: RwLock<()>,
+    /// locks of sitekeys that are being registered, by sitekey
+    keys: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// lock of a sitekey, released when dropped
+pub struct KeyGuard<'a> {
+    sync: &'a MasterSync,
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+    _all: RwLockReadGuard<'a, ()>,
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+        // waiters hold clones of the lock, so it's only forgotten once nobody
+        // waits on it
+        let mut keys = self.sync.keys.lock().unwrap();
+        if keys
+            .get(&self.key)
+            .map_or(false, |lock| Arc::strong_count(lock) == 1)
+        {
+            keys.remove(&self.key);
+        }
+    }
+}
+
+impl MasterSync {
+    /// lock sitekey `key`, waiting for registrations of it that are in progress
+    pub async fn key(&self, key: &str) -> KeyGuard<'_> {
+        let all = self.all.read().await;
+        let lock = self
+            .keys
+            .lock()
+            .unwrap()
+            .entry(key.to_owned())
+            .or_default()
+            .clone();
+        KeyGuard {
+            sync: self,
+            key: key.to_owned(),
+            guard: Some(lock.lock_owned().await),
+            _all: all,
+        }
+    }
+
+    /// lock all sitekeys, waiting for registrations that are in progress
+    pub async fn all(&self) -> RwLockWriteGuard<'_, ()> {
+        self.all.write().await
+    }
+
+    /// number of sitekeys that are locked or waited on
+    #[cfg(test)]
+    fn locked(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    const WAIT: Duration = Duration::from_millis(50);
+
+    #[actix_rt::test]
+    async fn master_sync_works() {
+        let sync = MasterSync::default();
+
+        // registrations of the same sitekey are serialized
+        let a = sync.key("a").await;
+        assert!(timeout(WAIT, sync.key("a")).await.is_err());
+        // but those of other sitekeys aren't held up
+        let b = timeout(WAIT, sync.key("b")).await.unwrap();
+        assert_eq!(sync.locked(), 2);
+
+        // operations on all sitekeys wait for registrations in progress
+        assert!(timeout(WAIT, sync.all()).await.is_err());
+        drop(a);
+        drop(b);
+        assert_eq!(sync.locked(), 0);
+        let all = timeout(WAIT, sync.all()).await.unwrap();
+        assert!(timeout(WAIT, sync.key("a")).await.is_err());
+        drop(all);
+        assert!(timeout(WAIT, sync.key("a")).await.is_ok());
+        assert_eq!(sync.locked(), 0);
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Challenges and validation tokens kept in memcached
+//!
+//! With `cache.backend = "memcached"`, [MemcachedCache] keeps challenges and
+//! validation tokens in memcached, so that instances sharing the servers can
+//! verify challenges and validate tokens issued by each other. memcached can't
+//! keep the visitor counts that difficulty is set from, so they are kept by
+//! the embedded master of each instance; a [cluster](crate::cluster) syncs
+//! them. Entries expire with the cooldown of their sitekey, and are removed
+//! once used so that they can't be used twice. The IP address that a
+//! validation token is [bound to](crate::tokens) is kept next to it.
+//!
+//! The memcached client blocks, so the cache runs on a pool of
+//! `cache.memcached_pool` threads.
+use actix::prelude::*;
+use libmcaptcha::cache::messages::{
+    CachePoW, CacheResult, CachedPoWConfig, RetrivePoW, VerifyCaptchaResult,
+};
+use libmcaptcha::cache::Save;
+use libmcaptcha::errors::{CaptchaError, CaptchaResult};
+use memcache::{Client, MemcacheError};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot::channel;
+
+use crate::settings::Settings;
+
+/// prefix of keys that challenges are kept at
+const POW_PREFIX: &str = "mcaptcha_cache:pow:";
+/// prefix of keys that validation tokens are kept at
+const TOKEN_PREFIX: &str = "mcaptcha_cache:token:";
+/// prefix of keys that the IP address hashes of validation tokens are kept at
+const BINDING_PREFIX: &str = "mcaptcha_cache:binding:";
+
+/// bind validation token to the client whose IP address hashes to `ip_hash`
+pub struct BindToken {
+    pub token: String,
+    pub ip_hash: String,
+    pub duration: u64,
+}
+
+impl Message for BindToken {
+    type Result = CaptchaResult<()>;
+}
+
+/// get hash of the IP address that a validation token is bound to
+pub struct GetTokenBinding(pub String);
+
+impl Message for GetTokenBinding {
+    type Result = CaptchaResult<Option<String>>;
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// challenge, as kept in memcached
+struct CachedChallenge {
+    key: String,
+    difficulty_factor: u32,
+    duration: u64,
+}
+
+fn map_err(e: MemcacheError) -> CaptchaError {
+    log::error!("memcached error: {e}");
+    CaptchaError::MailboxError
+}
+
+/// memcached client, shared by the threads of the cache
+pub fn client(s: &Settings) -> Result<Client, MemcacheError> {
+    Client::with_pool_size(s.cache.memcached_urls.clone(), s.cache.memcached_pool)
+}
+
+/// check that all memcached servers answer
+pub fn ping(client: &Client) -> Result<(), MemcacheError> {
+    client.version().map(|_| ())
+}
+
+pub struct MemcachedCache {
+    client: Client,
+}
+
+impl MemcachedCache {
+    /// start the cache on `threads` threads
+    pub fn start(client: Client, threads: usize) -> Addr<Self> {
+        SyncArbiter::start(threads, move || Self {
+            client: client.clone(),
+        })
+    }
+
+    /// get and remove the value at `key`. Only one of concurrent takers gets
+    /// the value
+    fn take(&self, key: &str) -> CaptchaResult<Option<String>> {
+        let val: Option<String> = self.client.get(key).map_err(map_err)?;
+        match val {
+            Some(val) if self.client.delete(key).map_err(map_err)? => Ok(Some(val)),
+            _ => Ok(None),
+        }
+    }
+
+    fn save_pow(&self, msg: CachePoW) -> CaptchaResult<()> {
+        let challenge = CachedChallenge {
+            key: msg.key,
+            difficulty_factor: msg.difficulty_factor,
+            duration: msg.duration,
+        };
+        let val = serde_json::to_string(&challenge).unwrap();
+        self.client
+            .set(
+                &format!("{POW_PREFIX}{}", msg.string),
+                val,
+                msg.duration as u32,
+            )
+            .map_err(map_err)
+    }
+
+    fn retrieve_pow(&self, msg: RetrivePoW) -> CaptchaResult<Option<CachedPoWConfig>> {
+        let val = match self.take(&format!("{POW_PREFIX}{}", msg.0.token))? {
+            Some(val) => val,
+            None => return Ok(None),
+        };
+        let challenge: CachedChallenge = match serde_json::from_str(&val) {
+            Ok(challenge) => challenge,
+            Err(_) => return Ok(None),
+        };
+        if challenge.key != msg.0.key {
+            return Ok(None);
+        }
+        Ok(Some(CachedPoWConfig {
+            key: challenge.key,
+            difficulty_factor: challenge.difficulty_factor,
+            duration: challenge.duration,
+        }))
+    }
+
+    fn save_result(&self, msg: CacheResult) -> CaptchaResult<()> {
+        self.client
+            .set(
+                &format!("{TOKEN_PREFIX}{}", msg.token),
+                msg.key,
+                msg.duration as u32,
+            )
+            .map_err(map_err)
+    }
+
+    fn verify_result(&self, msg: VerifyCaptchaResult) -> CaptchaResult<bool> {
+        let key = self.take(&format!("{TOKEN_PREFIX}{}", msg.token))?;
+        Ok(key.as_deref() == Some(msg.key.as_str()))
+    }
+
+    fn bind_token(&self, msg: BindToken) -> CaptchaResult<()> {
+        self.client
+            .set(
+                &format!("{BINDING_PREFIX}{}", msg.token),
+                msg.ip_hash,
+                msg.duration as u32,
+            )
+            .map_err(map_err)
+    }
+
+    fn token_binding(&self, msg: GetTokenBinding) -> CaptchaResult<Option<String>> {
+        self.client
+            .get(&format!("{BINDING_PREFIX}{}", msg.0))
+            .map_err(map_err)
+    }
+}
+
+impl Actor for MemcachedCache {
+    type Context = SyncContext<Self>;
+}
+
+impl Save for MemcachedCache {}
+
+/// cache challenges
+impl Handler<CachePoW> for MemcachedCache {
+    type Result = MessageResult<CachePoW>;
+    fn handle(&mut self, msg: CachePoW, _ctx: &mut Self::Context) -> Self::Result {
+        let (tx, rx) = channel();
+        let _ = tx.send(self.save_pow(msg));
+        MessageResult(rx)
+    }
+}
+
+/// retrieve and remove challenges
+impl Handler<RetrivePoW> for MemcachedCache {
+    type Result = MessageResult<RetrivePoW>;
+    fn handle(&mut self, msg: RetrivePoW, _ctx: &mut Self::Context) -> Self::Result {
+        let (tx, rx) = channel();
+        let _ = tx.send(self.retrieve_pow(msg));
+        MessageResult(rx)
+    }
+}
+
+/// cache validation tokens
+impl Handler<CacheResult> for MemcachedCache {
+    type Result = MessageResult<CacheResult>;
+    fn handle(&mut self, msg: CacheResult, _ctx: &mut Self::Context) -> Self::Result {
+        let (tx, rx) = channel();
+        let _ = tx.send(self.save_result(msg));
+        MessageResult(rx)
+    }
+}
+
+/// validate and remove validation tokens
+impl Handler<VerifyCaptchaResult> for MemcachedCache {
+    type Result = MessageResult<VerifyCaptchaResult>;
+    fn handle(
+        &mut self,
+        msg: VerifyCaptchaResult,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let (tx, rx) = channel();
+        let _ = tx.send(self.verify_result(msg));
+        MessageResult(rx)
+    }
+}
+
+/// bind validation tokens to IP addresses
+impl Handler<BindToken> for MemcachedCache {
+    type Result = CaptchaResult<()>;
+    fn handle(&mut self, msg: BindToken, _ctx: &mut Self::Context) -> Self::Result {
+        self.bind_token(msg)
+    }
+}
+
+/// get IP addresses that validation tokens are bound to
+impl Handler<GetTokenBinding> for MemcachedCache {
+    type Result = CaptchaResult<Option<String>>;
+    fn handle(
+        &mut self,
+        msg: GetTokenBinding,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.token_binding(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::v1::pow::get_config::get_config_runner;
+    use crate::api::v1::pow::verify_pow::{verify_pow_runner, ApiWork};
+    use crate::api::v1::pow::verify_token::{runners, VerifyCaptchaResultPayload};
+    use crate::data::SystemGroup;
+    use crate::settings::CacheBackend;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn memcached_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        memcached_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn memcached_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        memcached_works(data).await;
+    }
+
+    async fn instance(data: &ArcData) -> AppData {
+        let mut settings = data.settings.clone();
+        settings.redis = None;
+        settings.cache.backend = CacheBackend::Memcached;
+        settings.cache.memcached_urls = vec!["memcache://127.0.0.1:11211".into()];
+        let data = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        AppData::new(data)
+    }
+
+    async fn memcached_works(data: ArcData) {
+        const NAME: &str = "memcachedusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "memcachedusr@a.com";
+
+        delete_user(&data, NAME).await;
+        register_and_signin(&data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(&data, NAME, PASSWORD).await;
+        let key = token_key.key.clone();
+        let a = instance(&data).await;
+        let b = instance(&data).await;
+        assert!(matches!(a.captcha, SystemGroup::Memcached(_)));
+
+        // challenges issued by an instance are verified by the other, once
+        let config = get_config_runner(&a, &key).await.unwrap();
+        // sitekeys are registered with the master of each instance
+        get_config_runner(&b, &key).await.unwrap();
+        let (nonce, result) = solve(&config);
+        let work = ApiWork {
+            string: config.string.clone(),
+            result,
+            nonce,
+            key: key.clone(),
+            time: None,
+            worker_type: None,
+            correlation_id: None,
+        };
+        let token = verify_pow_runner(&b, work.clone(), "127.0.1.1".into())
+            .await
+            .unwrap();
+        assert!(verify_pow_runner(&a, work, "127.0.1.1".into())
+            .await
+            .is_err());
+
+        // and so are validation tokens, along with the IP address they are
+        // bound to
+        let mut payload = VerifyCaptchaResultPayload {
+            secret: data.db.get_secret(NAME).await.unwrap().secret,
+            key: key.clone(),
+            token: token.token,
+            ip: Some("192.0.2.1".into()),
+        };
+        assert!(!runners::validate(&a, payload.clone()).await.unwrap());
+        payload.ip = Some("127.0.1.1".into());
+        assert!(runners::validate(&a, payload.clone()).await.unwrap());
+        assert!(!runners::validate(&b, payload).await.unwrap());
+    }
+}
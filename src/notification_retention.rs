@@ -0,0 +1,180 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Retention of read notifications
+//!
+//! When `notifications.retention` is set, [NotificationRetention] deletes read
+//! notifications once they are that many seconds old, so that
+//! `mcaptcha_notifications` doesn't grow forever. Unread notifications are
+//! kept. Notifications are deleted in batches, so that a large backlog doesn't
+//! lock the table for long.
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::errors::*;
+use crate::jobs::NOTIFICATION_RETENTION_JOB;
+use crate::AppData;
+
+/// interval, in seconds, at which old notifications are deleted
+const CLEANUP_INTERVAL: u32 = 60 * 60;
+/// maximum number of notifications deleted by a single query
+const BATCH_SIZE: u32 = 1000;
+
+pub struct NotificationRetention {
+    tx: Sender<()>,
+}
+
+impl NotificationRetention {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// delete read notifications that were received before `before` and
+    /// return their number
+    pub async fn cleanup(data: &AppData, before: i64) -> ServiceResult<u64> {
+        let mut deleted = 0;
+        loop {
+            let batch = data
+                .db
+                .delete_read_notifications(before, BATCH_SIZE)
+                .await?;
+            deleted += batch;
+            if batch < BATCH_SIZE as u64 {
+                break;
+            }
+        }
+        if deleted > 0 {
+            log::info!("Deleted {deleted} read notifications");
+            data.maintenance.record_deletion();
+        }
+        Ok(deleted)
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs
+            .register(NOTIFICATION_RETENTION_JOB, CLEANUP_INTERVAL as u64);
+        let retention = data.settings.notifications.retention as i64;
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let before = started.unix_timestamp() - retention;
+                let res = Self::cleanup(&data, before).await.map(|_| ());
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while deleting read notifications: {:?}", err);
+                }
+                data.jobs.finished(
+                    NOTIFICATION_RETENTION_JOB,
+                    started,
+                    timer.elapsed(),
+                    &res,
+                );
+
+                for _ in 0..CLEANUP_INTERVAL {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn notification_retention_works_pg() {
+        let data = pg::get_data().await;
+        notification_retention_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn notification_retention_works_maria() {
+        let data = maria::get_data().await;
+        notification_retention_works(data).await;
+    }
+
+    async fn notification_retention_works(data: ArcData) {
+        const NAME: &str = "notifretentionuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "notifretentionuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let n = db_core::AddNotification {
+            to: NAME,
+            from: NAME,
+            heading: "retention",
+            message: "retention",
+        };
+        data.db.create_notification(&n).await.unwrap();
+        data.db.create_notification(&n).await.unwrap();
+        let notifications = data.db.get_all_unread_notifications(NAME).await.unwrap();
+        data.db
+            .mark_notification_read(NAME, notifications[0].id.unwrap())
+            .await
+            .unwrap();
+
+        // notifications of other tests are read concurrently, so only
+        // notifications that are older than any test are deleted
+        let app_data = AppData::new(data.clone());
+        let before = OffsetDateTime::now_utc().unix_timestamp() - 60 * 60 * 24;
+        NotificationRetention::cleanup(&app_data, before)
+            .await
+            .unwrap();
+        let filter = db_core::NotificationFilter {
+            status: db_core::NotificationStatus::All,
+            ..Default::default()
+        };
+        assert_eq!(
+            data.db
+                .get_notifications(NAME, &filter, 0, 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Real-time notification streams
+//!
+//! Created notifications are broadcast to the notification streams that are
+//! open on this instance. When Redis is configured, they are also published
+//! on [CHANNEL], and [NotificationRelay] rebroadcasts notifications that
+//! other instances publish, so that streams receive notifications regardless
+//! of the instance that they are connected to.
+use std::time::Duration;
+
+use actix::clock::sleep;
+use actix::spawn;
+use futures::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::webhooks::NotificationPayload;
+use crate::{AppData, Data};
+
+/// Redis channel on which instances publish notifications
+pub const CHANNEL: &str = "mcaptcha:notifications";
+/// number of notifications that a slow stream can fall behind by before it
+/// misses some
+const CAPACITY: usize = 256;
+/// interval, in seconds, at which the relay reconnects to Redis after an error
+const RECONNECT_INTERVAL: u64 = 5;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// notification, as published on [CHANNEL]
+struct Published {
+    /// instance that published the notification
+    instance: String,
+    notification: NotificationPayload,
+}
+
+/// Broadcasts notifications to the open notification streams of this instance
+pub struct NotificationStream {
+    tx: broadcast::Sender<NotificationPayload>,
+    /// identifies this instance on [CHANNEL]
+    instance: String,
+}
+
+impl Default for NotificationStream {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        Self {
+            tx,
+            instance: get_random(16),
+        }
+    }
+}
+
+impl NotificationStream {
+    /// receive notifications created from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<NotificationPayload> {
+        self.tx.subscribe()
+    }
+
+    /// broadcast notification to the streams of this instance
+    pub fn send(&self, n: NotificationPayload) {
+        // there are no receivers when no stream is open
+        let _ = self.tx.send(n);
+    }
+}
+
+/// broadcast notification to the streams of all instances
+pub async fn publish(data: &Data, n: NotificationPayload) {
+    if let Some(redis) = &data.settings.redis {
+        let published = Published {
+            instance: data.notification_stream.instance.clone(),
+            notification: n.clone(),
+        };
+        if let Err(e) = publish_redis(&redis.connection_url(), &published).await {
+            log::error!("Unable to publish notification to Redis: {e}");
+        }
+    }
+    data.notification_stream.send(n);
+}
+
+async fn publish_redis(url: &str, published: &Published) -> redis::RedisResult<()> {
+    let client = redis::Client::open(url)?;
+    let mut con = client.get_async_connection().await?;
+    let _: () = con
+        .publish(CHANNEL, serde_json::to_string(published).unwrap())
+        .await?;
+    Ok(())
+}
+
+/// Rebroadcasts notifications that other instances publish on [CHANNEL] to the
+/// streams of this instance
+pub struct NotificationRelay {
+    tx: Sender<()>,
+}
+
+impl NotificationRelay {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    /// relay notifications until the connection fails or the relay is aborted;
+    /// returns false when aborted
+    async fn relay(
+        data: &AppData,
+        url: &str,
+        rx: &mut Receiver<()>,
+    ) -> redis::RedisResult<bool> {
+        let client = redis::Client::open(url)?;
+        let mut pubsub = client.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(CHANNEL).await?;
+        let mut messages = pubsub.on_message();
+        while Self::can_run(rx) {
+            let msg = match timeout(Duration::new(1, 0), messages.next()).await {
+                Ok(Some(msg)) => msg,
+                Ok(None) => return Ok(true),
+                Err(_) => continue,
+            };
+            let payload: String = msg.get_payload()?;
+            match serde_json::from_str::<Published>(&payload) {
+                Ok(p) if p.instance != data.notification_stream.instance => {
+                    data.notification_stream.send(p.notification)
+                }
+                Ok(_) => (),
+                Err(e) => log::error!("Received malformed notification from Redis: {e}"),
+            }
+        }
+        Ok(false)
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let url = match &data.settings.redis {
+            Some(redis) => redis.connection_url(),
+            None => return Ok(spawn(async {})),
+        };
+        let fut = async move {
+            loop {
+                match Self::relay(&data, &url, &mut rx).await {
+                    Ok(false) => break,
+                    Ok(true) => log::warn!("Redis closed the notification channel"),
+                    Err(e) => {
+                        log::error!("Unable to relay notifications from Redis: {e}")
+                    }
+                }
+                for _ in 0..RECONNECT_INTERVAL {
+                    if !Self::can_run(&mut rx) {
+                        return;
+                    }
+                    sleep(Duration::new(1, 0)).await;
+                }
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn notification_stream_works() {
+        let stream = NotificationStream::default();
+        // nobody is listening
+        let n = db_core::AddNotification {
+            to: "receiver",
+            from: "sender",
+            heading: "heading",
+            message: "message",
+        };
+        stream.send(NotificationPayload::new(&n));
+
+        let mut rx = stream.subscribe();
+        stream.send(NotificationPayload::new(&n));
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.to, n.to);
+        assert_eq!(received.heading, n.heading);
+        assert!(rx.try_recv().is_err());
+    }
+}
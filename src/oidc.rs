@@ -0,0 +1,427 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! OpenID Connect single sign-on
+//!
+//! mCaptcha is a relying party that uses the authorization code flow with
+//! PKCE. Provider metadata is discovered from the configured issuer and
+//! cached. The ID token is received directly from the provider's token
+//! endpoint, so its signature isn't verified(OpenID Connect Core 1.0, section
+//! 3.1.3.7), but issuer, audience and nonce are checked.
+//!
+//! Login attempts are tracked in memory, so the callback has to reach the
+//! instance that started the login. Each attempt is bound to the browser that
+//! started it by a random value, which the browser holds in a cookie and has
+//! to present at the callback. Otherwise, anyone could sign victims into their
+//! own account by getting them to open a callback URL.
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::types::time::OffsetDateTime;
+use url::Url;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::settings::Settings;
+
+/// seconds within which a login attempt has to be completed
+pub const LOGIN_TTL: i64 = 600;
+/// login attempts that can be pending at once. Login can be started without
+/// signing in, so attempts are capped to bound memory use; beyond the cap, the
+/// oldest attempt is dropped
+pub const MAX_PENDING_LOGINS: usize = 10_000;
+/// login attempts that a single client IP can have pending at once, so that
+/// one client can't crowd out the attempts of others
+pub const MAX_PENDING_LOGINS_PER_IP: usize = 10;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// provider metadata, as published at `/.well-known/openid-configuration`
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: Url,
+    pub token_endpoint: Url,
+    pub userinfo_endpoint: Option<Url>,
+}
+
+#[derive(Clone, Debug)]
+/// login attempt awaiting callback from the provider
+pub struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    /// binds the attempt to the browser that started it
+    browser: String,
+    /// IP address of the client that started the attempt
+    ip: String,
+    created: i64,
+    /// signed-in user, to whom the provider's account will be linked
+    pub link_to: Option<String>,
+    /// page to redirect to after sign-in
+    pub redirect_to: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+/// user, as identified by the provider
+pub struct Claims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(default)]
+    pub aud: serde_json::Value,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+}
+
+impl Claims {
+    /// email address, if the provider has verified it
+    pub fn verified_email(&self) -> Option<&str> {
+        match self.email_verified {
+            Some(true) => self.email.as_deref(),
+            _ => None,
+        }
+    }
+
+    fn has_audience(&self, client_id: &str) -> bool {
+        match &self.aud {
+            serde_json::Value::String(aud) => aud == client_id,
+            serde_json::Value::Array(aud) => aud.iter().any(|a| a == client_id),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+struct UserInfo {
+    sub: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    preferred_username: Option<String>,
+}
+
+/// decode claims of an ID token
+pub fn decode_id_token(id_token: &str) -> ServiceResult<Claims> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .ok_or(ServiceError::OidcProviderError)?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|_| ServiceError::OidcProviderError)?;
+    serde_json::from_slice(&payload).map_err(|_| ServiceError::OidcProviderError)
+}
+
+/// PKCE code challenge of `verifier`
+pub fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn provider_error(e: reqwest::Error) -> ServiceError {
+    log::error!("OpenID Connect provider request failed: {e}");
+    ServiceError::OidcProviderError
+}
+
+pub struct OidcClient {
+    settings: crate::settings::Oidc,
+    redirect_uri: String,
+    client: Client,
+    discovery: RwLock<Option<Discovery>>,
+    pending: RwLock<HashMap<String, PendingLogin>>,
+}
+
+impl OidcClient {
+    /// OpenID Connect client, if it is configured
+    pub fn new(s: &Settings) -> Option<Self> {
+        let settings = s.oidc.clone()?;
+        let callback = crate::V1_API_ROUTES.oidc.callback;
+        Some(Self {
+            settings,
+            redirect_uri: format!("{}{callback}", s.server.url()),
+            client: Client::new(),
+            discovery: RwLock::new(None),
+            pending: RwLock::new(HashMap::default()),
+        })
+    }
+
+    /// create accounts for users who sign in for the first time
+    pub fn auto_provision(&self) -> bool {
+        self.settings.auto_provision
+    }
+
+    /// fetch provider metadata; cached after the first successful fetch
+    pub async fn discover(&self) -> ServiceResult<Discovery> {
+        if let Some(discovery) = self.discovery.read().unwrap().as_ref() {
+            return Ok(discovery.clone());
+        }
+
+        let issuer = self.settings.issuer.as_str().trim_end_matches('/');
+        let url = format!("{issuer}/.well-known/openid-configuration");
+        let discovery: Discovery = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        if discovery.issuer.trim_end_matches('/') != issuer {
+            log::error!(
+                "OpenID Connect provider identifies as {}, expected {issuer}",
+                discovery.issuer
+            );
+            return Err(ServiceError::OidcProviderError);
+        }
+
+        *self.discovery.write().unwrap() = Some(discovery.clone());
+        Ok(discovery)
+    }
+
+    /// start login and get the provider's authorization URL to which the user
+    /// should be redirected, and the value that binds the login to the user's
+    /// browser
+    pub async fn begin(
+        &self,
+        ip: &str,
+        link_to: Option<String>,
+        redirect_to: Option<String>,
+    ) -> ServiceResult<(Url, String)> {
+        let discovery = self.discover().await?;
+        let state = get_random(32);
+        let pending = PendingLogin {
+            nonce: get_random(32),
+            verifier: get_random(64),
+            browser: get_random(32),
+            ip: ip.to_string(),
+            created: OffsetDateTime::now_utc().unix_timestamp(),
+            link_to,
+            redirect_to,
+        };
+
+        let mut scopes = self.settings.scopes.clone();
+        if !scopes.split_whitespace().any(|s| s == "openid") {
+            scopes = format!("openid {scopes}");
+        }
+
+        let mut url = discovery.authorization_endpoint;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.settings.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", scopes.trim())
+            .append_pair("state", &state)
+            .append_pair("nonce", &pending.nonce)
+            .append_pair("code_challenge", &code_challenge(&pending.verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        let browser = pending.browser.clone();
+        self.track(state, pending)?;
+        Ok((url, browser))
+    }
+
+    /// track a pending login, unless its client has too many pending. The
+    /// oldest attempt is dropped when too many are pending in total
+    fn track(&self, state: String, pending: PendingLogin) -> ServiceResult<()> {
+        let mut w = self.pending.write().unwrap();
+        w.retain(|_, p| pending.created - p.created < LOGIN_TTL);
+        let from_ip = w.values().filter(|p| p.ip == pending.ip);
+        if from_ip.clone().count() >= MAX_PENDING_LOGINS_PER_IP {
+            let oldest = from_ip.map(|p| p.created).min().unwrap_or_default();
+            let reset = LOGIN_TTL - (pending.created - oldest);
+            return Err(ServiceError::RateLimited(reset.max(1) as u32));
+        }
+        if w.len() >= MAX_PENDING_LOGINS {
+            let oldest = w
+                .iter()
+                .min_by_key(|(_, p)| p.created)
+                .map(|(state, _)| state.clone());
+            if let Some(oldest) = oldest {
+                w.remove(&oldest);
+            }
+        }
+        w.insert(state, pending);
+        Ok(())
+    }
+
+    /// complete login: exchange authorization code and get the user's claims.
+    /// `browser` is the value returned by [Self::begin], as presented by the
+    /// browser
+    pub async fn finish(
+        &self,
+        state: &str,
+        browser: Option<&str>,
+        code: Option<&str>,
+    ) -> ServiceResult<(PendingLogin, Claims)> {
+        // attempts are removed on the first callback, so the binding can't be
+        // guessed over several requests
+        let pending = self
+            .pending
+            .write()
+            .unwrap()
+            .remove(state)
+            .ok_or(ServiceError::OidcStateInvalid)?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if now - pending.created >= LOGIN_TTL
+            || browser != Some(pending.browser.as_str())
+        {
+            return Err(ServiceError::OidcStateInvalid);
+        }
+        // provider redirects without a code when the user denies access
+        let code = code.ok_or(ServiceError::OidcProviderError)?;
+
+        let discovery = self.discover().await?;
+        let params = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("client_id", self.settings.client_id.as_str()),
+            ("code_verifier", pending.verifier.as_str()),
+        ];
+        let tokens: TokenResponse = self
+            .client
+            .post(discovery.token_endpoint.clone())
+            .basic_auth(&self.settings.client_id, Some(&self.settings.client_secret))
+            .form(&params)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+
+        let mut claims = decode_id_token(&tokens.id_token)?;
+        if claims.iss != discovery.issuer
+            || !claims.has_audience(&self.settings.client_id)
+            || claims.nonce.as_deref() != Some(pending.nonce.as_str())
+        {
+            log::error!(
+                "OpenID Connect provider returned an ID token meant for someone else"
+            );
+            return Err(ServiceError::OidcProviderError);
+        }
+
+        // ID tokens may leave out profile claims that are available at the
+        // userinfo endpoint
+        if let Some(userinfo_endpoint) = discovery.userinfo_endpoint {
+            let info: UserInfo = self
+                .client
+                .get(userinfo_endpoint)
+                .bearer_auth(&tokens.access_token)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(provider_error)?
+                .json()
+                .await
+                .map_err(provider_error)?;
+            if info.sub == claims.sub {
+                claims.email = info.email.or(claims.email);
+                claims.email_verified = info.email_verified.or(claims.email_verified);
+                claims.preferred_username =
+                    info.preferred_username.or(claims.preferred_username);
+            }
+        }
+
+        Ok((pending, claims))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_token_claims_work() {
+        let claims = r#"{"iss":"https://idp.example.org","sub":"1","aud":["a","mcaptcha"],"email":"a@example.org","email_verified":true}"#;
+        let id_token = format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims));
+        let claims = decode_id_token(&id_token).unwrap();
+        assert_eq!(claims.sub, "1");
+        assert!(claims.has_audience("mcaptcha"));
+        assert!(!claims.has_audience("b"));
+        assert_eq!(claims.verified_email(), Some("a@example.org"));
+        assert!(decode_id_token("notatoken").is_err());
+
+        // RFC 7636, appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCp-u17q5qi-DqR-Ea6qBTDM"
+        );
+    }
+
+    #[test]
+    fn pending_logins_are_capped() {
+        let mut settings = crate::tests::get_settings();
+        settings.oidc = Some(crate::settings::Oidc {
+            issuer: Url::parse("https://idp.example.org").unwrap(),
+            client_id: "mcaptcha".into(),
+            client_secret: "oidcsecret".into(),
+            scopes: "openid".into(),
+            auto_provision: false,
+        });
+        let oidc = OidcClient::new(&settings).unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let pending = |ip: &str, created| PendingLogin {
+            nonce: get_random(32),
+            verifier: get_random(64),
+            browser: get_random(32),
+            ip: ip.into(),
+            created,
+            link_to: None,
+            redirect_to: None,
+        };
+
+        // clients can only have a few attempts pending
+        for i in 0..MAX_PENDING_LOGINS_PER_IP {
+            oidc.track(format!("ip{i}"), pending("10.0.0.1", now))
+                .unwrap();
+        }
+        assert_eq!(
+            oidc.track("ipfull".into(), pending("10.0.0.1", now)),
+            Err(ServiceError::RateLimited(LOGIN_TTL as u32))
+        );
+        oidc.track("otherip".into(), pending("10.0.0.2", now))
+            .unwrap();
+
+        // expired attempts make room
+        oidc.track("expired".into(), pending("10.0.0.3", now - LOGIN_TTL))
+            .unwrap();
+        oidc.track("fresh".into(), pending("10.0.0.3", now))
+            .unwrap();
+        assert!(!oidc.pending.read().unwrap().contains_key("expired"));
+
+        // and the oldest attempt is dropped beyond the cap
+        oidc.track("oldest".into(), pending("10.0.0.3", now - 2))
+            .unwrap();
+        let tracked = oidc.pending.read().unwrap().len();
+        for i in tracked..MAX_PENDING_LOGINS {
+            oidc.track(i.to_string(), pending(&i.to_string(), now - 1))
+                .unwrap();
+        }
+        oidc.track("full".into(), pending("10.0.0.4", now)).unwrap();
+        let w = oidc.pending.read().unwrap();
+        assert_eq!(w.len(), MAX_PENDING_LOGINS);
+        assert!(w.contains_key("full"));
+        assert!(!w.contains_key("oldest"));
+    }
+}
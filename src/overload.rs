@@ -0,0 +1,267 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Load shedding during attack peaks
+//!
+//! [LoadShedder] watches the number of in-flight PoW verifications and the
+//! latency of database queries on the CAPTCHA hot path. When either crosses
+//! its threshold, the instance is considered overloaded: stats and analytics
+//! writes are skipped and served difficulty factors are raised, so that
+//! incoming solves slow down instead of piling up into timeouts. Normal
+//! operation resumes once both drop below half of their thresholds.
+//!
+//! Past the capacity of PoW verification, its runners and their queue,
+//! verifications and challenge fetches are turned away with
+//! [ServiceError::PoWSaturated] while it lasts, which asks clients to retry after
+//! [RETRY_AFTER] seconds, instead of piling up until they time out.
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+use crate::settings::LoadShedding;
+
+/// seconds that clients are asked to wait for when PoW verification is
+/// saturated
+pub const RETRY_AFTER: u32 = 2;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// state of the load shedder
+pub struct LoadMetrics {
+    /// is the instance currently shedding load
+    pub overloaded: bool,
+    /// number of PoW verifications in progress
+    pub inflight: usize,
+    /// moving average of database latency, in milliseconds
+    pub db_latency: u64,
+    /// number of stats and analytics writes skipped
+    pub shed: usize,
+    /// number of times the instance entered overload
+    pub overloads: usize,
+    /// number of requests turned away because PoW verification was saturated
+    #[serde(default)]
+    pub saturated: usize,
+}
+
+pub struct LoadShedder {
+    settings: LoadShedding,
+    inflight: AtomicUsize,
+    /// moving average of database latency, in microseconds
+    db_latency: AtomicU64,
+    overloaded: AtomicBool,
+    shed: AtomicUsize,
+    overloads: AtomicUsize,
+    saturated: AtomicUsize,
+}
+
+/// Marks a PoW verification as in-flight until dropped
+pub struct Inflight<'a>(&'a LoadShedder);
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        self.0.inflight.fetch_sub(1, Ordering::SeqCst);
+        self.0.update();
+    }
+}
+
+impl LoadShedder {
+    pub fn new(settings: &LoadShedding) -> Self {
+        Self {
+            settings: settings.clone(),
+            inflight: AtomicUsize::new(0),
+            db_latency: AtomicU64::new(0),
+            overloaded: AtomicBool::new(false),
+            shed: AtomicUsize::new(0),
+            overloads: AtomicUsize::new(0),
+            saturated: AtomicUsize::new(0),
+        }
+    }
+
+    /// track a PoW verification for as long as the returned guard lives
+    pub fn verification(&self) -> Inflight<'_> {
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        self.update();
+        Inflight(self)
+    }
+
+    /// track a PoW verification like [LoadShedder::verification], unless
+    /// `capacity` verifications are in-flight already
+    pub fn try_verification(&self, capacity: usize) -> ServiceResult<Inflight<'_>> {
+        let inflight = self.verification();
+        if self.inflight.load(Ordering::SeqCst) > capacity {
+            self.saturated.fetch_add(1, Ordering::SeqCst);
+            return Err(ServiceError::PoWSaturated);
+        }
+        Ok(inflight)
+    }
+
+    /// fail when `capacity` PoW verifications are in-flight, so that
+    /// challenges aren't issued that can't be verified
+    pub fn check_capacity(&self, capacity: usize) -> ServiceResult<()> {
+        if self.inflight.load(Ordering::SeqCst) >= capacity {
+            self.saturated.fetch_add(1, Ordering::SeqCst);
+            return Err(ServiceError::PoWSaturated);
+        }
+        Ok(())
+    }
+
+    /// record latency of a database query on the hot path
+    pub fn record_db_latency(&self, elapsed: Duration) {
+        let sample = elapsed.as_micros() as u64;
+        // exponentially weighted, so that a single slow query doesn't trip it
+        let average = |avg: u64| Some((avg * 7 + sample) / 8);
+        let _ =
+            self.db_latency
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, average);
+        self.update();
+    }
+
+    fn update(&self) {
+        if !self.settings.enabled {
+            return;
+        }
+        let inflight = self.inflight.load(Ordering::SeqCst);
+        let latency = self.db_latency.load(Ordering::SeqCst) / 1000;
+        let max_inflight = self.settings.max_inflight;
+        let max_latency = self.settings.max_db_latency;
+
+        if self.overloaded.load(Ordering::SeqCst) {
+            if inflight <= max_inflight / 2
+                && latency <= max_latency / 2
+                && self.overloaded.swap(false, Ordering::SeqCst)
+            {
+                log::info!("Load back to normal, resuming stats and analytics writes");
+            }
+        } else if (inflight > max_inflight || latency > max_latency)
+            && !self.overloaded.swap(true, Ordering::SeqCst)
+        {
+            self.overloads.fetch_add(1, Ordering::SeqCst);
+            log::warn!(
+                "Overloaded ({inflight} verifications in-flight, {latency}ms database latency), shedding load"
+            );
+        }
+    }
+
+    /// is the instance currently shedding load
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded.load(Ordering::SeqCst)
+    }
+
+    /// should low-priority work(stats, analytics) be skipped. Skipped work is
+    /// counted
+    pub fn shed(&self) -> bool {
+        let overloaded = self.is_overloaded();
+        if overloaded {
+            self.shed.fetch_add(1, Ordering::SeqCst);
+        }
+        overloaded
+    }
+
+    /// difficulty factor to serve: raised by the configured percentage when
+    /// overloaded
+    pub fn difficulty(&self, difficulty_factor: u32) -> u32 {
+        if !self.is_overloaded() {
+            return difficulty_factor;
+        }
+        let increase =
+            difficulty_factor as u64 * self.settings.difficulty_increase as u64 / 100;
+        difficulty_factor.saturating_add(increase.max(1) as u32)
+    }
+
+    /// get state of the load shedder
+    pub fn metrics(&self) -> LoadMetrics {
+        LoadMetrics {
+            overloaded: self.is_overloaded(),
+            inflight: self.inflight.load(Ordering::SeqCst),
+            db_latency: self.db_latency.load(Ordering::SeqCst) / 1000,
+            shed: self.shed.load(Ordering::SeqCst),
+            overloads: self.overloads.load(Ordering::SeqCst),
+            saturated: self.saturated.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> LoadShedding {
+        LoadShedding {
+            enabled: true,
+            max_inflight: 2,
+            max_db_latency: 100,
+            difficulty_increase: 20,
+        }
+    }
+
+    #[test]
+    fn inflight_overload_works() {
+        let load = LoadShedder::new(&settings());
+        assert_eq!(load.difficulty(500), 500);
+
+        let a = load.verification();
+        let b = load.verification();
+        assert!(!load.shed());
+        let c = load.verification();
+        assert!(load.is_overloaded());
+        assert!(load.shed());
+        assert_eq!(load.difficulty(500), 600);
+        assert_eq!(load.difficulty(1), 2);
+
+        // recovers only after falling below half of the threshold
+        drop(c);
+        assert!(load.is_overloaded());
+        drop(b);
+        assert!(!load.is_overloaded());
+        drop(a);
+
+        let metrics = load.metrics();
+        assert_eq!(metrics.inflight, 0);
+        assert_eq!(metrics.shed, 1);
+        assert_eq!(metrics.overloads, 1);
+    }
+
+    #[test]
+    fn saturation_works() {
+        let load = LoadShedder::new(&settings());
+        let a = load.try_verification(2).unwrap();
+        assert!(load.check_capacity(2).is_ok());
+        let _b = load.try_verification(2).unwrap();
+        assert_eq!(load.check_capacity(2), Err(ServiceError::PoWSaturated));
+        assert_eq!(
+            load.try_verification(2).err(),
+            Some(ServiceError::PoWSaturated)
+        );
+        assert_eq!(load.metrics().inflight, 2);
+        drop(a);
+        assert!(load.try_verification(2).is_ok());
+        assert_eq!(load.metrics().saturated, 2);
+    }
+
+    #[test]
+    fn db_latency_overload_works() {
+        let load = LoadShedder::new(&settings());
+        load.record_db_latency(Duration::from_millis(2000));
+        assert!(load.is_overloaded());
+        for _ in 0..50 {
+            load.record_db_latency(Duration::from_millis(1));
+        }
+        assert!(!load.is_overloaded());
+        assert_eq!(load.metrics().overloads, 1);
+    }
+
+    #[test]
+    fn disabled_load_shedder_never_sheds() {
+        let mut settings = settings();
+        settings.enabled = false;
+        let load = LoadShedder::new(&settings);
+        let _guards: Vec<Inflight> = (0..10).map(|_| load.verification()).collect();
+        load.record_db_latency(Duration::from_millis(2000));
+        assert!(!load.shed());
+        assert_eq!(load.difficulty(500), 500);
+        assert_eq!(load.metrics().inflight, 10);
+    }
+}
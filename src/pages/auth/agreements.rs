@@ -0,0 +1,139 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_identity::Identity;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use sailfish::TemplateOnce;
+
+use crate::agreements::{self, Document};
+use crate::errors::PageResult;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+use crate::PAGES;
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "auth/agreements/index.html")]
+struct IndexPage {
+    pending: Vec<Document>,
+    csp_nonce: String,
+}
+
+const PAGE: &str = "Review Agreements";
+
+fn redirect_home() -> HttpResponse {
+    HttpResponse::Found()
+        .append_header((header::LOCATION, PAGES.panel.home))
+        .finish()
+}
+
+#[my_codegen::get(
+    path = "PAGES.auth.agreements",
+    wrap = "crate::pages::get_middleware()"
+)]
+pub async fn agreements(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let pending = agreements::pending(&data, &username).await?;
+    if pending.is_empty() {
+        return Ok(redirect_home());
+    }
+    let body = IndexPage {
+        pending,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[my_codegen::post(
+    path = "PAGES.auth.agreements",
+    wrap = "crate::pages::get_middleware()"
+)]
+pub async fn accept_agreements(
+    req: HttpRequest,
+    data: AppData,
+    id: Identity,
+) -> PageResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let ip = data.trusted_proxies.client_ip(&req);
+    agreements::accept(&data, &username, &ip).await?;
+    Ok(redirect_home())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn agreements_page_works_pg() {
+        let data = pg::get_data().await;
+        agreements_page_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn agreements_page_works_maria() {
+        let data = maria::get_data().await;
+        agreements_page_works(data).await;
+    }
+
+    async fn agreements_page_works(data: ArcData) {
+        const NAME: &str = "agreementspageuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "agreementspageuser@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.commercial = true;
+        settings.agreements.tos_version = Some("1".into());
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.auth.agreements)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(PAGES.auth.agreements)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(data.db.fetch_agreements(NAME).await.unwrap().len(), 1);
+
+        // nothing left to accept
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.auth.agreements)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{web, HttpResponseBuilder, Responder, ResponseError};
+use sailfish::TemplateOnce;
+
+use crate::email::change;
+use crate::errors::*;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+use crate::PAGES;
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "auth/email-change/index.html")]
+struct IndexPage {
+    error: Option<String>,
+    cancelled: bool,
+    csp_nonce: String,
+}
+
+const PAGE: &str = "Email Change";
+
+fn render(res: ServiceResult<()>, cancelled: bool, nonce: CspNonce) -> impl Responder {
+    let (status, error) = match res {
+        Ok(_) => (actix_web::http::StatusCode::OK, None),
+        Err(e) => (e.status_code(), Some(e.to_string())),
+    };
+    let body = IndexPage {
+        error,
+        cancelled,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    HttpResponseBuilder::new(status)
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+/// change email address from the link sent to the new address
+#[my_codegen::get(path = "PAGES.auth.confirm_email_change")]
+pub async fn confirm_email_change(
+    path: web::Path<String>,
+    data: AppData,
+    nonce: CspNonce,
+) -> impl Responder {
+    render(change::confirm(&data, &path).await, false, nonce)
+}
+
+/// cancel email change from the link sent to the current address
+#[my_codegen::get(path = "PAGES.auth.cancel_email_change")]
+pub async fn cancel_email_change(
+    path: web::Path<String>,
+    data: AppData,
+    nonce: CspNonce,
+) -> impl Responder {
+    render(change::cancel(&data, &path).await, true, nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use db_core::AddEmailChange;
+
+    use crate::api::v1::account::email::Email;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn email_change_works_pg() {
+        let data = pg::get_data().await;
+        email_change_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn email_change_works_maria() {
+        let data = maria::get_data().await;
+        email_change_works(data).await;
+    }
+
+    async fn email_change_works(data: ArcData) {
+        const NAME: &str = "emailchangeuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "emailchangeuser@a.com";
+        const EMAIL2: &str = "emailchangeuser2@a.com";
+
+        let data = &data;
+        assert!(data.mailer.is_some());
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        // the address isn't changed until the change is confirmed
+        let email = Email {
+            email: EMAIL2.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&email, V1_API_ROUTES.account.update_email)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(data.db.get_email(NAME).await.unwrap().unwrap(), EMAIL);
+        let pending = data.db.get_email_change(NAME).await.unwrap().unwrap();
+        assert_eq!(pending.email, EMAIL2);
+
+        let get = |url: String| test::TestRequest::get().uri(&url).to_request();
+        let c = AddEmailChange {
+            token: "emailchangeusertoken",
+            cancel_token: "emailchangeusercanceltoken",
+            email: EMAIL2,
+        };
+
+        // cancelling discards the change and signs out all sessions
+        data.db.add_email_change(NAME, &c).await.unwrap();
+        let url = PAGES.auth.get_cancel_email_change(c.cancel_token);
+        let resp = test::call_service(&app, get(url)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.get_email_change(NAME).await.unwrap().is_none());
+        assert!(data.db.get_user_sessions(NAME).await.unwrap().is_empty());
+        let url = PAGES.auth.get_confirm_email_change(c.token);
+        let resp = test::call_service(&app, get(url)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(data.db.get_email(NAME).await.unwrap().unwrap(), EMAIL);
+
+        // confirming changes the address and marks it as verified
+        data.db.add_email_change(NAME, &c).await.unwrap();
+        let url = PAGES.auth.get_confirm_email_change(c.token);
+        let resp = test::call_service(&app, get(url.clone())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(data.db.get_email(NAME).await.unwrap().unwrap(), EMAIL2);
+        assert!(data.db.is_email_verified(NAME).await.unwrap());
+
+        // links are single use
+        let resp = test::call_service(&app, get(url)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
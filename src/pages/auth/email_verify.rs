@@ -3,37 +3,141 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-//! Email operations: verification, notification, etc
-use lettre::{
-    message::{header, MultiPart, SinglePart},
-    AsyncTransport, Message,
-};
+use actix_web::{web, HttpResponseBuilder, Responder, ResponseError};
 use sailfish::TemplateOnce;
 
-use crate::errors::*;
-use crate::Data;
-use crate::SETTINGS;
+use crate::email::verification;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+use crate::PAGES;
 
-const PAGE: &str = "Login";
-
-#[derive(Clone, Default, TemplateOnce)]
+#[derive(Clone, TemplateOnce)]
 #[template(path = "auth/email-verification/index.html")]
 struct IndexPage {
-    email: String,
+    error: Option<String>,
+    csp_nonce: String,
 }
 
+const PAGE: &str = "Verify Email";
 
-lazy_static! {
-    static ref INDEX: String = IndexPage::default().render_once().unwrap();
-}
-
-#[get(path = "PAGES.auth.login")]
-pub async fn email_verification() -> impl Responder {
-    HttpResponse::Ok()
+/// confirm email address from the link sent in the verification email
+#[my_codegen::get(path = "PAGES.auth.verify_email")]
+pub async fn verify_email(
+    path: web::Path<String>,
+    data: AppData,
+    nonce: CspNonce,
+) -> impl Responder {
+    let (status, error) = match verification::verify(&data, &path).await {
+        Ok(_) => (actix_web::http::StatusCode::OK, None),
+        Err(e) => (e.status_code(), Some(e.to_string())),
+    };
+    let body = IndexPage {
+        error,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    HttpResponseBuilder::new(status)
         .content_type("text/html; charset=utf-8")
-        .body(&*INDEX)
+        .body(body)
 }
 
-//TODO
-// Design cookie system to handle registration to showing this page,
-// verifying email and discarding the cookie
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::api::v1::account::email::Email;
+    use crate::api::v1::mcaptcha::create::CreateCaptcha;
+    use crate::email::verification;
+    use crate::errors::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn email_verification_gates_sitekeys_pg() {
+        let data = pg::get_data().await;
+        email_verification_gates_sitekeys(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn email_verification_gates_sitekeys_maria() {
+        let data = maria::get_data().await;
+        email_verification_gates_sitekeys(data).await;
+    }
+
+    async fn email_verification_gates_sitekeys(data: ArcData) {
+        const NAME: &str = "emailverifyuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "emailverifyuser@a.com";
+        const EMAIL2: &str = "emailverifyuser2@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.require_email_verification = true;
+        // no mails are sent, tokens are issued directly
+        settings.features.email = false;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let payload = CreateCaptcha {
+            levels: vec![L1, L2],
+            duration: 30,
+            description: "emailverify".into(),
+            publish_benchmarks: false,
+        };
+        let create = || {
+            post_request!(&payload, V1_API_ROUTES.captcha.create)
+                .cookie(cookies.clone())
+                .to_request()
+        };
+        let resp = test::call_service(&app, create()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.error, ServiceError::EmailNotVerified.to_string());
+
+        let token = verification::issue(data, NAME, EMAIL).await.unwrap();
+        let url = PAGES.auth.get_verify_email(&token);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri(&url).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.is_email_verified(NAME).await.unwrap());
+        let resp = test::call_service(&app, create()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // tokens are single use
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri(&url).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // changing email address requires verifying the new address
+        let email = Email {
+            email: EMAIL2.into(),
+        };
+        let resp = test::call_service(
+            &app,
+            post_request!(&email, V1_API_ROUTES.account.update_email)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!data.db.is_email_verified(NAME).await.unwrap());
+        let resp = test::call_service(&app, create()).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // tokens issued for the old address aren't valid
+        let token = verification::issue(data, NAME, EMAIL).await.unwrap();
+        let url = PAGES.auth.get_verify_email(&token);
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri(&url).to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(!data.db.is_email_verified(NAME).await.unwrap());
+    }
+}
@@ -4,30 +4,23 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_web::{HttpResponse, Responder};
-use lazy_static::lazy_static;
 use my_codegen::get;
 use sailfish::TemplateOnce;
 
+use crate::security_headers::CspNonce;
 use crate::PAGES;
 
 #[derive(Clone, TemplateOnce)]
 #[template(path = "auth/login/index.html")]
-struct IndexPage;
-const PAGE: &str = "Login";
-
-impl Default for IndexPage {
-    fn default() -> Self {
-        IndexPage
-    }
-}
-
-lazy_static! {
-    static ref INDEX: String = IndexPage.render_once().unwrap();
+struct IndexPage {
+    csp_nonce: String,
 }
+const PAGE: &str = "Login";
 
 #[get(path = "PAGES.auth.login")]
-pub async fn login() -> impl Responder {
+pub async fn login(nonce: CspNonce) -> impl Responder {
+    let body = IndexPage { csp_nonce: nonce.0 }.render_once().unwrap();
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(&**INDEX)
+        .body(body)
 }
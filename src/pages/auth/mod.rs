@@ -3,13 +3,23 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+pub mod agreements;
+pub mod email_change;
+pub mod email_verify;
 pub mod login;
 pub mod register;
+pub mod revoke_sessions;
 pub mod sudo;
 
 pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(login::login);
     cfg.service(register::join);
+    cfg.service(agreements::agreements);
+    cfg.service(agreements::accept_agreements);
+    cfg.service(email_verify::verify_email);
+    cfg.service(email_change::confirm_email_change);
+    cfg.service(email_change::cancel_email_change);
+    cfg.service(revoke_sessions::revoke_sessions);
 }
 
 pub mod routes {
@@ -18,15 +28,45 @@ pub mod routes {
     pub struct Auth {
         pub login: &'static str,
         pub join: &'static str,
+        pub agreements: &'static str,
+        pub verify_email: &'static str,
+        pub confirm_email_change: &'static str,
+        pub cancel_email_change: &'static str,
+        pub revoke_sessions: &'static str,
     }
     impl Auth {
         pub const fn new() -> Auth {
             Auth {
                 login: "/login",
                 join: "/join",
+                agreements: "/agreements",
+                verify_email: "/verify/{token}",
+                confirm_email_change: "/email-change/confirm/{token}",
+                cancel_email_change: "/email-change/cancel/{token}",
+                revoke_sessions: "/sessions/revoke/{token}",
             }
         }
 
+        pub fn get_verify_email(&self, token: &str) -> String {
+            self.verify_email.replace("{token}", token)
+        }
+
+        pub fn get_confirm_email_change(&self, token: &str) -> String {
+            self.confirm_email_change.replace("{token}", token)
+        }
+
+        pub fn get_cancel_email_change(&self, token: &str) -> String {
+            self.cancel_email_change.replace("{token}", token)
+        }
+
+        pub fn get_revoke_sessions(&self, token: &str) -> String {
+            self.revoke_sessions.replace("{token}", token)
+        }
+
+        pub fn get_join_invite(&self, token: &str) -> String {
+            format!("{}?invite={}", self.join, urlencoding::encode(token))
+        }
+
         pub const fn get_sitemap() -> [&'static str; 2] {
             const AUTH: Auth = Auth::new();
             [AUTH.login, AUTH.join]
@@ -3,29 +3,60 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use actix_web::{HttpResponse, Responder};
-use lazy_static::lazy_static;
+use actix_web::{web, HttpResponse, Responder};
 use sailfish::TemplateOnce;
+use serde::Deserialize;
+
+use crate::email::invitation;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+
+/// pending invitation with which the user is signing up
+#[derive(Clone)]
+struct PendingInvite {
+    token: String,
+    email: String,
+}
 
 #[derive(Clone, TemplateOnce)]
 #[template(path = "auth/register/index.html")]
-struct IndexPage;
+struct IndexPage {
+    invite: Option<PendingInvite>,
+    csp_nonce: String,
+}
 
 const PAGE: &str = "Join";
 
-impl Default for IndexPage {
-    fn default() -> Self {
-        IndexPage
-    }
-}
-
-lazy_static! {
-    static ref INDEX: String = IndexPage.render_once().unwrap();
+#[derive(Deserialize)]
+pub struct JoinQuery {
+    pub invite: Option<String>,
 }
 
+/// signup page. Signing up with a pending invitation fills in, and locks, the
+/// email address that the invitation was sent to
 #[my_codegen::get(path = "crate::PAGES.auth.join")]
-pub async fn join() -> impl Responder {
+pub async fn join(
+    query: web::Query<JoinQuery>,
+    data: AppData,
+    nonce: CspNonce,
+) -> impl Responder {
+    let invite = match query.into_inner().invite {
+        Some(token) => match invitation::get_pending(&data, &token).await {
+            Ok(invite) => Some(PendingInvite {
+                token,
+                email: invite.email,
+            }),
+            Err(_) => None,
+        },
+        None => None,
+    };
+    let body = IndexPage {
+        invite,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(&**INDEX)
+        .body(body)
 }
@@ -0,0 +1,90 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{web, HttpResponseBuilder, Responder, ResponseError};
+use sailfish::TemplateOnce;
+
+use crate::email::security;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+use crate::PAGES;
+
+#[derive(Clone, TemplateOnce)]
+#[template(path = "auth/revoke-sessions/index.html")]
+struct IndexPage {
+    error: Option<String>,
+    csp_nonce: String,
+}
+
+const PAGE: &str = "Sign Out All Sessions";
+
+/// sign out all sessions from the link sent in security alerts
+#[my_codegen::get(path = "PAGES.auth.revoke_sessions")]
+pub async fn revoke_sessions(
+    path: web::Path<String>,
+    data: AppData,
+    nonce: CspNonce,
+) -> impl Responder {
+    let (status, error) = match security::revoke_sessions(&data, &path).await {
+        Ok(_) => (actix_web::http::StatusCode::OK, None),
+        Err(e) => (e.status_code(), Some(e.to_string())),
+    };
+    let body = IndexPage {
+        error,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    HttpResponseBuilder::new(status)
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn revoke_sessions_works_pg() {
+        let data = pg::get_data().await;
+        revoke_sessions_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn revoke_sessions_works_maria() {
+        let data = maria::get_data().await;
+        revoke_sessions_works(data).await;
+    }
+
+    async fn revoke_sessions_works(data: ArcData) {
+        const NAME: &str = "revokesessionsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "revokesessionsuser@a.com";
+        const TOKEN: &str = "revokesessionsusertoken";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let app = get_app!(data).await;
+        assert!(!data.db.get_user_sessions(NAME).await.unwrap().is_empty());
+        assert!(data.db.count_known_devices(NAME).await.unwrap() > 0);
+
+        data.db.add_session_revocation(NAME, TOKEN).await.unwrap();
+        let url = PAGES.auth.get_revoke_sessions(TOKEN);
+        let get = || test::TestRequest::get().uri(&url).to_request();
+        let resp = test::call_service(&app, get()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(data.db.get_user_sessions(NAME).await.unwrap().is_empty());
+        assert_eq!(data.db.count_known_devices(NAME).await.unwrap(), 0);
+
+        // links are single use
+        let resp = test::call_service(&app, get()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
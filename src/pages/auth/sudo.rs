@@ -8,6 +8,8 @@ use std::fmt::Display;
 use sailfish::runtime::Render;
 use sailfish::TemplateOnce;
 
+use crate::security_headers::CspNonce;
+
 #[derive(Clone, TemplateOnce)]
 #[template(path = "auth/sudo/index.html")]
 pub struct SudoPage<'a, K, V>
@@ -17,6 +19,7 @@ where
 {
     url: &'a str,
     data: Option<Vec<(K, V)>>,
+    csp_nonce: String,
 }
 
 pub const PAGE: &str = "Confirm Access";
@@ -27,7 +30,11 @@ where
     V: Display + Render,
 {
     //pub fn new(url: &'a str, data: Option<Vec<(&'a str, &'a str)>>) -> Self {
-    pub fn new(url: &'a str, data: Option<Vec<(K, V)>>) -> Self {
-        Self { url, data }
+    pub fn new(url: &'a str, data: Option<Vec<(K, V)>>, nonce: CspNonce) -> Self {
+        Self {
+            url,
+            data,
+            csp_nonce: nonce.0,
+        }
     }
 }
@@ -4,56 +4,46 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_web::{web, HttpResponse, Responder};
-use lazy_static::lazy_static;
 use sailfish::TemplateOnce;
 
 use crate::errors::PageError;
+use crate::security_headers::CspNonce;
 
 #[derive(Clone, TemplateOnce)]
 #[template(path = "errors/index.html")]
 struct ErrorPage<'a> {
     title: &'a str,
     message: &'a str,
+    csp_nonce: String,
 }
 
 const PAGE: &str = "Error";
 
 impl<'a> ErrorPage<'a> {
-    fn new(title: &'a str, message: &'a str) -> Self {
-        ErrorPage { title, message }
+    fn new(title: &'a str, message: &'a str, nonce: CspNonce) -> Self {
+        ErrorPage {
+            title,
+            message,
+            csp_nonce: nonce.0,
+        }
     }
 }
 
-lazy_static! {
-    static ref INTERNAL_SERVER_ERROR_BODY: String = ErrorPage::new(
-        "Internal Server Error",
-        &format!("{}", PageError::InternalServerError),
-    )
-    .render_once()
-    .unwrap();
-    static ref UNKNOWN_ERROR_BODY: String = ErrorPage::new(
-        "Something went wrong",
-        &format!("{}", PageError::InternalServerError),
-    )
-    .render_once()
-    .unwrap();
-}
-
 const ERROR_ROUTE: &str = "/error/{id}";
 
 #[my_codegen::get(path = "ERROR_ROUTE")]
-async fn error(path: web::Path<usize>) -> impl Responder {
-    let resp = match path.into_inner() {
-        500 => HttpResponse::InternalServerError()
-            .content_type("text/html; charset=utf-8")
-            .body(&**INTERNAL_SERVER_ERROR_BODY),
-
-        _ => HttpResponse::InternalServerError()
-            .content_type("text/html; charset=utf-8")
-            .body(&**UNKNOWN_ERROR_BODY),
+async fn error(path: web::Path<usize>, nonce: CspNonce) -> impl Responder {
+    let title = match path.into_inner() {
+        500 => "Internal Server Error",
+        _ => "Something went wrong",
     };
-
-    resp
+    let message = PageError::InternalServerError.to_string();
+    let body = ErrorPage::new(title, &message, nonce)
+        .render_once()
+        .unwrap();
+    HttpResponse::InternalServerError()
+        .content_type("text/html; charset=utf-8")
+        .body(body)
 }
 
 pub fn services(cfg: &mut web::ServiceConfig) {
@@ -25,10 +25,15 @@ pub fn get_middleware() -> Authentication<routes::Routes> {
     Authentication::with_identity(routes::ROUTES)
 }
 
+/// public URL of this instance, used in page metadata
+pub fn instance_url() -> String {
+    crate::SETTINGS.server.url()
+}
+
 #[cfg(not(tarpaulin_include))]
 #[cfg(test)]
 mod tests {
-    use actix_web::http::StatusCode;
+    use actix_web::http::{header, StatusCode};
     use actix_web::test;
 
     use super::*;
@@ -72,6 +77,9 @@ mod tests {
             PAGES.panel.settings.home,
             PAGES.panel.settings.delete_account,
             PAGES.panel.settings.update_secret,
+            PAGES.panel.settings.sessions,
+            PAGES.panel.settings.notification_key,
+            PAGES.panel.orgs.list,
             &delete_sitekey_url,
             &edit_sitekey_url,
         ];
@@ -92,6 +100,140 @@ mod tests {
             .await;
 
             assert_eq!(authenticated_resp.status(), StatusCode::OK);
+
+            // scripts carry the nonce of the policy of the response
+            let csp = authenticated_resp
+                .headers()
+                .get(header::CONTENT_SECURITY_POLICY)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let body = test::read_body(authenticated_resp).await;
+            let body = String::from_utf8_lossy(&body);
+            let nonce = body
+                .split("nonce=\"")
+                .nth(1)
+                .and_then(|n| n.split('"').next())
+                .unwrap();
+            assert!(!nonce.is_empty(), "{url}");
+            assert!(csp.contains(&format!("'nonce-{nonce}'")), "{url}");
+        }
+
+        delete_user(data, NAME).await;
+    }
+
+    /// asset references(scripts, stylesheets, images, fonts) that point to an
+    /// external origin. Links that users navigate to aren't assets and are
+    /// ignored
+    fn external_assets(html: &str) -> Vec<String> {
+        fn is_external(url: &str) -> bool {
+            let url = url.trim_start_matches(['\'', '"']);
+            url.starts_with("http://")
+                || url.starts_with("https://")
+                || url.starts_with("//")
+        }
+
+        let mut found = Vec::new();
+        for tag in html.split('<').skip(1) {
+            let tag = &tag[0..tag.find('>').unwrap_or(tag.len())];
+            let name = tag.split_whitespace().next().unwrap_or_default();
+            if name == "a" || name.starts_with('!') || name.starts_with('?') {
+                continue;
+            }
+            for attr in ["src=", "href=", "srcset=", "data="] {
+                for (i, _) in tag.match_indices(attr) {
+                    if is_external(&tag[i + attr.len()..]) {
+                        found.push(tag.to_string());
+                    }
+                }
+            }
+        }
+        for (i, _) in html.match_indices("url(") {
+            if is_external(&html[i + 4..]) {
+                found.push(html[i..].chars().take(80).collect());
+            }
+        }
+        for (i, _) in html.match_indices("@import") {
+            if is_external(html[i + 7..].trim_start()) {
+                found.push(html[i..].chars().take(80).collect());
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn external_assets_are_detected() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="https://fonts.example.org/font.css" />
+            <script src="//cdn.example.org/lib.js"></script>
+            <style>body { background: url('https://example.org/bg.png') }</style>
+            </head><body>
+            <a href="https://mcaptcha.org">mCaptcha</a>
+            <img src="/assets/logo.svg" />
+            </body></html>"#;
+        assert_eq!(external_assets(html).len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn pages_are_self_hosted_pg() {
+        let data = pg::get_data().await;
+        pages_are_self_hosted(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn pages_are_self_hosted_maria() {
+        let data = maria::get_data().await;
+        pages_are_self_hosted(data).await;
+    }
+
+    /// every asset that a page loads must be served by the instance itself,
+    /// so that it works in air-gapped deployments
+    async fn pages_are_self_hosted(data: ArcData) {
+        const NAME: &str = "selfhosteduser";
+        const PASSWORD: &str = "longpassword";
+        const EMAIL: &str = "selfhosteduser@a.com";
+
+        let data = &data;
+        delete_user(data, NAME).await;
+
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, signin_resp, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+
+        let app = get_app!(data).await;
+
+        let edit_sitekey_url = PAGES.panel.sitekey.get_edit_advance(&token_key.key);
+        let view_sitekey_url = PAGES.panel.sitekey.get_view(&token_key.key);
+        let urls = [
+            PAGES.auth.login,
+            PAGES.auth.join,
+            crate::WIDGET_ROUTES.verification_widget,
+            crate::DOCS.home,
+            PAGES.home,
+            PAGES.panel.sitekey.add_advance,
+            PAGES.panel.sitekey.add_easy,
+            PAGES.panel.sitekey.list,
+            PAGES.panel.notifications,
+            PAGES.panel.settings.home,
+            &edit_sitekey_url,
+            &view_sitekey_url,
+        ];
+
+        for url in urls.iter() {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(url)
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK, "{url}");
+            let body = test::read_body(resp).await;
+            let html = String::from_utf8_lossy(&body);
+            let external = external_assets(&html);
+            assert!(external.is_empty(), "{url} loads {external:?}");
         }
 
         delete_user(data, NAME).await;
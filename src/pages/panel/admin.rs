@@ -0,0 +1,171 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{InstanceStats, InstanceUser, PendingRegistration};
+use sailfish::TemplateOnce;
+
+use crate::api::v1::admin::{DeadLetter, UsersQuery, USERS_PER_PAGE};
+use crate::email::relays::RelayHealth;
+use crate::errors::PageResult;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+
+#[derive(TemplateOnce)]
+#[template(path = "panel/admin/index.html")]
+pub struct AdminPage {
+    stats: InstanceStats,
+    users: Vec<InstanceUser>,
+    /// registrations that are pending approval
+    registrations: Vec<PendingRegistration>,
+    /// email domains that are banned from registration
+    banned_domains: Vec<String>,
+    /// health of the SMTP relays, when emails are enabled
+    relays: Vec<RelayHealth>,
+    /// emails that couldn't be sent
+    dead_letters: Vec<DeadLetter>,
+    page: usize,
+    /// is there a next page of users
+    has_next: bool,
+    csp_nonce: String,
+}
+
+impl AdminPage {
+    fn new(
+        stats: InstanceStats,
+        users: Vec<InstanceUser>,
+        registrations: Vec<PendingRegistration>,
+        banned_domains: Vec<String>,
+        relays: Vec<RelayHealth>,
+        dead_letters: Vec<DeadLetter>,
+        page: usize,
+    ) -> Self {
+        let has_next = users.len() == USERS_PER_PAGE;
+        AdminPage {
+            stats,
+            users,
+            registrations,
+            banned_domains,
+            relays,
+            dead_letters,
+            page,
+            has_next,
+            csp_nonce: String::new(),
+        }
+    }
+
+    fn page_route(page: usize) -> String {
+        format!("{}?page={}", crate::PAGES.panel.admin, page)
+    }
+}
+
+const PAGE: &str = "Administration";
+
+#[my_codegen::get(
+    path = "crate::PAGES.panel.admin",
+    wrap = "crate::pages::get_middleware()"
+)]
+pub async fn admin(
+    query: web::Query<UsersQuery>,
+    data: AppData,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
+    let stats = crate::demo::instance_stats(&data).await?;
+    let users = data.db.get_users(query.page, USERS_PER_PAGE).await?;
+    let registrations = data.db.get_pending_registrations(0, USERS_PER_PAGE).await?;
+    let banned_domains = data.db.get_banned_email_domains().await?;
+    let relays = data.mailer.as_ref().map(|m| m.health()).unwrap_or_default();
+    let dead_letters = data
+        .db
+        .get_dead_letters()
+        .await?
+        .into_iter()
+        .map(DeadLetter::from)
+        .collect();
+    let mut page = AdminPage::new(
+        stats,
+        users,
+        registrations,
+        banned_domains,
+        relays,
+        dead_letters,
+        query.page,
+    );
+    page.csp_nonce = nonce.0;
+    let body = page.render_once().unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+    use actix_web::web::Bytes;
+
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn admin_page_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        admin_page_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn admin_page_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        admin_page_works(data).await;
+    }
+
+    async fn admin_page_works(data: ArcData) {
+        const NAME: &str = "adminpageuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "adminpageuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get().uri(PAGES.panel.admin).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        // users that aren't administrators are sent to the dashboard
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.panel.admin)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        assert_eq!(
+            resp.headers().get(header::LOCATION).unwrap(),
+            PAGES.panel.home
+        );
+
+        data.db.set_admin(NAME, true).await.unwrap();
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(PAGES.panel.admin)
+                .cookie(cookies)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Bytes = test::read_body(resp).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Registered users"));
+    }
+}
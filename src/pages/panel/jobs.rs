@@ -0,0 +1,111 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_web::{HttpResponse, Responder};
+use sailfish::TemplateOnce;
+use sqlx::types::time::OffsetDateTime;
+
+use crate::date::Date;
+use crate::errors::PageResult;
+use crate::jobs::JobStatus;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+
+#[derive(TemplateOnce)]
+#[template(path = "panel/jobs/index.html")]
+pub struct JobsPage {
+    jobs: Vec<JobStatus>,
+    csp_nonce: String,
+}
+
+impl JobsPage {
+    fn new(jobs: Vec<JobStatus>, nonce: CspNonce) -> Self {
+        JobsPage {
+            jobs,
+            csp_nonce: nonce.0,
+        }
+    }
+
+    fn print_time(timestamp: Option<i64>) -> String {
+        match timestamp.map(OffsetDateTime::from_unix_timestamp) {
+            Some(Ok(time)) => Date::format(&time),
+            _ => "-".into(),
+        }
+    }
+
+    fn print_duration(duration: Option<u64>) -> String {
+        match duration {
+            Some(d) => format!("{d}ms"),
+            None => "-".into(),
+        }
+    }
+}
+
+const PAGE: &str = "Background Jobs";
+
+#[my_codegen::get(
+    path = "crate::PAGES.panel.jobs",
+    wrap = "crate::pages::get_middleware()"
+)]
+pub async fn jobs(data: AppData, nonce: CspNonce) -> PageResult<impl Responder> {
+    let body = JobsPage::new(data.jobs.list(), nonce)
+        .render_once()
+        .unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Bytes;
+
+    use crate::jobs::DEMO_USER_JOB;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn jobs_page_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        jobs_page_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn jobs_page_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        jobs_page_works(data).await;
+    }
+
+    async fn jobs_page_works(data: ArcData) {
+        const NAME: &str = "jobspageuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "jobspageuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        data.jobs.register(DEMO_USER_JOB, 60);
+        let jobs_page = || {
+            test::TestRequest::get()
+                .uri(PAGES.panel.jobs)
+                .cookie(cookies.clone())
+                .to_request()
+        };
+        let resp = test::call_service(&app, jobs_page()).await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        data.db.set_admin(NAME, true).await.unwrap();
+        let resp = test::call_service(&app, jobs_page()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Bytes = test::read_body(resp).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(DEMO_USER_JOB));
+    }
+}
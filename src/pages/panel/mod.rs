@@ -7,7 +7,10 @@ use actix_identity::Identity;
 use actix_web::{HttpResponse, Responder};
 use sailfish::TemplateOnce;
 
+mod admin;
+mod jobs;
 mod notifications;
+pub mod orgs;
 mod settings;
 pub mod sitekey;
 mod utils;
@@ -15,17 +18,25 @@ mod utils;
 use db_core::Captcha;
 
 use crate::errors::PageResult;
+use crate::quotas::Usage;
+use crate::security_headers::CspNonce;
 use crate::AppData;
 
 #[derive(TemplateOnce, Clone)]
 #[template(path = "panel/index.html")]
 pub struct IndexPage {
     sitekeys: Vec<Captcha>,
+    quota: Usage,
+    csp_nonce: String,
 }
 
 impl IndexPage {
-    fn new(sitekeys: Vec<Captcha>) -> Self {
-        IndexPage { sitekeys }
+    fn new(sitekeys: Vec<Captcha>, quota: Usage, nonce: CspNonce) -> Self {
+        IndexPage {
+            sitekeys,
+            quota,
+            csp_nonce: nonce.0,
+        }
     }
 }
 
@@ -35,10 +46,20 @@ const PAGE: &str = "Dashboard";
     path = "crate::PAGES.panel.home",
     wrap = "crate::pages::get_middleware()"
 )]
-async fn panel(data: AppData, id: Identity) -> PageResult<impl Responder> {
+async fn panel(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
     let username = id.identity().unwrap();
     let sitekeys = data.db.get_all_user_captchas(&username).await?;
-    let body = IndexPage::new(sitekeys).render_once().unwrap();
+    let quota = Usage {
+        used: sitekeys.len(),
+        limit: crate::quotas::get(&data, &username).await?.max_captchas,
+    };
+    let body = IndexPage::new(sitekeys, quota, nonce)
+        .render_once()
+        .unwrap();
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(body))
@@ -50,9 +71,13 @@ pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     sitekey::services(cfg);
     utils::services(cfg);
     cfg.service(notifications::notifications);
+    cfg.service(jobs::jobs);
+    cfg.service(admin::admin);
+    orgs::services(cfg);
 }
 
 pub mod routes {
+    use super::orgs::routes::Orgs;
     use super::settings::routes::Settings;
     use super::sitekey::routes::Sitekey;
     use super::utils::routes::Utils;
@@ -61,6 +86,9 @@ pub mod routes {
         pub home: &'static str,
         pub sitekey: Sitekey,
         pub notifications: &'static str,
+        pub jobs: &'static str,
+        pub admin: &'static str,
+        pub orgs: Orgs,
         pub settings: Settings,
         pub utils: Utils,
     }
@@ -71,6 +99,9 @@ pub mod routes {
                 home: "/",
                 sitekey: Sitekey::new(),
                 notifications: "/notifications",
+                jobs: "/jobs",
+                admin: "/admin",
+                orgs: Orgs::new(),
                 settings: Settings::new(),
                 utils: Utils::new(),
             }
@@ -10,6 +10,7 @@ use sqlx::types::time::OffsetDateTime;
 
 use crate::date::Date;
 use crate::errors::PageResult;
+use crate::security_headers::CspNonce;
 use crate::AppData;
 
 #[derive(TemplateOnce)]
@@ -17,11 +18,15 @@ use crate::AppData;
 pub struct IndexPage {
     /// notifications
     n: Vec<Notification>,
+    csp_nonce: String,
 }
 
 impl IndexPage {
-    fn new(n: Vec<Notification>) -> Self {
-        IndexPage { n }
+    fn new(n: Vec<Notification>, nonce: CspNonce) -> Self {
+        IndexPage {
+            n,
+            csp_nonce: nonce.0,
+        }
     }
 }
 
@@ -31,6 +36,8 @@ pub struct Notification {
     pub message: String,
     pub received: OffsetDateTime,
     pub id: i32,
+    /// is the message an encrypted message, to be decrypted in the browser
+    pub encrypted: bool,
 }
 
 impl From<db_core::Notification> for Notification {
@@ -41,6 +48,7 @@ impl From<db_core::Notification> for Notification {
             received: OffsetDateTime::from_unix_timestamp(n.received.unwrap()).unwrap(),
             id: n.id.unwrap(),
             message: n.message.unwrap(),
+            encrypted: n.encrypted,
         }
     }
 }
@@ -57,7 +65,14 @@ const PAGE: &str = "Notifications";
     path = "crate::PAGES.panel.notifications",
     wrap = "crate::pages::get_middleware()"
 )]
-pub async fn notifications(data: AppData, id: Identity) -> PageResult<impl Responder> {
+pub async fn notifications(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Ok(HttpResponse::NotFound().finish());
+    }
     let receiver = id.identity().unwrap();
     // TODO handle error where payload.to doesn't exist
 
@@ -65,7 +80,7 @@ pub async fn notifications(data: AppData, id: Identity) -> PageResult<impl Respo
     let mut notifications = data.db.get_all_unread_notifications(&receiver).await?;
     let notifications = notifications.drain(0..).map(|x| x.into()).collect();
 
-    let body = IndexPage::new(notifications).render_once().unwrap();
+    let body = IndexPage::new(notifications, nonce).render_once().unwrap();
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(body))
@@ -84,6 +99,7 @@ mod tests {
             heading: String::default(),
             message: String::default(),
             id: 1,
+            encrypted: false,
         };
 
         let timestamp = n.received.unix_timestamp();
@@ -0,0 +1,196 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use actix_identity::Identity;
+use actix_web::{web, HttpResponse, Responder};
+use db_core::{Captcha, OrgMember, OrgMembership, OrgRole};
+use sailfish::TemplateOnce;
+
+use crate::api::v1::orgs::runners::require_role;
+use crate::errors::PageResult;
+use crate::security_headers::CspNonce;
+use crate::AppData;
+
+pub mod routes {
+    pub struct Orgs {
+        pub list: &'static str,
+        pub view: &'static str,
+    }
+
+    impl Orgs {
+        pub const fn new() -> Self {
+            Orgs {
+                list: "/orgs",
+                view: "/orgs/{org}",
+            }
+        }
+
+        pub fn get_view(&self, org: &str) -> String {
+            self.view.replace("{org}", org)
+        }
+    }
+}
+
+pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.service(list_orgs);
+    cfg.service(view_org);
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "panel/orgs/index.html")]
+pub struct ListOrgsPage {
+    orgs: Vec<OrgMembership>,
+    csp_nonce: String,
+}
+
+const PAGE: &str = "Organizations";
+
+/// route handler that lists the user's organizations
+#[my_codegen::get(
+    path = "crate::PAGES.panel.orgs.list",
+    wrap = "crate::pages::get_middleware()"
+)]
+pub async fn list_orgs(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let orgs = data.db.get_user_orgs(&username).await?;
+    let body = ListOrgsPage {
+        orgs,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "panel/orgs/view/index.html")]
+pub struct ViewOrgPage {
+    org: String,
+    username: String,
+    role: OrgRole,
+    members: Vec<OrgMember>,
+    sitekeys: Vec<Captcha>,
+    /// user's sitekeys that can be moved into the organization
+    movable: Vec<Captcha>,
+    csp_nonce: String,
+}
+
+/// route handler that renders an organization's members and sitekeys
+#[my_codegen::get(
+    path = "crate::PAGES.panel.orgs.view",
+    wrap = "crate::pages::get_middleware()"
+)]
+pub async fn view_org(
+    path: web::Path<String>,
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let org = path.into_inner();
+    let role = require_role(&data, &org, &username, OrgRole::Viewer).await?;
+    let members = data.db.get_org_members(&org).await?;
+    let sitekeys = data.db.get_org_captchas(&org).await?;
+    let mut movable = Vec::new();
+    if role >= OrgRole::Editor {
+        for captcha in data.db.get_all_user_captchas(&username).await? {
+            if data.db.get_captcha_org(&captcha.key).await?.is_none() {
+                movable.push(captcha);
+            }
+        }
+    }
+    let body = ViewOrgPage {
+        org,
+        username,
+        role,
+        members,
+        sitekeys,
+        movable,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+    use actix_web::web::Bytes;
+
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn get_org_routes_work() {
+        assert_eq!(PAGES.panel.orgs.get_view("acme"), "/orgs/acme");
+    }
+
+    #[actix_rt::test]
+    async fn org_pages_work_pg() {
+        let data = crate::tests::pg::get_data().await;
+        org_pages_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn org_pages_work_maria() {
+        let data = crate::tests::maria::get_data().await;
+        org_pages_work(data).await;
+    }
+
+    async fn org_pages_work(data: ArcData) {
+        const NAME: &str = "orgpageuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "orgpageuser@a.com";
+        const ORG: &str = "org-page-test";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        if data.db.org_exists(ORG).await.unwrap() {
+            data.db.delete_org(ORG).await.unwrap();
+        }
+        let (_, signin_resp) = register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let cookies = get_cookie!(signin_resp);
+        let app = get_app!(data).await;
+
+        let view = PAGES.panel.orgs.get_view(ORG);
+        // organizations of which the user isn't a member aren't found
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&view)
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        data.db.create_org(NAME, ORG).await.unwrap();
+        for url in [PAGES.panel.orgs.list, view.as_str()] {
+            let resp = test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri(url)
+                    .cookie(cookies.clone())
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body: Bytes = test::read_body(resp).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains(ORG));
+        }
+        data.db.delete_org(ORG).await.unwrap();
+    }
+}
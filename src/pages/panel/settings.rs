@@ -4,11 +4,17 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_identity::Identity;
-use actix_web::{HttpResponse, Responder};
+use actix_web::{HttpRequest, HttpResponse, Responder};
+use db_core::DigestFrequency;
 use sailfish::TemplateOnce;
+use sqlx::types::time::OffsetDateTime;
 
+use crate::api::v1::account::sessions::{runners as sessions_runners, SessionInfo};
+use crate::date::Date;
 use crate::errors::PageResult;
 use crate::pages::auth::sudo::SudoPage;
+use crate::security_headers::CspNonce;
+use crate::sessions::CurrentSession;
 use crate::AppData;
 
 pub mod routes {
@@ -16,6 +22,8 @@ pub mod routes {
         pub home: &'static str,
         pub delete_account: &'static str,
         pub update_secret: &'static str,
+        pub sessions: &'static str,
+        pub notification_key: &'static str,
     }
 
     impl Settings {
@@ -24,6 +32,8 @@ pub mod routes {
                 home: "/settings",
                 delete_account: "/settings/account/delete",
                 update_secret: "/settings/secret/update",
+                sessions: "/settings/sessions",
+                notification_key: "/settings/notification-key",
             }
         }
 
@@ -39,6 +49,8 @@ pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     cfg.service(settings);
     cfg.service(update_secret);
     cfg.service(delete_account);
+    cfg.service(sessions);
+    cfg.service(notification_key);
 }
 
 const PAGE: &str = "Settings";
@@ -47,25 +59,41 @@ const PAGE: &str = "Settings";
 #[template(path = "panel/settings/index.html")]
 pub struct IndexPage<'a> {
     email: Option<String>,
+    /// address that the user is changing their email to, until confirmed
+    pending_email: Option<String>,
     secret: String,
     username: &'a str,
+    /// set when stats digests can be sent, see [crate::digest]
+    digests: bool,
+    digest: Option<DigestFrequency>,
+    csp_nonce: String,
 }
 
 #[my_codegen::get(
     path = "crate::PAGES.panel.settings.home",
     wrap = "crate::pages::get_middleware()"
 )]
-async fn settings(data: AppData, id: Identity) -> PageResult<impl Responder> {
+async fn settings(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
     let username = id.identity().unwrap();
 
     let secret = data.db.get_secret(&username).await?;
     let secret = secret.secret;
     let email = data.db.get_email(&username).await?;
+    let pending_email = data.db.get_email_change(&username).await?.map(|c| c.email);
+    let digest = data.db.get_digest_frequency(&username).await?;
 
     let data = IndexPage {
         email,
+        pending_email,
         secret,
         username: &username,
+        digests: data.mailer.is_some(),
+        digest,
+        csp_nonce: nonce.0,
     };
 
     let body = data.render_once().unwrap();
@@ -78,8 +106,8 @@ async fn settings(data: AppData, id: Identity) -> PageResult<impl Responder> {
     path = "crate::PAGES.panel.settings.delete_account",
     wrap = "crate::pages::get_middleware()"
 )]
-async fn delete_account() -> impl Responder {
-    let page = SudoPage::<u8, u8>::new(crate::V1_API_ROUTES.account.delete, None)
+async fn delete_account(nonce: CspNonce) -> impl Responder {
+    let page = SudoPage::<u8, u8>::new(crate::V1_API_ROUTES.account.delete, None, nonce)
         .render_once()
         .unwrap();
     HttpResponse::Ok()
@@ -91,11 +119,93 @@ async fn delete_account() -> impl Responder {
     path = "crate::PAGES.panel.settings.update_secret",
     wrap = "crate::pages::get_middleware()"
 )]
-async fn update_secret() -> impl Responder {
-    let page = SudoPage::<u8, u8>::new(crate::V1_API_ROUTES.account.update_secret, None)
+async fn update_secret(nonce: CspNonce) -> impl Responder {
+    let route = crate::V1_API_ROUTES.account.update_secret;
+    let page = SudoPage::<u8, u8>::new(route, None, nonce)
         .render_once()
         .unwrap();
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(page)
 }
+
+pub struct Session {
+    pub info: SessionInfo,
+}
+
+impl Session {
+    pub fn print_last_active(&self) -> String {
+        let time = OffsetDateTime::from_unix_timestamp(self.info.last_active).unwrap();
+        Date::format(&time)
+    }
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "panel/settings/sessions/index.html")]
+pub struct SessionsPage {
+    sessions: Vec<Session>,
+    csp_nonce: String,
+}
+
+#[my_codegen::get(
+    path = "crate::PAGES.panel.settings.sessions",
+    wrap = "crate::pages::get_middleware()"
+)]
+async fn sessions(
+    req: HttpRequest,
+    data: AppData,
+    id: Identity,
+) -> PageResult<impl Responder> {
+    let username = id.identity().unwrap();
+    let current = CurrentSession::get(&req);
+    let csp_nonce = CspNonce::of(&req).0;
+    let sessions = sessions_runners::list(&data, &username, current.as_deref())
+        .await?
+        .into_iter()
+        .map(|info| Session { info })
+        .collect();
+
+    let body = SessionsPage {
+        sessions,
+        csp_nonce,
+    }
+    .render_once()
+    .unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
+
+#[derive(TemplateOnce)]
+#[template(path = "panel/settings/notification-key/index.html")]
+pub struct NotificationKeyPage {
+    /// has the user set a notification key
+    key_set: bool,
+    csp_nonce: String,
+}
+
+#[my_codegen::get(
+    path = "crate::PAGES.panel.settings.notification_key",
+    wrap = "crate::pages::get_middleware()"
+)]
+async fn notification_key(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
+    if !data.settings.features.notifications {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let username = id.identity().unwrap();
+    let key_set = data.db.get_notification_key(&username).await?.is_some();
+
+    let body = NotificationKeyPage {
+        key_set,
+        csp_nonce: nonce.0,
+    }
+    .render_once()
+    .unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body))
+}
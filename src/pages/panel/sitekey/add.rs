@@ -4,16 +4,11 @@
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
 use actix_web::{HttpResponse, Responder};
-use lazy_static::lazy_static;
 use sailfish::TemplateOnce;
 
-const PAGE: &str = "Add Sitekey";
+use crate::security_headers::CspNonce;
 
-lazy_static! {
-    static ref ADVANCE_INDEX: String =
-        AdvanceIndexPage::default().render_once().unwrap();
-    static ref EASY_INDEX: String = EasyIndexPage::default().render_once().unwrap();
-}
+const PAGE: &str = "Add Sitekey";
 
 #[derive(TemplateOnce, Clone)]
 #[template(path = "panel/sitekey/add/advance/index.html")]
@@ -22,6 +17,7 @@ pub struct AdvanceIndexPage<'a> {
     pub form_title: &'a str,
     pub form_description: &'a str,
     pub form_duration: usize,
+    pub csp_nonce: String,
 }
 
 impl<'a> Default for AdvanceIndexPage<'a> {
@@ -31,6 +27,7 @@ impl<'a> Default for AdvanceIndexPage<'a> {
             form_description: "",
             form_title: PAGE,
             form_duration: 30,
+            csp_nonce: String::new(),
         }
     }
 }
@@ -39,10 +36,16 @@ impl<'a> Default for AdvanceIndexPage<'a> {
     path = "crate::PAGES.panel.sitekey.add_advance",
     wrap = "crate::pages::get_middleware()"
 )]
-pub async fn advance() -> impl Responder {
+pub async fn advance(nonce: CspNonce) -> impl Responder {
+    let body = AdvanceIndexPage {
+        csp_nonce: nonce.0,
+        ..Default::default()
+    }
+    .render_once()
+    .unwrap();
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(&**ADVANCE_INDEX)
+        .body(body)
 }
 
 #[derive(TemplateOnce, Clone)]
@@ -53,6 +56,7 @@ pub struct EasyIndexPage<'a> {
     pub peak_sustainable_traffic: Option<usize>,
     pub avg_traffic: Option<usize>,
     pub broke_my_site_traffic: Option<usize>,
+    pub csp_nonce: String,
 }
 
 impl<'a> Default for EasyIndexPage<'a> {
@@ -63,6 +67,7 @@ impl<'a> Default for EasyIndexPage<'a> {
             avg_traffic: None,
             broke_my_site_traffic: None,
             form_title: PAGE,
+            csp_nonce: String::new(),
         }
     }
 }
@@ -71,8 +76,14 @@ impl<'a> Default for EasyIndexPage<'a> {
     path = "crate::PAGES.panel.sitekey.add_easy",
     wrap = "crate::pages::get_middleware()"
 )]
-pub async fn easy() -> impl Responder {
+pub async fn easy(nonce: CspNonce) -> impl Responder {
+    let body = EasyIndexPage {
+        csp_nonce: nonce.0,
+        ..Default::default()
+    }
+    .render_once()
+    .unwrap();
     HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
-        .body(&**EASY_INDEX)
+        .body(body)
 }
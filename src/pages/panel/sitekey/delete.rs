@@ -8,16 +8,17 @@ use my_codegen::get;
 use sailfish::TemplateOnce;
 
 use crate::pages::auth::sudo::SudoPage;
+use crate::security_headers::CspNonce;
 use crate::{PAGES, V1_API_ROUTES};
 
 #[get(
     path = "PAGES.panel.sitekey.delete",
     wrap = "crate::pages::get_middleware()"
 )]
-pub async fn delete_sitekey(path: web::Path<String>) -> impl Responder {
+pub async fn delete_sitekey(path: web::Path<String>, nonce: CspNonce) -> impl Responder {
     let key = path.into_inner();
     let data = vec![("sitekey", key)];
-    let page = SudoPage::new(V1_API_ROUTES.captcha.delete, Some(data))
+    let page = SudoPage::new(V1_API_ROUTES.captcha.delete, Some(data), nonce)
         .render_once()
         .unwrap();
     HttpResponse::Ok()
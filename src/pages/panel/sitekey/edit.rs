@@ -8,11 +8,12 @@ use actix_web::{http, web, HttpResponse, Responder};
 use sailfish::TemplateOnce;
 
 use db_core::errors::DBError;
-use db_core::Captcha;
+use db_core::{Captcha, WidgetTheme};
 use libmcaptcha::defense::Level;
 
 use crate::api::v1::mcaptcha::easy::TrafficPatternRequest;
 use crate::errors::*;
+use crate::security_headers::CspNonce;
 use crate::AppData;
 
 const PAGE: &str = "Edit Sitekey";
@@ -25,6 +26,8 @@ struct AdvanceEditPage {
     key: String,
     levels: Vec<Level>,
     publish_benchmarks: bool,
+    theme: WidgetTheme,
+    csp_nonce: String,
 }
 
 impl AdvanceEditPage {
@@ -33,6 +36,8 @@ impl AdvanceEditPage {
         levels: Vec<Level>,
         key: String,
         publish_benchmarks: bool,
+        theme: WidgetTheme,
+        nonce: CspNonce,
     ) -> Self {
         AdvanceEditPage {
             duration: config.duration as u32,
@@ -40,6 +45,8 @@ impl AdvanceEditPage {
             levels,
             key,
             publish_benchmarks,
+            theme,
+            csp_nonce: nonce.0,
         }
     }
 }
@@ -53,6 +60,7 @@ pub async fn advance(
     path: web::Path<String>,
     data: AppData,
     id: Identity,
+    nonce: CspNonce,
 ) -> PageResult<impl Responder> {
     let username = id.identity().unwrap();
     let key = path.into_inner();
@@ -60,10 +68,12 @@ pub async fn advance(
     let config = data.db.get_captcha_config(&username, &key).await?;
     let levels = data.db.get_captcha_levels(Some(&username), &key).await?;
     let publish_benchmarks = data.db.analytics_captcha_is_published(&key).await?;
+    let theme = crate::widget::config::theme(&data, &key).await?;
 
-    let body = AdvanceEditPage::new(config, levels, key, publish_benchmarks)
-        .render_once()
-        .unwrap();
+    let body =
+        AdvanceEditPage::new(config, levels, key, publish_benchmarks, theme, nonce)
+            .render_once()
+            .unwrap();
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(body))
@@ -75,14 +85,23 @@ pub struct EasyEditPage<'a> {
     pub form_title: &'a str,
     pub pattern: TrafficPatternRequest,
     pub key: String,
+    pub theme: WidgetTheme,
+    pub csp_nonce: String,
 }
 
 impl<'a> EasyEditPage<'a> {
-    pub fn new(key: String, pattern: TrafficPatternRequest) -> Self {
+    pub fn new(
+        key: String,
+        pattern: TrafficPatternRequest,
+        theme: WidgetTheme,
+        nonce: CspNonce,
+    ) -> Self {
         Self {
             form_title: PAGE,
             pattern,
             key,
+            theme,
+            csp_nonce: nonce.0,
         }
     }
 }
@@ -96,6 +115,7 @@ pub async fn easy(
     path: web::Path<String>,
     data: AppData,
     id: Identity,
+    nonce: CspNonce,
 ) -> PageResult<impl Responder> {
     let username = id.identity().unwrap();
     let key = path.into_inner();
@@ -113,7 +133,10 @@ pub async fn easy(
                 publish_benchmarks,
             };
 
-            let page = EasyEditPage::new(key, pattern).render_once().unwrap();
+            let theme = crate::widget::config::theme(&data, &key).await?;
+            let page = EasyEditPage::new(key, pattern, theme, nonce)
+                .render_once()
+                .unwrap();
             return Ok(HttpResponse::Ok()
                 .content_type("text/html; charset=utf-8")
                 .body(page));
@@ -189,6 +212,8 @@ mod test {
         assert!(body.contains(&L1.difficulty_factor.to_string()));
         assert!(body.contains(&L2.difficulty_factor.to_string()));
         assert!(body.contains(&L2.visitor_threshold.to_string()));
+        assert!(body.contains("Widget Theme"));
+        assert!(body.contains(&db_core::WidgetTheme::default().accent_color));
 
         let easy_url = PAGES.panel.sitekey.get_edit_easy(&key.key);
 
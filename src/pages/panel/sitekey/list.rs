@@ -10,19 +10,27 @@ use sailfish::TemplateOnce;
 use db_core::Captcha;
 
 use crate::errors::*;
+use crate::quotas::Usage;
+use crate::security_headers::CspNonce;
 use crate::AppData;
 
 #[derive(TemplateOnce, Clone)]
 #[template(path = "panel/sitekey/list/index.html")]
 pub struct IndexPage {
     sitekeys: Vec<Captcha>,
+    quota: Usage,
+    csp_nonce: String,
 }
 
 const PAGE: &str = "SiteKeys";
 
 impl IndexPage {
-    fn new(sitekeys: Vec<Captcha>) -> Self {
-        IndexPage { sitekeys }
+    fn new(sitekeys: Vec<Captcha>, quota: Usage, nonce: CspNonce) -> Self {
+        IndexPage {
+            sitekeys,
+            quota,
+            csp_nonce: nonce.0,
+        }
     }
 }
 
@@ -31,10 +39,18 @@ impl IndexPage {
     path = "crate::PAGES.panel.sitekey.list",
     wrap = "crate::pages::get_middleware()"
 )]
-pub async fn list_sitekeys(data: AppData, id: Identity) -> PageResult<impl Responder> {
+pub async fn list_sitekeys(
+    data: AppData,
+    id: Identity,
+    nonce: CspNonce,
+) -> PageResult<impl Responder> {
     let username = id.identity().unwrap();
     let res = data.db.get_all_user_captchas(&username).await?;
-    let body = IndexPage::new(res).render_once().unwrap();
+    let quota = Usage {
+        used: res.len(),
+        limit: crate::quotas::get(&data, &username).await?.max_captchas,
+    };
+    let body = IndexPage::new(res, quota, nonce).render_once().unwrap();
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(body))
@@ -6,16 +6,30 @@
 use actix_identity::Identity;
 use actix_web::{web, HttpResponse, Responder};
 use sailfish::TemplateOnce;
+use sqlx::types::time::OffsetDateTime;
 
-use db_core::Captcha;
+use db_core::errors::DBError;
+use db_core::{Captcha, Fallback, InvisibleMode, WebhookDelivery};
 use libmcaptcha::defense::Level;
 
+use crate::api::v1::mcaptcha::stats::StatsFilter;
 use crate::errors::*;
-use crate::stats::CaptchaStats;
+use crate::recommendation::Recommendation;
+use crate::security_headers::CspNonce;
+use crate::stats::{CaptchaStats, StatsQuery};
+use crate::widget::embed::EmbedSnippet;
 use crate::AppData;
 
 const PAGE: &str = "SiteKeys";
 
+/// ranges of stats that the view links to, in days before now
+const STATS_RANGES: [(&str, i64); 4] = [
+    ("Last day", 1),
+    ("Last week", 7),
+    ("Last month", 30),
+    ("Last year", 365),
+];
+
 #[derive(TemplateOnce, Clone)]
 #[template(path = "panel/sitekey/view/index.html")]
 struct IndexPage {
@@ -24,28 +38,84 @@ struct IndexPage {
     key: String,
     levels: Vec<Level>,
     stats: CaptchaStats,
+    /// range and limit of `stats`
+    stats_query: StatsQuery,
+    /// links to the stats of other ranges
+    stats_ranges: Vec<(&'static str, String)>,
     publish_benchmarks: bool,
+    strict_tokens: bool,
+    replays: Vec<i64>,
+    webhook: Option<String>,
+    deliveries: Vec<WebhookDelivery>,
+    allowed_domains: Vec<String>,
+    /// sitekeys need allowed domains to serve challenges
+    domains_required: bool,
+    /// unix timestamp until which the sitekey serves challenges without
+    /// allowed domains, when they are required
+    domains_deadline: Option<i64>,
+    /// not set when analytics are disabled
+    recommendation: Option<Recommendation>,
+    /// snippet that embeds the widget of the sitekey
+    embed: Option<EmbedSnippet>,
+    invisible: InvisibleMode,
+    fallback: Fallback,
+    /// PoW verifications are scheduled ahead of others, as set by
+    /// administrators
+    high_priority: bool,
+    csp_nonce: String,
 }
 
 impl IndexPage {
     fn new(
         stats: CaptchaStats,
+        stats_query: StatsQuery,
         config: Captcha,
         levels: Vec<Level>,
         key: String,
         publish_benchmarks: bool,
+        strict_tokens: bool,
+        replays: Vec<i64>,
     ) -> Self {
+        let stats_ranges = stats_ranges(&key);
         IndexPage {
             duration: config.duration as u32,
             name: config.description,
             levels,
             key,
             stats,
+            stats_query,
+            stats_ranges,
             publish_benchmarks,
+            strict_tokens,
+            replays,
+            webhook: None,
+            deliveries: Vec::new(),
+            allowed_domains: Vec::new(),
+            domains_required: false,
+            domains_deadline: None,
+            recommendation: None,
+            embed: None,
+            invisible: InvisibleMode::default(),
+            fallback: Fallback::default(),
+            high_priority: false,
+            csp_nonce: String::new(),
         }
     }
 }
 
+/// links to the stats of [STATS_RANGES] of a sitekey
+fn stats_ranges(key: &str) -> Vec<(&'static str, String)> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let view = crate::PAGES.panel.sitekey.get_view(key);
+    STATS_RANGES
+        .iter()
+        .map(|(name, days)| {
+            let from = now - days * 60 * 60 * 24;
+            (*name, format!("{view}?from={from}"))
+        })
+        .collect()
+}
+
 /// route handler that renders individual views for sitekeys
 #[my_codegen::get(
     path = "crate::PAGES.panel.sitekey.view",
@@ -53,19 +123,67 @@ impl IndexPage {
 )]
 pub async fn view_sitekey(
     path: web::Path<String>,
+    query: web::Query<StatsFilter>,
     data: AppData,
     id: Identity,
+    nonce: CspNonce,
 ) -> PageResult<impl Responder> {
     let username = id.identity().unwrap();
     let key = path.into_inner();
     let config = data.db.get_captcha_config(&username, &key).await?;
     let levels = data.db.get_captcha_levels(Some(&username), &key).await?;
-    let stats = data.stats.fetch(&data, &username, &key).await?;
+    let stats_query = query.query()?;
+    let stats = data
+        .stats
+        .fetch(&data, &username, &key, &stats_query)
+        .await?;
     let publish_benchmarks = data.db.analytics_captcha_is_published(&key).await?;
+    let strict_tokens = data.db.captcha_strict_tokens(&key).await?;
+    let replays = data.db.fetch_token_replays(&username, &key).await?;
+    let webhook = match data.db.get_webhook(&key).await {
+        Ok(webhook) => Some(webhook.url),
+        Err(DBError::WebhookNotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+    let deliveries = data
+        .db
+        .fetch_webhook_deliveries(
+            &username,
+            &key,
+            crate::api::v1::mcaptcha::webhook::DELIVERY_LOG_LIMIT,
+        )
+        .await?;
+    let allowed_domains = data.db.get_allowed_domains(&key).await?;
+    let domains_required = data.settings.captcha.require_allowed_domains;
+    let domains_deadline = data.db.get_allowed_domains_deadline(&key).await?;
+    let recommendation = if data.settings.features.analytics {
+        Some(crate::recommendation::recommend(&data, &username, &key).await?)
+    } else {
+        None
+    };
 
-    let body = IndexPage::new(stats, config, levels, key, publish_benchmarks)
-        .render_once()
-        .unwrap();
+    let mut page = IndexPage::new(
+        stats,
+        stats_query,
+        config,
+        levels,
+        key,
+        publish_benchmarks,
+        strict_tokens,
+        replays,
+    );
+    page.webhook = webhook;
+    page.deliveries = deliveries;
+    page.allowed_domains = allowed_domains;
+    page.domains_required = domains_required;
+    page.domains_deadline = domains_deadline;
+    page.recommendation = recommendation;
+    page.embed = Some(EmbedSnippet::new(&data.settings, &page.key));
+    page.invisible = data.db.captcha_invisible_mode(&page.key).await?;
+    page.fallback = data.db.captcha_fallback(&page.key).await?;
+    page.high_priority = data.db.captcha_high_priority(&page.key).await?;
+    page.csp_nonce = nonce.0;
+    let body = page.render_once().unwrap();
     Ok(HttpResponse::Ok()
         .content_type("text/html; charset=utf-8")
         .body(body))
@@ -128,5 +246,31 @@ mod test {
         assert!(body.contains(&L1.difficulty_factor.to_string()));
         assert!(body.contains(&L2.difficulty_factor.to_string()));
         assert!(body.contains(&L2.visitor_threshold.to_string()));
+        assert!(body.contains("Token Replays"));
+        assert!(body.contains("Webhook Deliveries"));
+        assert!(body.contains("Allowed Domains"));
+        assert!(body.contains("Recommended Levels"));
+        assert!(body.contains("Last week"));
+        assert!(body.contains(*crate::EMBED_JS));
+
+        // stats of a range
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{url}?from=0&limit=10"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("{url}?from=10&to=5"))
+                .cookie(cookies.clone())
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 }
@@ -10,6 +10,7 @@ use sailfish::TemplateOnce;
 use crate::api::v1::stats::{percentile_bench_runner, PercentileReq, PercentileResp};
 use crate::errors::PageResult;
 use crate::pages::auth::sudo::SudoPage;
+use crate::security_headers::CspNonce;
 use crate::AppData;
 
 pub mod routes {
@@ -44,17 +45,19 @@ pub struct PercentilePage {
     time: Option<u32>,
     percentile: Option<f64>,
     difficulty_factor: Option<u32>,
+    csp_nonce: String,
 }
 
 #[my_codegen::get(
     path = "crate::PAGES.panel.utils.percentile",
     wrap = "crate::pages::get_middleware()"
 )]
-async fn get_percentile(id: Identity) -> PageResult<impl Responder> {
+async fn get_percentile(id: Identity, nonce: CspNonce) -> PageResult<impl Responder> {
     let data = PercentilePage {
         time: None,
         percentile: None,
         difficulty_factor: None,
+        csp_nonce: nonce.0,
     };
 
     let body = data.render_once().unwrap();
@@ -71,12 +74,14 @@ async fn post_percentile(
     data: AppData,
     id: Identity,
     payload: web::Form<PercentileReq>,
+    nonce: CspNonce,
 ) -> PageResult<impl Responder> {
     let resp = percentile_bench_runner(&data, &payload).await?;
     let page = PercentilePage {
         time: Some(payload.time),
         percentile: Some(payload.percentile),
         difficulty_factor: resp.difficulty_factor,
+        csp_nonce: nonce.0,
     };
 
     let body = page.render_once().unwrap();
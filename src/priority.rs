@@ -0,0 +1,223 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Scheduling of PoW verifications by sitekey priority
+//!
+//! PoW runners take verifications from their queue in the order they were
+//! queued, so a flood of verifications against one sitekey delays those of
+//! every other sitekey. [PriorityGate] admits one verification per runner into
+//! the [system](crate::data::SystemGroup) at a time. When all runners are busy,
+//! verifications wait at the gate instead of in the queue, and those of high
+//! priority sitekeys are admitted first. Verifications of the same priority
+//! are admitted in the order they arrived.
+//!
+//! Administrators mark sitekeys as high priority, since priority is taken from
+//! the capacity shared by all sitekeys of the instance.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot::{channel, Receiver, Sender};
+
+#[derive(Default)]
+struct GateState {
+    /// verifications that can be admitted at once
+    limit: usize,
+    /// verifications admitted
+    running: usize,
+    /// verifications of high priority sitekeys waiting to be admitted
+    high: VecDeque<Sender<()>>,
+    /// other verifications waiting to be admitted
+    normal: VecDeque<Sender<()>>,
+}
+
+impl GateState {
+    /// hand a slot over to the next waiting verification. Returns false when
+    /// none are waiting
+    fn hand_over(&mut self) -> bool {
+        while let Some(tx) = self.high.pop_front().or_else(|| self.normal.pop_front()) {
+            // waiters that gave up have dropped their receivers
+            if tx.send(()).is_ok() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// admits PoW verifications by priority. See [crate::priority]
+pub struct PriorityGate {
+    state: Mutex<GateState>,
+}
+
+/// slot of an admitted verification, handed over to the next waiting
+/// verification when dropped
+pub struct Permit<'a> {
+    gate: &'a PriorityGate,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.gate.release();
+    }
+}
+
+/// verification waiting at the gate. Slots handed over to verifications that
+/// are dropped while waiting are released
+struct Waiting<'a> {
+    gate: &'a PriorityGate,
+    rx: Option<Receiver<()>>,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.gate.release();
+            }
+        }
+    }
+}
+
+impl PriorityGate {
+    /// gate that admits `limit` verifications at once
+    pub fn new(limit: usize) -> Self {
+        let state = GateState {
+            limit,
+            ..Default::default()
+        };
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// wait until the verification can be admitted
+    pub async fn acquire(&self, high_priority: bool) -> Permit<'_> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < state.limit {
+                state.running += 1;
+                return Permit { gate: self };
+            }
+            let (tx, rx) = channel();
+            if high_priority {
+                state.high.push_back(tx);
+            } else {
+                state.normal.push_back(tx);
+            }
+            rx
+        };
+
+        let mut waiting = Waiting {
+            gate: self,
+            rx: Some(rx),
+        };
+        // senders are only dropped once the gate is
+        let _ = waiting.rx.as_mut().unwrap().await;
+        waiting.rx = None;
+        Permit { gate: self }
+    }
+
+    /// are all slots taken, so that verifications have to wait
+    pub fn contended(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.running >= state.limit
+    }
+
+    /// verifications of high priority sitekeys and others waiting to be
+    /// admitted
+    pub fn waiting(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.high.len(), state.normal.len())
+    }
+
+    /// change the number of verifications that can be admitted at once.
+    /// Verifications admitted beyond a lowered limit finish as usual
+    pub fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit;
+        while state.running < state.limit && state.hand_over() {
+            state.running += 1;
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.running > state.limit || !state.hand_over() {
+            state.running -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[actix_rt::test]
+    async fn high_priority_verifications_are_admitted_first() {
+        let gate = Arc::new(PriorityGate::new(1));
+        let permit = gate.acquire(false).await;
+        assert!(gate.contended());
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut handles = Vec::new();
+        for (name, high_priority) in
+            [("normal-1", false), ("high", true), ("normal-2", false)]
+        {
+            let gate = gate.clone();
+            let order = order.clone();
+            handles.push(actix_rt::spawn(async move {
+                let _permit = gate.acquire(high_priority).await;
+                order.lock().unwrap().push(name);
+            }));
+            // queued in the order they are spawned
+            loop {
+                let (high, normal) = gate.waiting();
+                if high + normal == handles.len() {
+                    break;
+                }
+                actix_rt::task::yield_now().await;
+            }
+        }
+        assert_eq!(gate.waiting(), (1, 2));
+
+        drop(permit);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["high", "normal-1", "normal-2"]);
+        assert!(!gate.contended());
+    }
+
+    #[actix_rt::test]
+    async fn slots_are_kept_across_limits_and_cancellations() {
+        let gate = PriorityGate::new(1);
+        let permit = gate.acquire(false).await;
+
+        // verifications that give up while waiting don't take the slot
+        assert!(gate.acquire(true).now_or_never().is_none());
+        drop(permit);
+        assert!(!gate.contended());
+
+        // raising the limit admits waiting verifications
+        let first = gate.acquire(false).await;
+        let mut second = Box::pin(gate.acquire(false));
+        assert!((&mut second).now_or_never().is_none());
+        gate.set_limit(2);
+        let second = second.await;
+        assert!(gate.contended());
+
+        // verifications beyond a lowered limit give their slots up
+        gate.set_limit(1);
+        drop(first);
+        assert!(gate.contended());
+        drop(second);
+        assert!(!gate.contended());
+    }
+}
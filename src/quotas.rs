@@ -0,0 +1,150 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Per-user quotas
+//!
+//! Instance-wide quotas are set in the `quotas` section of the configuration
+//! and administrators can override them for individual users. A quota of 0 is
+//! disabled.
+//!
+//! Users that reach their captcha quota can't create captchas, and imports are
+//! rejected as a whole when the quota can't hold all of their sitekeys. Analytics
+//! records of captchas that reach their quota are no longer recorded, but
+//! verification is unaffected.
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+use crate::Data;
+
+/// effective quotas of a user
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Quota {
+    pub max_captchas: usize,
+    pub max_analytics_records: usize,
+}
+
+/// usage of a quota
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Usage {
+    pub used: usize,
+    /// 0 when the quota is disabled
+    pub limit: usize,
+}
+
+impl Usage {
+    pub fn reached(&self) -> bool {
+        self.limit > 0 && self.used >= self.limit
+    }
+
+    /// the quota can hold `new` more
+    pub fn fits(&self, new: usize) -> bool {
+        self.limit == 0 || self.used + new <= self.limit
+    }
+}
+
+/// get quotas of a user, with overrides applied over instance-wide quotas
+pub async fn get(data: &Data, username: &str) -> ServiceResult<Quota> {
+    let overrides = data.db.get_user_quota(username).await?;
+    let quotas = &data.settings.quotas;
+    Ok(Quota {
+        max_captchas: overrides
+            .max_captchas
+            .map_or(quotas.max_captchas, |q| q as usize),
+        max_analytics_records: overrides
+            .max_analytics_records
+            .map_or(quotas.max_analytics_records, |q| q as usize),
+    })
+}
+
+/// usage of the captcha quota of a user
+pub async fn captchas(data: &Data, username: &str) -> ServiceResult<Usage> {
+    let limit = get(data, username).await?.max_captchas;
+    let used = data.db.get_all_user_captchas(username).await?.len();
+    Ok(Usage { used, limit })
+}
+
+/// usage of the analytics quota of a captcha
+pub async fn analytics(data: &Data, key: &str) -> ServiceResult<Usage> {
+    let owner = data.db.get_captcha_owner(key).await?;
+    let limit = get(data, &owner).await?.max_analytics_records;
+    let used = if limit > 0 {
+        data.db.analytics_count(key).await?
+    } else {
+        0
+    };
+    Ok(Usage { used, limit })
+}
+
+/// how long [AnalyticsQuotas] trusts whether a captcha records analytics
+const ANALYTICS_QUOTA_TTL: Duration = Duration::from_secs(60);
+
+/// Whether captchas record analytics, cached so that verification doesn't
+/// count analytics records and look up quotas on every solve. A captcha can
+/// record a few records beyond its quota before the cache expires
+#[derive(Debug, Default)]
+pub struct AnalyticsQuotas {
+    records: RwLock<HashMap<String, (Instant, bool)>>,
+}
+
+impl AnalyticsQuotas {
+    /// whether analytics of captcha `key` are recorded: its analytics quota
+    /// isn't reached and it isn't a demo sitekey
+    pub async fn records(&self, data: &Data, key: &str) -> ServiceResult<bool> {
+        if let Some((cached_at, records)) = self.records.read().unwrap().get(key) {
+            if cached_at.elapsed() < ANALYTICS_QUOTA_TTL {
+                return Ok(*records);
+            }
+        }
+        let records = !analytics(data, key).await?.reached()
+            && !crate::demo::is_demo_sitekey(data, key).await?;
+        let mut w = self.records.write().unwrap();
+        w.insert(key.to_string(), (Instant::now(), records));
+        Ok(records)
+    }
+
+    /// forget whether captcha records analytics, after its records were
+    /// deleted
+    pub fn invalidate(&self, key: &str) {
+        self.records.write().unwrap().remove(key);
+    }
+}
+
+/// reject users whose captcha quota can't hold `new` more captchas
+pub async fn check_captcha_quota(
+    data: &Data,
+    username: &str,
+    new: usize,
+) -> ServiceResult<()> {
+    if !captchas(data, username).await?.fits(new) {
+        return Err(ServiceError::CaptchaQuotaReached);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_works() {
+        let mut usage = Usage { used: 5, limit: 0 };
+        assert!(!usage.reached());
+        usage.limit = 6;
+        assert!(!usage.reached());
+        usage.limit = 5;
+        assert!(usage.reached());
+
+        assert!(!usage.fits(1));
+        usage.limit = 7;
+        assert!(usage.fits(2));
+        assert!(!usage.fits(3));
+        usage.limit = 0;
+        assert!(usage.fits(3));
+    }
+}
@@ -0,0 +1,519 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Rate limiting
+//!
+//! Requests are counted per IP address and per signed-in account over windows
+//! of `rate_limit.window` seconds. Every request counts towards the global
+//! limits and towards the limits of its [class](RouteClass) of routes, if it
+//! has one. Requests of the shared demo account also count towards the limits
+//! of [RouteClass::Demo], so that demo visitors can be held to tighter limits
+//! than other accounts. Requests over a limit are rejected with
+//! [ServiceError::RateLimited], and responses carry the `RateLimit-Limit`,
+//! `RateLimit-Remaining` and `RateLimit-Reset` headers of the limit that is
+//! closest to being reached.
+//!
+//! Counters are kept in Redis when it's configured, so that instances sharing
+//! it share limits, and in memory otherwise or while Redis can't be reached.
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use actix_identity::RequestIdentity;
+use actix_web::body::EitherBody;
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{Error, ResponseError};
+use futures::future::LocalBoxFuture;
+use redis::aio::MultiplexedConnection;
+use sqlx::types::time::OffsetDateTime;
+
+use crate::errors::*;
+use crate::settings::{RateLimit, RouteLimit, Settings};
+use crate::AppData;
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName =
+    HeaderName::from_static("ratelimit-remaining");
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// prefix of the keys of counters in Redis
+const REDIS_PREFIX: &str = "mcaptcha:rate_limit";
+
+/// paths of sign-in, sign-up and single sign-on routes
+const AUTH_PATHS: [&str; 3] = ["/api/v1/signin", "/api/v1/signup", "/api/v1/auth"];
+const ACCOUNT_PATH: &str = "/api/v1/account";
+const NOTIFICATIONS_PATH: &str = "/api/v1/notifications";
+const POW_PATH: &str = "/api/v1/pow";
+const TRIAL_PATH: &str = "/api/v1/trial";
+
+fn is_under(path: &str, prefix: &str) -> bool {
+    path == prefix || path.starts_with(&format!("{prefix}/"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Routes that share limits
+pub enum RouteClass {
+    /// all routes
+    Global,
+    /// sign-in, sign-up and single sign-on
+    Auth,
+    /// requests that change accounts
+    Account,
+    Notifications,
+    Pow,
+    /// trial sitekey creation, which is available without an account
+    Trial,
+    /// all requests of the demo account
+    Demo,
+}
+
+impl RouteClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Global => "global",
+            Self::Auth => "auth",
+            Self::Account => "account",
+            Self::Notifications => "notifications",
+            Self::Pow => "pow",
+            Self::Trial => "trial",
+            Self::Demo => "demo",
+        }
+    }
+
+    /// class of a request, other than [RouteClass::Global] and
+    /// [RouteClass::Demo]
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        if AUTH_PATHS.iter().any(|p| is_under(path, p)) {
+            Some(Self::Auth)
+        } else if is_under(path, ACCOUNT_PATH) {
+            if method == Method::GET || method == Method::HEAD {
+                None
+            } else {
+                Some(Self::Account)
+            }
+        } else if is_under(path, NOTIFICATIONS_PATH) {
+            Some(Self::Notifications)
+        } else if is_under(path, POW_PATH) {
+            Some(Self::Pow)
+        } else if is_under(path, TRIAL_PATH) {
+            Some(Self::Trial)
+        } else {
+            None
+        }
+    }
+
+    fn limit(&self, s: &RateLimit) -> RouteLimit {
+        match self {
+            Self::Global => s.global,
+            Self::Auth => s.auth,
+            Self::Account => s.account,
+            Self::Notifications => s.notifications,
+            Self::Pow => s.pow,
+            Self::Trial => s.trial,
+            Self::Demo => s.demo,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// state of a limit after a request was counted
+pub struct Quota {
+    /// requests allowed per window
+    pub limit: u32,
+    /// requests left in the current window
+    pub remaining: u32,
+    /// seconds until the current window ends
+    pub reset: u32,
+    /// the request is over the limit
+    pub exceeded: bool,
+}
+
+impl Quota {
+    fn new(limit: u32, count: u32, reset: u32) -> Self {
+        Self {
+            limit,
+            remaining: limit.saturating_sub(count),
+            reset,
+            exceeded: count > limit,
+        }
+    }
+
+    fn is_tighter_than(&self, other: &Self) -> bool {
+        (self.exceeded && !other.exceeded)
+            || (self.exceeded == other.exceeded && self.remaining < other.remaining)
+    }
+
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            (RATELIMIT_LIMIT, self.limit),
+            (RATELIMIT_REMAINING, self.remaining),
+            (RATELIMIT_RESET, self.reset),
+        ] {
+            headers.insert(name, HeaderValue::from(value));
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    /// index of the window that is being counted
+    window: i64,
+    /// requests counted in the window, by counter key
+    counts: HashMap<String, u32>,
+}
+
+#[derive(Default)]
+pub struct RateLimits {
+    /// counters, when they aren't kept in Redis
+    counts: Mutex<Counts>,
+    /// Redis client, when Redis is configured
+    redis: Option<redis::Client>,
+    /// connection to Redis, once it's established
+    con: Mutex<Option<MultiplexedConnection>>,
+}
+
+impl RateLimits {
+    pub fn new(s: &Settings) -> Self {
+        let redis = s.redis.as_ref().and_then(|r| {
+            redis::Client::open(r.connection_url())
+                .map_err(|e| log::error!("Unable to keep rate limits in Redis: {e}"))
+                .ok()
+        });
+        Self {
+            redis,
+            ..Default::default()
+        }
+    }
+
+    fn count_in_memory(&self, key: &str, window: i64) -> u32 {
+        let mut c = self.counts.lock().unwrap();
+        if c.window != window {
+            c.window = window;
+            c.counts.clear();
+        }
+        let count = c.counts.entry(key.to_owned()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    async fn count_in_redis(
+        &self,
+        client: &redis::Client,
+        key: &str,
+        ttl: u64,
+    ) -> redis::RedisResult<u32> {
+        let con = self.con.lock().unwrap().clone();
+        let mut con = match con {
+            Some(con) => con,
+            None => {
+                let con = client.get_multiplexed_tokio_connection().await?;
+                *self.con.lock().unwrap() = Some(con.clone());
+                con
+            }
+        };
+        let res: redis::RedisResult<(u32,)> = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, ttl as usize)
+            .ignore()
+            .query_async(&mut con)
+            .await;
+        if res.is_err() {
+            *self.con.lock().unwrap() = None;
+        }
+        Ok(res?.0)
+    }
+
+    /// count a request towards `key` at `now`, and return the number of
+    /// requests counted in the current window
+    async fn count(&self, key: &str, now: i64, window_len: u64) -> u32 {
+        let window = now / window_len as i64;
+        if let Some(client) = self.redis.as_ref() {
+            let key = format!("{REDIS_PREFIX}:{key}:{window}");
+            match self.count_in_redis(client, &key, window_len).await {
+                Ok(count) => return count,
+                Err(e) => log::error!("Unable to count request in Redis: {e}"),
+            }
+        }
+        self.count_in_memory(key, window)
+    }
+
+    /// count a request of `class` from `ip`, and of `username` when signed in,
+    /// at `now`. Requests of the demo account, when `demo` is set, also count
+    /// towards [RouteClass::Demo]. Returns the limit that is closest to being
+    /// reached, if any applies
+    pub async fn consume(
+        &self,
+        s: &RateLimit,
+        class: Option<RouteClass>,
+        ip: &str,
+        username: Option<&str>,
+        demo: bool,
+        now: i64,
+    ) -> Option<Quota> {
+        let reset = (s.window - now as u64 % s.window) as u32;
+        let mut tightest: Option<Quota> = None;
+        let classes = std::iter::once(RouteClass::Global)
+            .chain(class)
+            .chain(demo.then_some(RouteClass::Demo));
+        for class in classes {
+            let limit = class.limit(s);
+            for (limit, kind, id) in [
+                (limit.per_ip, "ip", Some(ip)),
+                (limit.per_account, "account", username),
+            ] {
+                let id = match id {
+                    Some(id) if limit > 0 => id,
+                    _ => continue,
+                };
+                let key = format!("{}:{kind}:{id}", class.name());
+                let count = self.count(&key, now, s.window).await;
+                let quota = Quota::new(limit, count, reset);
+                if tightest.map_or(true, |t| quota.is_tighter_than(&t)) {
+                    tightest = Some(quota);
+                }
+            }
+        }
+        tightest
+    }
+}
+
+/// Middleware that enforces rate limits, when they are enabled
+///
+/// Must be registered before the identity middleware so that it runs after it.
+pub struct RateLimiter;
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let quota = match req.app_data::<AppData>().cloned() {
+                Some(data) if data.settings.rate_limit.enabled => {
+                    let class = RouteClass::of(req.method(), req.path());
+                    let ip = data.trusted_proxies.client_ip(req.request());
+                    let username = req.get_identity();
+                    let demo = username
+                        .as_deref()
+                        .map_or(false, |u| crate::demo::is_demo_user(&data, u));
+                    let now = OffsetDateTime::now_utc().unix_timestamp();
+                    data.rate_limits
+                        .consume(
+                            &data.settings.rate_limit,
+                            class,
+                            &ip,
+                            username.as_deref(),
+                            demo,
+                            now,
+                        )
+                        .await
+                }
+                _ => None,
+            };
+            let mut res = match quota {
+                Some(quota) if quota.exceeded => {
+                    let resp = ServiceError::RateLimited(quota.reset).error_response();
+                    req.into_response(resp).map_into_right_body()
+                }
+                _ => service.call(req).await?.map_into_left_body(),
+            };
+            if let Some(quota) = quota {
+                quota.insert_headers(res.headers_mut());
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+
+    use super::*;
+    use crate::api::v1::auth::runners::Login;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn route_classes_work() {
+        let of = RouteClass::of;
+        assert_eq!(
+            of(&Method::POST, V1_API_ROUTES.auth.login),
+            Some(RouteClass::Auth)
+        );
+        assert_eq!(
+            of(&Method::POST, V1_API_ROUTES.auth.login_challenge),
+            Some(RouteClass::Auth)
+        );
+        assert_eq!(
+            of(&Method::POST, V1_API_ROUTES.account.update_username),
+            Some(RouteClass::Account)
+        );
+        assert_eq!(of(&Method::GET, V1_API_ROUTES.account.get_secret), None);
+        assert_eq!(
+            of(&Method::POST, V1_API_ROUTES.pow.verify_pow),
+            Some(RouteClass::Pow)
+        );
+        assert_eq!(
+            of(&Method::POST, V1_API_ROUTES.trial.create),
+            Some(RouteClass::Trial)
+        );
+        assert_eq!(of(&Method::GET, "/api/v1/signinx"), None);
+        assert_eq!(of(&Method::GET, PAGES.home), None);
+    }
+
+    #[actix_rt::test]
+    async fn consume_works() {
+        let s = RateLimit {
+            enabled: true,
+            window: 60,
+            global: RouteLimit {
+                per_ip: 3,
+                per_account: 0,
+            },
+            auth: RouteLimit {
+                per_ip: 0,
+                per_account: 1,
+            },
+            account: RouteLimit::default(),
+            notifications: RouteLimit::default(),
+            pow: RouteLimit::default(),
+            trial: RouteLimit::default(),
+            demo: RouteLimit {
+                per_ip: 0,
+                per_account: 2,
+            },
+        };
+        let limits = RateLimits::default();
+        let now = 125;
+
+        let quota = limits
+            .consume(&s, None, "1.1.1.1", None, false, now)
+            .await
+            .unwrap();
+        assert_eq!(quota, Quota::new(3, 1, 55));
+
+        // account limit of auth routes is tighter
+        let auth = Some(RouteClass::Auth);
+        let quota = limits
+            .consume(&s, auth, "1.1.1.1", Some("user"), false, now)
+            .await;
+        assert_eq!(quota, Some(Quota::new(1, 1, 55)));
+        let quota = limits
+            .consume(&s, auth, "1.1.1.1", Some("user"), false, now)
+            .await;
+        assert!(quota.unwrap().exceeded);
+
+        // limits are per IP address
+        let quota = limits.consume(&s, None, "1.1.1.1", None, false, now).await;
+        assert!(quota.unwrap().exceeded);
+        let quota = limits.consume(&s, None, "2.2.2.2", None, false, now).await;
+        assert!(!quota.unwrap().exceeded);
+
+        // demo visitors share the limits of the demo account
+        let demo =
+            |ip: &'static str| limits.consume(&s, None, ip, Some("demo"), true, now);
+        assert_eq!(demo("3.3.3.3").await, Some(Quota::new(2, 1, 55)));
+        assert!(!demo("4.4.4.4").await.unwrap().exceeded);
+        assert!(demo("5.5.5.5").await.unwrap().exceeded);
+        let quota = limits
+            .consume(&s, None, "6.6.6.6", Some("demo"), false, now)
+            .await;
+        assert!(!quota.unwrap().exceeded);
+
+        // counts are reset with each window
+        let quota = limits.consume(&s, None, "1.1.1.1", None, false, 180).await;
+        assert_eq!(quota, Some(Quota::new(3, 1, 60)));
+    }
+
+    #[actix_rt::test]
+    async fn rate_limits_work_pg() {
+        let data = pg::get_data().await;
+        rate_limits_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn rate_limits_work_maria() {
+        let data = maria::get_data().await;
+        rate_limits_work(data).await;
+    }
+
+    async fn rate_limits_work(data: ArcData) {
+        const NAME: &str = "ratelimituser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "ratelimituser@a.com";
+
+        delete_user(&data, NAME).await;
+        register(&data, NAME, EMAIL, PASSWORD).await;
+
+        let mut settings = data.settings.clone();
+        // count in memory, so that runs don't share counters
+        settings.redis = None;
+        settings.rate_limit.enabled = true;
+        settings.rate_limit.auth.per_ip = 2;
+        let data = crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app = get_app!(data).await;
+
+        let signin = || {
+            let creds = Login {
+                login: NAME.into(),
+                password: "wrongpassword".into(),
+                totp: None,
+                challenge: None,
+            };
+            post_request!(&creds, V1_API_ROUTES.auth.login).to_request()
+        };
+        for remaining in [1, 0] {
+            let resp = test::call_service(&app, signin()).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                resp.headers().get(RATELIMIT_REMAINING).unwrap(),
+                &remaining.to_string()
+            );
+        }
+        let resp = test::call_service(&app, signin()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key(header::RETRY_AFTER));
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.code, "rate_limited");
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Level recommendations from observed solve times
+//!
+//! The performance analytics of a sitekey tell how much difficulty its
+//! visitors solve per millisecond. Taking the 90th percentile solve time of
+//! each difficulty factor, the recommended difficulty of a level is the one
+//! that 90% of visitors solve within a target time. Target times rise
+//! linearly from `avg_traffic_time` for the lowest level to
+//! `broke_my_site_traffic_time` for the highest, as set in
+//! `captcha.default_difficulty_strategy`. Visitor thresholds are kept.
+//!
+//! Owners can opt in to have recommendations applied by [ApplyRecommendations]
+//! once a day. Recommendations that are within [APPLY_TOLERANCE] percent of
+//! the current difficulty aren't applied, so that levels don't churn.
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::errors::DBError;
+use db_core::{Level, SolveTimePercentiles};
+use libmcaptcha::defense::LevelBuilder;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::api::v1::mcaptcha::update::{runner::update_captcha, UpdateCaptcha};
+use crate::jobs::RECOMMENDATION_JOB;
+use crate::settings::DefaultDifficultyStrategy;
+use crate::*;
+
+use errors::*;
+
+/// fewest analyzed solves that levels are recommended from
+pub const MIN_SAMPLES: u64 = 50;
+/// percentage by which a recommended difficulty has to differ from the
+/// current one to be applied automatically
+pub const APPLY_TOLERANCE: u32 = 10;
+/// interval, in seconds, at which recommendations are applied automatically
+const APPLY_INTERVAL: u32 = 60 * 60 * 24;
+/// target solve times, in seconds, when the default difficulty strategy
+/// doesn't set them
+const DEFAULT_TARGET_TIMES: (u32, u32) = (1, 5);
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+/// recommendation for a level of a sitekey
+pub struct LevelRecommendation {
+    pub visitor_threshold: u32,
+    pub difficulty_factor: u32,
+    pub recommended_difficulty_factor: u32,
+    /// time within which 90% of visitors should solve the level, in
+    /// milliseconds
+    pub target_time: u32,
+    /// observed 90th percentile solve time of the level, in milliseconds
+    pub p90: Option<u32>,
+    /// highest nonce that solved the level so far
+    pub max_nonce: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+/// recommended levels of a sitekey
+pub struct Recommendation {
+    /// number of solves analyzed
+    pub samples: u64,
+    /// difficulty that 90% of visitors solve per millisecond; not set when
+    /// fewer than [MIN_SAMPLES] solves were analyzed
+    pub solve_rate: Option<f64>,
+    pub levels: Vec<LevelRecommendation>,
+    /// recommendations are applied automatically
+    pub auto_apply: bool,
+}
+
+impl Recommendation {
+    /// recommended levels, if enough solves were analyzed
+    pub fn recommended_levels(&self) -> Option<Vec<Level>> {
+        self.solve_rate?;
+        self.levels
+            .iter()
+            .map(|l| {
+                LevelBuilder::default()
+                    .visitor_threshold(l.visitor_threshold)
+                    .difficulty_factor(l.recommended_difficulty_factor)
+                    .ok()?
+                    .build()
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// does a recommended difficulty differ from the current one by more
+    /// than [APPLY_TOLERANCE]
+    pub fn is_significant(&self) -> bool {
+        self.solve_rate.is_some()
+            && self.levels.iter().any(|l| {
+                let diff = l
+                    .recommended_difficulty_factor
+                    .abs_diff(l.difficulty_factor) as u64;
+                diff * 100 > l.difficulty_factor as u64 * APPLY_TOLERANCE as u64
+            })
+    }
+}
+
+/// difficulty that 90% of visitors solve per millisecond, averaged over
+/// difficulty factors and worker types by their number of samples
+pub fn solve_rate(percentiles: &[SolveTimePercentiles]) -> Option<f64> {
+    let samples: u64 = percentiles.iter().map(|p| p.samples).sum();
+    if samples < MIN_SAMPLES {
+        return None;
+    }
+    let weighted: f64 = percentiles
+        .iter()
+        .map(|p| p.samples as f64 * p.difficulty_factor as f64 / p.p90.max(1) as f64)
+        .sum();
+    Some(weighted / samples as f64)
+}
+
+/// target solve times of `levels` levels, in milliseconds, lowest level first
+pub fn target_times(strategy: &DefaultDifficultyStrategy, levels: usize) -> Vec<u32> {
+    let low = strategy.avg_traffic_time.unwrap_or(DEFAULT_TARGET_TIMES.0) as f64;
+    let high = strategy
+        .broke_my_site_traffic_time
+        .unwrap_or(DEFAULT_TARGET_TIMES.1) as f64;
+    (0..levels)
+        .map(|i| {
+            let share = if levels > 1 {
+                i as f64 / (levels - 1) as f64
+            } else {
+                0.0
+            };
+            ((low + (high - low) * share) * 1000.0) as u32
+        })
+        .collect()
+}
+
+/// difficulty factors that are solved within `targets` at `rate`. Difficulty
+/// factors increase with the levels
+pub fn recommend_difficulties(rate: f64, targets: &[u32]) -> Vec<u32> {
+    let mut last = 0;
+    targets
+        .iter()
+        .map(|target| {
+            let difficulty = ((rate * *target as f64).round() as u32).max(last + 1);
+            last = difficulty;
+            difficulty
+        })
+        .collect()
+}
+
+/// 90th percentile solve time of `difficulty_factor`, from the worker type
+/// with the most samples
+fn p90(percentiles: &[SolveTimePercentiles], difficulty_factor: u32) -> Option<u32> {
+    percentiles
+        .iter()
+        .filter(|p| p.difficulty_factor == difficulty_factor)
+        .max_by_key(|p| p.samples)
+        .map(|p| p.p90)
+}
+
+/// recommend levels of a sitekey from its performance analytics
+pub async fn recommend(
+    data: &AppData,
+    username: &str,
+    key: &str,
+) -> ServiceResult<Recommendation> {
+    let levels = data.db.get_captcha_levels(Some(username), key).await?;
+    let percentiles = data.db.analytics_fetch_percentiles(username, key).await?;
+    let auto_apply = data.db.captcha_auto_recommendation(key).await?;
+
+    let rate = solve_rate(&percentiles);
+    let targets = target_times(
+        &data.settings.captcha.default_difficulty_strategy,
+        levels.len(),
+    );
+    let recommended = match rate {
+        Some(rate) => recommend_difficulties(rate, &targets),
+        None => levels.iter().map(|l| l.difficulty_factor).collect(),
+    };
+
+    let mut recommendations = Vec::with_capacity(levels.len());
+    for ((level, target_time), recommended) in
+        levels.iter().zip(targets).zip(recommended)
+    {
+        let max_nonce = data
+            .db
+            .get_max_nonce_for_level(key, level.difficulty_factor)
+            .await?;
+        recommendations.push(LevelRecommendation {
+            visitor_threshold: level.visitor_threshold,
+            difficulty_factor: level.difficulty_factor,
+            recommended_difficulty_factor: recommended,
+            target_time,
+            p90: p90(&percentiles, level.difficulty_factor),
+            max_nonce,
+        });
+    }
+
+    Ok(Recommendation {
+        samples: percentiles.iter().map(|p| p.samples).sum(),
+        solve_rate: rate,
+        levels: recommendations,
+        auto_apply,
+    })
+}
+
+/// fail if levels of a sitekey are computed from a traffic pattern, so that
+/// recommendations wouldn't stick
+pub async fn check_not_easy_mode(
+    data: &AppData,
+    username: &str,
+    key: &str,
+) -> ServiceResult<()> {
+    match data.db.get_traffic_pattern(username, key).await {
+        Ok(_) => Err(ServiceError::EasyModeLevels),
+        Err(DBError::TrafficPatternNotFound) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// replace the levels of a sitekey with the recommended ones
+pub async fn apply(
+    data: &AppData,
+    username: &str,
+    key: &str,
+    recommendation: &Recommendation,
+) -> ServiceResult<()> {
+    let levels = recommendation
+        .recommended_levels()
+        .ok_or(ServiceError::NotEnoughAnalytics)?;
+    let config = data.db.get_captcha_config(username, key).await?;
+    let payload = UpdateCaptcha {
+        levels,
+        duration: config.duration as u32,
+        description: config.description,
+        key: key.into(),
+        publish_benchmarks: data.db.analytics_captcha_is_published(key).await?,
+    };
+    update_captcha(&payload, data, username).await
+}
+
+pub struct ApplyRecommendations {
+    tx: Sender<()>,
+}
+
+impl ApplyRecommendations {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// apply significant recommendations to the sitekeys that opted in and
+    /// return the number of sitekeys that were updated
+    pub async fn apply_all(data: &AppData) -> ServiceResult<usize> {
+        let mut applied = 0;
+        for c in data.db.get_captchas_with_auto_recommendation().await? {
+            let res = match check_not_easy_mode(data, &c.owner, &c.key).await {
+                Ok(_) => recommend(data, &c.owner, &c.key).await,
+                Err(e) => Err(e),
+            };
+            let recommendation = match res {
+                Ok(r) if r.is_significant() => r,
+                Ok(_) => continue,
+                Err(e) => {
+                    log::error!("Unable to recommend levels of sitekey {}: {e}", c.key);
+                    continue;
+                }
+            };
+            match apply(data, &c.owner, &c.key, &recommendation).await {
+                Ok(_) => applied += 1,
+                Err(e) => {
+                    log::error!(
+                        "Unable to apply recommended levels to sitekey {}: {e}",
+                        c.key
+                    )
+                }
+            }
+        }
+        if applied > 0 {
+            log::info!("Applied recommended levels to {applied} sitekeys");
+        }
+        Ok(applied)
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs
+            .register(RECOMMENDATION_JOB, APPLY_INTERVAL as u64);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                if exit {
+                    break;
+                }
+                for _ in 0..APPLY_INTERVAL {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+                if exit {
+                    break;
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::apply_all(&data).await.map(|_| ());
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while applying recommended levels: {:?}", err);
+                }
+                data.jobs
+                    .finished(RECOMMENDATION_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percentiles(
+        difficulty_factor: u32,
+        samples: u64,
+        p90: u32,
+    ) -> SolveTimePercentiles {
+        SolveTimePercentiles {
+            difficulty_factor,
+            worker_type: "wasm".into(),
+            samples,
+            p50: p90 / 2,
+            p90,
+            p99: p90 * 2,
+        }
+    }
+
+    #[test]
+    fn solve_rate_works() {
+        assert_eq!(solve_rate(&[percentiles(1000, MIN_SAMPLES - 1, 10)]), None);
+        assert_eq!(
+            solve_rate(&[percentiles(1000, 30, 10), percentiles(4000, 30, 20)]),
+            Some(150.0)
+        );
+    }
+
+    #[test]
+    fn recommendations_work() {
+        let strategy = DefaultDifficultyStrategy {
+            avg_traffic_difficulty: 50000,
+            avg_traffic_time: Some(1),
+            peak_sustainable_traffic_difficulty: 3000000,
+            peak_sustainable_traffic_time: Some(3),
+            broke_my_site_traffic_difficulty: 5000000,
+            broke_my_site_traffic_time: Some(5),
+            duration: 30,
+        };
+        assert_eq!(target_times(&strategy, 3), vec![1000, 3000, 5000]);
+        assert_eq!(target_times(&strategy, 1), vec![1000]);
+        assert_eq!(
+            recommend_difficulties(100.0, &[1000, 3000, 5000]),
+            vec![100000, 300000, 500000]
+        );
+        // difficulty factors increase even when the rate is tiny
+        assert_eq!(recommend_difficulties(0.0001, &[1000, 3000]), vec![1, 2]);
+
+        let level =
+            |difficulty_factor, recommended_difficulty_factor| LevelRecommendation {
+                visitor_threshold: 10,
+                difficulty_factor,
+                recommended_difficulty_factor,
+                ..Default::default()
+            };
+        let mut r = Recommendation {
+            samples: MIN_SAMPLES,
+            solve_rate: Some(100.0),
+            levels: vec![level(1000, 1050)],
+            auto_apply: true,
+        };
+        assert!(!r.is_significant());
+        r.levels.push(level(1000, 1200));
+        assert!(r.is_significant());
+        assert_eq!(r.recommended_levels().unwrap()[1].difficulty_factor, 1200);
+        r.solve_rate = None;
+        assert!(!r.is_significant());
+        assert!(r.recommended_levels().is_none());
+    }
+}
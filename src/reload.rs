@@ -0,0 +1,83 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Reloading of settings that can change without a restart
+//!
+//! On `SIGHUP`, the configuration is read again and `captcha.runners` and
+//! `captcha.queue_length` are applied to [PoW verification](crate::data::PoWTuning),
+//! overriding tuning set through the admin API. Other settings need a restart.
+use std::time::Duration;
+
+use actix::spawn;
+use actix_rt::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use crate::data::PoWTuning;
+use crate::errors::*;
+use crate::settings::Settings;
+use crate::AppData;
+
+/// apply settings that can change without a restart
+pub fn apply(data: &AppData, s: &Settings) -> ServiceResult<()> {
+    data.captcha.tune(PoWTuning::new(s))
+}
+
+pub struct ConfigReloader {
+    tx: Sender<()>,
+}
+
+impl ConfigReloader {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let mut hangup = signal(SignalKind::hangup()).map_err(|e| {
+            log::error!("Unable to listen for SIGHUP: {e}");
+            ServiceError::InternalServerError
+        })?;
+        let fut = async move {
+            while Self::can_run(&mut rx) {
+                // woken up every second to check for exit
+                if timeout(Duration::new(1, 0), hangup.recv()).await.is_err() {
+                    continue;
+                }
+                log::info!("Received SIGHUP, reloading configuration");
+                let res = Settings::new()
+                    .map_err(|e| {
+                        log::error!("Unable to read configuration: {e}");
+                        ServiceError::InternalServerError
+                    })
+                    .and_then(|s| apply(&data, &s));
+                if let Err(e) = res {
+                    log::error!("Unable to reload configuration: {e}");
+                }
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
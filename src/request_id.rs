@@ -0,0 +1,184 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Request IDs
+//!
+//! Every request is given an ID, which is logged in the access log and in the
+//! span of the request, sent back in the `X-Request-Id` header and included
+//! in error responses, so that users reporting a failure can give operators
+//! something to grep for. Requests that carry a valid `X-Request-Id`, e.g.
+//! set by a reverse proxy, keep theirs.
+use std::future::{ready, Ready};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{
+    forward_ready, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+use tracing::Instrument;
+use uuid::Uuid;
+
+/// header that carries request IDs
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+/// maximum length of request IDs received in [REQUEST_ID_HEADER]
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request that is being handled, if any
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// check if request ID received from a client can be used
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Middleware that assigns request IDs. Must wrap all other middleware, so
+/// that the ID is known to all of them
+pub struct RequestIds;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIds
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdsMiddleware { service }))
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let id = req
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .filter(|id| is_valid(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let value = HeaderValue::from_str(&id).unwrap();
+        // for the access log
+        req.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+
+        let http_req = req.request().clone();
+        let span = tracing::info_span!("request", request_id = %id);
+        let fut = REQUEST_ID.sync_scope(id.clone(), || self.service.call(req));
+        let fut = REQUEST_ID.scope(id.clone(), async move {
+            // errors are rendered here, so that their body holds the ID
+            match fut.await {
+                Ok(res) => res.map_into_left_body(),
+                Err(e) => ServiceResponse::from_err(e, http_req).map_into_right_body(),
+            }
+        });
+        Box::pin(async move {
+            let mut res = fut.instrument(span).await;
+            res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::errors::*;
+
+    #[test]
+    fn is_valid_works() {
+        assert!(is_valid("4bf92f3577b34da6a3ce929d0e0e4736"));
+        assert!(is_valid("req-1.2:3_4"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("has space"));
+        assert!(!is_valid(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+
+    #[actix_rt::test]
+    async fn request_ids_work() {
+        async fn fail() -> ServiceResult<HttpResponse> {
+            Err(ServiceError::CaptchaNotFound)
+        }
+        let app = test::init_service(
+            App::new()
+                .wrap(RequestIds)
+                .route("/", web::get().to(|| async { HttpResponse::Ok() }))
+                .route("/fail", web::get().to(fail)),
+        )
+        .await;
+        fn id<B>(resp: &ServiceResponse<B>) -> String {
+            resp.headers()
+                .get(&REQUEST_ID_HEADER)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        }
+
+        // valid IDs are honored
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/")
+                .insert_header((REQUEST_ID_HEADER, "proxyrequestid"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(id(&resp), "proxyrequestid");
+
+        // others are replaced
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/")
+                .insert_header((REQUEST_ID_HEADER, "not valid"))
+                .to_request(),
+        )
+        .await;
+        assert_ne!(id(&resp), "not valid");
+        assert!(is_valid(&id(&resp)));
+
+        // error responses hold the ID
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/fail").to_request())
+                .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let request_id = id(&resp);
+        let err: ErrorToResponse = test::read_body_json(resp).await;
+        assert_eq!(err.request_id, Some(request_id));
+        assert_eq!(current(), None);
+    }
+}
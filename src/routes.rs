@@ -5,6 +5,7 @@
 
 pub fn services(cfg: &mut actix_web::web::ServiceConfig) {
     crate::api::v1::services(cfg);
+    crate::api::v2::services(cfg);
     crate::docs::services(cfg);
     crate::widget::services(cfg);
     crate::pages::services(cfg);
@@ -0,0 +1,270 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Security headers
+//!
+//! Responses are sent with `X-Content-Type-Options: nosniff` and, when HTTPS
+//! is served by mCaptcha or by the reverse proxy, `Strict-Transport-Security`.
+//! Responses other than those of [embeddable paths](crate::settings::Security)
+//! also get the configured `Content-Security-Policy` and `X-Frame-Options`.
+//!
+//! Each request that gets a policy gets its own CSP nonce, which is put in
+//! the request extensions as [CspNonce]. Page handlers extract it and render
+//! it into the `nonce` attribute of the scripts of their templates.
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::dev::{
+    forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::LocalBoxFuture;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::settings::Settings;
+
+/// placeholder of the nonce in the configured policy
+const POLICY_NONCE: &str = "{nonce}";
+/// length of CSP nonces
+const NONCE_LEN: usize = 24;
+
+/// CSP nonce of a request, which pages set on their scripts. Empty when the
+/// response doesn't get a policy
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    pub fn of(req: &HttpRequest) -> Self {
+        req.extensions().get::<Self>().cloned().unwrap_or_default()
+    }
+}
+
+impl FromRequest for CspNonce {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self::of(req)))
+    }
+}
+
+/// headers that are sent, as configured
+struct Policy {
+    content_security_policy: Option<String>,
+    frame_options: Option<HeaderValue>,
+    hsts: Option<HeaderValue>,
+    embeddable_paths: Vec<String>,
+}
+
+impl Policy {
+    fn is_embeddable(&self, path: &str) -> bool {
+        self.embeddable_paths.iter().any(|p| {
+            let p = p.trim_end_matches('/');
+            path == p || path.starts_with(&format!("{p}/"))
+        })
+    }
+}
+
+/// Middleware that sends the security headers of `[server.security]`, when
+/// they are enabled
+pub struct SecurityHeaders(Option<Rc<Policy>>);
+
+impl SecurityHeaders {
+    pub fn new(s: &Settings) -> Self {
+        let security = &s.server.security;
+        if !security.enabled {
+            return Self(None);
+        }
+        let value = |v: &str| (!v.is_empty()).then(|| v.to_owned());
+        let has_tls = s.server.proxy_has_tls || s.server.tls.is_some();
+        let hsts = (has_tls && security.hsts_max_age > 0).then(|| {
+            HeaderValue::from_str(&format!("max-age={}", security.hsts_max_age)).unwrap()
+        });
+        // checked by Settings::check_security
+        let frame_options = value(&security.frame_options).map(|v| {
+            HeaderValue::from_str(&v).expect("server.security.frame_options is checked")
+        });
+        Self(Some(Rc::new(Policy {
+            content_security_policy: value(&security.content_security_policy),
+            frame_options,
+            hsts,
+            embeddable_paths: security.embeddable_paths.clone(),
+        })))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service,
+            policy: self.0.clone(),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    policy: Option<Rc<Policy>>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let policy = self.policy.clone();
+        let embeddable = policy
+            .as_ref()
+            .map_or(false, |p| p.is_embeddable(req.path()));
+        let nonce = match policy.as_ref() {
+            Some(p) if !embeddable && p.content_security_policy.is_some() => {
+                let nonce = get_random(NONCE_LEN);
+                req.extensions_mut().insert(CspNonce(nonce.clone()));
+                Some(nonce)
+            }
+            _ => None,
+        };
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let policy = match policy {
+                Some(policy) => policy,
+                None => return Ok(res),
+            };
+
+            let headers = res.headers_mut();
+            headers.insert(
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            );
+            if let Some(hsts) = policy.hsts.as_ref() {
+                headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+            }
+            if embeddable {
+                return Ok(res);
+            }
+            if let Some(frame_options) = policy.frame_options.as_ref() {
+                headers.insert(header::X_FRAME_OPTIONS, frame_options.clone());
+            }
+            if let (Some(csp), Some(nonce)) =
+                (policy.content_security_policy.as_ref(), nonce)
+            {
+                // checked by Settings::check_security, and nonces are alphanumeric
+                let csp = HeaderValue::from_str(&csp.replace(POLICY_NONCE, &nonce))
+                    .expect("server.security.content_security_policy is checked");
+                headers.insert(header::CONTENT_SECURITY_POLICY, csp);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+    use crate::WIDGET_ROUTES;
+
+    #[actix_rt::test]
+    async fn security_headers_work() {
+        const PAGE: &str = "/page";
+
+        let mut settings = crate::tests::get_settings();
+        settings.server.proxy_has_tls = true;
+        let page = |nonce: CspNonce| async move {
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .body(format!("<script nonce=\"{}\"></script>", nonce.0))
+        };
+        let app = test::init_service(
+            App::new()
+                .wrap(SecurityHeaders::new(&settings))
+                .route(PAGE, web::get().to(page))
+                .route(WIDGET_ROUTES.verification_widget, web::get().to(page)),
+        )
+        .await;
+        let get = |path| test::TestRequest::get().uri(path).to_request();
+
+        let resp = test::call_service(&app, get(PAGE)).await;
+        let headers = resp.headers().clone();
+        assert_eq!(headers.get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert_eq!(
+            headers.get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+            "nosniff"
+        );
+        assert!(headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        let csp = headers
+            .get(header::CONTENT_SECURITY_POLICY)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        let nonce = body
+            .trim_start_matches("<script nonce=\"")
+            .trim_end_matches("\"></script>");
+        assert_eq!(nonce.len(), NONCE_LEN);
+        assert!(csp.contains(&format!("'nonce-{nonce}'")));
+
+        // nonces are unique to each response
+        let resp = test::call_service(&app, get(PAGE)).await;
+        let body = test::read_body(resp).await;
+        assert!(!String::from_utf8_lossy(&body).contains(nonce));
+
+        // pages can't pick their nonce by sending the old placeholder
+        let echo = || async {
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .body("<script nonce=\"mcaptcha-csp-nonce\"></script>")
+        };
+        let echo_app = test::init_service(
+            App::new()
+                .wrap(SecurityHeaders::new(&settings))
+                .route(PAGE, web::get().to(echo)),
+        )
+        .await;
+        let resp = test::call_service(&echo_app, get(PAGE)).await;
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("mcaptcha-csp-nonce"));
+
+        // the widget can be framed
+        let resp =
+            test::call_service(&app, get(WIDGET_ROUTES.verification_widget)).await;
+        assert!(!resp.headers().contains_key(header::X_FRAME_OPTIONS));
+        assert!(!resp.headers().contains_key(header::CONTENT_SECURITY_POLICY));
+        assert!(resp.headers().contains_key(header::X_CONTENT_TYPE_OPTIONS));
+        let body = test::read_body(resp).await;
+        assert_eq!(&body[..], b"<script nonce=\"\"></script>");
+
+        // headers aren't sent when disabled
+        settings.server.security.enabled = false;
+        let app = test::init_service(
+            App::new()
+                .wrap(SecurityHeaders::new(&settings))
+                .route(PAGE, web::get().to(page)),
+        )
+        .await;
+        let resp = test::call_service(&app, get(PAGE)).await;
+        assert!(!resp.headers().contains_key(header::X_CONTENT_TYPE_OPTIONS));
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Server-side sign-in sessions
+//!
+//! The identity cookie holds a session identifier instead of the username.
+//! Sessions are stored in the database along with the IP address and user
+//! agent from which the user signed in, so that users can list their
+//! sessions and revoke them. Requests with a revoked session are treated as
+//! signed out. Requests without a session can authenticate with a scoped API
+//! token instead, see [crate::api_tokens].
+use actix_identity::{CookieIdentityPolicy, IdentityPolicy};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::{Error, HttpMessage, HttpRequest};
+use db_core::errors::DBError;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use sqlx::types::time::OffsetDateTime;
+
+use crate::api::v1::mcaptcha::get_random;
+use crate::errors::*;
+use crate::AppData;
+
+/// seconds after which the last activity time of a session is updated
+const TOUCH_INTERVAL: i64 = 60;
+/// maximum length of user agents stored with sessions
+const USER_AGENT_LEN: usize = 500;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// session of the current request, as received in the identity cookie
+pub struct CurrentSession(pub String);
+
+impl CurrentSession {
+    /// session of the current request, if any
+    pub fn get(req: &HttpRequest) -> Option<String> {
+        req.extensions().get::<Self>().map(|s| s.0.clone())
+    }
+}
+
+/// Identity policy that stores sessions in the database. Wraps
+/// [CookieIdentityPolicy], which stores the session identifier.
+pub struct SessionIdentityPolicy(CookieIdentityPolicy);
+
+impl SessionIdentityPolicy {
+    pub fn new(cookie: CookieIdentityPolicy) -> Self {
+        Self(cookie)
+    }
+}
+
+impl SessionIdentityPolicy {
+    /// identity of requests that authenticate with an API token instead of
+    /// the session cookie
+    fn from_api_token(
+        req: &ServiceRequest,
+    ) -> LocalBoxFuture<'static, Result<Option<String>, Error>> {
+        let (token, data) = match (
+            crate::api_tokens::bearer(req),
+            req.app_data::<AppData>().cloned(),
+        ) {
+            (Some(token), Some(data)) => (token, data),
+            _ => return async { Ok(None) }.boxed_local(),
+        };
+        // PoW endpoints are public; sites that embed the widget may send
+        // their own bearer tokens
+        let path = req.path().to_string();
+        if path.starts_with(crate::V1_API_ROUTES.pow.scope) {
+            return async { Ok(None) }.boxed_local();
+        }
+        let method = req.method().clone();
+        async move {
+            let username =
+                crate::api_tokens::authenticate(&data, &token, &method, &path).await?;
+            Ok(Some(username))
+        }
+        .boxed_local()
+    }
+}
+
+impl IdentityPolicy for SessionIdentityPolicy {
+    type Future = LocalBoxFuture<'static, Result<Option<String>, Error>>;
+    type ResponseFuture = LocalBoxFuture<'static, Result<(), Error>>;
+
+    fn from_request(&self, req: &mut ServiceRequest) -> Self::Future {
+        let session = match self.0.from_request(req).now_or_never() {
+            Some(Ok(Some(session))) => session,
+            Some(Err(e)) => return async { Err(e) }.boxed_local(),
+            _ => return Self::from_api_token(req),
+        };
+        req.extensions_mut().insert(CurrentSession(session.clone()));
+        let data = req.app_data::<AppData>().cloned();
+
+        async move {
+            let data = match data {
+                Some(data) => data,
+                None => return Ok(None),
+            };
+            match data.db.get_session(&session).await {
+                Ok(s) => {
+                    let now = OffsetDateTime::now_utc().unix_timestamp();
+                    if now - s.last_active >= TOUCH_INTERVAL {
+                        data.db
+                            .touch_session(&session)
+                            .await
+                            .map_err(ServiceError::from)?;
+                    }
+                    Ok(Some(s.username))
+                }
+                Err(DBError::SessionNotFound) => Ok(None),
+                Err(e) => Err(ServiceError::from(e).into()),
+            }
+        }
+        .boxed_local()
+    }
+
+    fn to_response<B>(
+        &self,
+        identity: Option<String>,
+        changed: bool,
+        res: &mut ServiceResponse<B>,
+    ) -> Self::ResponseFuture {
+        if !changed {
+            return self.0.to_response(identity, changed, res).boxed_local();
+        }
+
+        let req = res.request().clone();
+        let data = req.app_data::<AppData>().cloned();
+        let current = CurrentSession::get(&req);
+
+        // new sign-in: issue fresh session
+        let new = identity.map(|username| (get_random(32), username));
+        let cookie = new.as_ref().map(|(session, _)| session.clone());
+        let fut = self.0.to_response(cookie, changed, res);
+
+        async move {
+            fut.await?;
+            let data = match data {
+                Some(data) => data,
+                None => return Ok(()),
+            };
+
+            if let Some(current) = current {
+                if let Ok(s) = data.db.get_session(&current).await {
+                    data.db
+                        .delete_session(&s.username, &current)
+                        .await
+                        .map_err(ServiceError::from)?;
+                }
+            }
+
+            if let Some((session, username)) = new {
+                let ip = data.trusted_proxies.client_ip(&req);
+                let user_agent = req
+                    .headers()
+                    .get(header::USER_AGENT)
+                    .and_then(|ua| ua.to_str().ok())
+                    .unwrap_or_default();
+                let user_agent: String =
+                    user_agent.chars().take(USER_AGENT_LEN).collect();
+                let s = db_core::CreateSession {
+                    id: &session,
+                    ip: &ip,
+                    user_agent: &user_agent,
+                };
+                data.db
+                    .create_session(&username, &s)
+                    .await
+                    .map_err(ServiceError::from)?;
+                // sign-ins don't fail when the alert can't be sent
+                if let Err(e) =
+                    crate::email::security::signed_in(&data, &username, &ip, &user_agent)
+                        .await
+                {
+                    log::error!("Unable to check sign-in of {username}: {e}");
+                }
+            }
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
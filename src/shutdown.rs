@@ -0,0 +1,153 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Embedded master state that outlives graceful restarts
+//!
+//! The embedded master keeps visitor counts of sitekeys, which set their
+//! difficulty, in memory. Restarting an instance without Redis would reset
+//! every sitekey to its lowest level, even mid-attack. When
+//! `captcha.state_file` is set, the state of the master is [saved](save) to it
+//! on shutdown, once pending verifications are drained and buffered stats are
+//! flushed, and [restored](restore) from it at boot.
+//!
+//! Sitekeys that were deleted while the instance was down aren't restored.
+//! The file is removed once restored, so that a crash later on doesn't restore
+//! stale state. The Redis master keeps its state itself, so nothing is saved
+//! with Redis.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use libmcaptcha::MCaptcha;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::errors::*;
+use crate::AppData;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+/// state of the embedded master, as saved to `captcha.state_file`
+pub struct Snapshot {
+    /// unix timestamp at which the state was saved
+    pub saved: i64,
+    /// visitor counts and difficulty state, by sitekey
+    pub sitekeys: HashMap<String, MCaptcha>,
+}
+
+fn io_err(path: &str, e: impl std::fmt::Display) -> ServiceError {
+    log::error!("Unable to access state file {path}: {e}");
+    ServiceError::InternalServerError
+}
+
+/// save the state of the embedded master to `path`. Returns the number of
+/// sitekeys saved, or `None` with Redis
+pub async fn save(data: &AppData, path: &str) -> ServiceResult<Option<usize>> {
+    let sitekeys = match data.captcha.get_internal_data().await? {
+        Some(sitekeys) => sitekeys,
+        None => return Ok(None),
+    };
+    let saved = sitekeys.len();
+    let snapshot = Snapshot {
+        saved: OffsetDateTime::now_utc().unix_timestamp(),
+        sitekeys,
+    };
+    let contents = serde_json::to_vec(&snapshot).map_err(|e| io_err(path, e))?;
+
+    // written to a temporary file first, so that a crash doesn't leave a
+    // partial state file behind
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, contents).map_err(|e| io_err(&tmp, e))?;
+    fs::rename(&tmp, path).map_err(|e| io_err(path, e))?;
+    Ok(Some(saved))
+}
+
+/// restore the state of the embedded master from `path`, if it exists.
+/// Returns the number of sitekeys restored
+pub async fn restore(data: &AppData, path: &str) -> ServiceResult<usize> {
+    if data.settings.redis.is_some() || !Path::new(path).exists() {
+        return Ok(0);
+    }
+    let contents = fs::read(path).map_err(|e| io_err(path, e))?;
+    let snapshot: Snapshot =
+        serde_json::from_slice(&contents).map_err(|e| io_err(path, e))?;
+
+    let mut sitekeys = HashMap::with_capacity(snapshot.sitekeys.len());
+    for (key, mcaptcha) in snapshot.sitekeys.into_iter() {
+        if data.db.captcha_exists(None, &key).await? {
+            sitekeys.insert(key, mcaptcha);
+        }
+    }
+    let restored = sitekeys.len();
+    data.captcha.set_internal_data(sitekeys).await?;
+    fs::remove_file(path).map_err(|e| io_err(path, e))?;
+    log::info!(
+        "Restored state of {restored} sitekeys, saved at {}",
+        snapshot.saved
+    );
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use libmcaptcha::master::messages::RemoveCaptcha;
+
+    use super::*;
+    use crate::api::v1::mcaptcha::get_random;
+    use crate::api::v1::pow::get_config::get_config_runner;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn state_survives_restarts_pg() {
+        let data = crate::tests::pg::get_data().await;
+        state_survives_restarts(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn state_survives_restarts_maria() {
+        let data = crate::tests::maria::get_data().await;
+        state_survives_restarts(data).await;
+    }
+
+    async fn state_survives_restarts(data: ArcData) {
+        const NAME: &str = "shutdownstateusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "shutdownstateusr@a.com";
+
+        // only the embedded master keeps its state in memory
+        let mut settings = data.settings.clone();
+        settings.redis = None;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app_data = AppData::new(data.clone());
+
+        let path =
+            std::env::temp_dir().join(format!("mcaptcha-{}.json", get_random(16)));
+        let path = path.to_str().unwrap();
+
+        // nothing to restore on first boot
+        assert_eq!(restore(&app_data, path).await.unwrap(), 0);
+
+        for _ in 0..L1.visitor_threshold * 2 {
+            get_config_runner(&app_data, &token_key.key).await.unwrap();
+        }
+        let config = get_config_runner(&app_data, &token_key.key).await.unwrap();
+        assert!(config.difficulty_factor > L1.difficulty_factor);
+
+        assert_eq!(save(&app_data, path).await.unwrap(), Some(1));
+        // state of the master is lost on restart
+        data.captcha
+            .remove(RemoveCaptcha(token_key.key.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(restore(&app_data, path).await.unwrap(), 1);
+        assert!(!Path::new(path).exists());
+        let config = get_config_runner(&app_data, &token_key.key).await.unwrap();
+        assert!(config.difficulty_factor > L1.difficulty_factor);
+    }
+}
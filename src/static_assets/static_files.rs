@@ -61,6 +61,10 @@ pub mod assets {
             FILES.get("./static/cache/img/svg/bar-chart.svg").unwrap(),
             "Statistics"
         );
+        pub static ref USER_ICON: Img = (
+            FILES.get("./static/cache/img/svg/user.svg").unwrap(),
+            "Organizations"
+        );
     }
 }
 
@@ -140,6 +144,7 @@ mod tests {
             *crate::JS,
             *crate::VERIFICATIN_WIDGET_JS,
             *crate::VERIFICATIN_WIDGET_CSS,
+            *crate::EMBED_JS,
             crate::FILES
                 .get("./static/cache/img/icon-trans.png")
                 .unwrap(),
@@ -3,25 +3,100 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+//! CAPTCHA event stats
+//!
+//! [Buffered] keeps configuration fetches, solves and confirms off the hot path:
+//! entries are pushed to a bounded [StatsQueue] and [StatsFlusher] writes them
+//! to the database in batches. When the queue is full, because the database is
+//! slow or unreachable, new entries are dropped and counted instead of blocking
+//! requests.
+//!
+//! [External] writes stats to the time-series store configured in
+//! `stats_sink` instead of the database; see [crate::stats_sink].
+//!
+//! Stats are fetched for a [StatsQuery]: the latest records of each kind
+//! within a time range, so that reading the stats of a sitekey doesn't slow
+//! down as it ages.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
 use async_trait::async_trait;
-use db_core::errors::DBResult;
+use db_core::errors::{DBError, DBResult};
+use db_core::{CountryStats, StatsRecord, TimeRange};
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
 
 use crate::data::Data;
+use crate::errors::*;
+use crate::jobs::STATS_FLUSH_JOB;
+use crate::stats_sink::StatsSink;
+use crate::AppData;
 
 #[async_trait]
 pub trait Stats: std::marker::Send + std::marker::Sync + CloneStats {
     /// record PoWConfig fetches
     async fn record_fetch(&self, d: &Data, key: &str) -> DBResult<()>;
 
-    /// record PoWConfig solves
-    async fn record_solve(&self, d: &Data, key: &str) -> DBResult<()>;
+    /// record PoWConfig solves, along with the country of the solver if known
+    async fn record_solve(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()>;
 
-    /// record PoWConfig confirms
-    async fn record_confirm(&self, d: &Data, key: &str) -> DBResult<()>;
+    /// record PoWConfig confirms, along with the country of the solver if known
+    async fn record_confirm(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()>;
 
-    /// fetch stats
-    async fn fetch(&self, d: &Data, user: &str, key: &str) -> DBResult<CaptchaStats>;
+    /// fetch stats matching `query`
+    async fn fetch(
+        &self,
+        d: &Data,
+        user: &str,
+        key: &str,
+        query: &StatsQuery,
+    ) -> DBResult<CaptchaStats>;
+}
+
+/// seconds of stats that are fetched by default
+pub const DEFAULT_STATS_RANGE: i64 = 60 * 60 * 24 * 30;
+/// records of each kind that are fetched by default
+pub const DEFAULT_STATS_LIMIT: usize = 100;
+/// most records of each kind that can be fetched at once
+pub const MAX_STATS_LIMIT: usize = 1000;
+
+/// stats to fetch: the latest `limit` fetches, solves and confirms within
+/// `range`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatsQuery {
+    pub range: TimeRange,
+    pub limit: usize,
+}
+
+impl StatsQuery {
+    /// latest [DEFAULT_STATS_LIMIT] records of the last [DEFAULT_STATS_RANGE]
+    /// seconds
+    pub fn recent() -> Self {
+        let now = OffsetDateTime::now_utc().unix_timestamp() + 1;
+        Self {
+            range: TimeRange {
+                from: now - DEFAULT_STATS_RANGE,
+                to: now,
+            },
+            limit: DEFAULT_STATS_LIMIT,
+        }
+    }
 }
 
 /// Trait to clone MCDatabase
@@ -51,6 +126,9 @@ pub struct CaptchaStats {
     pub config_fetches: Vec<i64>,
     pub solves: Vec<i64>,
     pub confirms: Vec<i64>,
+    /// solves and confirms by country of the solver, when GeoIP is enabled
+    #[serde(default)]
+    pub countries: Vec<CountryStats>,
 }
 
 #[derive(Clone, Default, PartialEq, Debug)]
@@ -64,28 +142,52 @@ impl Stats for Real {
     }
 
     /// record PoWConfig solves
-    async fn record_solve(&self, d: &Data, key: &str) -> DBResult<()> {
-        d.db.record_solve(key).await
+    async fn record_solve(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        d.db.record_solve(key, country).await
     }
 
     /// record PoWConfig confirms
-    async fn record_confirm(&self, d: &Data, key: &str) -> DBResult<()> {
-        d.db.record_confirm(key).await
+    async fn record_confirm(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        d.db.record_confirm(key, country).await
     }
 
     /// fetch stats
-    async fn fetch(&self, d: &Data, user: &str, key: &str) -> DBResult<CaptchaStats> {
-        let config_fetches_fut = d.db.fetch_config_fetched(user, key);
-        let solves_fut = d.db.fetch_solve(user, key);
-        let confirms_fut = d.db.fetch_confirm(user, key);
+    async fn fetch(
+        &self,
+        d: &Data,
+        user: &str,
+        key: &str,
+        query: &StatsQuery,
+    ) -> DBResult<CaptchaStats> {
+        let (range, limit) = (&query.range, query.limit);
+        let config_fetches_fut =
+            d.db.fetch_config_fetched_latest(user, key, range, limit);
+        let solves_fut = d.db.fetch_solve_latest(user, key, range, limit);
+        let confirms_fut = d.db.fetch_confirm_latest(user, key, range, limit);
+        let countries_fut = d.db.fetch_country_stats(user, key);
 
-        let (config_fetches, solves, confirms) =
-            futures::try_join!(config_fetches_fut, solves_fut, confirms_fut)?;
+        let (config_fetches, solves, confirms, countries) = futures::try_join!(
+            config_fetches_fut,
+            solves_fut,
+            confirms_fut,
+            countries_fut
+        )?;
 
         let res = CaptchaStats {
             config_fetches,
             solves,
             confirms,
+            countries,
         };
 
         Ok(res)
@@ -103,17 +205,425 @@ impl Stats for Dummy {
     }
 
     /// record PoWConfig solves
-    async fn record_solve(&self, _: &Data, _: &str) -> DBResult<()> {
+    async fn record_solve(&self, _: &Data, _: &str, _: Option<&str>) -> DBResult<()> {
         Ok(())
     }
 
     /// record PoWConfig confirms
-    async fn record_confirm(&self, _: &Data, _: &str) -> DBResult<()> {
+    async fn record_confirm(&self, _: &Data, _: &str, _: Option<&str>) -> DBResult<()> {
         Ok(())
     }
 
     /// fetch stats
-    async fn fetch(&self, _: &Data, _: &str, _: &str) -> DBResult<CaptchaStats> {
+    async fn fetch(
+        &self,
+        _: &Data,
+        _: &str,
+        _: &str,
+        _: &StatsQuery,
+    ) -> DBResult<CaptchaStats> {
         Ok(CaptchaStats::default())
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsKind {
+    Fetch,
+    Solve,
+    Confirm,
+}
+
+impl StatsKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fetch => "fetch",
+            Self::Solve => "solve",
+            Self::Confirm => "confirm",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StatsEntry {
+    kind: StatsKind,
+    record: StatsRecord,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// state of the buffered stats queue
+pub struct StatsQueueMetrics {
+    /// maximum number of entries that can be queued
+    pub capacity: usize,
+    /// number of entries awaiting flush
+    pub depth: usize,
+    /// number of entries dropped because the queue was full or the flush failed
+    pub dropped: usize,
+    /// number of entries written to the database
+    pub flushed: usize,
+}
+
+/// Bounded queue of stats entries awaiting flush
+pub struct StatsQueue {
+    tx: mpsc::Sender<StatsEntry>,
+    rx: Mutex<Option<mpsc::Receiver<StatsEntry>>>,
+    capacity: usize,
+    depth: AtomicUsize,
+    dropped: AtomicUsize,
+    flushed: AtomicUsize,
+}
+
+impl StatsQueue {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (tx, rx) = mpsc::channel(capacity);
+        Self {
+            tx,
+            rx: Mutex::new(Some(rx)),
+            capacity,
+            depth: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            flushed: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, kind: StatsKind, key: &str, country: Option<&str>) {
+        let entry = StatsEntry {
+            kind,
+            record: StatsRecord {
+                key: key.to_string(),
+                time: OffsetDateTime::now_utc().unix_timestamp(),
+                country: country.map(|c| c.to_string()),
+            },
+        };
+        match self.tx.try_send(entry) {
+            Ok(_) => self.depth.fetch_add(1, Ordering::SeqCst),
+            Err(_) => self.dropped.fetch_add(1, Ordering::SeqCst),
+        };
+    }
+
+    /// get state of the queue
+    pub fn metrics(&self) -> StatsQueueMetrics {
+        StatsQueueMetrics {
+            capacity: self.capacity,
+            depth: self.depth.load(Ordering::SeqCst),
+            dropped: self.dropped.load(Ordering::SeqCst),
+            flushed: self.flushed.load(Ordering::SeqCst),
+        }
+    }
+
+    fn take_receiver(&self) -> Option<mpsc::Receiver<StatsEntry>> {
+        self.rx.lock().unwrap().take()
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Buffered;
+
+#[async_trait]
+impl Stats for Buffered {
+    /// record PoWConfig fetches
+    async fn record_fetch(&self, d: &Data, key: &str) -> DBResult<()> {
+        d.stats_queue.push(StatsKind::Fetch, key, None);
+        Ok(())
+    }
+
+    /// record PoWConfig solves
+    async fn record_solve(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        d.stats_queue.push(StatsKind::Solve, key, country);
+        Ok(())
+    }
+
+    /// record PoWConfig confirms
+    async fn record_confirm(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        d.stats_queue.push(StatsKind::Confirm, key, country);
+        Ok(())
+    }
+
+    /// fetch stats
+    async fn fetch(
+        &self,
+        d: &Data,
+        user: &str,
+        key: &str,
+        query: &StatsQuery,
+    ) -> DBResult<CaptchaStats> {
+        Real.fetch(d, user, key, query).await
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct External;
+
+impl External {
+    /// buffer the record when stats are buffered, or write it to the sink
+    async fn record(
+        &self,
+        d: &Data,
+        kind: StatsKind,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        if d.settings.captcha.stats_buffer_size > 0 {
+            d.stats_queue.push(kind, key, country);
+            return Ok(());
+        }
+        let record = StatsRecord {
+            key: key.to_string(),
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            country: country.map(|c| c.to_string()),
+        };
+        Self::sink(d).write(kind, &[record]).await
+    }
+
+    fn sink(d: &Data) -> &dyn StatsSink {
+        d.stats_sink
+            .as_deref()
+            .expect("external stats are only used when stats_sink is set")
+    }
+}
+
+#[async_trait]
+impl Stats for External {
+    /// record PoWConfig fetches
+    async fn record_fetch(&self, d: &Data, key: &str) -> DBResult<()> {
+        self.record(d, StatsKind::Fetch, key, None).await
+    }
+
+    /// record PoWConfig solves
+    async fn record_solve(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        self.record(d, StatsKind::Solve, key, country).await
+    }
+
+    /// record PoWConfig confirms
+    async fn record_confirm(
+        &self,
+        d: &Data,
+        key: &str,
+        country: Option<&str>,
+    ) -> DBResult<()> {
+        self.record(d, StatsKind::Confirm, key, country).await
+    }
+
+    /// fetch stats
+    async fn fetch(
+        &self,
+        d: &Data,
+        user: &str,
+        key: &str,
+        query: &StatsQuery,
+    ) -> DBResult<CaptchaStats> {
+        // the sink doesn't know who owns sitekeys
+        if !d.db.captcha_exists(Some(user), key).await? {
+            return Err(DBError::CaptchaNotFound);
+        }
+        Self::sink(d).fetch(key, query).await
+    }
+}
+
+pub struct StatsFlusher {
+    tx: Sender<()>,
+}
+
+impl StatsFlusher {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// write queued stats entries to the database, or to the stats sink
+    pub async fn flush(
+        data: &AppData,
+        queue: &mut mpsc::Receiver<StatsEntry>,
+    ) -> ServiceResult<()> {
+        let (mut fetches, mut solves, mut confirms) =
+            (Vec::new(), Vec::new(), Vec::new());
+        // entries that arrive while flushing are left for the next run
+        for _ in 0..data.stats_queue.capacity {
+            let entry = match queue.try_recv() {
+                Ok(entry) => entry,
+                Err(_) => break,
+            };
+            data.stats_queue.depth.fetch_sub(1, Ordering::SeqCst);
+            match entry.kind {
+                StatsKind::Fetch => fetches.push(entry.record),
+                StatsKind::Solve => solves.push(entry.record),
+                StatsKind::Confirm => confirms.push(entry.record),
+            }
+        }
+
+        let total = fetches.len() + solves.len() + confirms.len();
+        if total == 0 {
+            return Ok(());
+        }
+
+        let res = match data.stats_sink.as_deref() {
+            Some(sink) => futures::try_join!(
+                sink.write(StatsKind::Fetch, &fetches),
+                sink.write(StatsKind::Solve, &solves),
+                sink.write(StatsKind::Confirm, &confirms)
+            )
+            .map(|_| ()),
+            None => futures::try_join!(
+                data.db.record_fetches(&fetches),
+                data.db.record_solves(&solves),
+                data.db.record_confirms(&confirms)
+            )
+            .map(|_| ()),
+        };
+        match res {
+            Ok(_) => {
+                data.stats_queue.flushed.fetch_add(total, Ordering::SeqCst);
+                Ok(())
+            }
+            Err(e) => {
+                data.stats_queue.dropped.fetch_add(total, Ordering::SeqCst);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let mut queue = data
+            .stats_queue
+            .take_receiver()
+            .expect("stats flusher is already running");
+        let duration = data.settings.captcha.stats_flush_interval;
+        data.jobs.register(STATS_FLUSH_JOB, duration);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                for _ in 0..duration {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+
+                // flush one last time on shutdown so that queued entries aren't lost
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::flush(&data, &mut queue).await;
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Tried to flush stats {:?}", err);
+                }
+                data.jobs
+                    .finished(STATS_FLUSH_JOB, started, timer.elapsed(), &res);
+
+                if exit {
+                    break;
+                }
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn buffered_stats_work_pg() {
+        let data = pg::get_data().await;
+        buffered_stats_work(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn buffered_stats_work_maria() {
+        let data = maria::get_data().await;
+        buffered_stats_work(data).await;
+    }
+
+    async fn buffered_stats_work(data: ArcData) {
+        const NAME: &str = "bufferedstatsuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "bufferedstatsuser@a.com";
+
+        let mut settings = data.settings.clone();
+        settings.captcha.enable_stats = true;
+        settings.captcha.stats_buffer_size = 2;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = actix_web::web::Data::new(data.clone());
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let key = &token_key.key;
+        let mut queue = data.stats_queue.take_receiver().unwrap();
+
+        let stats = Buffered;
+        stats.record_fetch(data, key).await.unwrap();
+        stats.record_solve(data, key, Some("FR")).await.unwrap();
+        // queue is full; entry is dropped
+        stats.record_confirm(data, key, None).await.unwrap();
+        let metrics = data.stats_queue.metrics();
+        assert_eq!(metrics.capacity, 2);
+        assert_eq!(metrics.depth, 2);
+        assert_eq!(metrics.dropped, 1);
+        assert!(stats
+            .fetch(data, NAME, key, &StatsQuery::recent())
+            .await
+            .unwrap()
+            .config_fetches
+            .is_empty());
+
+        StatsFlusher::flush(&app_data, &mut queue).await.unwrap();
+        let metrics = data.stats_queue.metrics();
+        assert_eq!(metrics.depth, 0);
+        assert_eq!(metrics.flushed, 2);
+        let res = stats
+            .fetch(data, NAME, key, &StatsQuery::recent())
+            .await
+            .unwrap();
+        assert_eq!(res.config_fetches.len(), 1);
+        assert_eq!(res.solves.len(), 1);
+        assert!(res.confirms.is_empty());
+        assert_eq!(
+            res.countries,
+            vec![CountryStats {
+                country: "FR".into(),
+                solves: 1,
+                confirms: 0,
+            }]
+        );
+    }
+}
@@ -0,0 +1,407 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! External time-series stores for stats
+//!
+//! When `stats_sink` is set, configuration fetches, solves and confirms are
+//! written to a ClickHouse or TimescaleDB table instead of the stats tables of
+//! the database, and the stats of sitekeys are read from it. The table is
+//! created on startup if it doesn't exist, with one row per event:
+//!
+//! | column        | value                                   |
+//! | ------------- | --------------------------------------- |
+//! | `kind`        | `fetch`, `solve` or `confirm`           |
+//! | `captcha_key` | sitekey                                 |
+//! | `time`        | time of the event                       |
+//! | `country`     | country of the solver, when GeoIP is on |
+use async_trait::async_trait;
+use db_core::errors::{DBError, DBResult};
+use db_core::{CountryStats, StatsRecord};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Row;
+use url::Url;
+
+use crate::settings::{Settings, StatsSinkKind};
+use crate::stats::{CaptchaStats, StatsKind, StatsQuery};
+
+/// connections to TimescaleDB
+const TIMESCALE_POOL: u32 = 4;
+
+#[async_trait]
+pub trait StatsSink: std::marker::Send + std::marker::Sync {
+    /// create the stats table if it doesn't exist
+    async fn init(&self) -> DBResult<()>;
+
+    /// write stats records of `kind`
+    async fn write(&self, kind: StatsKind, records: &[StatsRecord]) -> DBResult<()>;
+
+    /// fetch stats of a sitekey matching `query`
+    async fn fetch(&self, key: &str, query: &StatsQuery) -> DBResult<CaptchaStats>;
+}
+
+/// store configured in `stats_sink`, if any. Panics when it can't be reached,
+/// like the database does on startup
+pub async fn connect(s: &Settings) -> Option<Box<dyn StatsSink>> {
+    let sink = s.stats_sink.as_ref()?;
+    let store: Box<dyn StatsSink> = match sink.kind {
+        StatsSinkKind::ClickHouse => {
+            Box::new(ClickHouse::new(&sink.url, sink.table.clone()))
+        }
+        StatsSinkKind::Timescale => Box::new(
+            Timescale::connect(&sink.url, sink.table.clone())
+                .await
+                .unwrap_or_else(|e| panic!("Unable to connect to TimescaleDB: {e}")),
+        ),
+    };
+    store
+        .init()
+        .await
+        .unwrap_or_else(|e| panic!("Unable to create stats table {}: {e}", sink.table));
+    log::info!("Writing stats to {} table {}", sink.kind, sink.table);
+    Some(store)
+}
+
+/// stats of a sitekey from its events, newest first
+fn to_stats(events: Vec<(String, i64)>, countries: Vec<CountryStats>) -> CaptchaStats {
+    let mut stats = CaptchaStats {
+        countries,
+        ..Default::default()
+    };
+    for (kind, time) in events {
+        match kind.as_str() {
+            "fetch" => stats.config_fetches.push(time),
+            "solve" => stats.solves.push(time),
+            "confirm" => stats.confirms.push(time),
+            _ => (),
+        }
+    }
+    stats
+}
+
+fn sink_err(e: impl std::error::Error + Send + Sync + 'static) -> DBError {
+    DBError::DBError(Box::new(e))
+}
+
+/// ClickHouse, over its HTTP interface
+pub struct ClickHouse {
+    client: Client,
+    url: String,
+    table: String,
+}
+
+#[derive(Serialize)]
+struct ClickHouseRow<'a> {
+    kind: &'static str,
+    captcha_key: &'a str,
+    time: i64,
+    country: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ClickHouseEvent {
+    kind: String,
+    timestamp: i64,
+}
+
+#[derive(Deserialize)]
+struct ClickHouseCountry {
+    code: String,
+    solves: u64,
+    confirms: u64,
+}
+
+impl ClickHouse {
+    pub fn new(url: &str, table: String) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.into(),
+            table,
+        }
+    }
+
+    /// run `query`, binding `params` to its `{name:Type}` placeholders
+    async fn query(
+        &self,
+        query: &str,
+        params: &[(&str, &str)],
+        body: String,
+    ) -> DBResult<String> {
+        let mut url = Url::parse(&self.url).map_err(sink_err)?;
+        {
+            let mut pairs = url.query_pairs_mut();
+            pairs.append_pair("query", query);
+            pairs.append_pair("output_format_json_quote_64bit_integers", "0");
+            for (name, value) in params {
+                pairs.append_pair(&format!("param_{name}"), value);
+            }
+        }
+        let resp = self
+            .client
+            .post(url)
+            .body(body)
+            .send()
+            .await
+            .map_err(sink_err)?;
+        let status = resp.status();
+        let text = resp.text().await.map_err(sink_err)?;
+        if !status.is_success() {
+            return Err(DBError::DBError(
+                format!("ClickHouse responded with {status}: {text}").into(),
+            ));
+        }
+        Ok(text)
+    }
+
+    fn parse<T: for<'de> Deserialize<'de>>(text: &str) -> DBResult<Vec<T>> {
+        text.lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_str(l).map_err(sink_err))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl StatsSink for ClickHouse {
+    async fn init(&self) -> DBResult<()> {
+        let query = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                kind LowCardinality(String),
+                captcha_key String,
+                time DateTime,
+                country Nullable(FixedString(2))
+            ) ENGINE = MergeTree ORDER BY (captcha_key, time)",
+            self.table
+        );
+        self.query(&query, &[], String::new()).await?;
+        Ok(())
+    }
+
+    async fn write(&self, kind: StatsKind, records: &[StatsRecord]) -> DBResult<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut body = String::new();
+        for r in records {
+            let row = ClickHouseRow {
+                kind: kind.name(),
+                captcha_key: &r.key,
+                time: r.time,
+                country: r.country.as_deref(),
+            };
+            body.push_str(&serde_json::to_string(&row).map_err(sink_err)?);
+            body.push('\n');
+        }
+        let query = format!("INSERT INTO {} FORMAT JSONEachRow", self.table);
+        self.query(&query, &[], body).await?;
+        Ok(())
+    }
+
+    async fn fetch(&self, key: &str, query: &StatsQuery) -> DBResult<CaptchaStats> {
+        let (from, to) = (query.range.from.to_string(), query.range.to.to_string());
+        let params = [("key", key), ("from", &from), ("to", &to)];
+        let events = format!(
+            "SELECT kind, toUnixTimestamp(time) AS timestamp FROM {}
+            WHERE captcha_key = {{key:String}}
+            AND time >= toDateTime({{from:Int64}}) AND time < toDateTime({{to:Int64}})
+            ORDER BY timestamp DESC
+            LIMIT {} BY kind
+            FORMAT JSONEachRow",
+            self.table, query.limit
+        );
+        let countries = format!(
+            "SELECT
+                assumeNotNull(country) AS code,
+                countIf(kind = 'solve') AS solves,
+                countIf(kind = 'confirm') AS confirms
+            FROM {}
+            WHERE captcha_key = {{key:String}} AND country IS NOT NULL
+            GROUP BY country
+            ORDER BY solves DESC, confirms DESC, code
+            FORMAT JSONEachRow",
+            self.table
+        );
+        let (events, countries) = futures::try_join!(
+            self.query(&events, &params, String::new()),
+            self.query(&countries, &params, String::new())
+        )?;
+        let events: Vec<ClickHouseEvent> = Self::parse(&events)?;
+        let countries: Vec<ClickHouseCountry> = Self::parse(&countries)?;
+        Ok(to_stats(
+            events.into_iter().map(|e| (e.kind, e.timestamp)).collect(),
+            countries
+                .into_iter()
+                .map(|c| CountryStats {
+                    country: c.code,
+                    solves: c.solves,
+                    confirms: c.confirms,
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// TimescaleDB; the stats table is a hypertable partitioned by time
+pub struct Timescale {
+    pool: PgPool,
+    table: String,
+}
+
+impl Timescale {
+    pub async fn connect(url: &str, table: String) -> DBResult<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(TIMESCALE_POOL)
+            .connect(url)
+            .await
+            .map_err(sink_err)?;
+        Ok(Self { pool, table })
+    }
+}
+
+#[async_trait]
+impl StatsSink for Timescale {
+    async fn init(&self) -> DBResult<()> {
+        let create = format!(
+            "CREATE TABLE IF NOT EXISTS {} (
+                kind TEXT NOT NULL,
+                captcha_key TEXT NOT NULL,
+                time TIMESTAMPTZ NOT NULL,
+                country CHAR(2)
+            )",
+            self.table
+        );
+        let hypertable = format!(
+            "SELECT create_hypertable('{}', 'time', if_not_exists => TRUE)",
+            self.table
+        );
+        let index = format!(
+            "CREATE INDEX IF NOT EXISTS {}_key_time ON {} (captcha_key, time DESC)",
+            self.table.replace('.', "_"),
+            self.table
+        );
+        for query in [create, hypertable, index] {
+            sqlx::query(&query)
+                .execute(&self.pool)
+                .await
+                .map_err(sink_err)?;
+        }
+        Ok(())
+    }
+
+    async fn write(&self, kind: StatsKind, records: &[StatsRecord]) -> DBResult<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let keys: Vec<&str> = records.iter().map(|r| r.key.as_str()).collect();
+        let times: Vec<i64> = records.iter().map(|r| r.time).collect();
+        let countries: Vec<Option<&str>> =
+            records.iter().map(|r| r.country.as_deref()).collect();
+        let query = format!(
+            "INSERT INTO {} (kind, captcha_key, time, country)
+            SELECT $1, k, to_timestamp(t), c
+            FROM UNNEST($2::TEXT[], $3::BIGINT[], $4::TEXT[]) AS u(k, t, c)",
+            self.table
+        );
+        sqlx::query(&query)
+            .bind(kind.name())
+            .bind(&keys)
+            .bind(&times)
+            .bind(&countries)
+            .execute(&self.pool)
+            .await
+            .map_err(sink_err)?;
+        Ok(())
+    }
+
+    async fn fetch(&self, key: &str, query: &StatsQuery) -> DBResult<CaptchaStats> {
+        let events = format!(
+            "SELECT kind, time FROM (
+                SELECT
+                    kind,
+                    EXTRACT(EPOCH FROM time)::BIGINT AS time,
+                    ROW_NUMBER() OVER (PARTITION BY kind ORDER BY time DESC) AS n
+                FROM {}
+                WHERE captcha_key = $1
+                AND time >= to_timestamp($2) AND time < to_timestamp($3)
+            ) AS events
+            WHERE n <= $4
+            ORDER BY 2 DESC",
+            self.table
+        );
+        let countries = format!(
+            "SELECT
+                country::TEXT AS country,
+                COUNT(*) FILTER (WHERE kind = 'solve') AS solves,
+                COUNT(*) FILTER (WHERE kind = 'confirm') AS confirms
+            FROM {}
+            WHERE captcha_key = $1 AND country IS NOT NULL
+            GROUP BY country
+            ORDER BY 2 DESC, 3 DESC, country",
+            self.table
+        );
+        let (events, countries) = futures::try_join!(
+            sqlx::query(&events)
+                .bind(key)
+                .bind(query.range.from)
+                .bind(query.range.to)
+                .bind(query.limit as i64)
+                .fetch_all(&self.pool),
+            sqlx::query(&countries).bind(key).fetch_all(&self.pool)
+        )
+        .map_err(sink_err)?;
+
+        let events = events
+            .iter()
+            .map(|r| Ok((r.try_get("kind")?, r.try_get("time")?)))
+            .collect::<Result<Vec<(String, i64)>, sqlx::Error>>()
+            .map_err(sink_err)?;
+        let countries = countries
+            .iter()
+            .map(|r| {
+                Ok(CountryStats {
+                    country: r.try_get("country")?,
+                    solves: r.try_get::<i64, _>("solves")? as u64,
+                    confirms: r.try_get::<i64, _>("confirms")? as u64,
+                })
+            })
+            .collect::<Result<Vec<CountryStats>, sqlx::Error>>()
+            .map_err(sink_err)?;
+        Ok(to_stats(events, countries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_stats_works() {
+        let events = vec![
+            ("confirm".to_string(), 3),
+            ("solve".to_string(), 2),
+            ("fetch".to_string(), 1),
+            ("fetch".to_string(), 0),
+        ];
+        let stats = to_stats(events, Vec::new());
+        assert_eq!(stats.config_fetches, vec![1, 0]);
+        assert_eq!(stats.solves, vec![2]);
+        assert_eq!(stats.confirms, vec![3]);
+    }
+
+    #[test]
+    fn clickhouse_output_is_parsed() {
+        let text = "{\"kind\":\"solve\",\"timestamp\":1700000000}\n{\"kind\":\"fetch\",\"timestamp\":1699999999}\n";
+        let events: Vec<ClickHouseEvent> = ClickHouse::parse(text).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, "solve");
+        assert_eq!(events[1].timestamp, 1699999999);
+
+        let text = "{\"code\":\"FR\",\"solves\":2,\"confirms\":1}\n";
+        let countries: Vec<ClickHouseCountry> = ClickHouse::parse(text).unwrap();
+        assert_eq!(countries[0].code, "FR");
+        assert_eq!(countries[0].solves, 2);
+    }
+}
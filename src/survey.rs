@@ -2,19 +2,41 @@
 // SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Uploads of benchmarks to mCaptcha/survey nodes
+//!
+//! Every `survey.rate_limit` seconds, [Survey] registers with the configured
+//! nodes that this instance doesn't have a secret of yet, and uploads the
+//! analytics of published campaigns that nodes haven't received yet, in
+//! batches of [UPLOAD_BATCH] records signed with the secret of the node. See
+//! [Data::register_survey](crate::data::Data::register_survey) and
+//! [Data::upload_survey_job](crate::data::Data::upload_survey_job).
+//!
+//! Nodes that fail are skipped for [BACKOFF] seconds, doubling with every
+//! consecutive failure up to [MAX_BACKOFF], so that an unreachable node doesn't
+//! hold up uploads to the others. [NodeHealthStore] tracks the health of each
+//! node.
+//!
+//! Before upload, analytics can be [anonymized](Anonymization): solve times
+//! are noised and quantized, and campaigns with too few records aren't
+//! uploaded.
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::RwLock;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use db_core::PerformanceAnalytics;
+use rand::{thread_rng, Rng};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
 
 use crate::errors::*;
-use crate::settings::Settings;
+use crate::jobs::SURVEY_UPLOAD_JOB;
+use crate::settings::Anonymization;
 use crate::AppData;
 use crate::V1_API_ROUTES;
 
@@ -26,9 +48,127 @@ pub trait SurveyClientTrait {
     async fn register(&self) -> ServiceResult<()>;
 }
 
+/// analytics records uploaded to mCaptcha/survey nodes at a time
+pub const UPLOAD_BATCH: usize = 50;
+/// seconds that a node is skipped for after it fails
+pub const BACKOFF: i64 = 60;
+/// most seconds that a failing node is skipped for
+pub const MAX_BACKOFF: i64 = 60 * 60 * 24;
+/// seconds that requests to nodes may take
+pub const NODE_TIMEOUT: u64 = 30;
+
+/// client for requests to mCaptcha/survey nodes
+pub fn client() -> Client {
+    Client::builder()
+        .timeout(Duration::from_secs(NODE_TIMEOUT))
+        .build()
+        .unwrap()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+/// health of a mCaptcha/survey node
+pub struct NodeHealth {
+    /// whether the last request to the node got a response; unset before the
+    /// first request
+    pub reachable: Option<bool>,
+    /// unix timestamp of the last successful upload
+    pub last_upload: Option<i64>,
+    /// unix timestamp of the last failure
+    pub last_failure: Option<i64>,
+    /// cause of the last failure
+    pub error: Option<String>,
+    /// failures since the node last responded
+    pub failures: u32,
+    /// the node is skipped until this unix timestamp
+    pub retry_at: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NodeHealthStore {
+    nodes: Arc<RwLock<HashMap<String, NodeHealth>>>,
+}
+
+impl NodeHealthStore {
+    pub fn get(&self, node: &str) -> NodeHealth {
+        let r = self.nodes.read().unwrap();
+        r.get(node).cloned().unwrap_or_default()
+    }
+
+    /// whether `node` may be tried at `now`, or is backing off
+    pub fn is_available(&self, node: &str, now: i64) -> bool {
+        self.get(node)
+            .retry_at
+            .map_or(true, |retry_at| now >= retry_at)
+    }
+
+    /// `node` responded
+    pub fn reached(&self, node: &str) {
+        let mut w = self.nodes.write().unwrap();
+        let health = w.entry(node.to_owned()).or_default();
+        health.reachable = Some(true);
+        health.failures = 0;
+        health.retry_at = None;
+    }
+
+    /// `node` accepted an upload at `now`
+    pub fn uploaded(&self, node: &str, now: i64) {
+        self.reached(node);
+        let mut w = self.nodes.write().unwrap();
+        w.entry(node.to_owned()).or_default().last_upload = Some(now);
+    }
+
+    /// a request to `node` failed at `now`; it is skipped until its backoff
+    /// expires
+    pub fn failed(&self, node: &str, now: i64, error: String, reachable: bool) {
+        let mut w = self.nodes.write().unwrap();
+        let health = w.entry(node.to_owned()).or_default();
+        health.failures = health.failures.saturating_add(1);
+        let backoff = BACKOFF
+            .saturating_mul(1 << (health.failures - 1).min(20))
+            .min(MAX_BACKOFF);
+        health.reachable = Some(reachable);
+        health.last_failure = Some(now);
+        health.error = Some(error);
+        health.retry_at = Some(now + backoff);
+    }
+}
+
+/// downloads of benchmarks that an IP may make within [DOWNLOAD_WINDOW]
+pub const DOWNLOADS_PER_WINDOW: u32 = 30;
+/// seconds over which downloads of benchmarks are counted
+pub const DOWNLOAD_WINDOW: i64 = 60;
+
+#[derive(Clone, Debug, Default)]
+/// downloads of benchmarks from this instance, counted per IP in fixed
+/// windows of [DOWNLOAD_WINDOW] seconds
+pub struct DownloadLimiter {
+    /// start of the current window and downloads within it, by IP
+    windows: Arc<RwLock<HashMap<String, (i64, u32)>>>,
+}
+
+impl DownloadLimiter {
+    /// count a download by `ip` at `now`. Returns false when the IP made
+    /// [DOWNLOADS_PER_WINDOW] downloads already in the current window
+    pub fn allow(&self, ip: &str, now: i64) -> bool {
+        let mut w = self.windows.write().unwrap();
+        w.retain(|_, (start, _)| now - *start < DOWNLOAD_WINDOW);
+        let (_, count) = w.entry(ip.to_owned()).or_insert((now, 0));
+        if *count >= DOWNLOADS_PER_WINDOW {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct SecretsStore {
     store: Arc<RwLock<HashMap<String, String>>>,
+    /// auth tokens of registrations that nodes haven't answered yet, by node
+    pending: Arc<RwLock<HashMap<String, String>>>,
+    /// ID of the last analytics record of a campaign uploaded to a node, by
+    /// node and psuedo ID
+    cursors: Arc<RwLock<HashMap<(String, String), usize>>>,
 }
 
 impl SecretsStore {
@@ -48,6 +188,115 @@ impl SecretsStore {
         w.insert(key, value);
         drop(w);
     }
+
+    /// track the auth token of a registration with `node`, returning the
+    /// token of the previous registration that the node didn't answer
+    pub fn set_pending(&self, node: &str, auth_token: String) -> Option<String> {
+        let mut w = self.pending.write().unwrap();
+        w.insert(node.to_owned(), auth_token)
+    }
+
+    /// stop tracking the registration with `node`
+    pub fn rm_pending(&self, node: &str) {
+        let mut w = self.pending.write().unwrap();
+        w.remove(node);
+    }
+
+    /// ID of the last analytics record of campaign `psuedo_id` uploaded to
+    /// `node`; 0 when none were uploaded
+    pub fn cursor(&self, node: &str, psuedo_id: &str) -> usize {
+        let r = self.cursors.read().unwrap();
+        r.get(&(node.to_owned(), psuedo_id.to_owned()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_cursor(&self, node: &str, psuedo_id: &str, id: usize) {
+        let mut w = self.cursors.write().unwrap();
+        w.insert((node.to_owned(), psuedo_id.to_owned()), id);
+    }
+
+    /// forget the upload progress of campaign `psuedo_id` on all nodes
+    pub fn rm_cursors(&self, psuedo_id: &str) {
+        let mut w = self.cursors.write().unwrap();
+        w.retain(|(_, id), _| id != psuedo_id);
+    }
+}
+
+/// registration with a mCaptcha/survey node. The node authenticates itself
+/// with `auth_token` when it uploads its secret
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Registration {
+    pub url: url::Url,
+    pub auth_token: String,
+}
+
+/// anonymized analytics record, as uploaded to mCaptcha/survey nodes
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UploadRecord {
+    pub time: u32,
+    pub difficulty_factor: u32,
+    pub worker_type: String,
+}
+
+impl UploadRecord {
+    pub const CSV_HEADER: &'static str = "time,difficulty_factor,worker_type\n";
+
+    pub fn csv(&self) -> String {
+        format!(
+            "{},{},\"{}\"\n",
+            self.time,
+            self.difficulty_factor,
+            self.worker_type.replace('"', "\"\"")
+        )
+    }
+}
+
+impl From<PerformanceAnalytics> for UploadRecord {
+    fn from(a: PerformanceAnalytics) -> Self {
+        Self {
+            time: a.time,
+            difficulty_factor: a.difficulty_factor,
+            worker_type: a.worker_type,
+        }
+    }
+}
+
+/// batch of analytics of a campaign uploaded to a mCaptcha/survey node. The
+/// body is signed with the secret of the node
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Upload {
+    pub analytics: Vec<UploadRecord>,
+}
+
+/// sample of Laplace noise of scale `scale`
+fn laplace<R: Rng>(rng: &mut R, scale: f64) -> f64 {
+    let u: f64 = rng.gen_range(-0.5..0.5);
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+impl Anonymization {
+    /// whether a campaign with `records` analytics records may be uploaded
+    pub fn allows(&self, records: usize) -> bool {
+        records >= self.min_records
+    }
+
+    /// add noise to and quantize the solve times of a batch of records. Solve
+    /// times are never negative
+    pub fn apply(&self, records: &mut [UploadRecord]) {
+        let mut rng = thread_rng();
+        for r in records.iter_mut() {
+            let mut time = r.time as f64;
+            if self.noise > 0 {
+                time = (time + laplace(&mut rng, self.noise as f64)).max(0.0);
+            }
+            if self.quantize > 0 {
+                let q = self.quantize as f64;
+                time = (time / q).round() * q;
+            }
+            r.time = time.min(u32::MAX as f64) as u32;
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -61,7 +310,7 @@ impl Survey {
             panic!("Survey uploader shouldn't be initialized it isn't configured, please report this bug")
         }
         Survey {
-            client: Client::new(),
+            client: client(),
             app_ctx,
         }
     }
@@ -79,47 +328,48 @@ impl SurveyClientTrait for Survey {
 
         let (tx, mut rx) = oneshot::channel();
         let this = self.clone();
-        let mut register = false;
+        let rate_limit = this.app_ctx.settings.survey.as_ref().unwrap().rate_limit;
+        this.app_ctx.jobs.register(SURVEY_UPLOAD_JOB, rate_limit);
         let fut = async move {
-            loop {
+            // nodes call back this instance to complete registration
+            while !matches!(this.is_online().await, Ok(true)) {
                 if !can_run(&mut rx) {
                     log::info!("Stopping survey uploads");
-                    break;
+                    return;
                 }
+                sleep(Duration::new(1, 0)).await;
+            }
 
-                if !register {
-                    loop {
-                        if this.is_online().await.unwrap() {
-                            this.register().await.unwrap();
-                            register = true;
-                            break;
-                        } else {
-                            sleep(Duration::new(1, 0)).await;
-                        }
-                    }
+            'job: loop {
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                if let Err(e) = this.register().await {
+                    log::error!("Unable to register with survey nodes: {e}");
+                }
+                let res = this.schedule_upload_job().await;
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Error while uploading benchmarks: {:?}", err);
                 }
+                this.app_ctx.jobs.finished(
+                    SURVEY_UPLOAD_JOB,
+                    started,
+                    timer.elapsed(),
+                    &res,
+                );
 
-                for i in 0..this.app_ctx.settings.survey.as_ref().unwrap().rate_limit {
+                for _ in 0..rate_limit {
                     if !can_run(&mut rx) {
                         log::info!("Stopping survey uploads");
-                        break;
+                        break 'job;
                     }
                     sleep(Duration::new(1, 0)).await;
                 }
-                let _ = this.schedule_upload_job().await;
-
-                // for url in this.app_ctx.settings.survey.as_ref().unwrap().nodes.iter() {
-                //     if !can_run(&mut rx) {
-                //         log::info!("Stopping survey uploads");
-                //         break;
-                //     }
-                //     log::info!("Uploading to survey instance {}", url);
-                // }
             }
         };
         let handle = tokio::spawn(fut);
         Ok((tx, handle))
     }
+
     async fn is_online(&self) -> ServiceResult<bool> {
         let res = self
             .client
@@ -129,81 +379,185 @@ impl SurveyClientTrait for Survey {
                 V1_API_ROUTES.meta.health
             ))
             .send()
-            .await
-            .unwrap();
-        Ok(res.status() == 200)
+            .await;
+        Ok(matches!(res, Ok(res) if res.status() == 200))
     }
 
     async fn schedule_upload_job(&self) -> ServiceResult<()> {
         log::debug!("Running upload job");
-        #[derive(Serialize)]
-        struct Secret {
-            secret: String,
-        }
-        let mut page = 0;
-        loop {
-            let psuedo_ids = self.app_ctx.db.analytics_get_all_psuedo_ids(page).await?;
-            if psuedo_ids.is_empty() {
-                log::debug!("upload job complete, no more IDs to upload");
-                break;
-            }
-            for id in psuedo_ids {
-                for url in self.app_ctx.settings.survey.as_ref().unwrap().nodes.iter() {
-                    if let Some(secret) = self.app_ctx.survey_secrets.get(url.as_str()) {
-                        let payload = Secret { secret };
-
-                        log::info!("Uploading to survey instance {} campaign {id}", url);
-                        let mut url = url.clone();
-                        url.set_path(&format!("/mcaptcha/api/v1/{id}/upload"));
-                        let resp =
-                            self.client.post(url).json(&payload).send().await.unwrap();
-                        println!("{}", resp.text().await.unwrap());
-                    }
-                }
-            }
-            page += 1;
-        }
-        Ok(())
+        self.app_ctx.upload_survey_job(&self.client).await
     }
 
     async fn register(&self) -> ServiceResult<()> {
-        #[derive(Serialize)]
-        struct MCaptchaInstance {
-            url: url::Url,
-            auth_token: String,
+        self.app_ctx.register_survey(&self.client).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{Survey as SurveySettings, SurveyNode};
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn secrets_store_tracks_uploads() {
+        const NODE: &str = "https://survey.example.org/";
+        let store = SecretsStore::default();
+        assert_eq!(store.set_pending(NODE, "first".into()), None);
+        assert_eq!(
+            store.set_pending(NODE, "second".into()),
+            Some("first".into())
+        );
+        store.rm_pending(NODE);
+        assert_eq!(store.set_pending(NODE, "third".into()), None);
+
+        assert_eq!(store.cursor(NODE, "campaign"), 0);
+        store.set_cursor(NODE, "campaign", 42);
+        assert_eq!(store.cursor(NODE, "campaign"), 42);
+        assert_eq!(store.cursor(NODE, "other"), 0);
+        store.rm_cursors("campaign");
+        assert_eq!(store.cursor(NODE, "campaign"), 0);
+    }
+
+    #[test]
+    fn downloads_are_rate_limited() {
+        let limiter = DownloadLimiter::default();
+        for _ in 0..DOWNLOADS_PER_WINDOW {
+            assert!(limiter.allow("1.1.1.1", 100));
         }
+        assert!(!limiter.allow("1.1.1.1", 100 + DOWNLOAD_WINDOW - 1));
+        assert!(limiter.allow("2.2.2.2", 100));
+        assert!(limiter.allow("1.1.1.1", 100 + DOWNLOAD_WINDOW));
+    }
+
+    #[test]
+    fn anonymization_works() {
+        let record = |time| UploadRecord {
+            time,
+            difficulty_factor: 500,
+            worker_type: "wasm".into(),
+        };
+        let mut records = vec![record(1249), record(1251), record(0)];
+
+        let off = Anonymization::default();
+        off.apply(&mut records);
+        assert_eq!(records, vec![record(1249), record(1251), record(0)]);
+        assert!(off.allows(0));
 
-        let this_instance_url = self
-            .app_ctx
-            .settings
-            .survey
-            .as_ref()
-            .unwrap()
-            .instance_root_url
-            .clone();
-        for url in self.app_ctx.settings.survey.as_ref().unwrap().nodes.iter() {
-            // mCaptcha/survey must send this token while uploading secret to authenticate itself
-            // this token must be sent to mCaptcha/survey with the registration payload
-            let secret_upload_auth_token = crate::api::v1::mcaptcha::get_random(20);
-
-            let payload = MCaptchaInstance {
-                url: this_instance_url.clone(),
-                auth_token: secret_upload_auth_token.clone(),
-            };
-
-            // SecretsStore will store auth tokens generated by both mCaptcha/mCaptcha and
-            // mCaptcha/survey
-            //
-            // Storage schema:
-            // - mCaptcha/mCaptcha generated auth token: (<auth_token>, <survey_instance_url>)
-            // - mCaptcha/survey generated auth token (<survey_instance_url>, <auth_token)
-            self.app_ctx
-                .survey_secrets
-                .set(secret_upload_auth_token, url.to_string());
-            let mut url = url.clone();
-            url.set_path("/mcaptcha/api/v1/register");
-            let resp = self.client.post(url).json(&payload).send().await.unwrap();
+        let quantize = Anonymization {
+            quantize: 500,
+            ..Default::default()
+        };
+        quantize.apply(&mut records);
+        assert_eq!(records, vec![record(1000), record(1500), record(0)]);
+
+        let noise = Anonymization {
+            quantize: 100,
+            noise: 200,
+            min_records: 10,
+        };
+        let mut noisy: Vec<UploadRecord> = (0..1000).map(|_| record(1500)).collect();
+        noise.apply(&mut noisy);
+        assert!(noisy.iter().all(|r| r.time % 100 == 0));
+        assert!(noisy.iter().any(|r| r.time != 1500));
+        let mean = noisy.iter().map(|r| r.time as f64).sum::<f64>() / 1000.0;
+        assert!((mean - 1500.0).abs() < 100.0);
+        assert!(!noise.allows(9));
+        assert!(noise.allows(10));
+    }
+
+    #[test]
+    fn node_health_backs_off() {
+        const NODE: &str = "https://survey.example.org/";
+        let store = NodeHealthStore::default();
+        assert!(store.is_available(NODE, 0));
+
+        store.failed(NODE, 0, "timed out".into(), false);
+        assert!(!store.is_available(NODE, BACKOFF - 1));
+        assert!(store.is_available(NODE, BACKOFF));
+        store.failed(NODE, BACKOFF, "timed out".into(), false);
+        assert_eq!(store.get(NODE).retry_at, Some(3 * BACKOFF));
+        for _ in 0..40 {
+            store.failed(NODE, 0, "timed out".into(), false);
         }
-        Ok(())
+        assert_eq!(store.get(NODE).retry_at, Some(MAX_BACKOFF));
+
+        store.uploaded(NODE, 10);
+        let health = store.get(NODE);
+        assert_eq!(health.reachable, Some(true));
+        assert_eq!(health.failures, 0);
+        assert_eq!(health.last_upload, Some(10));
+        assert!(store.is_available(NODE, 10));
+    }
+
+    #[actix_rt::test]
+    async fn survey_upload_survives_unreachable_nodes_pg() {
+        let data = pg::get_data().await;
+        survey_upload_survives_unreachable_nodes(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn survey_upload_survives_unreachable_nodes_maria() {
+        let data = maria::get_data().await;
+        survey_upload_survives_unreachable_nodes(data).await;
+    }
+
+    async fn survey_upload_survives_unreachable_nodes(data: ArcData) {
+        const NAME: &str = "surveyuploaduser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "surveyuploaduser@a.com";
+        // nothing listens on port 1
+        let node: url::Url = "http://127.0.0.1:1/".parse().unwrap();
+
+        let mut settings = data.settings.clone();
+        settings.survey = Some(SurveySettings {
+            nodes: vec![SurveyNode {
+                url: node.clone(),
+                region: None,
+            }],
+            rate_limit: 10,
+            instance_root_url: "http://localhost:7000".parse().unwrap(),
+            anonymization: Default::default(),
+        });
+        let data = &crate::data::Data::new(&settings, SecretsStore::default()).await;
+        let client = client();
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, key) = add_levels_util(data, NAME, PASSWORD).await;
+        data.db
+            .analytics_create_psuedo_id_if_not_exists(&key.key)
+            .await
+            .unwrap();
+        let psuedo_id = data
+            .db
+            .analytics_get_psuedo_id_from_capmaign_id(&key.key)
+            .await
+            .unwrap();
+        let analytics = db_core::CreatePerformanceAnalytics {
+            time: 1,
+            difficulty_factor: 1,
+            worker_type: "wasm".into(),
+        };
+        data.db.analysis_save(&key.key, &analytics).await.unwrap();
+
+        // registration is retried once the backoff expires
+        data.register_survey(&client).await.unwrap();
+        let token = data
+            .survey_secrets
+            .set_pending(node.as_str(), "next".into());
+        let token = token.unwrap();
+        assert_eq!(data.survey_secrets.get(&token).unwrap(), node.as_str());
+        let health = data.survey_health.get(node.as_str());
+        assert_eq!(health.reachable, Some(false));
+        assert_eq!(health.failures, 1);
+        assert!(health.error.is_some());
+
+        // the node is skipped while backing off
+        data.survey_secrets.set(node.to_string(), "secret".into());
+        data.upload_survey_job(&client).await.unwrap();
+        assert_eq!(data.survey_health.get(node.as_str()).failures, 1);
+        assert_eq!(data.survey_secrets.cursor(node.as_str(), &psuedo_id), 0);
     }
 }
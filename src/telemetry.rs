@@ -0,0 +1,53 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Logging and tracing setup
+//!
+//! With tracing disabled, logs are printed by pretty_env_logger. With tracing
+//! enabled, logs and spans go through a `tracing` subscriber and, when an OTLP
+//! endpoint is configured, spans are exported to an OpenTelemetry collector.
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::settings::Settings;
+
+/// initialize logger and, when enabled, tracing
+pub fn init(settings: &Settings) {
+    if !settings.tracing.enabled {
+        pretty_env_logger::init();
+        return;
+    }
+
+    let otel = settings.tracing.otlp_endpoint.as_ref().map(|endpoint| {
+        let resource = Resource::new(vec![KeyValue::new(
+            "service.name",
+            settings.tracing.service_name.clone(),
+        )]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(resource))
+            .install_batch(runtime::Tokio)
+            .expect("unable to install OTLP exporter");
+        tracing_opentelemetry::layer().with_tracer(tracer)
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel)
+        .init();
+}
+
+/// flush pending spans. No-op when OTLP export is disabled
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -143,10 +143,19 @@ macro_rules! get_app {
     ($data:expr) => {
         test::init_service(
             App::new()
+                .wrap($crate::security_headers::SecurityHeaders::new(
+                    &$data.settings,
+                ))
+                .wrap($crate::api::v2::compat::DeprecationHeaders)
+                .wrap($crate::agreements::AgreementGate)
+                .wrap($crate::admin::AdminGate)
+                .wrap($crate::body_limits::BodyLimits)
+                .wrap($crate::rate_limit::RateLimiter)
                 .wrap(get_identity_service(&$data.settings))
                 .wrap(actix_middleware::NormalizePath::new(
                     actix_middleware::TrailingSlash::Trim,
                 ))
+                .wrap($crate::request_id::RequestIds)
                 .configure($crate::routes::services)
                 //.data(std::sync::Arc::new(crate::data::Data::new().await))
                 .app_data(actix_web::web::Data::new($data.clone())),
@@ -183,6 +192,7 @@ pub async fn register(data: &ArcData, name: &str, email: &str, password: &str) {
         password: password.into(),
         confirm_password: password.into(),
         email: Some(email.into()),
+        invite: None,
     };
     let resp =
         test::call_service(&app, post_request!(&msg, ROUTES.auth.register).to_request())
@@ -202,6 +212,8 @@ pub async fn signin(
     let creds = Login {
         login: name.into(),
         password: password.into(),
+        totp: None,
+        challenge: None,
     };
     let signin_resp =
         test::call_service(&app, post_request!(&creds, ROUTES.auth.login).to_request())
@@ -228,6 +240,7 @@ pub async fn bad_post_req_test_no_auth<T: Serialize>(
     let resp_err: ErrorToResponse = test::read_body_json(resp).await;
     //println!("{}", txt.error);
     assert_eq!(resp_err.error, format!("{}", err));
+    assert_eq!(resp_err.code, err.code());
 }
 
 /// pub duplicate test
@@ -258,6 +271,7 @@ pub async fn bad_post_req_test<T: Serialize>(
     let resp_err: ErrorToResponse = test::read_body_json(resp).await;
     //println!("{}", txt.error);
     assert_eq!(resp_err.error, format!("{}", err));
+    assert_eq!(resp_err.code, err.code());
 }
 
 pub async fn add_levels_util(
@@ -304,3 +318,56 @@ pub fn get_level_data() -> CreateCaptcha {
         publish_benchmarks: false,
     }
 }
+
+/// solve PoW challenge `config`. Returns the nonce and hash of the solution
+pub fn solve(config: &crate::api::v1::pow::get_config::ApiPoWConfig) -> (u64, String) {
+    let pow = mcaptcha_pow_sha256::ConfigBuilder::default()
+        .salt(config.salt.clone())
+        .build()
+        .unwrap();
+    let work = pow
+        .prove_work(&config.string, config.difficulty_factor)
+        .unwrap();
+    (work.nonce, work.result)
+}
+
+/// fetch PoW config for `key`, solve it and return the validation token
+pub async fn get_validation_token(data: &ArcData, key: &str) -> String {
+    use crate::api::v1::pow::get_config::{ApiPoWConfig, GetConfigPayload};
+    use crate::api::v1::pow::verify_pow::{ApiWork, ValidationToken};
+
+    let app = get_app!(data).await;
+
+    let get_config_payload = GetConfigPayload {
+        key: key.into(),
+        version: None,
+        origin: None,
+    };
+    let get_config_resp = test::call_service(
+        &app,
+        post_request!(&get_config_payload, ROUTES.pow.get_config).to_request(),
+    )
+    .await;
+    assert_eq!(get_config_resp.status(), StatusCode::OK);
+    let config: ApiPoWConfig = test::read_body_json(get_config_resp).await;
+
+    let (nonce, result) = solve(&config);
+    let work = ApiWork {
+        string: config.string,
+        result,
+        nonce,
+        key: key.into(),
+        time: None,
+        worker_type: None,
+        correlation_id: None,
+    };
+
+    let pow_verify_resp = test::call_service(
+        &app,
+        post_request!(&work, ROUTES.pow.verify_pow).to_request(),
+    )
+    .await;
+    assert_eq!(pow_verify_resp.status(), StatusCode::OK);
+    let token: ValidationToken = test::read_body_json(pow_verify_resp).await;
+    token.token
+}
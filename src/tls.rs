@@ -0,0 +1,103 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Native TLS listener
+//!
+//! When `server.tls` is set, mCaptcha serves HTTPS itself, for small
+//! deployments without a reverse proxy. The certificate is either read from
+//! `server.tls.cert` and `server.tls.key`, or obtained and renewed over ACME
+//! with the TLS-ALPN-01 challenge, which is answered by the listener itself.
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use futures::StreamExt;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_acme::acme::ACME_TLS_ALPN_NAME;
+use rustls_acme::caches::DirCache;
+use rustls_acme::AcmeConfig;
+use rustls_pemfile::Item;
+
+use crate::settings::{Acme, Server};
+
+/// rustls configuration of the listener, if TLS is enabled. Panics when the
+/// certificate can't be loaded, like other invalid settings do on startup
+pub fn server_config(server: &Server) -> Option<ServerConfig> {
+    let tls = server.tls.as_ref()?;
+    let config = match (&tls.acme, &tls.cert, &tls.key) {
+        (Some(acme), _, _) => self::acme(acme, &server.domain),
+        (None, Some(cert), Some(key)) => load(cert, key).unwrap_or_else(|e| {
+            panic!("Unable to load TLS certificate {cert} and key {key}: {e}")
+        }),
+        _ => unreachable!("server.tls is validated when settings are loaded"),
+    };
+    Some(config)
+}
+
+/// read PEM encoded certificate chain and private key
+pub fn load(cert: &str, key: &str) -> io::Result<ServerConfig> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    let mut reader = BufReader::new(File::open(key)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => {
+                break PrivateKey(key)
+            }
+            Some(_) => continue,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "no private key found",
+                ))
+            }
+        }
+    };
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// configuration that serves certificates of `domain` obtained over ACME.
+/// Certificates are ordered and renewed in the background
+fn acme(acme: &Acme, domain: &str) -> ServerConfig {
+    let mut state = AcmeConfig::new([domain])
+        .contact(acme.email.iter().map(|email| format!("mailto:{email}")))
+        .cache(DirCache::new(acme.cache_dir.clone()))
+        .directory_lets_encrypt(!acme.staging)
+        .state();
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(state.resolver());
+    // HTTP ALPN protocols are added by actix
+    config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+
+    actix::spawn(async move {
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(event) => log::info!("ACME: {event:?}"),
+                Err(e) => log::error!("ACME error: {e:?}"),
+            }
+        }
+    });
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_fails_on_missing_files() {
+        let err = load("/nonexistent/cert.pem", "/nonexistent/key.pem").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
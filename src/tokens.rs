@@ -0,0 +1,220 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Ledger of issued validation tokens
+//!
+//! libmcaptcha stores validation tokens in its cache and deletes them
+//! once they are verified, so it can't answer questions about a token's
+//! lifecycle. The ledger records issuance and consumption alongside it.
+//! It is local to the instance.
+//!
+//! Tokens are bound to the solving client's IP address. Only a
+//! [keyed hash](crate::client_ip::hash_ip) of the address is stored. It is
+//! keyed with `server.cookie_secret`, which every instance of a deployment has
+//! and which, unlike `captcha.salt`, isn't sent to widgets: instances that
+//! share validation tokens compute the same hashes, and nobody else can. The
+//! hash is stored next to tokens that are shared: in the database in a
+//! [cluster](crate::cluster), and in [memcached](crate::memcached). Redis
+//! holds tokens in libmcaptcha's format, so with Redis, only the instance that
+//! issued a token knows its binding.
+//!
+//! When the protected backend passes the client's address along with a token,
+//! the token is only valid if it is known to be bound to that address. Tokens
+//! whose binding is unknown are rejected.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+
+use crate::cluster;
+use crate::errors::*;
+use crate::AppData;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// lifecycle of a validation token
+pub struct TokenInfo {
+    /// sitekey for which the token was issued
+    pub key: String,
+    /// unix timestamp of issuance
+    pub issued: i64,
+    /// validity of the token in seconds
+    pub ttl: u64,
+    /// unix timestamp at which the token was consumed
+    pub consumed: Option<i64>,
+    /// hash of the IP address of the client that solved the PoW
+    pub ip_hash: String,
+    /// country of the client that solved the PoW, when GeoIP is enabled
+    pub country: Option<String>,
+    /// whether the token was issued for a completed fallback challenge,
+    /// without PoW. See [crate::fallback]
+    #[serde(default)]
+    pub fallback: bool,
+}
+
+impl TokenInfo {
+    /// seconds until the token expires
+    pub fn remaining_ttl(&self, now: i64) -> u64 {
+        let expires = self.issued + self.ttl as i64;
+        if expires > now {
+            (expires - now) as u64
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenLedger {
+    store: Arc<RwLock<HashMap<String, TokenInfo>>>,
+    secret: String,
+}
+
+impl TokenLedger {
+    /// ledger that hashes IP addresses with `secret`, which has to stay on the
+    /// server
+    pub fn new(secret: &str) -> Self {
+        Self {
+            store: Arc::default(),
+            secret: secret.into(),
+        }
+    }
+
+    /// keyed hash of IP address `ip`
+    pub fn hash_ip(&self, ip: &str) -> String {
+        crate::client_ip::hash_ip(&self.secret, ip)
+    }
+
+    /// record newly issued token
+    pub fn issue(
+        &self,
+        token: &str,
+        key: &str,
+        ttl: u64,
+        ip: &str,
+        country: Option<String>,
+    ) {
+        self.record(token, key, ttl, ip, country, false);
+    }
+
+    /// record newly issued token of a completed fallback challenge
+    pub fn issue_fallback(
+        &self,
+        token: &str,
+        key: &str,
+        ttl: u64,
+        ip: &str,
+        country: Option<String>,
+    ) {
+        self.record(token, key, ttl, ip, country, true);
+    }
+
+    fn record(
+        &self,
+        token: &str,
+        key: &str,
+        ttl: u64,
+        ip: &str,
+        country: Option<String>,
+        fallback: bool,
+    ) {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let info = TokenInfo {
+            key: key.into(),
+            issued: now,
+            ttl,
+            consumed: None,
+            ip_hash: self.hash_ip(ip),
+            country,
+            fallback,
+        };
+        let mut w = self.store.write().unwrap();
+        w.retain(|_, t| t.remaining_ttl(now) > 0);
+        w.insert(token.into(), info);
+        drop(w);
+    }
+
+    /// mark token as consumed
+    pub fn consume(&self, token: &str) {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut w = self.store.write().unwrap();
+        if let Some(t) = w.get_mut(token) {
+            t.consumed = Some(now);
+        }
+        drop(w);
+    }
+
+    /// get token details
+    pub fn get(&self, token: &str) -> Option<TokenInfo> {
+        let r = self.store.read().unwrap();
+        r.get(token).cloned()
+    }
+
+    /// hash of the IP address that `token` of sitekey `key` was issued to, if
+    /// this instance issued it
+    fn ip_hash(&self, key: &str, token: &str) -> Option<String> {
+        let r = self.store.read().unwrap();
+        r.get(token)
+            .filter(|t| t.key == key)
+            .map(|t| t.ip_hash.clone())
+    }
+}
+
+/// check that `token` of sitekey `key` is bound to IP address `ip`. Bindings
+/// that this instance doesn't know are looked up where the token is shared.
+/// Tokens whose binding can't be found don't match
+pub async fn ip_matches(
+    data: &AppData,
+    key: &str,
+    token: &str,
+    ip: &str,
+) -> ServiceResult<bool> {
+    let ip_hash = match data.tokens.ip_hash(key, token) {
+        Some(ip_hash) => Some(ip_hash),
+        None if cluster::shares_challenges(data) => {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            data.db.get_cluster_token_ip(key, token, now).await?
+        }
+        None => data.captcha.token_binding(token).await?,
+    };
+    Ok(ip_hash.map_or(false, |h| h == data.tokens.hash_ip(ip)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_ledger_works() {
+        let ledger = TokenLedger::new("secret");
+        ledger.issue("token", "key", 30, "192.0.2.1", Some("DE".into()));
+        let info = ledger.get("token").unwrap();
+        assert_eq!(info.key, "key");
+        assert_eq!(info.country.as_deref(), Some("DE"));
+        assert!(info.consumed.is_none());
+        assert!(!info.fallback);
+        assert_eq!(info.remaining_ttl(info.issued + 10), 20);
+        assert_eq!(info.remaining_ttl(info.issued + 40), 0);
+
+        let ip_hash = ledger.hash_ip("192.0.2.1");
+        assert_eq!(ledger.ip_hash("key", "token"), Some(ip_hash.clone()));
+        assert_ne!(ledger.hash_ip("192.0.2.2"), ip_hash);
+        assert_ne!(TokenLedger::new("other").hash_ip("192.0.2.1"), ip_hash);
+        assert_eq!(ledger.ip_hash("otherkey", "token"), None);
+        assert_eq!(ledger.ip_hash("key", "nonexistent"), None);
+
+        ledger.consume("token");
+        assert!(ledger.get("token").unwrap().consumed.is_some());
+        assert!(ledger.get("nonexistent").is_none());
+
+        ledger.issue_fallback("fallback", "key", 30, "192.0.2.1", None);
+        assert!(ledger.get("fallback").unwrap().fallback);
+
+        // expired tokens are pruned
+        ledger.issue("expired", "key", 0, "192.0.2.1", None);
+        ledger.issue("token2", "key", 30, "192.0.2.1", None);
+        assert!(ledger.get("expired").is_none());
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Trial sitekeys
+//!
+//! When `trial.enabled` is set, anyone can create a sitekey without an account
+//! to evaluate mCaptcha. Each trial sitekey belongs to a throwaway account,
+//! which is deleted along with the sitekey by [TrialCleanup] once it is
+//! `trial.ttl` seconds old. At most `trial.max_active` trial sitekeys exist at
+//! once.
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::errors::DBError;
+use db_core::{CreateCaptcha as DBCreateCaptcha, TrafficPattern};
+use libmcaptcha::master::messages::RemoveCaptcha;
+use serde::{Deserialize, Serialize};
+use sqlx::types::time::OffsetDateTime;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+
+use crate::api::v1::account::delete::runners::delete_user;
+use crate::api::v1::auth::runners::UNUSABLE_HASH;
+use crate::api::v1::mcaptcha::{easy::calculate, get_random};
+use crate::jobs::TRIAL_CLEANUP_JOB;
+use crate::settings::KeyAlphabet;
+use crate::*;
+
+use errors::*;
+
+/// prefix of the usernames of trial accounts
+pub const TRIAL_USER_PREFIX: &str = "trial-";
+/// description of trial sitekeys
+pub const TRIAL_DESCRIPTION: &str = "Trial sitekey";
+/// interval, in seconds, at which expired trial sitekeys are deleted
+const CLEANUP_INTERVAL: u32 = 60;
+
+/// traffic pattern that the difficulty of trial sitekeys is computed from
+const TRIAL_TRAFFIC: TrafficPattern = TrafficPattern {
+    avg_traffic: 50,
+    peak_sustainable_traffic: 500,
+    broke_my_site_traffic: None,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// A freshly created trial sitekey
+pub struct TrialSitekey {
+    /// sitekey
+    pub key: String,
+    /// secret of the trial account, used to verify tokens
+    pub secret: String,
+    /// unix timestamp after which the sitekey is deleted
+    pub expires: i64,
+}
+
+/// Create a trial sitekey, along with the throwaway account that owns it
+pub async fn create(data: &AppData) -> ServiceResult<TrialSitekey> {
+    let settings = &data.settings.trial;
+    if !settings.enabled {
+        return Err(ServiceError::TrialsDisabled);
+    }
+    if data.db.count_trials().await? >= settings.max_active {
+        return Err(ServiceError::TrialLimitReached);
+    }
+
+    let mut username;
+    let mut secret;
+    loop {
+        username = format!(
+            "{TRIAL_USER_PREFIX}{}",
+            keys::generate(KeyAlphabet::Lowercase, 16)
+        );
+        secret = get_random(32);
+        let p = db_core::Register {
+            username: &username,
+            // trial accounts can't sign in
+            hash: UNUSABLE_HASH,
+            email: None,
+            secret: &secret,
+        };
+        match data.db.register(&p).await {
+            Ok(_) => break,
+            Err(DBError::SecretTaken) | Err(DBError::UsernameTaken) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let expires = OffsetDateTime::now_utc().unix_timestamp() + settings.ttl as i64;
+    let key = match add_sitekey(data, &username, expires).await {
+        Ok(key) => key,
+        Err(e) => {
+            delete_user(&username, data).await?;
+            return Err(e);
+        }
+    };
+    log::info!("Created trial sitekey {key}, expires at {expires}");
+    Ok(TrialSitekey {
+        key,
+        secret,
+        expires,
+    })
+}
+
+/// add the sitekey of trial account `username`, which expires at `expires`
+async fn add_sitekey(
+    data: &AppData,
+    username: &str,
+    expires: i64,
+) -> ServiceResult<String> {
+    data.db.add_trial(username, expires).await?;
+
+    let strategy = &data.settings.captcha.default_difficulty_strategy;
+    let levels = calculate(&TRIAL_TRAFFIC, strategy)?;
+    let duration = strategy.duration as i32;
+    let key = keys::unique(data, |key| async move {
+        let p = DBCreateCaptcha {
+            description: TRIAL_DESCRIPTION,
+            key: &key,
+            duration,
+        };
+        data.db.create_captcha(username, &p).await
+    })
+    .await?;
+    data.db.add_captcha_levels(username, &key, &levels).await?;
+
+    // nobody can set allowed domains of trial sitekeys, so they are served
+    // until they expire
+    if data.settings.captcha.require_allowed_domains {
+        data.db.set_allowed_domains_deadline(&key, expires).await?;
+    }
+    Ok(key)
+}
+
+/// Deletes trial sitekeys, and the accounts that own them, once they expire
+pub struct TrialCleanup {
+    tx: Sender<()>,
+}
+
+impl TrialCleanup {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// delete expired trial accounts and return their number
+    pub async fn cleanup(data: &AppData) -> ServiceResult<usize> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let users = data.db.get_expired_trials(now).await?;
+        for username in users.iter() {
+            let sitekeys = data.db.get_all_user_captchas(username).await?;
+            match delete_user(username, data).await {
+                Ok(_) | Err(ServiceError::AccountNotFound) => (),
+                Err(e) => return Err(e),
+            }
+            for sitekey in sitekeys.into_iter() {
+                if let Err(err) = data.captcha.remove(RemoveCaptcha(sitekey.key)).await {
+                    log::error!(
+                        "Error while trying to remove captcha from cache {}",
+                        err
+                    );
+                }
+            }
+        }
+        if !users.is_empty() {
+            log::info!("Deleted {} expired trial sitekeys", users.len());
+        }
+        Ok(users.len())
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        data.jobs
+            .register(TRIAL_CLEANUP_JOB, CLEANUP_INTERVAL as u64);
+        let mut exit = false;
+        let fut = async move {
+            loop {
+                if exit {
+                    break;
+                }
+                for _ in 0..CLEANUP_INTERVAL {
+                    if Self::can_run(&mut rx) {
+                        sleep(Duration::new(1, 0)).await;
+                        continue;
+                    } else {
+                        exit = true;
+                        break;
+                    }
+                }
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = Self::cleanup(&data).await.map(|_| ());
+                if let Some(err) = res.as_ref().err() {
+                    log::error!(
+                        "Error while deleting expired trial sitekeys: {:?}",
+                        err
+                    );
+                }
+                data.jobs
+                    .finished(TRIAL_CLEANUP_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Registration of captchas with the master at boot
+//!
+//! Captchas are registered with the master lazily, when their configuration is
+//! first requested, which costs the visitor a round of database queries and
+//! exposes them to its error paths. When `captcha.warm_up` is set, captchas are
+//! registered before the server starts instead: all of them, or the
+//! `captcha.warm_up_limit` most recently fetched ones. Captchas that are
+//! already registered, because they were [restored](crate::shutdown) or are
+//! kept in Redis, are left as they are so that their visitor counts are kept.
+use std::collections::HashSet;
+use std::time::Instant;
+
+use crate::api::v1::pow::get_config::init_mcaptcha;
+use crate::consistency::cached_captchas;
+use crate::data::SystemGroup;
+use crate::errors::*;
+use crate::AppData;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// outcome of a warm-up
+pub struct WarmUpReport {
+    /// captchas that were registered with the master
+    pub loaded: usize,
+    /// captchas that were registered already
+    pub skipped: usize,
+    /// captchas that couldn't be registered
+    pub failed: Vec<String>,
+}
+
+/// keys of captchas registered with the master
+async fn registered(data: &AppData) -> ServiceResult<HashSet<String>> {
+    match (&data.captcha, &data.settings.redis) {
+        (SystemGroup::Redis(_), Some(redis)) => {
+            let keys = cached_captchas(&redis.connection_url())
+                .await
+                .map_err(|e| {
+                    log::error!("Unable to list captchas in Redis: {e}");
+                    ServiceError::InternalServerError
+                })?;
+            Ok(keys.into_iter().collect())
+        }
+        _ => {
+            let sitekeys = data.captcha.get_internal_data().await?;
+            Ok(sitekeys.unwrap_or_default().into_keys().collect())
+        }
+    }
+}
+
+/// register the `limit` most recently fetched captchas with the master, or
+/// all of them when `limit` is 0
+pub async fn warm_up(data: &AppData, limit: usize) -> ServiceResult<WarmUpReport> {
+    let timer = Instant::now();
+    let keys = if limit == 0 {
+        data.db.get_all_captcha_keys().await?
+    } else {
+        data.db.get_recently_active_captcha_keys(limit).await?
+    };
+    let registered = registered(data).await?;
+
+    let mut report = WarmUpReport::default();
+    for key in keys.iter() {
+        if registered.contains(key) {
+            report.skipped += 1;
+            continue;
+        }
+        match init_mcaptcha(data, key).await {
+            Ok(_) => report.loaded += 1,
+            Err(e) => {
+                log::error!("Unable to warm up captcha {key}: {e}");
+                report.failed.push(key.clone());
+            }
+        }
+    }
+    log::info!(
+        "Warmed up {} captchas in {}ms, {} registered already, {} failed",
+        report.loaded,
+        timer.elapsed().as_millis(),
+        report.skipped,
+        report.failed.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn warm_up_works_pg() {
+        let data = crate::tests::pg::get_data().await;
+        warm_up_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn warm_up_works_maria() {
+        let data = crate::tests::maria::get_data().await;
+        warm_up_works(data).await;
+    }
+
+    async fn warm_up_works(data: ArcData) {
+        const NAME: &str = "warmupusr";
+        const PASSWORD: &str = "testingpas";
+        const EMAIL: &str = "warmupusr@a.com";
+
+        // registrations are read back from the embedded master
+        let mut settings = data.settings.clone();
+        settings.redis = None;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app_data = AppData::new(data.clone());
+
+        let report = warm_up(&app_data, 1).await.unwrap();
+        assert_eq!(report.loaded, 1);
+        assert!(report.failed.is_empty());
+        let sitekeys = data.captcha.get_internal_data().await.unwrap().unwrap();
+        assert!(sitekeys.contains_key(&token_key.key));
+
+        // registered captchas are left as they are
+        let report = warm_up(&app_data, 0).await.unwrap();
+        assert_eq!(report.loaded, 0);
+        assert_eq!(report.skipped, 1);
+    }
+}
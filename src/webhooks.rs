@@ -0,0 +1,746 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Webhook notifications on verification events and user notifications
+//!
+//! Verification handlers enqueue events without blocking; the dispatcher
+//! looks up the sitekey's webhook, POSTs the signed payload and logs every
+//! attempt. Failed deliveries are retried with exponential backoff.
+//!
+//! Notifications are delivered the same way to the webhook of the user that
+//! receives them, if they set one.
+//!
+//! Webhook URLs are set by users, so they could point requests at services
+//! that are only reachable from the instance. Webhooks are only delivered to
+//! public addresses: URLs are checked when they are set and before every
+//! delivery, names are resolved to public addresses only, when connecting, and
+//! redirects aren't followed.
+//!
+//! The queue is bounded; events that arrive while it is full are dropped.
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use actix::clock::sleep;
+use actix::spawn;
+use db_core::errors::DBError;
+use db_core::{AddNotification, CreateWebhookDelivery};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::types::time::OffsetDateTime;
+use tokio::net::lookup_host;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot::{channel, error::TryRecvError, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+use url::{Host, Url};
+
+use crate::alerts::Alert;
+use crate::client_ip::IpRange;
+use crate::errors::*;
+use crate::fraud::FraudSignal;
+use crate::jobs::WEBHOOK_JOB;
+use crate::AppData;
+
+/// header carrying the HMAC-SHA256 signature of the request body
+pub const SIGNATURE_HEADER: &str = "X-mCaptcha-Signature";
+/// maximum number of delivery attempts per event
+pub const MAX_ATTEMPTS: u32 = 5;
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// events that can be queued for delivery at once
+pub const QUEUE_LEN: usize = 10_000;
+
+/// ranges of addresses that aren't public: unspecified, private, shared,
+/// loopback, link-local(which has cloud metadata services), documentation,
+/// multicast and otherwise reserved addresses, and NAT64 addresses, which can
+/// map to any of them
+const NON_PUBLIC: [&str; 23] = [
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "64:ff9b::/96",
+    "100::/64",
+    "2001:db8::/32",
+    "fc00::/7",
+    "fe80::/10",
+    "fec0::/10",
+    "ff00::/8",
+];
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+/// verification events that are delivered to webhooks
+pub enum WebhookEvent {
+    /// PoW was solved and a validation token was issued
+    Solve,
+    /// validation token was confirmed by the sitekey owner's backend
+    Confirm,
+    /// difficulty factor of the sitekey went up
+    Escalation,
+    /// submission raised a fraud heuristic
+    #[serde(rename = "fraud.suspected")]
+    FraudSuspected,
+    /// traffic of the sitekey departed from its baseline
+    #[serde(rename = "anomaly.detected")]
+    AnomalyDetected,
+    /// notification was created for the user
+    Notification,
+}
+
+impl WebhookEvent {
+    /// name of the event, as stored in delivery logs
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Solve => "solve",
+            Self::Confirm => "confirm",
+            Self::Escalation => "escalation",
+            Self::FraudSuspected => "fraud.suspected",
+            Self::AnomalyDetected => "anomaly.detected",
+            Self::Notification => "notification",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// body of webhook requests
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+    pub key: String,
+    pub time: i64,
+    pub difficulty_factor: Option<u32>,
+    /// heuristic that was raised, on [WebhookEvent::FraudSuspected] events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<FraudSignal>,
+    /// anomaly that was detected, on [WebhookEvent::AnomalyDetected] events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anomaly: Option<Alert>,
+}
+
+/// sign webhook request body. Receivers should compute the same value over
+/// the raw body and compare it against the [SIGNATURE_HEADER] header
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// is `ip` a public address, that webhooks can be delivered to
+pub fn is_public(ip: IpAddr) -> bool {
+    !NON_PUBLIC
+        .iter()
+        .map(|r| r.parse::<IpRange>().expect("ranges are valid"))
+        .any(|r| r.contains(ip))
+}
+
+/// public addresses of `host`. Hosts with any address that isn't public are
+/// rejected, so that they can't be resolved to a public address when checked
+/// and to another one when connected to
+async fn lookup(host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = lookup_host((host, port)).await?.collect();
+    if addrs.is_empty() || !addrs.iter().all(|a| is_public(a.ip())) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{host} doesn't resolve to public addresses"),
+        ));
+    }
+    Ok(addrs)
+}
+
+/// check that webhook URL `url` is an HTTP(S) URL of a public address
+pub async fn check_url(url: &Url) -> ServiceResult<()> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(ServiceError::NotAUrl);
+    }
+    let port = url.port_or_known_default().ok_or(ServiceError::NotAUrl)?;
+    let public = match url.host().ok_or(ServiceError::NotAUrl)? {
+        Host::Ipv4(ip) => is_public(ip.into()),
+        Host::Ipv6(ip) => is_public(ip.into()),
+        Host::Domain(host) => lookup(host, port).await.is_ok(),
+    };
+    if !public {
+        return Err(ServiceError::WebhookAddressNotAllowed);
+    }
+    Ok(())
+}
+
+/// resolver of webhook clients, which only resolves names to public addresses
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            // the client sets the port of the URL
+            let addrs = lookup(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// HTTP client that webhooks are delivered with
+pub fn client() -> Client {
+    Client::builder()
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(Policy::none())
+        .build()
+        .expect("webhook client is valid")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// body of notification webhook requests
+pub struct NotificationPayload {
+    /// always [WebhookEvent::Notification]
+    pub event: WebhookEvent,
+    pub to: String,
+    pub from: String,
+    pub heading: String,
+    pub message: String,
+    pub time: i64,
+}
+
+impl NotificationPayload {
+    pub fn new(n: &AddNotification<'_>) -> Self {
+        Self {
+            event: WebhookEvent::Notification,
+            to: n.to.to_string(),
+            from: n.from.to_string(),
+            heading: n.heading.to_string(),
+            message: n.message.to_string(),
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    pub payload: WebhookPayload,
+    pub attempt: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct NotificationDelivery {
+    pub payload: NotificationPayload,
+    pub attempt: u32,
+}
+
+#[derive(Clone, Debug)]
+enum Queued {
+    Sitekey(Delivery),
+    Notification(NotificationDelivery),
+}
+
+/// Queue of webhook events awaiting delivery
+pub struct WebhookQueue {
+    tx: mpsc::Sender<Queued>,
+    rx: Mutex<Option<mpsc::Receiver<Queued>>>,
+    difficulty: Arc<RwLock<HashMap<String, u32>>>,
+}
+
+impl Default for WebhookQueue {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        Self {
+            tx,
+            rx: Mutex::new(Some(rx)),
+            difficulty: Arc::new(RwLock::new(HashMap::default())),
+        }
+    }
+}
+
+impl WebhookQueue {
+    /// enqueue event for delivery
+    pub fn enqueue(
+        &self,
+        event: WebhookEvent,
+        key: &str,
+        difficulty_factor: Option<u32>,
+    ) {
+        let payload = WebhookPayload {
+            event,
+            key: key.to_string(),
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            difficulty_factor,
+            signal: None,
+            anomaly: None,
+        };
+        self.send(payload);
+    }
+
+    /// enqueue [WebhookEvent::FraudSuspected] event for delivery
+    pub fn enqueue_fraud(
+        &self,
+        key: &str,
+        signal: FraudSignal,
+        difficulty_factor: Option<u32>,
+    ) {
+        let payload = WebhookPayload {
+            event: WebhookEvent::FraudSuspected,
+            key: key.to_string(),
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            difficulty_factor,
+            signal: Some(signal),
+            anomaly: None,
+        };
+        self.send(payload);
+    }
+
+    /// enqueue [WebhookEvent::AnomalyDetected] event for delivery
+    pub fn enqueue_anomaly(&self, key: &str, anomaly: Alert) {
+        let payload = WebhookPayload {
+            event: WebhookEvent::AnomalyDetected,
+            key: key.to_string(),
+            time: OffsetDateTime::now_utc().unix_timestamp(),
+            difficulty_factor: None,
+            signal: None,
+            anomaly: Some(anomaly),
+        };
+        self.send(payload);
+    }
+
+    /// enqueue notification for delivery to the receiver's webhook
+    pub fn enqueue_notification(&self, n: &AddNotification<'_>) {
+        Self::push(
+            &self.tx,
+            Queued::Notification(NotificationDelivery {
+                payload: NotificationPayload::new(n),
+                attempt: 1,
+            }),
+        );
+    }
+
+    fn send(&self, payload: WebhookPayload) {
+        Self::push(
+            &self.tx,
+            Queued::Sitekey(Delivery {
+                payload,
+                attempt: 1,
+            }),
+        );
+    }
+
+    /// queue event without waiting; it's dropped when the queue is full
+    fn push(tx: &mpsc::Sender<Queued>, queued: Queued) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = tx.try_send(queued) {
+            log::warn!("Webhook queue is full, dropping event");
+        }
+    }
+
+    /// record the difficulty factor served for a sitekey and check if it went up
+    /// since the last time it was served
+    pub fn escalated(&self, key: &str, difficulty_factor: u32) -> bool {
+        if let Some(last) = self.difficulty.read().unwrap().get(key) {
+            if *last == difficulty_factor {
+                return false;
+            }
+        }
+        let mut difficulty = self.difficulty.write().unwrap();
+        let prev = difficulty.insert(key.to_string(), difficulty_factor);
+        matches!(prev, Some(prev) if prev < difficulty_factor)
+    }
+
+    /// schedule failed delivery for another attempt
+    fn retry(&self, mut delivery: Delivery) {
+        let backoff = Duration::from_secs(2u64.pow(delivery.attempt));
+        delivery.attempt += 1;
+        self.send_after(Queued::Sitekey(delivery), backoff);
+    }
+
+    /// schedule failed notification delivery for another attempt
+    fn retry_notification(&self, mut delivery: NotificationDelivery) {
+        let backoff = Duration::from_secs(2u64.pow(delivery.attempt));
+        delivery.attempt += 1;
+        self.send_after(Queued::Notification(delivery), backoff);
+    }
+
+    fn send_after(&self, queued: Queued, backoff: Duration) {
+        let tx = self.tx.clone();
+        spawn(async move {
+            sleep(backoff).await;
+            Self::push(&tx, queued);
+        });
+    }
+
+    fn take_receiver(&self) -> Option<mpsc::Receiver<Queued>> {
+        self.rx.lock().unwrap().take()
+    }
+}
+
+/// POST signed `body` to `url` and return the response status, if any, and
+/// whether the delivery succeeded
+async fn post(
+    client: &Client,
+    url: &str,
+    secret: &str,
+    body: Vec<u8>,
+) -> (Option<u16>, bool) {
+    // the URL may have been set before it was checked, or its host may resolve
+    // to other addresses since. The resolver of [client] checks names again
+    // when connecting
+    let allowed = match Url::parse(url) {
+        Ok(url) => check_url(&url).await,
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = allowed {
+        log::warn!("Not delivering webhook to {url}: {e}");
+        return (None, false);
+    }
+
+    let signature = sign(secret, &body);
+    let res = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .timeout(TIMEOUT)
+        .body(body)
+        .send()
+        .await;
+
+    let status = res.as_ref().ok().map(|r| r.status().as_u16());
+    let success = matches!(&res, Ok(r) if r.status().is_success());
+    (status, success)
+}
+
+pub struct WebhookDispatcher {
+    tx: Sender<()>,
+}
+
+impl WebhookDispatcher {
+    pub async fn spawn(data: AppData) -> ServiceResult<(Self, JoinHandle<()>)> {
+        let (tx, rx) = channel();
+        let handle = Self::run(data, rx).await?;
+        let d = Self { tx };
+
+        Ok((d, handle))
+    }
+
+    #[allow(dead_code)]
+    pub fn abort(mut self) {
+        self.tx.send(());
+    }
+
+    /// deliver event to the sitekey's webhook, if one is set, and log the attempt
+    pub async fn deliver(
+        data: &AppData,
+        client: &Client,
+        delivery: Delivery,
+    ) -> ServiceResult<()> {
+        let key = &delivery.payload.key;
+        let webhook = match data.db.get_webhook(key).await {
+            Ok(webhook) => webhook,
+            Err(DBError::WebhookNotFound) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if delivery.payload.event == WebhookEvent::Escalation && !webhook.on_escalation {
+            return Ok(());
+        }
+
+        let body = serde_json::to_vec(&delivery.payload).unwrap();
+        let (status, success) = post(client, &webhook.url, &webhook.secret, body).await;
+        let log = CreateWebhookDelivery {
+            event: delivery.payload.event.name(),
+            status,
+            attempt: delivery.attempt,
+            success,
+        };
+        data.db.record_webhook_delivery(key, &log).await?;
+
+        if !success && delivery.attempt < MAX_ATTEMPTS {
+            data.webhooks.retry(delivery);
+        }
+        Ok(())
+    }
+
+    /// deliver notification to the receiver's webhook, if one is set, and log
+    /// the attempt
+    pub async fn deliver_notification(
+        data: &AppData,
+        client: &Client,
+        delivery: NotificationDelivery,
+    ) -> ServiceResult<()> {
+        let username = &delivery.payload.to;
+        let webhook = match data.db.get_notification_webhook(username).await {
+            Ok(webhook) => webhook,
+            Err(DBError::WebhookNotFound) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let body = serde_json::to_vec(&delivery.payload).unwrap();
+        let (status, success) = post(client, &webhook.url, &webhook.secret, body).await;
+        let log = CreateWebhookDelivery {
+            event: delivery.payload.event.name(),
+            status,
+            attempt: delivery.attempt,
+            success,
+        };
+        data.db
+            .record_notification_webhook_delivery(username, &log)
+            .await?;
+
+        if !success && delivery.attempt < MAX_ATTEMPTS {
+            data.webhooks.retry_notification(delivery);
+        }
+        Ok(())
+    }
+
+    fn can_run(rx: &mut Receiver<()>) -> bool {
+        match rx.try_recv() {
+            Err(TryRecvError::Empty) => true,
+            _ => false,
+        }
+    }
+
+    pub async fn run(
+        data: AppData,
+        mut rx: Receiver<()>,
+    ) -> ServiceResult<JoinHandle<()>> {
+        let mut queue = data
+            .webhooks
+            .take_receiver()
+            .expect("webhook dispatcher is already running");
+        data.jobs.register(WEBHOOK_JOB, 1);
+        let client = client();
+        let fut = async move {
+            while Self::can_run(&mut rx) {
+                let next = timeout(Duration::new(1, 0), queue.recv()).await;
+                let delivery = match next {
+                    Ok(Some(delivery)) => delivery,
+                    Ok(None) => break,
+                    Err(_) => continue,
+                };
+
+                let started = OffsetDateTime::now_utc();
+                let timer = Instant::now();
+                let res = match delivery {
+                    Queued::Sitekey(d) => Self::deliver(&data, &client, d).await,
+                    Queued::Notification(d) => {
+                        Self::deliver_notification(&data, &client, d).await
+                    }
+                };
+                if let Some(err) = res.as_ref().err() {
+                    log::error!("Tried to deliver webhook {:?}", err);
+                }
+                data.jobs
+                    .finished(WEBHOOK_JOB, started, timer.elapsed(), &res);
+            }
+        };
+        let handle = spawn(fut);
+        Ok(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+    use crate::*;
+
+    #[test]
+    fn signature_works() {
+        let sig = sign("secret", b"{}");
+        assert!(sig.starts_with("sha256="));
+        assert_eq!(sig.len(), "sha256=".len() + 64);
+        assert_eq!(sig, sign("secret", b"{}"));
+        assert_ne!(sig, sign("secret2", b"{}"));
+        assert_ne!(sig, sign("secret", b"{ }"));
+    }
+
+    #[actix_rt::test]
+    async fn public_addresses_work() {
+        for ip in ["1.1.1.1", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        let check = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            async move { check_url(&url).await }
+        };
+        assert!(check("https://1.1.1.1/hook").await.is_ok());
+        assert_eq!(
+            check("http://localhost:8080/").await,
+            Err(ServiceError::WebhookAddressNotAllowed)
+        );
+        assert_eq!(
+            check("http://[fe80::1]/").await,
+            Err(ServiceError::WebhookAddressNotAllowed)
+        );
+        assert_eq!(check("ftp://1.1.1.1/").await, Err(ServiceError::NotAUrl));
+
+        // delivery to addresses that aren't public isn't attempted
+        let (status, success) =
+            post(&client(), "http://127.0.0.1:1/", "s", vec![]).await;
+        assert_eq!((status, success), (None, false));
+    }
+
+    #[test]
+    fn queue_is_bounded() {
+        let queue = WebhookQueue::default();
+        let mut rx = queue.take_receiver().unwrap();
+        for _ in 0..QUEUE_LEN + 1 {
+            queue.enqueue(WebhookEvent::Solve, "key", None);
+        }
+        let mut queued = 0;
+        while rx.try_recv().is_ok() {
+            queued += 1;
+        }
+        assert_eq!(queued, QUEUE_LEN);
+    }
+
+    #[test]
+    fn escalation_works() {
+        let queue = WebhookQueue::default();
+        assert!(!queue.escalated("key", 50));
+        assert!(!queue.escalated("key", 50));
+        assert!(queue.escalated("key", 500));
+        assert!(!queue.escalated("key", 50));
+    }
+
+    #[test]
+    fn notifications_are_queued() {
+        let queue = WebhookQueue::default();
+        let mut rx = queue.take_receiver().unwrap();
+        let n = db_core::AddNotification {
+            to: "receiver",
+            from: "sender",
+            heading: "heading",
+            message: "message",
+        };
+        queue.enqueue_notification(&n);
+        match rx.try_recv().unwrap() {
+            Queued::Notification(d) => {
+                assert_eq!(d.payload.event, WebhookEvent::Notification);
+                assert_eq!(d.payload.to, n.to);
+                assert_eq!(d.payload.from, n.from);
+                assert_eq!(d.attempt, 1);
+            }
+            _ => panic!("notification wasn't queued"),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn webhook_delivery_works_pg() {
+        let data = pg::get_data().await;
+        webhook_delivery_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn webhook_delivery_works_maria() {
+        let data = maria::get_data().await;
+        webhook_delivery_works(data).await;
+    }
+
+    async fn webhook_delivery_works(data: ArcData) {
+        const NAME: &str = "webhookdeliveryuser";
+        const PASSWORD: &str = "longpassworddomain";
+        const EMAIL: &str = "webhookdeliveryuser@a.com";
+        let data = &data;
+
+        delete_user(data, NAME).await;
+        register_and_signin(data, NAME, EMAIL, PASSWORD).await;
+        let (_, _, token_key) = add_levels_util(data, NAME, PASSWORD).await;
+        let app_data = actix_web::web::Data::new(data.clone());
+        let client = Client::new();
+
+        let delivery = Delivery {
+            payload: WebhookPayload {
+                event: WebhookEvent::Solve,
+                key: token_key.key.clone(),
+                time: 0,
+                difficulty_factor: None,
+                signal: None,
+                anomaly: None,
+            },
+            attempt: MAX_ATTEMPTS,
+        };
+
+        // no webhook set; event is dropped
+        WebhookDispatcher::deliver(&app_data, &client, delivery.clone())
+            .await
+            .unwrap();
+        let deliveries = data
+            .db
+            .fetch_webhook_deliveries(NAME, &token_key.key, 10)
+            .await
+            .unwrap();
+        assert!(deliveries.is_empty());
+
+        let webhook = db_core::Webhook {
+            url: "http://127.0.0.1:1/".into(),
+            secret: "secret".into(),
+            on_escalation: false,
+        };
+        data.db
+            .set_webhook(NAME, &token_key.key, &webhook)
+            .await
+            .unwrap();
+
+        // escalations are not delivered unless enabled
+        let mut escalation = delivery.clone();
+        escalation.payload.event = WebhookEvent::Escalation;
+        WebhookDispatcher::deliver(&app_data, &client, escalation)
+            .await
+            .unwrap();
+        assert!(data
+            .db
+            .fetch_webhook_deliveries(NAME, &token_key.key, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // receiver isn't public; failed attempt is logged
+        WebhookDispatcher::deliver(&app_data, &client, delivery)
+            .await
+            .unwrap();
+        let deliveries = data
+            .db
+            .fetch_webhook_deliveries(NAME, &token_key.key, 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].event, WebhookEvent::Solve.name());
+        assert_eq!(deliveries[0].status, None);
+        assert_eq!(deliveries[0].attempt, MAX_ATTEMPTS);
+        assert!(!deliveries[0].success);
+    }
+}
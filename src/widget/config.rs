@@ -0,0 +1,64 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Configuration of the widget of a sitekey, fetched by the widget when it
+//! loads. Owners set it with the [theme API](crate::api::v1::mcaptcha::theme)
+//! and [invisible mode](crate::api::v1::mcaptcha::update::update_invisible_mode)
+use actix_web::{http::header, web, HttpResponse, Responder};
+use db_core::errors::DBError;
+use db_core::WidgetTheme;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::*;
+use crate::AppData;
+
+/// seconds that widgets cache their configuration for
+pub const CONFIG_CACHE_AGE: u32 = 60;
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct WidgetConfig {
+    pub theme: WidgetTheme,
+    /// widget solves challenges in the background when they allow it. See
+    /// [ApiPoWConfig::invisible](crate::api::v1::pow::get_config::ApiPoWConfig::invisible)
+    #[serde(default)]
+    pub invisible: bool,
+    /// widget offers the [fallback challenge](crate::fallback) when it can't
+    /// solve PoW
+    #[serde(default)]
+    pub fallback: bool,
+}
+
+/// theme of the widget of a sitekey; the default theme when the owner didn't
+/// set one
+pub async fn theme(data: &AppData, key: &str) -> ServiceResult<WidgetTheme> {
+    match data.db.get_widget_theme(key).await {
+        Ok(theme) => Ok(theme),
+        Err(DBError::WidgetThemeNotFound) => Ok(WidgetTheme::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// configuration of the widget of a sitekey
+#[my_codegen::get(path = "crate::WIDGET_ROUTES.config")]
+pub async fn config(
+    path: web::Path<String>,
+    data: AppData,
+) -> ServiceResult<impl Responder> {
+    let key = path.into_inner();
+    if !data.db.captcha_exists(None, &key).await? {
+        return Err(ServiceError::CaptchaNotFound);
+    }
+    let config = WidgetConfig {
+        theme: theme(&data, &key).await?,
+        invisible: data.db.captcha_invisible_mode(&key).await?.enabled,
+        fallback: data.db.captcha_fallback(&key).await?.enabled,
+    };
+    Ok(HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![
+            header::CacheDirective::Public,
+            header::CacheDirective::MaxAge(CONFIG_CACHE_AGE),
+        ]))
+        .json(config))
+}
@@ -0,0 +1,169 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Test page of demo instances, which embeds the widget with a sitekey of the
+//! demo account and verifies the tokens that it submits
+use actix_web::{web, HttpResponse, Responder};
+use sailfish::TemplateOnce;
+use serde::{Deserialize, Serialize};
+
+use crate::api::v1::pow::verify_token::{runners::validate, VerifyCaptchaResultPayload};
+use crate::demo::{test_page_sitekey, DEMO_USER};
+use crate::errors::*;
+use crate::AppData;
+
+#[derive(TemplateOnce, Clone)]
+#[template(path = "widget/demo.html")]
+pub struct DemoPage {
+    sitekey: String,
+    /// result of verifying the submitted token, if any
+    valid: Option<bool>,
+}
+
+const PAGE: &str = "Try mCaptcha";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TestPageForm {
+    /// token that the widget received
+    #[serde(rename = "mcaptcha__token", default)]
+    pub token: String,
+}
+
+/// the test page needs the demo account
+fn enabled(data: &AppData) -> bool {
+    data.settings.allow_demo && data.settings.demo.test_page
+}
+
+async fn render(data: &AppData, valid: Option<bool>) -> PageResult<HttpResponse> {
+    if !enabled(&data) {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let sitekey = test_page_sitekey(data).await?;
+    let page = DemoPage { sitekey, valid }.render_once().unwrap();
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page))
+}
+
+/// render the test page
+#[my_codegen::get(path = "crate::WIDGET_ROUTES.demo")]
+pub async fn demo_page(data: AppData) -> PageResult<impl Responder> {
+    render(&data, None).await
+}
+
+/// verify the token submitted from the test page
+#[my_codegen::post(path = "crate::WIDGET_ROUTES.demo")]
+pub async fn verify_demo(
+    payload: web::Form<TestPageForm>,
+    data: AppData,
+) -> PageResult<impl Responder> {
+    if !enabled(&data) {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let key = test_page_sitekey(&data).await?;
+    let payload = VerifyCaptchaResultPayload {
+        secret: data.db.get_secret(DEMO_USER).await?.secret,
+        key,
+        token: payload.into_inner().token,
+        ip: None,
+    };
+    let valid = validate(&data, payload).await.unwrap_or(false);
+    render(&data, Some(valid)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use super::*;
+    use crate::demo::DemoUser;
+    use crate::tests::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn test_page_works_pg() {
+        let data = pg::get_data().await;
+        test_page_works(data).await;
+    }
+
+    #[actix_rt::test]
+    async fn test_page_works_maria() {
+        let data = maria::get_data().await;
+        test_page_works(data).await;
+    }
+
+    async fn test_page_works(data: ArcData) {
+        let data = &data;
+        let app = get_app!(data).await;
+
+        // disabled by default
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(WIDGET_ROUTES.demo)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let mut settings = data.settings.clone();
+        settings.allow_demo = true;
+        settings.demo.test_page = true;
+        let data = &crate::data::Data::new(&settings, data.survey_secrets.clone()).await;
+        let app_data = AppData::new(data.clone());
+        delete_user(data, DEMO_USER).await;
+        DemoUser::register_demo_user(&app_data).await.unwrap();
+        let app = get_app!(data).await;
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(WIDGET_ROUTES.demo)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let key = test_page_sitekey(&app_data).await.unwrap();
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains(&key));
+        assert_eq!(
+            data.db
+                .get_all_user_captchas(DEMO_USER)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // tokens from the widget are verified with the demo account's secret
+        let form = TestPageForm {
+            token: get_validation_token(data, &key).await,
+        };
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(WIDGET_ROUTES.demo)
+                .set_form(&form)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("The token is valid"));
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(WIDGET_ROUTES.demo)
+                .set_form(&form)
+                .to_request(),
+        )
+        .await;
+        let body = test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("The token is invalid"));
+        delete_user(data, DEMO_USER).await;
+    }
+}
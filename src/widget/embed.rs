@@ -0,0 +1,107 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Script that sites embed the widget with, and snippets that embed it
+//!
+//! The script is embedded in the binary and served from a content-hashed URL
+//! under `/assets`, which is cached for [CACHE_AGE](crate::CACHE_AGE) seconds.
+//! [EMBED_SCRIPT](super::routes::Widget::embed_script) redirects to the current
+//! version, for sites that can't update their snippets.
+use actix_web::{http::header, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+/// seconds that redirects to the current version of the script are cached for
+pub const REDIRECT_CACHE_AGE: u32 = 300;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// copy-paste snippet that embeds the widget of a sitekey on a site
+pub struct EmbedSnippet {
+    /// content-hashed URL of the script
+    pub script_url: String,
+    /// URL of the widget of the sitekey
+    pub widget_url: String,
+    /// HTML to paste into forms
+    pub html: String,
+}
+
+impl EmbedSnippet {
+    pub fn new(s: &Settings, key: &str) -> Self {
+        let base = s.server.url();
+        let script_url = format!("{base}{}", *crate::EMBED_JS);
+        let widget_url = format!(
+            "{base}{}/?sitekey={key}",
+            crate::WIDGET_ROUTES.verification_widget
+        );
+        let html = format!(
+            r#"<label data-mcaptcha_url="{widget_url}" for="mcaptcha__token" id="mcaptcha__token-label">
+  mCaptcha authorization token.
+  <input type="text" name="mcaptcha__token" id="mcaptcha__token" />
+</label>
+<div id="mcaptcha__widget-container"></div>
+<script src="{script_url}"></script>
+"#
+        );
+        Self {
+            script_url,
+            widget_url,
+            html,
+        }
+    }
+}
+
+/// redirect to the content-hashed URL of the current version of the script
+#[my_codegen::get(path = "crate::WIDGET_ROUTES.embed_script")]
+pub async fn embed_script() -> impl Responder {
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, *crate::EMBED_JS))
+        .insert_header(header::CacheControl(vec![
+            header::CacheDirective::Public,
+            header::CacheDirective::MaxAge(REDIRECT_CACHE_AGE),
+        ]))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test;
+
+    use super::*;
+    use crate::*;
+
+    #[actix_rt::test]
+    async fn embed_script_works() {
+        let app = get_app!().await;
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(crate::WIDGET_ROUTES.embed_script)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let location = resp.headers().get(header::LOCATION).unwrap();
+        assert_eq!(location.to_str().unwrap(), *crate::EMBED_JS);
+
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::get().uri(*crate::EMBED_JS).to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let cache = resp.headers().get(header::CACHE_CONTROL).unwrap();
+        assert!(cache.to_str().unwrap().contains("immutable"));
+
+        let snippet = EmbedSnippet::new(&crate::SETTINGS, "embedsitekey");
+        assert!(snippet.script_url.ends_with(*crate::EMBED_JS));
+        assert!(snippet
+            .widget_url
+            .ends_with("/widget/?sitekey=embedsitekey"));
+        assert!(snippet.html.contains(&snippet.script_url));
+        assert!(snippet.html.contains(&snippet.widget_url));
+    }
+}
@@ -10,19 +10,37 @@ use sailfish::TemplateOnce;
 
 use crate::errors::*;
 
+pub mod config;
+pub mod demo;
+pub mod embed;
+
 pub const WIDGET_ROUTES: routes::Widget = routes::Widget::new();
 
 pub mod routes {
     pub struct Widget {
         pub verification_widget: &'static str,
+        /// test page of demo instances
+        pub demo: &'static str,
+        /// redirect to the current version of the script that embeds the
+        /// widget
+        pub embed_script: &'static str,
+        /// configuration of the widget of a sitekey
+        pub config: &'static str,
     }
 
     impl Widget {
         pub const fn new() -> Self {
             Widget {
                 verification_widget: "/widget",
+                demo: "/widget/demo",
+                embed_script: "/widget/embed.js",
+                config: "/widget/config/{key}",
             }
         }
+
+        pub fn get_config(&self, key: &str) -> String {
+            self.config.replace("{key}", key)
+        }
     }
 }
 
@@ -53,6 +71,10 @@ async fn show_widget() -> PageResult<impl Responder> {
 /// widget services
 pub fn services(cfg: &mut web::ServiceConfig) {
     cfg.service(show_widget);
+    cfg.service(demo::demo_page);
+    cfg.service(demo::verify_demo);
+    cfg.service(embed::embed_script);
+    cfg.service(config::config);
 }
 
 #[cfg(test)]
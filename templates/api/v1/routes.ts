@@ -6,6 +6,7 @@
 const ROUTES = {
   registerUser: "/api/v1/signup",
   loginUser: "/api/v1/signin",
+  loginChallenge: "/api/v1/signin/challenge",
   signoutUser: "/api/v1/signout",
   deleteAccount: "/api/v1/account/delete",
   usernameExists: "/api/v1/account/username/exists",
@@ -13,6 +14,27 @@ const ROUTES = {
   healthCheck: "/api/v1/meta/health",
   buildDetails: "/api/v1/meta/build",
   markNotificationRead: "/api/v1/notifications/read",
+  notificationStream: "/api/v1/notifications/stream",
+  revokeSession: "/api/v1/account/sessions/revoke",
+  adminDeleteUser: "/api/v1/admin/users/delete",
+  adminNotificationKey: "/api/v1/admin/notifications/key",
+  adminNotify: "/api/v1/admin/notifications/add",
+  adminApprove: "/api/v1/admin/registrations/approve",
+  adminReject: "/api/v1/admin/registrations/reject",
+  adminSuspend: "/api/v1/admin/users/suspend",
+  adminUnsuspend: "/api/v1/admin/users/unsuspend",
+  adminBanDomain: "/api/v1/admin/bans/domains/add",
+  adminUnbanDomain: "/api/v1/admin/bans/domains/delete",
+  adminRetryDeadLetter: "/api/v1/admin/mail/dead/retry",
+  updateNotificationKey: "/api/v1/notifications/key/update",
+  createOrg: "/api/v1/orgs/create",
+  deleteOrg: "/api/v1/orgs/delete",
+  setOrgMember: "/api/v1/orgs/members/set",
+  removeOrgMember: "/api/v1/orgs/members/remove",
+  addOrgSitekey: "/api/v1/orgs/sitekeys/add",
+  removeOrgSitekey: "/api/v1/orgs/sitekeys/remove",
+  setWidgetTheme: (key: string): string =>
+    `/api/v1/mcaptcha/${encodeURIComponent(key)}/theme/set`,
 };
 
 export default ROUTES;
@@ -6,6 +6,9 @@
 //import {init} from "mcaptcha-glue";
 
 import VIEWS from "../../../views/v1/routes";
+import ROUTES from "../../../api/v1/routes";
+import prove from "../../../widget/prove";
+import { PoWConfig } from "../../../widget/types";
 
 import isBlankString from "../../../utils/isBlankString";
 import genJsonPayload from "../../../utils/genJsonPayload";
@@ -25,6 +28,39 @@ export const getPassword = (): string | null => {
   return passwordElement.value;
 };
 
+/** error code sent by the server when a TOTP is required */
+export const TOTP_REQUIRED = "totp_required";
+
+/** get TOTP or recovery code, if the field is present and filled */
+export const getTotp = (): string | undefined => {
+  const totpElement = <HTMLInputElement>document.getElementById("totp");
+  if (totpElement === null || totpElement.value.trim() === "") {
+    return;
+  }
+  return totpElement.value;
+};
+
+/** error code sent by the server when the sign-in challenge has to be solved */
+export const LOGIN_CHALLENGE_REQUIRED = "login_challenge_required";
+
+type LoginWork = {
+  string: string;
+  nonce: number;
+  result: string;
+};
+
+/** fetch and solve the sign-in challenge */
+export const solveChallenge = async (): Promise<LoginWork> => {
+  const res = await fetch(ROUTES.loginChallenge);
+  const config: PoWConfig = await res.json();
+  const work = await prove(config, () => undefined);
+  return {
+    string: config.string,
+    nonce: work.nonce,
+    result: work.result,
+  };
+};
+
 const login = async (e: Event): Promise<void> => {
   e.preventDefault();
   const loginElement = <HTMLInputElement>document.getElementById("login");
@@ -41,15 +77,27 @@ const login = async (e: Event): Promise<void> => {
   const payload = {
     login,
     password,
+    totp: getTotp(),
+    challenge: <LoginWork | undefined>undefined,
   };
 
   const formUrl = getFormUrl();
 
-  const res = await fetch(formUrl, genJsonPayload(payload));
+  let res = await fetch(formUrl, genJsonPayload(payload));
+  if (res.status === 429) {
+    const err = await res.clone().json();
+    if (err.code === LOGIN_CHALLENGE_REQUIRED) {
+      payload.challenge = await solveChallenge();
+      res = await fetch(formUrl, genJsonPayload(payload));
+    }
+  }
   if (res.ok) {
     window.location.assign(VIEWS.panelHome);
   } else {
     const err = await res.json();
+    if (err.code === TOTP_REQUIRED) {
+      document.getElementById("totp-label").hidden = false;
+    }
     createError(err.error);
   }
 };
@@ -41,10 +41,15 @@ const registerUser = async (e: Event): Promise<void> => {
     return;
   }
 
+  const inviteElement = <HTMLInputElement | null>(
+    document.getElementById("invite")
+  );
+  const invite = inviteElement ? inviteElement.value : null;
+
   let email: string | null = emailElement.value;
   if (!email.replace(/\s/g, "").length) {
     email = null;
-  } else {
+  } else if (!invite) {
     exists = await emailExists();
     if (exists) {
       return;
@@ -56,6 +61,7 @@ const registerUser = async (e: Event): Promise<void> => {
     password,
     confirm_password: passwordCheck,
     email,
+    invite,
   };
   const formUrl = getFormUrl();
 
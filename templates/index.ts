@@ -11,6 +11,8 @@ import * as panel from "./panel/ts/index";
 import settings from "./panel/settings/";
 import * as deleteAccount from "./panel/settings/account/delete";
 import * as updateSecret from "./panel/settings/secret/update";
+import * as sessions from "./panel/settings/sessions";
+import * as notificationKey from "./panel/settings/notification-key";
 import * as addSiteKeyAdvance from "./panel/sitekey/add/advance/ts";
 import * as addSiteKeyEasy from "./panel/sitekey/add/novice/ts";
 import * as editSitekeyAdvance from "./panel/sitekey/edit/";
@@ -18,6 +20,9 @@ import * as editSitekeyEasy from "./panel/sitekey/edit/easy/";
 import * as deleteSitekey from "./panel/sitekey/delete/";
 import * as listSitekeys from "./panel/sitekey/list/ts";
 import * as notidications from "./panel/notifications/ts";
+import * as admin from "./panel/admin";
+import * as orgs from "./panel/orgs";
+import * as viewOrg from "./panel/orgs/view";
 import { MODE } from "./logger";
 import log from "./logger";
 
@@ -31,9 +36,14 @@ router.register(VIEWS.panelHome, panel.index);
 router.register(VIEWS.settings, settings);
 router.register(VIEWS.deleteAccount, deleteAccount.index);
 router.register(VIEWS.updateSecret, updateSecret.index);
+router.register(VIEWS.sessions, sessions.index);
+router.register(VIEWS.notificationKey, notificationKey.index);
 router.register(VIEWS.registerUser, register.index);
 router.register(VIEWS.loginUser, login.index);
 router.register(VIEWS.notifications, notidications.index);
+router.register(VIEWS.admin, admin.index);
+router.register(VIEWS.orgs, orgs.index);
+router.register(VIEWS.viewOrg("[A-Za-z0-9_-]+"), viewOrg.index);
 router.register(VIEWS.listSitekey, listSitekeys.index);
 router.register(VIEWS.addSiteKeyAdvance, addSiteKeyAdvance.index);
 router.register(VIEWS.addSiteKeyEasy, addSiteKeyEasy.index);
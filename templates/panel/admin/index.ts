@@ -0,0 +1,160 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+import genJsonPayload from "../../utils/genJsonPayload";
+import createError from "../../components/error";
+import { encrypt } from "../../utils/notificationCrypto";
+
+import ROUTES from "../../api/v1/routes";
+
+const deleteUser = async (e: Event) => {
+  const element = <HTMLElement>e.target;
+  const username = element.dataset.username;
+  if (!confirm(`Delete ${username} and all of their sitekeys?`)) {
+    return;
+  }
+  const res = await fetch(ROUTES.adminDeleteUser, genJsonPayload({ username }));
+  if (res.ok) {
+    document.getElementById(`admin__user-${username}`).remove();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const decide = (route: string, action: string) => async (e: Event) => {
+  const element = <HTMLElement>e.target;
+  const username = element.dataset.username;
+  if (!confirm(`${action} registration of ${username}?`)) {
+    return;
+  }
+  const res = await fetch(route, genJsonPayload({ username }));
+  if (res.ok) {
+    window.location.reload();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const suspend = (route: string, action: string) => async (e: Event) => {
+  const element = <HTMLElement>e.target;
+  const username = element.dataset.username;
+  if (!confirm(`${action} ${username}?`)) {
+    return;
+  }
+  const res = await fetch(route, genJsonPayload({ username }));
+  if (res.ok) {
+    window.location.reload();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const banDomain = async (e: Event) => {
+  e.preventDefault();
+  const domain = (<HTMLInputElement>document.getElementById("admin__ban-domain"))
+    .value;
+  const res = await fetch(ROUTES.adminBanDomain, genJsonPayload({ domain }));
+  if (res.ok) {
+    window.location.reload();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const unbanDomain = async (e: Event) => {
+  const element = <HTMLElement>e.target;
+  const domain = element.dataset.domain;
+  const res = await fetch(ROUTES.adminUnbanDomain, genJsonPayload({ domain }));
+  if (res.ok) {
+    window.location.reload();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const retryDeadLetter = async (e: Event) => {
+  const element = <HTMLElement>e.target;
+  const id = parseInt(element.dataset.id);
+  const res = await fetch(ROUTES.adminRetryDeadLetter, genJsonPayload({ id }));
+  if (res.ok) {
+    window.location.reload();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const notify = async (e: Event) => {
+  e.preventDefault();
+  const form = <HTMLFormElement>e.target;
+  const field = (id: string) =>
+    (<HTMLInputElement>document.getElementById(id)).value;
+  const to = field("admin__notify-to");
+
+  const res = await fetch(
+    ROUTES.adminNotificationKey,
+    genJsonPayload({ username: to })
+  );
+  if (!res.ok) {
+    const err = await res.json();
+    createError(err.error);
+    return;
+  }
+  const { key } = await res.json();
+  if (!key) {
+    createError(`${to} hasn't set up a notification key`);
+    return;
+  }
+
+  const message = await encrypt(key, field("admin__notify-message"));
+  const payload = { to, heading: field("admin__notify-heading"), message };
+  const sent = await fetch(ROUTES.adminNotify, genJsonPayload(payload));
+  if (sent.ok) {
+    form.reset();
+  } else {
+    const err = await sent.json();
+    createError(err.error);
+  }
+};
+
+export const index = (): void => {
+  document.querySelectorAll(".admin__delete-btn").forEach(btn => {
+    btn.addEventListener("click", deleteUser, true);
+  });
+  document.querySelectorAll(".admin__approve-btn").forEach(btn => {
+    btn.addEventListener("click", decide(ROUTES.adminApprove, "Approve"), true);
+  });
+  document.querySelectorAll(".admin__reject-btn").forEach(btn => {
+    btn.addEventListener("click", decide(ROUTES.adminReject, "Reject"), true);
+  });
+  document.querySelectorAll(".admin__suspend-btn").forEach(btn => {
+    btn.addEventListener("click", suspend(ROUTES.adminSuspend, "Suspend"), true);
+  });
+  document.querySelectorAll(".admin__unsuspend-btn").forEach(btn => {
+    btn.addEventListener(
+      "click",
+      suspend(ROUTES.adminUnsuspend, "Lift suspension of"),
+      true
+    );
+  });
+  document.querySelectorAll(".admin__unban-btn").forEach(btn => {
+    btn.addEventListener("click", unbanDomain, true);
+  });
+  document.querySelectorAll(".admin__retry-btn").forEach(btn => {
+    btn.addEventListener("click", retryDeadLetter, true);
+  });
+  document
+    .getElementById("admin__ban-form")
+    .addEventListener("submit", banDomain, true);
+  const form = document.getElementById("admin__notify-form");
+  if (form) {
+    form.addEventListener("submit", notify, true);
+  }
+};
@@ -5,6 +5,10 @@
 
 import genJsonPayload from "../../../utils/genJsonPayload";
 import createError from "../../../components/error";
+import {
+  decrypt,
+  PRIVATE_KEY_STORAGE,
+} from "../../../utils/notificationCrypto";
 
 import ROUTES from "../../../api/v1/routes";
 
@@ -38,6 +42,70 @@ const addMarkReadEventListenet = () => {
   });
 };
 
+const decryptMessages = async () => {
+  const privateKey = localStorage.getItem(PRIVATE_KEY_STORAGE);
+  if (!privateKey) {
+    return;
+  }
+  const messages = TABLE_BODY.querySelectorAll(
+    ".notification__item-text--encrypted"
+  );
+  for (const element of Array.from(messages)) {
+    const message = <HTMLElement>element;
+    try {
+      message.innerText = await decrypt(
+        privateKey,
+        JSON.parse(message.dataset.message)
+      );
+    } catch (e) {
+      message.innerText =
+        "Unable to decrypt this message with the private key in this browser";
+    }
+  }
+};
+
+type StreamedNotification = {
+  from: string;
+  heading: string;
+  message: string;
+};
+
+/** show notifications as they are received, without reloading the page */
+const showNewNotifications = () => {
+  if (!window.EventSource) {
+    return;
+  }
+  const stream = new EventSource(ROUTES.notificationStream);
+  stream.addEventListener("notification", (e: MessageEvent) => {
+    const n: StreamedNotification = JSON.parse(e.data);
+    const row = document.createElement("tr");
+    row.className = "notification__item";
+    const cell = document.createElement("td");
+
+    const heading = document.createElement("h3");
+    heading.className = "notification__item-heading";
+    heading.innerText = n.heading;
+    const message = document.createElement("p");
+    message.className = "notification__item-text";
+    message.innerText = n.message;
+    const details = document.createElement("div");
+    details.className = "notification-data__container";
+    const sender = document.createElement("span");
+    sender.className = "notification__sender";
+    sender.innerText = n.from;
+    const received = document.createElement("span");
+    received.className = "notification__received";
+    received.innerText = "just now";
+    details.append(sender, " · ", received);
+
+    cell.append(heading, message, details);
+    row.appendChild(cell);
+    TABLE_BODY.prepend(row);
+  });
+};
+
 export const index = (): void => {
   addMarkReadEventListenet();
+  decryptMessages();
+  showNewNotifications();
 };
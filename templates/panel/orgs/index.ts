@@ -0,0 +1,27 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+import genJsonPayload from "../../utils/genJsonPayload";
+import createError from "../../components/error";
+
+import ROUTES from "../../api/v1/routes";
+import VIEWS from "../../views/v1/routes";
+
+const create = async (e: Event) => {
+  e.preventDefault();
+  const name = (<HTMLInputElement>document.getElementById("orgs__name")).value;
+  const res = await fetch(ROUTES.createOrg, genJsonPayload({ name }));
+  if (res.ok) {
+    window.location.assign(VIEWS.viewOrg(name));
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+export const index = (): void => {
+  const form = document.getElementById("orgs__create-form");
+  form.addEventListener("submit", create, true);
+};
@@ -0,0 +1,107 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+import genJsonPayload from "../../../utils/genJsonPayload";
+import createError from "../../../components/error";
+
+import ROUTES from "../../../api/v1/routes";
+import VIEWS from "../../../views/v1/routes";
+
+const getOrg = (): string => document.getElementById("orgs__org").dataset.org;
+
+/** POST payload and reload page, or show error */
+const submit = async (route: string, payload: object, next?: string) => {
+  const res = await fetch(route, genJsonPayload(payload));
+  if (res.ok) {
+    if (next) {
+      window.location.assign(next);
+    } else {
+      window.location.reload();
+    }
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
+const setRole = async (e: Event) => {
+  const select = <HTMLSelectElement>e.target;
+  const username = select.dataset.username;
+  await submit(ROUTES.setOrgMember, {
+    org: getOrg(),
+    username,
+    role: select.value,
+  });
+};
+
+const removeMember = async (e: Event) => {
+  const { username, leave } = (<HTMLElement>e.target).dataset;
+  const question = leave
+    ? "Leave the organization?"
+    : `Remove ${username} from the organization?`;
+  if (!confirm(question)) {
+    return;
+  }
+  // members that leave can't view the organization anymore
+  const next = leave ? VIEWS.orgs : undefined;
+  await submit(ROUTES.removeOrgMember, { org: getOrg(), username }, next);
+};
+
+const addMember = async (e: Event) => {
+  e.preventDefault();
+  const field = (id: string) =>
+    (<HTMLInputElement>document.getElementById(id)).value;
+  await submit(ROUTES.setOrgMember, {
+    org: getOrg(),
+    username: field("orgs__member-username"),
+    role: field("orgs__member-role"),
+  });
+};
+
+const addSitekey = async (e: Event) => {
+  e.preventDefault();
+  const key = (<HTMLSelectElement>document.getElementById("orgs__sitekey"))
+    .value;
+  await submit(ROUTES.addOrgSitekey, { org: getOrg(), key });
+};
+
+const removeSitekey = async (e: Event) => {
+  const key = (<HTMLElement>e.target).dataset.key;
+  await submit(ROUTES.removeOrgSitekey, { org: getOrg(), key });
+};
+
+const deleteOrg = async () => {
+  const org = getOrg();
+  if (!confirm(`Delete ${org}? Its sitekeys are kept by their holders.`)) {
+    return;
+  }
+  await submit(ROUTES.deleteOrg, { name: org }, VIEWS.orgs);
+};
+
+export const index = (): void => {
+  document.querySelectorAll(".orgs__role-select").forEach(select => {
+    select.addEventListener("change", setRole, true);
+  });
+  document.querySelectorAll(".orgs__remove-member-btn").forEach(btn => {
+    btn.addEventListener("click", removeMember, true);
+  });
+  document.querySelectorAll(".orgs__remove-sitekey-btn").forEach(btn => {
+    btn.addEventListener("click", removeSitekey, true);
+  });
+  const forms: Array<[string, (e: Event) => Promise<void>]> = [
+    ["orgs__add-member-form", addMember],
+    ["orgs__add-sitekey-form", addSitekey],
+  ];
+  forms.forEach(([id, fn]) => {
+    const form = document.getElementById(id);
+    if (form) {
+      form.addEventListener("submit", fn, true);
+    }
+  });
+  const del = document.getElementById("orgs__delete");
+  if (del) {
+    del.addEventListener("click", deleteOrg, true);
+  }
+};
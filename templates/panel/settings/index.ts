@@ -25,6 +25,7 @@ const DELETE_FORM = "settings__delete-form";
 const EMAIL_FORM = "settings__email-form";
 const USERNAME_FORM = "settings__username-form";
 const SECRET_FORM = "settings__secret-form";
+const DIGEST_FORM = "settings__digest-form";
 
 // form elements
 const deleteForm = new LazyElement(DELETE_FORM);
@@ -34,6 +35,7 @@ const secretForm = new LazyElement(SECRET_FORM);
 
 // field IDs
 const EMAIL = "email";
+const DIGEST = "digest";
 const USERNAME = "username";
 
 // field elements
@@ -86,6 +88,23 @@ const updateUsername = async (e: Event) => {
   }
 };
 
+const updateDigest = async (e: Event) => {
+  e.preventDefault();
+  const digestElement = <HTMLSelectElement>document.getElementById(DIGEST);
+  const frequency = digestElement.value;
+  const url = getFormUrl(<HTMLFormElement>e.target);
+  const payload = {
+    frequency: frequency ? frequency : null,
+  };
+  const res = await fetch(url, genJsonPayload(payload));
+  if (res.ok) {
+    window.location.reload();
+  } else {
+    const err = await res.json();
+    createError(err.error);
+  }
+};
+
 const updateSecret = (e: Event) => {
   e.preventDefault();
   const msg =
@@ -114,6 +133,11 @@ const registerForms = () => {
     .get()
     .addEventListener("input", async () => await userExists(), false);
   secretForm.get().addEventListener("submit", (e) => updateSecret(e), true);
+  // only shown when email is configured
+  const digestForm = document.getElementById(DIGEST_FORM);
+  if (digestForm) {
+    digestForm.addEventListener("submit", (e) => updateDigest(e), true);
+  }
 };
 
 // set up copying account secret to clipboard
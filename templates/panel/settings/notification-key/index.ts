@@ -0,0 +1,71 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+import genJsonPayload from "../../../utils/genJsonPayload";
+import createError from "../../../components/error";
+import {
+  generateKeyPair,
+  PRIVATE_KEY_STORAGE,
+} from "../../../utils/notificationCrypto";
+
+import ROUTES from "../../../api/v1/routes";
+
+const PRIVATE_KEY = () =>
+  <HTMLTextAreaElement>document.getElementById("notification-key__private");
+
+const updateKey = async (key?: string): Promise<boolean> => {
+  const res = await fetch(ROUTES.updateNotificationKey, genJsonPayload({ key }));
+  if (res.ok) {
+    return true;
+  }
+  const err = await res.json();
+  createError(err.error);
+  return false;
+};
+
+const generate = async () => {
+  const [publicKey, privateKey] = await generateKeyPair();
+  if (await updateKey(publicKey)) {
+    localStorage.setItem(PRIVATE_KEY_STORAGE, privateKey);
+    PRIVATE_KEY().value = privateKey;
+    document.getElementById("notification-key__status").innerText =
+      "A new notification key is set up. Save a copy of the private key below.";
+  }
+};
+
+const importKey = () => {
+  const privateKey = PRIVATE_KEY().value.trim();
+  if (privateKey.length === 0) {
+    createError("Please paste your private key");
+    return;
+  }
+  localStorage.setItem(PRIVATE_KEY_STORAGE, privateKey);
+  document.getElementById("notification-key__status").innerText =
+    "This browser can now read your encrypted notifications.";
+};
+
+const remove = async () => {
+  if (await updateKey()) {
+    localStorage.removeItem(PRIVATE_KEY_STORAGE);
+    window.location.reload();
+  }
+};
+
+export const index = (): void => {
+  const privateKey = localStorage.getItem(PRIVATE_KEY_STORAGE);
+  if (privateKey) {
+    PRIVATE_KEY().value = privateKey;
+  }
+  document
+    .getElementById("notification-key__generate")
+    .addEventListener("click", generate, true);
+  document
+    .getElementById("notification-key__import")
+    .addEventListener("click", importKey, true);
+  const removeBtn = document.getElementById("notification-key__remove");
+  if (removeBtn) {
+    removeBtn.addEventListener("click", remove, true);
+  }
+};
@@ -3,7 +3,7 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-import {getPassword} from "../../../auth/login/ts/";
+import {getPassword, getTotp} from "../../../auth/login/ts/";
 import FORM from "../../../auth/sudo/";
 
 import getFormUrl from "../../../utils/getFormUrl";
@@ -19,6 +19,7 @@ const submit = async (e: Event) => {
 
   const payload = {
     password,
+    totp: getTotp(),
   };
 
   const formUrl = getFormUrl(<HTMLFormElement>FORM.get());
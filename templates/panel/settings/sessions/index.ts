@@ -0,0 +1,48 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+import genJsonPayload from "../../../utils/genJsonPayload";
+import createError from "../../../components/error";
+
+import ROUTES from "../../../api/v1/routes";
+import VIEWS from "../../../views/v1/routes";
+
+const revoke = async (id?: string): Promise<boolean> => {
+  const res = await fetch(ROUTES.revokeSession, genJsonPayload({ id }));
+  if (res.ok) {
+    return true;
+  }
+  const err = await res.json();
+  createError(err.error);
+  return false;
+};
+
+const revokeSession = async (e: Event) => {
+  const element = <HTMLElement>e.target;
+  const id = element.dataset.id;
+  if (await revoke(id)) {
+    const current = element.closest("tr").querySelector("b") !== null;
+    if (current) {
+      window.location.assign(VIEWS.loginUser);
+    } else {
+      document.getElementById(`sessions__item-${id}`).remove();
+    }
+  }
+};
+
+const revokeAll = async () => {
+  if (await revoke()) {
+    window.location.assign(VIEWS.loginUser);
+  }
+};
+
+export const index = (): void => {
+  document.querySelectorAll(".sessions__revoke-btn").forEach(btn => {
+    btn.addEventListener("click", revokeSession, true);
+  });
+  document
+    .getElementById("sessions__revoke-all")
+    .addEventListener("click", revokeAll, true);
+};
@@ -3,7 +3,7 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-import { getPassword } from "../../../auth/login/ts/";
+import { getPassword, getTotp } from "../../../auth/login/ts/";
 import FORM from "../../../auth/sudo/";
 import additionalData from "../../../components/additional-data";
 import registerShowPassword from "../../../components/showPassword";
@@ -21,6 +21,7 @@ const submit = async (e: Event) => {
 
   const payload = {
     password,
+    totp: getTotp(),
     key,
   };
 
@@ -6,6 +6,7 @@
 import getFormUrl from "../../../../utils/getFormUrl";
 import genJsonPayload from "../../../../utils/genJsonPayload";
 import createError from "../../../../components/error";
+import saveTheme from "../theme";
 
 import VIEWS from "../../../../views/v1/routes";
 
@@ -25,7 +26,10 @@ const submit = async (e: Event) => {
   };
   console.debug(`[form submition] json payload: ${JSON.stringify(payload)}`);
 
-  const res = await fetch(formUrl, genJsonPayload(payload));
+  let res = await fetch(formUrl, genJsonPayload(payload));
+  if (res.ok) {
+    res = await saveTheme(key);
+  }
   if (res.ok) {
     window.location.assign(VIEWS.viewSitekey(key));
   } else {
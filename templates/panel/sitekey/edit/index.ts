@@ -16,6 +16,7 @@ import { LEVELS } from "../add/advance/ts/levels";
 import getFormUrl from "../../../utils/getFormUrl";
 import genJsonPayload from "../../../utils/genJsonPayload";
 import createError from "../../../components/error";
+import saveTheme from "./theme";
 import LazyElement from "../../../utils/lazyElement";
 
 import VIEWS from "../../../views/v1/routes";
@@ -53,7 +54,10 @@ const submit = async (e: Event) => {
 
   console.debug(`[form submition] json payload: ${JSON.stringify(payload)}`);
 
-  const res = await fetch(formUrl, genJsonPayload(payload));
+  let res = await fetch(formUrl, genJsonPayload(payload));
+  if (res.ok) {
+    res = await saveTheme(key);
+  }
   if (res.ok) {
     window.location.assign(VIEWS.viewSitekey(key));
   } else {
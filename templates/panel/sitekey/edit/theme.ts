@@ -0,0 +1,35 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+import genJsonPayload from "../../../utils/genJsonPayload";
+import ROUTES from "../../../api/v1/routes";
+
+const input = (id: string): HTMLInputElement =>
+  <HTMLInputElement>document.getElementById(id);
+
+/** optional color; unset when left empty */
+const color = (id: string): string | null => {
+  const value = input(id).value.trim();
+  return value.length === 0 ? null : value;
+};
+
+/** theme of the widget, as set in the theme fieldset of the form */
+export const readTheme = (): object => ({
+  mode: (<HTMLSelectElement>document.getElementById("theme_mode")).value,
+  accent_color: input("theme_accent_color").value,
+  background_color: color("theme_background_color"),
+  text_color: color("theme_text_color"),
+  border_radius: parseInt(input("theme_border_radius").value) || 0,
+  show_logo: input("theme_show_logo").checked,
+});
+
+/**
+ * save the theme of the widget of a sitekey
+ * @returns {Response} response of the theme API
+ * */
+export const saveTheme = (key: string): Promise<Response> =>
+  fetch(ROUTES.setWidgetTheme(key), genJsonPayload(readTheme()));
+
+export default saveTheme;
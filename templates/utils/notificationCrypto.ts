@@ -0,0 +1,93 @@
+// Copyright (C) 2022  Aravinth Manivannan <realaravinth@batsense.net>
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+/** local storage key under which the private notification key is kept */
+export const PRIVATE_KEY_STORAGE = "mcaptcha-notification-key";
+
+const RSA = { name: "RSA-OAEP", hash: "SHA-256" };
+const AES_KEY_LEN = 256;
+const IV_LEN = 12;
+
+/** notification message encrypted to a notification key */
+export type EncryptedMessage = {
+  v: number;
+  key: string;
+  iv: string;
+  data: string;
+};
+
+const encode = (buf: ArrayBuffer): string =>
+  btoa(String.fromCharCode(...new Uint8Array(buf)));
+
+const decode = (val: string): Uint8Array =>
+  Uint8Array.from(atob(val), c => c.charCodeAt(0));
+
+/** generate key pair; returns base64 encoded SPKI public and PKCS8 private keys */
+export const generateKeyPair = async (): Promise<[string, string]> => {
+  const pair = await crypto.subtle.generateKey(
+    {
+      ...RSA,
+      modulusLength: 4096,
+      publicExponent: new Uint8Array([1, 0, 1]),
+    },
+    true,
+    ["encrypt", "decrypt"]
+  );
+  const publicKey = await crypto.subtle.exportKey("spki", pair.publicKey);
+  const privateKey = await crypto.subtle.exportKey("pkcs8", pair.privateKey);
+  return [encode(publicKey), encode(privateKey)];
+};
+
+/** encrypt message to a base64 encoded SPKI public key */
+export const encrypt = async (
+  publicKey: string,
+  message: string
+): Promise<EncryptedMessage> => {
+  const rsa = await crypto.subtle.importKey(
+    "spki",
+    decode(publicKey),
+    RSA,
+    false,
+    ["encrypt"]
+  );
+  const aes = await crypto.subtle.generateKey(
+    { name: "AES-GCM", length: AES_KEY_LEN },
+    true,
+    ["encrypt"]
+  );
+  const iv = crypto.getRandomValues(new Uint8Array(IV_LEN));
+  const data = await crypto.subtle.encrypt(
+    { name: "AES-GCM", iv },
+    aes,
+    new TextEncoder().encode(message)
+  );
+  const rawKey = await crypto.subtle.exportKey("raw", aes);
+  const key = await crypto.subtle.encrypt(RSA, rsa, rawKey);
+  return { v: 1, key: encode(key), iv: encode(iv), data: encode(data) };
+};
+
+/** decrypt message with a base64 encoded PKCS8 private key */
+export const decrypt = async (
+  privateKey: string,
+  message: EncryptedMessage
+): Promise<string> => {
+  const rsa = await crypto.subtle.importKey(
+    "pkcs8",
+    decode(privateKey),
+    RSA,
+    false,
+    ["decrypt"]
+  );
+  const rawKey = await crypto.subtle.decrypt(RSA, rsa, decode(message.key));
+  const aes = await crypto.subtle.importKey("raw", rawKey, "AES-GCM", false, [
+    "decrypt",
+  ]);
+  const data = await crypto.subtle.decrypt(
+    { name: "AES-GCM", iv: decode(message.iv) },
+    aes,
+    decode(message.data)
+  );
+  return new TextDecoder().decode(data);
+};
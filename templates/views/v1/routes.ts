@@ -11,8 +11,13 @@ const ROUTES = {
   settings: "/settings/",
   updateSecret: "/settings/secret/update/",
   deleteAccount: "/settings/account/delete/",
+  sessions: "/settings/sessions",
+  notificationKey: "/settings/notification-key",
   docsHome: "/docs/",
   notifications: "/notifications",
+  admin: "/admin",
+  orgs: "/orgs",
+  viewOrg: (org: string): string => `/orgs/${org}`,
   listSitekey: "/sitekeys/",
   viewSitekey: (key: string): string => `/sitekey/${key}/`,
   editSitekeyAdvance: (key: string): string => `/sitekey/${key}/advance/edit/`,
@@ -0,0 +1,36 @@
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+/** times a request is retried while the server is saturated */
+export const MAX_RETRIES = 3;
+
+/** seconds to wait before retrying a response, when the server asks to */
+export const retryAfter = (res: Response): number | null => {
+  if (res.status !== 429 && res.status !== 503) {
+    return null;
+  }
+  const seconds = parseInt(res.headers.get("Retry-After") || "", 10);
+  return Number.isNaN(seconds) ? null : seconds;
+};
+
+const sleep = (ms: number) => new Promise((r) => setTimeout(r, ms));
+
+/** fetch, waiting out and retrying responses of a saturated server. Waits
+ * double on every retry, with jitter so that widgets don't retry at once */
+export const fetchWithBackoff = async (
+  url: string,
+  init: object
+): Promise<Response> => {
+  for (let attempt = 0; ; attempt++) {
+    const res = await fetch(url, init);
+    const wait = retryAfter(res);
+    if (wait === null || attempt >= MAX_RETRIES) {
+      return res;
+    }
+    console.debug(`server saturated, retrying in ${wait * 2 ** attempt}s`);
+    await sleep((wait * 2 ** attempt + Math.random()) * 1000);
+  }
+};
+
+export default fetchWithBackoff;
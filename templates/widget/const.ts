@@ -26,12 +26,25 @@ export const sitekey = (): string => {
 export const ROUTES = (() => {
   const getConfig = "/api/v1/pow/config";
   const verififyPoW = "/api/v1/pow/verify";
+  const probe = (key: string) => `/api/v1/pow/probe/${encodeURIComponent(key)}`;
+  const widgetConfig = (key: string) =>
+    `/widget/config/${encodeURIComponent(key)}`;
+  const startFallback = "/api/v1/pow/fallback/start";
+  const completeFallback = "/api/v1/pow/fallback/complete";
 
   return {
     /** get URL to fetch PoW configuration */
     getConfig,
     /** get URL to verify PoW*/
     verififyPoW,
+    /** get URL to check whether a sitekey exists and where it's allowed */
+    probe,
+    /** get URL to fetch the configuration of the widget of a sitekey */
+    widgetConfig,
+    /** get URL to start a fallback challenge */
+    startFallback,
+    /** get URL to complete a fallback challenge */
+    completeFallback,
   };
 })();
 
@@ -53,13 +66,16 @@ type messageTextReturn = {
   before: () => void;
   after: () => void;
   during: () => void;
-  error: () => void;
+  error: (correlationId?: string) => void;
+  unavailable: (reason: string) => void;
 };
 
 export const BEFORE = "I'm not a robot";
 export const DURING = "Processing...";
 export const AFTER = "Verified!";
 export const ERROR = "Something went wrong";
+export const SITEKEY_NOT_FOUND = "Invalid sitekey";
+export const ORIGIN_NOT_ALLOWED = "Domain not allow-listed";
 
 export const messageText = (): messageTextReturn => {
   const conatinerID = "widget__verification-text";
@@ -88,9 +104,19 @@ export const messageText = (): messageTextReturn => {
       showMsg(DURING);
     },
 
-    /** display "error" message **/
-    error: () => {
-      showMsg(ERROR);
+    /** display "error" message, with the challenge's correlation ID when
+     * available so that users can quote it to support **/
+    error: (correlationId?: string) => {
+      if (correlationId) {
+        showMsg(`${ERROR} (ID: ${correlationId})`);
+      } else {
+        showMsg(ERROR);
+      }
+    },
+
+    /** display why the widget can't be used on this page **/
+    unavailable: (reason: string) => {
+      showMsg(reason);
     },
   };
 };
@@ -0,0 +1,8 @@
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+// Script that sites embed the widget with, served by the instance so that
+// sites don't depend on a CDN. The glue renders the widget into
+// `#mcaptcha__widget-container` once it loads.
+import "@mcaptcha/vanilla-glue";
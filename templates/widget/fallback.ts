@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+import genJsonPayload from "../utils/genJsonPayload";
+import * as CONST from "./const";
+import { parentOrigin } from "./probeSitekey";
+import { Token } from "./types";
+
+type FallbackChallenge = {
+  challenge: string;
+  wait: number;
+};
+
+/** whether the browser can run the PoW worker */
+export const canSolvePoW = (): boolean =>
+  typeof Worker !== "undefined" && typeof WebAssembly !== "undefined";
+
+const post = async <T>(url: string, payload: object): Promise<T> => {
+  const res = await fetch(url, genJsonPayload(payload));
+  if (!res.ok) {
+    const err = await res.json();
+    throw new Error(err.error);
+  }
+  return res.json();
+};
+
+/** get a validation token through the fallback challenge of the sitekey:
+ * start it, wait as long as the server asks and complete it */
+export const solveFallback = async (): Promise<Token> => {
+  const key = CONST.sitekey();
+  const challenge = await post<FallbackChallenge>(CONST.ROUTES.startFallback, {
+    key,
+    origin: parentOrigin(),
+  });
+  // a little margin, so that the wait is over on the server too
+  await new Promise((r) => setTimeout(r, challenge.wait * 1000 + 500));
+  return post<Token>(CONST.ROUTES.completeFallback, {
+    key,
+    challenge: challenge.challenge,
+  });
+};
+
+export default solveFallback;
@@ -4,11 +4,14 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 import genJsonPayload from "../utils/genJsonPayload";
+import fetchWithBackoff from "./backoff";
 import * as CONST from "./const";
+import { parentOrigin } from "./probeSitekey";
 import { PoWConfig } from "./types";
 
 type GetConfigPayload = {
   key: string;
+  origin?: string;
 };
 
 /**
@@ -18,9 +21,13 @@ type GetConfigPayload = {
 export const fetchPoWConfig = async (): Promise<PoWConfig> => {
   const payload: GetConfigPayload = {
     key: CONST.sitekey(),
+    origin: parentOrigin(),
   };
 
-  const res = await fetch(CONST.ROUTES.getConfig, genJsonPayload(payload));
+  const res = await fetchWithBackoff(
+    CONST.ROUTES.getConfig,
+    genJsonPayload(payload)
+  );
   if (res.ok) {
     const config: PoWConfig = await res.json();
     return config;
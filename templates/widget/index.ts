@@ -5,24 +5,30 @@
 
 import {Work, ServiceWorkerMessage} from "./types";
 import fetchPoWConfig from "./fetchPoWConfig";
+import probeSitekey from "./probeSitekey";
 import sendWork from "./sendWork";
 import sendToParent from "./sendToParent";
+import loadTheme from "./theme";
+import { canSolvePoW, solveFallback } from "./fallback";
 import * as CONST from "./const";
 
 import "./main.scss";
 
 let LOCK = false;
 
-const workerPromise = new Promise<Worker>((res) => {
-  const worker = new Worker("/bench.js");
-  worker.onmessage = (event: MessageEvent) => {
-    const message: ServiceWorkerMessage = event.data;
-    if(message.type === "ready") {
-      console.log("worker ready");
-      res(worker);
-    }
-  };
-});
+/** not set when the browser can't run the PoW worker */
+const workerPromise = canSolvePoW()
+  ? new Promise<Worker>((res) => {
+      const worker = new Worker("/bench.js");
+      worker.onmessage = (event: MessageEvent) => {
+        const message: ServiceWorkerMessage = event.data;
+        if (message.type === "ready") {
+          console.log("worker ready");
+          res(worker);
+        }
+      };
+    })
+  : null;
 
 /** add  mcaptcha widget element to DOM */
 export const registerVerificationEventHandler = (): void => {
@@ -30,14 +36,68 @@ export const registerVerificationEventHandler = (): void => {
     document.querySelector(".widget__verification-container")
   );
   verificationContainer.style.display = "flex";
-  workerPromise.then((worker: Worker) => {
+  workerPromise?.then((worker: Worker) => {
     const btn = CONST.btn();
     btn.disabled = false;
     btn.addEventListener("click", (e) => solveCaptchaRunner(worker, e));
   });
 };
 
-export const solveCaptchaRunner = async (worker: Worker, e: Event): Promise<void> => {
+/** let visitors whose browsers can't solve PoW wait instead */
+export const registerFallbackHandler = (): void => {
+  const btn = CONST.btn();
+  btn.disabled = false;
+  btn.addEventListener("click", (e) => fallbackRunner(e));
+};
+
+export const fallbackRunner = async (e: Event): Promise<void> => {
+  if (LOCK) {
+    e.preventDefault();
+    return;
+  }
+  if (CONST.btn().checked == false) {
+    CONST.messageText().before();
+    CONST.btn().ariaChecked = <any>false;
+    return;
+  }
+  e.preventDefault();
+  LOCK = true;
+  CONST.messageText().during();
+  try {
+    const token = await solveFallback();
+    sendToParent(token);
+    CONST.btn().checked = true;
+    CONST.btn().ariaChecked = <any>true;
+    CONST.messageText().after();
+  } catch (err) {
+    console.error(err);
+    CONST.btn().checked = false;
+    CONST.btn().ariaChecked = <any>false;
+    CONST.messageText().error();
+  }
+  LOCK = false;
+};
+
+/** solve challenges of sitekeys in invisible mode in the background. Visitors
+ * click the checkbox as usual once challenges escalate */
+export const solveInvisibly = async (): Promise<void> => {
+  if (!workerPromise) {
+    return;
+  }
+  const worker = await workerPromise;
+  const btn = CONST.btn();
+  if (LOCK || btn.checked) {
+    return;
+  }
+  btn.checked = true;
+  await solveCaptchaRunner(worker, new Event("click"), true);
+};
+
+export const solveCaptchaRunner = async (
+  worker: Worker,
+  e: Event,
+  invisible = false
+): Promise<void> => {
   const PROGRESS_FILL = <HTMLElement>document.querySelector(".progress__fill");
 
   const setWidth = (width: number) => {
@@ -69,6 +129,12 @@ export const solveCaptchaRunner = async (worker: Worker, e: Event): Promise<void
     CONST.messageText().during();
     // 1. get config
     const config = await fetchPoWConfig();
+    if (invisible && !config.invisible) {
+      CONST.btn().checked = false;
+      CONST.messageText().before();
+      LOCK = false;
+      return;
+    }
     const max_recorded_nonce = config.max_recorded_nonce;
     // 2. prove work
     worker.postMessage(config);
@@ -90,12 +156,23 @@ export const solveCaptchaRunner = async (worker: Worker, e: Event): Promise<void
           result: resp.value.work.result,
           time: Math.trunc(resp.value.work.time),
           worker_type: resp.value.work.worker_type,
+          correlation_id: config.correlation_id,
         };
 
         width = 90;
         setWidth(width);
         // 3. submit work
-        const token = await sendWork(proof);
+        let token;
+        try {
+          token = await sendWork(proof);
+        } catch {
+          // error and correlation ID are displayed by sendWork
+          CONST.btn().checked = false;
+          CONST.btn().ariaChecked = <any>false;
+          setWidth(0);
+          LOCK = false;
+          return;
+        }
         // 4. send token
         sendToParent(token);
         // 5. mark checkbox checked
@@ -115,10 +192,24 @@ export const solveCaptchaRunner = async (worker: Worker, e: Event): Promise<void
       }
     };
   } catch (e) {
-    CONST.messageText().error();
     console.error(e);
+    const reason = await probeSitekey();
+    if (reason) {
+      CONST.messageText().unavailable(reason);
+    } else {
+      CONST.messageText().error();
+    }
     LOCK = false;
   }
 };
 
 registerVerificationEventHandler();
+loadTheme().then((config) => {
+  if (!workerPromise) {
+    if (config?.fallback) {
+      registerFallbackHandler();
+    }
+  } else if (config?.invisible) {
+    solveInvisibly();
+  }
+});
@@ -0,0 +1,69 @@
+// Copyright © 2021 Aravinth Manivnanan <realaravinth@batsense.net>.
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+import * as CONST from "./const";
+
+type SitekeyProbe = {
+  exists: boolean;
+  allowed_origins: Array<string>;
+};
+
+/** origin of the page that embeds the widget, when the browser discloses it */
+export const parentOrigin = (): string | undefined => {
+  const ancestors = window.location.ancestorOrigins;
+  if (ancestors && ancestors.length > 0) {
+    return ancestors[0];
+  }
+  if (document.referrer) {
+    return new URL(document.referrer).origin;
+  }
+  return undefined;
+};
+
+/**
+ * check if `origin` is on one of the allowed domains or their subdomains
+ * @param {string} origin - origin of the page that embeds the widget
+ * @param {Array<string>} allowed - allowed domains of the sitekey
+ * */
+export const isAllowed = (origin: string, allowed: Array<string>): boolean => {
+  if (allowed.includes("*")) {
+    return true;
+  }
+  const host = new URL(origin).hostname;
+  return allowed.some((d) => host === d || host.endsWith(`.${d}`));
+};
+
+/**
+ * find out why the widget can't fetch a challenge
+ * @returns {string | undefined} message to display; undefined when the
+ * sitekey is usable on this page and the failure lies elsewhere
+ * */
+export const probeSitekey = async (): Promise<string | undefined> => {
+  let probe: SitekeyProbe;
+  try {
+    const res = await fetch(CONST.ROUTES.probe(CONST.sitekey()));
+    if (!res.ok) {
+      return undefined;
+    }
+    probe = await res.json();
+  } catch (err) {
+    console.error(err);
+    return undefined;
+  }
+
+  if (!probe.exists) {
+    return CONST.SITEKEY_NOT_FOUND;
+  }
+  const origin = parentOrigin();
+  if (probe.allowed_origins.length === 0) {
+    return CONST.ORIGIN_NOT_ALLOWED;
+  }
+  if (origin && !isAllowed(origin, probe.allowed_origins)) {
+    return CONST.ORIGIN_NOT_ALLOWED;
+  }
+  return undefined;
+};
+
+export default probeSitekey;
@@ -4,22 +4,17 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 import genJsonPayload from "../utils/genJsonPayload";
+import fetchWithBackoff from "./backoff";
 import * as CONST from "./const";
 import { Work, Token } from "./types";
 
 export const sendWork = async (payload: Work): Promise<Token> => {
+  let res: Response;
   try {
-    const res = await fetch(CONST.ROUTES.verififyPoW, genJsonPayload(payload));
-    if (res.ok) {
-      console.debug("work verified");
-      const token: Token = await res.json();
-      console.debug(`token ${token.token}`);
-      return token;
-    } else {
-      const err = await res.json();
-      console.error(`error: ${err.error}`);
-      throw new Error(err);
-    }
+    res = await fetchWithBackoff(
+      CONST.ROUTES.verififyPoW,
+      genJsonPayload(payload)
+    );
   } catch (err) {
     CONST.messageText().error();
     console.error(err);
@@ -27,6 +22,20 @@ export const sendWork = async (payload: Work): Promise<Token> => {
     window.location.reload();
     throw err;
   }
+
+  if (res.ok) {
+    console.debug("work verified");
+    const token: Token = await res.json();
+    console.debug(`token ${token.token}`);
+    return token;
+  } else {
+    const err = await res.json();
+    const correlationId = err.correlation_id || payload.correlation_id;
+    console.error(`error: ${err.error} correlation ID: ${correlationId}`);
+    // keep the correlation ID on screen so that users can quote it to support
+    CONST.messageText().error(correlationId);
+    throw new Error(err.error);
+  }
 };
 
 export default sendWork;
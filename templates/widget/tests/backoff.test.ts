@@ -0,0 +1,19 @@
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+import { retryAfter } from "../backoff";
+
+const response = (status: number, headers: Record<string, string> = {}) =>
+  ({
+    status,
+    headers: { get: (name: string) => headers[name] ?? null },
+  } as unknown as Response);
+
+it("retry after works", () => {
+  expect(retryAfter(response(503, { "Retry-After": "2" }))).toBe(2);
+  expect(retryAfter(response(429, { "Retry-After": "5" }))).toBe(5);
+  expect(retryAfter(response(503))).toBe(null);
+  expect(retryAfter(response(400, { "Retry-After": "2" }))).toBe(null);
+  expect(retryAfter(response(200))).toBe(null);
+});
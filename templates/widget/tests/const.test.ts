@@ -30,4 +30,14 @@ it("const works", () => {
   // display error
   CONST.messageText().error();
   expect(TESTElements.Msg.innerText).toBe(CONST.ERROR);
+
+  // display error with correlation ID
+  CONST.messageText().error("correlationid");
+  expect(TESTElements.Msg.innerText).toBe(`${CONST.ERROR} (ID: correlationid)`);
+
+  // display why the widget is unavailable
+  CONST.messageText().unavailable(CONST.ORIGIN_NOT_ALLOWED);
+  expect(TESTElements.Msg.innerText).toBe(CONST.ORIGIN_NOT_ALLOWED);
+
+  expect(CONST.ROUTES.probe("a/b")).toBe("/api/v1/pow/probe/a%2Fb");
 });
@@ -0,0 +1,15 @@
+// Copyright © 2021 Aravinth Manivnanan <realaravinth@batsense.net>.
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+import { isAllowed } from "../probeSitekey";
+
+it("isAllowed works", () => {
+  expect(isAllowed("https://unrelated.example.net", ["*"])).toBe(true);
+  const allowed = ["example.com"];
+  expect(isAllowed("https://example.com", allowed)).toBe(true);
+  expect(isAllowed("https://shop.example.com:8443", allowed)).toBe(true);
+  expect(isAllowed("https://notexample.com", allowed)).toBe(false);
+  expect(isAllowed("https://example.com.evil.org", allowed)).toBe(false);
+});
@@ -0,0 +1,30 @@
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+import { applyTheme, WidgetTheme } from "../theme";
+
+it("theme works", () => {
+  const theme: WidgetTheme = {
+    mode: "dark",
+    accent_color: "#ff8800",
+    background_color: "#101010",
+    text_color: null,
+    border_radius: 8,
+    show_logo: false,
+  };
+  applyTheme(theme);
+  const style = document.documentElement.style;
+  expect(document.body.classList.contains("widget--dark")).toBe(true);
+  expect(document.body.classList.contains("widget--no-logo")).toBe(true);
+  expect(style.getPropertyValue("--widget-accent")).toBe("#ff8800");
+  expect(style.getPropertyValue("--widget-background")).toBe("#101010");
+  expect(style.getPropertyValue("--widget-radius")).toBe("8px");
+  expect(style.getPropertyValue("--widget-text")).toBe("");
+
+  applyTheme({ ...theme, mode: "auto", background_color: null, show_logo: true });
+  expect(document.body.classList.contains("widget--dark")).toBe(false);
+  expect(document.body.classList.contains("widget--light")).toBe(false);
+  expect(document.body.classList.contains("widget--no-logo")).toBe(false);
+  expect(style.getPropertyValue("--widget-background")).toBe("");
+});
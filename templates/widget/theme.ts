@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: 2023 Aravinth Manivannan <realaravinth@batsense.net>
+//
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+import * as CONST from "./const";
+
+export type WidgetTheme = {
+  mode: "light" | "dark" | "auto";
+  accent_color: string;
+  background_color: string | null;
+  text_color: string | null;
+  border_radius: number;
+  show_logo: boolean;
+};
+
+export type WidgetConfig = {
+  theme: WidgetTheme;
+  invisible: boolean;
+  fallback: boolean;
+};
+
+/** style the widget with the theme of its sitekey */
+export const applyTheme = (theme: WidgetTheme): void => {
+  const body = document.body;
+  const style = document.documentElement.style;
+  body.classList.toggle("widget--light", theme.mode === "light");
+  body.classList.toggle("widget--dark", theme.mode === "dark");
+  body.classList.toggle("widget--no-logo", !theme.show_logo);
+  style.setProperty("--widget-accent", theme.accent_color);
+  style.setProperty("--widget-radius", `${theme.border_radius}px`);
+  const optional = (name: string, value: string | null) => {
+    if (value) {
+      style.setProperty(name, value);
+    } else {
+      style.removeProperty(name);
+    }
+  };
+  optional("--widget-background", theme.background_color);
+  optional("--widget-text", theme.text_color);
+};
+
+/** fetch the configuration of the widget and apply its theme. Widgets keep
+ * the default theme when it can't be fetched */
+export const loadTheme = async (): Promise<WidgetConfig | null> => {
+  try {
+    const res = await fetch(CONST.ROUTES.widgetConfig(CONST.sitekey()));
+    if (!res.ok) {
+      return null;
+    }
+    const config: WidgetConfig = await res.json();
+    applyTheme(config.theme);
+    return config;
+  } catch (err) {
+    console.error(err);
+    return null;
+  }
+};
+
+export default loadTheme;
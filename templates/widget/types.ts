@@ -10,6 +10,7 @@ export type Work = {
   key: string;
   time: number;
   worker_type: string;
+  correlation_id: string;
 };
 
 export type SubmitWork = {
@@ -33,6 +34,8 @@ export type PoWConfig = {
   difficulty_factor: number;
   salt: string;
   max_recorded_nonce: number;
+  correlation_id: string;
+  invisible?: boolean;
 };
 
 export type Token = {
@@ -11,6 +11,7 @@ module.exports = {
   entry: {
     bundle: "./templates/index.ts",
     verificationWidget: "./templates/widget/index.ts",
+    embed: "./templates/widget/embed.ts",
     bench: "./templates/widget/service-worker.ts",
   },
   output: {